@@ -0,0 +1,57 @@
+// cargo run --no-default-features --bin breakout_bot -- --server 127.0.0.1:6000
+use std::net::SocketAddr;
+
+use bevy::{app::AppExit, log::LogPlugin, prelude::*};
+use bevy_quinnet_breakout_server_example::{
+  bot::{bot_app, BotSettings, BotState, RoomChoice},
+  server::default_tick_rate,
+};
+use clap::Parser;
+
+/// Plays one match of breakout against whoever else joins, then prints the result.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+  /// Server to connect to
+  #[arg(long, default_value = "127.0.0.1:6000")]
+  server: SocketAddr,
+  /// Create a room with this name
+  #[arg(long, conflicts_with = "join")]
+  create: Option<String>,
+  /// Join this room instead of the first one waiting for players
+  #[arg(long)]
+  join: Option<u32>,
+  /// Inputs per second, as the server's tick rate
+  #[arg(long, default_value_t = default_tick_rate())]
+  tick_rate: f32,
+}
+
+fn exit_when_over(bot: Res<BotState>, mut exit: EventWriter<AppExit>) {
+  if let Some(result) = &bot.result {
+    info!(
+      "match over at tick {}: scores {:?}, winner {:?}",
+      result.tick, result.scores, result.winner
+    );
+    exit.send(AppExit);
+  }
+}
+
+fn main() {
+  let args = Args::parse();
+  let room = match (args.create, args.join) {
+    (Some(name), _) => RoomChoice::Create(name),
+    (None, Some(id)) => RoomChoice::Join(id),
+    (None, None) => RoomChoice::Any,
+  };
+  let mut app = bot_app(
+    BotSettings {
+      server: args.server,
+      room,
+    },
+    args.tick_rate,
+  );
+  app
+    .add_plugins(LogPlugin::default())
+    .add_systems(Update, exit_when_over);
+  app.run();
+}
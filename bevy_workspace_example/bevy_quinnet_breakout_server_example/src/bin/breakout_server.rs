@@ -0,0 +1,45 @@
+// cargo run --no-default-features --bin breakout_server -- --players-per-room 4
+use std::net::SocketAddr;
+
+use bevy::log::LogPlugin;
+use bevy_quinnet_breakout_server_example::{
+  lobby::LobbyConfig,
+  server::{default_tick_rate, headless_app},
+  LOCAL_BIND_IP, SERVER_PORT,
+};
+use clap::Parser;
+
+/// Headless breakout server: rooms, matches and nothing to draw.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+  /// Address to listen on
+  #[arg(long, default_value_t = SocketAddr::new(LOCAL_BIND_IP, SERVER_PORT))]
+  addr: SocketAddr,
+  /// Players needed to start a match
+  #[arg(long, default_value_t = 2)]
+  players_per_room: usize,
+  /// Limit the bricks to this many rows
+  #[arg(long)]
+  brick_rows: Option<usize>,
+  /// End matches after this many ticks
+  #[arg(long)]
+  max_ticks: Option<u32>,
+  /// Simulation steps per second
+  #[arg(long, default_value_t = default_tick_rate())]
+  tick_rate: f32,
+}
+
+fn main() {
+  let args = Args::parse();
+  let defaults = LobbyConfig::default();
+  let lobby = LobbyConfig {
+    players_per_room: args.players_per_room,
+    brick_rows: args.brick_rows,
+    max_ticks: args.max_ticks.unwrap_or(defaults.max_ticks),
+    ..defaults
+  };
+  let mut app = headless_app(args.addr, lobby, args.tick_rate);
+  app.add_plugins(LogPlugin::default());
+  app.run();
+}
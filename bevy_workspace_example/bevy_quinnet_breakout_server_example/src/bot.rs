@@ -0,0 +1,282 @@
+//! A headless player: joins a room, follows the balls with its paddle and records how the match
+//! went. Used by the `breakout_bot` binary and the integration tests.
+
+use std::{
+  net::{IpAddr, Ipv6Addr, SocketAddr},
+  time::Duration,
+};
+
+use bevy::{app::ScheduleRunnerPlugin, prelude::*};
+use bevy_quinnet::{
+  client::{
+    certificate::CertificateVerificationMode,
+    connection::{ConnectionConfiguration, ConnectionEvent},
+    Client, QuinnetClientPlugin,
+  },
+  shared::channel::ChannelId,
+};
+
+use crate::{
+  prediction::Predictor,
+  protocol::{
+    ClientMessage, PaddleInput, RoomId, RoomInfo, RoomState, ServerMessage, SessionToken, Slot,
+    Tick,
+  },
+  sim::MatchState,
+  LOCAL_BIND_IP,
+};
+
+/// The paddle stops this close to where it wants to be, to avoid jittering around it.
+const DEAD_ZONE: f32 = 5.0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoomChoice {
+  Create(String),
+  Join(RoomId),
+  /// Joins the first room still waiting for players, or creates one.
+  Any,
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct BotSettings {
+  pub server: SocketAddr,
+  pub room: RoomChoice,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchResult {
+  pub tick: Tick,
+  pub scores: Vec<u32>,
+  pub winner: Option<Slot>,
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct BotState {
+  pub room: Option<RoomInfo>,
+  pub slot: Option<Slot>,
+  pub token: Option<SessionToken>,
+  pub state: Option<MatchState>,
+  pub predictor: Option<Predictor>,
+  pub last_snapshot: Option<Tick>,
+  pub snapshots: u32,
+  pub rejoins: u32,
+  pub result: Option<MatchResult>,
+  pub errors: Vec<String>,
+  next_input_tick: Tick,
+}
+
+impl BotState {
+  pub fn in_match(&self) -> bool {
+    self.predictor.is_some() && self.result.is_none()
+  }
+}
+
+pub struct BotPlugin;
+
+impl Plugin for BotPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<BotState>()
+      .add_systems(Startup, connect)
+      .add_systems(
+        Update,
+        (handle_connection_events, handle_server_messages).chain(),
+      )
+      .add_systems(FixedUpdate, play);
+  }
+}
+
+/// A bot sending one input per `tick_rate`, which should match the server's.
+pub fn bot_app(settings: BotSettings, tick_rate: f32) -> App {
+  let mut app = App::new();
+  app
+    .add_plugins((
+      MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f32(
+        0.5 / tick_rate,
+      ))),
+      QuinnetClientPlugin::default(),
+      BotPlugin,
+    ))
+    .insert_resource(settings)
+    .insert_resource(FixedTime::new_from_secs(1.0 / tick_rate));
+  app
+}
+
+fn connect(mut client: ResMut<Client>, settings: Res<BotSettings>) {
+  open_connection(&mut client, settings.server);
+}
+
+fn open_connection(client: &mut Client, server: SocketAddr) {
+  let local_ip = match server.ip() {
+    IpAddr::V4(_) => LOCAL_BIND_IP,
+    IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+  };
+  client
+    .open_connection(
+      ConnectionConfiguration::from_addrs(server, SocketAddr::new(local_ip, 0)),
+      CertificateVerificationMode::SkipVerification,
+    )
+    .unwrap();
+}
+
+/// Drops the connection without leaving the room, as a crash or network loss would.
+pub fn disconnect(world: &mut World) {
+  world.resource_mut::<Client>().close_all_connections().ok();
+}
+
+/// Opens a new connection; the bot rejoins its match once connected.
+pub fn reconnect(world: &mut World) {
+  let server = world.resource::<BotSettings>().server;
+  open_connection(&mut world.resource_mut::<Client>(), server);
+}
+
+fn handle_connection_events(
+  mut events: EventReader<ConnectionEvent>,
+  client: Res<Client>,
+  settings: Res<BotSettings>,
+  mut bot: ResMut<BotState>,
+) {
+  for _ in events.iter() {
+    let message = match (bot.token, &settings.room) {
+      (Some(token), _) if bot.result.is_none() => {
+        bot.rejoins += 1;
+        ClientMessage::Rejoin { token }
+      }
+      (_, RoomChoice::Create(name)) => ClientMessage::CreateRoom { name: name.clone() },
+      (_, RoomChoice::Join(room)) => ClientMessage::JoinRoom { room: *room },
+      // The server sends the room list on its own
+      (_, RoomChoice::Any) => continue,
+    };
+    client.connection().try_send_message(message);
+  }
+}
+
+fn handle_server_messages(
+  mut client: ResMut<Client>,
+  settings: Res<BotSettings>,
+  mut bot: ResMut<BotState>,
+) {
+  let Some(connection) = client.get_connection_mut() else {
+    return;
+  };
+  while let Some(message) = connection.try_receive_message::<ServerMessage>() {
+    match message {
+      ServerMessage::RoomList { rooms } => {
+        // Only the listing sent on first connecting matters, and only when looking for a room.
+        if settings.room != RoomChoice::Any || bot.token.is_some() {
+          continue;
+        }
+        let open = rooms
+          .iter()
+          .find(|room| room.state == RoomState::Waiting && room.players < room.capacity);
+        connection.try_send_message(match open {
+          Some(room) => ClientMessage::JoinRoom { room: room.id },
+          None => ClientMessage::CreateRoom {
+            name: "bots".to_string(),
+          },
+        });
+      }
+      ServerMessage::Joined {
+        room,
+        slot,
+        token,
+        next_input_tick,
+      } => {
+        bot.room = Some(room);
+        bot.slot = Some(slot);
+        bot.token = Some(token);
+        bot.next_input_tick = next_input_tick;
+      }
+      ServerMessage::RoomChanged { room } => bot.room = Some(room),
+      ServerMessage::MatchStarted { state } => {
+        let slot = bot.slot.unwrap() as usize;
+        bot.predictor = Some(Predictor::new(state.paddles[slot], bot.next_input_tick));
+        bot.last_snapshot = Some(state.tick);
+        bot.state = Some(state);
+      }
+      ServerMessage::Snapshot {
+        tick,
+        ack,
+        paddles,
+        balls,
+      } => {
+        // Snapshots are unreliable: older ones than what we have are stale.
+        if bot.last_snapshot.is_some_and(|last| tick <= last) {
+          continue;
+        }
+        let slot = bot.slot.unwrap_or_default() as usize;
+        let bot = &mut *bot;
+        let (Some(state), Some(predictor)) = (bot.state.as_mut(), bot.predictor.as_mut()) else {
+          continue;
+        };
+        state.tick = tick;
+        for (paddle, x) in state.paddles.iter_mut().zip(&paddles) {
+          paddle.position.x = *x;
+        }
+        state.balls = balls;
+        predictor.reconcile(ack, paddles[slot]);
+        bot.last_snapshot = Some(tick);
+        bot.snapshots += 1;
+      }
+      ServerMessage::BrickDestroyed { by, brick_id, .. } => {
+        if let Some(state) = bot.state.as_mut() {
+          state.alive[brick_id as usize] = false;
+          state.scores[by as usize] += 1;
+        }
+      }
+      ServerMessage::MatchEnded {
+        tick,
+        scores,
+        winner,
+      } => {
+        bot.result = Some(MatchResult {
+          tick,
+          scores,
+          winner,
+        })
+      }
+      ServerMessage::Error { message } => bot.errors.push(message),
+    }
+  }
+}
+
+fn play(client: Res<Client>, mut bot: ResMut<BotState>) {
+  let Some(connection) = client.get_connection().filter(|c| c.is_connected()) else {
+    return;
+  };
+  if !bot.in_match() {
+    return;
+  }
+  let bot = &mut *bot;
+  let (Some(state), Some(predictor)) = (bot.state.as_ref(), bot.predictor.as_mut()) else {
+    return;
+  };
+  let paddle = state.paddles[bot.slot.unwrap() as usize];
+
+  // Follow the closest ball heading our way, or the closest one at all.
+  let incoming = |velocity_y: f32| velocity_y.signum() == paddle.position.y.signum();
+  let distance = |position: Vec2| (position.y - paddle.position.y).abs();
+  let target = state
+    .balls
+    .iter()
+    .filter(|ball| incoming(ball.velocity.y))
+    .min_by(|a, b| distance(a.position).total_cmp(&distance(b.position)))
+    .or_else(|| {
+      state
+        .balls
+        .iter()
+        .min_by(|a, b| distance(a.position).total_cmp(&distance(b.position)))
+    })
+    .map_or(paddle.position.x, |ball| ball.position.x);
+
+  let x = predictor.x();
+  let input = if target < x - DEAD_ZONE {
+    PaddleInput::Left
+  } else if target > x + DEAD_ZONE {
+    PaddleInput::Right
+  } else {
+    PaddleInput::None
+  };
+  let tick = predictor.apply(input);
+  connection.try_send_message_on(ChannelId::Unreliable, ClientMessage::Input { tick, input });
+}
@@ -1,35 +1,32 @@
-use std::collections::HashMap;
-
-use bevy::{
-  audio::Volume,
-  prelude::{
-    default, AssetServer, AudioBundle, BuildChildren, Bundle, Button, ButtonBundle, Camera2dBundle,
-    Changed, Color, Commands, Component, DespawnRecursiveExt, Entity, EventReader, EventWriter,
-    Input, KeyCode, Local, NextState, PlaybackSettings, Query, Res, ResMut, Resource, TextBundle,
-    Transform, Vec2, Vec3, With, Without,
-  },
-  sprite::{Sprite, SpriteBundle},
-  text::{Text, TextSection, TextStyle},
-  ui::{
-    AlignItems, BackgroundColor, Interaction, JustifyContent, PositionType, Style, UiRect, Val,
-  },
+use std::{
+  collections::HashMap,
+  net::{IpAddr, SocketAddr},
 };
+
+use bevy::{audio::Volume, prelude::*};
 use bevy_quinnet::{
-  client::{certificate::CertificateVerificationMode, connection::ConnectionConfiguration, Client},
-  shared::ClientId,
+  client::{
+    certificate::CertificateVerificationMode,
+    connection::{ConnectionConfiguration, ConnectionEvent, ConnectionLostEvent},
+    Client,
+  },
+  shared::channel::ChannelId,
 };
 
 use crate::{
-  protocol::{ClientMessage, PaddleInput, ServerMessage},
-  BrickId, CollisionEvent, CollisionSound, GameState, Score, Velocity, WallLocation, BALL_SIZE,
-  BALL_SPEED, BRICK_SIZE, GAP_BETWEEN_BRICKS, LOCAL_BIND_IP, PADDLE_SIZE, SERVER_HOST, SERVER_PORT,
-  TIME_STEP,
+  lobby::LobbyConfig,
+  prediction::Predictor,
+  protocol::{ClientMessage, PaddleInput, RoomState, ServerMessage, SessionToken, Slot, Tick},
+  server::{self, BreakoutServerPlugin},
+  sim::MatchState,
+  BrickId, WallLocation, BALL_SIZE, BRICK_SIZE, LOCAL_BIND_IP, PADDLE_SIZE, SERVER_HOST,
+  SERVER_PORT, TIME_STEP,
 };
 
 const SCOREBOARD_FONT_SIZE: f32 = 40.0;
 const SCOREBOARD_TEXT_PADDING: Val = Val::Px(5.0);
 
-pub(crate) const BACKGROUND_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
+pub const BACKGROUND_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
 const PADDLE_COLOR: Color = Color::rgb(0.3, 0.3, 0.7);
 const OPPONENT_PADDLE_COLOR: Color = Color::rgb(1.0, 0.5, 0.5);
 const BALL_COLOR: Color = Color::rgb(0.35, 0.35, 0.6);
@@ -47,39 +44,50 @@ const BOLD_FONT: &str = "fonts/FiraSans-Bold.ttf";
 const NORMAL_FONT: &str = "fonts/FiraMono-Medium.ttf";
 const COLLISION_SOUND_EFFECT: &str = "sounds/breakout_collision.ogg";
 
-#[derive(Resource, Debug, Clone, Default)]
-pub(crate) struct ClientData {
-  self_id: ClientId,
+#[derive(Default, Clone, Eq, PartialEq, Debug, Hash, States)]
+pub enum GameState {
+  #[default]
+  MainMenu,
+  HostingLobby,
+  JoiningLobby,
+  Running,
 }
 
-#[derive(Resource, Default)]
-pub(crate) struct NetworkMapping {
-  // Network entity id to local entity id
-  map: HashMap<Entity, Entity>,
-}
-#[derive(Resource, Default)]
-pub struct BricksMapping {
-  map: HashMap<BrickId, Entity>,
-}
+#[derive(Default, Event)]
+struct CollisionEvent;
 
-// This resource tracks the game's score
 #[derive(Resource)]
-pub(crate) struct Scoreboard {
-  pub(crate) score: i32,
+struct CollisionSound(Handle<AudioSource>);
+
+/// Our seat and view of the match, kept across reconnections.
+#[derive(Resource, Default)]
+struct Session {
+  slot: Slot,
+  token: Option<SessionToken>,
+  next_input_tick: Tick,
+  state: Option<MatchState>,
+  predictor: Option<Predictor>,
+  last_snapshot: Option<Tick>,
+  bricks: HashMap<BrickId, Entity>,
+  ended: bool,
 }
 
 #[derive(Component)]
-pub(crate) struct Paddle;
+struct Paddle(Slot);
+
+#[derive(Component)]
+struct Ball(usize);
 
 #[derive(Component)]
-pub(crate) struct Ball;
+struct Score;
 
+/// Everything despawned when a match is (re)built from a `MatchStarted`.
 #[derive(Component)]
-pub(crate) struct Brick(BrickId);
+struct MatchEntity;
 
 /// The buttons in the main menu.
 #[derive(Clone, Copy, Component)]
-pub(crate) enum MenuItem {
+enum MenuItem {
   Host,
   Join,
 }
@@ -89,11 +97,67 @@ pub(crate) enum MenuItem {
 struct WallBundle {
   sprite_bundle: SpriteBundle,
 }
-pub(crate) fn start_connection(mut client: ResMut<Client>) {
+
+/// The game window: a menu to host or join, then the match. Expects `DefaultPlugins` and both
+/// quinnet plugins, since hosting runs the server in the same app.
+pub struct ClientPlugin;
+
+impl Plugin for ClientPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .add_event::<CollisionEvent>()
+      .add_state::<GameState>()
+      .add_plugins(BreakoutServerPlugin {
+        addr: SocketAddr::new(LOCAL_BIND_IP, SERVER_PORT),
+        lobby: LobbyConfig::default(),
+      })
+      .insert_resource(FixedTime::new_from_secs(TIME_STEP))
+      .insert_resource(ClearColor(BACKGROUND_COLOR))
+      .init_resource::<Session>();
+
+    // ------ Main menu
+    app
+      .add_systems(Update, bevy::window::close_on_esc)
+      .add_systems(OnEnter(GameState::MainMenu), setup_main_menu)
+      .add_systems(
+        Update,
+        handle_menu_buttons.run_if(in_state(GameState::MainMenu)),
+      )
+      .add_systems(OnExit(GameState::MainMenu), teardown_main_menu);
+
+    // ------ Hosting a server on a client, or just joining one
+    app
+      .add_systems(
+        OnEnter(GameState::HostingLobby),
+        (server::start_listening, start_connection),
+      )
+      .add_systems(OnEnter(GameState::JoiningLobby), start_connection)
+      .add_systems(
+        Update,
+        (handle_connection_events, handle_server_messages)
+          .chain()
+          .run_if(not(in_state(GameState::MainMenu))),
+      );
+
+    // ------ Running the game
+    app
+      .add_systems(OnEnter(GameState::Running), setup_breakout)
+      .add_systems(
+        FixedUpdate,
+        move_paddle.run_if(in_state(GameState::Running)),
+      )
+      .add_systems(
+        Update,
+        (update_scoreboard, play_collision_sound).run_if(in_state(GameState::Running)),
+      );
+  }
+}
+
+fn start_connection(mut client: ResMut<Client>) {
   client
     .open_connection(
       ConnectionConfiguration::from_ips(
-        SERVER_HOST.parse().unwrap(),
+        SERVER_HOST.parse::<IpAddr>().unwrap(),
         SERVER_PORT,
         LOCAL_BIND_IP,
         0,
@@ -103,12 +167,163 @@ pub(crate) fn start_connection(mut client: ResMut<Client>) {
     .unwrap();
 }
 
-fn spawn_paddle(commands: &mut Commands, position: &Vec3, owned: bool) -> Entity {
-  commands
-    .spawn((
+fn handle_connection_events(
+  mut connected: EventReader<ConnectionEvent>,
+  mut lost: EventReader<ConnectionLostEvent>,
+  mut client: ResMut<Client>,
+  session: Res<Session>,
+  state: Res<State<GameState>>,
+) {
+  // When joining, the room list the server sends on connection is enough to pick a room.
+  for _ in connected.iter() {
+    let message = match (session.token, state.get()) {
+      (Some(token), _) => ClientMessage::Rejoin { token },
+      (None, GameState::HostingLobby) => ClientMessage::CreateRoom {
+        name: "breakout".to_string(),
+      },
+      (None, _) => continue,
+    };
+    client.connection().try_send_message(message);
+  }
+  // Reconnect right away: the server keeps our seat during a match.
+  if lost.iter().count() > 0 && !session.ended {
+    client.close_all_connections().ok();
+    start_connection(client);
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn handle_server_messages(
+  mut commands: Commands,
+  mut client: ResMut<Client>,
+  mut session: ResMut<Session>,
+  game_state: Res<State<GameState>>,
+  mut next_state: ResMut<NextState<GameState>>,
+  match_entities: Query<Entity, With<MatchEntity>>,
+  mut paddles: Query<(&Paddle, &mut Transform)>,
+  mut balls: Query<(&Ball, &mut Transform, &mut Sprite), Without<Paddle>>,
+  mut collision_events: EventWriter<CollisionEvent>,
+) {
+  let Some(connection) = client.get_connection_mut() else {
+    return;
+  };
+  while let Some(message) = connection.try_receive_message::<ServerMessage>() {
+    match message {
+      ServerMessage::RoomList { rooms } => {
+        if session.token.is_some() || *game_state.get() != GameState::JoiningLobby {
+          continue;
+        }
+        let open = rooms
+          .iter()
+          .find(|room| room.state == RoomState::Waiting && room.players < room.capacity);
+        connection.try_send_message(match open {
+          Some(room) => ClientMessage::JoinRoom { room: room.id },
+          None => ClientMessage::CreateRoom {
+            name: "breakout".to_string(),
+          },
+        });
+      }
+      ServerMessage::Joined {
+        slot,
+        token,
+        next_input_tick,
+        ..
+      } => {
+        session.slot = slot;
+        session.token = Some(token);
+        session.next_input_tick = next_input_tick;
+      }
+      ServerMessage::RoomChanged { room } => {
+        info!("{}: {}/{} players", room.name, room.players, room.capacity)
+      }
+      ServerMessage::MatchStarted { state } => {
+        for entity in &match_entities {
+          commands.entity(entity).despawn();
+        }
+        let slot = session.slot;
+        session.bricks = spawn_match(&mut commands, &state, slot);
+        session.predictor = Some(Predictor::new(
+          state.paddles[slot as usize],
+          session.next_input_tick,
+        ));
+        session.last_snapshot = Some(state.tick);
+        session.state = Some(state);
+        // After a rejoin we are already running
+        if *game_state.get() != GameState::Running {
+          next_state.set(GameState::Running);
+        }
+      }
+      ServerMessage::Snapshot {
+        tick,
+        ack,
+        paddles: paddles_x,
+        balls: snapshot_balls,
+      } => {
+        if session.last_snapshot.is_some_and(|last| tick <= last) {
+          continue;
+        }
+        session.last_snapshot = Some(tick);
+        let slot = session.slot;
+        if let Some(predictor) = session.predictor.as_mut() {
+          predictor.reconcile(ack, paddles_x[slot as usize]);
+        }
+        for (paddle, mut transform) in &mut paddles {
+          if paddle.0 != slot {
+            transform.translation.x = paddles_x[paddle.0 as usize];
+          }
+        }
+        let Some(state) = session.state.as_mut() else {
+          continue;
+        };
+        for (ball, mut transform, mut sprite) in &mut balls {
+          let (old, new) = (&state.balls[ball.0], &snapshot_balls[ball.0]);
+          // A flipped velocity means the ball bounced off something
+          if old.velocity.x.signum() != new.velocity.x.signum()
+            || old.velocity.y.signum() != new.velocity.y.signum()
+          {
+            collision_events.send_default();
+          }
+          transform.translation = new.position.extend(1.0);
+          sprite.color = ball_color_from_bool(new.last_hit_by == slot);
+        }
+        state.balls = snapshot_balls;
+      }
+      ServerMessage::BrickDestroyed { by, brick_id, .. } => {
+        if let Some(state) = session.state.as_mut() {
+          state.alive[brick_id as usize] = false;
+          state.scores[by as usize] += 1;
+        }
+        if let Some(brick) = session.bricks.remove(&brick_id) {
+          commands.entity(brick).despawn();
+        }
+      }
+      ServerMessage::MatchEnded { scores, winner, .. } => {
+        info!("match over, scores: {:?}", scores);
+        if let Some(state) = session.state.as_mut() {
+          state.scores = scores;
+        }
+        session.ended = true;
+        session.predictor = None;
+        if winner == Some(session.slot) {
+          info!("you won!");
+        }
+      }
+      ServerMessage::Error { message } => warn!("server error: {}", message),
+    }
+  }
+}
+
+fn spawn_match(
+  commands: &mut Commands,
+  state: &MatchState,
+  slot: Slot,
+) -> HashMap<BrickId, Entity> {
+  for (index, paddle) in state.paddles.iter().enumerate() {
+    let owned = index == slot as usize;
+    commands.spawn((
       SpriteBundle {
         transform: Transform {
-          translation: *position,
+          translation: paddle.position.extend(0.0),
           scale: PADDLE_SIZE,
           ..default()
         },
@@ -122,204 +337,109 @@ fn spawn_paddle(commands: &mut Commands, position: &Vec3, owned: bool) -> Entity
         },
         ..default()
       },
-      Paddle,
-    ))
-    .id()
-}
+      Paddle(index as Slot),
+      MatchEntity,
+    ));
+  }
 
-fn spawn_ball(commands: &mut Commands, pos: &Vec3, direction: &Vec2, owned: bool) -> Entity {
-  commands
-    .spawn((
-      Ball,
+  // We set the z-value of the balls to 1 so they render on top in the case of overlapping
+  // sprites.
+  for (index, ball) in state.balls.iter().enumerate() {
+    commands.spawn((
       SpriteBundle {
         transform: Transform {
           scale: BALL_SIZE,
-          translation: *pos,
+          translation: ball.position.extend(1.0),
           ..default()
         },
         sprite: Sprite {
-          color: ball_color_from_bool(owned),
+          color: ball_color_from_bool(ball.last_hit_by == slot),
           ..default()
         },
         ..default()
       },
-      Velocity(direction.normalize() * BALL_SPEED),
-    ))
-    .id()
-}
-
-pub(crate) fn spawn_bricks(
-  commands: &mut Commands,
-  bricks: &mut ResMut<BricksMapping>,
-  offset: Vec2,
-  rows: usize,
-  columns: usize,
-) {
-  let mut brick_id = 0;
-  for row in 0 .. rows {
-    for column in 0 .. columns {
-      let brick_position = Vec2::new(
-        offset.x + column as f32 * (BRICK_SIZE.x + GAP_BETWEEN_BRICKS),
-        offset.y + row as f32 * (BRICK_SIZE.y + GAP_BETWEEN_BRICKS),
-      );
-
-      let brick = commands
-        .spawn((
-          Brick(brick_id),
-          SpriteBundle {
-            sprite: Sprite {
-              color: BRICK_COLOR,
-              ..default()
-            },
-            transform: Transform {
-              translation: brick_position.extend(0.0),
-              scale: Vec3::new(BRICK_SIZE.x, BRICK_SIZE.y, 1.0),
-              ..default()
-            },
-            ..default()
-          },
-        ))
-        .id();
-      bricks.map.insert(brick_id, brick);
-      brick_id += 1;
-    }
+      Ball(index),
+      MatchEntity,
+    ));
   }
-}
 
-pub(crate) fn handle_server_messages(
-  mut commands: Commands,
-  mut client: ResMut<Client>,
-  mut client_data: ResMut<ClientData>,
-  mut entity_mapping: ResMut<NetworkMapping>,
-  mut next_state: ResMut<NextState<GameState>>,
-  mut paddles: Query<&mut Transform, With<Paddle>>,
-  mut balls: Query<(&mut Transform, &mut Velocity, &mut Sprite), (With<Ball>, Without<Paddle>)>,
-  mut bricks: ResMut<BricksMapping>,
-  mut scoreboard: ResMut<Scoreboard>,
-  mut collision_events: EventWriter<CollisionEvent>,
-) {
-  while let Some(message) = client
-    .connection_mut()
-    .try_receive_message::<ServerMessage>()
-  {
-    match message {
-      ServerMessage::InitClient { client_id } => {
-        client_data.self_id = client_id;
-      }
-      ServerMessage::SpawnPaddle {
-        owner_client_id,
-        entity,
-        position,
-      } => {
-        let paddle = spawn_paddle(
-          &mut commands,
-          &position,
-          owner_client_id == client_data.self_id,
-        );
-        entity_mapping.map.insert(entity, paddle);
-      }
-      ServerMessage::SpawnBall {
-        owner_client_id,
-        entity,
-        position,
-        direction,
-      } => {
-        let ball = spawn_ball(
-          &mut commands,
-          &position,
-          &direction,
-          owner_client_id == client_data.self_id,
-        );
-        entity_mapping.map.insert(entity, ball);
-      }
-      ServerMessage::SpawnBricks {
-        offset,
-        rows,
-        columns,
-      } => spawn_bricks(&mut commands, &mut bricks, offset, rows, columns),
-      ServerMessage::StartGame {} => next_state.set(GameState::Running),
-      ServerMessage::BrickDestroyed {
-        by_client_id,
-        brick_id,
-      } => {
-        if by_client_id == client_data.self_id {
-          scoreboard.score += 1;
-        } else {
-          scoreboard.score -= 1;
-        }
-        if let Some(brick_entity) = bricks.map.get(&brick_id) {
-          commands.entity(*brick_entity).despawn();
-        }
-      }
-      ServerMessage::BallCollided {
-        owner_client_id,
-        entity,
-        position,
-        velocity,
-      } => {
-        if let Some(local_ball) = entity_mapping.map.get(&entity) {
-          if let Ok((mut ball_transform, mut ball_velocity, mut ball_sprite)) =
-            balls.get_mut(*local_ball)
-          {
-            ball_transform.translation = position;
-            ball_velocity.0 = velocity;
-            ball_sprite.color = ball_color_from_bool(owner_client_id == client_data.self_id);
-          }
-        }
-        // Sends a collision event so that other systems can react to the collision
-        collision_events.send_default();
-      }
-      ServerMessage::PaddleMoved { entity, position } => {
-        if let Some(local_paddle) = entity_mapping.map.get(&entity) {
-          if let Ok(mut paddle_transform) = paddles.get_mut(*local_paddle) {
-            paddle_transform.translation = position;
-          }
-        }
-      }
+  let mut bricks = HashMap::new();
+  for brick_id in 0 .. state.bricks.len() as BrickId {
+    if !state.alive[brick_id as usize] {
+      continue;
     }
+    let brick = commands
+      .spawn((
+        SpriteBundle {
+          sprite: Sprite {
+            color: BRICK_COLOR,
+            ..default()
+          },
+          transform: Transform {
+            translation: state.bricks.position(brick_id).extend(0.0),
+            scale: Vec3::new(BRICK_SIZE.x, BRICK_SIZE.y, 1.0),
+            ..default()
+          },
+          ..default()
+        },
+        MatchEntity,
+      ))
+      .id();
+    bricks.insert(brick_id, brick);
   }
+  bricks
 }
 
-#[derive(Default)]
-pub(crate) struct PaddleState {
-  current_input: PaddleInput,
-}
-
-pub(crate) fn move_paddle(
+/// Moves our paddle right away and sends the input stamped with its tick.
+fn move_paddle(
   client: Res<Client>,
   keyboard_input: Res<Input<KeyCode>>,
-  mut local: Local<PaddleState>,
+  mut session: ResMut<Session>,
+  mut paddles: Query<(&Paddle, &mut Transform)>,
 ) {
-  let mut paddle_input = PaddleInput::None;
+  let slot = session.slot;
+  let Some(predictor) = session.predictor.as_mut() else {
+    return;
+  };
+  let Some(connection) = client.get_connection().filter(|c| c.is_connected()) else {
+    return;
+  };
 
+  let mut input = PaddleInput::None;
   if keyboard_input.pressed(KeyCode::Left) {
-    paddle_input = PaddleInput::Left;
+    input = PaddleInput::Left;
   }
-
   if keyboard_input.pressed(KeyCode::Right) {
-    paddle_input = PaddleInput::Right;
+    input = PaddleInput::Right;
   }
+  let tick = predictor.apply(input);
+  connection.try_send_message_on(ChannelId::Unreliable, ClientMessage::Input { tick, input });
 
-  if local.current_input != paddle_input {
-    client
-      .connection()
-      .try_send_message(ClientMessage::PaddleInput {
-        input: paddle_input.clone(),
-      });
-    local.current_input = paddle_input;
+  for (paddle, mut transform) in &mut paddles {
+    if paddle.0 == slot {
+      transform.translation.x = predictor.x();
+    }
   }
 }
 
-pub(crate) fn update_scoreboard(
-  scoreboard: Res<Scoreboard>,
-  mut query: Query<&mut Text, With<Score>>,
-) {
+fn update_scoreboard(session: Res<Session>, mut query: Query<&mut Text, With<Score>>) {
+  let Some(state) = session.state.as_ref() else {
+    return;
+  };
+  // Our bricks count for us, everybody else's against us
+  let own = state.scores[session.slot as usize] as i64;
+  let others: i64 = state.scores.iter().map(|s| *s as i64).sum::<i64>() - own;
+  let score = own - others;
   let mut text = query.single_mut();
-  text.sections[1].value = scoreboard.score.to_string();
-  text.sections[1].style.color = ball_color_from_bool(scoreboard.score >= 0);
+  text.sections[1].value = if session.ended {
+    format!("{} (match over)", score)
+  } else {
+    score.to_string()
+  };
+  text.sections[1].style.color = ball_color_from_bool(score >= 0);
 }
 
-pub(crate) fn play_collision_sound(
+fn play_collision_sound(
   mut commands: Commands,
   mut collision_events: EventReader<CollisionEvent>,
   sound: Res<CollisionSound>,
@@ -336,7 +456,7 @@ pub(crate) fn play_collision_sound(
   }
 }
 
-pub(crate) fn setup_main_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn setup_main_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
   // Camera
   commands.spawn(Camera2dBundle::default());
 
@@ -382,7 +502,8 @@ pub(crate) fn setup_main_menu(mut commands: Commands, asset_server: Res<AssetSer
     });
 }
 
-pub(crate) fn handle_menu_buttons(
+#[allow(clippy::type_complexity)]
+fn handle_menu_buttons(
   mut interaction_query: Query<
     (&Interaction, &mut BackgroundColor, &MenuItem),
     (Changed<Interaction>, With<Button>),
@@ -408,13 +529,13 @@ pub(crate) fn handle_menu_buttons(
   }
 }
 
-pub(crate) fn teardown_main_menu(mut commands: Commands, query: Query<Entity, With<Button>>) {
+fn teardown_main_menu(mut commands: Commands, query: Query<Entity, With<Button>>) {
   for entity in query.iter() {
     commands.entity(entity).despawn_recursive();
   }
 }
 
-pub(crate) fn setup_breakout(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn setup_breakout(mut commands: Commands, asset_server: Res<AssetServer>) {
   // Sound
   let ball_collision_sound = asset_server.load(COLLISION_SOUND_EFFECT);
   commands.insert_resource(CollisionSound(ball_collision_sound));
@@ -452,13 +573,6 @@ pub(crate) fn setup_breakout(mut commands: Commands, asset_server: Res<AssetServ
   commands.spawn(WallBundle::new(WallLocation::Top));
 }
 
-pub(crate) fn apply_velocity(mut query: Query<(&mut Transform, &Velocity), With<Ball>>) {
-  for (mut transform, velocity) in &mut query {
-    transform.translation.x += velocity.x * TIME_STEP;
-    transform.translation.y += velocity.y * TIME_STEP;
-  }
-}
-
 fn ball_color_from_bool(owned: bool) -> Color {
   if owned {
     BALL_COLOR
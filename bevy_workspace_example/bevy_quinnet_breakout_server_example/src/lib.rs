@@ -1 +1,87 @@
+//! A simplified implementation of the classic game "Breakout".
+//! => Original example by Bevy, modified for Bevy Quinnet to add a multiplayer versus mode with
+//! lobbies, an authoritative server and client-side prediction.
 
+use std::net::{IpAddr, Ipv4Addr};
+
+use bevy::prelude::{Vec2, Vec3};
+
+pub mod bot;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod lobby;
+pub mod prediction;
+pub mod protocol;
+pub mod server;
+pub mod sim;
+
+pub const SERVER_HOST: &str = "127.0.0.1";
+pub const LOCAL_BIND_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
+pub const SERVER_PORT: u16 = 6000;
+
+// Defines the amount of time that should elapse between each physics step.
+pub const TIME_STEP: f32 = 1.0 / 60.0;
+
+// These constants are defined in `Transform` units.
+// Using the default 2D camera they correspond 1:1 with screen pixels.
+const PADDLE_SIZE: Vec3 = Vec3::new(120.0, 20.0, 0.0);
+const GAP_BETWEEN_PADDLE_AND_FLOOR: f32 = 60.0;
+const PADDLE_SPEED: f32 = 500.0;
+// How close can the paddle get to the wall
+const PADDLE_PADDING: f32 = 10.0;
+
+const BALL_SIZE: Vec3 = Vec3::new(30.0, 30.0, 0.0);
+const BALL_SPEED: f32 = 400.0;
+
+const WALL_THICKNESS: f32 = 10.0;
+// x coordinates
+const LEFT_WALL: f32 = -450.;
+const RIGHT_WALL: f32 = 450.;
+// y coordinates
+const BOTTOM_WALL: f32 = -300.;
+const TOP_WALL: f32 = 300.;
+
+const BRICK_SIZE: Vec2 = Vec2::new(100., 30.);
+// These values are exact
+const GAP_BETWEEN_PADDLE_AND_BRICKS: f32 = 140.0;
+const GAP_BETWEEN_BRICKS: f32 = 5.0;
+// These values are lower bounds, as the number of bricks is computed
+const GAP_BETWEEN_BRICKS_AND_SIDES: f32 = 20.0;
+
+pub type BrickId = u64;
+
+/// Which side of the arena is this wall located on?
+enum WallLocation {
+  Left,
+  Right,
+  Bottom,
+  Top,
+}
+
+impl WallLocation {
+  fn position(&self) -> Vec2 {
+    match self {
+      WallLocation::Left => Vec2::new(LEFT_WALL, 0.),
+      WallLocation::Right => Vec2::new(RIGHT_WALL, 0.),
+      WallLocation::Bottom => Vec2::new(0., BOTTOM_WALL),
+      WallLocation::Top => Vec2::new(0., TOP_WALL),
+    }
+  }
+
+  fn size(&self) -> Vec2 {
+    let arena_height = TOP_WALL - BOTTOM_WALL;
+    let arena_width = RIGHT_WALL - LEFT_WALL;
+    // Make sure we haven't messed up our constants
+    assert!(arena_height > 0.0);
+    assert!(arena_width > 0.0);
+
+    match self {
+      WallLocation::Left | WallLocation::Right => {
+        Vec2::new(WALL_THICKNESS, arena_height + WALL_THICKNESS)
+      }
+      WallLocation::Bottom | WallLocation::Top => {
+        Vec2::new(arena_width + WALL_THICKNESS, WALL_THICKNESS)
+      }
+    }
+  }
+}
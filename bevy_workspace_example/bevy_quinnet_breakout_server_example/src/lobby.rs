@@ -0,0 +1,679 @@
+//! Rooms, seats and sessions, independent of the transport: every call queues the messages to
+//! send in an outbox that [`crate::server`] drains onto the network.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use bevy::prelude::Resource;
+use bevy_quinnet::shared::ClientId;
+
+use crate::{
+  protocol::{
+    ClientMessage, PaddleInput, RoomId, RoomInfo, RoomState, ServerMessage, SessionToken, Slot,
+    Tick,
+  },
+  sim::{MatchConfig, MatchState, SimEvent},
+};
+
+/// Inputs queued beyond this are dropped from the front, so a client that sends too fast cannot
+/// build up lag.
+const MAX_BUFFERED_INPUTS: usize = 8;
+
+/// Seats number their inputs from 0 as the match starts and one per step, like the match ticks,
+/// so an input numbered this far past the match tick is dropped: taking it would leave the seat
+/// ignoring every input sent honestly until the match caught up.
+const MAX_INPUT_LEAD: Tick = 64;
+
+#[derive(Debug, Clone)]
+pub struct LobbyConfig {
+  pub players_per_room: usize,
+  pub brick_rows: Option<usize>,
+  pub max_ticks: Tick,
+  /// A match nobody is connected to is dropped after this many ticks.
+  pub abandon_after: Tick,
+}
+
+impl Default for LobbyConfig {
+  fn default() -> Self {
+    LobbyConfig {
+      players_per_room: 2,
+      brick_rows: None,
+      max_ticks: MatchConfig::default().max_ticks,
+      abandon_after: 60 * 60,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+  Reliable,
+  Unreliable,
+}
+
+#[derive(Debug, Clone)]
+pub struct Outgoing {
+  pub to: ClientId,
+  pub delivery: Delivery,
+  pub message: ServerMessage,
+}
+
+#[derive(Debug)]
+struct Seat {
+  token: SessionToken,
+  client: Option<ClientId>,
+  inputs: VecDeque<(Tick, PaddleInput)>,
+  last_received: Option<Tick>,
+  current: PaddleInput,
+  ack: Option<Tick>,
+}
+
+impl Seat {
+  fn new(token: SessionToken, client: ClientId) -> Seat {
+    Seat {
+      token,
+      client: Some(client),
+      inputs: VecDeque::new(),
+      last_received: None,
+      current: PaddleInput::None,
+      ack: None,
+    }
+  }
+
+  fn next_input_tick(&self) -> Tick {
+    self.last_received.map_or(0, |tick| tick.saturating_add(1))
+  }
+
+  /// The input for this step: the next queued one, or the last one again if none arrived.
+  fn take_input(&mut self) -> PaddleInput {
+    if let Some((tick, input)) = self.inputs.pop_front() {
+      self.current = input;
+      self.ack = Some(tick);
+    }
+    self.current
+  }
+}
+
+#[derive(Debug)]
+enum Phase {
+  Waiting,
+  Playing(Box<MatchState>),
+}
+
+#[derive(Debug)]
+struct Room {
+  id: RoomId,
+  name: String,
+  seats: Vec<Option<Seat>>,
+  phase: Phase,
+  idle_ticks: Tick,
+}
+
+impl Room {
+  fn info(&self) -> RoomInfo {
+    RoomInfo {
+      id: self.id,
+      name: self.name.clone(),
+      players: self.seats.iter().flatten().count(),
+      capacity: self.seats.len(),
+      state: match self.phase {
+        Phase::Waiting => RoomState::Waiting,
+        Phase::Playing(_) => RoomState::Playing,
+      },
+    }
+  }
+
+  fn clients(&self) -> impl Iterator<Item = ClientId> + '_ {
+    self.seats.iter().flatten().filter_map(|seat| seat.client)
+  }
+
+  fn is_full(&self) -> bool {
+    self.seats.iter().all(Option::is_some)
+  }
+}
+
+#[derive(Resource)]
+pub struct Lobby {
+  config: LobbyConfig,
+  rooms: BTreeMap<RoomId, Room>,
+  clients: HashMap<ClientId, (RoomId, Slot)>,
+  sessions: HashMap<SessionToken, (RoomId, Slot)>,
+  next_room_id: RoomId,
+  outbox: Vec<Outgoing>,
+}
+
+impl Lobby {
+  pub fn new(config: LobbyConfig) -> Lobby {
+    assert!(config.players_per_room > 0);
+    Lobby {
+      config,
+      rooms: BTreeMap::new(),
+      clients: HashMap::new(),
+      sessions: HashMap::new(),
+      next_room_id: 1,
+      outbox: vec![],
+    }
+  }
+
+  pub fn rooms(&self) -> Vec<RoomInfo> {
+    self.rooms.values().map(Room::info).collect()
+  }
+
+  /// Messages queued since the last call.
+  pub fn drain(&mut self) -> Vec<Outgoing> {
+    std::mem::take(&mut self.outbox)
+  }
+
+  pub fn connect(&mut self, client: ClientId) {
+    let rooms = self.rooms();
+    self.send(client, ServerMessage::RoomList { rooms });
+  }
+
+  /// Before the match a seat is given up at once; during it the seat is kept for a [`Rejoin`].
+  ///
+  /// [`Rejoin`]: ClientMessage::Rejoin
+  pub fn disconnect(&mut self, client: ClientId) {
+    let Some((room_id, slot)) = self.clients.remove(&client) else {
+      return;
+    };
+    let room = self.rooms.get_mut(&room_id).unwrap();
+    match room.phase {
+      Phase::Waiting => self.vacate(room_id, slot),
+      Phase::Playing(_) => {
+        if let Some(seat) = room.seats[slot as usize].as_mut() {
+          seat.client = None;
+          seat.inputs.clear();
+          seat.current = PaddleInput::None;
+        }
+      }
+    }
+  }
+
+  pub fn handle(&mut self, client: ClientId, message: ClientMessage) {
+    match message {
+      ClientMessage::ListRooms => self.connect(client),
+      ClientMessage::CreateRoom { name } => {
+        if self.clients.contains_key(&client) {
+          return self.error(client, "already in a room");
+        }
+        let id = self.next_room_id;
+        self.next_room_id += 1;
+        let seats = (0 .. self.config.players_per_room).map(|_| None).collect();
+        self.rooms.insert(
+          id,
+          Room {
+            id,
+            name,
+            seats,
+            phase: Phase::Waiting,
+            idle_ticks: 0,
+          },
+        );
+        self.take_seat(client, id);
+      }
+      ClientMessage::JoinRoom { room } => {
+        if self.clients.contains_key(&client) {
+          return self.error(client, "already in a room");
+        }
+        match self.rooms.get(&room) {
+          None => self.error(client, "no such room"),
+          Some(r) if matches!(r.phase, Phase::Playing(_)) => {
+            self.error(client, "the match has already started")
+          }
+          Some(r) if r.is_full() => self.error(client, "the room is full"),
+          Some(_) => self.take_seat(client, room),
+        }
+      }
+      ClientMessage::LeaveRoom => {
+        if let Some((room_id, slot)) = self.clients.remove(&client) {
+          let room = self.rooms.get_mut(&room_id).unwrap();
+          if let Some(seat) = room.seats[slot as usize].as_mut() {
+            seat.client = None;
+            self.sessions.remove(&seat.token);
+          }
+          if matches!(room.phase, Phase::Waiting) {
+            self.vacate(room_id, slot);
+          }
+        }
+        self.connect(client);
+      }
+      ClientMessage::Rejoin { token } => self.rejoin(client, token),
+      ClientMessage::Input { tick, input } => {
+        let Some((room_id, slot)) = self.clients.get(&client) else {
+          return;
+        };
+        let room = self.rooms.get_mut(room_id).unwrap();
+        let Phase::Playing(state) = &room.phase else {
+          return;
+        };
+        if tick > state.tick.saturating_add(MAX_INPUT_LEAD) {
+          return;
+        }
+        let Some(seat) = room.seats[*slot as usize].as_mut() else {
+          return;
+        };
+        // Inputs travel unreliably, so late duplicates and reordering are expected.
+        if seat.last_received.is_some_and(|last| tick <= last) {
+          return;
+        }
+        seat.last_received = Some(tick);
+        seat.inputs.push_back((tick, input));
+        if seat.inputs.len() > MAX_BUFFERED_INPUTS {
+          seat.inputs.pop_front();
+        }
+      }
+    }
+  }
+
+  /// Steps every running match once and queues snapshots.
+  pub fn tick(&mut self) {
+    let mut finished = vec![];
+    for room in self.rooms.values_mut() {
+      let Phase::Playing(state) = &mut room.phase else {
+        continue;
+      };
+      if room
+        .seats
+        .iter()
+        .flatten()
+        .all(|seat| seat.client.is_none())
+      {
+        room.idle_ticks += 1;
+        if room.idle_ticks >= self.config.abandon_after {
+          finished.push(room.id);
+        }
+        continue;
+      }
+      room.idle_ticks = 0;
+
+      let inputs: Vec<PaddleInput> = room
+        .seats
+        .iter_mut()
+        .map(|seat| seat.as_mut().map_or(PaddleInput::None, Seat::take_input))
+        .collect();
+      let events = state.step(&inputs);
+      let clients: Vec<ClientId> = room
+        .seats
+        .iter()
+        .flatten()
+        .filter_map(|s| s.client)
+        .collect();
+
+      for event in events {
+        if let SimEvent::BrickDestroyed { by, brick_id } = event {
+          for client in &clients {
+            self.outbox.push(Outgoing {
+              to: *client,
+              delivery: Delivery::Reliable,
+              message: ServerMessage::BrickDestroyed {
+                tick: state.tick,
+                by,
+                brick_id,
+              },
+            });
+          }
+        }
+      }
+
+      let paddles: Vec<f32> = state.paddles.iter().map(|p| p.position.x).collect();
+      for seat in room.seats.iter().flatten() {
+        if let Some(client) = seat.client {
+          self.outbox.push(Outgoing {
+            to: client,
+            delivery: Delivery::Unreliable,
+            message: ServerMessage::Snapshot {
+              tick: state.tick,
+              ack: seat.ack,
+              paddles: paddles.clone(),
+              balls: state.balls.clone(),
+            },
+          });
+        }
+      }
+
+      if state.is_over() {
+        for client in &clients {
+          self.outbox.push(Outgoing {
+            to: *client,
+            delivery: Delivery::Reliable,
+            message: ServerMessage::MatchEnded {
+              tick: state.tick,
+              scores: state.scores.clone(),
+              winner: state.winner(),
+            },
+          });
+        }
+        finished.push(room.id);
+      }
+    }
+
+    for room_id in finished {
+      self.close(room_id);
+    }
+  }
+
+  fn take_seat(&mut self, client: ClientId, room_id: RoomId) {
+    let token = loop {
+      let token = rand::random::<SessionToken>();
+      if !self.sessions.contains_key(&token) {
+        break token;
+      }
+    };
+    let room = self.rooms.get_mut(&room_id).unwrap();
+    let slot = room.seats.iter().position(Option::is_none).unwrap();
+    room.seats[slot] = Some(Seat::new(token, client));
+    let slot = slot as Slot;
+    self.clients.insert(client, (room_id, slot));
+    self.sessions.insert(token, (room_id, slot));
+
+    let info = room.info();
+    let others: Vec<ClientId> = room.clients().filter(|c| *c != client).collect();
+    self.send(
+      client,
+      ServerMessage::Joined {
+        room: info.clone(),
+        slot,
+        token,
+        next_input_tick: 0,
+      },
+    );
+    for other in others {
+      self.send(other, ServerMessage::RoomChanged { room: info.clone() });
+    }
+
+    let room = self.rooms.get_mut(&room_id).unwrap();
+    if room.is_full() {
+      let state = MatchState::new(&MatchConfig {
+        players: room.seats.len(),
+        brick_rows: self.config.brick_rows,
+        max_ticks: self.config.max_ticks,
+      });
+      let clients: Vec<ClientId> = room.clients().collect();
+      for client in clients {
+        self.send(
+          client,
+          ServerMessage::MatchStarted {
+            state: state.clone(),
+          },
+        );
+      }
+      let room = self.rooms.get_mut(&room_id).unwrap();
+      room.phase = Phase::Playing(Box::new(state));
+    }
+  }
+
+  fn rejoin(&mut self, client: ClientId, token: SessionToken) {
+    let Some((room_id, slot)) = self.sessions.get(&token).copied() else {
+      return self.error(client, "unknown or expired session");
+    };
+    if let Some(previous) = self.clients.get(&client) {
+      if *previous != (room_id, slot) {
+        return self.error(client, "already in a room");
+      }
+    }
+    let room = self.rooms.get_mut(&room_id).unwrap();
+    let seat = room.seats[slot as usize].as_mut().unwrap();
+    // The old connection may not have been noticed as lost yet.
+    if let Some(old) = seat.client.replace(client) {
+      if old != client {
+        self.clients.remove(&old);
+      }
+    }
+    let next_input_tick = seat.next_input_tick();
+    self.clients.insert(client, (room_id, slot));
+
+    let info = room.info();
+    let state = match &room.phase {
+      Phase::Playing(state) => Some((**state).clone()),
+      Phase::Waiting => None,
+    };
+    self.send(
+      client,
+      ServerMessage::Joined {
+        room: info,
+        slot,
+        token,
+        next_input_tick,
+      },
+    );
+    if let Some(state) = state {
+      self.send(client, ServerMessage::MatchStarted { state });
+    }
+  }
+
+  fn vacate(&mut self, room_id: RoomId, slot: Slot) {
+    let room = self.rooms.get_mut(&room_id).unwrap();
+    if let Some(seat) = room.seats[slot as usize].take() {
+      self.sessions.remove(&seat.token);
+    }
+    if room.seats.iter().all(Option::is_none) {
+      self.rooms.remove(&room_id);
+      return;
+    }
+    let info = room.info();
+    let clients: Vec<ClientId> = room.clients().collect();
+    for client in clients {
+      self.send(client, ServerMessage::RoomChanged { room: info.clone() });
+    }
+  }
+
+  fn close(&mut self, room_id: RoomId) {
+    if let Some(room) = self.rooms.remove(&room_id) {
+      for seat in room.seats.into_iter().flatten() {
+        self.sessions.remove(&seat.token);
+        if let Some(client) = seat.client {
+          self.clients.remove(&client);
+        }
+      }
+    }
+  }
+
+  fn send(&mut self, to: ClientId, message: ServerMessage) {
+    self.outbox.push(Outgoing {
+      to,
+      delivery: Delivery::Reliable,
+      message,
+    });
+  }
+
+  fn error(&mut self, to: ClientId, message: &str) {
+    self.send(
+      to,
+      ServerMessage::Error {
+        message: message.to_string(),
+      },
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn lobby() -> Lobby {
+    Lobby::new(LobbyConfig {
+      players_per_room: 2,
+      brick_rows: Some(1),
+      max_ticks: 100,
+      abandon_after: 10,
+    })
+  }
+
+  fn joined(outgoing: &[Outgoing], client: ClientId) -> (RoomId, Slot, SessionToken, Tick) {
+    outgoing
+      .iter()
+      .find_map(|o| match &o.message {
+        ServerMessage::Joined {
+          room,
+          slot,
+          token,
+          next_input_tick,
+        } if o.to == client => Some((room.id, *slot, *token, *next_input_tick)),
+        _ => None,
+      })
+      .expect("no Joined message")
+  }
+
+  fn errors(outgoing: &[Outgoing], client: ClientId) -> usize {
+    outgoing
+      .iter()
+      .filter(|o| o.to == client && matches!(o.message, ServerMessage::Error { .. }))
+      .count()
+  }
+
+  fn start(lobby: &mut Lobby, a: ClientId, b: ClientId) -> RoomId {
+    lobby.handle(a, ClientMessage::CreateRoom { name: "r".into() });
+    let room = lobby.rooms().last().unwrap().id;
+    lobby.handle(b, ClientMessage::JoinRoom { room });
+    room
+  }
+
+  #[test]
+  fn rooms_start_when_full_and_run_side_by_side() {
+    let mut lobby = lobby();
+    let first = start(&mut lobby, 1, 2);
+    let second = start(&mut lobby, 3, 4);
+    assert_ne!(first, second);
+    let out = lobby.drain();
+    assert_eq!(joined(&out, 2).1, 1);
+    for client in 1 ..= 4 {
+      assert!(out
+        .iter()
+        .any(|o| o.to == client && matches!(o.message, ServerMessage::MatchStarted { .. })));
+    }
+
+    lobby.handle(5, ClientMessage::JoinRoom { room: first });
+    lobby.handle(1, ClientMessage::CreateRoom { name: "x".into() });
+    let out = lobby.drain();
+    assert_eq!(errors(&out, 5), 1);
+    assert_eq!(errors(&out, 1), 1);
+
+    lobby.tick();
+    let snapshots = lobby
+      .drain()
+      .into_iter()
+      .filter(|o| matches!(o.message, ServerMessage::Snapshot { tick: 1, .. }))
+      .count();
+    assert_eq!(snapshots, 4);
+  }
+
+  #[test]
+  fn leaving_before_the_match_frees_the_seat() {
+    let mut lobby = lobby();
+    lobby.handle(1, ClientMessage::CreateRoom { name: "r".into() });
+    lobby.handle(2, ClientMessage::CreateRoom { name: "s".into() });
+    lobby.disconnect(1);
+    assert_eq!(lobby.rooms().len(), 1);
+    let room = lobby.rooms()[0].id;
+    lobby.handle(3, ClientMessage::JoinRoom { room });
+    assert_eq!(lobby.rooms()[0].state, RoomState::Playing);
+  }
+
+  #[test]
+  fn inputs_are_applied_in_order_and_acknowledged() {
+    let mut lobby = lobby();
+    start(&mut lobby, 1, 2);
+    lobby.drain();
+    for tick in [0, 1, 1, 0, 2] {
+      lobby.handle(
+        1,
+        ClientMessage::Input {
+          tick,
+          input: PaddleInput::Left,
+        },
+      );
+    }
+    let mut acks = vec![];
+    for _ in 0 .. 4 {
+      lobby.tick();
+      acks.extend(lobby.drain().into_iter().filter_map(|o| match o.message {
+        ServerMessage::Snapshot { ack, .. } if o.to == 1 => Some(ack),
+        _ => None,
+      }));
+    }
+    assert_eq!(acks, [Some(0), Some(1), Some(2), Some(2)]);
+  }
+
+  #[test]
+  fn inputs_far_ahead_of_the_match_are_dropped() {
+    let mut lobby = lobby();
+    start(&mut lobby, 1, 2);
+    let (_, _, token, _) = joined(&lobby.drain(), 1);
+    for tick in [Tick::MAX, MAX_INPUT_LEAD + 1, 0] {
+      lobby.handle(
+        1,
+        ClientMessage::Input {
+          tick,
+          input: PaddleInput::Left,
+        },
+      );
+    }
+    lobby.tick();
+    let acks: Vec<_> = lobby
+      .drain()
+      .into_iter()
+      .filter_map(|o| match o.message {
+        ServerMessage::Snapshot { ack, .. } if o.to == 1 => Some(ack),
+        _ => None,
+      })
+      .collect();
+    assert_eq!(acks, [Some(0)]);
+
+    lobby.disconnect(1);
+    lobby.handle(10, ClientMessage::Rejoin { token });
+    assert_eq!(joined(&lobby.drain(), 10).3, 1);
+  }
+
+  #[test]
+  fn rejoining_restores_the_seat() {
+    let mut lobby = lobby();
+    let room = start(&mut lobby, 1, 2);
+    let (_, slot, token, _) = joined(&lobby.drain(), 1);
+    lobby.handle(
+      1,
+      ClientMessage::Input {
+        tick: 7,
+        input: PaddleInput::Right,
+      },
+    );
+    lobby.tick();
+    lobby.disconnect(1);
+    lobby.tick();
+    lobby.drain();
+
+    lobby.handle(10, ClientMessage::Rejoin { token: 42 });
+    assert_eq!(errors(&lobby.drain(), 10), 1);
+
+    lobby.handle(10, ClientMessage::Rejoin { token });
+    let out = lobby.drain();
+    assert_eq!(joined(&out, 10), (room, slot, token, 8));
+    let state = out.iter().find_map(|o| match &o.message {
+      ServerMessage::MatchStarted { state } => Some(state.clone()),
+      _ => None,
+    });
+    assert_eq!(state.unwrap().tick, 2);
+  }
+
+  #[test]
+  fn matches_end_and_abandoned_ones_are_dropped() {
+    let mut lobby = lobby();
+    start(&mut lobby, 1, 2);
+    let mut ended = 0;
+    for _ in 0 .. 100 {
+      lobby.tick();
+      ended += lobby
+        .drain()
+        .iter()
+        .filter(|o| matches!(o.message, ServerMessage::MatchEnded { .. }))
+        .count();
+    }
+    assert_eq!(ended, 2);
+    assert!(lobby.rooms().is_empty());
+
+    start(&mut lobby, 1, 2);
+    lobby.disconnect(1);
+    lobby.disconnect(2);
+    for _ in 0 .. 10 {
+      lobby.tick();
+    }
+    assert!(lobby.rooms().is_empty());
+  }
+}
@@ -0,0 +1,114 @@
+use std::collections::VecDeque;
+
+use crate::{
+  protocol::{PaddleInput, Tick},
+  sim::{move_paddle, Paddle},
+};
+
+/// Moves the local paddle as soon as input is given, then corrects it when the server says where
+/// it really is: the server position with every input it has not applied yet replayed on top.
+#[derive(Debug, Clone)]
+pub struct Predictor {
+  paddle: Paddle,
+  pending: VecDeque<(Tick, PaddleInput)>,
+  next_tick: Tick,
+  /// How many snapshots disagreed with what had been predicted.
+  pub corrections: u32,
+}
+
+impl Predictor {
+  pub fn new(paddle: Paddle, next_tick: Tick) -> Predictor {
+    Predictor {
+      paddle,
+      pending: VecDeque::new(),
+      next_tick,
+      corrections: 0,
+    }
+  }
+
+  pub fn x(&self) -> f32 {
+    self.paddle.position.x
+  }
+
+  /// Applies `input` locally and returns the tick to send it with.
+  pub fn apply(&mut self, input: PaddleInput) -> Tick {
+    let tick = self.next_tick;
+    self.next_tick += 1;
+    self.paddle.position.x = move_paddle(&self.paddle, input);
+    self.pending.push_back((tick, input));
+    tick
+  }
+
+  /// Takes the server's paddle position after it applied inputs up to `ack`.
+  pub fn reconcile(&mut self, ack: Option<Tick>, server_x: f32) {
+    if let Some(ack) = ack {
+      while self.pending.front().is_some_and(|(tick, _)| *tick <= ack) {
+        self.pending.pop_front();
+      }
+    }
+    let predicted = self.paddle.position.x;
+    self.paddle.position.x = server_x;
+    for (_, input) in &self.pending {
+      self.paddle.position.x = move_paddle(&self.paddle, *input);
+    }
+    if (self.paddle.position.x - predicted).abs() > 0.01 {
+      self.corrections += 1;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::sim::{MatchConfig, MatchState};
+
+  fn paddle() -> Paddle {
+    MatchState::new(&MatchConfig::default()).paddles[0]
+  }
+
+  #[test]
+  fn agrees_with_the_server_when_nothing_is_lost() {
+    let mut server = paddle();
+    let mut predictor = Predictor::new(server, 0);
+    let inputs = [
+      PaddleInput::Left,
+      PaddleInput::Left,
+      PaddleInput::Right,
+      PaddleInput::None,
+    ];
+
+    // The server is two ticks behind the client.
+    let mut sent = VecDeque::new();
+    for input in inputs.iter().cycle().take(40) {
+      sent.push_back((predictor.apply(*input), *input));
+      if sent.len() > 2 {
+        let (tick, input) = sent.pop_front().unwrap();
+        server.position.x = move_paddle(&server, input);
+        predictor.reconcile(Some(tick), server.position.x);
+      }
+    }
+    assert_eq!(predictor.corrections, 0);
+    assert_eq!(predictor.pending.len(), 2);
+  }
+
+  #[test]
+  fn snaps_to_the_server_and_replays_pending_inputs() {
+    let start = paddle();
+    let mut predictor = Predictor::new(start, 10);
+    assert_eq!(predictor.apply(PaddleInput::Right), 10);
+    assert_eq!(predictor.apply(PaddleInput::Right), 11);
+
+    // The server never got tick 10 and moved the paddle elsewhere.
+    let mut server = start;
+    server.position.x -= 50.0;
+    predictor.reconcile(None, server.position.x);
+    server.position.x = move_paddle(&server, PaddleInput::Right);
+    server.position.x = move_paddle(&server, PaddleInput::Right);
+    assert_eq!(predictor.x(), server.position.x);
+    assert_eq!(predictor.corrections, 1);
+
+    predictor.reconcile(Some(11), start.position.x);
+    assert_eq!(predictor.x(), start.position.x);
+    assert!(predictor.pending.is_empty());
+  }
+}
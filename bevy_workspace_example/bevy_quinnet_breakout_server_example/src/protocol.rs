@@ -1,58 +1,102 @@
-use bevy::prelude::{Entity, Vec2, Vec3};
-use bevy_quinnet::shared::ClientId;
 use serde::{Deserialize, Serialize};
 
-use crate::BrickId;
+use crate::{
+  sim::{Ball, MatchState},
+  BrickId,
+};
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
-pub(crate) enum PaddleInput {
+/// Server simulation step. Inputs carry the client's own tick count instead, see
+/// [`ClientMessage::Input`].
+pub type Tick = u32;
+pub type RoomId = u32;
+/// Seat of a player in a room, from 0 to the room's capacity.
+pub type Slot = u8;
+/// Handed out when a player takes a seat; presenting it again after a disconnect gets the seat
+/// back.
+pub type SessionToken = u64;
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PaddleInput {
   #[default]
   None,
   Left,
   Right,
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum RoomState {
+  Waiting,
+  Playing,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RoomInfo {
+  pub id: RoomId,
+  pub name: String,
+  pub players: usize,
+  pub capacity: usize,
+  pub state: RoomState,
+}
+
 // Messages from clients
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub(crate) enum ClientMessage {
-  PaddleInput { input: PaddleInput },
+pub enum ClientMessage {
+  ListRooms,
+  CreateRoom {
+    name: String,
+  },
+  JoinRoom {
+    room: RoomId,
+  },
+  LeaveRoom,
+  Rejoin {
+    token: SessionToken,
+  },
+  /// `tick` counts up by one per client tick, starting at the `next_input_tick` of
+  /// [`ServerMessage::Joined`]. The server applies one input per simulation step.
+  Input {
+    tick: Tick,
+    input: PaddleInput,
+  },
 }
 
 // Messages from the server
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub(crate) enum ServerMessage {
-  InitClient {
-    client_id: ClientId,
-  },
-  SpawnPaddle {
-    owner_client_id: ClientId,
-    entity: Entity,
-    position: Vec3,
-  },
-  SpawnBall {
-    owner_client_id: ClientId,
-    entity: Entity,
-    position: Vec3,
-    direction: Vec2,
-  },
-  SpawnBricks {
-    offset: Vec2,
-    rows: usize,
-    columns: usize,
-  },
-  StartGame,
+pub enum ServerMessage {
+  RoomList {
+    rooms: Vec<RoomInfo>,
+  },
+  Joined {
+    room: RoomInfo,
+    slot: Slot,
+    token: SessionToken,
+    next_input_tick: Tick,
+  },
+  RoomChanged {
+    room: RoomInfo,
+  },
+  /// The whole match, sent when it starts and to players rejoining it.
+  MatchStarted {
+    state: MatchState,
+  },
+  /// Sent every tick, unreliably. `ack` is the last input of the recipient the server applied.
+  Snapshot {
+    tick: Tick,
+    ack: Option<Tick>,
+    paddles: Vec<f32>,
+    balls: Vec<Ball>,
+  },
   BrickDestroyed {
-    by_client_id: ClientId,
+    tick: Tick,
+    by: Slot,
     brick_id: BrickId,
   },
-  BallCollided {
-    owner_client_id: ClientId,
-    entity: Entity,
-    position: Vec3,
-    velocity: Vec2,
+  MatchEnded {
+    tick: Tick,
+    scores: Vec<u32>,
+    winner: Option<Slot>,
   },
-  PaddleMoved {
-    entity: Entity,
-    position: Vec3,
+  Error {
+    message: String,
   },
 }
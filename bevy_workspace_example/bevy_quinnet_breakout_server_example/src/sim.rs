@@ -0,0 +1,420 @@
+//! The game itself, without Bevy or networking: the server steps it, clients replay their own
+//! paddle with [`move_paddle`] to predict it.
+
+use bevy::prelude::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  protocol::{PaddleInput, Slot, Tick},
+  BrickId, WallLocation, BALL_SIZE, BALL_SPEED, BOTTOM_WALL, BRICK_SIZE, GAP_BETWEEN_BRICKS,
+  GAP_BETWEEN_BRICKS_AND_SIDES, GAP_BETWEEN_PADDLE_AND_BRICKS, GAP_BETWEEN_PADDLE_AND_FLOOR,
+  LEFT_WALL, PADDLE_PADDING, PADDLE_SIZE, PADDLE_SPEED, RIGHT_WALL, TIME_STEP, TOP_WALL,
+  WALL_THICKNESS,
+};
+
+const GAP_BETWEEN_PADDLE_AND_BALL: f32 = 35.;
+
+#[derive(Debug, Clone)]
+pub struct MatchConfig {
+  pub players: usize,
+  /// Fewer rows than fit in the arena, for short matches.
+  pub brick_rows: Option<usize>,
+  /// The match ends when all bricks are gone or after this many ticks.
+  pub max_ticks: Tick,
+}
+
+impl Default for MatchConfig {
+  fn default() -> Self {
+    MatchConfig {
+      players: 2,
+      brick_rows: None,
+      max_ticks: 3 * 60 * 60,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Paddle {
+  pub position: Vec2,
+  /// How far the paddle may move; with several players on one side each gets a lane.
+  pub min_x: f32,
+  pub max_x: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Ball {
+  pub position: Vec2,
+  pub velocity: Vec2,
+  pub last_hit_by: Slot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BrickLayout {
+  pub offset: Vec2,
+  pub rows: usize,
+  pub columns: usize,
+}
+
+impl BrickLayout {
+  pub fn len(&self) -> usize {
+    self.rows * self.columns
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  pub fn position(&self, brick_id: BrickId) -> Vec2 {
+    let row = brick_id as usize / self.columns;
+    let column = brick_id as usize % self.columns;
+    Vec2::new(
+      self.offset.x + column as f32 * (BRICK_SIZE.x + GAP_BETWEEN_BRICKS),
+      self.offset.y + row as f32 * (BRICK_SIZE.y + GAP_BETWEEN_BRICKS),
+    )
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimEvent {
+  BrickDestroyed { by: Slot, brick_id: BrickId },
+  BallCollided { ball: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatchState {
+  pub tick: Tick,
+  pub max_ticks: Tick,
+  /// Indexed by slot, like `balls` and `scores`.
+  pub paddles: Vec<Paddle>,
+  pub balls: Vec<Ball>,
+  pub bricks: BrickLayout,
+  /// Indexed by brick id.
+  pub alive: Vec<bool>,
+  pub scores: Vec<u32>,
+}
+
+/// Where `paddle` ends up after one tick of `input`.
+pub fn move_paddle(paddle: &Paddle, input: PaddleInput) -> f32 {
+  let direction = match input {
+    PaddleInput::None => return paddle.position.x,
+    PaddleInput::Left => -1.0,
+    PaddleInput::Right => 1.0,
+  };
+  let new_paddle_position = paddle.position.x + direction * PADDLE_SPEED * TIME_STEP;
+  new_paddle_position.clamp(paddle.min_x, paddle.max_x)
+}
+
+impl MatchState {
+  pub fn new(config: &MatchConfig) -> MatchState {
+    assert!(config.players > 0);
+    let mut paddles = Vec::with_capacity(config.players);
+    let mut balls = Vec::with_capacity(config.players);
+    // Even slots play from the bottom, odd slots from the top; players sharing a side split it
+    // into lanes.
+    for slot in 0 .. config.players {
+      let bottom = slot % 2 == 0;
+      let lanes = (config.players + usize::from(bottom)) / 2;
+      let lane = slot / 2;
+      let left = LEFT_WALL + WALL_THICKNESS / 2.0;
+      let lane_width = (RIGHT_WALL - WALL_THICKNESS / 2.0 - left) / lanes as f32;
+      let min_x = left + lane as f32 * lane_width + PADDLE_SIZE.x / 2.0 + PADDLE_PADDING;
+      let max_x = left + (lane + 1) as f32 * lane_width - PADDLE_SIZE.x / 2.0 - PADDLE_PADDING;
+      let x = (min_x + max_x) / 2.0;
+
+      let (paddle_y, ball_y, direction) = if bottom {
+        let y = BOTTOM_WALL + GAP_BETWEEN_PADDLE_AND_FLOOR;
+        (y, y + GAP_BETWEEN_PADDLE_AND_BALL, Vec2::new(0.5, -0.5))
+      } else {
+        let y = TOP_WALL - GAP_BETWEEN_PADDLE_AND_FLOOR;
+        (y, y - GAP_BETWEEN_PADDLE_AND_BALL, Vec2::new(-0.5, 0.5))
+      };
+      paddles.push(Paddle {
+        position: Vec2::new(x, paddle_y),
+        min_x,
+        max_x,
+      });
+      balls.push(Ball {
+        position: Vec2::new(x, ball_y),
+        velocity: direction.normalize() * BALL_SPEED,
+        last_hit_by: slot as Slot,
+      });
+    }
+
+    let bricks = brick_layout(config.brick_rows);
+    MatchState {
+      tick: 0,
+      max_ticks: config.max_ticks,
+      paddles,
+      balls,
+      alive: vec![true; bricks.len()],
+      bricks,
+      scores: vec![0; config.players],
+    }
+  }
+
+  pub fn players(&self) -> usize {
+    self.paddles.len()
+  }
+
+  pub fn bricks_left(&self) -> usize {
+    self.alive.iter().filter(|alive| **alive).count()
+  }
+
+  pub fn is_over(&self) -> bool {
+    self.tick >= self.max_ticks || self.bricks_left() == 0
+  }
+
+  /// The player with the most bricks, unless that is a tie.
+  pub fn winner(&self) -> Option<Slot> {
+    let best = *self.scores.iter().max()?;
+    let mut leaders = self.scores.iter().enumerate().filter(|(_, s)| **s == best);
+    let (slot, _) = leaders.next()?;
+    match leaders.next() {
+      Some(_) => None,
+      None => Some(slot as Slot),
+    }
+  }
+
+  /// Advances one tick. `inputs` is indexed by slot.
+  pub fn step(&mut self, inputs: &[PaddleInput]) -> Vec<SimEvent> {
+    for (paddle, input) in self.paddles.iter_mut().zip(inputs) {
+      paddle.position.x = move_paddle(paddle, *input);
+    }
+    for ball in &mut self.balls {
+      ball.position += ball.velocity * TIME_STEP;
+    }
+
+    let walls = [
+      WallLocation::Left,
+      WallLocation::Right,
+      WallLocation::Bottom,
+      WallLocation::Top,
+    ]
+    .map(|wall| (wall.position(), wall.size()));
+    let mut events = vec![];
+    for (index, ball) in self.balls.iter_mut().enumerate() {
+      let ball_size = BALL_SIZE.truncate();
+      let mut collisions = vec![];
+
+      collisions.extend(
+        walls
+          .iter()
+          .filter_map(|(position, size)| collide(ball.position, ball_size, *position, *size)),
+      );
+      for (slot, paddle) in self.paddles.iter().enumerate() {
+        if let Some(collision) = collide(
+          ball.position,
+          ball_size,
+          paddle.position,
+          PADDLE_SIZE.truncate(),
+        ) {
+          // When a ball hits a paddle, it belongs to that player
+          ball.last_hit_by = slot as Slot;
+          collisions.push(collision);
+        }
+      }
+      for (brick_id, alive) in self.alive.iter_mut().enumerate() {
+        if !*alive {
+          continue;
+        }
+        let brick_position = self.bricks.position(brick_id as BrickId);
+        if let Some(collision) = collide(ball.position, ball_size, brick_position, BRICK_SIZE) {
+          // Bricks should be destroyed on collision
+          *alive = false;
+          self.scores[ball.last_hit_by as usize] += 1;
+          events.push(SimEvent::BrickDestroyed {
+            by: ball.last_hit_by,
+            brick_id: brick_id as BrickId,
+          });
+          collisions.push(collision);
+        }
+      }
+
+      for collision in &collisions {
+        // only reflect if the ball's velocity is going in the opposite direction of the
+        // collision
+        match collision {
+          Collision::Left if ball.velocity.x > 0.0 => ball.velocity.x = -ball.velocity.x,
+          Collision::Right if ball.velocity.x < 0.0 => ball.velocity.x = -ball.velocity.x,
+          Collision::Top if ball.velocity.y < 0.0 => ball.velocity.y = -ball.velocity.y,
+          Collision::Bottom if ball.velocity.y > 0.0 => ball.velocity.y = -ball.velocity.y,
+          _ => {}
+        }
+      }
+      if !collisions.is_empty() {
+        events.push(SimEvent::BallCollided { ball: index });
+      }
+    }
+
+    self.tick += 1;
+    events
+  }
+}
+
+/// The brick grid, centred between the two sides. With `max_rows`, only that many rows (at most)
+/// are laid out.
+fn brick_layout(max_rows: Option<usize>) -> BrickLayout {
+  let total_width_of_bricks = (RIGHT_WALL - LEFT_WALL) - 2. * GAP_BETWEEN_BRICKS_AND_SIDES;
+  let bottom_edge_of_bricks =
+    BOTTOM_WALL + GAP_BETWEEN_PADDLE_AND_FLOOR + GAP_BETWEEN_PADDLE_AND_BRICKS;
+  let available_height_for_bricks = TOP_WALL
+    - bottom_edge_of_bricks
+    - (GAP_BETWEEN_PADDLE_AND_FLOOR + GAP_BETWEEN_PADDLE_AND_BRICKS);
+
+  assert!(total_width_of_bricks > 0.0);
+  assert!(available_height_for_bricks > 0.0);
+
+  // Given the space available, compute how many rows and columns of bricks we can fit
+  let n_columns = (total_width_of_bricks / (BRICK_SIZE.x + GAP_BETWEEN_BRICKS)).floor() as usize;
+  let mut n_rows =
+    (available_height_for_bricks / (BRICK_SIZE.y + GAP_BETWEEN_BRICKS)).floor() as usize;
+  if let Some(max_rows) = max_rows {
+    n_rows = n_rows.min(max_rows);
+  }
+  let height_occupied_by_bricks =
+    n_rows as f32 * (BRICK_SIZE.y + GAP_BETWEEN_BRICKS) - GAP_BETWEEN_BRICKS;
+  let n_vertical_gaps = n_columns - 1;
+
+  // Because we need to round the number of columns,
+  // the space on the top and sides of the bricks only captures a lower bound, not an exact value
+  let center_of_bricks = (LEFT_WALL + RIGHT_WALL) / 2.0;
+  let left_edge_of_bricks = center_of_bricks
+        // Space taken up by the bricks
+        - (n_columns as f32 / 2.0 * BRICK_SIZE.x)
+        // Space taken up by the gaps
+        - n_vertical_gaps as f32 / 2.0 * GAP_BETWEEN_BRICKS;
+
+  // In Bevy, the `translation` of an entity describes the center point,
+  // not its bottom-left corner
+  let offset_x = left_edge_of_bricks + BRICK_SIZE.x / 2.;
+  let offset_y = bottom_edge_of_bricks
+    + BRICK_SIZE.y / 2.
+    + (available_height_for_bricks - height_occupied_by_bricks) / 2.; // Offset so that both sides are at an equal distance of the bricks
+
+  BrickLayout {
+    offset: Vec2::new(offset_x, offset_y),
+    rows: n_rows,
+    columns: n_columns,
+  }
+}
+
+/// Where `a` hit `b`, from `a`'s point of view; the same rules as Bevy's `collide_aabb`, which
+/// needs the render crates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collision {
+  Left,
+  Right,
+  Top,
+  Bottom,
+  Inside,
+}
+
+pub fn collide(a_pos: Vec2, a_size: Vec2, b_pos: Vec2, b_size: Vec2) -> Option<Collision> {
+  let a_min = a_pos - a_size / 2.0;
+  let a_max = a_pos + a_size / 2.0;
+  let b_min = b_pos - b_size / 2.0;
+  let b_max = b_pos + b_size / 2.0;
+
+  // check to see if the two rectangles are intersecting
+  if a_min.x < b_max.x && a_max.x > b_min.x && a_min.y < b_max.y && a_max.y > b_min.y {
+    // check to see if we hit on the left or right side
+    let (x_collision, x_depth) = if a_min.x < b_min.x && a_max.x > b_min.x && a_max.x < b_max.x {
+      (Collision::Left, b_min.x - a_max.x)
+    } else if a_min.x > b_min.x && a_min.x < b_max.x && a_max.x > b_max.x {
+      (Collision::Right, a_min.x - b_max.x)
+    } else {
+      (Collision::Inside, -f32::INFINITY)
+    };
+
+    // check to see if we hit on the top or bottom side
+    let (y_collision, y_depth) = if a_min.y < b_min.y && a_max.y > b_min.y && a_max.y < b_max.y {
+      (Collision::Bottom, b_min.y - a_max.y)
+    } else if a_min.y > b_min.y && a_min.y < b_max.y && a_max.y > b_max.y {
+      (Collision::Top, a_min.y - b_max.y)
+    } else {
+      (Collision::Inside, -f32::INFINITY)
+    };
+
+    // if we had an "x" and a "y" collision, pick the "primary" side using penetration depth
+    if y_depth.abs() < x_depth.abs() {
+      Some(y_collision)
+    } else {
+      Some(x_collision)
+    }
+  } else {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn config(players: usize) -> MatchConfig {
+    MatchConfig {
+      players,
+      brick_rows: Some(2),
+      max_ticks: 60 * 60,
+    }
+  }
+
+  #[test]
+  fn lanes_do_not_overlap() {
+    for players in 1 ..= 6 {
+      let state = MatchState::new(&config(players));
+      for (slot, a) in state.paddles.iter().enumerate() {
+        assert!(a.min_x <= a.max_x, "{} players, slot {}", players, slot);
+        for b in &state.paddles[slot + 1 ..] {
+          if a.position.y == b.position.y {
+            assert!(a.max_x + PADDLE_SIZE.x <= b.min_x || b.max_x + PADDLE_SIZE.x <= a.min_x);
+          }
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn paddles_stay_in_their_lane() {
+    let mut state = MatchState::new(&config(4));
+    for _ in 0 .. 200 {
+      state.step(&[
+        PaddleInput::Left,
+        PaddleInput::Right,
+        PaddleInput::Right,
+        PaddleInput::Left,
+      ]);
+    }
+    assert_eq!(state.paddles[0].position.x, state.paddles[0].min_x);
+    assert_eq!(state.paddles[1].position.x, state.paddles[1].max_x);
+    assert_eq!(state.paddles[2].position.x, state.paddles[2].max_x);
+    assert_eq!(state.paddles[3].position.x, state.paddles[3].min_x);
+  }
+
+  #[test]
+  fn a_match_ends_and_every_brick_is_credited() {
+    let mut state = MatchState::new(&config(2));
+    let bricks = state.bricks.len();
+    let mut destroyed = 0;
+    while !state.is_over() {
+      let events = state.step(&[PaddleInput::None, PaddleInput::None]);
+      destroyed += events
+        .iter()
+        .filter(|e| matches!(e, SimEvent::BrickDestroyed { .. }))
+        .count();
+      for ball in &state.balls {
+        assert!(ball.position.x.abs() < RIGHT_WALL && ball.position.y.abs() < TOP_WALL);
+      }
+    }
+    assert_eq!(destroyed, bricks - state.bricks_left());
+    assert_eq!(state.scores.iter().sum::<u32>() as usize, destroyed);
+  }
+
+  #[test]
+  fn winner_needs_a_unique_best_score() {
+    let mut state = MatchState::new(&config(3));
+    state.scores = vec![3, 5, 1];
+    assert_eq!(state.winner(), Some(1));
+    state.scores = vec![5, 5, 1];
+    assert_eq!(state.winner(), None);
+  }
+}
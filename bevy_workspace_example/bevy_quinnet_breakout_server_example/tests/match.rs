@@ -0,0 +1,123 @@
+use std::{
+  net::{Ipv4Addr, SocketAddr, UdpSocket},
+  time::{Duration, Instant},
+};
+
+use bevy::prelude::App;
+use bevy_quinnet_breakout_server_example::{
+  bot::{self, bot_app, BotSettings, BotState, RoomChoice},
+  lobby::{Lobby, LobbyConfig},
+  protocol::RoomState,
+  server::headless_app,
+};
+
+const TICK_RATE: f32 = 300.0;
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+fn free_addr() -> SocketAddr {
+  let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+  socket.local_addr().unwrap()
+}
+
+fn start(mut app: App) -> App {
+  app.finish();
+  app.cleanup();
+  app
+}
+
+struct Harness {
+  server: App,
+  bots: Vec<App>,
+}
+
+impl Harness {
+  fn update(&mut self) {
+    self.server.update();
+    for bot in &mut self.bots {
+      bot.update();
+    }
+    std::thread::sleep(Duration::from_millis(1));
+  }
+
+  fn update_until(&mut self, what: &str, done: impl Fn(&Harness) -> bool) {
+    let start = Instant::now();
+    while !done(self) {
+      assert!(start.elapsed() < TIMEOUT, "timed out waiting for {}", what);
+      self.update();
+    }
+  }
+
+  fn add_bot(&mut self, server: SocketAddr, room: RoomChoice) -> usize {
+    self
+      .bots
+      .push(start(bot_app(BotSettings { server, room }, TICK_RATE)));
+    self.bots.len() - 1
+  }
+
+  fn bot(&self, index: usize) -> &BotState {
+    self.bots[index].world.resource::<BotState>()
+  }
+}
+
+#[test]
+fn bots_play_two_rooms_and_one_rejoins() {
+  let addr = free_addr();
+  let lobby = LobbyConfig {
+    players_per_room: 2,
+    brick_rows: Some(1),
+    max_ticks: 600,
+    ..Default::default()
+  };
+  let mut harness = Harness {
+    server: start(headless_app(addr, lobby, TICK_RATE)),
+    bots: vec![],
+  };
+
+  // Seat the bots one at a time so each room gets a creator and a joiner.
+  for room in ["one", "two"] {
+    let creator = harness.add_bot(addr, RoomChoice::Create(room.to_string()));
+    harness.update_until("a room", |h| h.bot(creator).room.is_some());
+    let joiner = harness.add_bot(addr, RoomChoice::Any);
+    harness.update_until("the match to start", |h| {
+      h.bot(creator).in_match() && h.bot(joiner).in_match()
+    });
+  }
+  let rooms = harness.server.world.resource::<Lobby>().rooms();
+  assert_eq!(rooms.len(), 2);
+  assert!(rooms.iter().all(|room| room.state == RoomState::Playing));
+
+  // Drop one player for a while, then let it rejoin with its token.
+  harness.update_until("snapshots", |h| h.bot(1).snapshots >= 30);
+  let slot = harness.bot(1).slot;
+  bot::disconnect(&mut harness.bots[1].world);
+  for _ in 0 .. 30 {
+    harness.update();
+  }
+  bot::reconnect(&mut harness.bots[1].world);
+  harness.update_until("all matches to end", |h| {
+    (0 .. 4).all(|i| h.bot(i).result.is_some())
+  });
+
+  let rejoined = harness.bot(1);
+  assert_eq!(rejoined.rejoins, 1);
+  assert_eq!(rejoined.slot, slot);
+  for index in 0 .. 4 {
+    let bot = harness.bot(index);
+    assert!(bot.errors.is_empty(), "{:?}", bot.errors);
+    assert!(bot.snapshots > 0);
+  }
+  for (a, b) in [(0, 1), (2, 3)] {
+    let (a, b) = (harness.bot(a), harness.bot(b));
+    assert_eq!(a.room.as_ref().unwrap().id, b.room.as_ref().unwrap().id);
+    assert_ne!(a.slot, b.slot);
+    assert_eq!(a.result, b.result);
+    let result = a.result.as_ref().unwrap();
+    let bricks = a.state.as_ref().unwrap().bricks.len() as u32;
+    assert!(result.scores.iter().sum::<u32>() <= bricks);
+  }
+  assert_ne!(
+    harness.bot(0).room.as_ref().unwrap().id,
+    harness.bot(2).room.as_ref().unwrap().id
+  );
+  assert!(harness.server.world.resource::<Lobby>().rooms().is_empty());
+}
@@ -0,0 +1,51 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+/// Settings loaded from the translate config file (see `translate.toml`).
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+  /// The workbook the localization team fills in.
+  pub workbook: PathBuf,
+  /// The sheet inside the workbook, e.g. `output1` or `server_translate`.
+  pub sheet: String,
+  /// Root directory searched for the files named in the sheet.
+  pub dest_dir: PathBuf,
+  #[serde(default)]
+  pub layout: Layout,
+}
+
+/// Where each value lives inside a pair of rows.
+///
+/// Rows are read from the bottom of the sheet two at a time, so a single header row at the top is
+/// skipped. The first row of a pair holds `file: line`, the second one holds the original line
+/// together with the source and translated text.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Layout {
+  pub location_column: usize,
+  pub location_separator: String,
+  pub line_column: usize,
+  pub source_column: usize,
+  pub translation_column: usize,
+}
+
+impl Default for Layout {
+  fn default() -> Self {
+    Layout {
+      location_column: 1,
+      location_separator: ": ".to_string(),
+      line_column: 1,
+      source_column: 2,
+      translation_column: 3,
+    }
+  }
+}
+
+impl Config {
+  pub fn load(path: &PathBuf) -> Result<Config> {
+    let text = fs::read_to_string(path).with_context(|| format!("read config {:?}", path))?;
+    toml::from_str(&text).with_context(|| format!("parse config {:?}", path))
+  }
+}
@@ -0,0 +1,97 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use rust_xlsxwriter::Workbook;
+
+use crate::{config::Layout, sheet::Entry};
+
+/// Writes entries back out in the same reverse-pair layout the patcher reads, so the exported
+/// workbook can be handed to translators and fed straight into `apply` afterwards.
+pub fn write_untranslated(
+  entries: &[Entry],
+  layout: &Layout,
+  sheet: &str,
+  path: &Path,
+) -> Result<usize> {
+  let mut workbook = Workbook::new();
+  let worksheet = workbook.add_worksheet();
+  worksheet.set_name(sheet)?;
+
+  worksheet.write_string(0, layout.location_column as u16, "location")?;
+  worksheet.write_string(0, layout.source_column as u16, "source")?;
+  worksheet.write_string(0, layout.translation_column as u16, "translation")?;
+
+  let mut count = 0;
+  for entry in entries.iter().filter(|e| e.translation.is_empty()) {
+    let location_row = 1 + 2 * count as u32;
+    worksheet.write_string(
+      location_row,
+      layout.location_column as u16,
+      entry.location(layout),
+    )?;
+    worksheet.write_string(
+      location_row + 1,
+      layout.line_column as u16,
+      &entry.whole_line,
+    )?;
+    worksheet.write_string(location_row + 1, layout.source_column as u16, &entry.source)?;
+    count += 1;
+  }
+
+  workbook
+    .save(path)
+    .with_context(|| format!("write {:?}", path))?;
+  Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use super::*;
+  use crate::{config::Config, sheet};
+
+  #[test]
+  fn round_trips_through_the_reader() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("untranslated.xlsx");
+    let layout = Layout::default();
+    let entries = vec![
+      Entry {
+        row: 2,
+        file: "ui/a.xml".to_string(),
+        line_num: 3,
+        whole_line: "<t>你好</t>".to_string(),
+        source: "你好".to_string(),
+        translation: "".to_string(),
+      },
+      Entry {
+        row: 4,
+        file: "b.lua".to_string(),
+        line_num: 7,
+        whole_line: "print('好的')".to_string(),
+        source: "好的".to_string(),
+        translation: "OK".to_string(),
+      },
+    ];
+
+    assert_eq!(
+      write_untranslated(&entries, &layout, "output1", &path).unwrap(),
+      1
+    );
+
+    let config = Config {
+      workbook: path,
+      sheet: "output1".to_string(),
+      dest_dir: PathBuf::from("."),
+      layout,
+    };
+    let read = sheet::read_entries(&config).unwrap();
+    assert_eq!(read.len(), 1);
+    assert_eq!(read[0].file, "ui/a.xml");
+    assert_eq!(read[0].line_num, 3);
+    assert_eq!(read[0].whole_line, "<t>你好</t>");
+    assert_eq!(read[0].source, "你好");
+    assert_eq!(read[0].translation, "");
+  }
+}
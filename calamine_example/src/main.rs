@@ -1,196 +1,124 @@
-use std::{
-  fs,
-  fs::File,
-  io::{BufRead, BufReader},
-  path::PathBuf,
-};
+use std::path::PathBuf;
 
-use calamine::{open_workbook, DataType, Reader, Xlsx};
-use walkdir::WalkDir;
+use anyhow::Result;
+use clap::{Parser, Subcommand};
 
-const PATH: &str = "a.xlsx";
-const SHEET_NAME: &str = "output1";
-// const COMMENT_PREFIX: &str = "<![CDATA[ ";
-// const RIGHT_COMMENT_PREFIX: &str = " ]]>";
-const DEST_DIR: &str = "/Users/gerald/other_project/art/resource/skins";
-// const SHEET_NAME: &str = "server_translate";
-// const COMMENT_PREFIX: &str = "--";
-// const DEST_DIR : &str = "/Users/gerald/other_project/server/xyef/liblua";
+mod config;
+mod export;
+mod patch;
+mod sheet;
 
-fn main() {
-  let mut excel: Xlsx<_> = open_workbook(PATH).unwrap();
-  if let Ok(r) = excel.worksheet_range(SHEET_NAME) {
-    let mut odd_num = 0;
-    let mut whole_line: String = "".to_string();
-    let mut chinese_str: String = "".to_string();
-    let mut english_str: String = "".to_string();
-    for row in r.rows().rev() {
-      // read file every two lines
-      // println!("row:{:?}", row);
+use config::Config;
+use patch::{FileIndex, Stale, Status};
 
-      odd_num = (odd_num + 1) % 2;
-      // println!("line:{}, row:{:?}, odd_num:{:?}", line!(), row, odd_num);
-      match odd_num {
-        0 => match row.get(1) {
-          Some(DataType::String(filename_and_line)) => {
-            // println!("line:sssssss, {}", line!());
-            let mut split = filename_and_line.split(": ");
-            if let Some(match_filename) = split.next() {
-              if let Some(line_num_str) = split.next() {
-                // get the match filename and the line_number
-                match line_num_str.parse::<i32>() {
-                  Ok(line_num) => {
-                    // println!(
-                    //    "filename: {}, line_num:{}",
-                    // match_filename, line_num
-                    //);
-                    // get the match filename and the line name
-                    change_file_with_translate_words(
-                      match_filename,
-                      line_num,
-                      &whole_line,
-                      &chinese_str,
-                      &english_str,
-                    );
-                  }
-                  other => {
-                    println!("line:sssssss, {} , other:{:?}", line!(), other)
-                  }
-                }
-              } else {
-                println!("not match");
-              }
-            }
-          }
-          other => {
-            // not filename and line_num_str
-            println!("other{:?}, not found", other);
-          }
-        },
+// cargo run -- apply --dry-run
+// cargo run -- stale -c translate.toml
+// cargo run -- export untranslated.xlsx
 
-        _ => {
-          // println!("line:sssssss, {}", line!());
-          // println!(
-          //    "row:{:?}, 1 {:?},2 {:?}, 3 {:?}",
-          // row,
-          // row.get(1),
-          // row.get(2),
-          // row.get(3)
-          //);
-          if let Some(DataType::String(origin_whole_line)) = row.get(1) {
-            if let Some(DataType::String(origin_chinese_str)) = row.get(2) {
-              if let Some(DataType::String(origin_english_str)) = row.get(3) {
-                // println!(
-                //  "origin_whole_line:{:?},
-                // origin_chinese_str:{:?},
-                // origin_english_str:{:?}",
-                //  origin_whole_line, origin_chinese_str, origin_english_str
-                //);
-                whole_line = origin_whole_line.to_string();
-                chinese_str = origin_chinese_str.to_string();
-                english_str = origin_english_str.to_string();
-              }
-            }
-          }
-        }
-      }
-    }
-  }
+/// Patch translated strings from the localization sheet into the source files
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+  /// Config file describing the workbook, sheet layout and destination directory
+  #[arg(short, long, default_value = "translate.toml")]
+  config: PathBuf,
+
+  #[command(subcommand)]
+  command: Command,
 }
 
-fn change_file_with_translate_words(
-  match_filename: &str,
-  line_num: i32,
-  whole_line: &str,
-  chinese_str: &str,
-  english_str: &str,
-) {
-  // println!(
-  //    "match_filename: {:?}, line_num:{:?}
-  //, whole_line: {:?}, chinese_str: {:?},  english_str:{:?}",
-  //    match_filename, line_num, whole_line, chinese_str, english_str
-  //);
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Replace each listed line with its translation
+  Apply {
+    /// Print a unified diff instead of writing the files
+    #[arg(long)]
+    dry_run: bool,
+  },
+  /// List rows whose file or line no longer matches the sheet
+  Stale,
+  /// Write rows without a translation to a new workbook for the translators
+  Export { output: PathBuf },
+}
+
+fn main() -> Result<()> {
+  let args = Args::parse();
+  let config = Config::load(&args.config)?;
+  let entries = sheet::read_entries(&config)?;
 
-  let mut chinese_str_split = chinese_str.split_whitespace();
-  let replace_whole_line: String = match chinese_str_split.clone().count() {
-    1 => str::replace(whole_line, chinese_str, english_str),
-    chinese_count => {
-      let mut english_str_split = english_str.split_whitespace();
-      let mut temp_whole_line = whole_line.to_string();
-      if english_str_split.clone().count() == chinese_count {
-        while let Some(chinese_word) = chinese_str_split.next() {
-          if let Some(english_word) = english_str_split.next() {
-            temp_whole_line = str::replace(&temp_whole_line, chinese_word, english_word);
-          }
+  match args.command {
+    Command::Apply { dry_run } => {
+      let plan = patch::plan(entries, &FileIndex::new(&config.dest_dir))?;
+      for file in &plan.patches {
+        if dry_run {
+          print!("{}", file.unified_diff());
+        } else {
+          file.write()?;
+          println!("patched {}", file.path.display());
         }
-        temp_whole_line
-      } else {
-        println!(
-          "not_match_not_replace, match_filename: {}  line_num: {}",
-          match_filename, line_num
-        );
-        // not found
-        whole_line.to_string()
       }
+      let replaced = plan
+        .outcomes
+        .iter()
+        .filter(|o| o.status == Status::Replaced)
+        .count();
+      let stale = report_stale(&plan.outcomes, &config);
+      println!(
+        "{} lines {}, {} files, {} stale rows",
+        replaced,
+        if dry_run { "to replace" } else { "replaced" },
+        plan.patches.len(),
+        stale
+      );
     }
-  };
-  if replace_whole_line != "".to_string() {
-    match find_file(match_filename) {
-      None => println!("No rusv file was found."),
-      Some(filepath) => {
-        // println!("Rusv file was found: {:?}", filepath);
-        if let Ok(file) = File::open(filepath.clone()) {
-          read_write_line(filepath, file, line_num, replace_whole_line);
-        }
-        //                if let Ok(file) = File::open(filepath.clone()) {
-        //                    let mut reader = BufReader::new(file);
-        //                    let mut buf = Vec::new();
-        //                    reader.read_to_end(&mut buf).unwrap();
-        //                    if let Some(elem) = buf.get_mut((line_num - 1) as usize) {
-        //                        *elem = 42;
-        //                    }
-        //                    File::create(filepath).unwrap().write_all(&buf).unwrap();
-        //                }
-      }
+    Command::Stale => {
+      let plan = patch::plan(entries, &FileIndex::new(&config.dest_dir))?;
+      let stale = report_stale(&plan.outcomes, &config);
+      println!("{} stale rows", stale);
+    }
+    Command::Export { output } => {
+      let count = export::write_untranslated(&entries, &config.layout, &config.sheet, &output)?;
+      println!(
+        "exported {} untranslated rows to {}",
+        count,
+        output.display()
+      );
     }
   }
+  Ok(())
 }
 
-fn read_write_line(path: PathBuf, file: File, line_num: i32, replace_whole_line: String) {
-  let reader = BufReader::new(file);
-  // let mut lines_string: Vec<String> = vec![];
-  // println!("lines:{:?}", lines);
-  // for line in reader.lines() {
-  // println!("{}", line.as_ref().unwrap());
-  //    lines_string.push(line.unwrap());
-  //}
-
-  let mut lines_string: Vec<String> = reader
-    .lines()
-    .map(|l| l.expect("Could not parse line"))
-    .collect();
-
-  if let Some(elem) = lines_string.get_mut((line_num - 1) as usize) {
-    *elem = replace_whole_line
+fn report_stale(outcomes: &[patch::Outcome], config: &Config) -> usize {
+  let mut count = 0;
+  for outcome in outcomes {
+    let reason = match &outcome.status {
+      Status::Stale(Stale::FileNotFound) => {
+        format!("no file under {}", config.dest_dir.display())
+      }
+      Status::Stale(Stale::LineOutOfRange { lines }) => {
+        format!("{} has only {} lines", display(&outcome.path), lines)
+      }
+      Status::Stale(Stale::LineChanged { found }) => {
+        format!("line in {} is now {:?}", display(&outcome.path), found)
+      }
+      Status::WordCountMismatch => "source and translation word counts differ".to_string(),
+      Status::NoSource => "no source text".to_string(),
+      _ => continue,
+    };
+    count += 1;
+    println!(
+      "row {}: {}: {}",
+      outcome.entry.row,
+      outcome.entry.location(&config.layout),
+      reason
+    );
   }
-
-  // if let Ok(mut file) = File::create(path) {
-  //   for i in &lines_string {
-  //    _ = writeln!(file, "{}", i);
-  //}
-  // }
-  fs::write(path, lines_string.join("\n")).expect("");
+  count
 }
 
-fn find_file(filename: &str) -> Option<PathBuf> {
-  for entry in WalkDir::new(DEST_DIR)
-    .follow_links(true)
-    .into_iter()
-    .filter_map(|e| e.ok())
-  {
-    if entry.file_name().to_string_lossy().ends_with(filename) {
-      return Some(entry.path().to_path_buf());
-    }
-  }
-  return None;
+fn display(path: &Option<PathBuf>) -> String {
+  path
+    .as_ref()
+    .map(|p| p.display().to_string())
+    .unwrap_or_default()
 }
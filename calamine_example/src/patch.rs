@@ -0,0 +1,316 @@
+use std::{
+  collections::BTreeMap,
+  fs,
+  io::Write,
+  path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use similar::TextDiff;
+use tempfile::NamedTempFile;
+use walkdir::WalkDir;
+
+use crate::sheet::Entry;
+
+/// What happened (or would happen) to one sheet entry.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+  Replaced,
+  AlreadyTranslated,
+  Untranslated,
+  /// The source cell is empty, so there is nothing to look for in the line.
+  NoSource,
+  /// The source and translated text split into a different number of words.
+  WordCountMismatch,
+  Stale(Stale),
+}
+
+/// Why an entry no longer points at the line it was exported from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stale {
+  FileNotFound,
+  LineOutOfRange { lines: usize },
+  LineChanged { found: String },
+}
+
+#[derive(Debug)]
+pub struct Outcome {
+  pub entry: Entry,
+  pub path: Option<PathBuf>,
+  pub status: Status,
+}
+
+/// Pending edits for one file, kept next to the content they were computed from.
+#[derive(Debug)]
+pub struct FilePatch {
+  pub path: PathBuf,
+  original: String,
+  patched: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Plan {
+  pub outcomes: Vec<Outcome>,
+  pub patches: Vec<FilePatch>,
+}
+
+/// Replaces the source text in `whole_line` with its translation.
+///
+/// Multi-word sources are replaced word by word, so they need the same number of words on both
+/// sides; otherwise, or if `source` is blank, `None` is returned and the line is left alone.
+pub fn translate_line(whole_line: &str, source: &str, translation: &str) -> Option<String> {
+  let source_words: Vec<&str> = source.split_whitespace().collect();
+  if source_words.is_empty() {
+    return None;
+  }
+  if source_words.len() == 1 {
+    return Some(str::replace(whole_line, source, translation));
+  }
+  let translation_words: Vec<&str> = translation.split_whitespace().collect();
+  if translation_words.len() != source_words.len() {
+    return None;
+  }
+  let mut line = whole_line.to_string();
+  for (source_word, translation_word) in source_words.iter().zip(translation_words) {
+    line = str::replace(&line, source_word, translation_word);
+  }
+  Some(line)
+}
+
+/// Every file under `dest_dir`, searched by name suffix like the sheet expects.
+pub struct FileIndex {
+  files: Vec<PathBuf>,
+}
+
+impl FileIndex {
+  pub fn new(dest_dir: &Path) -> FileIndex {
+    let mut files: Vec<PathBuf> = WalkDir::new(dest_dir)
+      .follow_links(true)
+      .into_iter()
+      .filter_map(|e| e.ok())
+      .filter(|e| e.file_type().is_file())
+      .map(|e| e.into_path())
+      .collect();
+    files.sort();
+    FileIndex { files }
+  }
+
+  pub fn find(&self, filename: &str) -> Option<&PathBuf> {
+    self
+      .files
+      .iter()
+      .find(|path| path.ends_with(filename) || path.to_string_lossy().ends_with(filename))
+  }
+}
+
+pub fn plan(entries: Vec<Entry>, index: &FileIndex) -> Result<Plan> {
+  let mut plan = Plan::default();
+  let mut by_file: BTreeMap<PathBuf, Vec<(Entry, String)>> = BTreeMap::new();
+
+  for entry in entries {
+    let replacement = if entry.source.trim().is_empty() {
+      Err(Status::NoSource)
+    } else if entry.translation.is_empty() {
+      Err(Status::Untranslated)
+    } else {
+      translate_line(&entry.whole_line, &entry.source, &entry.translation)
+        .ok_or(Status::WordCountMismatch)
+    };
+    let path = index.find(&entry.file).cloned();
+    match (replacement, path) {
+      (Err(status), path) => plan.outcomes.push(Outcome {
+        entry,
+        path,
+        status,
+      }),
+      (Ok(_), None) => plan.outcomes.push(Outcome {
+        entry,
+        path: None,
+        status: Status::Stale(Stale::FileNotFound),
+      }),
+      (Ok(replacement), Some(path)) => by_file.entry(path).or_default().push((entry, replacement)),
+    }
+  }
+
+  for (path, edits) in by_file {
+    let original = fs::read_to_string(&path).with_context(|| format!("read {:?}", path))?;
+    let mut lines: Vec<String> = original.split_inclusive('\n').map(String::from).collect();
+    let mut changed = false;
+    for (entry, replacement) in edits {
+      let status = match lines.get_mut(entry.line_num - 1) {
+        None => Status::Stale(Stale::LineOutOfRange { lines: lines.len() }),
+        Some(line) => {
+          let (content, ending) = split_line_ending(line);
+          if content == entry.whole_line {
+            *line = format!("{}{}", replacement, ending);
+            changed = true;
+            Status::Replaced
+          } else if content == replacement {
+            Status::AlreadyTranslated
+          } else {
+            Status::Stale(Stale::LineChanged {
+              found: content.to_string(),
+            })
+          }
+        }
+      };
+      plan.outcomes.push(Outcome {
+        entry,
+        path: Some(path.clone()),
+        status,
+      });
+    }
+    if changed {
+      plan.patches.push(FilePatch {
+        path,
+        original,
+        patched: lines.concat(),
+      });
+    }
+  }
+
+  plan.outcomes.sort_by_key(|o| o.entry.row);
+  Ok(plan)
+}
+
+fn split_line_ending(line: &str) -> (&str, &str) {
+  let content = line.trim_end_matches(['\n', '\r']);
+  (content, &line[content.len() ..])
+}
+
+impl FilePatch {
+  pub fn unified_diff(&self) -> String {
+    let name = self.path.display().to_string();
+    TextDiff::from_lines(&self.original, &self.patched)
+      .unified_diff()
+      .header(&name, &name)
+      .to_string()
+  }
+
+  /// Writes the patched content through a temp file in the same directory and renames it over the
+  /// original, after checking the file was not modified since the plan was made.
+  pub fn write(&self) -> Result<()> {
+    let current =
+      fs::read_to_string(&self.path).with_context(|| format!("read {:?}", self.path))?;
+    if current != self.original {
+      bail!("{:?} changed while patching, run again", self.path);
+    }
+    let dir = self.path.parent().unwrap_or(Path::new("."));
+    let mut tmp = NamedTempFile::new_in(dir).with_context(|| format!("temp file in {:?}", dir))?;
+    tmp.write_all(self.patched.as_bytes())?;
+    tmp.as_file().sync_all()?;
+    fs::set_permissions(tmp.path(), fs::metadata(&self.path)?.permissions())?;
+    tmp
+      .persist(&self.path)
+      .with_context(|| format!("replace {:?}", self.path))?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn entry(
+    row: usize,
+    file: &str,
+    line_num: usize,
+    whole_line: &str,
+    source: &str,
+    translation: &str,
+  ) -> Entry {
+    Entry {
+      row,
+      file: file.to_string(),
+      line_num,
+      whole_line: whole_line.to_string(),
+      source: source.to_string(),
+      translation: translation.to_string(),
+    }
+  }
+
+  #[test]
+  fn translates_word_by_word() {
+    assert_eq!(
+      translate_line("a 你好 b", "你好", "Hello"),
+      Some("a Hello b".to_string())
+    );
+    assert_eq!(
+      translate_line("<t>确定 取消</t>", "确定 取消", "OK Cancel"),
+      Some("<t>OK Cancel</t>".to_string())
+    );
+    assert_eq!(translate_line("确定 取消", "确定 取消", "OK"), None);
+    assert_eq!(translate_line("<t>确定</t>", "", "OK"), None);
+    assert_eq!(translate_line("<t>确定</t>", " ", "OK"), None);
+  }
+
+  #[test]
+  fn plans_and_writes_verified_edits() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("ui").join("a.xml");
+    fs::create_dir_all(file.parent().unwrap()).unwrap();
+    fs::write(
+      &file,
+      "<a>\r\n<t>你好</t>\r\n<t>再见</t>\r\n<t>好的</t>\r\n",
+    )
+    .unwrap();
+
+    let entries = vec![
+      entry(2, "ui/a.xml", 2, "<t>你好</t>", "你好", "Hello"),
+      entry(4, "a.xml", 3, "<t>拜拜</t>", "拜拜", "Bye"),
+      entry(6, "a.xml", 9, "<t>好的</t>", "好的", "OK"),
+      entry(8, "missing.xml", 1, "x", "x", "y"),
+      entry(10, "a.xml", 4, "<t>好的</t>", "好的", ""),
+      entry(12, "a.xml", 4, "<t>好的</t>", "", "OK"),
+    ];
+    let plan = plan(entries, &FileIndex::new(dir.path())).unwrap();
+    let statuses: Vec<&Status> = plan.outcomes.iter().map(|o| &o.status).collect();
+    assert_eq!(
+      statuses,
+      vec![
+        &Status::Replaced,
+        &Status::Stale(Stale::LineChanged {
+          found: "<t>再见</t>".to_string()
+        }),
+        &Status::Stale(Stale::LineOutOfRange { lines: 4 }),
+        &Status::Stale(Stale::FileNotFound),
+        &Status::Untranslated,
+        &Status::NoSource,
+      ]
+    );
+
+    assert_eq!(plan.patches.len(), 1);
+    assert!(plan.patches[0].unified_diff().contains("+<t>Hello</t>"));
+    plan.patches[0].write().unwrap();
+    assert_eq!(
+      fs::read_to_string(&file).unwrap(),
+      "<a>\r\n<t>Hello</t>\r\n<t>再见</t>\r\n<t>好的</t>\r\n"
+    );
+
+    let again = super::plan(
+      vec![entry(2, "a.xml", 2, "<t>你好</t>", "你好", "Hello")],
+      &FileIndex::new(dir.path()),
+    )
+    .unwrap();
+    assert_eq!(again.outcomes[0].status, Status::AlreadyTranslated);
+    assert!(again.patches.is_empty());
+  }
+
+  #[test]
+  fn refuses_to_overwrite_concurrent_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("b.lua");
+    fs::write(&file, "print('你好')\n").unwrap();
+    let plan = plan(
+      vec![entry(2, "b.lua", 1, "print('你好')", "你好", "hi")],
+      &FileIndex::new(dir.path()),
+    )
+    .unwrap();
+    fs::write(&file, "print('你好')\nprint(1)\n").unwrap();
+    assert!(plan.patches[0].write().is_err());
+    assert_eq!(
+      fs::read_to_string(&file).unwrap(),
+      "print('你好')\nprint(1)\n"
+    );
+  }
+}
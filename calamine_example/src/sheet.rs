@@ -0,0 +1,131 @@
+use anyhow::{anyhow, Context, Result};
+use calamine::{open_workbook, DataType, Range, Reader, Xlsx};
+
+use crate::config::{Config, Layout};
+
+/// One translated line taken from a pair of rows in the sheet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+  /// 1-based row number of the `file: line` row, used when reporting.
+  pub row: usize,
+  pub file: String,
+  pub line_num: usize,
+  pub whole_line: String,
+  pub source: String,
+  pub translation: String,
+}
+
+impl Entry {
+  pub fn location(&self, layout: &Layout) -> String {
+    format!(
+      "{}{}{}",
+      self.file, layout.location_separator, self.line_num
+    )
+  }
+}
+
+pub fn read_entries(config: &Config) -> Result<Vec<Entry>> {
+  let mut excel: Xlsx<_> =
+    open_workbook(&config.workbook).with_context(|| format!("open {:?}", config.workbook))?;
+  let range = excel
+    .worksheet_range(&config.sheet)
+    .map_err(|e| anyhow!("read sheet {}: {}", config.sheet, e))?;
+  Ok(entries_from_range(&range, &config.layout))
+}
+
+/// Walks the rows in reverse pairs, the same way the sheet has always been read.
+pub fn entries_from_range(range: &Range<DataType>, layout: &Layout) -> Vec<Entry> {
+  let (first_row, first_column) = range
+    .start()
+    .map(|(row, column)| (row as usize, column as usize))
+    .unwrap_or((0, 0));
+  // Cells are indexed from the first used column, not from column A.
+  let cell = |row: &[DataType], column: usize| match column.checked_sub(first_column) {
+    Some(column) => cell(row, column),
+    None => "".to_string(),
+  };
+  let rows: Vec<(usize, &[DataType])> = range
+    .rows()
+    .enumerate()
+    .map(|(i, row)| (first_row + i + 1, row))
+    .collect();
+
+  let mut entries = vec![];
+  for pair in rows.rchunks_exact(2).rev() {
+    let (row, location_row) = pair[0];
+    let (_, text_row) = pair[1];
+    let location = cell(location_row, layout.location_column);
+    let Some((file, line_num)) = parse_location(&location, &layout.location_separator) else {
+      eprintln!(
+        "row {}: {:?} is not a `file{}line` location",
+        row, location, layout.location_separator
+      );
+      continue;
+    };
+    entries.push(Entry {
+      row,
+      file,
+      line_num,
+      whole_line: cell(text_row, layout.line_column),
+      source: cell(text_row, layout.source_column),
+      translation: cell(text_row, layout.translation_column),
+    });
+  }
+  entries
+}
+
+fn parse_location(location: &str, separator: &str) -> Option<(String, usize)> {
+  let (file, line_num) = location.rsplit_once(separator)?;
+  let line_num = line_num.trim().parse::<usize>().ok().filter(|n| *n > 0)?;
+  Some((file.to_string(), line_num))
+}
+
+fn cell(row: &[DataType], column: usize) -> String {
+  match row.get(column) {
+    Some(DataType::String(s)) => s.to_string(),
+    Some(DataType::Empty) | None => "".to_string(),
+    Some(other) => other.to_string(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn text(s: &str) -> DataType {
+    DataType::String(s.to_string())
+  }
+
+  #[test]
+  fn reads_reverse_pairs_and_skips_header() {
+    let mut range = Range::new((0, 0), (4, 3));
+    range.set_value((0, 1), text("header"));
+    range.set_value((1, 1), text("ui/a.xml: 3"));
+    range.set_value((2, 1), text("<t>你好</t>"));
+    range.set_value((2, 2), text("你好"));
+    range.set_value((2, 3), text("Hello"));
+    range.set_value((3, 1), text("b.lua: 10"));
+    range.set_value((4, 1), text("print('再见')"));
+    range.set_value((4, 2), text("再见"));
+
+    let entries = entries_from_range(&range, &Layout::default());
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].file, "ui/a.xml");
+    assert_eq!(entries[0].line_num, 3);
+    assert_eq!(entries[0].row, 2);
+    assert_eq!(entries[0].translation, "Hello");
+    assert_eq!(entries[1].file, "b.lua");
+    assert_eq!(entries[1].translation, "");
+  }
+
+  #[test]
+  fn rejects_bad_locations() {
+    assert_eq!(parse_location("a.lua: x", ": "), None);
+    assert_eq!(parse_location("a.lua: 0", ": "), None);
+    assert_eq!(parse_location("a.lua", ": "), None);
+    assert_eq!(
+      parse_location("a: b.lua: 7", ": "),
+      Some(("a: b.lua".to_string(), 7))
+    );
+  }
+}
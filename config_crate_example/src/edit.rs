@@ -0,0 +1,113 @@
+//! Writes values back into TOML files with toml_edit, keeping their comments, ordering and
+//! formatting.
+
+use std::{fs, io, path::Path};
+
+use toml_edit::{DocumentMut, Item, Value};
+
+use crate::Error;
+
+/// `text` as a TOML value (`8080`, `true`, `[1, 2]`), or as a string when it is not one.
+pub fn parse_value(text: &str) -> Value {
+  text.parse().unwrap_or_else(|_| text.into())
+}
+
+/// Sets the dotted `key` to `value` in the TOML file at `path`, creating the file and the
+/// tables on the way as needed. A value replaced keeps the comment after it.
+pub fn set(path: &Path, key: &str, mut value: Value) -> Result<(), Error> {
+  let text = match fs::read_to_string(path) {
+    Ok(text) => text,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+    Err(e) => return Err(Error::io(path)(e)),
+  };
+  let mut document: DocumentMut = text.parse().map_err(|source| Error::Toml {
+    path: path.to_owned(),
+    source,
+  })?;
+  let edit_error = |reason: String| Error::Edit {
+    path: path.to_owned(),
+    key: key.into(),
+    reason,
+  };
+
+  let parts = key.split('.').collect::<Vec<_>>();
+  let (last, tables) = parts
+    .split_last()
+    .filter(|_| parts.iter().all(|p| !p.is_empty()))
+    .ok_or_else(|| edit_error("not a key".into()))?;
+  let mut table = document.as_table_mut() as &mut dyn toml_edit::TableLike;
+  for part in tables {
+    table = table
+      .entry(part)
+      .or_insert(toml_edit::table())
+      .as_table_like_mut()
+      .ok_or_else(|| edit_error(format!("{} is not a table", part)))?;
+  }
+  match table.get_mut(last) {
+    Some(Item::Value(old)) => {
+      *value.decor_mut() = old.decor().clone();
+      *old = value;
+    }
+    Some(Item::None) | None => {
+      table.insert(last, Item::Value(value));
+    }
+    Some(_) => return Err(edit_error(format!("{} is a table", last))),
+  }
+
+  // Written aside then renamed, for watchers never to read half a file
+  let temporary = path.with_extension("toml.tmp");
+  fs::write(&temporary, document.to_string()).map_err(Error::io(&temporary))?;
+  fs::rename(&temporary, path).map_err(Error::io(path))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const CONFIG: &str = r#"# The service
+debug = false
+
+[server]
+# Where to listen
+host = "0.0.0.0"
+port = 8080 # the usual one
+
+[db]
+url = "postgres://localhost/app"
+"#;
+
+  fn edit(key: &str, value: &str) -> Result<String, Error> {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("app.toml");
+    fs::write(&path, CONFIG).unwrap();
+    set(&path, key, parse_value(value))?;
+    Ok(fs::read_to_string(&path).unwrap())
+  }
+
+  #[test]
+  fn keeps_comments_and_ordering() {
+    assert_eq!(
+      edit("server.port", "9090").unwrap(),
+      CONFIG.replace("port = 8080 #", "port = 9090 #")
+    );
+    assert_eq!(
+      edit("db.url", "sqlite://app.db").unwrap(),
+      CONFIG.replace("postgres://localhost/app", "sqlite://app.db")
+    );
+    assert_eq!(
+      edit("db.pool_size", "10").unwrap(),
+      format!("{}pool_size = 10\n", CONFIG)
+    );
+    assert_eq!(
+      edit("log.level", "debug").unwrap(),
+      format!("{}\n[log]\nlevel = \"debug\"\n", CONFIG)
+    );
+  }
+
+  #[test]
+  fn refuses_to_replace_tables() {
+    assert!(matches!(edit("server", "1"), Err(Error::Edit { .. })));
+    assert!(matches!(edit("debug.x", "1"), Err(Error::Edit { .. })));
+    assert!(matches!(edit("db..url", "1"), Err(Error::Edit { .. })));
+  }
+}
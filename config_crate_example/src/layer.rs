@@ -0,0 +1,114 @@
+use std::{fs, io, path::Path};
+
+use config::{Config, ConfigError, File, FileFormat, Map, Source, Value, ValueKind};
+
+use crate::{settings::Settings, Error};
+
+/// A [`Source`] whose values all tell where they come from, for errors to point at it: the
+/// file as it was named, the defaults, the environment variable or the command line flag.
+#[derive(Debug, Clone)]
+pub struct Layer {
+  values: Map<String, Value>,
+}
+
+impl Layer {
+  pub fn defaults() -> Result<Layer, ConfigError> {
+    let origin = "the defaults".to_string();
+    let values = Config::try_from(&Settings::default())?
+      .collect()?
+      .into_iter()
+      .map(|(key, value)| (key, stamp(value, &origin)))
+      .collect();
+    Ok(Layer { values })
+  }
+
+  /// The YAML, TOML or JSON file at `path`, if there is one or it must be.
+  pub fn file(path: &Path, required: bool) -> Result<Option<Layer>, Error> {
+    let text = match fs::read_to_string(path) {
+      Ok(text) => text,
+      Err(e) if e.kind() == io::ErrorKind::NotFound && !required => return Ok(None),
+      Err(e) => return Err(Error::io(path)(e)),
+    };
+    let format = match path.extension().and_then(|e| e.to_str()) {
+      Some("yml" | "yaml") => FileFormat::Yaml,
+      Some("toml") => FileFormat::Toml,
+      Some("json") => FileFormat::Json,
+      _ => return Err(Error::Format(path.to_owned())),
+    };
+    // config would name the file relative to the current directory
+    let origin = path.display().to_string();
+    let values = File::from_str(&text, format)
+      .collect()
+      .map_err(|e| match e {
+        ConfigError::FileParse { cause, .. } => ConfigError::FileParse {
+          uri: Some(origin.clone()),
+          cause,
+        },
+        e => e,
+      })?
+      .into_iter()
+      .map(|(key, value)| (key, stamp(value, &origin)))
+      .collect();
+    Ok(Some(Layer { values }))
+  }
+
+  /// The variables starting with `<prefix>__`, `__` separating the levels of their keys:
+  /// `APP__DB__URL` sets `db.url`.
+  pub fn env(prefix: &str, vars: impl IntoIterator<Item = (String, String)>) -> Layer {
+    let prefix = format!("{}__", prefix);
+    let values = vars
+      .into_iter()
+      .filter_map(|(name, value)| {
+        let key = name
+          .strip_prefix(&prefix)?
+          .split("__")
+          .map(str::to_lowercase)
+          .collect::<Vec<_>>()
+          .join(".");
+        let origin = format!("${}", name);
+        Some((key, Value::new(Some(&origin), ValueKind::String(value))))
+      })
+      .collect();
+    Layer { values }
+  }
+
+  /// Values given on the command line, by key.
+  pub fn flags<'a>(flags: impl IntoIterator<Item = &'a (String, String)>) -> Layer {
+    let values = flags
+      .into_iter()
+      .map(|(key, value)| {
+        let origin = format!("the command line flag for {}", key);
+        let value = Value::new(Some(&origin), ValueKind::String(value.clone()));
+        (key.clone(), value)
+      })
+      .collect();
+    Layer { values }
+  }
+}
+
+fn stamp(value: Value, origin: &String) -> Value {
+  let kind = match value.kind {
+    ValueKind::Table(table) => ValueKind::Table(
+      table
+        .into_iter()
+        .map(|(key, value)| (key, stamp(value, origin)))
+        .collect(),
+    ),
+    ValueKind::Array(array) => {
+      ValueKind::Array(array.into_iter().map(|v| stamp(v, origin)).collect())
+    }
+    kind => kind,
+  };
+  Value::new(Some(origin), kind)
+}
+
+impl Source for Layer {
+  fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+    Box::new(self.clone())
+  }
+
+  /// Keys may be paths like `db.url`, set into the tables they name.
+  fn collect(&self) -> Result<Map<String, Value>, ConfigError> {
+    Ok(self.values.clone())
+  }
+}
@@ -0,0 +1,213 @@
+//! Layered configuration: the defaults, then the files in order, then `APP__*` environment
+//! variables, then command line flags, each layer overriding the keys it gives, all
+//! deserialized into [`Settings`].
+
+use std::{
+  env, io,
+  path::{Path, PathBuf},
+};
+
+use config::{Config, ConfigError, Source, Value, ValueKind};
+
+pub mod edit;
+mod layer;
+pub mod settings;
+pub mod watch;
+
+use layer::Layer;
+pub use settings::Settings;
+
+/// The prefix of the environment variables read.
+pub const ENV_PREFIX: &str = "APP";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+  /// Files that do not parse, and values of the wrong type with the key and where it was set.
+  #[error(transparent)]
+  Config(#[from] ConfigError),
+  #[error("{key} = {value} from {origin}: {reason}")]
+  Invalid {
+    key: String,
+    value: String,
+    origin: String,
+    reason: &'static str,
+  },
+  #[error("{}: not a YAML, TOML or JSON file", .0.display())]
+  Format(PathBuf),
+  #[error("cannot access {}: {source}", path.display())]
+  Io { path: PathBuf, source: io::Error },
+  #[error("{}: {source}", path.display())]
+  Toml {
+    path: PathBuf,
+    source: toml_edit::TomlError,
+  },
+  #[error("cannot set {key} in {}: {reason}", path.display())]
+  Edit {
+    path: PathBuf,
+    key: String,
+    reason: String,
+  },
+  #[error("cannot watch the configuration files: {0}")]
+  Watch(#[from] notify::Error),
+}
+
+impl Error {
+  pub(crate) fn io(path: &Path) -> impl FnOnce(io::Error) -> Error + '_ {
+    move |source| Error::Io {
+      path: path.to_owned(),
+      source,
+    }
+  }
+}
+
+/// How to load the [`Settings`], layer by layer.
+#[derive(Debug, Clone, Default)]
+pub struct Loader {
+  /// The files, and whether they must exist.
+  files: Vec<(PathBuf, bool)>,
+  /// The environment, when not the one of the process.
+  env: Option<Vec<(String, String)>>,
+  flags: Vec<(String, String)>,
+}
+
+impl Loader {
+  pub fn new() -> Loader {
+    Loader::default()
+  }
+
+  /// A YAML, TOML or JSON file, after the files already given. It must exist.
+  pub fn file(mut self, path: impl Into<PathBuf>) -> Loader {
+    self.files.push((path.into(), true));
+    self
+  }
+
+  /// A file like [`Loader::file`], skipped if missing.
+  pub fn optional_file(mut self, path: impl Into<PathBuf>) -> Loader {
+    self.files.push((path.into(), false));
+    self
+  }
+
+  /// Reads these variables instead of those of the process.
+  pub fn env(mut self, vars: impl IntoIterator<Item = (String, String)>) -> Loader {
+    self.env = Some(vars.into_iter().collect());
+    self
+  }
+
+  /// A command line flag setting `key` to `value`, over everything else.
+  pub fn set(mut self, key: impl Into<String>, value: impl Into<String>) -> Loader {
+    self.flags.push((key.into(), value.into()));
+    self
+  }
+
+  pub fn files(&self) -> impl Iterator<Item = &Path> {
+    self.files.iter().map(|(path, _)| path.as_path())
+  }
+
+  pub fn load(&self) -> Result<Loaded, Error> {
+    let mut builder = Config::builder().add_source(Layer::defaults()?);
+    for (path, required) in &self.files {
+      if let Some(file) = Layer::file(path, *required)? {
+        builder = builder.add_source(file);
+      }
+    }
+    let env = match &self.env {
+      Some(vars) => Layer::env(ENV_PREFIX, vars.clone()),
+      None => Layer::env(ENV_PREFIX, env::vars()),
+    };
+    let config = builder
+      .add_source(env)
+      .add_source(Layer::flags(&self.flags))
+      .build()?;
+
+    let settings: Settings = config.clone().try_deserialize()?;
+    let loaded = Loaded { settings, config };
+    if let Err((key, reason)) = loaded.settings.validate() {
+      let (value, origin) = loaded.value(key).unwrap_or_default();
+      return Err(Error::Invalid {
+        key: key.into(),
+        value,
+        origin,
+        reason,
+      });
+    }
+    Ok(loaded)
+  }
+
+  /// Sets `key` to `value` in the TOML file at `path`, one of the files as given to the loader,
+  /// keeping its comments and layout, as long as the settings it makes are valid without the
+  /// command line flags: the file is put back as it was otherwise.
+  pub fn persist(&self, path: &Path, key: &str, value: &str) -> Result<Loaded, Error> {
+    if !self.files().any(|file| file == path) {
+      return Err(Error::Edit {
+        path: path.to_owned(),
+        key: key.into(),
+        reason: "not one of the files read".into(),
+      });
+    }
+    // Flags would hide the value written, they are not saved
+    let without_flags = Loader {
+      flags: Vec::new(),
+      ..self.clone()
+    };
+    let before = match std::fs::read_to_string(path) {
+      Ok(text) => Some(text),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+      Err(e) => return Err(Error::io(path)(e)),
+    };
+    edit::set(path, key, edit::parse_value(value))?;
+    without_flags.load().inspect_err(|_| {
+      let _ = match &before {
+        Some(text) => std::fs::write(path, text),
+        None => std::fs::remove_file(path),
+      };
+    })?;
+    self.load()
+  }
+}
+
+/// Settings, with where each of their values comes from.
+#[derive(Debug, Clone)]
+pub struct Loaded {
+  pub settings: Settings,
+  config: Config,
+}
+
+impl Loaded {
+  /// The value of `key`, and where it was set.
+  pub fn value(&self, key: &str) -> Option<(String, String)> {
+    // Deserializing a `Value` would lose its origin
+    let mut parts = key.split('.');
+    let mut value = self.config.collect().ok()?.remove(parts.next()?)?;
+    for part in parts {
+      value = value.into_table().ok()?.remove(part)?;
+    }
+    Some((value.to_string(), origin(&value)))
+  }
+
+  /// Every key, with its value and where it was set.
+  pub fn provenance(&self) -> Vec<(String, String, String)> {
+    let mut keys = Vec::new();
+    if let Ok(table) = self.config.collect() {
+      for (key, value) in table {
+        flatten(key, value, &mut keys);
+      }
+    }
+    keys.sort();
+    keys
+  }
+}
+
+fn origin(value: &Value) -> String {
+  value.origin().unwrap_or("unknown").to_string()
+}
+
+fn flatten(key: String, value: Value, keys: &mut Vec<(String, String, String)>) {
+  match value.kind {
+    ValueKind::Table(table) => {
+      for (k, v) in table {
+        flatten(format!("{}.{}", key, k), v, keys);
+      }
+    }
+    _ => keys.push((key, value.to_string(), origin(&value))),
+  }
+}
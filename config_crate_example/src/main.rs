@@ -1,20 +1,133 @@
-use std::{collections::HashMap, path::Path};
+use std::{path::PathBuf, process::ExitCode};
 
-use config::*;
+use clap::{Parser, Subcommand};
+use config_crate_example::{Error, Loader};
 
-fn main() {
-  // Option 1
-  // --------
-  // Gather all conf files from conf/ manually
-  let mut settings = Config::default();
-  settings
-    // File::with_name(..) is shorthand for File::from(Path::new(..))
-    .merge(File::from(Path::new("conf/sys.yml")))
-    .unwrap();
+/// Layered configuration: the defaults, then the files, then APP__* environment variables
+/// (APP__DB__URL sets db.url), then the flags.
+#[derive(Parser, Debug)]
+struct Flags {
+  /// Configuration files in YAML, TOML or JSON, later ones overriding earlier ones
+  /// [default: conf/sys.yml conf/app.toml, and conf/local.json if there is one]
+  #[clap(short = 'c', long = "config", value_name = "FILE")]
+  files: Vec<PathBuf>,
+  /// Sets any key, like --set db.pool_size=10
+  #[clap(long = "set", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+  overrides: Vec<(String, String)>,
+  /// Sets debug
+  #[clap(long)]
+  debug: bool,
+  /// Sets server.port
+  #[clap(long)]
+  port: Option<u16>,
+  /// Sets db.url
+  #[clap(long)]
+  db_url: Option<String>,
+  #[clap(subcommand)]
+  command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Prints every key, with its value and where it was set (the default)
+  Show,
+  /// Prints the value of a key, or those of a table, and where they were set
+  Get { key: String },
+  /// Writes a value into a TOML file, keeping its comments and layout
+  Set {
+    key: String,
+    value: String,
+    /// One of the files read
+    #[clap(long, default_value = "conf/app.toml")]
+    file: PathBuf,
+  },
+  /// Prints the settings again whenever the files change
+  Watch,
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+  s.split_once('=')
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .ok_or_else(|| format!("expected KEY=VALUE, got {:?}", s))
+}
+
+impl Flags {
+  fn loader(&self) -> Loader {
+    let mut loader = Loader::new();
+    if self.files.is_empty() {
+      loader = loader
+        .file("conf/sys.yml")
+        .file("conf/app.toml")
+        .optional_file("conf/local.json");
+    }
+    for file in &self.files {
+      loader = loader.file(file);
+    }
+    if self.debug {
+      loader = loader.set("debug", "true");
+    }
+    if let Some(port) = self.port {
+      loader = loader.set("server.port", port.to_string());
+    }
+    if let Some(url) = &self.db_url {
+      loader = loader.set("db.url", url);
+    }
+    for (key, value) in &self.overrides {
+      loader = loader.set(key, value);
+    }
+    loader
+  }
+}
+
+async fn run(flags: Flags) -> Result<(), Error> {
+  let loader = flags.loader();
+  match flags.command.unwrap_or(Command::Show) {
+    Command::Show => {
+      for (key, value, origin) in loader.load()?.provenance() {
+        println!("{} = {}  # {}", key, value, origin);
+      }
+    }
+    Command::Get { key } => {
+      // A table is shown key by key, each of its values may come from elsewhere
+      let prefix = format!("{}.", key);
+      for (k, value, origin) in loader.load()?.provenance() {
+        if k == key || k.starts_with(&prefix) {
+          println!("{} = {}  # {}", k, value, origin);
+        }
+      }
+    }
+    Command::Set { key, value, file } => {
+      let loaded = loader.persist(&file, &key, &value)?;
+      if let Some((value, origin)) = loaded.value(&key) {
+        println!("{} = {}  # {}", key, value, origin);
+      }
+    }
+    Command::Watch => {
+      let mut watcher = loader.watch()?;
+      println!("{:#?}", watcher.settings.borrow_and_update());
+      loop {
+        tokio::select! {
+          changed = watcher.settings.changed() => {
+            if changed.is_err() {
+              break;
+            }
+            println!("{:#?}", watcher.settings.borrow_and_update());
+          }
+          Some(e) = watcher.errors.recv() => eprintln!("Kept the settings as they were: {}", e),
+        }
+      }
+    }
+  }
+  Ok(())
+}
 
-  // Print out our settings (as a HashMap)
-  println!(
-    "\n{:?} \n\n-----------",
-    settings.try_into::<HashMap<String, String>>().unwrap()
-  );
+#[tokio::main]
+async fn main() -> ExitCode {
+  match run(Flags::parse()).await {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(e) => {
+      eprintln!("{}", e);
+      ExitCode::FAILURE
+    }
+  }
 }
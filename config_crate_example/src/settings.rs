@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+/// The typed configuration. Every key has a default, so that each layer only needs to
+/// give the keys it changes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+  pub debug: bool,
+  pub secret: String,
+  pub number: u32,
+  pub server: Server,
+  pub db: Db,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Server {
+  pub host: String,
+  pub port: u16,
+  pub workers: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Db {
+  pub url: String,
+  pub pool_size: u32,
+  pub timeout_secs: u64,
+}
+
+impl Default for Server {
+  fn default() -> Server {
+    Server {
+      host: "127.0.0.1".into(),
+      port: 8080,
+      workers: 4,
+    }
+  }
+}
+
+impl Default for Db {
+  fn default() -> Db {
+    Db {
+      url: "sqlite://app.db".into(),
+      pool_size: 5,
+      timeout_secs: 30,
+    }
+  }
+}
+
+impl Settings {
+  /// What the types alone do not rule out: the key at fault, and why.
+  pub fn validate(&self) -> Result<(), (&'static str, &'static str)> {
+    if self.secret.is_empty() {
+      return Err(("secret", "must not be empty"));
+    }
+    if self.server.port == 0 {
+      return Err(("server.port", "must not be 0"));
+    }
+    if self.server.workers == 0 {
+      return Err(("server.workers", "must be at least 1"));
+    }
+    if !(1 ..= 100).contains(&self.db.pool_size) {
+      return Err(("db.pool_size", "must be from 1 to 100"));
+    }
+    if !self.db.url.contains("://") {
+      return Err(("db.url", "must be a URL like postgres://host/db"));
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,89 @@
+//! Hot reload: the settings are loaded again whenever one of the files changes.
+
+use std::{collections::HashSet, path::Path, sync::Arc, time::Duration};
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+use tokio::sync::{mpsc, watch};
+
+use crate::{Error, Loader, Settings};
+
+/// How long to wait for the other events of a change, editors saving a file in several steps.
+const SETTLE: Duration = Duration::from_millis(50);
+
+/// Settings kept up to date with the files, until dropped.
+pub struct Watcher {
+  /// The settings last loaded. A change that does not load keeps them as they were.
+  pub settings: watch::Receiver<Arc<Settings>>,
+  /// Why changes did not load.
+  pub errors: mpsc::UnboundedReceiver<Error>,
+  _watcher: RecommendedWatcher,
+}
+
+impl Loader {
+  /// Loads the settings, then again whenever the files change. Must be called within a tokio
+  /// runtime.
+  pub fn watch(self) -> Result<Watcher, Error> {
+    let (settings_tx, settings) = watch::channel(Arc::new(self.load()?.settings));
+    let (errors_tx, errors) = mpsc::unbounded_channel();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+
+    // The directories are watched rather than the files, which editors replace
+    let mut files = HashSet::new();
+    let mut dirs = HashSet::new();
+    for path in self.files() {
+      let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+      };
+      let dir = dir.canonicalize().map_err(Error::io(dir))?;
+      if let Some(name) = path.file_name() {
+        files.insert(dir.join(name));
+      }
+      dirs.insert(dir);
+    }
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+      let event = match event {
+        Ok(event) if matches!(event.kind, EventKind::Access(_)) => return,
+        Ok(event) if !event.paths.iter().any(|p| files.contains(p)) => return,
+        Ok(_) => Ok(()),
+        Err(e) => Err(e),
+      };
+      let _ = events_tx.send(event);
+    })?;
+    for dir in &dirs {
+      watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+
+    // Ends with the watcher, which owns the sender of the events
+    tokio::spawn(async move {
+      while let Some(event) = events.recv().await {
+        if let Err(e) = event {
+          let _ = errors_tx.send(e.into());
+          continue;
+        }
+        tokio::time::sleep(SETTLE).await;
+        while events.try_recv().is_ok() {}
+        match self.load() {
+          Ok(loaded) => {
+            settings_tx.send_if_modified(|settings| {
+              let modified = **settings != loaded.settings;
+              if modified {
+                *settings = Arc::new(loaded.settings);
+              }
+              modified
+            });
+          }
+          Err(e) => {
+            let _ = errors_tx.send(e);
+          }
+        }
+      }
+    });
+
+    Ok(Watcher {
+      settings,
+      errors,
+      _watcher: watcher,
+    })
+  }
+}
@@ -0,0 +1,215 @@
+use std::{fs, path::Path, time::Duration};
+
+use config_crate_example::{Error, Loader};
+use tempfile::TempDir;
+
+const SYS: &str = "secret: THIS IS SECRET\ndebug: true\nnumber: 3\n";
+
+const APP: &str = r#"# The service
+[server]
+port = 9000 # not 8080
+
+[db]
+url = "postgres://localhost/app"
+pool_size = 10
+"#;
+
+/// A directory with sys.yml and app.toml in it, read in that order with no environment.
+fn files() -> (TempDir, Loader) {
+  let dir = tempfile::tempdir().unwrap();
+  fs::write(dir.path().join("sys.yml"), SYS).unwrap();
+  fs::write(dir.path().join("app.toml"), APP).unwrap();
+  let loader = Loader::new()
+    .file(dir.path().join("sys.yml"))
+    .file(dir.path().join("app.toml"))
+    .optional_file(dir.path().join("local.json"))
+    .env([]);
+  (dir, loader)
+}
+
+fn path(dir: &TempDir, name: &str) -> String {
+  dir.path().join(name).display().to_string()
+}
+
+#[test]
+fn layers_override_in_order() {
+  let (dir, loader) = files();
+  let settings = loader.load().unwrap().settings;
+  assert_eq!(settings.secret, "THIS IS SECRET");
+  assert_eq!(settings.server.port, 9000);
+  assert_eq!(settings.server.host, "127.0.0.1");
+  assert_eq!(settings.db.pool_size, 10);
+
+  fs::write(
+    dir.path().join("local.json"),
+    r#"{ "db": { "pool_size": 20 } }"#,
+  )
+  .unwrap();
+  let loaded = loader
+    .clone()
+    .env([
+      ("APP__DB__URL".into(), "postgres://db/app".into()),
+      ("APP__DB__POOL_SIZE".into(), "30".into()),
+      ("OTHER__DB__URL".into(), "ignored".into()),
+    ])
+    .set("db.pool_size", "40")
+    .load()
+    .unwrap();
+  assert_eq!(loaded.settings.db.url, "postgres://db/app");
+  assert_eq!(loaded.settings.db.pool_size, 40);
+
+  assert_eq!(
+    loaded.provenance(),
+    [
+      (
+        "db.pool_size",
+        "40",
+        "the command line flag for db.pool_size"
+      ),
+      ("db.timeout_secs", "30", "the defaults"),
+      ("db.url", "postgres://db/app", "$APP__DB__URL"),
+      ("debug", "true", &path(&dir, "sys.yml")),
+      ("number", "3", &path(&dir, "sys.yml")),
+      ("secret", "THIS IS SECRET", &path(&dir, "sys.yml")),
+      ("server.host", "127.0.0.1", "the defaults"),
+      ("server.port", "9000", &path(&dir, "app.toml")),
+      ("server.workers", "4", "the defaults"),
+    ]
+    .map(|(k, v, o)| (k.to_string(), v.to_string(), o.to_string()))
+  );
+}
+
+#[test]
+fn errors_tell_where_values_come_from() {
+  let (dir, loader) = files();
+  let err = loader
+    .clone()
+    .env([("APP__SERVER__PORT".into(), "http".into())])
+    .load()
+    .unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "invalid type: string \"http\", expected an integer for key `server.port` in \
+     $APP__SERVER__PORT"
+  );
+
+  let err = loader.clone().set("db.pool_size", "0").load().unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "db.pool_size = 0 from the command line flag for db.pool_size: must be from 1 to 100"
+  );
+
+  fs::write(
+    dir.path().join("app.toml"),
+    APP.replace("9000", "\"nine thousand\""),
+  )
+  .unwrap();
+  let err = loader.load().unwrap_err().to_string();
+  assert!(err.contains("for key `server.port`"), "{}", err);
+  assert!(
+    err.ends_with(&format!("in {}", path(&dir, "app.toml"))),
+    "{}",
+    err
+  );
+
+  fs::write(dir.path().join("app.toml"), "[server\nport = 1").unwrap();
+  let err = loader.load().unwrap_err().to_string();
+  assert!(err.contains(&path(&dir, "app.toml")), "{}", err);
+
+  let err = Loader::new().file(dir.path().join("missing.yml")).load();
+  assert!(matches!(err, Err(Error::Io { .. })));
+  let err = Loader::new().file(dir.path().join("sys.ini")).load();
+  assert!(matches!(err, Err(Error::Io { .. })));
+  fs::write(dir.path().join("sys.ini"), "").unwrap();
+  let err = Loader::new().file(dir.path().join("sys.ini")).load();
+  assert!(matches!(err, Err(Error::Format(_))));
+}
+
+#[test]
+fn persists_valid_changes_only() {
+  let (dir, loader) = files();
+  let app = dir.path().join("app.toml");
+  let loaded = loader.persist(&app, "db.pool_size", "12").unwrap();
+  assert_eq!(loaded.settings.db.pool_size, 12);
+  assert_eq!(
+    fs::read_to_string(&app).unwrap(),
+    APP.replace("pool_size = 10", "pool_size = 12")
+  );
+
+  let err = loader.persist(&app, "server.port", "0").unwrap_err();
+  assert!(matches!(err, Error::Invalid { .. }), "{}", err);
+  // Even with a flag over it
+  let err = loader
+    .clone()
+    .set("server.port", "9090")
+    .persist(&app, "server.port", "0")
+    .unwrap_err();
+  assert!(matches!(err, Error::Invalid { .. }), "{}", err);
+  assert_eq!(
+    fs::read_to_string(&app).unwrap(),
+    APP.replace("pool_size = 10", "pool_size = 12")
+  );
+
+  // Only the files read are written
+  let other = dir.path().join("other.toml");
+  let err = loader.persist(&other, "server.port", "0").unwrap_err();
+  assert!(matches!(err, Error::Edit { .. }), "{}", err);
+  assert!(!other.exists());
+
+  // A file created for the change is removed again
+  let local = dir.path().join("local.toml");
+  let loader = loader.optional_file(&local);
+  loader.persist(&local, "server.workers", "0").unwrap_err();
+  assert!(!local.exists());
+  let loaded = loader.persist(&local, "server.workers", "8").unwrap();
+  assert_eq!(loaded.settings.server.workers, 8);
+  assert_eq!(
+    fs::read_to_string(&local).unwrap(),
+    "[server]\nworkers = 8\n"
+  );
+}
+
+async fn changed(watcher: &mut config_crate_example::watch::Watcher) {
+  tokio::time::timeout(Duration::from_secs(10), watcher.settings.changed())
+    .await
+    .expect("no reload")
+    .unwrap();
+}
+
+fn rewrite(path: &Path, from: &str, to: &str) {
+  let text = fs::read_to_string(path).unwrap();
+  fs::write(path, text.replace(from, to)).unwrap();
+}
+
+#[tokio::test]
+async fn reloads_when_files_change() {
+  let (dir, loader) = files();
+  let app = dir.path().join("app.toml");
+  let mut watcher = loader.clone().watch().unwrap();
+  assert_eq!(watcher.settings.borrow_and_update().server.port, 9000);
+
+  rewrite(&app, "9000", "9001");
+  changed(&mut watcher).await;
+  assert_eq!(watcher.settings.borrow_and_update().server.port, 9001);
+
+  // Written back with toml_edit
+  loader.persist(&app, "db.pool_size", "11").unwrap();
+  changed(&mut watcher).await;
+  assert_eq!(watcher.settings.borrow_and_update().db.pool_size, 11);
+
+  // A broken change keeps the settings
+  rewrite(&app, "9001", "0");
+  let err = tokio::time::timeout(Duration::from_secs(10), watcher.errors.recv())
+    .await
+    .unwrap()
+    .unwrap();
+  assert!(matches!(err, Error::Invalid { .. }), "{}", err);
+  assert_eq!(watcher.settings.borrow().server.port, 9001);
+  assert!(!watcher.settings.has_changed().unwrap());
+
+  // Other files of the directory are not watched
+  fs::write(dir.path().join("notes.txt"), "hello").unwrap();
+  rewrite(&app, "port = 0", "port = 9002");
+  changed(&mut watcher).await;
+  assert_eq!(watcher.settings.borrow_and_update().server.port, 9002);
+}
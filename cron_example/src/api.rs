@@ -0,0 +1,207 @@
+use std::sync::Arc;
+
+use axum::{
+  extract::{Path, State},
+  http::StatusCode,
+  response::{IntoResponse, Response},
+  routing::{get, post},
+  Json, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+  scheduler::{Fired, Job, Scheduler},
+  store::RunRecord,
+};
+
+#[derive(Serialize)]
+struct JobView {
+  name: String,
+  schedule: String,
+  timezone: String,
+  paused: bool,
+  running: bool,
+  last_fire: DateTime<Utc>,
+  next_fire: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct JobDetail {
+  #[serde(flatten)]
+  job: JobView,
+  runs: Vec<RunRecord>,
+}
+
+impl JobView {
+  fn new(job: &Job, now: DateTime<Utc>) -> JobView {
+    JobView {
+      name: job.name().to_string(),
+      schedule: job.config.schedule.clone(),
+      timezone: job.config.timezone.to_string(),
+      paused: job.is_paused(),
+      running: job.is_running(),
+      last_fire: job.last_fire(),
+      next_fire: if job.is_paused() {
+        None
+      } else {
+        job.next_fire_after(job.last_fire().max(now))
+      },
+    }
+  }
+}
+
+/// `GET /jobs`, `GET /jobs/:name`, `POST /jobs/:name/{trigger,pause,resume}`.
+pub fn router(scheduler: Arc<Scheduler>) -> Router {
+  Router::new()
+    .route("/jobs", get(list_jobs))
+    .route("/jobs/:name", get(get_job))
+    .route("/jobs/:name/trigger", post(trigger_job))
+    .route("/jobs/:name/pause", post(pause_job))
+    .route("/jobs/:name/resume", post(resume_job))
+    .with_state(scheduler)
+}
+
+fn not_found(name: &str) -> Response {
+  (
+    StatusCode::NOT_FOUND,
+    Json(json!({ "error": format!("no job named {}", name) })),
+  )
+    .into_response()
+}
+
+fn internal(e: impl std::fmt::Display) -> Response {
+  (
+    StatusCode::INTERNAL_SERVER_ERROR,
+    Json(json!({ "error": e.to_string() })),
+  )
+    .into_response()
+}
+
+async fn list_jobs(State(scheduler): State<Arc<Scheduler>>) -> Json<Vec<JobView>> {
+  let now = scheduler.now();
+  Json(
+    scheduler
+      .jobs()
+      .iter()
+      .map(|job| JobView::new(job, now))
+      .collect(),
+  )
+}
+
+async fn get_job(State(scheduler): State<Arc<Scheduler>>, Path(name): Path<String>) -> Response {
+  let Some(job) = scheduler.job(&name) else {
+    return not_found(&name);
+  };
+  match scheduler.store().runs(&name, 20) {
+    Ok(runs) => Json(JobDetail {
+      job: JobView::new(job, scheduler.now()),
+      runs,
+    })
+    .into_response(),
+    Err(e) => internal(e),
+  }
+}
+
+async fn trigger_job(
+  State(scheduler): State<Arc<Scheduler>>,
+  Path(name): Path<String>,
+) -> Response {
+  match scheduler.trigger(&name) {
+    None => not_found(&name),
+    Some(Err(e)) => internal(e),
+    Some(Ok(Fired::Started(id))) => {
+      (StatusCode::ACCEPTED, Json(json!({ "run_id": id }))).into_response()
+    }
+    Some(Ok(Fired::Overlapping(id))) => (
+      StatusCode::CONFLICT,
+      Json(json!({ "run_id": id, "error": "job is already running" })),
+    )
+      .into_response(),
+  }
+}
+
+async fn pause_job(State(scheduler): State<Arc<Scheduler>>, Path(name): Path<String>) -> Response {
+  set_paused(&scheduler, &name, true)
+}
+
+async fn resume_job(State(scheduler): State<Arc<Scheduler>>, Path(name): Path<String>) -> Response {
+  set_paused(&scheduler, &name, false)
+}
+
+fn set_paused(scheduler: &Scheduler, name: &str, paused: bool) -> Response {
+  match scheduler.set_paused(name, paused) {
+    None => not_found(name),
+    Some(Err(e)) => internal(e),
+    Some(Ok(())) => StatusCode::NO_CONTENT.into_response(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use axum::{body::Body, http::Request};
+  use chrono::TimeZone;
+  use http_body_util::BodyExt;
+  use serde_json::Value;
+  use tower::ServiceExt;
+
+  use super::*;
+  use crate::{clock::MockClock, config::Config, store::Store};
+
+  async fn call(app: &Router, method: &str, uri: &str) -> (StatusCode, Value) {
+    let response = app
+      .clone()
+      .oneshot(
+        Request::builder()
+          .method(method)
+          .uri(uri)
+          .body(Body::empty())
+          .unwrap(),
+      )
+      .await
+      .unwrap();
+    let status = response.status();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let value = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+    (status, value)
+  }
+
+  #[tokio::test]
+  async fn lists_triggers_and_pauses_jobs() {
+    let t0 = Utc.with_ymd_and_hms(2024, 5, 1, 9, 0, 0).unwrap();
+    let config = Config::parse(
+      "[[job]]\nname = \"slow\"\nschedule = \"0 0 * * * *\"\ncommand = [\"sleep\", \"0.5\"]",
+    )
+    .unwrap();
+    let scheduler = Scheduler::new(
+      &config,
+      Arc::new(Store::open_in_memory().unwrap()),
+      Arc::new(MockClock::new(t0)),
+    )
+    .unwrap();
+    let app = router(scheduler);
+
+    let (status, jobs) = call(&app, "GET", "/jobs").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(jobs[0]["name"], "slow");
+    assert_eq!(jobs[0]["next_fire"], "2024-05-01T10:00:00Z");
+
+    let (status, body) = call(&app, "POST", "/jobs/slow/trigger").await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert!(body["run_id"].is_i64());
+    let (status, _) = call(&app, "POST", "/jobs/slow/trigger").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, _) = call(&app, "POST", "/jobs/slow/pause").await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, job) = call(&app, "GET", "/jobs/slow").await;
+    assert_eq!(job["paused"], true);
+    assert_eq!(job["running"], true);
+    assert_eq!(job["next_fire"], Value::Null);
+    assert_eq!(job["runs"].as_array().unwrap().len(), 2);
+
+    let (status, _) = call(&app, "POST", "/jobs/nope/trigger").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+  }
+}
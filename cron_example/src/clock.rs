@@ -0,0 +1,69 @@
+use std::{future, future::Future, pin::Pin, time};
+
+use chrono::{DateTime, Duration, Utc};
+use tokio::sync::watch;
+
+/// Source of "now" for the scheduler, so tests can move time by hand.
+pub trait Clock: Send + Sync + 'static {
+  fn now(&self) -> DateTime<Utc>;
+
+  fn sleep_until(&self, deadline: DateTime<Utc>) -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+
+  /// Sleeps for `duration` from now, or forever if that is past the end of time.
+  fn sleep(&self, duration: time::Duration) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+    let deadline = Duration::from_std(duration)
+      .ok()
+      .and_then(|duration| self.now().checked_add_signed(duration));
+    match deadline {
+      Some(deadline) => self.sleep_until(deadline),
+      None => Box::pin(future::pending()),
+    }
+  }
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> DateTime<Utc> {
+    Utc::now()
+  }
+
+  fn sleep_until(&self, deadline: DateTime<Utc>) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+    let wait = (deadline - Utc::now()).to_std().unwrap_or_default();
+    Box::pin(tokio::time::sleep(wait))
+  }
+}
+
+/// A clock that only moves when `advance` or `set` is called.
+pub struct MockClock {
+  now: watch::Sender<DateTime<Utc>>,
+}
+
+impl MockClock {
+  pub fn new(now: DateTime<Utc>) -> MockClock {
+    MockClock {
+      now: watch::Sender::new(now),
+    }
+  }
+
+  pub fn advance(&self, by: Duration) {
+    self.now.send_modify(|now| *now += by);
+  }
+
+  pub fn set(&self, now: DateTime<Utc>) {
+    self.now.send_replace(now);
+  }
+}
+
+impl Clock for MockClock {
+  fn now(&self) -> DateTime<Utc> {
+    *self.now.borrow()
+  }
+
+  fn sleep_until(&self, deadline: DateTime<Utc>) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+    let mut rx = self.now.subscribe();
+    Box::pin(async move {
+      _ = rx.wait_for(|now| *now >= deadline).await;
+    })
+  }
+}
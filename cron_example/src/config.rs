@@ -0,0 +1,210 @@
+use std::{collections::HashMap, fs, path::Path, str::FromStr, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use chrono_tz::Tz;
+use cron::Schedule;
+use serde::Deserialize;
+
+/// The whole `jobs.toml` file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+  #[serde(default = "default_listen")]
+  pub listen: String,
+  #[serde(default = "default_database")]
+  pub database: String,
+  #[serde(default, rename = "job")]
+  pub jobs: Vec<JobConfig>,
+}
+
+/// What to do with fire times that passed while the daemon was down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissedPolicy {
+  /// Run once for all missed fire times.
+  CatchUp,
+  /// Forget them and wait for the next fire time.
+  Skip,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+  /// Program followed by its arguments.
+  Command(Vec<String>),
+  Http {
+    url: String,
+    #[serde(default = "default_method")]
+    method: String,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+  },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JobConfig {
+  pub name: String,
+  /// `sec min hour day-of-month month day-of-week [year]`, like `cron::Schedule` expects.
+  pub schedule: String,
+  #[serde(default = "default_timezone")]
+  pub timezone: Tz,
+  #[serde(default = "default_missed")]
+  pub missed: MissedPolicy,
+  #[serde(default = "default_timeout_secs")]
+  pub timeout_secs: f64,
+  #[serde(default)]
+  pub retries: u32,
+  /// Wait before the first retry, doubled for every further one.
+  #[serde(default = "default_backoff_ms")]
+  pub backoff_ms: u64,
+  #[serde(flatten)]
+  pub target: Target,
+}
+
+fn default_listen() -> String {
+  "127.0.0.1:8080".to_string()
+}
+
+fn default_database() -> String {
+  "cron.db".to_string()
+}
+
+fn default_method() -> String {
+  "GET".to_string()
+}
+
+fn default_timezone() -> Tz {
+  Tz::UTC
+}
+
+fn default_missed() -> MissedPolicy {
+  MissedPolicy::Skip
+}
+
+fn default_timeout_secs() -> f64 {
+  60.0
+}
+
+fn default_backoff_ms() -> u64 {
+  1000
+}
+
+impl JobConfig {
+  pub fn parse_schedule(&self) -> Result<Schedule> {
+    Schedule::from_str(&self.schedule)
+      .map_err(|e| anyhow::anyhow!("job {}: bad schedule {:?}: {}", self.name, self.schedule, e))
+  }
+
+  /// Checked by [`Config::parse`], the longest wait otherwise.
+  pub fn timeout(&self) -> Duration {
+    Duration::try_from_secs_f64(self.timeout_secs).unwrap_or(Duration::MAX)
+  }
+
+  pub fn backoff(&self, attempt: u32) -> Duration {
+    Duration::from_millis(self.backoff_ms.saturating_mul(1 << attempt.min(16)))
+  }
+}
+
+impl Config {
+  pub fn load(path: &Path) -> Result<Config> {
+    let text = fs::read_to_string(path).with_context(|| format!("read {:?}", path))?;
+    Config::parse(&text).with_context(|| format!("parse {:?}", path))
+  }
+
+  pub fn parse(text: &str) -> Result<Config> {
+    let config: Config = toml::from_str(text)?;
+    let mut names = std::collections::HashSet::new();
+    for job in &config.jobs {
+      job.parse_schedule()?;
+      if !names.insert(&job.name) {
+        bail!("job {} is defined twice", job.name);
+      }
+      // Negative, NaN and infinite timeouts are errors too
+      if Duration::try_from_secs_f64(job.timeout_secs).map_or(true, |t| t.is_zero()) {
+        bail!(
+          "job {}: timeout_secs must be a positive number, got {}",
+          job.name,
+          job.timeout_secs
+        );
+      }
+      if let Target::Command(command) = &job.target {
+        if command.is_empty() {
+          bail!("job {}: empty command", job.name);
+        }
+      }
+    }
+    Ok(config)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_command_and_http_jobs() {
+    let config = Config::parse(
+      r#"
+      listen = "0.0.0.0:9000"
+
+      [[job]]
+      name = "backup"
+      schedule = "0 30 9 * * Mon-Fri"
+      timezone = "Asia/Shanghai"
+      missed = "catch_up"
+      retries = 2
+      command = ["sh", "-c", "echo backup"]
+
+      [[job]]
+      name = "ping"
+      schedule = "0 */5 * * * *"
+      http = { url = "http://127.0.0.1:9/ping", method = "POST", body = "{}" }
+      "#,
+    )
+    .unwrap();
+    assert_eq!(config.listen, "0.0.0.0:9000");
+    assert_eq!(config.database, "cron.db");
+    let backup = &config.jobs[0];
+    assert_eq!(backup.timezone, chrono_tz::Asia::Shanghai);
+    assert_eq!(backup.missed, MissedPolicy::CatchUp);
+    assert_eq!(backup.backoff(2), Duration::from_secs(4));
+    assert_eq!(
+      backup.target,
+      Target::Command(vec!["sh".into(), "-c".into(), "echo backup".into()])
+    );
+    let ping = &config.jobs[1];
+    assert_eq!(ping.missed, MissedPolicy::Skip);
+    assert!(matches!(&ping.target, Target::Http { method, .. } if method == "POST"));
+  }
+
+  #[test]
+  fn rejects_bad_jobs() {
+    let bad_schedule = r#"
+      [[job]]
+      name = "x"
+      schedule = "every minute"
+      command = ["true"]
+    "#;
+    assert!(Config::parse(bad_schedule).is_err());
+    let duplicate = r#"
+      [[job]]
+      name = "x"
+      schedule = "0 * * * * *"
+      command = ["true"]
+      [[job]]
+      name = "x"
+      schedule = "0 * * * * *"
+      command = ["true"]
+    "#;
+    assert!(Config::parse(duplicate).is_err());
+    for timeout in ["0", "-1", "nan", "inf", "1e300"] {
+      let bad_timeout = format!(
+        "[[job]]\nname = \"x\"\nschedule = \"0 * * * * *\"\ntimeout_secs = {}\ncommand = \
+         [\"true\"]",
+        timeout
+      );
+      assert!(Config::parse(&bad_timeout).is_err(), "{}", timeout);
+    }
+  }
+}
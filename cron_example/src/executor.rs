@@ -0,0 +1,181 @@
+use std::process::Stdio;
+
+use tokio::process::Command;
+
+use crate::{
+  clock::Clock,
+  config::{JobConfig, Target},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+  /// `ok`, `failed` or `timeout`, the result of the last attempt.
+  pub status: &'static str,
+  pub attempts: u32,
+  pub message: Option<String>,
+}
+
+enum AttemptError {
+  Timeout,
+  Failed(String),
+}
+
+/// Runs the job's target, retrying with exponential backoff until it succeeds or runs out of
+/// retries. Every attempt is cut off after the job's timeout. Both are timed by `clock`.
+pub async fn execute(job: &JobConfig, http: &reqwest::Client, clock: &dyn Clock) -> Outcome {
+  let mut attempt = 0;
+  loop {
+    let result = tokio::select! {
+      result = attempt_once(&job.target, http) => result,
+      _ = clock.sleep(job.timeout()) => Err(AttemptError::Timeout),
+    };
+    attempt += 1;
+    match result {
+      Ok(()) => {
+        return Outcome {
+          status: "ok",
+          attempts: attempt,
+          message: None,
+        }
+      }
+      Err(e) if attempt > job.retries => {
+        let (status, message) = match e {
+          AttemptError::Timeout => ("timeout", format!("timed out after {:?}", job.timeout())),
+          AttemptError::Failed(message) => ("failed", message),
+        };
+        return Outcome {
+          status,
+          attempts: attempt,
+          message: Some(message),
+        };
+      }
+      Err(_) => clock.sleep(job.backoff(attempt - 1)).await,
+    }
+  }
+}
+
+async fn attempt_once(target: &Target, http: &reqwest::Client) -> Result<(), AttemptError> {
+  match target {
+    Target::Command(command) => {
+      // kill_on_drop makes the timeout above stop the process too.
+      let output = Command::new(&command[0])
+        .args(&command[1 ..])
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| AttemptError::Failed(format!("spawn {}: {}", command[0], e)))?;
+      if output.status.success() {
+        Ok(())
+      } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(AttemptError::Failed(format!(
+          "{}: {}",
+          output.status,
+          stderr.trim()
+        )))
+      }
+    }
+    Target::Http {
+      url,
+      method,
+      body,
+      headers,
+    } => {
+      let method = reqwest::Method::from_bytes(method.as_bytes())
+        .map_err(|e| AttemptError::Failed(format!("method {}: {}", method, e)))?;
+      let mut request = http.request(method, url);
+      for (name, value) in headers {
+        request = request.header(name, value);
+      }
+      if let Some(body) = body {
+        request = request.body(body.clone());
+      }
+      let response = request
+        .send()
+        .await
+        .map_err(|e| AttemptError::Failed(e.to_string()))?;
+      if response.status().is_success() {
+        Ok(())
+      } else {
+        Err(AttemptError::Failed(format!(
+          "{} {}",
+          url,
+          response.status()
+        )))
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{sync::Arc, time::Duration};
+
+  use chrono::{TimeZone, Utc};
+
+  use super::*;
+  use crate::{
+    clock::{MockClock, SystemClock},
+    config::Config,
+  };
+
+  fn job(extra: &str) -> JobConfig {
+    Config::parse(&format!(
+      "[[job]]\nname = \"t\"\nschedule = \"0 * * * * *\"\nbackoff_ms = 10\n{}",
+      extra
+    ))
+    .unwrap()
+    .jobs
+    .remove(0)
+  }
+
+  #[tokio::test]
+  async fn retries_until_out_of_attempts() {
+    let http = reqwest::Client::new();
+    let ok = execute(&job("command = [\"true\"]"), &http, &SystemClock).await;
+    assert_eq!(ok.status, "ok");
+    assert_eq!(ok.attempts, 1);
+
+    let failing = job("retries = 2\ncommand = [\"sh\", \"-c\", \"echo boom >&2; exit 3\"]");
+    let failed = execute(&failing, &http, &SystemClock).await;
+    assert_eq!(failed.status, "failed");
+    assert_eq!(failed.attempts, 3);
+    assert!(failed.message.unwrap().contains("boom"));
+  }
+
+  #[tokio::test]
+  async fn times_out_long_attempts() {
+    let http = reqwest::Client::new();
+    let slow = job("timeout_secs = 0.1\nretries = 1\ncommand = [\"sleep\", \"5\"]");
+    let started = std::time::Instant::now();
+    let outcome = execute(&slow, &http, &SystemClock).await;
+    assert_eq!(outcome.status, "timeout");
+    assert_eq!(outcome.attempts, 2);
+    assert!(started.elapsed() < Duration::from_secs(2));
+  }
+
+  #[tokio::test]
+  async fn times_out_and_backs_off_by_the_clock() {
+    let t0 = Utc.with_ymd_and_hms(2024, 5, 1, 9, 0, 0).unwrap();
+    let clock = Arc::new(MockClock::new(t0));
+    let mut slow = job("timeout_secs = 30\nretries = 1\ncommand = [\"sleep\", \"5\"]");
+    slow.backoff_ms = 60_000;
+    let started = std::time::Instant::now();
+    let running = tokio::spawn({
+      let clock = clock.clone();
+      async move { execute(&slow, &reqwest::Client::new(), &*clock).await }
+    });
+    while !running.is_finished() {
+      clock.advance(chrono::Duration::seconds(1));
+      tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    let outcome = running.await.unwrap();
+    assert_eq!(outcome.status, "timeout");
+    assert_eq!(outcome.attempts, 2);
+    // Two timeouts and the backoff between them, with the commands killed on the way
+    assert!(clock.now() - t0 >= chrono::Duration::seconds(120));
+    assert!(started.elapsed() < Duration::from_secs(5));
+  }
+}
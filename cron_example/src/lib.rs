@@ -0,0 +1,9 @@
+//! A cron daemon: jobs come from `jobs.toml`, last fire times and run history live in SQLite, and a
+//! small REST API lists, triggers and pauses jobs.
+
+pub mod api;
+pub mod clock;
+pub mod config;
+pub mod executor;
+pub mod scheduler;
+pub mod store;
@@ -1,14 +1,59 @@
-use std::str::FromStr;
+use std::{path::PathBuf, str::FromStr, sync::Arc};
 
+use anyhow::Result;
 use chrono::Utc;
+use clap::{Parser, Subcommand};
 use cron::Schedule;
+use cron_example::{api, clock::SystemClock, config::Config, scheduler::Scheduler, store::Store};
 
-fn main() {
-  //               sec  min   hour   day of month   month   day of week   year
-  let expression = "0   30   9,12,15     1,30       May-Oct  Mon,Wed,Fri  2022/10";
-  let schedule = Schedule::from_str(expression).unwrap();
-  println!("Upcoming fire times:");
-  for datetime in schedule.upcoming(Utc).take(10) {
-    println!("-> {}", datetime);
+// cargo run -- upcoming "0   30   9,12,15     1,30       May-Oct  Mon,Wed,Fri  2022/10"
+// cargo run -- daemon jobs.toml
+// curl localhost:8080/jobs
+// curl -X POST localhost:8080/jobs/hello/trigger
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Print the next fire times of a cron expression
+  Upcoming {
+    //       sec  min   hour   day of month   month   day of week   year
+    expression: String,
+    #[arg(short, long, default_value_t = 10)]
+    count: usize,
+  },
+  /// Run the jobs from a config file and serve the REST API
+  Daemon {
+    #[arg(default_value = "jobs.toml")]
+    config: PathBuf,
+  },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+  match Args::parse().command {
+    Command::Upcoming { expression, count } => {
+      let schedule = Schedule::from_str(&expression)?;
+      println!("Upcoming fire times:");
+      for datetime in schedule.upcoming(Utc).take(count) {
+        println!("-> {}", datetime);
+      }
+    }
+    Command::Daemon { config } => {
+      let config = Config::load(&config)?;
+      let store = Arc::new(Store::open(&config.database)?);
+      let scheduler = Scheduler::new(&config, store, Arc::new(SystemClock))?;
+      tokio::spawn(scheduler.clone().run());
+
+      let listener = tokio::net::TcpListener::bind(&config.listen).await?;
+      println!("listening on {}", config.listen);
+      axum::serve(listener, api::router(scheduler)).await?;
+    }
   }
+  Ok(())
 }
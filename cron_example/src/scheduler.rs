@@ -0,0 +1,407 @@
+use std::sync::{
+  atomic::{AtomicBool, Ordering},
+  Arc, Mutex,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use tokio::sync::Notify;
+
+use crate::{
+  clock::Clock,
+  config::{Config, JobConfig, MissedPolicy},
+  executor,
+  store::Store,
+};
+
+/// One configured job and its runtime state.
+pub struct Job {
+  pub config: JobConfig,
+  schedule: Schedule,
+  running: AtomicBool,
+  paused: AtomicBool,
+  last_fire: Mutex<DateTime<Utc>>,
+}
+
+impl Job {
+  pub fn name(&self) -> &str {
+    &self.config.name
+  }
+
+  pub fn is_running(&self) -> bool {
+    self.running.load(Ordering::SeqCst)
+  }
+
+  pub fn is_paused(&self) -> bool {
+    self.paused.load(Ordering::SeqCst)
+  }
+
+  pub fn last_fire(&self) -> DateTime<Utc> {
+    *self.last_fire.lock().unwrap()
+  }
+
+  /// First fire time strictly after `after`, evaluated in the job's timezone.
+  pub fn next_fire_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let tz = self.config.timezone;
+    self
+      .schedule
+      .after(&after.with_timezone(&tz))
+      .next()
+      .map(|t| t.with_timezone(&Utc))
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fired {
+  Started(i64),
+  /// The previous run is still going, the new one was recorded as skipped.
+  Overlapping(i64),
+}
+
+pub struct Scheduler {
+  clock: Arc<dyn Clock>,
+  store: Arc<Store>,
+  http: reqwest::Client,
+  jobs: Vec<Arc<Job>>,
+  started_at: DateTime<Utc>,
+  wake: Notify,
+}
+
+impl Scheduler {
+  /// Restores last fire times and pause flags from the store, and fails the runs the previous
+  /// process left running. Jobs seen for the first time start counting from now, so they do not
+  /// fire immediately.
+  pub fn new(config: &Config, store: Arc<Store>, clock: Arc<dyn Clock>) -> Result<Arc<Scheduler>> {
+    let started_at = clock.now();
+    let interrupted = store.fail_interrupted_runs(started_at)?;
+    if interrupted > 0 {
+      println!(
+        "{} run(s) interrupted by the last shutdown marked failed",
+        interrupted
+      );
+    }
+    let mut jobs = vec![];
+    for job in &config.jobs {
+      let last_fire = match store.last_fire(&job.name)? {
+        Some(last_fire) => last_fire,
+        None => {
+          store.set_last_fire(&job.name, started_at)?;
+          started_at
+        }
+      };
+      jobs.push(Arc::new(Job {
+        schedule: job.parse_schedule()?,
+        running: AtomicBool::new(false),
+        paused: AtomicBool::new(store.paused(&job.name)?),
+        last_fire: Mutex::new(last_fire),
+        config: job.clone(),
+      }));
+    }
+    Ok(Arc::new(Scheduler {
+      clock,
+      store,
+      http: reqwest::Client::new(),
+      jobs,
+      started_at,
+      wake: Notify::new(),
+    }))
+  }
+
+  pub fn jobs(&self) -> &[Arc<Job>] {
+    &self.jobs
+  }
+
+  pub fn job(&self, name: &str) -> Option<&Arc<Job>> {
+    self.jobs.iter().find(|j| j.name() == name)
+  }
+
+  pub fn store(&self) -> &Store {
+    &self.store
+  }
+
+  pub fn now(&self) -> DateTime<Utc> {
+    self.clock.now()
+  }
+
+  /// Runs forever, firing jobs as the clock reaches their schedule.
+  pub async fn run(self: Arc<Self>) {
+    loop {
+      let next = self.tick();
+      match next {
+        Some(at) => {
+          tokio::select! {
+            _ = self.clock.sleep_until(at) => {}
+            _ = self.wake.notified() => {}
+          }
+        }
+        None => self.wake.notified().await,
+      }
+    }
+  }
+
+  /// Fires every job that is due and returns when the next one will be.
+  pub fn tick(self: &Arc<Self>) -> Option<DateTime<Utc>> {
+    let now = self.clock.now();
+    let mut next: Option<DateTime<Utc>> = None;
+    for job in &self.jobs {
+      if job.is_paused() {
+        continue;
+      }
+      let Some(due) = job.next_fire_after(job.last_fire()) else {
+        continue;
+      };
+      if due > now {
+        next = Some(next.map_or(due, |n| n.min(due)));
+        continue;
+      }
+
+      // Fire times before the start were missed while the daemon was down.
+      let fired = match (due < self.started_at, job.config.missed) {
+        (false, _) => self.fire(job, "schedule", Some(due)).map(Some),
+        (true, MissedPolicy::CatchUp) => self.fire(job, "catch_up", Some(due)).map(Some),
+        (true, MissedPolicy::Skip) => {
+          println!("{}: skipping runs missed since {}", job.name(), due);
+          Ok(None)
+        }
+      };
+      if let Err(e) = fired {
+        eprintln!("{}: cannot start run: {}", job.name(), e);
+      }
+      // Several fire times may have passed, they collapse into the run above.
+      self.set_last_fire(job, now);
+      if let Some(due) = job.next_fire_after(now) {
+        next = Some(next.map_or(due, |n| n.min(due)));
+      }
+    }
+    next
+  }
+
+  fn set_last_fire(&self, job: &Job, at: DateTime<Utc>) {
+    *job.last_fire.lock().unwrap() = at;
+    if let Err(e) = self.store.set_last_fire(job.name(), at) {
+      eprintln!("{}: cannot save last fire time: {}", job.name(), e);
+    }
+  }
+
+  /// Runs a job now, outside its schedule. Returns `None` for an unknown job.
+  pub fn trigger(self: &Arc<Self>, name: &str) -> Option<Result<Fired>> {
+    let job = self.job(name)?.clone();
+    Some(self.fire(&job, "manual", None))
+  }
+
+  pub fn set_paused(&self, name: &str, paused: bool) -> Option<Result<()>> {
+    let job = self.job(name)?;
+    job.paused.store(paused, Ordering::SeqCst);
+    if !paused {
+      // Resuming does not replay what was scheduled while paused.
+      self.set_last_fire(job, self.clock.now());
+    }
+    let saved = self.store.set_paused(name, paused).map_err(Into::into);
+    self.wake.notify_one();
+    Some(saved)
+  }
+
+  fn fire(
+    self: &Arc<Self>,
+    job: &Arc<Job>,
+    reason: &str,
+    scheduled: Option<DateTime<Utc>>,
+  ) -> Result<Fired> {
+    let now = self.clock.now();
+    if job.running.swap(true, Ordering::SeqCst) {
+      let id = self
+        .store
+        .start_run(job.name(), reason, scheduled, now, "skipped_overlap")?;
+      self.store.finish_run(id, now, "skipped_overlap", 0, None)?;
+      println!("{}: still running, skipped", job.name());
+      return Ok(Fired::Overlapping(id));
+    }
+    let id = match self
+      .store
+      .start_run(job.name(), reason, scheduled, now, "running")
+    {
+      Ok(id) => id,
+      Err(e) => {
+        job.running.store(false, Ordering::SeqCst);
+        return Err(e.into());
+      }
+    };
+
+    let scheduler = self.clone();
+    let job = job.clone();
+    tokio::spawn(async move {
+      let outcome = executor::execute(&job.config, &scheduler.http, &*scheduler.clock).await;
+      println!(
+        "{}: {} after {} attempt(s)",
+        job.name(),
+        outcome.status,
+        outcome.attempts
+      );
+      if let Err(e) = scheduler.store.finish_run(
+        id,
+        scheduler.clock.now(),
+        outcome.status,
+        outcome.attempts,
+        outcome.message.as_deref(),
+      ) {
+        eprintln!("{}: cannot save run: {}", job.name(), e);
+      }
+      job.running.store(false, Ordering::SeqCst);
+    });
+    Ok(Fired::Started(id))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use chrono::TimeZone;
+
+  use super::*;
+  use crate::{clock::MockClock, store::RunRecord};
+
+  fn t0() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 5, 1, 9, 0, 0).unwrap()
+  }
+
+  fn config(extra: &str) -> Config {
+    Config::parse(&format!(
+      "[[job]]\nname = \"j\"\nschedule = \"0 * * * * *\"\n{}",
+      extra
+    ))
+    .unwrap()
+  }
+
+  /// Waits (in real time) until the store holds `count` finished runs.
+  async fn finished_runs(store: &Store, count: usize) -> Vec<RunRecord> {
+    for _ in 0 .. 200 {
+      let runs = store.runs("j", 100).unwrap();
+      if runs.iter().filter(|r| r.status != "running").count() >= count {
+        return runs;
+      }
+      tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("expected {} finished runs", count);
+  }
+
+  #[tokio::test]
+  async fn fires_when_the_mock_clock_reaches_the_schedule() {
+    let clock = Arc::new(MockClock::new(t0() + chrono::Duration::seconds(10)));
+    let store = Arc::new(Store::open_in_memory().unwrap());
+    let scheduler = Scheduler::new(
+      &config("command = [\"true\"]"),
+      store.clone(),
+      clock.clone(),
+    )
+    .unwrap();
+    tokio::spawn(scheduler.clone().run());
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(store.runs("j", 10).unwrap().is_empty());
+
+    clock.advance(chrono::Duration::seconds(50));
+    let runs = finished_runs(&store, 1).await;
+    assert_eq!(runs[0].status, "ok");
+    assert_eq!(runs[0].reason, "schedule");
+    assert_eq!(
+      runs[0].scheduled_at,
+      Some(t0() + chrono::Duration::minutes(1))
+    );
+
+    clock.advance(chrono::Duration::minutes(1));
+    let runs = finished_runs(&store, 2).await;
+    assert_eq!(runs.len(), 2);
+  }
+
+  #[tokio::test]
+  async fn missed_runs_follow_the_policy_after_restart() {
+    for (policy, expected) in [("catch_up", 1), ("skip", 0)] {
+      let store = Arc::new(Store::open_in_memory().unwrap());
+      store.set_last_fire("j", t0()).unwrap();
+      // Down for an hour: 60 fire times were missed.
+      let clock = Arc::new(MockClock::new(
+        t0() + chrono::Duration::minutes(60) + chrono::Duration::seconds(30),
+      ));
+      let config = config(&format!("missed = \"{}\"\ncommand = [\"true\"]", policy));
+      let scheduler = Scheduler::new(&config, store.clone(), clock.clone()).unwrap();
+
+      let next = scheduler.tick();
+      assert_eq!(next, Some(t0() + chrono::Duration::minutes(61)));
+      if expected > 0 {
+        let runs = finished_runs(&store, expected).await;
+        assert_eq!(runs[0].reason, "catch_up");
+        assert_eq!(
+          runs[0].scheduled_at,
+          Some(t0() + chrono::Duration::minutes(1))
+        );
+      }
+      tokio::time::sleep(Duration::from_millis(50)).await;
+      assert_eq!(
+        store.runs("j", 100).unwrap().len(),
+        expected,
+        "policy {}",
+        policy
+      );
+      assert_eq!(store.last_fire("j").unwrap(), Some(clock.now()));
+    }
+  }
+
+  #[tokio::test]
+  async fn runs_left_running_are_failed_on_start() {
+    let store = Arc::new(Store::open_in_memory().unwrap());
+    let id = store
+      .start_run("j", "schedule", Some(t0()), t0(), "running")
+      .unwrap();
+    let clock = Arc::new(MockClock::new(t0() + chrono::Duration::minutes(5)));
+    Scheduler::new(
+      &config("command = [\"true\"]"),
+      store.clone(),
+      clock.clone(),
+    )
+    .unwrap();
+
+    let runs = store.runs("j", 10).unwrap();
+    assert_eq!(runs.len(), 1);
+    assert_eq!(runs[0].id, id);
+    assert_eq!(runs[0].status, "failed");
+    assert_eq!(runs[0].finished_at, Some(clock.now()));
+    assert!(runs[0].message.as_deref().unwrap().contains("interrupted"));
+  }
+
+  #[tokio::test]
+  async fn never_overlaps_and_respects_pause() {
+    let clock = Arc::new(MockClock::new(t0()));
+    let store = Arc::new(Store::open_in_memory().unwrap());
+    let scheduler = Scheduler::new(
+      &config("command = [\"sleep\", \"0.3\"]"),
+      store.clone(),
+      clock.clone(),
+    )
+    .unwrap();
+
+    assert!(matches!(
+      scheduler.trigger("j").unwrap().unwrap(),
+      Fired::Started(_)
+    ));
+    assert!(matches!(
+      scheduler.trigger("j").unwrap().unwrap(),
+      Fired::Overlapping(_)
+    ));
+    assert!(scheduler.trigger("nope").is_none());
+    let runs = finished_runs(&store, 2).await;
+    let mut statuses: Vec<&str> = runs.iter().map(|r| r.status.as_str()).collect();
+    statuses.sort();
+    assert_eq!(statuses, vec!["ok", "skipped_overlap"]);
+
+    scheduler.set_paused("j", true).unwrap().unwrap();
+    clock.advance(chrono::Duration::minutes(5));
+    assert_eq!(scheduler.tick(), None);
+    assert_eq!(store.runs("j", 100).unwrap().len(), 2);
+
+    scheduler.set_paused("j", false).unwrap().unwrap();
+    assert_eq!(scheduler.tick(), Some(t0() + chrono::Duration::minutes(6)));
+    assert!(store.paused("j").is_ok_and(|p| !p));
+  }
+}
@@ -0,0 +1,207 @@
+use std::{
+  path::Path,
+  sync::{Mutex, MutexGuard},
+};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+/// Last fire times, pause flags and run history, kept in SQLite so they survive a restart.
+pub struct Store {
+  conn: Mutex<Connection>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunRecord {
+  pub id: i64,
+  pub job: String,
+  /// `schedule`, `catch_up` or `manual`.
+  pub reason: String,
+  pub scheduled_at: Option<DateTime<Utc>>,
+  pub started_at: DateTime<Utc>,
+  pub finished_at: Option<DateTime<Utc>>,
+  /// `running`, `ok`, `failed`, `timeout` or `skipped_overlap`.
+  pub status: String,
+  pub attempts: u32,
+  pub message: Option<String>,
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS job_state (
+  name      TEXT PRIMARY KEY,
+  last_fire TEXT,
+  paused    INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS runs (
+  id           INTEGER PRIMARY KEY AUTOINCREMENT,
+  job          TEXT NOT NULL,
+  reason       TEXT NOT NULL,
+  scheduled_at TEXT,
+  started_at   TEXT NOT NULL,
+  finished_at  TEXT,
+  status       TEXT NOT NULL,
+  attempts     INTEGER NOT NULL DEFAULT 0,
+  message      TEXT
+);
+";
+
+impl Store {
+  pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Store> {
+    Store::init(Connection::open(path)?)
+  }
+
+  pub fn open_in_memory() -> rusqlite::Result<Store> {
+    Store::init(Connection::open_in_memory()?)
+  }
+
+  fn init(conn: Connection) -> rusqlite::Result<Store> {
+    conn.execute_batch(SCHEMA)?;
+    Ok(Store {
+      conn: Mutex::new(conn),
+    })
+  }
+
+  fn conn(&self) -> MutexGuard<'_, Connection> {
+    self.conn.lock().unwrap()
+  }
+
+  pub fn last_fire(&self, job: &str) -> rusqlite::Result<Option<DateTime<Utc>>> {
+    self
+      .conn()
+      .query_row(
+        "SELECT last_fire FROM job_state WHERE name = ?1",
+        [job],
+        |row| row.get(0),
+      )
+      .optional()
+      .map(Option::flatten)
+  }
+
+  pub fn set_last_fire(&self, job: &str, at: DateTime<Utc>) -> rusqlite::Result<()> {
+    self.conn().execute(
+      "INSERT INTO job_state (name, last_fire) VALUES (?1, ?2)
+       ON CONFLICT(name) DO UPDATE SET last_fire = excluded.last_fire",
+      params![job, at],
+    )?;
+    Ok(())
+  }
+
+  pub fn paused(&self, job: &str) -> rusqlite::Result<bool> {
+    Ok(
+      self
+        .conn()
+        .query_row(
+          "SELECT paused FROM job_state WHERE name = ?1",
+          [job],
+          |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or(false),
+    )
+  }
+
+  pub fn set_paused(&self, job: &str, paused: bool) -> rusqlite::Result<()> {
+    self.conn().execute(
+      "INSERT INTO job_state (name, paused) VALUES (?1, ?2)
+       ON CONFLICT(name) DO UPDATE SET paused = excluded.paused",
+      params![job, paused],
+    )?;
+    Ok(())
+  }
+
+  pub fn start_run(
+    &self,
+    job: &str,
+    reason: &str,
+    scheduled_at: Option<DateTime<Utc>>,
+    started_at: DateTime<Utc>,
+    status: &str,
+  ) -> rusqlite::Result<i64> {
+    let conn = self.conn();
+    conn.execute(
+      "INSERT INTO runs (job, reason, scheduled_at, started_at, status)
+       VALUES (?1, ?2, ?3, ?4, ?5)",
+      params![job, reason, scheduled_at, started_at, status],
+    )?;
+    Ok(conn.last_insert_rowid())
+  }
+
+  pub fn finish_run(
+    &self,
+    id: i64,
+    finished_at: DateTime<Utc>,
+    status: &str,
+    attempts: u32,
+    message: Option<&str>,
+  ) -> rusqlite::Result<()> {
+    self.conn().execute(
+      "UPDATE runs SET finished_at = ?2, status = ?3, attempts = ?4, message = ?5 WHERE id = ?1",
+      params![id, finished_at, status, attempts, message],
+    )?;
+    Ok(())
+  }
+
+  /// Fails the runs left `running` by a process that stopped during them. Returns how many.
+  pub fn fail_interrupted_runs(&self, at: DateTime<Utc>) -> rusqlite::Result<usize> {
+    self.conn().execute(
+      "UPDATE runs SET finished_at = ?1, status = 'failed',
+         message = 'interrupted: the scheduler stopped during the run'
+       WHERE status = 'running'",
+      params![at],
+    )
+  }
+
+  /// Newest runs first.
+  pub fn runs(&self, job: &str, limit: usize) -> rusqlite::Result<Vec<RunRecord>> {
+    let conn = self.conn();
+    let mut stmt = conn.prepare(
+      "SELECT id, job, reason, scheduled_at, started_at, finished_at, status, attempts, message
+       FROM runs WHERE job = ?1 ORDER BY id DESC LIMIT ?2",
+    )?;
+    let rows = stmt.query_map(params![job, limit as i64], |row| {
+      Ok(RunRecord {
+        id: row.get(0)?,
+        job: row.get(1)?,
+        reason: row.get(2)?,
+        scheduled_at: row.get(3)?,
+        started_at: row.get(4)?,
+        finished_at: row.get(5)?,
+        status: row.get(6)?,
+        attempts: row.get(7)?,
+        message: row.get(8)?,
+      })
+    })?;
+    rows.collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::TimeZone;
+
+  use super::*;
+
+  #[test]
+  fn keeps_state_and_runs() {
+    let store = Store::open_in_memory().unwrap();
+    let t = Utc.with_ymd_and_hms(2024, 5, 1, 9, 30, 0).unwrap();
+    assert_eq!(store.last_fire("a").unwrap(), None);
+    assert!(!store.paused("a").unwrap());
+
+    store.set_paused("a", true).unwrap();
+    store.set_last_fire("a", t).unwrap();
+    assert_eq!(store.last_fire("a").unwrap(), Some(t));
+    assert!(store.paused("a").unwrap());
+
+    let id = store
+      .start_run("a", "schedule", Some(t), t, "running")
+      .unwrap();
+    store.finish_run(id, t, "ok", 1, None).unwrap();
+    let runs = store.runs("a", 10).unwrap();
+    assert_eq!(runs.len(), 1);
+    assert_eq!(runs[0].status, "ok");
+    assert_eq!(runs[0].scheduled_at, Some(t));
+    assert!(store.runs("b", 10).unwrap().is_empty());
+  }
+}
@@ -1,4 +1,7 @@
-use datafusion::{error::Result as DataFusionResult, prelude::*};
+use datafusion::{
+  arrow::util::pretty::print_batches, error::Result as DataFusionResult,
+  functions_aggregate::expr_fn::avg, prelude::*,
+};
 
 #[tokio::main]
 async fn main() -> DataFusionResult<()> {
@@ -26,7 +29,7 @@ async fn main() -> DataFusionResult<()> {
   // and collect the results
   let results = df.collect().await?;
 
-  println!("{:?}", results);
+  print_batches(&results)?;
 
   Ok(())
 }
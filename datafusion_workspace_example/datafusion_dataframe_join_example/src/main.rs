@@ -1,4 +1,6 @@
-use datafusion::{error::Result as DataFusionResult, prelude::*};
+use datafusion::{
+  arrow::util::pretty::print_batches, error::Result as DataFusionResult, prelude::*,
+};
 
 #[tokio::main]
 async fn main() -> DataFusionResult<()> {
@@ -17,12 +19,18 @@ async fn main() -> DataFusionResult<()> {
 
   // Create a data frame that represents an inner join
   // on both data frames
-  let join = students_df.join(scores_df, JoinType::Inner, &["student_id"], &["student"])?;
+  let join = students_df.join(
+    scores_df,
+    JoinType::Inner,
+    &["student_id"],
+    &["student"],
+    None,
+  )?;
 
   // Collect results
   let results = join.collect().await?;
 
-  println!("{:?}", results);
+  print_batches(&results)?;
 
   Ok(())
 }
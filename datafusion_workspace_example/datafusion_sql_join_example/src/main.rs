@@ -1,4 +1,6 @@
-use datafusion::{error::Result as DataFusionResult, prelude::*};
+use datafusion::{
+  arrow::util::pretty::print_batches, error::Result as DataFusionResult, prelude::*,
+};
 
 #[tokio::main]
 async fn main() -> DataFusionResult<()> {
@@ -33,7 +35,7 @@ async fn main() -> DataFusionResult<()> {
   // and collect the results
   let results = df.collect().await?;
 
-  println!("{:?}", results);
+  print_batches(&results)?;
 
   Ok(())
 }
@@ -0,0 +1,284 @@
+use std::{
+  collections::BTreeMap,
+  fs,
+  path::{Path, PathBuf},
+  sync::Arc,
+  time::SystemTime,
+};
+
+use datafusion::{
+  arrow::datatypes::SchemaRef,
+  catalog::TableProvider,
+  error::{DataFusionError, Result},
+  prelude::*,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+  Csv,
+  Parquet,
+  NdJson,
+}
+
+impl FileKind {
+  pub fn from_path(path: &Path) -> Option<FileKind> {
+    match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+      "csv" => Some(FileKind::Csv),
+      "parquet" => Some(FileKind::Parquet),
+      "json" | "jsonl" | "ndjson" => Some(FileKind::NdJson),
+      _ => None,
+    }
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      FileKind::Csv => "csv",
+      FileKind::Parquet => "parquet",
+      FileKind::NdJson => "ndjson",
+    }
+  }
+}
+
+/// Table name for a data file: the lowercased file stem with anything that is not a letter, digit
+/// or underscore turned into `_`, so it can be used in SQL without quoting.
+pub fn table_name(path: &Path) -> Option<String> {
+  let stem = path.file_stem()?.to_str()?;
+  Some(
+    stem
+      .chars()
+      .map(|c| match c {
+        'a' ..= 'z' | '0' ..= '9' | '_' => c,
+        'A' ..= 'Z' => c.to_ascii_lowercase(),
+        _ => '_',
+      })
+      .collect(),
+  )
+}
+
+/// Size and modification time; a file whose fingerprint changed gets its schema inferred again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Fingerprint {
+  len: u64,
+  modified: SystemTime,
+}
+
+impl Fingerprint {
+  fn of(path: &Path) -> std::io::Result<Fingerprint> {
+    let meta = fs::metadata(path)?;
+    Ok(Fingerprint {
+      len: meta.len(),
+      modified: meta.modified()?,
+    })
+  }
+}
+
+pub struct Table {
+  pub name: String,
+  pub path: PathBuf,
+  pub kind: FileKind,
+  fingerprint: Fingerprint,
+  provider: Arc<dyn TableProvider>,
+}
+
+impl Table {
+  pub fn schema(&self) -> SchemaRef {
+    self.provider.schema()
+  }
+}
+
+/// What one `refresh` changed. Files that could not be loaded are reported in `failed` the first
+/// time they fail, or when their error changes, and keep their previous table, if they had one.
+#[derive(Debug, Default)]
+pub struct Refresh {
+  pub added: Vec<String>,
+  pub updated: Vec<String>,
+  pub removed: Vec<String>,
+  pub failed: Vec<(PathBuf, DataFusionError)>,
+}
+
+/// A file that could not be loaded. It is only retried once its fingerprint changes.
+struct Failure {
+  fingerprint: Option<Fingerprint>,
+  error: String,
+}
+
+/// The data files of one directory, with their inferred schemas. Inference only runs for new or
+/// changed files, so refreshing before every query is cheap.
+pub struct Catalog {
+  dir: PathBuf,
+  ctx: SessionContext,
+  tables: BTreeMap<String, Table>,
+  failed: BTreeMap<PathBuf, Failure>,
+}
+
+impl Catalog {
+  /// Loads every file in `dir`; the returned [`Refresh`] lists the ones that failed.
+  pub async fn open(dir: impl Into<PathBuf>) -> Result<(Catalog, Refresh)> {
+    let mut catalog = Catalog {
+      dir: dir.into(),
+      ctx: SessionContext::new(),
+      tables: BTreeMap::new(),
+      failed: BTreeMap::new(),
+    };
+    let refresh = catalog.refresh().await?;
+    Ok((catalog, refresh))
+  }
+
+  pub fn dir(&self) -> &Path {
+    &self.dir
+  }
+
+  pub fn tables(&self) -> impl Iterator<Item = &Table> {
+    self.tables.values()
+  }
+
+  pub fn get(&self, name: &str) -> Option<&Table> {
+    self.tables.get(name)
+  }
+
+  /// Files skipped by the last refresh, with the reason.
+  pub fn failed(&self) -> impl Iterator<Item = (&Path, &str)> {
+    self
+      .failed
+      .iter()
+      .map(|(path, failure)| (path.as_path(), failure.error.as_str()))
+  }
+
+  /// Rescans the directory. Only an unreadable directory is an error.
+  pub async fn refresh(&mut self) -> Result<Refresh> {
+    let mut found = BTreeMap::new();
+    let mut failed = Vec::new();
+    let mut refresh = Refresh::default();
+    for entry in fs::read_dir(&self.dir)? {
+      let path = entry?.path();
+      let (Some(kind), Some(name)) = (FileKind::from_path(&path), table_name(&path)) else {
+        continue;
+      };
+      if !path.is_file() {
+        continue;
+      }
+      if let Some(other) = found.insert(name.clone(), (path.clone(), kind)) {
+        let e = DataFusionError::Plan(format!(
+          "table {} is already loaded from {:?}",
+          name, other.0
+        ));
+        failed.push((path, None, e));
+        found.insert(name, other);
+      }
+    }
+
+    let stale: Vec<String> = self
+      .tables
+      .keys()
+      .filter(|name| !found.contains_key(*name))
+      .cloned()
+      .collect();
+    for name in stale {
+      self.tables.remove(&name);
+      refresh.removed.push(name);
+    }
+
+    let mut known = std::mem::take(&mut self.failed);
+    for (name, (path, kind)) in found {
+      let fingerprint = match Fingerprint::of(&path) {
+        Ok(fingerprint) => fingerprint,
+        Err(e) => {
+          failed.push((path, None, e.into()));
+          continue;
+        }
+      };
+      if known
+        .get(&path)
+        .is_some_and(|f| f.fingerprint == Some(fingerprint))
+      {
+        let failure = known.remove(&path).unwrap();
+        self.failed.insert(path, failure);
+        continue;
+      }
+      let previous = self.tables.get(&name);
+      if previous.is_some_and(|t| t.path == path && t.fingerprint == fingerprint) {
+        continue;
+      }
+      let existed = previous.is_some();
+      match self.load(&name, &path, kind).await {
+        Ok(provider) => {
+          let table = Table {
+            name: name.clone(),
+            path,
+            kind,
+            fingerprint,
+            provider,
+          };
+          self.tables.insert(name.clone(), table);
+          if existed {
+            refresh.updated.push(name);
+          } else {
+            refresh.added.push(name);
+          }
+        }
+        Err(e) => failed.push((path, Some(fingerprint), e)),
+      }
+    }
+
+    for (path, fingerprint, e) in failed {
+      let error = e.to_string();
+      if known.get(&path).map(|f| &f.error) != Some(&error) {
+        refresh.failed.push((path.clone(), e));
+      }
+      self.failed.insert(path, Failure { fingerprint, error });
+    }
+    Ok(refresh)
+  }
+
+  async fn load(&self, name: &str, path: &Path, kind: FileKind) -> Result<Arc<dyn TableProvider>> {
+    let location = path
+      .to_str()
+      .ok_or_else(|| DataFusionError::Plan(format!("path is not UTF-8: {:?}", path)))?;
+    let extension = format!(
+      ".{}",
+      path.extension().and_then(|e| e.to_str()).unwrap_or("")
+    );
+    match kind {
+      FileKind::Csv => {
+        let options = CsvReadOptions::new().file_extension(&extension);
+        self.ctx.register_csv(name, location, options).await?
+      }
+      FileKind::Parquet => {
+        let options = ParquetReadOptions::default().file_extension(&extension);
+        self.ctx.register_parquet(name, location, options).await?
+      }
+      FileKind::NdJson => {
+        let options = NdJsonReadOptions::default().file_extension(&extension);
+        self.ctx.register_json(name, location, options).await?
+      }
+    }
+    let provider = self.ctx.table_provider(name).await;
+    self.ctx.deregister_table(name)?;
+    provider
+  }
+
+  /// Makes every table queryable in `ctx`.
+  pub fn register_all(&self, ctx: &SessionContext) -> Result<()> {
+    for table in self.tables.values() {
+      ctx.register_table(table.name.as_str(), Arc::clone(&table.provider))?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn names_are_sql_friendly() {
+    let name = |p: &str| table_name(Path::new(p)).unwrap();
+    assert_eq!(name("data/StudentACTResults.csv"), "studentactresults");
+    assert_eq!(name("my-data.v2.parquet"), "my_data_v2");
+    assert_eq!(
+      FileKind::from_path(Path::new("a.NDJSON")),
+      Some(FileKind::NdJson)
+    );
+    assert_eq!(FileKind::from_path(Path::new("README.org")), None);
+  }
+}
@@ -0,0 +1,143 @@
+use std::sync::Arc;
+
+use axum::{
+  body::Body,
+  extract::{Query, State},
+  http::{header, StatusCode},
+  response::{IntoResponse, Response},
+  routing::{get, post},
+  Json, Router,
+};
+use datafusion::error::DataFusionError;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  output::{self, Format},
+  service::Service,
+};
+
+#[derive(Serialize)]
+struct ColumnView {
+  name: String,
+  data_type: String,
+  nullable: bool,
+}
+
+#[derive(Serialize)]
+struct TableView {
+  name: String,
+  path: String,
+  format: &'static str,
+  columns: Vec<ColumnView>,
+}
+
+#[derive(Serialize)]
+struct SkippedView {
+  path: String,
+  error: String,
+}
+
+#[derive(Deserialize)]
+struct QueryParams {
+  #[serde(default)]
+  format: Format,
+}
+
+/// `GET /tables`, `GET /skipped` for the files that could not be loaded, and
+/// `POST /query?format={json,csv,arrow}` with the SQL as the request body.
+pub fn router(service: Arc<Service>) -> Router {
+  Router::new()
+    .route("/tables", get(list_tables))
+    .route("/skipped", get(list_skipped))
+    .route("/query", post(run_query))
+    .with_state(service)
+}
+
+async fn list_tables(State(service): State<Arc<Service>>) -> Response {
+  let tables = service
+    .with_catalog(|catalog| {
+      catalog
+        .tables()
+        .map(|table| TableView {
+          name: table.name.clone(),
+          path: table.path.display().to_string(),
+          format: table.kind.name(),
+          columns: table
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| ColumnView {
+              name: field.name().clone(),
+              data_type: field.data_type().to_string(),
+              nullable: field.is_nullable(),
+            })
+            .collect(),
+        })
+        .collect::<Vec<_>>()
+    })
+    .await;
+  match tables {
+    Ok(tables) => Json(tables).into_response(),
+    Err(e) => error_response(e),
+  }
+}
+
+async fn list_skipped(State(service): State<Arc<Service>>) -> Response {
+  let skipped = service
+    .with_catalog(|catalog| {
+      catalog
+        .failed()
+        .map(|(path, error)| SkippedView {
+          path: path.display().to_string(),
+          error: error.to_string(),
+        })
+        .collect::<Vec<_>>()
+    })
+    .await;
+  match skipped {
+    Ok(skipped) => Json(skipped).into_response(),
+    Err(e) => error_response(e),
+  }
+}
+
+async fn run_query(
+  State(service): State<Arc<Service>>,
+  Query(params): Query<QueryParams>,
+  sql: String,
+) -> Response {
+  let stream = match service.query(&sql).await {
+    Ok(stream) => stream,
+    Err(e) => return error_response(e),
+  };
+  let schema = stream.schema();
+
+  // Most execution errors, running out of memory included, show up on the first batch; wait for
+  // it so they get a proper status instead of a truncated body.
+  let mut batches = stream.peekable();
+  if let Some(Err(_)) = std::pin::Pin::new(&mut batches).peek().await {
+    if let Some(Err(e)) = batches.next().await {
+      return error_response(e);
+    }
+  }
+
+  match output::encode(schema, batches, params.format) {
+    Ok(body) => (
+      [(header::CONTENT_TYPE, params.format.content_type())],
+      Body::from_stream(body),
+    )
+      .into_response(),
+    Err(e) => error_response(e),
+  }
+}
+
+fn error_response(e: DataFusionError) -> Response {
+  let status = match e.find_root() {
+    DataFusionError::ResourcesExhausted(_) => StatusCode::PAYLOAD_TOO_LARGE,
+    DataFusionError::SQL(..) | DataFusionError::Plan(_) | DataFusionError::SchemaError(..) => {
+      StatusCode::BAD_REQUEST
+    }
+    _ => StatusCode::INTERNAL_SERVER_ERROR,
+  };
+  (status, format!("{}\n", e)).into_response()
+}
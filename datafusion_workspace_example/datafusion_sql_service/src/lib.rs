@@ -0,0 +1,5 @@
+pub mod catalog;
+pub mod http;
+pub mod output;
+pub mod repl;
+pub mod service;
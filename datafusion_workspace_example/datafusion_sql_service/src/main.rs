@@ -0,0 +1,74 @@
+// cargo run -- serve
+// curl -d 'SELECT * FROM studentactresults LIMIT 5' 'localhost:3000/query?format=csv'
+// cargo run -- repl
+// cargo run -- query 'SELECT * FROM students LIMIT 5' --format json
+
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use datafusion_sql_service::{
+  http,
+  output::{self, Format},
+  repl,
+  service::{parse_size, Service},
+};
+use futures::StreamExt;
+use tokio::io::AsyncWriteExt;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "SQL over a directory of CSV, Parquet and NDJSON files", long_about = None)]
+struct Args {
+  /// Every supported file in this directory becomes a table named after the file
+  #[arg(long, default_value = "../data")]
+  data: String,
+
+  /// Memory each query may use, e.g. 256M; unlimited if not set
+  #[arg(long, value_parser = parse_size)]
+  memory_limit: Option<usize>,
+
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Serve `GET /tables` and `POST /query`
+  Serve {
+    #[arg(long, default_value = "127.0.0.1:3000")]
+    addr: SocketAddr,
+  },
+  /// Interactive SQL prompt
+  Repl,
+  /// Run one statement and write the result to stdout
+  Query {
+    sql: String,
+    #[arg(long, value_enum, default_value = "csv")]
+    format: Format,
+  },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+  let args = Args::parse();
+  let service = Arc::new(Service::open(&args.data, args.memory_limit).await?);
+
+  match args.command {
+    Command::Serve { addr } => {
+      let listener = tokio::net::TcpListener::bind(addr).await?;
+      println!("listening on {}", listener.local_addr()?);
+      axum::serve(listener, http::router(service)).await?;
+    }
+    Command::Repl => repl::run(&service).await?,
+    Command::Query { sql, format } => {
+      let batches = service.query(&sql).await?;
+      let mut body = Box::pin(output::encode(batches.schema(), batches, format)?);
+      let mut stdout = tokio::io::stdout();
+      while let Some(chunk) = body.next().await {
+        stdout.write_all(&chunk?).await?;
+      }
+      stdout.flush().await?;
+    }
+  }
+  Ok(())
+}
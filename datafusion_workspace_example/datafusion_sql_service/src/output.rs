@@ -0,0 +1,120 @@
+use std::{
+  io::{self, Write},
+  sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use clap::ValueEnum;
+use datafusion::{
+  arrow::{
+    array::RecordBatch, csv, datatypes::SchemaRef, ipc::writer::StreamWriter,
+    json::LineDelimitedWriter,
+  },
+  error::Result,
+};
+use futures::{stream, Stream, StreamExt};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+  /// Arrow IPC stream
+  Arrow,
+  /// One JSON object per row
+  #[default]
+  Json,
+  Csv,
+}
+
+impl Format {
+  pub fn content_type(self) -> &'static str {
+    match self {
+      Format::Arrow => "application/vnd.apache.arrow.stream",
+      Format::Json => "application/x-ndjson",
+      Format::Csv => "text/csv",
+    }
+  }
+}
+
+/// Shared sink the arrow writers write into; each chunk is taken out right after a batch is
+/// written, so nothing is buffered across batches.
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Buffer {
+  fn take(&self) -> Bytes {
+    Bytes::from(std::mem::take(&mut *self.0.lock().unwrap()))
+  }
+}
+
+impl Write for Buffer {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.0.lock().unwrap().extend_from_slice(buf);
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+enum Encoder {
+  Arrow(StreamWriter<Buffer>),
+  Json(LineDelimitedWriter<Buffer>),
+  Csv(Box<csv::Writer<Buffer>>),
+}
+
+impl Encoder {
+  fn new(format: Format, buffer: Buffer, schema: &SchemaRef) -> Result<Encoder> {
+    Ok(match format {
+      Format::Arrow => Encoder::Arrow(StreamWriter::try_new(buffer, schema)?),
+      Format::Json => Encoder::Json(LineDelimitedWriter::new(buffer)),
+      Format::Csv => Encoder::Csv(Box::new(csv::Writer::new(buffer))),
+    })
+  }
+
+  fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+    match self {
+      Encoder::Arrow(writer) => writer.write(batch)?,
+      Encoder::Json(writer) => writer.write(batch)?,
+      Encoder::Csv(writer) => writer.write(batch)?,
+    }
+    Ok(())
+  }
+
+  fn finish(&mut self) -> Result<()> {
+    match self {
+      Encoder::Arrow(writer) => writer.finish()?,
+      Encoder::Json(writer) => writer.finish()?,
+      Encoder::Csv(_) => {}
+    }
+    Ok(())
+  }
+}
+
+/// Encodes batches as they arrive, one chunk per batch plus a trailer. The stream ends after the
+/// first error.
+pub fn encode<S>(
+  schema: SchemaRef,
+  batches: S,
+  format: Format,
+) -> Result<impl Stream<Item = Result<Bytes>> + Send>
+where
+  S: Stream<Item = Result<RecordBatch>> + Send + Unpin,
+{
+  let buffer = Buffer::default();
+  let encoder = Encoder::new(format, buffer.clone(), &schema)?;
+  // The Arrow schema message is written up front; hand it out with the first chunk.
+  let state = Some((batches, encoder, buffer));
+  Ok(stream::unfold(state, |state| async move {
+    let (mut batches, mut encoder, buffer) = state?;
+    match batches.next().await {
+      Some(Ok(batch)) => match encoder.write(&batch) {
+        Ok(()) => Some((Ok(buffer.take()), Some((batches, encoder, buffer)))),
+        Err(e) => Some((Err(e), None)),
+      },
+      Some(Err(e)) => Some((Err(e), None)),
+      None => Some((encoder.finish().map(|_| buffer.take()), None)),
+    }
+  }))
+}
@@ -0,0 +1,94 @@
+use datafusion::{arrow::util::pretty::pretty_format_batches, error::Result};
+use futures::TryStreamExt;
+use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use crate::service::Service;
+
+const HELP: &str = "\
+Statements end with `;` and may span lines.
+  \\d          list tables and the files that could not be loaded
+  \\d NAME     show the columns of a table
+  \\q          quit";
+
+/// Reads statements from stdin until EOF or `\q`. Errors are printed and the loop continues.
+pub async fn run(service: &Service) -> Result<()> {
+  let mut lines = BufReader::new(io::stdin()).lines();
+  let mut stdout = io::stdout();
+  let mut statement = String::new();
+  println!("{}", HELP);
+
+  loop {
+    let prompt = if statement.is_empty() {
+      "sql> "
+    } else {
+      "...> "
+    };
+    stdout.write_all(prompt.as_bytes()).await?;
+    stdout.flush().await?;
+    let Some(line) = lines.next_line().await? else {
+      break;
+    };
+    let line = line.trim();
+
+    if statement.is_empty() && line.starts_with('\\') {
+      match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["\\q"] => break,
+        ["\\d"] => describe(service, None).await?,
+        ["\\d", name] => describe(service, Some(name)).await?,
+        _ => println!("{}", HELP),
+      }
+      continue;
+    }
+
+    statement.push_str(line);
+    statement.push('\n');
+    if !line.ends_with(';') {
+      continue;
+    }
+    let sql = std::mem::take(&mut statement);
+    match execute(service, &sql).await {
+      Ok(table) => println!("{}", table),
+      Err(e) => eprintln!("error: {}", e),
+    }
+  }
+  Ok(())
+}
+
+async fn execute(service: &Service, sql: &str) -> Result<String> {
+  let batches: Vec<_> = service.query(sql).await?.try_collect().await?;
+  let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+  Ok(format!(
+    "{}\n{} rows",
+    pretty_format_batches(&batches)?,
+    rows
+  ))
+}
+
+async fn describe(service: &Service, name: Option<&str>) -> Result<()> {
+  service
+    .with_catalog(|catalog| match name {
+      None => {
+        for table in catalog.tables() {
+          println!(
+            "{:<24} {:<8} {}",
+            table.name,
+            table.kind.name(),
+            table.path.display()
+          );
+        }
+        for (path, error) in catalog.failed() {
+          println!("skipped {}: {}", path.display(), error);
+        }
+      }
+      Some(name) => match catalog.get(name) {
+        Some(table) => {
+          for field in table.schema().fields() {
+            let null = if field.is_nullable() { "" } else { " not null" };
+            println!("{:<24} {}{}", field.name(), field.data_type(), null);
+          }
+        }
+        None => println!("no table {}", name),
+      },
+    })
+    .await
+}
@@ -0,0 +1,107 @@
+use std::path::PathBuf;
+
+use datafusion::{
+  error::Result,
+  execution::{
+    disk_manager::{DiskManagerBuilder, DiskManagerMode},
+    runtime_env::RuntimeEnvBuilder,
+    SendableRecordBatchStream,
+  },
+  prelude::*,
+};
+use tokio::sync::Mutex;
+
+use crate::catalog::{Catalog, Refresh};
+
+/// Runs read-only SQL against a [`Catalog`]. Every query gets its own session and memory pool, so
+/// one expensive query cannot starve the others.
+pub struct Service {
+  catalog: Mutex<Catalog>,
+  memory_limit: Option<usize>,
+}
+
+impl Service {
+  pub async fn open(dir: impl Into<PathBuf>, memory_limit: Option<usize>) -> Result<Service> {
+    let (catalog, refresh) = Catalog::open(dir).await?;
+    log_failed(&refresh);
+    Ok(Service {
+      catalog: Mutex::new(catalog),
+      memory_limit,
+    })
+  }
+
+  /// Refreshes the catalog and hands it to `f`. A file that cannot be loaded is logged once, when
+  /// it starts failing; [`Catalog::failed`] lists all of them.
+  pub async fn with_catalog<T>(&self, f: impl FnOnce(&Catalog) -> T) -> Result<T> {
+    let mut catalog = self.catalog.lock().await;
+    let refresh = catalog.refresh().await?;
+    log_failed(&refresh);
+    Ok(f(&catalog))
+  }
+
+  /// Plans `sql` and starts executing it. DDL, DML and `SET`-style statements are rejected.
+  ///
+  /// With a memory limit, operators that would need more fail with
+  /// `DataFusionError::ResourcesExhausted` instead of spilling to disk.
+  pub async fn query(&self, sql: &str) -> Result<SendableRecordBatchStream> {
+    let mut runtime = RuntimeEnvBuilder::new();
+    if let Some(limit) = self.memory_limit {
+      runtime = runtime
+        .with_memory_limit(limit, 1.0)
+        .with_disk_manager_builder(
+          DiskManagerBuilder::default().with_mode(DiskManagerMode::Disabled),
+        );
+    }
+    let ctx = SessionContext::new_with_config_rt(SessionConfig::new(), runtime.build_arc()?);
+    self
+      .with_catalog(|catalog| catalog.register_all(&ctx))
+      .await??;
+
+    let options = SQLOptions::new()
+      .with_allow_ddl(false)
+      .with_allow_dml(false)
+      .with_allow_statements(false);
+    ctx
+      .sql_with_options(sql, options)
+      .await?
+      .execute_stream()
+      .await
+  }
+}
+
+fn log_failed(refresh: &Refresh) {
+  for (path, e) in &refresh.failed {
+    eprintln!("skipping {:?}: {}", path, e);
+  }
+}
+
+/// Parses sizes like `512M`, `2g` or `1048576` (bytes). Suffixes are binary: `K` is 1024.
+pub fn parse_size(s: &str) -> Result<usize, String> {
+  let s = s.trim();
+  let (digits, shift) = match s.char_indices().last() {
+    Some((i, 'k' | 'K')) => (&s[.. i], 10),
+    Some((i, 'm' | 'M')) => (&s[.. i], 20),
+    Some((i, 'g' | 'G')) => (&s[.. i], 30),
+    _ => (s, 0),
+  };
+  let n: usize = digits
+    .trim()
+    .parse()
+    .map_err(|_| format!("not a size: {:?}", s))?;
+  n.checked_mul(1 << shift)
+    .ok_or_else(|| format!("size too large: {:?}", s))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn sizes() {
+    assert_eq!(parse_size("1024"), Ok(1024));
+    assert_eq!(parse_size("512M"), Ok(512 << 20));
+    assert_eq!(parse_size("2g"), Ok(2 << 30));
+    assert!(parse_size("lots").is_err());
+    assert!(parse_size("").is_err());
+  }
+}
@@ -0,0 +1,238 @@
+use std::{fs, path::Path, sync::Arc};
+
+use axum::{
+  body::Body,
+  http::{Request, StatusCode},
+  Router,
+};
+use datafusion::{arrow::ipc::reader::StreamReader, prelude::SessionContext};
+use datafusion_sql_service::{catalog::Catalog, http, service::Service};
+use http_body_util::BodyExt;
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+/// Copies the bundled data so tests can add and change files.
+fn data_dir() -> TempDir {
+  let dir = tempfile::tempdir().unwrap();
+  let bundled = Path::new(env!("CARGO_MANIFEST_DIR")).join("../data");
+  for entry in fs::read_dir(bundled).unwrap() {
+    let path = entry.unwrap().path();
+    fs::copy(&path, dir.path().join(path.file_name().unwrap())).unwrap();
+  }
+  dir
+}
+
+async fn router(dir: &TempDir, memory_limit: Option<usize>) -> Router {
+  let service = Service::open(dir.path(), memory_limit).await.unwrap();
+  http::router(Arc::new(service))
+}
+
+async fn send(app: &Router, request: Request<Body>) -> (StatusCode, Vec<u8>) {
+  let response = app.clone().oneshot(request).await.unwrap();
+  let status = response.status();
+  let body = response.into_body().collect().await.unwrap().to_bytes();
+  (status, body.to_vec())
+}
+
+async fn query(app: &Router, format: &str, sql: &str) -> (StatusCode, Vec<u8>) {
+  let request = Request::post(format!("/query?format={}", format))
+    .body(Body::from(sql.to_string()))
+    .unwrap();
+  send(app, request).await
+}
+
+fn json_rows(body: &[u8]) -> Vec<serde_json::Value> {
+  serde_json::Deserializer::from_slice(body)
+    .into_iter()
+    .collect::<Result<_, _>>()
+    .unwrap()
+}
+
+#[tokio::test]
+async fn lists_tables_with_inferred_schemas() {
+  let dir = data_dir();
+  let app = router(&dir, None).await;
+  let (status, body) = send(&app, Request::get("/tables").body(Body::empty()).unwrap()).await;
+  assert_eq!(status, StatusCode::OK);
+
+  let tables: serde_json::Value = serde_json::from_slice(&body).unwrap();
+  let names: Vec<_> = tables
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|t| t["name"].as_str().unwrap())
+    .collect();
+  assert_eq!(names, ["studentactresults", "students"]);
+  let columns = &tables[0]["columns"];
+  assert_eq!(columns[0]["name"], "student");
+  assert_eq!(columns[0]["data_type"], "Int64");
+  assert_eq!(columns[1]["data_type"], "Boolean");
+}
+
+#[tokio::test]
+async fn streams_json_csv_and_arrow() {
+  let dir = data_dir();
+  let app = router(&dir, None).await;
+
+  let (status, body) = query(
+    &app,
+    "json",
+    r#"SELECT "group", COUNT(*) AS n FROM studentactresults GROUP BY "group" ORDER BY "group""#,
+  )
+  .await;
+  assert_eq!(status, StatusCode::OK);
+  let rows = json_rows(&body);
+  let counts: Vec<_> = rows.iter().map(|r| r["n"].as_i64().unwrap()).collect();
+  assert_eq!(counts, [210, 191, 200, 192, 207]);
+
+  let (status, body) = query(
+    &app,
+    "csv",
+    "SELECT first_name, english FROM students JOIN studentactresults ON student_id = student
+     ORDER BY first_name LIMIT 3",
+  )
+  .await;
+  assert_eq!(status, StatusCode::OK);
+  let text = String::from_utf8(body).unwrap();
+  let lines: Vec<_> = text.lines().collect();
+  assert_eq!(lines.len(), 4);
+  assert_eq!(lines[0], "first_name,english");
+
+  let (status, body) = query(&app, "arrow", "SELECT * FROM studentactresults").await;
+  assert_eq!(status, StatusCode::OK);
+  let reader = StreamReader::try_new(body.as_slice(), None).unwrap();
+  assert_eq!(reader.schema().fields().len(), 7);
+  let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+  assert_eq!(rows, 1000);
+}
+
+#[tokio::test]
+async fn rejects_bad_and_mutating_sql() {
+  let dir = data_dir();
+  let app = router(&dir, None).await;
+
+  let (status, _) = query(&app, "json", "SELEC 1").await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  let (status, _) = query(&app, "json", "SELECT nope FROM students").await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+
+  let out = dir.path().join("out.csv");
+  let copy = format!("COPY students TO '{}' STORED AS CSV", out.display());
+  let (status, _) = query(&app, "json", &copy).await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert!(!out.exists());
+  let (status, _) = query(&app, "json", "CREATE TABLE t AS SELECT 1").await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn caps_memory_per_query() {
+  let dir = data_dir();
+  let app = router(&dir, Some(1024)).await;
+  let sql = "SELECT * FROM studentactresults ORDER BY math, english";
+  let (status, body) = query(&app, "json", sql).await;
+  assert_eq!(
+    status,
+    StatusCode::PAYLOAD_TOO_LARGE,
+    "{}",
+    String::from_utf8_lossy(&body)
+  );
+
+  let app = router(&dir, Some(64 << 20)).await;
+  let (status, body) = query(&app, "json", sql).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(json_rows(&body).len(), 1000);
+}
+
+#[tokio::test]
+async fn picks_up_new_changed_and_removed_files() {
+  let dir = data_dir();
+  let app = router(&dir, None).await;
+
+  // Parquet written by DataFusion itself.
+  let parquet = dir.path().join("groups.parquet");
+  let ctx = SessionContext::new();
+  ctx
+    .sql(&format!(
+      "COPY (SELECT * FROM (VALUES (1, 'one'), (2, 'two')) AS t(id, label))
+       TO '{}' STORED AS PARQUET",
+      parquet.display()
+    ))
+    .await
+    .unwrap()
+    .collect()
+    .await
+    .unwrap();
+  fs::write(
+    dir.path().join("events.ndjson"),
+    "{\"id\": 1, \"kind\": \"a\"}\n{\"id\": 2, \"kind\": \"b\"}\n",
+  )
+  .unwrap();
+
+  let (status, body) = query(
+    &app,
+    "json",
+    "SELECT label, kind FROM groups JOIN events USING (id) ORDER BY id",
+  )
+  .await;
+  assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+  let rows = json_rows(&body);
+  assert_eq!(rows.len(), 2);
+  assert_eq!(rows[1]["label"], "two");
+  assert_eq!(rows[1]["kind"], "b");
+
+  // A new column shows up once the file changes.
+  fs::write(
+    dir.path().join("events.ndjson"),
+    "{\"id\": 1, \"kind\": \"a\", \"extra\": true}\n",
+  )
+  .unwrap();
+  let (status, body) = query(&app, "json", "SELECT extra FROM events").await;
+  assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+  assert_eq!(json_rows(&body)[0]["extra"], true);
+
+  fs::remove_file(dir.path().join("Students.csv")).unwrap();
+  let (status, _) = query(&app, "json", "SELECT * FROM students").await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn lists_files_that_cannot_be_loaded_until_they_change() {
+  let dir = data_dir();
+  let broken = dir.path().join("broken.parquet");
+  fs::write(&broken, "not parquet").unwrap();
+  let app = router(&dir, None).await;
+  let skipped = |body: Vec<u8>| serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+
+  let (status, body) = send(&app, Request::get("/skipped").body(Body::empty()).unwrap()).await;
+  assert_eq!(status, StatusCode::OK);
+  let body = skipped(body);
+  assert_eq!(body.as_array().unwrap().len(), 1);
+  assert!(body[0]["path"]
+    .as_str()
+    .unwrap()
+    .ends_with("broken.parquet"));
+  // The other tables are still queryable.
+  let (status, _) = query(&app, "json", "SELECT * FROM students").await;
+  assert_eq!(status, StatusCode::OK);
+
+  fs::write(&broken, "{\"id\": 1}\n").unwrap();
+  fs::rename(&broken, dir.path().join("broken.ndjson")).unwrap();
+  let (status, body) = send(&app, Request::get("/skipped").body(Body::empty()).unwrap()).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(skipped(body), serde_json::json!([]));
+  let (status, body) = query(&app, "json", "SELECT id FROM broken").await;
+  assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+}
+
+#[tokio::test]
+async fn reports_a_failure_once() {
+  let dir = data_dir();
+  fs::write(dir.path().join("broken.parquet"), "not parquet").unwrap();
+  let (mut catalog, refresh) = Catalog::open(dir.path()).await.unwrap();
+  assert_eq!(refresh.failed.len(), 1);
+
+  let refresh = catalog.refresh().await.unwrap();
+  assert!(refresh.failed.is_empty());
+  assert_eq!(catalog.failed().count(), 1);
+}
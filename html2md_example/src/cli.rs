@@ -0,0 +1,328 @@
+use std::{
+  io::{self, IsTerminal},
+  path::PathBuf,
+  str::FromStr,
+};
+
+use anyhow::{anyhow, Result};
+use clap::{ArgEnum, Parser};
+use reqwest::{Method, Url};
+
+// 以下部分用于处理 CLI
+
+// 定义 httpie 的 CLI 的主入口，它包含若干个子命令
+// 下面 /// 的注释是文档，clap 会将其作为 CLI 的帮助
+
+/// A naive httpie implementation with Rust, can you imagine how easy it is?
+#[derive(Parser, Debug)]
+#[clap(version = "1.0", author = "Tyr Chen <tyr@chen.com>")]
+pub struct Opts {
+  #[clap(subcommand)]
+  pub subcmd: SubCommand,
+}
+
+// 子命令分别对应不同的 HTTP 方法，它们的参数都一样
+
+#[derive(Parser, Debug)]
+pub enum SubCommand {
+  /// feed get with an url and we will retrieve the response for you
+  Get(Args),
+  /// feed post with an url and optional items. We will send the data items
+  /// as JSON (or as a form), and retrieve the response for you
+  Post(Args),
+  /// send a PUT request, with a body like post
+  Put(Args),
+  /// send a PATCH request, with a body like post
+  Patch(Args),
+  /// send a DELETE request
+  Delete(Args),
+  /// send a HEAD request, only the headers come back
+  Head(Args),
+  /// send an OPTIONS request
+  Options(Args),
+  /// send a TRACE request
+  Trace(Args),
+}
+
+impl SubCommand {
+  pub fn method(&self) -> Method {
+    match self {
+      SubCommand::Get(_) => Method::GET,
+      SubCommand::Post(_) => Method::POST,
+      SubCommand::Put(_) => Method::PUT,
+      SubCommand::Patch(_) => Method::PATCH,
+      SubCommand::Delete(_) => Method::DELETE,
+      SubCommand::Head(_) => Method::HEAD,
+      SubCommand::Options(_) => Method::OPTIONS,
+      SubCommand::Trace(_) => Method::TRACE,
+    }
+  }
+
+  pub fn args(&self) -> &Args {
+    match self {
+      SubCommand::Get(args)
+      | SubCommand::Post(args)
+      | SubCommand::Put(args)
+      | SubCommand::Patch(args)
+      | SubCommand::Delete(args)
+      | SubCommand::Head(args)
+      | SubCommand::Options(args)
+      | SubCommand::Trace(args) => args,
+    }
+  }
+}
+
+/// 每个子命令的参数：一个 url，和若干个可选的请求项
+#[derive(Parser, Debug)]
+pub struct Args {
+  /// HTTP 请求的 URL
+  #[clap(parse(try_from_str = parse_url))]
+  pub url: String,
+  /// Request items: `Name:value` headers (`Name:` drops the header), `k==v`
+  /// query parameters, `k=v` string fields, `k:=json` raw JSON fields and
+  /// `k@path` files to upload
+  #[clap(parse(try_from_str = parse_item))]
+  pub items: Vec<Item>,
+  /// Send the fields as a form (multipart when there are files) instead of JSON
+  #[clap(short, long)]
+  pub form: bool,
+  /// Send the fields as a multipart form, even without files
+  #[clap(long)]
+  pub multipart: bool,
+  /// Credentials: `user[:password]` for basic auth, the token for bearer auth
+  #[clap(short, long)]
+  pub auth: Option<String>,
+  /// How to send the credentials given with --auth
+  #[clap(short = 'A', long, arg_enum, default_value = "basic")]
+  pub auth_type: AuthType,
+  /// Keep cookies, headers and auth in the named session (or session file),
+  /// and send them again with the next requests using it
+  #[clap(long, value_name = "NAME")]
+  pub session: Option<String>,
+  /// Like --session, but leave the session as it was
+  #[clap(long, value_name = "NAME", conflicts_with = "session")]
+  pub session_read_only: Option<String>,
+  /// Where the named sessions are kept
+  #[clap(long, env = "HTML2MD_CONFIG_DIR", value_name = "DIR")]
+  pub config_dir: Option<PathBuf>,
+  /// Save the response body to a file instead of printing it
+  #[clap(short, long)]
+  pub download: bool,
+  /// The file to download to, named after the response by default
+  #[clap(short, long, requires = "download")]
+  pub output: Option<PathBuf>,
+  /// Resume the download into the existing --output file
+  #[clap(short = 'c', long = "continue", requires = "output")]
+  pub resume: bool,
+  /// What to print: H request headers, B request body, h response headers,
+  /// b response body. `hb` on a terminal and `b` otherwise by default
+  #[clap(short, long, parse(try_from_str = parse_parts))]
+  pub print: Option<Parts>,
+  /// Print the request and the response in full, like `--print HBhb`
+  #[clap(short, long, conflicts_with = "print")]
+  pub verbose: bool,
+  /// Whether to color and format the output, `all` on a terminal by default
+  #[clap(long, arg_enum)]
+  pub pretty: Option<Pretty>,
+  /// Print HTML as it came instead of rendering it to Markdown
+  #[clap(long)]
+  pub raw: bool,
+}
+
+impl Args {
+  pub fn parts(&self) -> Parts {
+    if self.verbose {
+      return Parts::ALL;
+    }
+    self.print.unwrap_or(if io::stdout().is_terminal() {
+      Parts {
+        response_headers: true,
+        response_body: true,
+        ..Parts::default()
+      }
+    } else {
+      Parts {
+        response_body: true,
+        ..Parts::default()
+      }
+    })
+  }
+
+  pub fn pretty(&self) -> bool {
+    match self.pretty {
+      Some(pretty) => pretty == Pretty::All,
+      None => io::stdout().is_terminal(),
+    }
+  }
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthType {
+  Basic,
+  Bearer,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pretty {
+  All,
+  None,
+}
+
+/// 要打印的部分
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Parts {
+  pub request_headers: bool,
+  pub request_body: bool,
+  pub response_headers: bool,
+  pub response_body: bool,
+}
+
+impl Parts {
+  pub const ALL: Parts = Parts {
+    request_headers: true,
+    request_body: true,
+    response_headers: true,
+    response_body: true,
+  };
+}
+
+fn parse_parts(s: &str) -> Result<Parts> {
+  let mut parts = Parts::default();
+  for c in s.chars() {
+    match c {
+      'H' => parts.request_headers = true,
+      'B' => parts.request_body = true,
+      'h' => parts.response_headers = true,
+      'b' => parts.response_body = true,
+      _ => return Err(anyhow!("Unknown part {:?}, expected some of HBhb", c)),
+    }
+  }
+  Ok(parts)
+}
+
+/// 命令行中的请求项，可以通过 parse_item 解析
+#[derive(Debug, PartialEq)]
+pub enum Item {
+  /// `Name:value`
+  Header(String, String),
+  /// `k==v`
+  Query(String, String),
+  /// `k=v`
+  Data(String, String),
+  /// `k:=json`
+  Json(String, serde_json::Value),
+  /// `k@path`
+  File(String, PathBuf),
+}
+
+/// 分隔符，同一位置上较长的在前
+const SEPARATORS: [&str; 5] = ["==", ":=", "=", ":", "@"];
+
+/// 当我们实现 FromStr trait 后，可以用 str.parse() 方法将字符串解析成 Item
+impl FromStr for Item {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let err = || anyhow!(format!("Failed to parse {}", s));
+    // 最先出现的分隔符决定了请求项的类型，这样值里面可以再出现分隔符，比如 url=http://a.b
+    let (i, separator) = s
+      .char_indices()
+      .find_map(|(i, _)| {
+        SEPARATORS
+          .iter()
+          .find(|sep| s[i ..].starts_with(*sep))
+          .map(|sep| (i, *sep))
+      })
+      .ok_or_else(err)?;
+    if i == 0 {
+      return Err(err());
+    }
+    let (k, v) = (s[.. i].to_string(), &s[i + separator.len() ..]);
+    Ok(match separator {
+      "==" => Item::Query(k, v.into()),
+      ":=" => Item::Json(
+        k,
+        serde_json::from_str(v).map_err(|e| anyhow!("Invalid JSON in {}: {}", s, e))?,
+      ),
+      "=" => Item::Data(k, v.into()),
+      ":" => Item::Header(k, v.into()),
+      _ => Item::File(k, v.into()),
+    })
+  }
+}
+
+/// 因为我们为 Item 实现了 FromStr，这里可以直接 s.parse() 得到 Item
+fn parse_item(s: &str) -> Result<Item> {
+  s.parse()
+}
+
+fn parse_url(s: &str) -> Result<String> {
+  // 这里我们仅仅检查一下 URL 是否合法
+  let _url: Url = s.parse()?;
+
+  Ok(s.into())
+}
+
+// 仅在 cargo test 时才编译
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  #[test]
+  fn parse_url_works() {
+    assert!(parse_url("abc").is_err());
+    assert!(parse_url("http://abc.xyz").is_ok());
+    assert!(parse_url("https://httpbin.org/post").is_ok());
+  }
+
+  #[test]
+  fn parse_item_works() {
+    assert!(parse_item("a").is_err());
+    assert!(parse_item("=1").is_err());
+    assert_eq!(
+      parse_item("a=1").unwrap(),
+      Item::Data("a".into(), "1".into())
+    );
+    assert_eq!(parse_item("b=").unwrap(), Item::Data("b".into(), "".into()));
+    assert_eq!(
+      parse_item("url=http://a.b/?c=d").unwrap(),
+      Item::Data("url".into(), "http://a.b/?c=d".into())
+    );
+    assert_eq!(
+      parse_item("q==rust lang").unwrap(),
+      Item::Query("q".into(), "rust lang".into())
+    );
+    assert_eq!(
+      parse_item("tags:=[1, \"a\"]").unwrap(),
+      Item::Json("tags".into(), json!([1, "a"]))
+    );
+    assert!(parse_item("tags:=[1").is_err());
+    assert_eq!(
+      parse_item("Accept:text/html").unwrap(),
+      Item::Header("Accept".into(), "text/html".into())
+    );
+    assert_eq!(
+      parse_item("Host:example.com:8080").unwrap(),
+      Item::Header("Host".into(), "example.com:8080".into())
+    );
+    assert_eq!(
+      parse_item("avatar@./me.png").unwrap(),
+      Item::File("avatar".into(), "./me.png".into())
+    );
+  }
+
+  #[test]
+  fn parse_parts_works() {
+    assert_eq!(parse_parts("HBhb").unwrap(), Parts::ALL);
+    assert_eq!(
+      parse_parts("h").unwrap(),
+      Parts {
+        response_headers: true,
+        ..Parts::default()
+      }
+    );
+    assert!(parse_parts("x").is_err());
+  }
+}
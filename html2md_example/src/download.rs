@@ -0,0 +1,147 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use reqwest::{
+  header::{CONTENT_DISPOSITION, CONTENT_RANGE},
+  Response, StatusCode,
+};
+use tokio::{
+  fs::{File, OpenOptions},
+  io::AsyncWriteExt,
+};
+
+use crate::output::get_content_type;
+
+/// 已经下载了多少字节，也就是续传时 Range 的起点
+pub async fn resume_from(output: &Path) -> u64 {
+  tokio::fs::metadata(output).await.map_or(0, |m| m.len())
+}
+
+/// 把响应的 body 一块块地写进文件，不整个读进内存。`resumed_from` 大于 0 时，
+/// 请求带上了 `Range: bytes=<resumed_from>-`，服务器支持的话就接着写在文件后面。
+pub async fn download(
+  mut resp: Response,
+  output: Option<&Path>,
+  resumed_from: u64,
+) -> Result<PathBuf> {
+  let status = resp.status();
+  if status == StatusCode::RANGE_NOT_SATISFIABLE && resumed_from > 0 {
+    let path = output.expect("resuming needs --output").to_owned();
+    eprintln!("{} was already downloaded", path.display());
+    return Ok(path);
+  }
+  if !status.is_success() {
+    bail!("The server answered {}, nothing was downloaded", status);
+  }
+
+  let path = match output {
+    Some(path) => path.to_owned(),
+    None => unique(Path::new(&filename(&resp))),
+  };
+  let mut file = if status == StatusCode::PARTIAL_CONTENT {
+    let start = content_range_start(&resp)?;
+    if start != resumed_from {
+      bail!(
+        "Asked for the bytes from {}, the server sent those from {}",
+        resumed_from,
+        start
+      );
+    }
+    OpenOptions::new().append(true).open(&path).await?
+  } else {
+    if resumed_from > 0 {
+      eprintln!("The server cannot resume downloads, downloading it all again");
+    }
+    File::create(&path).await?
+  };
+
+  let expected = resp.content_length();
+  eprintln!(
+    "Downloading {} to {}",
+    expected.map_or("unknown size".into(), |n| format!("{} bytes", n)),
+    path.display()
+  );
+  let mut written = 0;
+  while let Some(chunk) = resp.chunk().await? {
+    file.write_all(&chunk).await?;
+    written += chunk.len() as u64;
+  }
+  file.flush().await?;
+  if let Some(expected) = expected {
+    if written < expected {
+      bail!(
+        "Incomplete download: {} of {} bytes, resume it with --continue",
+        written,
+        expected
+      );
+    }
+  }
+  eprintln!("Done. {} bytes", resumed_from + written);
+  Ok(path)
+}
+
+/// `Content-Range: bytes <start>-<end>/<total>` 的起点
+fn content_range_start(resp: &Response) -> Result<u64> {
+  resp
+    .headers()
+    .get(CONTENT_RANGE)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| v.strip_prefix("bytes "))
+    .and_then(|v| v.split('-').next())
+    .and_then(|v| v.parse().ok())
+    .ok_or_else(|| anyhow!("Partial content without a valid Content-Range"))
+}
+
+/// 文件名取自 Content-Disposition，没有的话取 URL 最后一段，再没有就叫 index
+fn filename(resp: &Response) -> String {
+  let disposition = resp
+    .headers()
+    .get(CONTENT_DISPOSITION)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| {
+      v.split(';')
+        .filter_map(|p| p.trim().strip_prefix("filename="))
+        .next()
+    })
+    .map(|name| name.trim_matches('"').to_string());
+  let segment = resp
+    .url()
+    .path_segments()
+    .and_then(|mut s| s.next_back())
+    .map(str::to_string);
+  // 只要文件名，不能让服务器把文件写到别的目录里
+  let name = [disposition, segment]
+    .into_iter()
+    .flatten()
+    .filter_map(|name| {
+      Path::new(&name)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+    })
+    .find(|name| !name.is_empty())
+    .unwrap_or_else(|| "index".into());
+  if name.contains('.') {
+    return name;
+  }
+  let ext = get_content_type(resp.headers()).and_then(|m| match m.subtype().as_str() {
+    "html" => Some("html"),
+    "json" => Some("json"),
+    "plain" => Some("txt"),
+    _ => None,
+  });
+  match ext {
+    Some(ext) => format!("{}.{}", name, ext),
+    None => name,
+  }
+}
+
+/// 不覆盖已有的文件，依次试 name-1、name-2……
+fn unique(path: &Path) -> PathBuf {
+  if !path.exists() {
+    return path.to_owned();
+  }
+  (1 ..)
+    .map(|i| PathBuf::from(format!("{}-{}", path.display(), i)))
+    .find(|p| !p.exists())
+    .unwrap()
+}
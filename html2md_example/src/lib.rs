@@ -0,0 +1,180 @@
+use std::io::Write;
+
+use anyhow::{bail, Result};
+use reqwest::{
+  header::{self, HeaderMap, HeaderName, HeaderValue},
+  multipart::{Form, Part},
+  Client, Method, RequestBuilder, Url,
+};
+
+pub mod cli;
+pub mod download;
+pub mod output;
+pub mod session;
+
+use cli::{Args, Item, Opts};
+use output::Printer;
+use session::{Auth, Session};
+
+/// 为我们的 http 客户端添加一些缺省的 HTTP 头
+pub fn client() -> Result<Client> {
+  let mut headers = header::HeaderMap::new();
+  headers.insert("X-POWERED-BY", "Rust".parse()?);
+  headers.insert(header::USER_AGENT, "Rust Httpie".parse()?);
+  Ok(
+    reqwest::Client::builder()
+      .default_headers(headers)
+      .build()?,
+  )
+}
+
+/// 发出请求，打印到 `out`，或者下载到文件里
+pub async fn run(client: &Client, opts: &Opts, out: &mut dyn Write) -> Result<()> {
+  let args = opts.subcmd.args();
+  let url: Url = args.url.parse()?;
+
+  // 没有指定会话时也用一个内存里的会话，只是不保存
+  let name = args.session.as_ref().or(args.session_read_only.as_ref());
+  let path = name
+    .map(|name| session::path(name, &url, args.config_dir.as_deref()))
+    .transpose()?;
+  let mut session = match &path {
+    Some(path) => Session::load(path)?,
+    None => Session::default(),
+  };
+  let auth = args.auth.as_ref().map(|a| Auth::new(a, args.auth_type));
+  session.update(&url, &args.items, auth);
+
+  let mut request = build(client, opts.subcmd.method(), url, args, &session).await?;
+  let resumed_from = match &args.output {
+    Some(output) if args.resume => download::resume_from(output).await,
+    _ => 0,
+  };
+  if resumed_from > 0 {
+    request = request.header(header::RANGE, format!("bytes={}-", resumed_from));
+  }
+  let request = request.build()?;
+
+  let printer = Printer {
+    pretty: args.pretty(),
+    parts: args.parts(),
+    raw: args.raw,
+  };
+  if printer.pretty {
+    // --pretty all 时即使输出不是终端也要颜色
+    colored::control::set_override(true);
+  }
+  printer.print_request(out, &request)?;
+  let resp = client.execute(request).await?;
+
+  session.store_cookies(resp.url(), resp.headers());
+  if let (Some(path), Some(_)) = (&path, &args.session) {
+    session.save(path)?;
+  }
+
+  if args.download {
+    printer.print_head(out, &resp)?;
+    download::download(resp, args.output.as_deref(), resumed_from).await?;
+    Ok(())
+  } else {
+    printer.print_resp(out, resp).await
+  }
+}
+
+/// 用会话和请求项组装请求：请求项里的请求头覆盖会话里的，
+/// 字段默认作为 JSON 发送，--form 时作为表单，有文件时作为 multipart
+async fn build(
+  client: &Client,
+  method: Method,
+  url: Url,
+  args: &Args,
+  session: &Session,
+) -> Result<RequestBuilder> {
+  let mut headers = HeaderMap::new();
+  for (name, value) in &session.headers {
+    headers.insert(HeaderName::from_bytes(name.as_bytes())?, value.parse()?);
+  }
+  let mut query = Vec::new();
+  let mut fields = Vec::new();
+  for item in &args.items {
+    match item {
+      // 已经放进会话的 cookie 里了
+      Item::Header(name, _) if name.eq_ignore_ascii_case("cookie") => {}
+      Item::Header(name, value) if value.is_empty() => {
+        headers.remove(name.as_str());
+      }
+      Item::Header(name, value) => {
+        headers.insert(
+          HeaderName::from_bytes(name.as_bytes())?,
+          HeaderValue::from_str(value)?,
+        );
+      }
+      Item::Query(k, v) => query.push((k, v)),
+      _ => fields.push(item),
+    }
+  }
+  if let Some(cookies) = session.cookie_header(&url) {
+    headers.insert(header::COOKIE, cookies.parse()?);
+  }
+
+  let multipart = args.multipart || fields.iter().any(|f| matches!(f, Item::File(..)));
+  let json = !(args.form || multipart);
+  if !json && fields.iter().any(|f| matches!(f, Item::Json(..))) {
+    bail!("Raw JSON fields (k:=json) cannot be sent in a form");
+  }
+  if json && !fields.is_empty() {
+    headers
+      .entry(header::ACCEPT)
+      .or_insert(HeaderValue::from_static("application/json, */*;q=0.5"));
+  }
+  let auth = session.auth(&url).cloned();
+  let mut request = client.request(method, url).headers(headers);
+  if !query.is_empty() {
+    request = request.query(&query);
+  }
+  match auth {
+    Some(Auth::Basic { username, password }) => request = request.basic_auth(username, password),
+    Some(Auth::Bearer { token }) => request = request.bearer_auth(token),
+    None => {}
+  }
+  if fields.is_empty() {
+    return Ok(request);
+  }
+
+  if multipart {
+    let mut form = Form::new();
+    for field in fields {
+      form = match field {
+        Item::Data(k, v) => form.text(k.clone(), v.clone()),
+        Item::File(k, path) => {
+          let bytes = tokio::fs::read(path).await?;
+          let name = path
+            .file_name()
+            .map_or(k.clone(), |n| n.to_string_lossy().into_owned());
+          form.part(k.clone(), Part::bytes(bytes).file_name(name))
+        }
+        _ => unreachable!(),
+      };
+    }
+    Ok(request.multipart(form))
+  } else if args.form {
+    let form = fields
+      .into_iter()
+      .filter_map(|f| match f {
+        Item::Data(k, v) => Some((k, v)),
+        _ => None,
+      })
+      .collect::<Vec<_>>();
+    Ok(request.form(&form))
+  } else {
+    let mut body = serde_json::Map::new();
+    for field in fields {
+      match field {
+        Item::Data(k, v) => body.insert(k.clone(), v.clone().into()),
+        Item::Json(k, v) => body.insert(k.clone(), v.clone()),
+        _ => unreachable!(),
+      };
+    }
+    Ok(request.json(&body))
+  }
+}
@@ -0,0 +1,165 @@
+use std::io::Write;
+
+use anyhow::Result;
+use colored::{ColoredString, Colorize};
+use mime::Mime;
+use reqwest::{header, header::HeaderMap, Request, Response};
+use syntect::{
+  easy::HighlightLines,
+  highlighting::{Style, ThemeSet},
+  parsing::SyntaxSet,
+  util::{as_24_bit_terminal_escaped, LinesWithEndings},
+};
+
+use crate::cli::Parts;
+
+/// 打印请求和响应，`pretty` 时带颜色并格式化 JSON
+pub struct Printer {
+  pub pretty: bool,
+  pub parts: Parts,
+  /// 原样打印 HTML，而不是转成 Markdown
+  pub raw: bool,
+}
+
+impl Printer {
+  fn paint(&self, s: String, color: fn(&str) -> ColoredString) -> String {
+    if self.pretty {
+      color(&s).to_string()
+    } else {
+      s
+    }
+  }
+
+  fn print_headers(&self, out: &mut dyn Write, headers: &HeaderMap) -> Result<()> {
+    for (name, value) in headers {
+      let name = self.paint(name.to_string(), |s| s.green());
+      writeln!(
+        out,
+        "{}: {}",
+        name,
+        String::from_utf8_lossy(value.as_bytes())
+      )?;
+    }
+    writeln!(out)?;
+    Ok(())
+  }
+
+  /// 打印将要发出的请求
+  pub fn print_request(&self, out: &mut dyn Write, request: &Request) -> Result<()> {
+    if self.parts.request_headers {
+      let url = request.url();
+      let target = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+      };
+      let line = format!("{} {} {:?}", request.method(), target, request.version());
+      writeln!(out, "{}", self.paint(line, |s| s.blue()))?;
+      if let Some(host) = url.host_str() {
+        writeln!(
+          out,
+          "{}: {}",
+          self.paint("host".into(), |s| s.green()),
+          host
+        )?;
+      }
+      self.print_headers(out, request.headers())?;
+    }
+    if self.parts.request_body {
+      if let Some(body) = request.body() {
+        match body.as_bytes() {
+          Some(bytes) => self.print_body(out, get_content_type(request.headers()), bytes)?,
+          None => writeln!(out, "<streamed body>")?,
+        }
+        writeln!(out)?;
+      }
+    }
+    Ok(())
+  }
+
+  // 打印服务器版本号 + 状态码，以及服务器返回的 HTTP header
+  pub fn print_head(&self, out: &mut dyn Write, resp: &Response) -> Result<()> {
+    if self.parts.response_headers {
+      let status = format!("{:?} {}", resp.version(), resp.status());
+      writeln!(out, "{}", self.paint(status, |s| s.blue()))?;
+      self.print_headers(out, resp.headers())?;
+    }
+    Ok(())
+  }
+
+  /// 打印整个响应
+  pub async fn print_resp(&self, out: &mut dyn Write, resp: Response) -> Result<()> {
+    self.print_head(out, &resp)?;
+    if self.parts.response_body {
+      let mime = get_content_type(resp.headers());
+      let body = resp.bytes().await?;
+      self.print_body(out, mime, &body)?;
+    }
+    Ok(())
+  }
+
+  /// 打印 HTTP body
+  fn print_body(&self, out: &mut dyn Write, m: Option<Mime>, body: &[u8]) -> Result<()> {
+    let Ok(body) = std::str::from_utf8(body) else {
+      writeln!(out, "+-----------------------------------------+")?;
+      writeln!(out, "| NOTE: binary data not shown in terminal |")?;
+      writeln!(out, "+-----------------------------------------+")?;
+      return Ok(());
+    };
+    let json = m
+      .as_ref()
+      .is_some_and(|m| m.subtype() == mime::JSON || m.suffix() == Some(mime::JSON));
+    let html = m
+      .as_ref()
+      .is_some_and(|m| m.essence_str() == mime::TEXT_HTML.essence_str());
+    let (text, ext) = if json && self.pretty {
+      // 对于 JSON 我们 pretty print
+      let text = jsonxf::pretty_print(body).unwrap_or_else(|_| body.to_string());
+      (text, "json")
+    } else if json {
+      (body.to_string(), "json")
+    } else if html && self.raw {
+      (body.to_string(), "html")
+    } else if html {
+      // HTML 转成 Markdown，终端里好读得多
+      (html2md::parse_html(body), "md")
+    } else {
+      // 其它 mime type，我们就直接输出
+      (body.to_string(), "")
+    };
+    if self.pretty && !ext.is_empty() {
+      print_syntect(out, &text, ext)?;
+    } else {
+      write!(out, "{}", text)?;
+    }
+    if !text.is_empty() && !text.ends_with('\n') {
+      writeln!(out)?;
+    }
+    Ok(())
+  }
+}
+
+/// 将 content-type 解析成 Mime 类型
+pub fn get_content_type(headers: &HeaderMap) -> Option<Mime> {
+  headers
+    .get(header::CONTENT_TYPE)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| v.parse().ok())
+}
+
+fn print_syntect(out: &mut dyn Write, s: &str, ext: &str) -> Result<()> {
+  // Load these once at the start of your program
+  let ps = SyntaxSet::load_defaults_newlines();
+  let ts = ThemeSet::load_defaults();
+  let syntax = ps
+    .find_syntax_by_extension(ext)
+    .unwrap_or_else(|| ps.find_syntax_plain_text());
+  let mut h = HighlightLines::new(syntax, &ts.themes["base16-ocean.dark"]);
+  for line in LinesWithEndings::from(s) {
+    let ranges: Vec<(Style, &str)> = h.highlight(line, &ps);
+    let escaped = as_24_bit_terminal_escaped(&ranges[..], true);
+    write!(out, "{}", escaped)?;
+  }
+  // 恢复终端的颜色
+  write!(out, "\x1b[0m")?;
+  Ok(())
+}
@@ -0,0 +1,475 @@
+use std::{
+  collections::BTreeMap,
+  fs, io,
+  io::Write,
+  net::IpAddr,
+  path::{Path, PathBuf},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::{
+  header::{HeaderMap, SET_COOKIE},
+  Url,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::cli::{AuthType, Item};
+
+/// 会话：在多次请求之间保留的 cookie、请求头和认证信息，按 host 保存在磁盘上。
+/// 会话文件可以给不同的 host 用，所以 cookie 和认证信息都记着是哪个 host 的
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Session {
+  /// 请求头，名字都是小写的
+  #[serde(default)]
+  pub headers: BTreeMap<String, String>,
+  #[serde(default)]
+  pub cookies: Vec<Cookie>,
+  /// 认证信息，键是 `host` 或者 `host:port`
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub auth: BTreeMap<String, Auth>,
+}
+
+/// RFC 6265 5.3 存下来的 cookie，名字、域名和路径一样的是同一个
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cookie {
+  pub name: String,
+  pub value: String,
+  /// 小写，没有开头的点
+  pub domain: String,
+  /// 没有 Domain 属性时只发给 `domain` 这个 host 本身，不发给它的子域名
+  #[serde(default)]
+  pub host_only: bool,
+  pub path: String,
+  /// 只通过 https 发送
+  #[serde(default)]
+  pub secure: bool,
+  /// 过期时间，Unix 时间戳（秒）。没有的话就一直有效
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub expires: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Auth {
+  Basic {
+    username: String,
+    password: Option<String>,
+  },
+  Bearer {
+    token: String,
+  },
+}
+
+impl Auth {
+  pub fn new(credentials: &str, auth_type: AuthType) -> Auth {
+    match auth_type {
+      AuthType::Basic => match credentials.split_once(':') {
+        Some((username, password)) => Auth::Basic {
+          username: username.into(),
+          password: Some(password.into()),
+        },
+        None => Auth::Basic {
+          username: credentials.into(),
+          password: None,
+        },
+      },
+      AuthType::Bearer => Auth::Bearer {
+        token: credentials.into(),
+      },
+    }
+  }
+}
+
+impl Cookie {
+  /// RFC 6265 5.4 里要不要发给 `url`
+  fn matches(&self, url: &Url) -> bool {
+    let Some(host) = url.host_str() else {
+      return false;
+    };
+    let host = host.to_ascii_lowercase();
+    let domain_ok = if self.host_only {
+      host == self.domain
+    } else {
+      domain_matches(&self.domain, &host)
+    };
+    domain_ok && path_matches(&self.path, url.path()) && (!self.secure || url.scheme() == "https")
+  }
+
+  fn same(&self, other: &Cookie) -> bool {
+    self.name == other.name && self.domain == other.domain && self.path == other.path
+  }
+}
+
+/// RFC 6265 5.1.3 的域名匹配：`example.com` 匹配它自己和 `a.example.com`，IP 地址只匹配自己
+fn domain_matches(domain: &str, host: &str) -> bool {
+  let ip = host.starts_with('[') || host.parse::<IpAddr>().is_ok();
+  host == domain
+    || !ip
+      && host
+        .strip_suffix(domain)
+        .is_some_and(|rest| rest.ends_with('.'))
+}
+
+/// RFC 6265 5.1.4 的路径匹配：`/app` 匹配 `/app` 和 `/app/...`，不匹配 `/apple`
+fn path_matches(cookie_path: &str, request_path: &str) -> bool {
+  match request_path.strip_prefix(cookie_path) {
+    Some(rest) => rest.is_empty() || cookie_path.ends_with('/') || rest.starts_with('/'),
+    None => false,
+  }
+}
+
+/// RFC 6265 5.1.4 的默认路径：请求路径最后一个 `/` 之前的部分，没有的话是 `/`
+fn default_path(url: &Url) -> String {
+  match url.path().rfind('/') {
+    Some(0) | None => "/".into(),
+    Some(i) => url.path()[.. i].into(),
+  }
+}
+
+/// 认证信息按这个分开保存
+fn host_key(url: &Url) -> Option<String> {
+  let host = url.host_str()?.to_ascii_lowercase();
+  Some(match url.port() {
+    Some(port) => format!("{}:{}", host, port),
+    None => host,
+  })
+}
+
+fn now() -> i64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// 会话文件的位置：名字里带路径分隔符或者以 .json 结尾的就是文件本身，
+/// 否则是配置目录下 sessions/<host>/<name>.json
+pub fn path(name: &str, url: &Url, config_dir: Option<&Path>) -> Result<PathBuf> {
+  if name.contains('/') || name.contains(std::path::MAIN_SEPARATOR) || name.ends_with(".json") {
+    return Ok(name.into());
+  }
+  let dir = match config_dir {
+    Some(dir) => dir.to_owned(),
+    None => dirs::config_dir()
+      .ok_or_else(|| anyhow!("No config directory to keep sessions in, use --config-dir"))?
+      .join("html2md_example"),
+  };
+  let host = url
+    .host_str()
+    .ok_or_else(|| anyhow!("{} has no host to keep sessions for", url))?;
+  let host = match url.port() {
+    Some(port) => format!("{}_{}", host, port),
+    None => host.to_string(),
+  };
+  Ok(
+    dir
+      .join("sessions")
+      .join(host)
+      .join(format!("{}.json", name)),
+  )
+}
+
+impl Session {
+  /// 读取会话文件，文件不存在就是一个新的会话
+  pub fn load(path: &Path) -> Result<Session> {
+    match fs::read_to_string(path) {
+      Ok(json) => serde_json::from_str(&json)
+        .with_context(|| format!("Invalid session file {}", path.display())),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Session::default()),
+      Err(e) => Err(e).with_context(|| format!("Cannot read session file {}", path.display())),
+    }
+  }
+
+  /// 写回会话文件。里面有 cookie 和密码，所以只有自己能读写
+  pub fn save(&mut self, path: &Path) -> Result<()> {
+    let now = now();
+    self.cookies.retain(|c| c.expires.is_none_or(|t| t > now));
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+    let json = serde_json::to_string_pretty(self)?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let write = || -> io::Result<()> {
+      let mut file = options.open(path)?;
+      // 已经存在的文件不会按上面的 mode 创建
+      #[cfg(unix)]
+      file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+      file.write_all(json.as_bytes())
+    };
+    write().with_context(|| format!("Cannot write session file {}", path.display()))
+  }
+
+  /// 记下发给 `url` 的这次请求的请求头和认证信息。Content-* 和 If-* 只对这一次请求有意义，
+  /// Cookie 头拆成一个个只属于这个 host 的 cookie
+  pub fn update(&mut self, url: &Url, items: &[Item], auth: Option<Auth>) {
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    for item in items {
+      let Item::Header(name, value) = item else {
+        continue;
+      };
+      let name = name.to_ascii_lowercase();
+      if name == "cookie" {
+        for pair in value.split(';') {
+          if let Some((k, v)) = pair.trim().split_once('=') {
+            self.insert(Cookie {
+              name: k.into(),
+              value: v.into(),
+              domain: host.clone(),
+              host_only: true,
+              path: "/".into(),
+              secure: false,
+              expires: None,
+            });
+          }
+        }
+      } else if name.starts_with("content-") || name.starts_with("if-") {
+        continue;
+      } else if value.is_empty() {
+        self.headers.remove(&name);
+      } else {
+        self.headers.insert(name, value.clone());
+      }
+    }
+    if let (Some(auth), Some(host)) = (auth, host_key(url)) {
+      self.auth.insert(host, auth);
+    }
+  }
+
+  /// 发给 `url` 时用的认证信息
+  pub fn auth(&self, url: &Url) -> Option<&Auth> {
+    self.auth.get(&host_key(url)?)
+  }
+
+  /// 要发给 `url` 的 Cookie 头
+  pub fn cookie_header(&self, url: &Url) -> Option<String> {
+    let now = now();
+    let cookies = self
+      .cookies
+      .iter()
+      .filter(|c| c.expires.is_none_or(|t| t > now) && c.matches(url))
+      .map(|c| format!("{}={}", c.name, c.value))
+      .collect::<Vec<_>>();
+    (!cookies.is_empty()).then(|| cookies.join("; "))
+  }
+
+  /// 换掉名字、域名和路径都一样的 cookie
+  fn insert(&mut self, cookie: Cookie) {
+    match self.cookies.iter_mut().find(|c| c.same(&cookie)) {
+      Some(old) => *old = cookie,
+      None => self.cookies.push(cookie),
+    }
+  }
+
+  /// 记下 `url` 的响应里的 Set-Cookie，已经过期的就删掉。
+  /// Domain 不包括这个 host 的 cookie 不要（RFC 6265 5.3 第 6 步）
+  pub fn store_cookies(&mut self, url: &Url, headers: &HeaderMap) {
+    let now = now();
+    let Some(host) = url.host_str().map(str::to_ascii_lowercase) else {
+      return;
+    };
+    for value in headers.get_all(SET_COOKIE) {
+      let Some(cookie) = value
+        .to_str()
+        .ok()
+        .and_then(|v| cookie::Cookie::parse(v).ok())
+      else {
+        continue;
+      };
+      let expires = match (cookie.max_age(), cookie.expires_datetime()) {
+        (Some(max_age), _) => Some(now.saturating_add(max_age.whole_seconds())),
+        (None, Some(at)) => Some(at.unix_timestamp()),
+        (None, None) => None,
+      };
+      let (domain, host_only) = match cookie.domain().filter(|d| !d.is_empty()) {
+        Some(domain) => {
+          let domain = domain.to_ascii_lowercase();
+          if !domain_matches(&domain, &host) {
+            continue;
+          }
+          (domain, false)
+        }
+        None => (host.clone(), true),
+      };
+      let path = match cookie.path() {
+        Some(path) if path.starts_with('/') => path.into(),
+        _ => default_path(url),
+      };
+      let cookie = Cookie {
+        name: cookie.name().into(),
+        value: cookie.value().into(),
+        domain,
+        host_only,
+        path,
+        secure: cookie.secure().unwrap_or(false),
+        expires,
+      };
+      if expires.is_some_and(|t| t <= now) {
+        self.cookies.retain(|c| !c.same(&cookie));
+        continue;
+      }
+      self.insert(cookie);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use reqwest::header::HeaderValue;
+
+  use super::*;
+
+  fn set_cookies(cookies: &[&'static str]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for cookie in cookies {
+      headers.append(SET_COOKIE, HeaderValue::from_static(cookie));
+    }
+    headers
+  }
+
+  #[test]
+  fn keeps_and_drops_cookies() {
+    let url = |path| Url::parse(&format!("http://example.com{}", path)).unwrap();
+    let mut session = Session::default();
+    let mut headers = HeaderMap::new();
+    for cookie in [
+      "sid=abc; Path=/; HttpOnly",
+      "theme=dark; Path=/app; Max-Age=3600",
+      "old=1; Expires=Thu, 01 Jan 1970 00:00:00 GMT",
+      "forever=1; Path=/forever; Max-Age=9223372036854775807",
+    ] {
+      headers.append(SET_COOKIE, HeaderValue::from_static(cookie));
+    }
+    session.store_cookies(&url("/"), &headers);
+    assert_eq!(session.cookies.len(), 3);
+    assert_eq!(session.cookies[2].name, "forever");
+    assert_eq!(session.cookies[2].expires, Some(i64::MAX));
+
+    assert_eq!(session.cookie_header(&url("/")).unwrap(), "sid=abc");
+    assert_eq!(
+      session.cookie_header(&url("/app/settings")).unwrap(),
+      "sid=abc; theme=dark"
+    );
+    assert_eq!(
+      session.cookie_header(&url("/app")).unwrap(),
+      "sid=abc; theme=dark"
+    );
+    // 路径要在 / 处分开
+    assert_eq!(session.cookie_header(&url("/apple")).unwrap(), "sid=abc");
+
+    session.store_cookies(&url("/"), &set_cookies(&["sid=; Max-Age=0"]));
+    assert_eq!(session.cookie_header(&url("/")), None);
+  }
+
+  #[test]
+  fn scopes_cookies_to_their_domain() {
+    let url = |s| Url::parse(s).unwrap();
+    let mut session = Session::default();
+    session.store_cookies(
+      &url("https://www.example.com/shop/cart"),
+      &set_cookies(&[
+        "host=1",
+        "wide=1; Domain=.Example.com; Path=/",
+        "safe=1; Secure; Path=/",
+        "other=1; Domain=example.org",
+      ]),
+    );
+    assert_eq!(session.cookies.len(), 3);
+    // 没有 Path 属性时是请求路径所在的目录
+    assert_eq!(session.cookies[0].path, "/shop");
+
+    assert_eq!(
+      session
+        .cookie_header(&url("https://www.example.com/shop/"))
+        .unwrap(),
+      "host=1; wide=1; safe=1"
+    );
+    assert_eq!(
+      session
+        .cookie_header(&url("http://www.example.com/shop"))
+        .unwrap(),
+      "host=1; wide=1"
+    );
+    assert_eq!(
+      session
+        .cookie_header(&url("https://api.example.com/shop"))
+        .unwrap(),
+      "wide=1"
+    );
+    assert_eq!(session.cookie_header(&url("https://example.org/")), None);
+    assert_eq!(session.cookie_header(&url("https://notexample.com/")), None);
+
+    // 同名不同域名的是两个 cookie
+    session.store_cookies(&url("https://example.org/"), &set_cookies(&["host=2"]));
+    assert_eq!(
+      session.cookie_header(&url("https://example.org/")).unwrap(),
+      "host=2"
+    );
+    assert_eq!(session.cookies.len(), 4);
+  }
+
+  #[test]
+  fn keeps_headers_for_the_next_requests() {
+    let mut session = Session::default();
+    let items = [
+      "X-Api-Key:secret",
+      "Content-Type:text/plain",
+      "Cookie:a=1; b=2",
+      "q==1",
+    ]
+    .map(|s| s.parse().unwrap());
+    let url = Url::parse("http://localhost:8080/a").unwrap();
+    session.update(&url, &items, Some(Auth::new("me:pw", AuthType::Basic)));
+    assert_eq!(session.headers.len(), 1);
+    assert_eq!(session.headers["x-api-key"], "secret");
+    assert_eq!(session.cookie_header(&url).unwrap(), "a=1; b=2");
+    assert_eq!(
+      session.auth(&url),
+      Some(&Auth::Basic {
+        username: "me".into(),
+        password: Some("pw".into())
+      })
+    );
+
+    // 不带认证信息的请求沿用之前的，`Name:` 删掉请求头
+    session.update(&url, &["X-Api-Key:".parse().unwrap()], None);
+    assert!(session.headers.is_empty());
+    assert!(session.auth(&url).is_some());
+
+    // 别的 host 拿不到这个 host 的 cookie 和认证信息
+    let other = Url::parse("http://localhost:9090/a").unwrap();
+    assert_eq!(session.auth(&other), None);
+    let other = Url::parse("http://example.com/a").unwrap();
+    assert_eq!(session.auth(&other), None);
+    assert_eq!(session.cookie_header(&other), None);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn only_the_owner_reads_the_session_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("s.json");
+    fs::write(&path, "{}").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+    Session::default().save(&path).unwrap();
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+  }
+
+  #[test]
+  fn names_sessions_after_hosts() {
+    let url = Url::parse("http://localhost:8080/a").unwrap();
+    assert_eq!(
+      path("dev", &url, Some(Path::new("/cfg"))).unwrap(),
+      Path::new("/cfg/sessions/localhost_8080/dev.json")
+    );
+    assert_eq!(
+      path("./s.json", &url, Some(Path::new("/cfg"))).unwrap(),
+      Path::new("./s.json")
+    );
+  }
+}
@@ -0,0 +1,343 @@
+use std::{fs, path::Path};
+
+use anyhow::Result;
+use axum::{
+  body::Bytes,
+  http::{header, HeaderMap, Method, StatusCode, Uri},
+  response::{Html, IntoResponse, Response},
+  routing::{any, get, post},
+  Json, Router,
+};
+use clap::Parser;
+use html2md_example::{cli::Opts, client, run};
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+
+/// 一个本地的 HTTP 服务器，代替 httpbin
+async fn serve() -> String {
+  let app = Router::new()
+    .route("/echo", any(echo))
+    .route(
+      "/html",
+      get(|| async { Html("<h1>Title</h1><p>Some <b>bold</b> text</p>") }),
+    )
+    .route(
+      "/login",
+      post(|| async { [(header::SET_COOKIE, "sid=abc; Path=/; HttpOnly")] }),
+    )
+    .route(
+      "/logout",
+      post(|| async { [(header::SET_COOKIE, "sid=; Path=/; Max-Age=0")] }),
+    )
+    .route("/file", get(file))
+    .route("/whole", get(|| async { data() }))
+    .route("/missing", get(|| async { StatusCode::NOT_FOUND }));
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let addr = listener.local_addr().unwrap();
+  tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+  format!("http://{}", addr)
+}
+
+/// 把请求原样送回来
+async fn echo(method: Method, uri: Uri, headers: HeaderMap, body: Bytes) -> Json<Value> {
+  let headers = headers
+    .iter()
+    .map(|(name, value)| (name.to_string(), json!(value.to_str().unwrap())))
+    .collect::<serde_json::Map<_, _>>();
+  Json(json!({
+    "method": method.as_str(),
+    "query": uri.query(),
+    "headers": headers,
+    "body": String::from_utf8_lossy(&body),
+  }))
+}
+
+fn data() -> Vec<u8> {
+  (0 .. 100_000u32).map(|i| (i % 251) as u8).collect()
+}
+
+/// 支持 `Range: bytes=<start>-` 的下载
+async fn file(headers: HeaderMap) -> Response {
+  let data = data();
+  let len = data.len();
+  let start = headers
+    .get(header::RANGE)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| {
+      v.strip_prefix("bytes=")?
+        .strip_suffix('-')?
+        .parse::<usize>()
+        .ok()
+    });
+  match start {
+    Some(start) if start >= len => (
+      StatusCode::RANGE_NOT_SATISFIABLE,
+      [(header::CONTENT_RANGE, format!("bytes */{}", len))],
+    )
+      .into_response(),
+    Some(start) => (
+      StatusCode::PARTIAL_CONTENT,
+      [(
+        header::CONTENT_RANGE,
+        format!("bytes {}-{}/{}", start, len - 1, len),
+      )],
+      data[start ..].to_vec(),
+    )
+      .into_response(),
+    None => (
+      [(
+        header::CONTENT_DISPOSITION,
+        "attachment; filename=\"data.bin\"",
+      )],
+      data,
+    )
+      .into_response(),
+  }
+}
+
+/// 像在命令行上一样运行，不带颜色，返回打印出来的内容
+async fn http(args: &[&str]) -> Result<String> {
+  let mut argv = vec!["html2md_example", args[0], "--pretty", "none"];
+  argv.extend(&args[1 ..]);
+  let opts = Opts::try_parse_from(argv)?;
+  let mut out = Vec::new();
+  run(&client()?, &opts, &mut out).await?;
+  Ok(String::from_utf8(out)?)
+}
+
+/// 只打印响应的 body，也就是 /echo 送回来的请求
+async fn echoed(args: &[&str]) -> Value {
+  let mut args = args.to_vec();
+  args.extend(["--print", "b"]);
+  serde_json::from_str(&http(&args).await.unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn sends_every_method() {
+  let url = format!("{}/echo", serve().await);
+  for method in ["get", "post", "put", "patch", "delete", "options", "trace"] {
+    let echo = echoed(&[method, &url]).await;
+    assert_eq!(echo["method"], method.to_uppercase());
+  }
+
+  let head = http(&["head", &url, "--print", "hb"]).await.unwrap();
+  assert!(head.starts_with("HTTP/1.1 200 OK\n"), "{}", head);
+  assert!(head.ends_with("\n\n"), "{}", head);
+}
+
+#[tokio::test]
+async fn sends_items_as_json() {
+  let url = format!("{}/echo", serve().await);
+  let echo = echoed(&[
+    "post",
+    &url,
+    "name=Ferris",
+    "age:=7",
+    "tags:=[\"crab\", true]",
+    "q==rust lang",
+    "X-Custom:yes",
+  ])
+  .await;
+  assert_eq!(
+    serde_json::from_str::<Value>(echo["body"].as_str().unwrap()).unwrap(),
+    json!({ "name": "Ferris", "age": 7, "tags": ["crab", true] })
+  );
+  assert_eq!(echo["query"], "q=rust+lang");
+  assert_eq!(echo["headers"]["x-custom"], "yes");
+  assert_eq!(echo["headers"]["content-type"], "application/json");
+  assert_eq!(echo["headers"]["accept"], "application/json, */*;q=0.5");
+  assert_eq!(echo["headers"]["user-agent"], "Rust Httpie");
+
+  // 没有字段就没有 body
+  let echo = echoed(&["get", &url, "Accept:text/plain"]).await;
+  assert_eq!(echo["body"], "");
+  assert_eq!(echo["headers"]["accept"], "text/plain");
+}
+
+#[tokio::test]
+async fn sends_forms_and_files() {
+  let url = format!("{}/echo", serve().await);
+  let echo = echoed(&["post", &url, "--form", "a=1", "b=two words"]).await;
+  assert_eq!(echo["body"], "a=1&b=two+words");
+  assert_eq!(
+    echo["headers"]["content-type"],
+    "application/x-www-form-urlencoded"
+  );
+
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("notes.txt");
+  fs::write(&path, "file contents").unwrap();
+  let file = format!("notes@{}", path.display());
+  let echo = echoed(&["post", &url, "title=Notes", &file]).await;
+  assert!(echo["headers"]["content-type"]
+    .as_str()
+    .unwrap()
+    .starts_with("multipart/form-data; boundary="));
+  let body = echo["body"].as_str().unwrap();
+  assert!(body.contains("name=\"title\"\r\n\r\nNotes\r\n"), "{}", body);
+  assert!(
+    body.contains("name=\"notes\"; filename=\"notes.txt\"\r\n\r\nfile contents\r\n"),
+    "{}",
+    body
+  );
+
+  let err = http(&["post", &url, "--form", "n:=1"]).await.unwrap_err();
+  assert!(err.to_string().contains("cannot be sent in a form"));
+}
+
+#[tokio::test]
+async fn sends_credentials() {
+  let url = format!("{}/echo", serve().await);
+  let echo = echoed(&["get", &url, "--auth", "user:pass"]).await;
+  assert_eq!(echo["headers"]["authorization"], "Basic dXNlcjpwYXNz");
+  let echo = echoed(&["get", &url, "-A", "bearer", "-a", "token"]).await;
+  assert_eq!(echo["headers"]["authorization"], "Bearer token");
+}
+
+#[tokio::test]
+async fn keeps_sessions() {
+  let server = serve().await;
+  let url = format!("{}/echo", server);
+  let dir = tempfile::tempdir().unwrap();
+  let config = dir.path().to_str().unwrap();
+  let session = ["--session", "dev", "--config-dir", config];
+
+  http(&[&["post", &format!("{}/login", server)], &session[..]].concat())
+    .await
+    .unwrap();
+  let echo = echoed(&[&["get", &url, "X-Team:core", "-a", "me:pw"], &session[..]].concat()).await;
+  assert_eq!(echo["headers"]["cookie"], "sid=abc");
+
+  // 下一个请求带着同样的 cookie、请求头和认证信息
+  let echo = echoed(&[&["get", &url], &session[..]].concat()).await;
+  assert_eq!(echo["headers"]["cookie"], "sid=abc");
+  assert_eq!(echo["headers"]["x-team"], "core");
+  assert_eq!(echo["headers"]["authorization"], "Basic bWU6cHc=");
+
+  let port = server.rsplit(':').next().unwrap();
+  let file = dir
+    .path()
+    .join(format!("sessions/127.0.0.1_{}/dev.json", port));
+  let saved: Value = serde_json::from_str(&fs::read_to_string(&file).unwrap()).unwrap();
+  assert_eq!(saved["cookies"][0]["name"], "sid");
+  assert_eq!(saved["cookies"][0]["value"], "abc");
+  assert_eq!(saved["cookies"][0]["domain"], "127.0.0.1");
+  assert_eq!(saved["headers"]["x-team"], "core");
+
+  // 只读的会话不记下这次的请求头
+  let read_only = ["--session-read-only", "dev", "--config-dir", config];
+  let echo = echoed(&[&["get", &url, "X-Once:1"], &read_only[..]].concat()).await;
+  assert_eq!(echo["headers"]["x-once"], "1");
+  assert_eq!(echo["headers"]["x-team"], "core");
+  let echo = echoed(&[&["get", &url], &session[..]].concat()).await;
+  assert_eq!(echo["headers"].get("x-once"), None);
+
+  http(&[&["post", &format!("{}/logout", server)], &session[..]].concat())
+    .await
+    .unwrap();
+  let echo = echoed(&[&["get", &url], &session[..]].concat()).await;
+  assert_eq!(echo["headers"].get("cookie"), None);
+
+  // 会话也可以直接是一个文件
+  let path = dir.path().join("other.json");
+  let session = ["--session", path.to_str().unwrap()];
+  echoed(&[&["get", &url, "X-Other:1"], &session[..]].concat()).await;
+  assert!(path.exists());
+
+  // 这样的会话可以给几个 host 用，cookie 和认证信息只发给它们来自的 host
+  http(&[&["post", &format!("{}/login", server)], &session[..]].concat())
+    .await
+    .unwrap();
+  echoed(&[&["get", &url, "-a", "me:pw"], &session[..]].concat()).await;
+  let elsewhere = url.replace("127.0.0.1", "localhost");
+  let echo = echoed(&[&["get", &elsewhere], &session[..]].concat()).await;
+  assert_eq!(echo["headers"].get("cookie"), None);
+  assert_eq!(echo["headers"].get("authorization"), None);
+  assert_eq!(echo["headers"]["x-other"], "1");
+  let echo = echoed(&[&["get", &url], &session[..]].concat()).await;
+  assert_eq!(echo["headers"]["cookie"], "sid=abc");
+  assert_eq!(echo["headers"]["authorization"], "Basic bWU6cHc=");
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+  }
+}
+
+#[tokio::test]
+async fn renders_html_as_markdown() {
+  let url = format!("{}/html", serve().await);
+  let markdown = http(&["get", &url, "--print", "b"]).await.unwrap();
+  assert!(markdown.contains("Title\n=========="), "{}", markdown);
+  assert!(markdown.contains("Some **bold** text"), "{}", markdown);
+  assert!(!markdown.contains("<h1>"));
+
+  let html = http(&["get", &url, "--print", "b", "--raw"]).await.unwrap();
+  assert_eq!(html, "<h1>Title</h1><p>Some <b>bold</b> text</p>\n");
+}
+
+#[tokio::test]
+async fn prints_what_is_asked_for() {
+  let server = serve().await;
+  let out = http(&["post", &format!("{}/echo", server), "q==1", "a=b", "-v"])
+    .await
+    .unwrap();
+  assert!(
+    out.starts_with("POST /echo?q=1 HTTP/1.1\nhost: 127.0.0.1\n"),
+    "{}",
+    out
+  );
+  assert!(out.contains("\n{\"a\":\"b\"}\n"), "{}", out);
+  assert!(out.contains("HTTP/1.1 200 OK\n"), "{}", out);
+  assert!(out.contains("content-type: application/json\n"), "{}", out);
+
+  let out = http(&["get", &format!("{}/missing", server), "-p", "h"])
+    .await
+    .unwrap();
+  assert!(out.starts_with("HTTP/1.1 404 Not Found\n"), "{}", out);
+
+  let out = http(&["get", &format!("{}/file", server), "-p", "b"])
+    .await
+    .unwrap();
+  assert!(out.contains("binary data not shown"), "{}", out);
+}
+
+async fn download(server: &str, route: &str, path: &Path, resume: bool) -> Result<String> {
+  let url = format!("{}{}", server, route);
+  let mut args = vec!["get", &url, "--download", "-o", path.to_str().unwrap()];
+  if resume {
+    args.push("--continue");
+  }
+  http(&args).await
+}
+
+#[tokio::test]
+async fn downloads_and_resumes() {
+  let server = serve().await;
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("data.bin");
+
+  download(&server, "/file", &path, false).await.unwrap();
+  assert_eq!(fs::read(&path).unwrap(), data());
+
+  // 只下载了一部分的文件接着下完
+  fs::write(&path, &data()[.. 30_000]).unwrap();
+  download(&server, "/file", &path, true).await.unwrap();
+  assert_eq!(fs::read(&path).unwrap(), data());
+
+  // 已经下完了
+  download(&server, "/file", &path, true).await.unwrap();
+  assert_eq!(fs::read(&path).unwrap(), data());
+
+  // 不支持 Range 的服务器从头下载
+  fs::write(&path, &data()[.. 30_000]).unwrap();
+  download(&server, "/whole", &path, true).await.unwrap();
+  assert_eq!(fs::read(&path).unwrap(), data());
+
+  let missing = dir.path().join("missing");
+  assert!(download(&server, "/missing", &missing, false)
+    .await
+    .is_err());
+  assert!(!missing.exists());
+}
@@ -0,0 +1,139 @@
+//! Serves the same three routes with this crate and with axum, as `axum_vs_nginx_example` does,
+//! and hammers each over keep-alive HTTP/1 connections from the same process.
+//!
+//! `cargo bench --bench vs_axum -- [CONNECTIONS [SECONDS]]`
+
+use std::{
+  net::SocketAddr,
+  time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::{client::conn::http1, Request, StatusCode};
+use hyper_path_tree_example::{get, Json, Path, Router};
+use hyper_util::rt::TokioIo;
+use serde::Serialize;
+use tokio::net::{TcpListener, TcpStream};
+
+const PATHS: [&str; 3] = ["/", "/hello/ferris", "/users/42"];
+
+#[derive(Serialize)]
+struct User {
+  id: u32,
+  name: String,
+}
+
+fn user(id: u32) -> User {
+  User {
+    id,
+    name: format!("user{}", id),
+  }
+}
+
+async fn this_crate() -> SocketAddr {
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let addr = listener.local_addr().unwrap();
+  let app = Router::new()
+    .route("/", get(|| async { "Hello, Web!" }))
+    .route(
+      "/hello/:name",
+      get(|Path(name): Path<String>| async move { format!("Hello, {}!", name) }),
+    )
+    .route(
+      "/users/:id",
+      get(|Path(id): Path<u32>| async move { Json(user(id)) }),
+    );
+  tokio::spawn(app.serve(listener));
+  addr
+}
+
+async fn axum() -> SocketAddr {
+  use axum::{extract, routing};
+
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let addr = listener.local_addr().unwrap();
+  let app = axum::Router::new()
+    .route("/", routing::get(|| async { "Hello, Web!" }))
+    .route(
+      "/hello/:name",
+      routing::get(|extract::Path(name): extract::Path<String>| async move {
+        format!("Hello, {}!", name)
+      }),
+    )
+    .route(
+      "/users/:id",
+      routing::get(|extract::Path(id): extract::Path<u32>| async move { axum::Json(user(id)) }),
+    );
+  tokio::spawn(async move { axum::serve(listener, app).await });
+  addr
+}
+
+/// Sends requests one after another on a single connection until `deadline`, returning the
+/// latency of each.
+async fn client(addr: SocketAddr, deadline: Instant) -> Vec<Duration> {
+  let tcp = TcpStream::connect(addr).await.unwrap();
+  tcp.set_nodelay(true).unwrap();
+  let (mut sender, conn) = http1::handshake(TokioIo::new(tcp)).await.unwrap();
+  tokio::spawn(conn);
+
+  let mut latencies = Vec::new();
+  for path in PATHS.iter().cycle() {
+    let start = Instant::now();
+    if start >= deadline {
+      break;
+    }
+    let req = Request::get(*path)
+      .header("host", "localhost")
+      .body(Empty::<Bytes>::new())
+      .unwrap();
+    sender.ready().await.unwrap();
+    let response = sender.send_request(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    response.into_body().collect().await.unwrap();
+    latencies.push(start.elapsed());
+  }
+  latencies
+}
+
+async fn run(name: &str, addr: SocketAddr, connections: usize, duration: Duration) {
+  // Warm up
+  client(addr, Instant::now() + Duration::from_millis(200)).await;
+
+  let deadline = Instant::now() + duration;
+  let clients = (0 .. connections)
+    .map(|_| tokio::spawn(client(addr, deadline)))
+    .collect::<Vec<_>>();
+  let mut latencies = Vec::new();
+  for client in clients {
+    latencies.extend(client.await.unwrap());
+  }
+  latencies.sort();
+
+  let percentile = |p: usize| latencies[(latencies.len() * p / 100).min(latencies.len() - 1)];
+  println!(
+    "{:<16} {:>10.0} req/s   p50 {:>8.1?}   p99 {:>8.1?}",
+    name,
+    latencies.len() as f64 / duration.as_secs_f64(),
+    percentile(50),
+    percentile(99),
+  );
+}
+
+#[tokio::main]
+async fn main() {
+  // cargo bench passes --bench
+  let args = std::env::args()
+    .skip(1)
+    .filter(|a| !a.starts_with('-'))
+    .collect::<Vec<_>>();
+  let connections = args.first().map_or(16, |a| a.parse().unwrap());
+  let duration = Duration::from_secs(args.get(1).map_or(5, |a| a.parse().unwrap()));
+
+  println!(
+    "{} connections, {:?} each, paths {:?}",
+    connections, duration, PATHS
+  );
+  run("hyper_path_tree", this_crate().await, connections, duration).await;
+  run("axum", axum().await, connections, duration).await;
+}
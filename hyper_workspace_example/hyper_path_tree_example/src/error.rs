@@ -0,0 +1,94 @@
+use std::fmt;
+
+use hyper::{header, StatusCode};
+use serde::Serialize;
+
+use crate::response::{Body, IntoResponse, Response};
+
+/// What a handler, an extractor or the router failed with. Sent as
+/// `{"error": {"status": 404, "code": "not_found", "message": "..."}}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+  status: StatusCode,
+  code: &'static str,
+  message: String,
+}
+
+impl Error {
+  /// `code` is a short machine readable name for the failure, `message` is for people.
+  pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Error {
+    Error {
+      status,
+      code,
+      message: message.into(),
+    }
+  }
+
+  pub fn bad_request(message: impl Into<String>) -> Error {
+    Error::new(StatusCode::BAD_REQUEST, "bad_request", message)
+  }
+
+  pub fn not_found(message: impl Into<String>) -> Error {
+    Error::new(StatusCode::NOT_FOUND, "not_found", message)
+  }
+
+  pub fn internal(message: impl Into<String>) -> Error {
+    Error::new(StatusCode::INTERNAL_SERVER_ERROR, "internal", message)
+  }
+
+  pub fn status(&self) -> StatusCode {
+    self.status
+  }
+
+  pub fn code(&self) -> &'static str {
+    self.code
+  }
+
+  pub fn message(&self) -> &str {
+    &self.message
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} {}: {}",
+      self.status.as_u16(),
+      self.code,
+      self.message
+    )
+  }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+  error: Fields<'a>,
+}
+
+#[derive(Serialize)]
+struct Fields<'a> {
+  status: u16,
+  code: &'a str,
+  message: &'a str,
+}
+
+impl IntoResponse for Error {
+  fn into_response(self) -> Response {
+    let body = serde_json::to_vec(&Envelope {
+      error: Fields {
+        status: self.status.as_u16(),
+        code: self.code,
+        message: &self.message,
+      },
+    })
+    .unwrap();
+    hyper::Response::builder()
+      .status(self.status)
+      .header(header::CONTENT_TYPE, "application/json")
+      .body(Body::from(body))
+      .unwrap()
+  }
+}
@@ -0,0 +1,446 @@
+use std::future::Future;
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+use hyper::{
+  body::Incoming,
+  header::{self, HeaderMap},
+  http::request::Parts,
+  Method, StatusCode, Uri,
+};
+use serde::{
+  de::{
+    self,
+    value::{Error as ValueError, MapDeserializer, SeqDeserializer},
+    DeserializeOwned, IntoDeserializer, Visitor,
+  },
+  forward_to_deserialize_any,
+};
+
+use crate::{error::Error, middleware::BodyLimit};
+
+pub type Request = hyper::Request<Incoming>;
+
+/// Bodies are read up to this unless a [`BodyLimit`](crate::middleware::BodyLimit) says
+/// otherwise.
+pub const DEFAULT_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// The path parameters of the matched route, in order, percent-decoded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params(pub Vec<(String, String)>);
+
+/// Taken from the request line and headers; any number of them may come before the last
+/// argument of a handler.
+pub trait FromRequestParts: Sized {
+  fn from_request_parts(parts: &Parts) -> Result<Self, Error>;
+}
+
+#[doc(hidden)]
+pub mod marker {
+  pub enum ViaParts {}
+  pub enum ViaRequest {}
+}
+
+/// Taken from the whole request, body included: only the last argument of a handler.
+pub trait FromRequest<M = marker::ViaRequest>: Sized {
+  fn from_request(req: Request) -> impl Future<Output = Result<Self, Error>> + Send;
+}
+
+impl<T: FromRequestParts + Send> FromRequest<marker::ViaParts> for T {
+  async fn from_request(req: Request) -> Result<Self, Error> {
+    let (parts, _) = req.into_parts();
+    T::from_request_parts(&parts)
+  }
+}
+
+impl FromRequest for Request {
+  async fn from_request(req: Request) -> Result<Self, Error> {
+    Ok(req)
+  }
+}
+
+impl FromRequestParts for Params {
+  fn from_request_parts(parts: &Parts) -> Result<Self, Error> {
+    parts
+      .extensions
+      .get::<Params>()
+      .cloned()
+      .ok_or_else(|| Error::internal("no path parameters outside of a route"))
+  }
+}
+
+impl FromRequestParts for Method {
+  fn from_request_parts(parts: &Parts) -> Result<Self, Error> {
+    Ok(parts.method.clone())
+  }
+}
+
+impl FromRequestParts for Uri {
+  fn from_request_parts(parts: &Parts) -> Result<Self, Error> {
+    Ok(parts.uri.clone())
+  }
+}
+
+impl FromRequestParts for HeaderMap {
+  fn from_request_parts(parts: &Parts) -> Result<Self, Error> {
+    Ok(parts.headers.clone())
+  }
+}
+
+/// Path parameters deserialized: a struct or map by name, a tuple in order, or a single value
+/// for routes with one parameter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequestParts for Path<T> {
+  fn from_request_parts(parts: &Parts) -> Result<Self, Error> {
+    let params = Params::from_request_parts(parts)?;
+    T::deserialize(ParamsDeserializer(&params.0))
+      .map(Path)
+      .map_err(|e| Error::new(StatusCode::BAD_REQUEST, "invalid_path", e.to_string()))
+  }
+}
+
+/// The query string deserialized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequestParts for Query<T> {
+  fn from_request_parts(parts: &Parts) -> Result<Self, Error> {
+    serde_urlencoded::from_str(parts.uri.query().unwrap_or(""))
+      .map(Query)
+      .map_err(|e| Error::new(StatusCode::BAD_REQUEST, "invalid_query", e.to_string()))
+  }
+}
+
+/// Something put in the extensions of the request, by a middleware most likely.
+#[derive(Debug, Clone)]
+pub struct Extension<T>(pub T);
+
+impl<T: Clone + Send + Sync + 'static> FromRequestParts for Extension<T> {
+  fn from_request_parts(parts: &Parts) -> Result<Self, Error> {
+    parts
+      .extensions
+      .get::<T>()
+      .cloned()
+      .map(Extension)
+      .ok_or_else(|| {
+        Error::internal(format!(
+          "no {} in the request extensions",
+          std::any::type_name::<T>()
+        ))
+      })
+  }
+}
+
+/// A JSON body in, when the content type says so, or a JSON response out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+  async fn from_request(req: Request) -> Result<Self, Error> {
+    let json = req
+      .headers()
+      .get(header::CONTENT_TYPE)
+      .and_then(|v| v.to_str().ok())
+      .and_then(|v| v.split(';').next())
+      .map(|mime| {
+        let mime = mime.trim();
+        mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+      })
+      .unwrap_or(false);
+    if !json {
+      return Err(Error::new(
+        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        "unsupported_media_type",
+        "expected content-type: application/json",
+      ));
+    }
+
+    let body = Bytes::from_request(req).await?;
+    serde_json::from_slice(&body).map(Json).map_err(|e| {
+      if e.is_data() {
+        Error::new(
+          StatusCode::UNPROCESSABLE_ENTITY,
+          "invalid_json",
+          e.to_string(),
+        )
+      } else {
+        Error::new(StatusCode::BAD_REQUEST, "invalid_json", e.to_string())
+      }
+    })
+  }
+}
+
+impl FromRequest for Bytes {
+  async fn from_request(req: Request) -> Result<Self, Error> {
+    let limit = req
+      .extensions()
+      .get::<BodyLimit>()
+      .map_or(DEFAULT_BODY_LIMIT, BodyLimit::bytes);
+    let too_large = || {
+      Error::new(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        "payload_too_large",
+        format!("body larger than {} bytes", limit),
+      )
+    };
+
+    // Refused before reading when announced
+    let announced = req
+      .headers()
+      .get(header::CONTENT_LENGTH)
+      .and_then(|v| v.to_str().ok())
+      .and_then(|v| v.parse::<u64>().ok());
+    if announced.is_some_and(|len| len > limit as u64) {
+      return Err(too_large());
+    }
+
+    match Limited::new(req.into_body(), limit).collect().await {
+      Ok(body) => Ok(body.to_bytes()),
+      Err(e) if e.is::<LengthLimitError>() => Err(too_large()),
+      Err(e) => Err(Error::bad_request(format!("cannot read body: {}", e))),
+    }
+  }
+}
+
+impl FromRequest for String {
+  async fn from_request(req: Request) -> Result<Self, Error> {
+    let body = Bytes::from_request(req).await?;
+    String::from_utf8(body.to_vec()).map_err(|_| Error::bad_request("body is not UTF-8"))
+  }
+}
+
+/// Reads path parameters into whatever shape the handler asks for.
+struct ParamsDeserializer<'a>(&'a [(String, String)]);
+
+impl<'a> ParamsDeserializer<'a> {
+  fn single(&self) -> Result<ParamDeserializer<'a>, ValueError> {
+    match self.0 {
+      [(_, value)] => Ok(ParamDeserializer(value)),
+      params => Err(de::Error::custom(format!(
+        "expected 1 path parameter, the route has {}",
+        params.len()
+      ))),
+    }
+  }
+
+  fn values(&self) -> SeqDeserializer<impl Iterator<Item = ParamDeserializer<'a>>, ValueError> {
+    SeqDeserializer::new(self.0.iter().map(|(_, value)| ParamDeserializer(value)))
+  }
+}
+
+macro_rules! single {
+  ($($method:ident)*) => {
+    $(
+      fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.single()?.$method(visitor)
+      }
+    )*
+  };
+}
+
+impl<'de> de::Deserializer<'de> for ParamsDeserializer<'de> {
+  type Error = ValueError;
+
+  fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+    self.deserialize_map(visitor)
+  }
+
+  fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+    visitor.visit_map(MapDeserializer::new(
+      self
+        .0
+        .iter()
+        .map(|(name, value)| (name.as_str(), ParamDeserializer(value))),
+    ))
+  }
+
+  fn deserialize_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    _fields: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Self::Error> {
+    self.deserialize_map(visitor)
+  }
+
+  fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+    visitor.visit_seq(self.values())
+  }
+
+  fn deserialize_tuple<V: Visitor<'de>>(
+    self,
+    len: usize,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error> {
+    if len != self.0.len() {
+      return Err(de::Error::invalid_length(self.0.len(), &visitor));
+    }
+    visitor.visit_seq(self.values())
+  }
+
+  fn deserialize_tuple_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    len: usize,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error> {
+    self.deserialize_tuple(len, visitor)
+  }
+
+  fn deserialize_newtype_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error> {
+    visitor.visit_newtype_struct(self)
+  }
+
+  fn deserialize_enum<V: Visitor<'de>>(
+    self,
+    name: &'static str,
+    variants: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Self::Error> {
+    self.single()?.deserialize_enum(name, variants, visitor)
+  }
+
+  single! {
+    deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+    deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+    deserialize_f64 deserialize_char deserialize_str deserialize_string deserialize_option
+  }
+
+  forward_to_deserialize_any! {
+    i128 u128 bytes byte_buf unit unit_struct identifier ignored_any
+  }
+}
+
+/// One parameter, parsed from text into the type asked for.
+struct ParamDeserializer<'a>(&'a str);
+
+impl<'de> IntoDeserializer<'de, ValueError> for ParamDeserializer<'de> {
+  type Deserializer = Self;
+
+  fn into_deserializer(self) -> Self {
+    self
+  }
+}
+
+macro_rules! parse {
+  ($($method:ident => $visit:ident,)*) => {
+    $(
+      fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0.parse() {
+          Ok(v) => visitor.$visit(v),
+          Err(_) => Err(de::Error::invalid_value(de::Unexpected::Str(self.0), &visitor)),
+        }
+      }
+    )*
+  };
+}
+
+impl<'de> de::Deserializer<'de> for ParamDeserializer<'de> {
+  type Error = ValueError;
+
+  fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+    visitor.visit_borrowed_str(self.0)
+  }
+
+  fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+    visitor.visit_some(self)
+  }
+
+  fn deserialize_newtype_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, Self::Error> {
+    visitor.visit_newtype_struct(self)
+  }
+
+  fn deserialize_enum<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    _variants: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Self::Error> {
+    visitor.visit_enum(self.0.into_deserializer())
+  }
+
+  parse! {
+    deserialize_bool => visit_bool,
+    deserialize_i8 => visit_i8,
+    deserialize_i16 => visit_i16,
+    deserialize_i32 => visit_i32,
+    deserialize_i64 => visit_i64,
+    deserialize_u8 => visit_u8,
+    deserialize_u16 => visit_u16,
+    deserialize_u32 => visit_u32,
+    deserialize_u64 => visit_u64,
+    deserialize_f32 => visit_f32,
+    deserialize_f64 => visit_f64,
+    deserialize_char => visit_char,
+  }
+
+  forward_to_deserialize_any! {
+    i128 u128 str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+    identifier ignored_any
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use serde::Deserialize;
+
+  use super::*;
+
+  fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+      .iter()
+      .map(|(k, v)| (k.to_string(), v.to_string()))
+      .collect()
+  }
+
+  fn path<T: DeserializeOwned>(pairs: &[(&str, &str)]) -> Result<T, ValueError> {
+    T::deserialize(ParamsDeserializer(&params(pairs)))
+  }
+
+  #[test]
+  fn params_take_the_shape_asked_for() {
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Item {
+      user: String,
+      id: u32,
+      draft: Option<bool>,
+    }
+
+    assert_eq!(path::<u32>(&[("id", "42")]).unwrap(), 42);
+    assert_eq!(path::<String>(&[("name", "ferris")]).unwrap(), "ferris");
+    assert_eq!(
+      path::<(String, u32)>(&[("user", "ann"), ("id", "7")]).unwrap(),
+      ("ann".to_string(), 7)
+    );
+    assert_eq!(
+      path::<Item>(&[("user", "ann"), ("id", "7")]).unwrap(),
+      Item {
+        user: "ann".into(),
+        id: 7,
+        draft: None
+      }
+    );
+    let map = path::<HashMap<String, String>>(&[("a", "1")]).unwrap();
+    assert_eq!(map["a"], "1");
+  }
+
+  #[test]
+  fn params_that_do_not_fit_are_errors() {
+    assert!(path::<u32>(&[("id", "abc")]).is_err());
+    assert!(path::<u32>(&[("a", "1"), ("b", "2")]).is_err());
+    assert!(path::<(u32, u32, u32)>(&[("a", "1"), ("b", "2")]).is_err());
+    assert!(path::<i8>(&[("n", "300")]).is_err());
+  }
+}
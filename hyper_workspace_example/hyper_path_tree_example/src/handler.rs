@@ -0,0 +1,93 @@
+use std::{future::Future, marker::PhantomData, pin::Pin, sync::Arc};
+
+use crate::{
+  extract::{FromRequest, FromRequestParts, Request},
+  response::{IntoResponse, Response},
+};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// An async function whose arguments are extractors: any number of [`FromRequestParts`]
+/// followed by one [`FromRequest`], returning anything that is [`IntoResponse`]. `T` only
+/// tells the argument lists apart.
+pub trait Handler<T>: Clone + Send + Sync + 'static {
+  fn call(&self, req: Request) -> BoxFuture<'static, Response>;
+}
+
+impl<F, Fut, R> Handler<()> for F
+where
+  F: Fn() -> Fut + Clone + Send + Sync + 'static,
+  Fut: Future<Output = R> + Send,
+  R: IntoResponse,
+{
+  fn call(&self, _req: Request) -> BoxFuture<'static, Response> {
+    let handler = self.clone();
+    Box::pin(async move { handler().await.into_response() })
+  }
+}
+
+macro_rules! impl_handler {
+  ($($part:ident),*; $last:ident) => {
+    impl<F, Fut, R, M, $($part,)* $last> Handler<(M, $($part,)* $last,)> for F
+    where
+      F: Fn($($part,)* $last) -> Fut + Clone + Send + Sync + 'static,
+      Fut: Future<Output = R> + Send,
+      R: IntoResponse,
+      $($part: FromRequestParts + Send,)*
+      $last: FromRequest<M> + Send,
+    {
+      #[allow(non_snake_case)]
+      fn call(&self, req: Request) -> BoxFuture<'static, Response> {
+        let handler = self.clone();
+        Box::pin(async move {
+          #[allow(unused_mut)]
+          let (mut parts, body) = req.into_parts();
+          $(
+            let $part = match $part::from_request_parts(&parts) {
+              Ok(v) => v,
+              Err(e) => return e.into_response(),
+            };
+          )*
+          let req = Request::from_parts(parts, body);
+          let $last = match $last::from_request(req).await {
+            Ok(v) => v,
+            Err(e) => return e.into_response(),
+          };
+          handler($($part,)* $last).await.into_response()
+        })
+      }
+    }
+  };
+}
+
+impl_handler!(; T1);
+impl_handler!(T1; T2);
+impl_handler!(T1, T2; T3);
+impl_handler!(T1, T2, T3; T4);
+impl_handler!(T1, T2, T3, T4; T5);
+impl_handler!(T1, T2, T3, T4, T5; T6);
+
+/// A handler with its argument list forgotten, or a handler wrapped in middleware.
+pub(crate) trait Endpoint: Send + Sync + 'static {
+  fn call(&self, req: Request) -> BoxFuture<'static, Response>;
+}
+
+pub(crate) type BoxEndpoint = Arc<dyn Endpoint>;
+
+struct HandlerEndpoint<H, T> {
+  handler: H,
+  _args: PhantomData<fn() -> T>,
+}
+
+impl<H: Handler<T>, T: 'static> Endpoint for HandlerEndpoint<H, T> {
+  fn call(&self, req: Request) -> BoxFuture<'static, Response> {
+    self.handler.call(req)
+  }
+}
+
+pub(crate) fn endpoint<H: Handler<T>, T: 'static>(handler: H) -> BoxEndpoint {
+  Arc::new(HandlerEndpoint {
+    handler,
+    _args: PhantomData,
+  })
+}
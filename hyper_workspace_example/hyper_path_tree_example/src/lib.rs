@@ -0,0 +1,16 @@
+//! A small web framework on hyper and path-tree: method routing, extractors, middlewares and
+//! nested routers.
+
+pub mod error;
+pub mod extract;
+pub mod handler;
+pub mod middleware;
+pub mod response;
+mod router;
+
+pub use error::Error;
+pub use extract::{Extension, Json, Params, Path, Query, Request};
+pub use handler::Handler;
+pub use middleware::{Middleware, Next};
+pub use response::{IntoResponse, Response};
+pub use router::{delete, get, on, patch, post, put, MethodRouter, Router, RouterService};
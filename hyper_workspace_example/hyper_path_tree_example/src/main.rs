@@ -1,72 +1,83 @@
-use std::{convert::Infallible, future::Future, net::SocketAddr, pin::Pin, sync::Arc};
-
-use bytes::Bytes;
-use http_body_util::Full;
-use hyper::{body::Incoming, service::service_fn, Request, Response, StatusCode};
-use hyper_util::{
-  rt::{TokioExecutor, TokioIo},
-  server::conn::auto,
+use std::{net::SocketAddr, time::Duration};
+
+use hyper::{header, Method, StatusCode};
+use hyper_path_tree_example::{
+  get,
+  middleware::{BodyLimit, Compression, Cors, Logger, Timeout},
+  post, Error, Json, Params, Path, Query, Router,
 };
-use path_tree::PathTree;
+use serde::{Deserialize, Serialize};
 use tokio::net::TcpListener;
 
-static NOT_FOUND: &[u8] = b"Not Found";
+async fn index() -> &'static str {
+  "Hello, Web!"
+}
 
-type Params = Vec<(String, String)>;
-type Body = Full<Bytes>;
+async fn hello_world(Params(params): Params) -> String {
+  let mut s = String::new();
+  s.push_str("Hello, World!\n");
+  for (_, v) in params {
+    s.push_str(&format!("param = {v}"));
+  }
+  s
+}
 
-trait Handler: Send + Sync {
-  fn call<'a>(
-    &'a self,
-    req: Request<Incoming>,
-  ) -> Pin<Box<dyn Future<Output = Response<Body>> + Send + 'a>>;
+async fn hello_user(Path(name): Path<String>) -> String {
+  format!("Hello, name = {name}!")
 }
 
-impl<F, R> Handler for F
-where
-  F: Send + Sync + Fn(Request<Incoming>) -> R,
-  R: Future<Output = Response<Full<Bytes>>> + Send + 'static,
-{
-  fn call<'a>(
-    &'a self,
-    req: Request<Incoming>,
-  ) -> Pin<Box<dyn Future<Output = Response<Body>> + Send + 'a>> {
-    let fut = (self)(req);
-    Box::pin(async move { fut.await })
-  }
+async fn hello_rust() -> &'static str {
+  "Hello, Rust!"
 }
 
-async fn index(_: Request<Incoming>) -> Response<Body> {
-  Response::new(Body::from("Hello, Web!"))
+async fn login() -> &'static str {
+  "I'm logined!"
 }
 
-async fn hello_world(req: Request<Incoming>) -> Response<Body> {
-  let params = req.extensions().get::<Params>().unwrap();
-  let mut s = String::new();
-  s.push_str("Hello, World!\n");
-  for (_, v) in params {
-    s.push_str(&format!("param = {v}"));
-  }
-  Response::new(Body::from(s))
+#[derive(Deserialize)]
+struct Page {
+  #[serde(default)]
+  offset: usize,
+  #[serde(default = "Page::default_limit")]
+  limit: usize,
 }
 
-async fn hello_user(req: Request<Incoming>) -> Response<Body> {
-  let params = req.extensions().get::<Params>().unwrap();
-  let mut s = String::new();
-  s.push_str("Hello, ");
-  for (k, v) in params {
-    s.push_str(&format!("{k} = {v}"));
+impl Page {
+  fn default_limit() -> usize {
+    10
   }
-  s.push('!');
-  Response::new(Body::from(s))
 }
 
-async fn hello_rust(_: Request<Incoming>) -> Response<Body> {
-  Response::new(Body::from("Hello, Rust!"))
+#[derive(Serialize, Deserialize)]
+struct User {
+  id: u32,
+  name: String,
+}
+
+async fn list_users(Query(page): Query<Page>) -> Json<Vec<User>> {
+  let users = (1 ..= 100)
+    .skip(page.offset)
+    .take(page.limit)
+    .map(|id| User {
+      id,
+      name: format!("user{id}"),
+    })
+    .collect();
+  Json(users)
 }
 
-async fn login(_req: Request<Incoming>) -> Response<Body> {
-  Response::new(Body::from("I'm logined!"))
+async fn get_user(Path(id): Path<u32>) -> Result<Json<User>, Error> {
+  if !(1 ..= 100).contains(&id) {
+    return Err(Error::not_found(format!("no user {id}")));
+  }
+  Ok(Json(User {
+    id,
+    name: format!("user{id}"),
+  }))
+}
+
+async fn create_user(Json(user): Json<User>) -> (StatusCode, Json<User>) {
+  (StatusCode::CREATED, Json(user))
 }
 
 #[tokio::main]
@@ -75,58 +86,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
   let listener = TcpListener::bind(addr).await?;
 
-  // /
-  // ├── GET/ •0
-  // │   ├── hello/
-  // │   │   └── : •2
-  // │   ├── rust •3
-  // │   └── ** •1
-  // └── POST/login •4
-  let mut tree = PathTree::<Box<dyn Handler>>::new();
-  _ = tree.insert("/GET/", Box::new(index));
-  _ = tree.insert("/GET/*", Box::new(hello_world));
-  _ = tree.insert("/GET/hello/:name", Box::new(hello_user));
-  _ = tree.insert("/GET/rust", Box::new(hello_rust));
-  _ = tree.insert("/POST/login", Box::new(login));
-
-  let tree = Arc::new(tree);
-
-  loop {
-    let (tcp, _) = listener.accept().await?;
-    let io = TokioIo::new(tcp);
-    let router = Arc::clone(&tree);
-
-    tokio::task::spawn(async move {
-      if let Err(err) = auto::Builder::new(TokioExecutor::new())
-        .serve_connection(
-          io,
-          service_fn(move |mut req| {
-            let router = router.clone();
-            let path = "/".to_owned() + req.method().as_str() + req.uri().path();
-
-            async move {
-              Ok::<_, Infallible>(match router.find(&path) {
-                Some((handler, route)) => {
-                  let p = route
-                    .params()
-                    .iter()
-                    .map(|p| (p.0.to_string(), p.1.to_string()))
-                    .collect::<Params>();
-                  req.extensions_mut().insert(p);
-                  handler.call(req).await
-                }
-                None => Response::builder()
-                  .status(StatusCode::NOT_FOUND)
-                  .body(NOT_FOUND.into())
-                  .unwrap(),
-              })
-            }
-          }),
-        )
-        .await
-      {
-        println!("Error serving connection: {:?}", err);
-      }
-    });
-  }
+  let api = Router::new()
+    .route("/users", get(list_users).post(create_user))
+    .route("/users/:id", get(get_user))
+    .layer(
+      Cors::new()
+        .allow_any_origin()
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([header::CONTENT_TYPE])
+        .max_age(Duration::from_secs(600)),
+    )
+    .layer(BodyLimit::new(64 * 1024));
+
+  let app = Router::new()
+    .route("/", get(index))
+    .route("/*", get(hello_world))
+    .route("/hello/:name", get(hello_user))
+    .route("/rust", get(hello_rust))
+    .route("/login", post(login))
+    .nest("/api", api)
+    .layer(Logger)
+    .layer(Timeout(Duration::from_secs(10)))
+    .layer(Compression::new());
+
+  app.serve(listener).await?;
+  Ok(())
 }
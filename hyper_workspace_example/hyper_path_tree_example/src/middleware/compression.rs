@@ -0,0 +1,108 @@
+use std::io::Write;
+
+use flate2::{write::GzEncoder, Compression as Level};
+use http_body_util::BodyExt;
+use hyper::header::{self, HeaderMap, HeaderValue};
+
+use super::{Middleware, Next};
+use crate::{
+  extract::Request,
+  handler::BoxFuture,
+  response::{Body, Response},
+};
+
+/// Gzips text, JSON, JavaScript and XML responses of at least `min_size` bytes for clients
+/// that accept it.
+#[derive(Debug, Clone, Copy)]
+pub struct Compression {
+  pub min_size: usize,
+}
+
+impl Default for Compression {
+  fn default() -> Compression {
+    Compression { min_size: 256 }
+  }
+}
+
+impl Compression {
+  pub fn new() -> Compression {
+    Compression::default()
+  }
+}
+
+impl Middleware for Compression {
+  fn call<'a>(&'a self, req: Request, next: Next<'a>) -> BoxFuture<'a, Response> {
+    let gzip = accepts_gzip(req.headers());
+    Box::pin(async move {
+      let mut response = next.run(req).await;
+      response
+        .headers_mut()
+        .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+      if !gzip
+        || response.headers().contains_key(header::CONTENT_ENCODING)
+        || !compressible(response.headers())
+      {
+        return response;
+      }
+
+      let (mut parts, body) = response.into_parts();
+      let body = match body.collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(never) => match never {},
+      };
+      if body.len() < self.min_size {
+        return Response::from_parts(parts, Body::from(body));
+      }
+
+      let mut encoder = GzEncoder::new(Vec::with_capacity(body.len() / 2), Level::fast());
+      encoder.write_all(&body).unwrap();
+      let compressed = encoder.finish().unwrap();
+      parts.headers.remove(header::CONTENT_LENGTH);
+      parts
+        .headers
+        .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+      Response::from_parts(parts, Body::from(compressed))
+    })
+  }
+}
+
+/// `gzip` listed without `q=0`, or else `*` listed without it.
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+  let mut gzip = None;
+  let mut any = None;
+  let codings = headers
+    .get_all(header::ACCEPT_ENCODING)
+    .iter()
+    .filter_map(|v| v.to_str().ok())
+    .flat_map(|v| v.split(','));
+  for coding in codings {
+    let mut parts = coding.split(';').map(str::trim);
+    let name = parts.next().unwrap_or("");
+    let refused = parts.any(|p| {
+      p.strip_prefix("q=")
+        .and_then(|q| q.parse::<f32>().ok())
+        .is_some_and(|q| q == 0.0)
+    });
+    if name.eq_ignore_ascii_case("gzip") {
+      gzip.get_or_insert(!refused);
+    } else if name == "*" {
+      any.get_or_insert(!refused);
+    }
+  }
+  gzip.or(any).unwrap_or(false)
+}
+
+fn compressible(headers: &HeaderMap) -> bool {
+  let Some(mime) = headers
+    .get(header::CONTENT_TYPE)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| v.split(';').next())
+  else {
+    return false;
+  };
+  let mime = mime.trim();
+  mime.starts_with("text/")
+    || mime.ends_with("json")
+    || mime.ends_with("javascript")
+    || mime.ends_with("xml")
+}
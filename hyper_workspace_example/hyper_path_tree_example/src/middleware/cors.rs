@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+use hyper::{
+  header::{self, HeaderName, HeaderValue},
+  Method, StatusCode,
+};
+
+use super::{Middleware, Next};
+use crate::{
+  error::Error,
+  extract::Request,
+  handler::BoxFuture,
+  response::{IntoResponse, Response},
+};
+
+/// Answers preflight requests itself and adds `access-control-allow-origin` to the responses of
+/// allowed origins. Requests without an `origin` header go through untouched.
+///
+/// ```ignore
+/// Cors::new()
+///   .allow_origin("https://example.com")
+///   .allow_methods([Method::GET, Method::POST])
+///   .allow_headers([header::CONTENT_TYPE])
+///   .max_age(Duration::from_secs(600))
+/// ```
+#[derive(Debug, Clone)]
+pub struct Cors {
+  any_origin: bool,
+  origins: Vec<HeaderValue>,
+  methods: Vec<Method>,
+  headers: Vec<HeaderName>,
+  max_age: Option<Duration>,
+}
+
+impl Default for Cors {
+  fn default() -> Cors {
+    Cors {
+      any_origin: false,
+      origins: Vec::new(),
+      methods: vec![Method::GET, Method::HEAD, Method::POST],
+      headers: Vec::new(),
+      max_age: None,
+    }
+  }
+}
+
+impl Cors {
+  /// Allows no origin until told otherwise, and `GET`, `HEAD` and `POST`.
+  pub fn new() -> Cors {
+    Cors::default()
+  }
+
+  pub fn allow_any_origin(mut self) -> Cors {
+    self.any_origin = true;
+    self
+  }
+
+  /// May be called for each allowed origin. Panics if `origin` is not a valid header value.
+  pub fn allow_origin(mut self, origin: &str) -> Cors {
+    self.origins.push(HeaderValue::from_str(origin).unwrap());
+    self
+  }
+
+  pub fn allow_methods(mut self, methods: impl IntoIterator<Item = Method>) -> Cors {
+    self.methods = methods.into_iter().collect();
+    self
+  }
+
+  pub fn allow_headers(mut self, headers: impl IntoIterator<Item = HeaderName>) -> Cors {
+    self.headers = headers.into_iter().collect();
+    self
+  }
+
+  pub fn max_age(mut self, max_age: Duration) -> Cors {
+    self.max_age = Some(max_age);
+    self
+  }
+
+  fn allowed(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+    if self.any_origin {
+      Some(HeaderValue::from_static("*"))
+    } else if self.origins.contains(origin) {
+      Some(origin.clone())
+    } else {
+      None
+    }
+  }
+
+  fn preflight(&self, allow_origin: HeaderValue) -> Response {
+    let mut response = StatusCode::NO_CONTENT.into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+    headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, join(&self.methods));
+    if !self.headers.is_empty() {
+      headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, join(&self.headers));
+    }
+    if let Some(max_age) = self.max_age {
+      headers.insert(header::ACCESS_CONTROL_MAX_AGE, max_age.as_secs().into());
+    }
+    headers.insert(header::VARY, HeaderValue::from_static("origin"));
+    response
+  }
+}
+
+impl Middleware for Cors {
+  fn call<'a>(&'a self, req: Request, next: Next<'a>) -> BoxFuture<'a, Response> {
+    let Some(origin) = req.headers().get(header::ORIGIN).cloned() else {
+      return next.run(req);
+    };
+    let allow_origin = self.allowed(&origin);
+
+    let preflight = req.method() == Method::OPTIONS
+      && req
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+    if preflight {
+      let response = match allow_origin {
+        Some(allow_origin) => self.preflight(allow_origin),
+        None => Error::new(
+          StatusCode::FORBIDDEN,
+          "cors",
+          format!("origin {:?} is not allowed", origin),
+        )
+        .into_response(),
+      };
+      return Box::pin(async move { response });
+    }
+
+    Box::pin(async move {
+      let mut response = next.run(req).await;
+      if let Some(allow_origin) = allow_origin {
+        let headers = response.headers_mut();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+      }
+      response
+    })
+  }
+}
+
+fn join<T: AsRef<str>>(items: &[T]) -> HeaderValue {
+  let joined = items
+    .iter()
+    .map(AsRef::as_ref)
+    .collect::<Vec<_>>()
+    .join(", ");
+  HeaderValue::from_str(&joined).unwrap()
+}
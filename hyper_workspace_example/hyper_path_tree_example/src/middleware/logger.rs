@@ -0,0 +1,27 @@
+use std::time::Instant;
+
+use super::{Middleware, Next};
+use crate::{extract::Request, handler::BoxFuture, response::Response};
+
+/// Prints one line per request once it is answered.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Logger;
+
+impl Middleware for Logger {
+  fn call<'a>(&'a self, req: Request, next: Next<'a>) -> BoxFuture<'a, Response> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_owned();
+    let start = Instant::now();
+    Box::pin(async move {
+      let response = next.run(req).await;
+      println!(
+        "{} {} {} {:?}",
+        method,
+        path,
+        response.status().as_u16(),
+        start.elapsed()
+      );
+      response
+    })
+  }
+}
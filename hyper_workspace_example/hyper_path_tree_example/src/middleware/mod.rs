@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use crate::{
+  extract::Request,
+  handler::{BoxEndpoint, BoxFuture, Endpoint},
+  response::Response,
+};
+
+mod compression;
+mod cors;
+mod logger;
+mod timeout;
+
+pub use compression::Compression;
+pub use cors::Cors;
+pub use logger::Logger;
+pub use timeout::Timeout;
+
+/// Runs around the handlers of a router: may change the request, answer it itself, or pass it
+/// on with `next.run(req)` and change the response.
+pub trait Middleware: Send + Sync + 'static {
+  fn call<'a>(&'a self, req: Request, next: Next<'a>) -> BoxFuture<'a, Response>;
+}
+
+/// The rest of the chain: the middlewares after this one, then the handler.
+pub struct Next<'a> {
+  endpoint: &'a dyn Endpoint,
+  chain: &'a [Arc<dyn Middleware>],
+}
+
+impl<'a> Next<'a> {
+  pub fn run(self, req: Request) -> BoxFuture<'a, Response> {
+    match self.chain.split_first() {
+      Some((first, rest)) => first.call(
+        req,
+        Next {
+          endpoint: self.endpoint,
+          chain: rest,
+        },
+      ),
+      None => self.endpoint.call(req),
+    }
+  }
+}
+
+/// An endpoint behind a chain of middlewares, the first of which sees the request first.
+pub(crate) struct Layered {
+  chain: Arc<[Arc<dyn Middleware>]>,
+  endpoint: BoxEndpoint,
+}
+
+impl Layered {
+  pub(crate) fn wrap(chain: &[Arc<dyn Middleware>], endpoint: BoxEndpoint) -> BoxEndpoint {
+    if chain.is_empty() {
+      return endpoint;
+    }
+    Arc::new(Layered {
+      chain: chain.into(),
+      endpoint,
+    })
+  }
+}
+
+impl Endpoint for Layered {
+  fn call(&self, req: Request) -> BoxFuture<'static, Response> {
+    let chain = self.chain.clone();
+    let endpoint = self.endpoint.clone();
+    Box::pin(async move {
+      Next {
+        endpoint: &*endpoint,
+        chain: &chain,
+      }
+      .run(req)
+      .await
+    })
+  }
+}
+
+/// Bodies read by the `Json`, `Bytes` and `String` extractors are refused with 413 past this
+/// many bytes. Without it the limit is [`DEFAULT_BODY_LIMIT`](crate::extract::DEFAULT_BODY_LIMIT).
+#[derive(Debug, Clone, Copy)]
+pub struct BodyLimit(usize);
+
+impl BodyLimit {
+  pub fn new(bytes: usize) -> BodyLimit {
+    BodyLimit(bytes)
+  }
+
+  pub fn bytes(&self) -> usize {
+    self.0
+  }
+}
+
+impl Middleware for BodyLimit {
+  fn call<'a>(&'a self, mut req: Request, next: Next<'a>) -> BoxFuture<'a, Response> {
+    req.extensions_mut().insert(*self);
+    next.run(req)
+  }
+}
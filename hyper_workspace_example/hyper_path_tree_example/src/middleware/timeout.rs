@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+use hyper::StatusCode;
+
+use super::{Middleware, Next};
+use crate::{
+  error::Error,
+  extract::Request,
+  handler::BoxFuture,
+  response::{IntoResponse, Response},
+};
+
+/// Gives up on handlers slower than this with 503. The handler future is dropped.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeout(pub Duration);
+
+impl Middleware for Timeout {
+  fn call<'a>(&'a self, req: Request, next: Next<'a>) -> BoxFuture<'a, Response> {
+    Box::pin(async move {
+      match tokio::time::timeout(self.0, next.run(req)).await {
+        Ok(response) => response,
+        Err(_) => Error::new(
+          StatusCode::SERVICE_UNAVAILABLE,
+          "timeout",
+          format!("no response within {:?}", self.0),
+        )
+        .into_response(),
+      }
+    })
+  }
+}
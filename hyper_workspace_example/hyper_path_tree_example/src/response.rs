@@ -0,0 +1,97 @@
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{header, StatusCode};
+use serde::Serialize;
+
+use crate::{error::Error, extract::Json};
+
+pub type Body = Full<Bytes>;
+pub type Response = hyper::Response<Body>;
+
+/// What handlers may return.
+pub trait IntoResponse {
+  fn into_response(self) -> Response;
+}
+
+impl IntoResponse for Response {
+  fn into_response(self) -> Response {
+    self
+  }
+}
+
+impl IntoResponse for () {
+  fn into_response(self) -> Response {
+    Response::new(Body::default())
+  }
+}
+
+impl IntoResponse for StatusCode {
+  fn into_response(self) -> Response {
+    let mut response = ().into_response();
+    *response.status_mut() = self;
+    response
+  }
+}
+
+impl IntoResponse for &'static str {
+  fn into_response(self) -> Response {
+    text(Bytes::from_static(self.as_bytes()))
+  }
+}
+
+impl IntoResponse for String {
+  fn into_response(self) -> Response {
+    text(Bytes::from(self))
+  }
+}
+
+impl IntoResponse for Bytes {
+  fn into_response(self) -> Response {
+    hyper::Response::builder()
+      .header(header::CONTENT_TYPE, "application/octet-stream")
+      .body(Body::from(self))
+      .unwrap()
+  }
+}
+
+impl IntoResponse for Vec<u8> {
+  fn into_response(self) -> Response {
+    Bytes::from(self).into_response()
+  }
+}
+
+impl<T: IntoResponse> IntoResponse for (StatusCode, T) {
+  fn into_response(self) -> Response {
+    let mut response = self.1.into_response();
+    *response.status_mut() = self.0;
+    response
+  }
+}
+
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+  fn into_response(self) -> Response {
+    match self {
+      Ok(v) => v.into_response(),
+      Err(e) => e.into_response(),
+    }
+  }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+  fn into_response(self) -> Response {
+    match serde_json::to_vec(&self.0) {
+      Ok(body) => hyper::Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap(),
+      Err(e) => Error::internal(format!("cannot serialize response: {}", e)).into_response(),
+    }
+  }
+}
+
+fn text(body: Bytes) -> Response {
+  hyper::Response::builder()
+    .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+    .body(Body::from(body))
+    .unwrap()
+}
@@ -0,0 +1,310 @@
+use std::{convert::Infallible, io, sync::Arc};
+
+use hyper::{
+  header::{self, HeaderValue},
+  service::Service,
+  Method, StatusCode,
+};
+use hyper_util::{
+  rt::{TokioExecutor, TokioIo},
+  server::conn::auto,
+};
+use path_tree::PathTree;
+use percent_encoding::percent_decode_str;
+use tokio::net::TcpListener;
+
+use crate::{
+  error::Error,
+  extract::{Params, Request},
+  handler::{endpoint, BoxEndpoint, BoxFuture, Endpoint, Handler},
+  middleware::{Layered, Middleware},
+  response::{Body, IntoResponse, Response},
+};
+
+/// The handlers of one path, by method. `HEAD` falls back to the `GET` handler with the body
+/// left out, `OPTIONS` is answered with the allowed methods, anything else is 405.
+#[derive(Clone, Default)]
+pub struct MethodRouter {
+  endpoints: Vec<(Method, BoxEndpoint)>,
+}
+
+macro_rules! method {
+  ($($name:ident => $method:ident,)*) => {
+    $(
+      pub fn $name<H: Handler<T>, T: 'static>(handler: H) -> MethodRouter {
+        MethodRouter::default().on(Method::$method, handler)
+      }
+    )*
+
+    impl MethodRouter {
+      $(
+        pub fn $name<H: Handler<T>, T: 'static>(self, handler: H) -> MethodRouter {
+          self.on(Method::$method, handler)
+        }
+      )*
+    }
+  };
+}
+
+method! {
+  get => GET,
+  post => POST,
+  put => PUT,
+  patch => PATCH,
+  delete => DELETE,
+}
+
+pub fn on<H: Handler<T>, T: 'static>(method: Method, handler: H) -> MethodRouter {
+  MethodRouter::default().on(method, handler)
+}
+
+impl MethodRouter {
+  /// Panics if `method` already has a handler.
+  pub fn on<H: Handler<T>, T: 'static>(self, method: Method, handler: H) -> MethodRouter {
+    self.insert(method, endpoint(handler))
+  }
+
+  fn insert(mut self, method: Method, endpoint: BoxEndpoint) -> MethodRouter {
+    if self.find(&method).is_some() {
+      panic!("a {} handler is already routed here", method);
+    }
+    self.endpoints.push((method, endpoint));
+    self
+  }
+
+  fn merge(mut self, other: MethodRouter) -> MethodRouter {
+    for (method, endpoint) in other.endpoints {
+      self = self.insert(method, endpoint);
+    }
+    self
+  }
+
+  fn find(&self, method: &Method) -> Option<&BoxEndpoint> {
+    self
+      .endpoints
+      .iter()
+      .find(|(m, _)| m == method)
+      .map(|(_, endpoint)| endpoint)
+  }
+
+  fn allow(&self) -> HeaderValue {
+    let mut methods = self
+      .endpoints
+      .iter()
+      .map(|(m, _)| m.as_str())
+      .collect::<Vec<_>>();
+    if self.find(&Method::GET).is_some() && self.find(&Method::HEAD).is_none() {
+      methods.push("HEAD");
+    }
+    if self.find(&Method::OPTIONS).is_none() {
+      methods.push("OPTIONS");
+    }
+    HeaderValue::from_str(&methods.join(", ")).unwrap()
+  }
+}
+
+impl Endpoint for MethodRouter {
+  fn call(&self, req: Request) -> BoxFuture<'static, Response> {
+    if let Some(endpoint) = self.find(req.method()) {
+      return endpoint.call(req);
+    }
+
+    match *req.method() {
+      Method::HEAD if self.find(&Method::GET).is_some() => {
+        let get = self.find(&Method::GET).unwrap().call(req);
+        Box::pin(async move {
+          let (mut parts, body) = get.await.into_parts();
+          // A length only known once the body is read is left out rather than guessed
+          if let Some(len) = hyper::body::Body::size_hint(&body).exact() {
+            parts
+              .headers
+              .entry(header::CONTENT_LENGTH)
+              .or_insert(len.into());
+          }
+          Response::from_parts(parts, Body::default())
+        })
+      }
+      Method::OPTIONS => {
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        response.headers_mut().insert(header::ALLOW, self.allow());
+        Box::pin(async move { response })
+      }
+      _ => {
+        let mut response = Error::new(
+          StatusCode::METHOD_NOT_ALLOWED,
+          "method_not_allowed",
+          format!("{} is not allowed here", req.method()),
+        )
+        .into_response();
+        response.headers_mut().insert(header::ALLOW, self.allow());
+        Box::pin(async move { response })
+      }
+    }
+  }
+}
+
+/// Paths to handlers, with `:name` and `*` parameters as understood by `path-tree`.
+///
+/// ```ignore
+/// let app = Router::new()
+///   .route("/", get(index))
+///   .route("/hello/:name", get(hello_user))
+///   .nest("/api", api)
+///   .layer(Logger);
+/// app.serve(listener).await?;
+/// ```
+#[derive(Default)]
+pub struct Router {
+  routes: Vec<(String, MethodRouter)>,
+  nested: Vec<(String, BoxEndpoint)>,
+  layers: Vec<Arc<dyn Middleware>>,
+}
+
+impl Router {
+  pub fn new() -> Router {
+    Router::default()
+  }
+
+  /// Routing the same path twice adds to its methods. Panics if a method is routed twice.
+  pub fn route(mut self, path: &str, methods: MethodRouter) -> Router {
+    assert!(path.starts_with('/'), "route {:?} must start with /", path);
+    match self.routes.iter_mut().find(|(p, _)| p == path) {
+      Some((_, existing)) => *existing = std::mem::take(existing).merge(methods),
+      None => self.routes.push((path.to_owned(), methods)),
+    }
+    self
+  }
+
+  /// Serves the routes of `router` under `prefix`, behind the layers of `router` and then those
+  /// of `self`. Paths `router` does not know fall to the 404 of `self`.
+  pub fn nest(mut self, prefix: &str, router: Router) -> Router {
+    assert!(
+      prefix.starts_with('/'),
+      "prefix {:?} must start with /",
+      prefix
+    );
+    let prefix = prefix.trim_end_matches('/');
+    for (path, endpoint) in router.endpoints() {
+      let path = match path.as_str() {
+        "/" if !prefix.is_empty() => prefix.to_owned(),
+        path => format!("{}{}", prefix, path),
+      };
+      self.nested.push((path, endpoint));
+    }
+    self
+  }
+
+  /// Middlewares run in the order they were added, around every request this router gets,
+  /// including those that end up 404.
+  pub fn layer(mut self, middleware: impl Middleware) -> Router {
+    self.layers.push(Arc::new(middleware));
+    self
+  }
+
+  /// Every route as one endpoint behind the layers of this router.
+  fn endpoints(self) -> Vec<(String, BoxEndpoint)> {
+    let layers = self.layers;
+    self
+      .routes
+      .into_iter()
+      .map(|(path, methods)| (path, Arc::new(methods) as BoxEndpoint))
+      .chain(self.nested)
+      .map(|(path, endpoint)| (path, Layered::wrap(&layers, endpoint)))
+      .collect()
+  }
+
+  /// Panics if a path is routed both here and in a nested router.
+  pub fn into_service(self) -> RouterService {
+    let mut tree = PathTree::new();
+    let mut paths = Vec::new();
+    let routes = self
+      .routes
+      .into_iter()
+      .map(|(path, methods)| (path, Arc::new(methods) as BoxEndpoint));
+    for (path, endpoint) in routes.chain(self.nested) {
+      if paths.contains(&path) {
+        panic!("{} is routed twice", path);
+      }
+      _ = tree.insert(&path, endpoint);
+      paths.push(path);
+    }
+
+    RouterService {
+      endpoint: Layered::wrap(&self.layers, Arc::new(Dispatch(tree))),
+    }
+  }
+
+  /// Accepts connections until `listener` fails, serving each over HTTP/1 or HTTP/2.
+  pub async fn serve(self, listener: TcpListener) -> io::Result<()> {
+    let service = self.into_service();
+    loop {
+      let (tcp, _) = listener.accept().await?;
+      let io = TokioIo::new(tcp);
+      let service = service.clone();
+
+      tokio::task::spawn(async move {
+        if let Err(err) = auto::Builder::new(TokioExecutor::new())
+          .serve_connection(io, service)
+          .await
+        {
+          println!("Error serving connection: {:?}", err);
+        }
+      });
+    }
+  }
+}
+
+struct Dispatch(PathTree<BoxEndpoint>);
+
+impl Endpoint for Dispatch {
+  fn call(&self, mut req: Request) -> BoxFuture<'static, Response> {
+    let path = req.uri().path().to_owned();
+    let Some((endpoint, route)) = self.0.find(&path) else {
+      let response = Error::not_found(format!("no route for {}", path)).into_response();
+      return Box::pin(async move { response });
+    };
+
+    let params = route
+      .params()
+      .into_iter()
+      .map(|(k, v)| {
+        percent_decode_str(v)
+          .decode_utf8()
+          .map(|v| (k.to_owned(), v.into_owned()))
+      })
+      .collect::<Result<Vec<_>, _>>();
+    match params {
+      Ok(params) => {
+        req.extensions_mut().insert(Params(params));
+        endpoint.call(req)
+      }
+      Err(_) => {
+        let response = Error::bad_request("path is not UTF-8").into_response();
+        Box::pin(async move { response })
+      }
+    }
+  }
+}
+
+/// A [`Router`] ready to answer requests, cheap to clone.
+#[derive(Clone)]
+pub struct RouterService {
+  endpoint: BoxEndpoint,
+}
+
+impl RouterService {
+  pub fn handle(&self, req: Request) -> BoxFuture<'static, Response> {
+    self.endpoint.call(req)
+  }
+}
+
+impl Service<Request> for RouterService {
+  type Response = Response;
+  type Error = Infallible;
+  type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+  fn call(&self, req: Request) -> Self::Future {
+    let response = self.handle(req);
+    Box::pin(async move { Ok(response.await) })
+  }
+}
@@ -0,0 +1,390 @@
+use std::{io::Read, time::Duration};
+
+use bytes::Bytes;
+use flate2::read::GzDecoder;
+use http_body_util::{BodyExt, Full};
+use hyper::{
+  header::{self, HeaderMap, HeaderValue},
+  Method, StatusCode,
+};
+use hyper_path_tree_example::{
+  get,
+  handler::BoxFuture,
+  middleware::{BodyLimit, Compression, Cors, Timeout},
+  post, put, Extension, Json, Middleware, Next, Path, Query, Request, Response, Router,
+};
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::net::TcpListener;
+
+struct Reply {
+  status: StatusCode,
+  headers: HeaderMap,
+  body: Bytes,
+}
+
+impl Reply {
+  fn text(&self) -> &str {
+    std::str::from_utf8(&self.body).unwrap()
+  }
+
+  fn json(&self) -> Value {
+    serde_json::from_slice(&self.body).unwrap()
+  }
+
+  fn header(&self, name: header::HeaderName) -> &str {
+    self.headers.get(name).unwrap().to_str().unwrap()
+  }
+}
+
+struct App(String);
+
+impl App {
+  async fn serve(router: Router) -> App {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(router.serve(listener));
+    App(format!("http://{}", addr))
+  }
+
+  async fn send(&self, method: Method, path: &str, headers: &[(&str, &str)], body: &str) -> Reply {
+    let mut req = hyper::Request::builder()
+      .method(method)
+      .uri(format!("{}{}", self.0, path));
+    for (name, value) in headers {
+      req = req.header(*name, *value);
+    }
+    let req = req.body(Full::new(Bytes::from(body.to_owned()))).unwrap();
+    let client = Client::builder(TokioExecutor::new()).build_http();
+    let response = client.request(req).await.unwrap();
+    let (parts, body) = response.into_parts();
+    Reply {
+      status: parts.status,
+      headers: parts.headers,
+      body: body.collect().await.unwrap().to_bytes(),
+    }
+  }
+
+  async fn get(&self, path: &str) -> Reply {
+    self.send(Method::GET, path, &[], "").await
+  }
+}
+
+async fn hello() -> &'static str {
+  "hello"
+}
+
+#[tokio::test]
+async fn routes_by_method() {
+  let app = App::serve(
+    Router::new()
+      .route("/item", get(hello).post(|| async { StatusCode::CREATED }))
+      .route("/item", put(|| async { "put" })),
+  )
+  .await;
+
+  let reply = app.get("/item").await;
+  assert_eq!(reply.status, StatusCode::OK);
+  assert_eq!(reply.text(), "hello");
+  assert_eq!(
+    app.send(Method::POST, "/item", &[], "").await.status,
+    StatusCode::CREATED
+  );
+  assert_eq!(app.send(Method::PUT, "/item", &[], "").await.text(), "put");
+
+  let reply = app.send(Method::DELETE, "/item", &[], "").await;
+  assert_eq!(reply.status, StatusCode::METHOD_NOT_ALLOWED);
+  assert_eq!(reply.header(header::ALLOW), "GET, POST, PUT, HEAD, OPTIONS");
+  assert_eq!(reply.json()["error"]["code"], "method_not_allowed");
+
+  let reply = app.send(Method::HEAD, "/item", &[], "").await;
+  assert_eq!(reply.status, StatusCode::OK);
+  assert_eq!(reply.header(header::CONTENT_LENGTH), "5");
+  assert!(reply.body.is_empty());
+
+  let reply = app.send(Method::OPTIONS, "/item", &[], "").await;
+  assert_eq!(reply.status, StatusCode::NO_CONTENT);
+  assert_eq!(reply.header(header::ALLOW), "GET, POST, PUT, HEAD, OPTIONS");
+
+  let reply = app.get("/nowhere").await;
+  assert_eq!(reply.status, StatusCode::NOT_FOUND);
+  assert_eq!(reply.json()["error"]["status"], 404);
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Item {
+  user: String,
+  id: u32,
+}
+
+#[derive(Deserialize)]
+struct Search {
+  q: String,
+  limit: Option<u32>,
+}
+
+#[tokio::test]
+async fn extracts_path_query_and_json() {
+  let app = App::serve(
+    Router::new()
+      .route(
+        "/users/:user/items/:id",
+        get(|Path(item): Path<Item>| async move { format!("{} {}", item.user, item.id) }),
+      )
+      .route(
+        "/search",
+        get(
+          |Query(search): Query<Search>| async move { format!("{} {:?}", search.q, search.limit) },
+        ),
+      )
+      .route(
+        "/items",
+        post(|Json(item): Json<Item>| async move { Json(item) }),
+      )
+      .nest(
+        "/small",
+        Router::new()
+          .route("/", post(|body: String| async move { body }))
+          .layer(BodyLimit::new(4)),
+      ),
+  )
+  .await;
+
+  assert_eq!(app.get("/users/ann%20b/items/7").await.text(), "ann b 7");
+  let reply = app.get("/users/ann/items/seven").await;
+  assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+  assert_eq!(reply.json()["error"]["code"], "invalid_path");
+
+  assert_eq!(
+    app.get("/search?q=rust&limit=3").await.text(),
+    "rust Some(3)"
+  );
+  assert_eq!(app.get("/search?q=rust").await.text(), "rust None");
+  let reply = app.get("/search?limit=3").await;
+  assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+  assert_eq!(reply.json()["error"]["code"], "invalid_query");
+
+  let json = [("content-type", "application/json")];
+  let reply = app
+    .send(Method::POST, "/items", &json, r#"{"user":"ann","id":1}"#)
+    .await;
+  assert_eq!(reply.status, StatusCode::OK);
+  assert_eq!(reply.header(header::CONTENT_TYPE), "application/json");
+  assert_eq!(reply.json()["user"], "ann");
+
+  let reply = app
+    .send(Method::POST, "/items", &[], r#"{"user":"ann","id":1}"#)
+    .await;
+  assert_eq!(reply.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+  let reply = app
+    .send(Method::POST, "/items", &json, r#"{"user":"ann""#)
+    .await;
+  assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+  let reply = app
+    .send(Method::POST, "/items", &json, r#"{"user":1}"#)
+    .await;
+  assert_eq!(reply.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+  assert_eq!(
+    app.send(Method::POST, "/small", &[], "1234").await.text(),
+    "1234"
+  );
+  let reply = app.send(Method::POST, "/small", &[], "12345").await;
+  assert_eq!(reply.status, StatusCode::PAYLOAD_TOO_LARGE);
+  assert_eq!(reply.json()["error"]["code"], "payload_too_large");
+}
+
+#[tokio::test]
+async fn answers_cors_preflight() {
+  let app = App::serve(
+    Router::new().route("/api", get(hello)).layer(
+      Cors::new()
+        .allow_origin("http://good.example")
+        .allow_methods([Method::GET, Method::PUT])
+        .allow_headers([header::CONTENT_TYPE])
+        .max_age(Duration::from_secs(60)),
+    ),
+  )
+  .await;
+
+  let preflight = |origin| [("origin", origin), ("access-control-request-method", "PUT")];
+  let reply = app
+    .send(
+      Method::OPTIONS,
+      "/api",
+      &preflight("http://good.example"),
+      "",
+    )
+    .await;
+  assert_eq!(reply.status, StatusCode::NO_CONTENT);
+  assert_eq!(
+    reply.header(header::ACCESS_CONTROL_ALLOW_ORIGIN),
+    "http://good.example"
+  );
+  assert_eq!(
+    reply.header(header::ACCESS_CONTROL_ALLOW_METHODS),
+    "GET, PUT"
+  );
+  assert_eq!(
+    reply.header(header::ACCESS_CONTROL_ALLOW_HEADERS),
+    "content-type"
+  );
+  assert_eq!(reply.header(header::ACCESS_CONTROL_MAX_AGE), "60");
+
+  let reply = app
+    .send(
+      Method::OPTIONS,
+      "/api",
+      &preflight("http://bad.example"),
+      "",
+    )
+    .await;
+  assert_eq!(reply.status, StatusCode::FORBIDDEN);
+
+  let reply = app
+    .send(
+      Method::GET,
+      "/api",
+      &[("origin", "http://good.example")],
+      "",
+    )
+    .await;
+  assert_eq!(reply.text(), "hello");
+  assert_eq!(
+    reply.header(header::ACCESS_CONTROL_ALLOW_ORIGIN),
+    "http://good.example"
+  );
+  let reply = app
+    .send(Method::GET, "/api", &[("origin", "http://bad.example")], "")
+    .await;
+  assert_eq!(reply.text(), "hello");
+  assert!(!reply
+    .headers
+    .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+}
+
+#[tokio::test]
+async fn gzips_large_text() {
+  let app = App::serve(
+    Router::new()
+      .route("/large", get(|| async { "hello ".repeat(100) }))
+      .route("/small", get(hello))
+      .route("/binary", get(|| async { vec![0u8; 1000] }))
+      .layer(Compression::new()),
+  )
+  .await;
+
+  let gzip = [("accept-encoding", "br, gzip")];
+  let reply = app.send(Method::GET, "/large", &gzip, "").await;
+  assert_eq!(reply.header(header::CONTENT_ENCODING), "gzip");
+  assert_eq!(reply.header(header::VARY), "accept-encoding");
+  let mut text = String::new();
+  GzDecoder::new(&reply.body[..])
+    .read_to_string(&mut text)
+    .unwrap();
+  assert_eq!(text, "hello ".repeat(100));
+  let reply = app
+    .send(Method::GET, "/large", &[("accept-encoding", "br, *")], "")
+    .await;
+  assert_eq!(reply.header(header::CONTENT_ENCODING), "gzip");
+
+  for (path, headers) in [
+    ("/large", &[][..]),
+    ("/large", &[("accept-encoding", "gzip;q=0")][..]),
+    ("/large", &[("accept-encoding", "gzip;q=0, *")][..]),
+    ("/large", &[("accept-encoding", "*;q=0")][..]),
+    ("/small", &gzip[..]),
+    ("/binary", &gzip[..]),
+  ] {
+    let reply = app.send(Method::GET, path, headers, "").await;
+    assert!(
+      !reply.headers.contains_key(header::CONTENT_ENCODING),
+      "{} {:?}",
+      path,
+      headers
+    );
+  }
+}
+
+#[tokio::test]
+async fn times_out_slow_handlers() {
+  let app = App::serve(
+    Router::new()
+      .route(
+        "/slow",
+        get(|| async {
+          tokio::time::sleep(Duration::from_secs(5)).await;
+          "late"
+        }),
+      )
+      .route("/fast", get(hello))
+      .layer(Timeout(Duration::from_millis(50))),
+  )
+  .await;
+
+  let reply = app.get("/slow").await;
+  assert_eq!(reply.status, StatusCode::SERVICE_UNAVAILABLE);
+  assert_eq!(reply.json()["error"]["code"], "timeout");
+  assert_eq!(app.get("/fast").await.text(), "hello");
+}
+
+/// Adds its name to `x-trace` on the way in.
+struct Trace(&'static str);
+
+impl Middleware for Trace {
+  fn call<'a>(&'a self, mut req: Request, next: Next<'a>) -> BoxFuture<'a, Response> {
+    req
+      .headers_mut()
+      .append("x-trace", HeaderValue::from_static(self.0));
+    req.extensions_mut().insert(self.0);
+    next.run(req)
+  }
+}
+
+async fn trace(headers: HeaderMap) -> String {
+  headers
+    .get_all("x-trace")
+    .iter()
+    .map(|v| v.to_str().unwrap())
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+#[tokio::test]
+async fn nests_routers_behind_layers() {
+  let inner = Router::new()
+    .route("/", get(trace))
+    .route("/trace", get(trace))
+    .route(
+      "/last",
+      get(|Extension(name): Extension<&'static str>| async move { name }),
+    )
+    .layer(Trace("inner"));
+  let app = App::serve(
+    Router::new()
+      .route("/trace", get(trace))
+      .nest("/v1/", Router::new().nest("/inner", inner))
+      .layer(Trace("first"))
+      .layer(Trace("second")),
+  )
+  .await;
+
+  assert_eq!(app.get("/trace").await.text(), "first,second");
+  assert_eq!(app.get("/v1/inner").await.text(), "first,second,inner");
+  assert_eq!(
+    app.get("/v1/inner/trace").await.text(),
+    "first,second,inner"
+  );
+  assert_eq!(app.get("/v1/inner/last").await.text(), "inner");
+  assert_eq!(
+    app.get("/v1/inner/missing").await.status,
+    StatusCode::NOT_FOUND
+  );
+}
+
+#[test]
+#[should_panic(expected = "already routed")]
+fn refuses_a_method_twice() {
+  let _ = Router::new().route("/", get(hello)).route("/", get(hello));
+}
@@ -0,0 +1,177 @@
+//! Relay cursor connections over rows ordered by id: `first` rows `after` a cursor, or `last`
+//! rows `before` one.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use juniper::{FieldError, FieldResult, GraphQLObject};
+
+/// Rows in a page when neither `first` nor `last` is given.
+pub const DEFAULT_PAGE: i64 = 20;
+pub const MAX_PAGE: i64 = 100;
+
+/// Which rows to read: at most `limit` between the ids `after` and `before`, from the end when
+/// `backward`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Page {
+  pub after: Option<i64>,
+  pub before: Option<i64>,
+  pub limit: i64,
+  pub backward: bool,
+}
+
+impl Page {
+  pub fn new(
+    first: Option<i32>,
+    after: Option<String>,
+    last: Option<i32>,
+    before: Option<String>,
+  ) -> FieldResult<Page> {
+    let (limit, backward) = match (first, last) {
+      (Some(_), Some(_)) => return Err("`first` and `last` cannot be used together".into()),
+      (Some(n), None) => (n, false),
+      (None, Some(n)) => (n, true),
+      (None, None) => (DEFAULT_PAGE as i32, false),
+    };
+    if !(0 ..= MAX_PAGE as i32).contains(&limit) {
+      return Err(format!("pages hold from 0 to {MAX_PAGE} items").into());
+    }
+    Ok(Page {
+      after: after.as_deref().map(decode).transpose()?,
+      before: before.as_deref().map(decode).transpose()?,
+      limit: limit as i64,
+      backward,
+    })
+  }
+
+  pub(crate) fn order(&self) -> &'static str {
+    if self.backward {
+      "DESC"
+    } else {
+      "ASC"
+    }
+  }
+}
+
+pub fn cursor(id: i64) -> String {
+  URL_SAFE_NO_PAD.encode(format!("cursor:{id}"))
+}
+
+fn decode(cursor: &str) -> FieldResult<i64> {
+  URL_SAFE_NO_PAD
+    .decode(cursor)
+    .ok()
+    .and_then(|bytes| String::from_utf8(bytes).ok())
+    .and_then(|text| text.strip_prefix("cursor:")?.parse().ok())
+    .ok_or_else(|| FieldError::from(format!("invalid cursor {cursor:?}")))
+}
+
+/// Rows to identify by cursor.
+pub trait Node {
+  fn id(&self) -> i64;
+}
+
+/// A page of rows, in id order, read with one more row than asked to tell whether there are
+/// more.
+#[derive(Debug, Clone)]
+pub struct Slice<T> {
+  pub items: Vec<T>,
+  pub has_more: bool,
+  pub page: Page,
+}
+
+impl<T> Slice<T> {
+  /// From rows read in the order of `page`.
+  pub fn new(mut rows: Vec<T>, page: Page) -> Slice<T> {
+    let has_more = rows.len() as i64 > page.limit;
+    rows.truncate(page.limit as usize);
+    if page.backward {
+      rows.reverse();
+    }
+    Slice {
+      items: rows,
+      has_more,
+      page,
+    }
+  }
+}
+
+impl<T: Node> Slice<T> {
+  /// Whether there are rows on the other side of the cursor given is not looked for, as the
+  /// specification allows.
+  pub fn page_info(&self) -> PageInfo {
+    PageInfo {
+      has_next_page: !self.page.backward && self.has_more,
+      has_previous_page: self.page.backward && self.has_more,
+      start_cursor: self.items.first().map(|n| cursor(n.id())),
+      end_cursor: self.items.last().map(|n| cursor(n.id())),
+    }
+  }
+}
+
+#[derive(Debug, Clone, GraphQLObject)]
+pub struct PageInfo {
+  pub has_next_page: bool,
+  pub has_previous_page: bool,
+  pub start_cursor: Option<String>,
+  pub end_cursor: Option<String>,
+}
+
+/// Declares the connection and edge objects of a node type.
+macro_rules! connection {
+  ($connection:ident, $edge:ident, $node:ty) => {
+    pub struct $connection(pub $crate::connection::Slice<$node>);
+
+    #[juniper::graphql_object(context = Context)]
+    impl $connection {
+      fn edges(&self) -> Vec<$edge> {
+        self.0.items.iter().cloned().map($edge).collect()
+      }
+
+      /// The nodes of the edges, for clients that need no cursor.
+      fn nodes(&self) -> &[$node] {
+        &self.0.items
+      }
+
+      fn page_info(&self) -> $crate::connection::PageInfo {
+        self.0.page_info()
+      }
+    }
+
+    pub struct $edge(pub $node);
+
+    #[juniper::graphql_object(context = Context)]
+    impl $edge {
+      fn cursor(&self) -> String {
+        $crate::connection::cursor($crate::connection::Node::id(&self.0))
+      }
+
+      fn node(&self) -> &$node {
+        &self.0
+      }
+    }
+  };
+}
+
+pub(crate) use connection;
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn cursors_round_trip() {
+    assert_eq!(decode(&cursor(42)).unwrap(), 42);
+    assert!(decode("42").is_err());
+  }
+
+  #[test]
+  fn pages_are_bounded() {
+    let page = Page::new(None, None, Some(3), Some(cursor(10))).unwrap();
+    assert_eq!(
+      (page.limit, page.backward, page.before),
+      (3, true, Some(10))
+    );
+    assert!(Page::new(Some(1), None, Some(1), None).is_err());
+    assert!(Page::new(Some(MAX_PAGE as i32 + 1), None, None, None).is_err());
+    assert!(Page::new(Some(-1), None, None, None).is_err());
+  }
+}
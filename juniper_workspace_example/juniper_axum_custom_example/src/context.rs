@@ -0,0 +1,105 @@
+use dataloader::cached::Loader;
+use juniper::{FieldError, FieldResult};
+
+use crate::{
+  connection::{Page, Slice},
+  db::{Author, Book, Database, Review, Session},
+  loaders::{AuthorsById, BooksById, BooksOfAuthors, ReviewsOfBooks},
+};
+
+/// What resolvers share for one request, or one subscription connection: its session and
+/// the loaders batching and caching its reads.
+#[derive(Clone)]
+pub struct Context {
+  session: Session,
+  authors: Loader<i64, FieldResult<Option<Author>>, AuthorsById>,
+  books: Loader<i64, FieldResult<Option<Book>>, BooksById>,
+  books_of_authors: Loader<(i64, Page), FieldResult<Slice<Book>>, BooksOfAuthors>,
+  reviews_of_books: Loader<i64, FieldResult<Vec<Review>>, ReviewsOfBooks>,
+}
+
+impl juniper::Context for Context {}
+
+/// A key missing from its batch: the batch functions give every key a value.
+fn unloaded(e: std::io::Error) -> FieldError {
+  tracing::error!("{}", e);
+  FieldError::from("cannot load")
+}
+
+impl Context {
+  pub fn new(database: &Database) -> Context {
+    let session = database.session();
+    Context {
+      authors: Loader::new(AuthorsById(session.clone())),
+      books: Loader::new(BooksById(session.clone())),
+      books_of_authors: Loader::new(BooksOfAuthors(session.clone())),
+      reviews_of_books: Loader::new(ReviewsOfBooks(session.clone())),
+      session,
+    }
+  }
+
+  pub fn session(&self) -> &Session {
+    &self.session
+  }
+
+  /// How many SQL queries were made for the request so far.
+  pub fn queries(&self) -> usize {
+    self.session.queries()
+  }
+
+  /// Drops what was loaded, for connections living longer than the data stays the same.
+  pub async fn forget(&self) {
+    self.authors.clear_all().await;
+    self.books.clear_all().await;
+    self.books_of_authors.clear_all().await;
+    self.reviews_of_books.clear_all().await;
+  }
+
+  pub async fn author(&self, id: i64) -> FieldResult<Option<Author>> {
+    self.authors.try_load(id).await.map_err(unloaded)?
+  }
+
+  pub async fn book(&self, id: i64) -> FieldResult<Option<Book>> {
+    self.books.try_load(id).await.map_err(unloaded)?
+  }
+
+  pub async fn authors_page(&self, page: Page) -> FieldResult<Slice<Author>> {
+    let authors = self.session.authors(page).await?;
+    self
+      .authors
+      .prime_many(authors.items.iter().map(|a| (a.id, Ok(Some(a.clone())))))
+      .await;
+    Ok(authors)
+  }
+
+  pub async fn books_page(&self, page: Page) -> FieldResult<Slice<Book>> {
+    let books = self.session.books(page).await?;
+    self.prime_books(&books).await;
+    Ok(books)
+  }
+
+  pub async fn books_of_author(&self, author_id: i64, page: Page) -> FieldResult<Slice<Book>> {
+    let books = self
+      .books_of_authors
+      .try_load((author_id, page))
+      .await
+      .map_err(unloaded)??;
+    self.prime_books(&books).await;
+    Ok(books)
+  }
+
+  pub async fn reviews_of_book(&self, book_id: i64) -> FieldResult<Vec<Review>> {
+    self
+      .reviews_of_books
+      .try_load(book_id)
+      .await
+      .map_err(unloaded)?
+  }
+
+  async fn prime_books(&self, books: &Slice<Book>) {
+    self
+      .books
+      .prime_many(books.items.iter().map(|b| (b.id, Ok(Some(b.clone())))))
+      .await;
+  }
+}
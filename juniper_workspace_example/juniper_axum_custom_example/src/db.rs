@@ -0,0 +1,491 @@
+//! The library database: authors, their books and the reviews of those, in SQLite.
+//!
+//! Triggers record every write in a `changes` table, which is tailed into a broadcast channel
+//! for subscriptions, so that writes made by other processes are seen too.
+
+use std::{
+  collections::HashMap,
+  path::Path,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+
+use juniper::{FieldError, GraphQLEnum, IntoFieldError, ScalarValue};
+use sqlx::{
+  sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+  QueryBuilder, Sqlite, SqlitePool,
+};
+use tokio::sync::{broadcast, Notify};
+
+use crate::connection::{Page, Slice};
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS authors (
+  id INTEGER PRIMARY KEY,
+  name TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS books (
+  id INTEGER PRIMARY KEY,
+  author_id INTEGER NOT NULL REFERENCES authors (id) ON DELETE CASCADE,
+  title TEXT NOT NULL,
+  year INTEGER
+);
+CREATE INDEX IF NOT EXISTS books_by_author ON books (author_id, id);
+CREATE TABLE IF NOT EXISTS reviews (
+  id INTEGER PRIMARY KEY,
+  book_id INTEGER NOT NULL REFERENCES books (id) ON DELETE CASCADE,
+  rating INTEGER NOT NULL CHECK (rating BETWEEN 1 AND 5),
+  body TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS reviews_by_book ON reviews (book_id, id);
+CREATE TABLE IF NOT EXISTS persisted_queries (
+  hash TEXT PRIMARY KEY,
+  query TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS changes (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  entity TEXT NOT NULL,
+  row_id INTEGER NOT NULL,
+  op TEXT NOT NULL
+);
+"#;
+
+const TABLES: [(&str, &str); 3] = [
+  ("authors", "author"),
+  ("books", "book"),
+  ("reviews", "review"),
+];
+
+/// How often to look for changes written by other processes.
+const POLL: Duration = Duration::from_secs(1);
+
+/// How many changes to keep in the table once they were broadcast.
+const KEEP_CHANGES: i64 = 10_000;
+
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct Error(#[from] sqlx::Error);
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+  pub fn is_foreign_key_violation(&self) -> bool {
+    self
+      .0
+      .as_database_error()
+      .is_some_and(|e| e.is_foreign_key_violation())
+  }
+}
+
+impl<S: ScalarValue> IntoFieldError<S> for Error {
+  fn into_field_error(self) -> FieldError<S> {
+    tracing::error!("database error: {}", self);
+    FieldError::from("database error")
+  }
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Author {
+  pub id: i64,
+  pub name: String,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Book {
+  pub id: i64,
+  pub author_id: i64,
+  pub title: String,
+  pub year: Option<i32>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Review {
+  pub id: i64,
+  pub book_id: i64,
+  pub rating: i32,
+  pub body: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, GraphQLEnum)]
+pub enum Entity {
+  Author,
+  Book,
+  Review,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, GraphQLEnum)]
+pub enum Op {
+  Insert,
+  Update,
+  Delete,
+}
+
+/// A row written, as recorded by the triggers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Change {
+  pub entity: Entity,
+  pub id: i64,
+  pub op: Op,
+}
+
+impl Change {
+  fn parse(entity: &str, id: i64, op: &str) -> Option<Change> {
+    let entity = match entity {
+      "author" => Entity::Author,
+      "book" => Entity::Book,
+      "review" => Entity::Review,
+      _ => return None,
+    };
+    let op = match op {
+      "insert" => Op::Insert,
+      "update" => Op::Update,
+      "delete" => Op::Delete,
+      _ => return None,
+    };
+    Some(Change { entity, id, op })
+  }
+}
+
+#[derive(Clone)]
+pub struct Database {
+  pool: SqlitePool,
+  changes: broadcast::Sender<Change>,
+  written: Arc<Notify>,
+}
+
+impl Database {
+  pub async fn open(path: impl AsRef<Path>) -> Result<Database> {
+    let options = SqliteConnectOptions::new()
+      .filename(path)
+      .create_if_missing(true);
+    Database::with_pool(SqlitePool::connect_with(options).await?).await
+  }
+
+  /// A database gone with the process, on a single connection that is never closed.
+  pub async fn memory() -> Result<Database> {
+    let pool = SqlitePoolOptions::new()
+      .max_connections(1)
+      .idle_timeout(None)
+      .max_lifetime(None)
+      .connect_with(SqliteConnectOptions::new().in_memory(true))
+      .await?;
+    Database::with_pool(pool).await
+  }
+
+  async fn with_pool(pool: SqlitePool) -> Result<Database> {
+    sqlx::raw_sql(SCHEMA).execute(&pool).await?;
+    for (table, entity) in TABLES {
+      for (op, row) in [("insert", "NEW"), ("update", "NEW"), ("delete", "OLD")] {
+        sqlx::raw_sql(&format!(
+          "CREATE TRIGGER IF NOT EXISTS {table}_{op} AFTER {op} ON {table} BEGIN INSERT INTO \
+           changes (entity, row_id, op) VALUES ('{entity}', {row}.id, '{op}'); END"
+        ))
+        .execute(&pool)
+        .await?;
+      }
+    }
+
+    let (changes, _) = broadcast::channel(1024);
+    let database = Database {
+      pool,
+      changes,
+      written: Arc::new(Notify::new()),
+    };
+    let seen = sqlx::query_scalar("SELECT coalesce(max(id), 0) FROM changes")
+      .fetch_one(&database.pool)
+      .await?;
+    tokio::spawn(database.clone().tail(seen));
+    Ok(database)
+  }
+
+  /// Broadcasts the changes recorded after `seen`, as soon as this process writes or every
+  /// [`POLL`] for other writers.
+  async fn tail(self, mut seen: i64) {
+    loop {
+      tokio::select! {
+        _ = self.written.notified() => {}
+        _ = tokio::time::sleep(POLL) => {}
+      }
+      let rows = sqlx::query_as::<_, (i64, String, i64, String)>(
+        "SELECT id, entity, row_id, op FROM changes WHERE id > ? ORDER BY id",
+      )
+      .bind(seen)
+      .fetch_all(&self.pool)
+      .await;
+      let rows = match rows {
+        Ok(rows) if rows.is_empty() => continue,
+        Ok(rows) => rows,
+        Err(e) => {
+          tracing::warn!("cannot read changes: {}", e);
+          continue;
+        }
+      };
+      for (id, entity, row_id, op) in rows {
+        seen = id;
+        if let Some(change) = Change::parse(&entity, row_id, &op) {
+          // Nobody listening is fine
+          let _ = self.changes.send(change);
+        }
+      }
+      if let Err(e) = sqlx::query("DELETE FROM changes WHERE id <= ?")
+        .bind(seen - KEEP_CHANGES)
+        .execute(&self.pool)
+        .await
+      {
+        tracing::warn!("cannot prune changes: {}", e);
+      }
+    }
+  }
+
+  pub fn subscribe(&self) -> broadcast::Receiver<Change> {
+    self.changes.subscribe()
+  }
+
+  /// A handle for one request, counting its queries.
+  pub fn session(&self) -> Session {
+    Session {
+      database: self.clone(),
+      queries: Arc::default(),
+    }
+  }
+
+  pub async fn persisted_query(&self, hash: &str) -> Result<Option<String>> {
+    Ok(
+      sqlx::query_scalar("SELECT query FROM persisted_queries WHERE hash = ?")
+        .bind(hash)
+        .fetch_optional(&self.pool)
+        .await?,
+    )
+  }
+
+  /// Whether `query` was added, not already there nor over `max` queries.
+  pub async fn persist_query(&self, hash: &str, query: &str, max: i64) -> Result<bool> {
+    let done = sqlx::query(
+      "INSERT OR IGNORE INTO persisted_queries (hash, query) SELECT ?, ? WHERE (SELECT count(*) \
+       FROM persisted_queries) < ?",
+    )
+    .bind(hash)
+    .bind(query)
+    .bind(max)
+    .execute(&self.pool)
+    .await?;
+    Ok(done.rows_affected() > 0)
+  }
+
+  /// Fills an empty database with `authors` authors of `books` books with `reviews` reviews
+  /// each.
+  pub async fn seed(&self, authors: usize, books: usize, reviews: usize) -> Result<()> {
+    let mut tx = self.pool.begin().await?;
+    let (count,): (i64,) = sqlx::query_as("SELECT count(*) FROM authors")
+      .fetch_one(&mut *tx)
+      .await?;
+    if count > 0 {
+      return Ok(());
+    }
+    for a in 1 ..= authors {
+      let (author_id,): (i64,) =
+        sqlx::query_as("INSERT INTO authors (name) VALUES (?) RETURNING id")
+          .bind(format!("Author {a}"))
+          .fetch_one(&mut *tx)
+          .await?;
+      for b in 1 ..= books {
+        let (book_id,): (i64,) = sqlx::query_as(
+          "INSERT INTO books (author_id, title, year) VALUES (?, ?, ?) RETURNING id",
+        )
+        .bind(author_id)
+        .bind(format!("Book {b} by author {a}"))
+        .bind(1950 + (a * books + b) as i32)
+        .fetch_one(&mut *tx)
+        .await?;
+        for r in 1 ..= reviews {
+          sqlx::query("INSERT INTO reviews (book_id, rating, body) VALUES (?, ?, ?)")
+            .bind(book_id)
+            .bind((r % 5 + 1) as i32)
+            .bind(format!("Review {r}"))
+            .execute(&mut *tx)
+            .await?;
+        }
+      }
+    }
+    tx.commit().await?;
+    self.written.notify_one();
+    Ok(())
+  }
+}
+
+/// The database as seen by one GraphQL request: every query made through it is counted.
+#[derive(Clone)]
+pub struct Session {
+  database: Database,
+  queries: Arc<AtomicUsize>,
+}
+
+impl Session {
+  /// How many queries were made so far.
+  pub fn queries(&self) -> usize {
+    self.queries.load(Ordering::Relaxed)
+  }
+
+  pub fn database(&self) -> &Database {
+    &self.database
+  }
+
+  fn pool(&self) -> &SqlitePool {
+    self.queries.fetch_add(1, Ordering::Relaxed);
+    &self.database.pool
+  }
+
+  fn written(&self) {
+    self.database.written.notify_one();
+  }
+
+  pub async fn authors_by_id(&self, ids: &[i64]) -> Result<Vec<Author>> {
+    let mut query = QueryBuilder::new("SELECT id, name FROM authors WHERE id IN ");
+    push_ids(&mut query, ids);
+    Ok(query.build_query_as().fetch_all(self.pool()).await?)
+  }
+
+  pub async fn books_by_id(&self, ids: &[i64]) -> Result<Vec<Book>> {
+    let mut query = QueryBuilder::new("SELECT id, author_id, title, year FROM books WHERE id IN ");
+    push_ids(&mut query, ids);
+    Ok(query.build_query_as().fetch_all(self.pool()).await?)
+  }
+
+  pub async fn reviews_by_id(&self, ids: &[i64]) -> Result<Vec<Review>> {
+    let mut query = QueryBuilder::new("SELECT id, book_id, rating, body FROM reviews WHERE id IN ");
+    push_ids(&mut query, ids);
+    Ok(query.build_query_as().fetch_all(self.pool()).await?)
+  }
+
+  pub async fn authors(&self, page: Page) -> Result<Slice<Author>> {
+    let mut query = QueryBuilder::new("SELECT id, name FROM authors WHERE true");
+    push_page(&mut query, page);
+    let rows = query.build_query_as().fetch_all(self.pool()).await?;
+    Ok(Slice::new(rows, page))
+  }
+
+  pub async fn books(&self, page: Page) -> Result<Slice<Book>> {
+    let mut query = QueryBuilder::new("SELECT id, author_id, title, year FROM books WHERE true");
+    push_page(&mut query, page);
+    let rows = query.build_query_as().fetch_all(self.pool()).await?;
+    Ok(Slice::new(rows, page))
+  }
+
+  /// The same page of the books of each author, in one query.
+  pub async fn books_of_authors(
+    &self,
+    author_ids: &[i64],
+    page: Page,
+  ) -> Result<HashMap<i64, Slice<Book>>> {
+    let order = page.order();
+    let mut query = QueryBuilder::new(format!(
+      "SELECT id, author_id, title, year FROM (SELECT *, row_number() OVER (PARTITION BY \
+       author_id ORDER BY id {order}) AS n FROM books WHERE author_id IN "
+    ));
+    push_ids(&mut query, author_ids);
+    push_bounds(&mut query, page);
+    query
+      .push(") WHERE n <= ")
+      .push_bind(page.limit + 1)
+      .push(format!(" ORDER BY id {order}"));
+    let rows: Vec<Book> = query.build_query_as().fetch_all(self.pool()).await?;
+
+    let mut books = author_ids
+      .iter()
+      .map(|id| (*id, Vec::new()))
+      .collect::<HashMap<_, _>>();
+    for book in rows {
+      books.entry(book.author_id).or_default().push(book);
+    }
+    Ok(
+      books
+        .into_iter()
+        .map(|(id, rows)| (id, Slice::new(rows, page)))
+        .collect(),
+    )
+  }
+
+  pub async fn reviews_of_books(&self, book_ids: &[i64]) -> Result<Vec<Review>> {
+    let mut query =
+      QueryBuilder::new("SELECT id, book_id, rating, body FROM reviews WHERE book_id IN ");
+    push_ids(&mut query, book_ids);
+    query.push(" ORDER BY id");
+    Ok(query.build_query_as().fetch_all(self.pool()).await?)
+  }
+
+  pub async fn create_author(&self, name: &str) -> Result<Author> {
+    let author = sqlx::query_as("INSERT INTO authors (name) VALUES (?) RETURNING id, name")
+      .bind(name)
+      .fetch_one(self.pool())
+      .await?;
+    self.written();
+    Ok(author)
+  }
+
+  pub async fn create_book(&self, author_id: i64, title: &str, year: Option<i32>) -> Result<Book> {
+    let book = sqlx::query_as(
+      "INSERT INTO books (author_id, title, year) VALUES (?, ?, ?) RETURNING id, author_id, \
+       title, year",
+    )
+    .bind(author_id)
+    .bind(title)
+    .bind(year)
+    .fetch_one(self.pool())
+    .await?;
+    self.written();
+    Ok(book)
+  }
+
+  pub async fn add_review(&self, book_id: i64, rating: i32, body: &str) -> Result<Review> {
+    let review = sqlx::query_as(
+      "INSERT INTO reviews (book_id, rating, body) VALUES (?, ?, ?) RETURNING id, book_id, \
+       rating, body",
+    )
+    .bind(book_id)
+    .bind(rating)
+    .bind(body)
+    .fetch_one(self.pool())
+    .await?;
+    self.written();
+    Ok(review)
+  }
+
+  /// Deletes a book and its reviews. False if there was no such book.
+  pub async fn delete_book(&self, id: i64) -> Result<bool> {
+    let deleted = sqlx::query("DELETE FROM books WHERE id = ?")
+      .bind(id)
+      .execute(self.pool())
+      .await?
+      .rows_affected();
+    self.written();
+    Ok(deleted > 0)
+  }
+}
+
+fn push_ids(query: &mut QueryBuilder<Sqlite>, ids: &[i64]) {
+  query.push("(");
+  let mut list = query.separated(", ");
+  for id in ids {
+    list.push_bind(*id);
+  }
+  list.push_unseparated(")");
+}
+
+fn push_bounds(query: &mut QueryBuilder<Sqlite>, page: Page) {
+  query
+    .push(" AND id > ")
+    .push_bind(page.after.unwrap_or(i64::MIN))
+    .push(" AND id < ")
+    .push_bind(page.before.unwrap_or(i64::MAX));
+}
+
+/// Bounds, orders and limits to one more row than the page, to tell whether there are more.
+fn push_page(query: &mut QueryBuilder<Sqlite>, page: Page) {
+  push_bounds(query, page);
+  query
+    .push(format!(" ORDER BY id {} LIMIT ", page.order()))
+    .push_bind(page.limit + 1);
+}
@@ -0,0 +1,277 @@
+//! The custom [`Handler`]s: GraphQL over GET and POST with persisted queries and limits, and
+//! subscriptions over websockets.
+//!
+//! [`Handler`]: axum::handler::Handler
+
+use std::sync::Arc;
+
+use axum::{
+  async_trait,
+  extract::{FromRequest, Query, Request, WebSocketUpgrade},
+  http::{header, HeaderValue, Method, StatusCode},
+  response::{IntoResponse, Response},
+  Extension, Json,
+};
+use futures::{future, FutureExt};
+use juniper::{
+  http::{GraphQLRequest, GraphQLResponse},
+  FieldError, InputValue, IntoFieldError,
+};
+use juniper_graphql_ws::ConnectionConfig;
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{
+  context::Context,
+  db::Database,
+  limits::{Cost, Limits},
+  persisted::PersistedQueries,
+  schema::Schema,
+  ws,
+};
+
+/// The header telling how many SQL queries a request made.
+pub const SQL_QUERIES: &str = "x-sql-queries";
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Extensions {
+  #[serde(rename = "persistedQuery")]
+  persisted_query: Option<PersistedQuery>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PersistedQuery {
+  version: u32,
+  #[serde(rename = "sha256Hash")]
+  sha256_hash: String,
+}
+
+/// A GraphQL request whose query may be persisted instead of given.
+#[derive(Debug, Deserialize)]
+pub struct Payload {
+  query: Option<String>,
+  #[serde(rename = "operationName")]
+  operation_name: Option<String>,
+  variables: Option<InputValue>,
+  #[serde(default)]
+  extensions: Extensions,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Batch {
+  Single(Payload),
+  Many(Vec<Payload>),
+}
+
+/// The parameters of a GET request, `variables` and `extensions` in JSON.
+#[derive(Debug, Deserialize)]
+struct GetParams {
+  query: Option<String>,
+  #[serde(rename = "operationName")]
+  operation_name: Option<String>,
+  variables: Option<String>,
+  extensions: Option<String>,
+}
+
+fn json<T: DeserializeOwned>(text: Option<String>) -> serde_json::Result<Option<T>> {
+  text.map(|t| serde_json::from_str(&t)).transpose()
+}
+
+/// Extracts a [`Batch`] from the parameters of a GET request, or from the body of a POST one
+/// in `application/json`, or with the bare query in `application/graphql`.
+pub struct GraphQLPayload(pub Batch);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequest<S> for GraphQLPayload {
+  type Rejection = (StatusCode, String);
+
+  async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+    let bad_request = |e: &dyn std::fmt::Display| (StatusCode::BAD_REQUEST, e.to_string());
+    match *request.method() {
+      Method::GET => {
+        let Query(params) = Query::<GetParams>::from_request(request, state)
+          .await
+          .map_err(|e| bad_request(&e))?;
+        Ok(GraphQLPayload(Batch::Single(Payload {
+          query: params.query,
+          operation_name: params.operation_name,
+          variables: json(params.variables).map_err(|e| bad_request(&e))?,
+          extensions: json(params.extensions)
+            .map_err(|e| bad_request(&e))?
+            .unwrap_or_default(),
+        })))
+      }
+      Method::POST => {
+        let content_type = request
+          .headers()
+          .get(header::CONTENT_TYPE)
+          .and_then(|v| v.to_str().ok())
+          .unwrap_or_default()
+          .to_owned();
+        if content_type.starts_with("application/graphql") {
+          let body = String::from_request(request, state)
+            .await
+            .map_err(|e| bad_request(&e))?;
+          Ok(GraphQLPayload(Batch::Single(Payload {
+            query: Some(body),
+            operation_name: None,
+            variables: None,
+            extensions: Extensions::default(),
+          })))
+        } else {
+          let Json(batch) = Json::<Batch>::from_request(request, state)
+            .await
+            .map_err(|e| bad_request(&e))?;
+          Ok(GraphQLPayload(batch))
+        }
+      }
+      _ => Err((
+        StatusCode::METHOD_NOT_ALLOWED,
+        "GraphQL is served over GET and POST".into(),
+      )),
+    }
+  }
+}
+
+struct Executed {
+  response: GraphQLResponse,
+  /// Refused before running, for its persisted query or its cost.
+  rejected: bool,
+  /// The SQL queries it made.
+  queries: usize,
+}
+
+impl Executed {
+  fn ok(&self) -> bool {
+    !self.rejected && self.response.is_ok()
+  }
+}
+
+fn reject(error: FieldError) -> Executed {
+  Executed {
+    response: GraphQLResponse::error(error),
+    rejected: true,
+    queries: 0,
+  }
+}
+
+/// The query of one request, checked against the limits and remembered if it was sent with
+/// its hash, and its cost.
+async fn prepare(
+  persisted: &PersistedQueries,
+  limits: Limits,
+  payload: Payload,
+) -> Result<(GraphQLRequest, Cost), FieldError> {
+  let hash = payload
+    .extensions
+    .persisted_query
+    .as_ref()
+    .map(|p| (p.version, p.sha256_hash.as_str()));
+  let given = payload.query.is_some();
+  let query = persisted
+    .resolve(payload.query, hash)
+    .await
+    .map_err(IntoFieldError::into_field_error)?;
+
+  let request = GraphQLRequest::new(query, payload.operation_name, payload.variables);
+  let cost = limits.check(
+    &request.query,
+    request.operation_name.as_deref(),
+    &request.variables(),
+  )?;
+  if let Some((_, sha256_hash)) = hash.filter(|_| given) {
+    persisted
+      .remember(sha256_hash, &request.query)
+      .await
+      .map_err(IntoFieldError::into_field_error)?;
+  }
+  Ok((request, cost))
+}
+
+/// Runs one request with its own context.
+async fn execute(schema: &Schema, database: &Database, request: GraphQLRequest) -> Executed {
+  let context = Context::new(database);
+  Executed {
+    response: request.execute(schema, &context).await,
+    rejected: false,
+    queries: context.queries(),
+  }
+}
+
+pub async fn custom_graphql(
+  Extension(schema): Extension<Arc<Schema>>,
+  Extension(database): Extension<Database>,
+  Extension(persisted): Extension<Arc<PersistedQueries>>,
+  Extension(limits): Extension<Limits>,
+  GraphQLPayload(batch): GraphQLPayload,
+) -> Response {
+  let run = |prepared| match prepared {
+    Ok((request, _)) => execute(&schema, &database, request).boxed(),
+    Err(e) => future::ready(reject(e)).boxed(),
+  };
+  let (response, ok, queries) = match batch {
+    Batch::Single(payload) => {
+      let executed = run(prepare(&persisted, limits, payload).await).await;
+      let ok = executed.ok();
+      (
+        serde_json::to_value(executed.response),
+        ok,
+        executed.queries,
+      )
+    }
+    Batch::Many(payloads) => {
+      let prepare = |payload| prepare(&persisted, limits, payload);
+      let prepared = future::join_all(payloads.into_iter().map(prepare)).await;
+      // Run together, the requests of a batch are only as cheap as all of them
+      let costs = prepared.iter().flatten().map(|(_, cost)| *cost);
+      let executed = match limits.check_batch(costs) {
+        Ok(()) => future::join_all(prepared.into_iter().map(run)).await,
+        Err(e) => prepared
+          .iter()
+          .map(|_| reject(FieldError::from(e.clone())))
+          .collect(),
+      };
+      let ok = executed.iter().all(Executed::ok);
+      let queries = executed.iter().map(|e| e.queries).sum();
+      let responses = executed.into_iter().map(|e| e.response).collect::<Vec<_>>();
+      (serde_json::to_value(responses), ok, queries)
+    }
+  };
+  tracing::info!("GraphQL request made {} SQL queries", queries);
+
+  let status = if ok {
+    StatusCode::OK
+  } else {
+    StatusCode::BAD_REQUEST
+  };
+  let mut response = match response {
+    Ok(json) => (status, Json(json)).into_response(),
+    Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+  };
+  response
+    .headers_mut()
+    .insert(SQL_QUERIES, HeaderValue::from(queries));
+  response
+}
+
+/// Subscriptions get a context per connection, whose loaders forget what they loaded at every
+/// change. Persisted queries are not applied to them, but the limits are to every operation.
+pub async fn custom_subscriptions(
+  Extension(schema): Extension<Arc<Schema>>,
+  Extension(database): Extension<Database>,
+  Extension(limits): Extension<Limits>,
+  ws: WebSocketUpgrade,
+) -> Response {
+  ws.protocols(["graphql-transport-ws", "graphql-ws"])
+    .max_frame_size(1024)
+    .max_message_size(1024)
+    .max_write_buffer_size(100)
+    .on_upgrade(move |socket| {
+      ws::serve(
+        socket,
+        schema,
+        ConnectionConfig::new(Context::new(&database)).with_max_in_flight_operations(10),
+        limits,
+      )
+    })
+}
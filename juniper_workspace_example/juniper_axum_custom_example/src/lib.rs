@@ -0,0 +1,54 @@
+//! A library catalogue served over GraphQL with custom [`Handler`]s on [`axum`]: authors, books
+//! and reviews in SQLite, loaded in batches, paged with Relay cursors and watched through
+//! subscriptions.
+//!
+//! [`Handler`]: axum::handler::Handler
+
+use std::sync::Arc;
+
+use axum::{
+  response::Html,
+  routing::{get, on, MethodFilter},
+  Extension, Router,
+};
+use juniper_axum::{graphiql, playground};
+
+pub mod connection;
+pub mod context;
+pub mod db;
+pub mod http;
+pub mod limits;
+mod loaders;
+pub mod persisted;
+pub mod schema;
+mod ws;
+
+use crate::{
+  db::Database,
+  http::{custom_graphql, custom_subscriptions},
+  limits::Limits,
+  persisted::PersistedQueries,
+};
+
+async fn homepage() -> Html<&'static str> {
+  "<html><h1>juniper_axum/custom example</h1><div>visit <a \
+   href=\"/graphiql\">GraphiQL</a></div><div>visit <a href=\"/playground\">GraphQL \
+   Playground</a></div></html>"
+    .into()
+}
+
+pub fn app(database: Database, limits: Limits) -> Router {
+  Router::new()
+    .route(
+      "/graphql",
+      on(MethodFilter::GET.or(MethodFilter::POST), custom_graphql),
+    )
+    .route("/subscriptions", get(custom_subscriptions))
+    .route("/graphiql", get(graphiql("/graphql", "/subscriptions")))
+    .route("/playground", get(playground("/graphql", "/subscriptions")))
+    .route("/", get(homepage))
+    .layer(Extension(Arc::new(schema::schema())))
+    .layer(Extension(Arc::new(PersistedQueries::new(database.clone()))))
+    .layer(Extension(limits))
+    .layer(Extension(database))
+}
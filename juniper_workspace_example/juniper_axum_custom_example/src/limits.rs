@@ -0,0 +1,317 @@
+//! Rejects queries nesting too deep or asking for too much, before they run.
+//!
+//! Each field costs 1, and the fields under a connection cost once per item of the page: the
+//! `first` or `last` it is given, from the variables or their defaults, or [`DEFAULT_PAGE`] for
+//! fields selecting `edges` or `nodes` without those. Introspection fields and what they select
+//! cost nothing, but nest like any other field.
+
+use std::collections::{HashMap, HashSet};
+
+use graphql_parser::query::{
+  parse_query, Definition, Field, FragmentDefinition, OperationDefinition, Selection, SelectionSet,
+  Value, VariableDefinition,
+};
+use juniper::Variables;
+
+use crate::connection::DEFAULT_PAGE;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+  pub max_depth: usize,
+  pub max_complexity: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cost {
+  pub depth: usize,
+  pub complexity: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Exceeded {
+  #[error("the query nests {0} levels deep, more than the {1} allowed")]
+  Depth(usize, usize),
+  #[error("the query has a complexity of {0}, more than the {1} allowed")]
+  Complexity(usize, usize),
+  #[error("the batch has a complexity of {0}, more than the {1} allowed")]
+  Batch(usize, usize),
+}
+
+type Fragments<'a> = HashMap<&'a str, &'a FragmentDefinition<'a, &'a str>>;
+
+struct Walk<'a> {
+  fragments: Fragments<'a>,
+  variables: &'a Variables,
+  /// The default values of the variables of the operation, for those not given.
+  defaults: HashMap<&'a str, &'a Value<'a, &'a str>>,
+  max_depth: usize,
+  /// The fragments being expanded, against cycles.
+  expanding: HashSet<&'a str>,
+  /// The cost of each fragment walked, its depth counted from where it is spread. Walking each
+  /// spread again would take time exponential in the number of fragments.
+  costs: HashMap<&'a str, Cost>,
+}
+
+impl Limits {
+  /// The cost of the operation of `query` that would run. Queries that do not parse, or whose
+  /// operation is not found, cost nothing here and are left for the executor to reject.
+  pub fn check(
+    &self,
+    query: &str,
+    operation_name: Option<&str>,
+    variables: &Variables,
+  ) -> Result<Cost, Exceeded> {
+    let Ok(document) = parse_query::<&str>(query) else {
+      return Ok(Cost::default());
+    };
+    let mut fragments = Fragments::new();
+    let mut operations = Vec::new();
+    for definition in &document.definitions {
+      match definition {
+        Definition::Fragment(f) => {
+          fragments.insert(f.name, f);
+        }
+        Definition::Operation(o) => operations.push(o),
+      }
+    }
+    let operation = operations.into_iter().find(|o| {
+      let name = match o {
+        OperationDefinition::SelectionSet(_) => None,
+        OperationDefinition::Query(q) => q.name,
+        OperationDefinition::Mutation(m) => m.name,
+        OperationDefinition::Subscription(s) => s.name,
+      };
+      operation_name.is_none() || name == operation_name
+    });
+    let (definitions, selection_set): (&[VariableDefinition<_>], _) = match operation {
+      None => return Ok(Cost::default()),
+      Some(OperationDefinition::SelectionSet(s)) => (&[], s),
+      Some(OperationDefinition::Query(q)) => (&q.variable_definitions, &q.selection_set),
+      Some(OperationDefinition::Mutation(m)) => (&m.variable_definitions, &m.selection_set),
+      Some(OperationDefinition::Subscription(s)) => (&s.variable_definitions, &s.selection_set),
+    };
+
+    let mut walk = Walk {
+      fragments,
+      variables,
+      defaults: definitions
+        .iter()
+        .filter_map(|d| Some((d.name, d.default_value.as_ref()?)))
+        .collect(),
+      max_depth: self.max_depth,
+      expanding: HashSet::new(),
+      costs: HashMap::new(),
+    };
+    let cost = walk.selection_set(selection_set, 0)?;
+    if cost.complexity > self.max_complexity {
+      return Err(Exceeded::Complexity(cost.complexity, self.max_complexity));
+    }
+    Ok(cost)
+  }
+
+  /// Adds up the complexities of the requests of a batch, checked one by one already.
+  pub fn check_batch(&self, costs: impl IntoIterator<Item = Cost>) -> Result<(), Exceeded> {
+    let complexity = costs
+      .into_iter()
+      .fold(0, |sum: usize, cost| sum.saturating_add(cost.complexity));
+    if complexity > self.max_complexity {
+      return Err(Exceeded::Batch(complexity, self.max_complexity));
+    }
+    Ok(())
+  }
+}
+
+impl<'a> Walk<'a> {
+  /// The cost of the fields selected at `depth`.
+  fn selection_set(
+    &mut self,
+    selection_set: &'a SelectionSet<'a, &'a str>,
+    depth: usize,
+  ) -> Result<Cost, Exceeded> {
+    let mut cost = Cost {
+      depth,
+      complexity: 0,
+    };
+    for selection in &selection_set.items {
+      let inner = match selection {
+        Selection::Field(field) => self.field(field, depth + 1)?,
+        Selection::InlineFragment(fragment) => {
+          self.selection_set(&fragment.selection_set, depth)?
+        }
+        Selection::FragmentSpread(spread) => {
+          let inner = self.fragment(spread.fragment_name)?;
+          if depth + inner.depth > self.max_depth {
+            return Err(Exceeded::Depth(self.max_depth + 1, self.max_depth));
+          }
+          Cost {
+            depth: depth + inner.depth,
+            ..inner
+          }
+        }
+      };
+      cost.depth = cost.depth.max(inner.depth);
+      cost.complexity = cost.complexity.saturating_add(inner.complexity);
+    }
+    Ok(cost)
+  }
+
+  /// The cost of fragment `name` as if spread at depth 0, walked only the first time.
+  fn fragment(&mut self, name: &'a str) -> Result<Cost, Exceeded> {
+    if let Some(&cost) = self.costs.get(name) {
+      return Ok(cost);
+    }
+    let Some(fragment) = self.fragments.get(name).copied() else {
+      return Ok(Cost::default());
+    };
+    if !self.expanding.insert(name) {
+      return Ok(Cost::default());
+    }
+    let cost = self.selection_set(&fragment.selection_set, 0);
+    self.expanding.remove(name);
+    let cost = cost?;
+    self.costs.insert(name, cost);
+    Ok(cost)
+  }
+
+  fn field(&mut self, field: &'a Field<'a, &'a str>, depth: usize) -> Result<Cost, Exceeded> {
+    if depth > self.max_depth {
+      return Err(Exceeded::Depth(depth, self.max_depth));
+    }
+    let inner = self.selection_set(&field.selection_set, depth)?;
+    if field.name.starts_with("__") {
+      return Ok(Cost {
+        depth: inner.depth,
+        complexity: 0,
+      });
+    }
+    Ok(Cost {
+      depth: inner.depth,
+      complexity: inner
+        .complexity
+        .saturating_mul(self.items(field))
+        .saturating_add(1),
+    })
+  }
+
+  /// How many items the selection of `field` is made for.
+  fn items(&self, field: &Field<'a, &'a str>) -> usize {
+    let argument = field
+      .arguments
+      .iter()
+      .find(|(name, _)| *name == "first" || *name == "last");
+    let count = match argument {
+      Some((_, Value::Int(n))) => n.as_i64(),
+      Some((_, Value::Variable(name))) => {
+        let given = self.variables.get(*name).and_then(|v| v.as_int_value());
+        match (given, self.defaults.get(name)) {
+          (Some(n), _) => Some(i64::from(n)),
+          (None, Some(Value::Int(n))) => n.as_i64(),
+          (None, _) => None,
+        }
+      }
+      _ => None,
+    };
+    let connection = field
+      .selection_set
+      .items
+      .iter()
+      .any(|s| matches!(s, Selection::Field(f) if f.name == "edges" || f.name == "nodes"));
+    match count {
+      Some(n) => n.max(0) as usize,
+      None if connection => DEFAULT_PAGE as usize,
+      None => 1,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use juniper::InputValue;
+
+  use super::*;
+
+  const LIMITS: Limits = Limits {
+    max_depth: 5,
+    max_complexity: 200,
+  };
+
+  fn check(query: &str) -> Result<Cost, Exceeded> {
+    LIMITS.check(query, None, &Variables::new())
+  }
+
+  #[test]
+  fn counts_fields_per_item() {
+    let cost = check("{ authors(first: 3) { edges { node { name } } } }").unwrap();
+    assert_eq!(cost.depth, 4);
+    // authors, then edges, node and name for each of the 3 authors
+    assert_eq!(cost.complexity, 1 + 3 * 3);
+    // 20 authors by default
+    let cost = check("{ authors { nodes { id name } } }").unwrap();
+    assert_eq!(cost.complexity, 1 + 20 * 3);
+  }
+
+  #[test]
+  fn follows_fragments_and_variables() {
+    let query = "query Q($n: Int) { ...A } fragment A on Query { authors(first: $n) { nodes { ... \
+                 on Author { name } } } }";
+    let variables = [("n".to_string(), InputValue::scalar(10))]
+      .into_iter()
+      .collect();
+    let cost = LIMITS.check(query, Some("Q"), &variables).unwrap();
+    assert_eq!(cost.complexity, 1 + 10 * 2);
+
+    // Defaults count when the variable is not given
+    let query = query.replace("$n: Int", "$n: Int = 50");
+    let cost = LIMITS.check(&query, Some("Q"), &variables).unwrap();
+    assert_eq!(cost.complexity, 1 + 10 * 2);
+    let cost = LIMITS.check(&query, Some("Q"), &Variables::new()).unwrap();
+    assert_eq!(cost.complexity, 1 + 50 * 2);
+  }
+
+  #[test]
+  fn rejects_deep_and_costly_queries() {
+    assert_eq!(
+      check("{ authors { nodes { books { nodes { author { name } } } } } }"),
+      Err(Exceeded::Depth(6, 5))
+    );
+    assert!(matches!(
+      check("{ authors(first: 100) { nodes { books(first: 100) { nodes { title } } } } }"),
+      Err(Exceeded::Complexity(..))
+    ));
+    // Introspection is free, but not of the depth limit
+    assert_eq!(
+      check("{ __schema { types { fields { type { name } } } } }"),
+      Ok(Cost {
+        depth: 5,
+        complexity: 0
+      })
+    );
+    assert_eq!(
+      check("{ __schema { types { fields { type { ofType { name } } } } } }"),
+      Err(Exceeded::Depth(6, 5))
+    );
+    assert_eq!(
+      check(
+        "{ ...T } fragment T on Query { authors { nodes { books { ...B } } } } fragment B on \
+         BookConnection { nodes { author { name } } }"
+      ),
+      Err(Exceeded::Depth(6, 5))
+    );
+  }
+
+  #[test]
+  fn walks_each_fragment_once() {
+    // Each fragment spreads the next twice: 2^40 authors fields, walked again at every spread
+    let mut query = "{ ...F0 }".to_string();
+    for i in 0 .. 40 {
+      query += &format!(
+        " fragment F{} on Query {{ ...F{} ...F{} }}",
+        i,
+        i + 1,
+        i + 1
+      );
+    }
+    query += " fragment F40 on Query { authors(first: 1) { nodes { id } } }";
+    assert!(matches!(check(&query), Err(Exceeded::Complexity(..))));
+  }
+}
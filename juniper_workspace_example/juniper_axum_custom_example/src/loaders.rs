@@ -0,0 +1,95 @@
+//! Batch functions for the loaders of a request: the keys asked for while a level of the query
+//! resolves are loaded together, with one query.
+
+use std::{collections::HashMap, hash::Hash};
+
+use dataloader::BatchFn;
+use juniper::{FieldResult, IntoFieldError};
+
+use crate::{
+  connection::{Page, Slice},
+  db::{Author, Book, Result, Review, Session},
+};
+
+/// Gives every key its value from `loaded`, `missing` if it was not found, or the error.
+fn spread<K, V, T>(
+  keys: &[K],
+  loaded: Result<HashMap<K, T>>,
+  missing: impl Fn() -> V,
+  found: impl Fn(T) -> V,
+) -> HashMap<K, FieldResult<V>>
+where
+  K: Eq + Hash + Clone,
+{
+  match loaded {
+    Ok(mut loaded) => keys
+      .iter()
+      .map(|k| {
+        (
+          k.clone(),
+          Ok(loaded.remove(k).map_or_else(&missing, &found)),
+        )
+      })
+      .collect(),
+    Err(e) => {
+      let e = e.into_field_error();
+      keys.iter().map(|k| (k.clone(), Err(e.clone()))).collect()
+    }
+  }
+}
+
+pub struct AuthorsById(pub Session);
+
+impl BatchFn<i64, FieldResult<Option<Author>>> for AuthorsById {
+  async fn load(&mut self, ids: &[i64]) -> HashMap<i64, FieldResult<Option<Author>>> {
+    let authors = self.0.authors_by_id(ids).await;
+    let authors = authors.map(|authors| authors.into_iter().map(|a| (a.id, a)).collect());
+    spread(ids, authors, || None, Some)
+  }
+}
+
+pub struct BooksById(pub Session);
+
+impl BatchFn<i64, FieldResult<Option<Book>>> for BooksById {
+  async fn load(&mut self, ids: &[i64]) -> HashMap<i64, FieldResult<Option<Book>>> {
+    let books = self.0.books_by_id(ids).await;
+    let books = books.map(|books| books.into_iter().map(|b| (b.id, b)).collect());
+    spread(ids, books, || None, Some)
+  }
+}
+
+/// Pages of the books of authors, with a query for each different page asked for.
+pub struct BooksOfAuthors(pub Session);
+
+impl BatchFn<(i64, Page), FieldResult<Slice<Book>>> for BooksOfAuthors {
+  async fn load(&mut self, keys: &[(i64, Page)]) -> HashMap<(i64, Page), FieldResult<Slice<Book>>> {
+    let mut pages = HashMap::<Page, Vec<i64>>::new();
+    for (author_id, page) in keys {
+      pages.entry(*page).or_default().push(*author_id);
+    }
+
+    let mut loaded = HashMap::new();
+    for (page, author_ids) in pages {
+      let keys = author_ids.iter().map(|id| (*id, page)).collect::<Vec<_>>();
+      let books = self.0.books_of_authors(&author_ids, page).await;
+      let books = books.map(|books| books.into_iter().map(|(id, s)| ((id, page), s)).collect());
+      loaded.extend(spread(&keys, books, || Slice::new(Vec::new(), page), |s| s));
+    }
+    loaded
+  }
+}
+
+pub struct ReviewsOfBooks(pub Session);
+
+impl BatchFn<i64, FieldResult<Vec<Review>>> for ReviewsOfBooks {
+  async fn load(&mut self, book_ids: &[i64]) -> HashMap<i64, FieldResult<Vec<Review>>> {
+    let reviews = self.0.reviews_of_books(book_ids).await.map(|reviews| {
+      let mut by_book = HashMap::<i64, Vec<Review>>::new();
+      for review in reviews {
+        by_book.entry(review.book_id).or_default().push(review);
+      }
+      by_book
+    });
+    spread(book_ids, reviews, Vec::new, |r| r)
+  }
+}
@@ -1,59 +1,27 @@
-//! This example demonstrates custom [`Handler`]s with [`axum`], using the [`starwars::schema`].
-//!
-//! [`Handler`]: axum::handler::Handler
-//! [`starwars::schema`]: juniper::tests::fixtures::starwars::schema
+use std::{net::SocketAddr, path::PathBuf};
 
-use std::{net::SocketAddr, sync::Arc};
-
-use axum::{
-  extract::WebSocketUpgrade,
-  response::{Html, Response},
-  routing::{get, on, MethodFilter},
-  Extension, Router,
-};
-use juniper::{
-  tests::fixtures::starwars::schema::{Database, Query, Subscription},
-  EmptyMutation, RootNode,
-};
-use juniper_axum::{
-  extract::JuniperRequest, graphiql, playground, response::JuniperResponse, subscriptions,
-};
-use juniper_graphql_ws::ConnectionConfig;
+use clap::Parser;
+use juniper_axum_custom_example::{app, db::Database, limits::Limits};
 use tokio::net::TcpListener;
 
-type Schema = RootNode<'static, Query, EmptyMutation<Database>, Subscription>;
-
-async fn homepage() -> Html<&'static str> {
-  "<html><h1>juniper_axum/custom example</h1><div>visit <a \
-   href=\"/graphiql\">GraphiQL</a></div><div>visit <a href=\"/playground\">GraphQL \
-   Playground</a></div></html>"
-    .into()
-}
-
-pub async fn custom_subscriptions(
-  Extension(schema): Extension<Arc<Schema>>,
-  Extension(database): Extension<Database>,
-  ws: WebSocketUpgrade,
-) -> Response {
-  ws.protocols(["graphql-transport-ws", "graphql-ws"])
-    .max_frame_size(1024)
-    .max_message_size(1024)
-    .max_write_buffer_size(100)
-    .on_upgrade(move |socket| {
-      subscriptions::serve_ws(
-        socket,
-        schema,
-        ConnectionConfig::new(database).with_max_in_flight_operations(10),
-      )
-    })
-}
-
-async fn custom_graphql(
-  Extension(schema): Extension<Arc<Schema>>,
-  Extension(database): Extension<Database>,
-  JuniperRequest(request): JuniperRequest,
-) -> JuniperResponse {
-  JuniperResponse(request.execute(&*schema, &database).await)
+#[derive(Parser)]
+struct Flags {
+  /// Sets the SQLite database to serve, created if missing.
+  #[clap(long, default_value = "library.db")]
+  database: PathBuf,
+  /// Sets the address to listen on.
+  #[clap(long, default_value = "127.0.0.1:8080")]
+  listen: SocketAddr,
+  /// Rejects queries nesting fields deeper than this, introspection included: the query
+  /// GraphiQL fetches the schema with nests 13 deep.
+  #[clap(long, default_value_t = 15)]
+  max_depth: usize,
+  /// Rejects queries costing more than this, a field costing once per item it is selected for.
+  #[clap(long, default_value_t = 5000)]
+  max_complexity: usize,
+  /// Fills an empty database with a few authors, books and reviews.
+  #[clap(long)]
+  seed: bool,
 }
 
 #[tokio::main]
@@ -61,28 +29,28 @@ async fn main() {
   tracing_subscriber::fmt()
     .with_max_level(tracing::Level::INFO)
     .init();
+  let flags = Flags::parse();
 
-  let schema = Schema::new(Query, EmptyMutation::new(), Subscription);
-  let database = Database::new();
-
-  let app = Router::new()
-    .route(
-      "/graphql",
-      on(MethodFilter::GET.or(MethodFilter::POST), custom_graphql),
-    )
-    .route("/subscriptions", get(custom_subscriptions))
-    .route("/graphiql", get(graphiql("/graphql", "/subscriptions")))
-    .route("/playground", get(playground("/graphql", "/subscriptions")))
-    .route("/", get(homepage))
-    .layer(Extension(Arc::new(schema)))
-    .layer(Extension(database));
-
-  let addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+  let database = Database::open(&flags.database)
+    .await
+    .unwrap_or_else(|e| panic!("failed to open {}: {e}", flags.database.display()));
+  if flags.seed {
+    database
+      .seed(10, 5, 3)
+      .await
+      .unwrap_or_else(|e| panic!("failed to seed {}: {e}", flags.database.display()));
+  }
+  let limits = Limits {
+    max_depth: flags.max_depth,
+    max_complexity: flags.max_complexity,
+  };
+
+  let addr = flags.listen;
   let listener = TcpListener::bind(addr)
     .await
     .unwrap_or_else(|e| panic!("failed to listen on {addr}: {e}"));
   tracing::info!("listening on {addr}");
-  axum::serve(listener, app)
+  axum::serve(listener, app(database, limits))
     .await
     .unwrap_or_else(|e| panic!("failed to run `axum::serve`: {e}"));
 }
@@ -0,0 +1,120 @@
+//! Automatic persisted queries: once the server has seen a query, clients can send its SHA-256
+//! hash in `extensions.persistedQuery.sha256Hash` instead of the query.
+
+use std::{collections::HashMap, sync::RwLock};
+
+use juniper::{graphql_value, FieldError, IntoFieldError, ScalarValue};
+use sha2::{Digest, Sha256};
+
+use crate::db::Database;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+  #[error("PersistedQueryNotFound")]
+  NotFound,
+  #[error("PersistedQueryNotSupported")]
+  UnsupportedVersion(u32),
+  #[error("provided sha256Hash does not match query")]
+  HashMismatch,
+  #[error("no query given")]
+  NoQuery,
+  #[error(transparent)]
+  Database(#[from] crate::db::Error),
+}
+
+impl<S: ScalarValue> IntoFieldError<S> for Error {
+  fn into_field_error(self) -> FieldError<S> {
+    let code = match self {
+      Error::NotFound => "PERSISTED_QUERY_NOT_FOUND",
+      Error::UnsupportedVersion(_) => "PERSISTED_QUERY_NOT_SUPPORTED",
+      Error::HashMismatch | Error::NoQuery => "BAD_REQUEST",
+      Error::Database(e) => return e.into_field_error(),
+    };
+    FieldError::new(self, graphql_value!({ "code": code }))
+  }
+}
+
+/// How many queries are remembered at most. Past that, queries sent with their hash still run
+/// but are not remembered.
+pub const MAX_QUERIES: i64 = 10_000;
+
+pub fn hash(query: &str) -> String {
+  format!("{:x}", Sha256::digest(query))
+}
+
+/// The queries seen, in the database and in memory.
+pub struct PersistedQueries {
+  database: Database,
+  known: RwLock<HashMap<String, String>>,
+}
+
+impl PersistedQueries {
+  pub fn new(database: Database) -> PersistedQueries {
+    PersistedQueries {
+      database,
+      known: RwLock::default(),
+    }
+  }
+
+  /// The query to run: `query` when given, if it matches `sha256_hash`, else the query
+  /// remembered under that hash. Given queries are only remembered by [`remember`] once they
+  /// passed the limits.
+  ///
+  /// [`remember`]: PersistedQueries::remember
+  pub async fn resolve(
+    &self,
+    query: Option<String>,
+    persisted: Option<(u32, &str)>,
+  ) -> Result<String, Error> {
+    let Some((version, sha256_hash)) = persisted else {
+      return query.ok_or(Error::NoQuery);
+    };
+    if version != 1 {
+      return Err(Error::UnsupportedVersion(version));
+    }
+
+    if let Some(query) = query {
+      if hash(&query) != sha256_hash {
+        return Err(Error::HashMismatch);
+      }
+      return Ok(query);
+    }
+
+    if let Some(query) = self.known.read().unwrap().get(sha256_hash) {
+      return Ok(query.clone());
+    }
+    let query = self
+      .database
+      .persisted_query(sha256_hash)
+      .await?
+      .ok_or(Error::NotFound)?;
+    self
+      .known
+      .write()
+      .unwrap()
+      .insert(sha256_hash.to_owned(), query.clone());
+    Ok(query)
+  }
+
+  /// Remembers `query` under `sha256_hash`, both as [`resolve`] took them, unless
+  /// [`MAX_QUERIES`] are remembered already.
+  ///
+  /// [`resolve`]: PersistedQueries::resolve
+  pub async fn remember(&self, sha256_hash: &str, query: &str) -> Result<(), Error> {
+    if self.known.read().unwrap().contains_key(sha256_hash) {
+      return Ok(());
+    }
+    if self
+      .database
+      .persist_query(sha256_hash, query, MAX_QUERIES)
+      .await?
+    {
+      self
+        .known
+        .write()
+        .unwrap()
+        .insert(sha256_hash.to_owned(), query.to_owned());
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,328 @@
+//! The GraphQL schema of the library.
+//!
+//! Relations resolve through the loaders of the [`Context`], so that a query costs a query per
+//! level of nesting rather than one per object.
+
+use std::pin::Pin;
+
+use futures::{Stream, StreamExt};
+use juniper::{
+  graphql_object, graphql_subscription, FieldError, FieldResult, GraphQLInputObject, RootNode, ID,
+};
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+
+use crate::{
+  connection::{connection, Node, Page},
+  context::Context,
+  db::{Author, Book, Change, Entity, Op, Review},
+};
+
+pub type Schema = RootNode<'static, Query, Mutation, Subscription>;
+
+pub fn schema() -> Schema {
+  Schema::new(Query, Mutation, Subscription)
+}
+
+fn id(id: &ID) -> FieldResult<i64> {
+  id.parse()
+    .map_err(|_| FieldError::from(format!("invalid id {:?}", id.to_string())))
+}
+
+fn to_id(id: i64) -> ID {
+  ID::new(id.to_string())
+}
+
+connection!(AuthorConnection, AuthorEdge, Author);
+connection!(BookConnection, BookEdge, Book);
+
+impl Node for Author {
+  fn id(&self) -> i64 {
+    self.id
+  }
+}
+
+impl Node for Book {
+  fn id(&self) -> i64 {
+    self.id
+  }
+}
+
+#[graphql_object(context = Context)]
+impl Author {
+  fn id(&self) -> ID {
+    to_id(self.id)
+  }
+
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  async fn books(
+    &self,
+    context: &Context,
+    first: Option<i32>,
+    after: Option<String>,
+    last: Option<i32>,
+    before: Option<String>,
+  ) -> FieldResult<BookConnection> {
+    let page = Page::new(first, after, last, before)?;
+    Ok(BookConnection(
+      context.books_of_author(self.id, page).await?,
+    ))
+  }
+}
+
+#[graphql_object(context = Context)]
+impl Book {
+  fn id(&self) -> ID {
+    to_id(self.id)
+  }
+
+  fn title(&self) -> &str {
+    &self.title
+  }
+
+  fn year(&self) -> Option<i32> {
+    self.year
+  }
+
+  async fn author(&self, context: &Context) -> FieldResult<Author> {
+    context
+      .author(self.author_id)
+      .await?
+      .ok_or_else(|| "author not found".into())
+  }
+
+  async fn reviews(&self, context: &Context) -> FieldResult<Vec<Review>> {
+    context.reviews_of_book(self.id).await
+  }
+
+  /// The mean of the ratings of the reviews, if there are any.
+  async fn average_rating(&self, context: &Context) -> FieldResult<Option<f64>> {
+    let reviews = context.reviews_of_book(self.id).await?;
+    Ok(
+      (!reviews.is_empty())
+        .then(|| reviews.iter().map(|r| r.rating as f64).sum::<f64>() / reviews.len() as f64),
+    )
+  }
+}
+
+#[graphql_object(context = Context)]
+impl Review {
+  fn id(&self) -> ID {
+    to_id(self.id)
+  }
+
+  /// From 1 to 5.
+  fn rating(&self) -> i32 {
+    self.rating
+  }
+
+  fn body(&self) -> &str {
+    &self.body
+  }
+
+  async fn book(&self, context: &Context) -> FieldResult<Book> {
+    context
+      .book(self.book_id)
+      .await?
+      .ok_or_else(|| "book not found".into())
+  }
+}
+
+/// A row written, by this server or another process.
+#[graphql_object(context = Context)]
+impl Change {
+  fn entity(&self) -> Entity {
+    self.entity
+  }
+
+  fn id(&self) -> ID {
+    to_id(self.id)
+  }
+
+  fn op(&self) -> Op {
+    self.op
+  }
+}
+
+pub struct Query;
+
+#[graphql_object(context = Context)]
+impl Query {
+  async fn author(context: &Context, id: ID) -> FieldResult<Option<Author>> {
+    context.author(self::id(&id)?).await
+  }
+
+  async fn book(context: &Context, id: ID) -> FieldResult<Option<Book>> {
+    context.book(self::id(&id)?).await
+  }
+
+  async fn authors(
+    context: &Context,
+    first: Option<i32>,
+    after: Option<String>,
+    last: Option<i32>,
+    before: Option<String>,
+  ) -> FieldResult<AuthorConnection> {
+    let page = Page::new(first, after, last, before)?;
+    Ok(AuthorConnection(context.authors_page(page).await?))
+  }
+
+  async fn books(
+    context: &Context,
+    first: Option<i32>,
+    after: Option<String>,
+    last: Option<i32>,
+    before: Option<String>,
+  ) -> FieldResult<BookConnection> {
+    let page = Page::new(first, after, last, before)?;
+    Ok(BookConnection(context.books_page(page).await?))
+  }
+}
+
+#[derive(GraphQLInputObject)]
+pub struct NewBook {
+  pub author_id: ID,
+  pub title: String,
+  pub year: Option<i32>,
+}
+
+#[derive(GraphQLInputObject)]
+pub struct NewReview {
+  pub book_id: ID,
+  pub rating: i32,
+  pub body: String,
+}
+
+pub struct Mutation;
+
+#[graphql_object(context = Context)]
+impl Mutation {
+  async fn create_author(context: &Context, name: String) -> FieldResult<Author> {
+    if name.trim().is_empty() {
+      return Err("an author needs a name".into());
+    }
+    Ok(context.session().create_author(name.trim()).await?)
+  }
+
+  async fn create_book(context: &Context, input: NewBook) -> FieldResult<Book> {
+    if input.title.trim().is_empty() {
+      return Err("a book needs a title".into());
+    }
+    let author_id = id(&input.author_id)?;
+    match context
+      .session()
+      .create_book(author_id, input.title.trim(), input.year)
+      .await
+    {
+      Ok(book) => Ok(book),
+      Err(e) if e.is_foreign_key_violation() => Err(format!("no author {author_id}").into()),
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  async fn add_review(context: &Context, input: NewReview) -> FieldResult<Review> {
+    if !(1 ..= 5).contains(&input.rating) {
+      return Err("ratings go from 1 to 5".into());
+    }
+    let book_id = id(&input.book_id)?;
+    match context
+      .session()
+      .add_review(book_id, input.rating, &input.body)
+      .await
+    {
+      Ok(review) => Ok(review),
+      Err(e) if e.is_foreign_key_violation() => Err(format!("no book {book_id}").into()),
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  /// Deletes a book with its reviews. False if there was no such book.
+  async fn delete_book(context: &Context, id: ID) -> FieldResult<bool> {
+    Ok(context.session().delete_book(self::id(&id)?).await?)
+  }
+}
+
+type Changes<T> = Pin<Box<dyn Stream<Item = FieldResult<T>> + Send>>;
+
+/// The changes to `entity` rows, made with `op` if given. Every change drops what the context
+/// loaded so far, for the rows resolved from it to be up to date.
+fn changes(
+  context: &Context,
+  entity: Option<Entity>,
+  op: Option<Op>,
+) -> Changes<(Context, Change)> {
+  let context = context.clone();
+  BroadcastStream::new(context.session().database().subscribe())
+    .filter_map(move |change| {
+      let context = context.clone();
+      async move {
+        match change {
+          Ok(change)
+            if entity.is_none_or(|e| e == change.entity) && op.is_none_or(|o| o == change.op) =>
+          {
+            context.forget().await;
+            Some(Ok((context, change)))
+          }
+          Ok(_) => None,
+          Err(BroadcastStreamRecvError::Lagged(missed)) => {
+            Some(Err(format!("missed {missed} changes").into()))
+          }
+        }
+      }
+    })
+    .boxed()
+}
+
+pub struct Subscription;
+
+#[graphql_subscription(context = Context)]
+impl Subscription {
+  /// Books as they are added, by anyone or by the author given.
+  async fn book_added(context: &Context, author_id: Option<ID>) -> FieldResult<Changes<Book>> {
+    let author_id = author_id.as_ref().map(id).transpose()?;
+    let books =
+      changes(context, Some(Entity::Book), Some(Op::Insert)).filter_map(move |change| async move {
+        let (context, change) = match change {
+          Ok(change) => change,
+          Err(e) => return Some(Err(e)),
+        };
+        match context.book(change.id).await {
+          Ok(Some(book)) if author_id.is_none_or(|a| a == book.author_id) => Some(Ok(book)),
+          Ok(_) => None,
+          Err(e) => Some(Err(e)),
+        }
+      });
+    Ok(books.boxed())
+  }
+
+  /// Reviews as they are added, of any book or of the book given.
+  async fn review_added(context: &Context, book_id: Option<ID>) -> FieldResult<Changes<Review>> {
+    let book_id = book_id.as_ref().map(id).transpose()?;
+    let reviews = changes(context, Some(Entity::Review), Some(Op::Insert)).filter_map(
+      move |change| async move {
+        let (context, change) = match change {
+          Ok(change) => change,
+          Err(e) => return Some(Err(e)),
+        };
+        // Reviews are only ever loaded by book
+        match context.session().reviews_by_id(&[change.id]).await {
+          Ok(mut reviews) => reviews
+            .pop()
+            .filter(|r| book_id.is_none_or(|b| b == r.book_id))
+            .map(Ok),
+          Err(e) => Some(Err(e.into())),
+        }
+      },
+    );
+    Ok(reviews.boxed())
+  }
+
+  /// Every row written, or those of `entity`.
+  async fn changes(context: &Context, entity: Option<Entity>) -> Changes<Change> {
+    changes(context, entity, None)
+      .map(|change| change.map(|(_, change)| change))
+      .boxed()
+  }
+}
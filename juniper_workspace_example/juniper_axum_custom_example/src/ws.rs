@@ -0,0 +1,175 @@
+//! Subscriptions over websockets, served as [`juniper_axum::subscriptions::serve_ws`] does but
+//! with every operation started checked against the [`Limits`] first. One over them is taken
+//! like a message that does not parse: `graphql-transport-ws` closes the connection with code
+//! 4400 and the reason, `graphql-ws` answers with a connection error and goes on.
+
+use axum::extract::ws::{self, WebSocket};
+use futures::{future, SinkExt, StreamExt};
+use juniper::DefaultScalarValue;
+use juniper_graphql_ws::{graphql_transport_ws, graphql_ws, Init, Schema};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::limits::{Exceeded, Limits};
+
+pub async fn serve<S, I>(socket: WebSocket, schema: S, init: I, limits: Limits)
+where
+  S: Schema<ScalarValue = DefaultScalarValue>,
+  I: Init<DefaultScalarValue, S::Context> + Send,
+{
+  let graphql_ws = socket.protocol().map(AsRef::as_ref) == Some("graphql-ws".as_bytes());
+  let (ws_tx, ws_rx) = socket.split();
+  let frames = ws_rx.map(move |r| r.map(|message| Frame { message, limits }));
+
+  if graphql_ws {
+    let (s_tx, s_rx) = graphql_ws::Connection::new(schema, init).split();
+    let input = frames.forward(s_tx.sink_map_err(|e| match e {}));
+    let output = s_rx.map(|message| Ok(text(&message))).forward(ws_tx);
+    _ = future::select(input, output).await;
+  } else {
+    let (s_tx, s_rx) = graphql_transport_ws::Connection::new(schema, init).split();
+    let input = frames.forward(s_tx.sink_map_err(|e| match e {}));
+    let output = s_rx
+      .map(|output| {
+        Ok(match output {
+          graphql_transport_ws::Output::Message(message) => text(&message),
+          graphql_transport_ws::Output::Close { code, message } => {
+            ws::Message::Close(Some(ws::CloseFrame {
+              code,
+              reason: message.into(),
+            }))
+          }
+        })
+      })
+      .forward(ws_tx);
+    _ = future::select(input, output).await;
+  }
+}
+
+fn text(message: &impl Serialize) -> ws::Message {
+  serde_json::to_string(message)
+    .map(ws::Message::Text)
+    .unwrap_or_else(|e| {
+      ws::Message::Close(Some(ws::CloseFrame {
+        code: 1011,
+        reason: format!("cannot serialize the response: {}", e).into(),
+      }))
+    })
+}
+
+#[derive(Debug, thiserror::Error)]
+enum Error {
+  #[error("malformed message: {0}")]
+  Json(#[from] serde_json::Error),
+  #[error("unexpected message {0:?}")]
+  Unexpected(ws::Message),
+  #[error(transparent)]
+  Exceeded(#[from] Exceeded),
+}
+
+/// A message from the client, and the limits of the operations it may start.
+struct Frame {
+  message: ws::Message,
+  limits: Limits,
+}
+
+impl Frame {
+  /// The message in JSON, `None` for a close.
+  fn json<T: DeserializeOwned>(self) -> Result<Option<T>, Error> {
+    match self.message {
+      ws::Message::Text(text) => Ok(Some(serde_json::from_str(&text)?)),
+      ws::Message::Binary(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+      ws::Message::Close(_) => Ok(None),
+      other => Err(Error::Unexpected(other)),
+    }
+  }
+}
+
+impl TryFrom<Frame> for graphql_transport_ws::Input<DefaultScalarValue> {
+  type Error = Error;
+
+  fn try_from(frame: Frame) -> Result<Self, Error> {
+    let limits = frame.limits;
+    let Some(message) = frame.json()? else {
+      return Ok(Self::Close);
+    };
+    if let graphql_transport_ws::ClientMessage::Subscribe { payload, .. } = &message {
+      limits.check(
+        &payload.query,
+        payload.operation_name.as_deref(),
+        &payload.variables,
+      )?;
+    }
+    Ok(Self::Message(message))
+  }
+}
+
+impl TryFrom<Frame> for graphql_ws::ClientMessage<DefaultScalarValue> {
+  type Error = Error;
+
+  fn try_from(frame: Frame) -> Result<Self, Error> {
+    let limits = frame.limits;
+    let Some(message) = frame.json()? else {
+      return Ok(Self::ConnectionTerminate);
+    };
+    if let Self::Start { payload, .. } = &message {
+      limits.check(
+        &payload.query,
+        payload.operation_name.as_deref(),
+        &payload.variables,
+      )?;
+    }
+    Ok(message)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const LIMITS: Limits = Limits {
+    max_depth: 3,
+    max_complexity: 100,
+  };
+
+  fn frame(text: &str) -> Frame {
+    Frame {
+      message: ws::Message::Text(text.into()),
+      limits: LIMITS,
+    }
+  }
+
+  #[test]
+  fn operations_over_the_limits_are_refused() {
+    let within = r#"{ "query": "subscription { bookAdded { title } }" }"#;
+    let deep =
+      r#"{ "query": "subscription { bookAdded { author { books { nodes { title } } } } }" }"#;
+
+    let start = |id, payload| {
+      format!(
+        r#"{{ "type": "start", "id": "{}", "payload": {} }}"#,
+        id, payload
+      )
+    };
+    assert!(graphql_ws::ClientMessage::try_from(frame(&start(1, within))).is_ok());
+    let err = graphql_ws::ClientMessage::try_from(frame(&start(2, deep))).unwrap_err();
+    assert!(
+      matches!(err, Error::Exceeded(Exceeded::Depth(4, 3))),
+      "{}",
+      err
+    );
+
+    let subscribe = |id, payload| {
+      format!(
+        r#"{{ "type": "subscribe", "id": "{}", "payload": {} }}"#,
+        id, payload
+      )
+    };
+    assert!(graphql_transport_ws::Input::try_from(frame(&subscribe(1, within))).is_ok());
+    let err = graphql_transport_ws::Input::try_from(frame(&subscribe(2, deep))).unwrap_err();
+    assert!(
+      matches!(err, Error::Exceeded(Exceeded::Depth(4, 3))),
+      "{}",
+      err
+    );
+  }
+}
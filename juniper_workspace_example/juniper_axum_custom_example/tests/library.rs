@@ -0,0 +1,398 @@
+use std::time::Duration;
+
+use axum::{
+  body::Body,
+  http::{header, Request, StatusCode},
+};
+use futures::StreamExt;
+use http_body_util::BodyExt;
+use juniper::{graphql_value, Value, Variables};
+use juniper_axum_custom_example::{
+  app,
+  context::Context,
+  db::{Change, Database, Entity, Op},
+  http::SQL_QUERIES,
+  limits::Limits,
+  persisted::hash,
+  schema::schema,
+};
+use serde_json::json;
+use tower::ServiceExt;
+
+const LIMITS: Limits = Limits {
+  max_depth: 8,
+  max_complexity: 2000,
+};
+
+/// 5 authors of 4 books with 3 reviews each.
+async fn library() -> Database {
+  let database = Database::memory().await.unwrap();
+  let mut changes = database.subscribe();
+  database.seed(5, 4, 3).await.unwrap();
+  // Let the changes of the seed go by, for subscriptions to only see those of the test
+  let last = Change {
+    entity: Entity::Review,
+    id: 60,
+    op: Op::Insert,
+  };
+  while changes.recv().await.unwrap() != last {}
+  database
+}
+
+/// Runs `query` with a context of its own, giving its data and how many SQL queries it made.
+async fn run(database: &Database, query: &str) -> (serde_json::Value, usize) {
+  let context = Context::new(database);
+  let (value, errors) = juniper::execute(query, None, &schema(), &Variables::new(), &context)
+    .await
+    .unwrap();
+  assert!(errors.is_empty(), "{errors:?}");
+  (serde_json::to_value(value).unwrap(), context.queries())
+}
+
+async fn post(
+  database: &Database,
+  body: serde_json::Value,
+) -> (StatusCode, usize, serde_json::Value) {
+  let request = Request::post("/graphql")
+    .header(header::CONTENT_TYPE, "application/json")
+    .body(Body::from(body.to_string()))
+    .unwrap();
+  send(database, request).await
+}
+
+async fn send(
+  database: &Database,
+  request: Request<Body>,
+) -> (StatusCode, usize, serde_json::Value) {
+  let response = app(database.clone(), LIMITS)
+    .oneshot(request)
+    .await
+    .unwrap();
+  let status = response.status();
+  let queries = response.headers()[SQL_QUERIES]
+    .to_str()
+    .unwrap()
+    .parse()
+    .unwrap();
+  let body = response.into_body().collect().await.unwrap().to_bytes();
+  (status, queries, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn loads_a_level_of_relations_per_query() {
+  let database = library().await;
+  let (data, queries) = run(
+    &database,
+    "{ authors(first: 5) { edges { node { name books(first: 4) { nodes { title averageRating \
+     author { name } reviews { rating book { title } } } } } } } }",
+  )
+  .await;
+
+  let authors = data["authors"]["edges"].as_array().unwrap();
+  assert_eq!(authors.len(), 5);
+  for author in authors {
+    let books = author["node"]["books"]["nodes"].as_array().unwrap();
+    assert_eq!(books.len(), 4);
+    for book in books {
+      assert_eq!(book["author"]["name"], author["node"]["name"]);
+      assert_eq!(book["reviews"].as_array().unwrap().len(), 3);
+      assert_eq!(book["reviews"][0]["book"]["title"], book["title"]);
+    }
+  }
+  // Authors, their books, the reviews of those: the authors and books of the reviews are
+  // already loaded. One query per object would have made 1 + 5 + 20 * (1 + 1 + 3).
+  assert_eq!(queries, 3);
+}
+
+#[tokio::test]
+async fn loads_objects_asked_for_together_once() {
+  let database = library().await;
+  let (data, queries) = run(
+    &database,
+    r#"{ a: book(id: "1") { author { name } } b: book(id: "2") { author { name } }
+         c: book(id: "1") { title } d: book(id: "999") { title } }"#,
+  )
+  .await;
+  assert_eq!(data["a"]["author"], data["b"]["author"]);
+  assert_eq!(data["d"], serde_json::Value::Null);
+  // The books, then their author
+  assert_eq!(queries, 2);
+}
+
+#[tokio::test]
+async fn batches_each_page_asked_for() {
+  let database = library().await;
+  let (data, queries) = run(
+    &database,
+    "{ authors { nodes { first: books(first: 1) { nodes { title } } last: books(last: 2) { nodes \
+     { title } pageInfo { hasPreviousPage } } } } }",
+  )
+  .await;
+  let author = &data["authors"]["nodes"][0];
+  assert_eq!(author["first"]["nodes"][0]["title"], "Book 1 by author 1");
+  assert_eq!(
+    author["last"]["nodes"],
+    json!([{ "title": "Book 3 by author 1" }, { "title": "Book 4 by author 1" }])
+  );
+  assert_eq!(author["last"]["pageInfo"]["hasPreviousPage"], true);
+  // Authors, then a query per page
+  assert_eq!(queries, 3);
+}
+
+#[tokio::test]
+async fn pages_through_cursors() {
+  let database = library().await;
+  let page = |args: &str| {
+    format!(
+      "{{ authors({args}) {{ edges {{ cursor node {{ name }} }} pageInfo {{ hasNextPage \
+       hasPreviousPage startCursor endCursor }} }} }}"
+    )
+  };
+  let names = |data: &serde_json::Value| {
+    data["authors"]["edges"]
+      .as_array()
+      .unwrap()
+      .iter()
+      .map(|e| e["node"]["name"].as_str().unwrap().to_owned())
+      .collect::<Vec<_>>()
+  };
+
+  let (first, _) = run(&database, &page("first: 2")).await;
+  assert_eq!(names(&first), ["Author 1", "Author 2"]);
+  assert_eq!(first["authors"]["pageInfo"]["hasNextPage"], true);
+  let end = first["authors"]["pageInfo"]["endCursor"].as_str().unwrap();
+  assert_eq!(first["authors"]["edges"][1]["cursor"], end);
+
+  let (next, _) = run(&database, &page(&format!("first: 10, after: {end:?}"))).await;
+  assert_eq!(names(&next), ["Author 3", "Author 4", "Author 5"]);
+  assert_eq!(next["authors"]["pageInfo"]["hasNextPage"], false);
+
+  let start = next["authors"]["pageInfo"]["startCursor"].as_str().unwrap();
+  let (previous, _) = run(&database, &page(&format!("last: 1, before: {start:?}"))).await;
+  assert_eq!(names(&previous), ["Author 2"]);
+  assert_eq!(previous["authors"]["pageInfo"]["hasPreviousPage"], true);
+
+  let context = Context::new(&database);
+  let (_, errors) = juniper::execute(
+    "{ authors(first: 1, last: 1) { nodes { name } } }",
+    None,
+    &schema(),
+    &Variables::new(),
+    &context,
+  )
+  .await
+  .unwrap();
+  assert_eq!(errors.len(), 1);
+}
+
+#[tokio::test]
+async fn counts_queries_over_http() {
+  let database = library().await;
+  let (status, queries, body) = post(
+    &database,
+    json!({
+      "query": "query Books($n: Int) { books(first: $n) { nodes { title author { name } } } }",
+      "variables": { "n": 3 },
+    }),
+  )
+  .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body["data"]["books"]["nodes"].as_array().unwrap().len(), 3);
+  assert_eq!(queries, 2);
+
+  // Batches add up
+  let (status, queries, body) = post(
+    &database,
+    json!([{ "query": "{ book(id: \"1\") { title } }" }, { "query": "{ authors { nodes { name } } }" }]),
+  )
+  .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body.as_array().unwrap().len(), 2);
+  assert_eq!(queries, 2);
+
+  // And so do their costs: 841 each, so 3 of them are over 2000
+  let costly = json!({ "query": "{ authors { nodes { books { nodes { title } } } } }" });
+  let (status, _, _) = post(&database, json!([costly, costly])).await;
+  assert_eq!(status, StatusCode::OK);
+  let (status, queries, body) = post(&database, json!([costly, costly, costly])).await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(queries, 0);
+  assert_eq!(
+    body[2]["errors"][0]["message"],
+    "the batch has a complexity of 2523, more than the 2000 allowed"
+  );
+
+  let request = Request::get("/graphql?query=%7Bbook(id%3A%221%22)%7Btitle%7D%7D")
+    .body(Body::empty())
+    .unwrap();
+  let (status, queries, body) = send(&database, request).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body["data"]["book"]["title"], "Book 1 by author 1");
+  assert_eq!(queries, 1);
+}
+
+#[tokio::test]
+async fn runs_persisted_queries() {
+  let database = library().await;
+  let query = "{ authors(first: 1) { nodes { name } } }";
+  let persisted = |hash: &str| json!({ "persistedQuery": { "version": 1, "sha256Hash": hash } });
+
+  let (status, queries, body) =
+    post(&database, json!({ "extensions": persisted(&hash(query)) })).await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(queries, 0);
+  assert_eq!(body["errors"][0]["message"], "PersistedQueryNotFound");
+  assert_eq!(
+    body["errors"][0]["extensions"]["code"],
+    "PERSISTED_QUERY_NOT_FOUND"
+  );
+
+  let (status, _, body) = post(
+    &database,
+    json!({ "query": query, "extensions": persisted(&hash("{ other }")) }),
+  )
+  .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(body["errors"][0]["extensions"]["code"], "BAD_REQUEST");
+
+  let (status, _, _) = post(
+    &database,
+    json!({ "query": query, "extensions": persisted(&hash(query)) }),
+  )
+  .await;
+  assert_eq!(status, StatusCode::OK);
+
+  let (status, queries, body) =
+    post(&database, json!({ "extensions": persisted(&hash(query)) })).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body["data"]["authors"]["nodes"][0]["name"], "Author 1");
+  assert_eq!(queries, 1);
+
+  // Remembered in the database too, for the next server
+  let (status, _, _) = send(
+    &database,
+    Request::get(format!(
+      "/graphql?extensions=%7B%22persistedQuery%22%3A%7B%22version%22%3A1%2C%22sha256Hash%22%3A%\
+       22{}%22%7D%7D",
+      hash(query)
+    ))
+    .body(Body::empty())
+    .unwrap(),
+  )
+  .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(
+    database
+      .persisted_query(&hash(query))
+      .await
+      .unwrap()
+      .as_deref(),
+    Some(query)
+  );
+}
+
+#[tokio::test]
+async fn rejects_queries_over_the_limits() {
+  let database = library().await;
+  let (status, queries, body) = post(
+    &database,
+    json!({ "query": "{ book(id: \"1\") { author { books { nodes { author { books { nodes { \
+                        author { name } } } } } } } } }" }),
+  )
+  .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(queries, 0);
+  assert_eq!(
+    body["errors"][0]["message"],
+    "the query nests 9 levels deep, more than the 8 allowed"
+  );
+
+  let (status, queries, body) = post(
+    &database,
+    json!({ "query": "{ authors(first: 100) { nodes { books(first: 100) { nodes { title } } } } }" }),
+  )
+  .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(queries, 0);
+  assert!(body["errors"][0]["message"]
+    .as_str()
+    .unwrap()
+    .contains("complexity"));
+
+  // Nor remembered for later
+  let query = "query Q($n: Int = 100) { authors(first: $n) { nodes { books(first: $n) { nodes { \
+               title } } } } }";
+  let extensions = json!({ "persistedQuery": { "version": 1, "sha256Hash": hash(query) } });
+  let (status, _, _) = post(
+    &database,
+    json!({ "query": query, "extensions": extensions }),
+  )
+  .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(database.persisted_query(&hash(query)).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn streams_database_changes() {
+  let database = library().await;
+  let context = Context::new(&database);
+  let schema = schema();
+  let (value, errors) = juniper::resolve_into_stream(
+    r#"subscription { bookAdded(authorId: "2") { title author { name } reviews { rating } } }"#,
+    None,
+    &schema,
+    &Variables::new(),
+    &context,
+  )
+  .await
+  .unwrap();
+  assert!(errors.is_empty());
+  let Value::Object(fields) = value else {
+    panic!("not an object");
+  };
+  let Some((_, Value::Scalar(mut books))) = fields.into_iter().next() else {
+    panic!("not a stream");
+  };
+
+  for (author, title) in [(1, "Elsewhere"), (2, "Watched")] {
+    let (_, queries) = run(
+      &database,
+      &format!(
+        r#"mutation {{ createBook(input: {{ authorId: "{author}", title: "{title}" }}) {{ id }} }}"#
+      ),
+    )
+    .await;
+    assert_eq!(queries, 1);
+  }
+
+  let book = tokio::time::timeout(Duration::from_secs(5), books.next())
+    .await
+    .unwrap()
+    .unwrap()
+    .unwrap();
+  assert_eq!(
+    book,
+    graphql_value!({ "title": "Watched", "author": { "name": "Author 2" }, "reviews": [] })
+  );
+}
+
+#[tokio::test]
+async fn validates_mutations() {
+  let database = library().await;
+  let context = Context::new(&database);
+  let (_, errors) = juniper::execute(
+    r#"mutation { addReview(input: { bookId: "999", rating: 3, body: "?" }) { id } }"#,
+    None,
+    &schema(),
+    &Variables::new(),
+    &context,
+  )
+  .await
+  .unwrap();
+  assert_eq!(errors[0].error().message(), "no book 999");
+
+  let (data, _) = run(&database, r#"mutation { deleteBook(id: "1") }"#).await;
+  assert_eq!(data["deleteBook"], true);
+  let (data, _) = run(&database, r#"{ book(id: "1") { title } }"#).await;
+  assert_eq!(data["book"], serde_json::Value::Null);
+}
@@ -0,0 +1,298 @@
+use std::sync::Arc;
+
+use axum::{
+  extract::{Path, State},
+  http::{header, HeaderMap, StatusCode},
+  response::{IntoResponse, Response},
+  routing::{delete, get, post},
+  Json, Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::{
+  error::TokenError,
+  keys::Algorithm,
+  tokens::{AccessClaims, TokenService},
+};
+
+pub struct AppState {
+  pub service: TokenService,
+  /// Bearer token of the backend allowed to issue tokens and manage keys.
+  pub admin_secret: String,
+}
+
+#[derive(Deserialize)]
+struct IssueRequest {
+  subject: String,
+  #[serde(flatten)]
+  claims: AccessClaims,
+  nonce: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RefreshRequest {
+  refresh_token: String,
+  nonce: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct VerifyRequest {
+  token: String,
+  nonce: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RotateRequest {
+  alg: Algorithm,
+}
+
+/// Public: `GET /.well-known/jwks.json`, `POST /refresh`, `POST /revoke` and `POST /verify`.
+/// For the admin: `POST /token`, `GET /keys`, `POST /keys/rotate` and `DELETE /keys/:kid`.
+pub fn router(state: Arc<AppState>) -> Router {
+  Router::new()
+    .route("/.well-known/jwks.json", get(jwks))
+    .route("/token", post(issue))
+    .route("/refresh", post(refresh))
+    .route("/revoke", post(revoke))
+    .route("/verify", post(verify))
+    .route("/keys", get(list_keys))
+    .route("/keys/rotate", post(rotate_key))
+    .route("/keys/:kid", delete(retire_key))
+    .with_state(state)
+}
+
+impl IntoResponse for TokenError {
+  fn into_response(self) -> Response {
+    let (status, error) = match &self {
+      TokenError::Invalid(_) | TokenError::Expired | TokenError::UnknownKey(_) => {
+        (StatusCode::UNAUTHORIZED, "invalid_token")
+      }
+      TokenError::UnknownRefreshToken
+      | TokenError::RefreshExpired
+      | TokenError::RefreshReused
+      | TokenError::Revoked => (StatusCode::BAD_REQUEST, "invalid_grant"),
+      TokenError::Key { .. } | TokenError::Io { .. } | TokenError::Storage(_) => {
+        (StatusCode::INTERNAL_SERVER_ERROR, "server_error")
+      }
+    };
+    (
+      status,
+      Json(json!({ "error": error, "error_description": self.to_string() })),
+    )
+      .into_response()
+  }
+}
+
+fn is_admin(state: &AppState, headers: &HeaderMap) -> bool {
+  let Some(token) = headers
+    .get(header::AUTHORIZATION)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.strip_prefix("Bearer "))
+  else {
+    return false;
+  };
+  // Comparing digests leaks nothing about the secret through timing
+  Sha256::digest(token) == Sha256::digest(&state.admin_secret)
+}
+
+fn forbidden() -> Response {
+  (
+    StatusCode::UNAUTHORIZED,
+    Json(json!({ "error": "admin bearer token required" })),
+  )
+    .into_response()
+}
+
+async fn jwks(State(state): State<Arc<AppState>>) -> Response {
+  Json(state.service.jwks()).into_response()
+}
+
+async fn issue(
+  State(state): State<Arc<AppState>>,
+  headers: HeaderMap,
+  Json(request): Json<IssueRequest>,
+) -> Response {
+  if !is_admin(&state, &headers) {
+    return forbidden();
+  }
+  state
+    .service
+    .issue(&request.subject, request.claims, request.nonce.as_deref())
+    .map(Json)
+    .into_response()
+}
+
+async fn refresh(
+  State(state): State<Arc<AppState>>,
+  Json(request): Json<RefreshRequest>,
+) -> Response {
+  state
+    .service
+    .refresh(&request.refresh_token, request.nonce.as_deref())
+    .map(Json)
+    .into_response()
+}
+
+async fn revoke(
+  State(state): State<Arc<AppState>>,
+  Json(request): Json<RefreshRequest>,
+) -> Response {
+  state
+    .service
+    .revoke(&request.refresh_token)
+    .map(|()| StatusCode::NO_CONTENT)
+    .into_response()
+}
+
+async fn verify(
+  State(state): State<Arc<AppState>>,
+  Json(request): Json<VerifyRequest>,
+) -> Response {
+  state
+    .service
+    .verify(&request.token, request.nonce.as_deref())
+    .map(Json)
+    .into_response()
+}
+
+async fn list_keys(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+  if !is_admin(&state, &headers) {
+    return forbidden();
+  }
+  Json(state.service.keys()).into_response()
+}
+
+async fn rotate_key(
+  State(state): State<Arc<AppState>>,
+  headers: HeaderMap,
+  Json(request): Json<RotateRequest>,
+) -> Response {
+  if !is_admin(&state, &headers) {
+    return forbidden();
+  }
+  state.service.rotate(request.alg).map(Json).into_response()
+}
+
+async fn retire_key(
+  State(state): State<Arc<AppState>>,
+  headers: HeaderMap,
+  Path(kid): Path<String>,
+) -> Response {
+  if !is_admin(&state, &headers) {
+    return forbidden();
+  }
+  match state.service.retire(&kid) {
+    Ok(true) => StatusCode::NO_CONTENT.into_response(),
+    Ok(false) => (
+      StatusCode::NOT_FOUND,
+      Json(json!({ "error": format!("no key {:?}", kid) })),
+    )
+      .into_response(),
+    Err(e) => e.into_response(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use axum::{body::Body, http::Request};
+  use http_body_util::BodyExt;
+  use serde_json::Value;
+  use tempfile::TempDir;
+  use tower::ServiceExt;
+
+  use super::*;
+  use crate::{keys::KeyRing, refresh::RefreshStore, tokens::TokenConfig};
+
+  async fn call(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    admin: bool,
+    body: Value,
+  ) -> (StatusCode, Value) {
+    let mut request = Request::builder()
+      .method(method)
+      .uri(uri)
+      .header(header::CONTENT_TYPE, "application/json");
+    if admin {
+      request = request.header(header::AUTHORIZATION, "Bearer s3cret");
+    }
+    let response = app
+      .clone()
+      .oneshot(request.body(Body::from(body.to_string())).unwrap())
+      .await
+      .unwrap();
+    let status = response.status();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let value = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+    (status, value)
+  }
+
+  #[tokio::test]
+  async fn issues_refreshes_and_revokes() {
+    let dir = TempDir::new().unwrap();
+    let service = TokenService::new(
+      TokenConfig::default(),
+      KeyRing::open(dir.path(), Algorithm::EdDSA).unwrap(),
+      RefreshStore::open_in_memory().unwrap(),
+    );
+    let app = router(Arc::new(AppState {
+      service,
+      admin_secret: "s3cret".to_string(),
+    }));
+
+    let request = json!({ "subject": "alice", "email": "alice@example.com", "nonce": "n" });
+    let (status, _) = call(&app, "POST", "/token", false, request.clone()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, pair) = call(&app, "POST", "/token", true, request).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, claims) = call(
+      &app,
+      "POST",
+      "/verify",
+      false,
+      json!({ "token": pair["access_token"], "nonce": "n" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(claims["sub"], "alice");
+    assert_eq!(claims["email"], "alice@example.com");
+
+    let (_, jwks) = call(&app, "GET", "/.well-known/jwks.json", false, Value::Null).await;
+    assert_eq!(jwks["keys"][0]["kty"], "OKP");
+
+    let refresh = json!({ "refresh_token": pair["refresh_token"] });
+    let (status, next) = call(&app, "POST", "/refresh", false, refresh.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, error) = call(&app, "POST", "/refresh", false, refresh).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error["error"], "invalid_grant");
+
+    // The replay revoked the family
+    let next = json!({ "refresh_token": next["refresh_token"] });
+    let (status, _) = call(&app, "POST", "/refresh", false, next.clone()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = call(&app, "POST", "/revoke", false, next).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, key) = call(
+      &app,
+      "POST",
+      "/keys/rotate",
+      true,
+      json!({ "alg": "ES256" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, jwks) = call(&app, "GET", "/.well-known/jwks.json", false, Value::Null).await;
+    assert_eq!(jwks["keys"][0]["kid"], key["kid"]);
+    let uri = format!("/keys/{}", key["kid"].as_str().unwrap());
+    let (status, _) = call(&app, "DELETE", &uri, true, Value::Null).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = call(&app, "DELETE", &uri, true, Value::Null).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+  }
+}
@@ -0,0 +1,32 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum TokenError {
+  /// Malformed, badly signed, or issued for someone else.
+  #[error("invalid token: {0}")]
+  Invalid(String),
+  #[error("token has expired")]
+  Expired,
+  #[error("no key {0:?}")]
+  UnknownKey(String),
+  #[error("unknown refresh token")]
+  UnknownRefreshToken,
+  #[error("refresh token has expired")]
+  RefreshExpired,
+  /// The refresh token was already exchanged, so it may have been stolen: its family is revoked.
+  #[error("refresh token reused, its family is revoked")]
+  RefreshReused,
+  #[error("refresh token revoked")]
+  Revoked,
+  #[error("{path}: {source}")]
+  Io {
+    path: PathBuf,
+    source: std::io::Error,
+  },
+  #[error("key {kid:?}: {message}")]
+  Key { kid: String, message: String },
+  #[error(transparent)]
+  Storage(#[from] rusqlite::Error),
+}
@@ -0,0 +1,305 @@
+//! Signing keys on disk. `keyring.json` lists the keys oldest first; the newest signs new tokens
+//! and the others only verify tokens they signed before a rotation.
+
+use std::{
+  fs,
+  path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use jwt_simple::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{error::TokenError, unix_now};
+
+const MANIFEST: &str = "keyring.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub enum Algorithm {
+  /// Symmetric, so never published in the JWKS.
+  HS256,
+  ES256,
+  #[value(name = "eddsa")]
+  EdDSA,
+}
+
+enum Secret {
+  HS256(HS256Key),
+  ES256(ES256KeyPair),
+  EdDSA(Ed25519KeyPair),
+}
+
+/// What `keyring.json` records about a key; the key itself is in its own file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyInfo {
+  pub kid: String,
+  pub alg: Algorithm,
+  /// Unix time.
+  pub created: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+  keys: Vec<KeyInfo>,
+}
+
+pub struct Key {
+  pub info: KeyInfo,
+  secret: Secret,
+}
+
+impl Key {
+  fn generate(alg: Algorithm) -> Key {
+    let kid = format!("{:016x}", rand::random::<u64>());
+    let secret = match alg {
+      Algorithm::HS256 => Secret::HS256(HS256Key::generate().with_key_id(&kid)),
+      Algorithm::ES256 => Secret::ES256(ES256KeyPair::generate().with_key_id(&kid)),
+      Algorithm::EdDSA => Secret::EdDSA(Ed25519KeyPair::generate().with_key_id(&kid)),
+    };
+    Key {
+      info: KeyInfo {
+        kid,
+        alg,
+        created: unix_now(),
+      },
+      secret,
+    }
+  }
+
+  fn path(dir: &Path, info: &KeyInfo) -> PathBuf {
+    match info.alg {
+      Algorithm::HS256 => dir.join(format!("{}.key", info.kid)),
+      Algorithm::ES256 | Algorithm::EdDSA => dir.join(format!("{}.pem", info.kid)),
+    }
+  }
+
+  fn load(dir: &Path, info: KeyInfo) -> Result<Key, TokenError> {
+    let path = Key::path(dir, &info);
+    let bytes = fs::read(&path).map_err(|source| TokenError::Io { path, source })?;
+    let pem = || String::from_utf8_lossy(&bytes).into_owned();
+    let kid = info.kid.as_str();
+    let secret = match info.alg {
+      Algorithm::HS256 => Ok(Secret::HS256(HS256Key::from_bytes(&bytes).with_key_id(kid))),
+      Algorithm::ES256 => {
+        ES256KeyPair::from_pem(&pem()).map(|key| Secret::ES256(key.with_key_id(kid)))
+      }
+      Algorithm::EdDSA => {
+        Ed25519KeyPair::from_pem(&pem()).map(|key| Secret::EdDSA(key.with_key_id(kid)))
+      }
+    }
+    .map_err(|e| key_error(kid, e))?;
+    Ok(Key { info, secret })
+  }
+
+  fn save(&self, dir: &Path) -> Result<(), TokenError> {
+    let contents = match &self.secret {
+      Secret::HS256(key) => key.to_bytes(),
+      Secret::ES256(key) => key
+        .to_pem()
+        .map_err(|e| key_error(&self.info.kid, e))?
+        .into_bytes(),
+      Secret::EdDSA(key) => key.to_pem().into_bytes(),
+    };
+    write_private(&Key::path(dir, &self.info), &contents)
+  }
+
+  pub(crate) fn sign<C: Serialize + DeserializeOwned>(
+    &self,
+    claims: JWTClaims<C>,
+  ) -> Result<String, jwt_simple::Error> {
+    match &self.secret {
+      Secret::HS256(key) => key.authenticate(claims),
+      Secret::ES256(key) => key.sign(claims),
+      Secret::EdDSA(key) => key.sign(claims),
+    }
+  }
+
+  /// Also checks that the token's `alg` is this key's.
+  pub(crate) fn verify<C: Serialize + DeserializeOwned>(
+    &self,
+    token: &str,
+    options: VerificationOptions,
+  ) -> Result<JWTClaims<C>, jwt_simple::Error> {
+    match &self.secret {
+      Secret::HS256(key) => key.verify_token(token, Some(options)),
+      Secret::ES256(key) => key.public_key().verify_token(token, Some(options)),
+      Secret::EdDSA(key) => key.public_key().verify_token(token, Some(options)),
+    }
+  }
+
+  /// The public key as a JWK, if there is one.
+  pub fn jwk(&self) -> Option<Value> {
+    let jwk = match &self.secret {
+      Secret::HS256(_) => return None,
+      Secret::ES256(key) => key.public_key().to_jwk(),
+      Secret::EdDSA(key) => key.public_key().to_jwk(),
+    };
+    serde_json::from_str(&jwk).ok()
+  }
+}
+
+pub struct KeyRing {
+  dir: PathBuf,
+  /// Oldest first.
+  keys: Vec<Key>,
+}
+
+impl KeyRing {
+  /// Loads the keys of `dir`, generating an `alg` key on first run.
+  pub fn open(dir: &Path, alg: Algorithm) -> Result<KeyRing, TokenError> {
+    let manifest = dir.join(MANIFEST);
+    if !manifest.exists() {
+      fs::create_dir_all(dir).map_err(|source| TokenError::Io {
+        path: dir.to_path_buf(),
+        source,
+      })?;
+      let mut ring = KeyRing {
+        dir: dir.to_path_buf(),
+        keys: vec![],
+      };
+      ring.rotate(alg, 0)?;
+      return Ok(ring);
+    }
+    let json = fs::read_to_string(&manifest).map_err(|source| TokenError::Io {
+      path: manifest.clone(),
+      source,
+    })?;
+    let Manifest { keys } = serde_json::from_str(&json).map_err(|e| TokenError::Io {
+      path: manifest,
+      source: e.into(),
+    })?;
+    let keys = keys
+      .into_iter()
+      .map(|info| Key::load(dir, info))
+      .collect::<Result<Vec<_>, _>>()?;
+    if keys.is_empty() {
+      return Err(key_error("", "the key ring is empty"));
+    }
+    Ok(KeyRing {
+      dir: dir.to_path_buf(),
+      keys,
+    })
+  }
+
+  /// The key new tokens are signed with.
+  pub fn signing(&self) -> &Key {
+    self.keys.last().unwrap()
+  }
+
+  pub fn get(&self, kid: &str) -> Option<&Key> {
+    self.keys.iter().find(|key| key.info.kid == kid)
+  }
+
+  pub fn keys(&self) -> Vec<KeyInfo> {
+    self.keys.iter().map(|key| key.info.clone()).collect()
+  }
+
+  /// Signs with a new `alg` key from now on. The previous `retain` keys keep verifying the
+  /// tokens they signed; older ones are retired.
+  pub fn rotate(&mut self, alg: Algorithm, retain: usize) -> Result<&Key, TokenError> {
+    let key = Key::generate(alg);
+    key.save(&self.dir)?;
+    self.keys.push(key);
+    let retired = self.keys.len().saturating_sub(retain + 1);
+    let retired: Vec<Key> = self.keys.drain(.. retired).collect();
+    self.save()?;
+    for key in retired {
+      remove_key_file(&self.dir, &key.info);
+    }
+    Ok(self.signing())
+  }
+
+  /// Stops accepting tokens signed by `kid`. Retiring the signing key makes the previous one sign.
+  pub fn retire(&mut self, kid: &str) -> Result<bool, TokenError> {
+    let Some(index) = self.keys.iter().position(|key| key.info.kid == kid) else {
+      return Ok(false);
+    };
+    if self.keys.len() == 1 {
+      return Err(key_error(kid, "the last key cannot be retired"));
+    }
+    let key = self.keys.remove(index);
+    self.save()?;
+    remove_key_file(&self.dir, &key.info);
+    Ok(true)
+  }
+
+  /// The public keys, as served at `/.well-known/jwks.json`.
+  pub fn jwks(&self) -> Value {
+    let keys: Vec<Value> = self.keys.iter().rev().filter_map(Key::jwk).collect();
+    json!({ "keys": keys })
+  }
+
+  fn save(&self) -> Result<(), TokenError> {
+    let manifest = Manifest { keys: self.keys() };
+    let json = serde_json::to_string_pretty(&manifest).unwrap();
+    // Written aside then renamed, so a crash never leaves a truncated manifest
+    let path = self.dir.join(MANIFEST);
+    let staging = self.dir.join(format!("{}.new", MANIFEST));
+    fs::write(&staging, json)
+      .and_then(|()| fs::rename(&staging, &path))
+      .map_err(|source| TokenError::Io { path, source })
+  }
+}
+
+fn key_error(kid: &str, message: impl ToString) -> TokenError {
+  TokenError::Key {
+    kid: kid.to_string(),
+    message: message.to_string(),
+  }
+}
+
+fn remove_key_file(dir: &Path, info: &KeyInfo) {
+  let _ = fs::remove_file(Key::path(dir, info));
+}
+
+/// Creates `path` readable by its owner only.
+fn write_private(path: &Path, contents: &[u8]) -> Result<(), TokenError> {
+  use std::io::Write;
+
+  let mut options = fs::OpenOptions::new();
+  options.write(true).create_new(true);
+  #[cfg(unix)]
+  std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+  options
+    .open(path)
+    .and_then(|mut file| file.write_all(contents))
+    .map_err(|source| TokenError::Io {
+      path: path.to_path_buf(),
+      source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use tempfile::TempDir;
+
+  use super::*;
+
+  #[test]
+  fn keys_persist_and_rotate() {
+    let dir = TempDir::new().unwrap();
+    let mut ring = KeyRing::open(dir.path(), Algorithm::ES256).unwrap();
+    let first = ring.signing().info.clone();
+    assert_eq!(first.alg, Algorithm::ES256);
+
+    let second = ring.rotate(Algorithm::EdDSA, 1).unwrap().info.clone();
+    let third = ring.rotate(Algorithm::HS256, 1).unwrap().info.clone();
+    // Only the previous key is kept, and HS256 keys are not published
+    assert_eq!(ring.keys(), vec![second.clone(), third.clone()]);
+    assert!(!dir.path().join(format!("{}.pem", first.kid)).exists());
+    let jwks = ring.jwks();
+    assert_eq!(jwks["keys"].as_array().unwrap().len(), 1);
+    assert_eq!(jwks["keys"][0]["kid"], json!(second.kid));
+    assert_eq!(jwks["keys"][0]["crv"], json!("Ed25519"));
+
+    let reopened = KeyRing::open(dir.path(), Algorithm::ES256).unwrap();
+    assert_eq!(reopened.keys(), ring.keys());
+    assert_eq!(reopened.jwks(), jwks);
+
+    assert!(ring.retire(&third.kid).unwrap());
+    assert_eq!(ring.signing().info, second);
+    assert!(!ring.retire(&third.kid).unwrap());
+    assert!(ring.retire(&second.kid).is_err());
+  }
+}
@@ -0,0 +1,17 @@
+//! A token service: access tokens are JWTs signed with rotating HS256, ES256 or EdDSA keys,
+//! published as a JWKS, and refresh tokens are single use and revocable, kept in SQLite.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub mod api;
+pub mod error;
+pub mod keys;
+pub mod refresh;
+pub mod tokens;
+
+pub fn unix_now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap()
+    .as_secs()
+}
@@ -1,19 +1,123 @@
-// src/main.rs
-mod jwt_sign;
-use std::{
-  fs::File,
-  io::{BufWriter, Write},
+// cargo run -- --admin-secret s3cret serve
+// curl -s -X POST localhost:3000/token -H 'authorization: Bearer s3cret' \
+//   -H 'content-type: application/json' -d '{"subject":"someone","email":"someone@gmail.com"}'
+// curl -s localhost:3000/.well-known/jwks.json
+// cargo run -- rotate --alg eddsa
+
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use clap::{Parser, Subcommand};
+use jwt_simple_example::{
+  api::{self, AppState},
+  keys::{Algorithm, KeyRing},
+  refresh::RefreshStore,
+  tokens::{TokenConfig, TokenService},
+  unix_now,
 };
 
-use jwt_sign::create_jwt;
-use jwt_simple::prelude::*;
-fn main() {
-  let key = HS256Key::generate();
-  let byte_data = key.to_bytes();
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+  /// Directory of the signing keys, created with one key on first run
+  #[arg(long, default_value = "keys")]
+  keys: PathBuf,
+  /// Algorithm of the first key
+  #[arg(long, value_enum, default_value_t = Algorithm::ES256)]
+  alg: Algorithm,
+  /// SQLite database of refresh tokens
+  #[arg(long, default_value = "tokens.db")]
+  db: PathBuf,
+  /// Keys kept for verification after a rotation
+  #[arg(long, default_value_t = 2)]
+  retain_keys: usize,
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+  Serve {
+    #[arg(long, default_value = "127.0.0.1:3000")]
+    listen: SocketAddr,
+    /// Bearer token required to issue tokens and manage keys
+    #[arg(long, env = "JWT_ADMIN_SECRET")]
+    admin_secret: String,
+    #[arg(long, default_value = "jwt_simple_example")]
+    issuer: String,
+    #[arg(long, default_value = "api")]
+    audience: String,
+    /// Lifetime of access tokens, in seconds
+    #[arg(long, default_value_t = 15 * 60)]
+    access_ttl: u64,
+    /// Lifetime of refresh tokens, in seconds
+    #[arg(long, default_value_t = 30 * 24 * 60 * 60)]
+    refresh_ttl: u64,
+  },
+  /// List the keys
+  Keys,
+  /// Sign with a new key from now on; restart a running server to pick it up
+  Rotate {
+    #[arg(long, value_enum)]
+    alg: Option<Algorithm>,
+  },
+  /// Stop accepting tokens signed by a key
+  Retire { kid: String },
+  /// Revoke every refresh token of a subject
+  Revoke { subject: String },
+}
 
-  let f = File::create("key").expect("Unable to create  file");
-  let mut f = BufWriter::new(f);
-  f.write_all(&byte_data).expect("Unable to write data");
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+  let args = Args::parse();
+  let mut keys = KeyRing::open(&args.keys, args.alg)?;
+  let refresh = RefreshStore::open(&args.db)?;
 
-  print!("{}", create_jwt("someone@gmail.com".to_string()));
+  match args.command {
+    Command::Serve {
+      listen,
+      admin_secret,
+      issuer,
+      audience,
+      access_ttl,
+      refresh_ttl,
+    } => {
+      let pruned = refresh.prune(unix_now())?;
+      println!("pruned {} expired refresh tokens", pruned);
+      let config = TokenConfig {
+        issuer,
+        audience,
+        access_ttl: Duration::from_secs(access_ttl),
+        refresh_ttl: Duration::from_secs(refresh_ttl),
+        retain_keys: args.retain_keys,
+        ..Default::default()
+      };
+      let state = Arc::new(AppState {
+        service: TokenService::new(config, keys, refresh),
+        admin_secret,
+      });
+      let listener = tokio::net::TcpListener::bind(listen).await?;
+      println!("listening on {}", listener.local_addr()?);
+      axum::serve(listener, api::router(state)).await?;
+    }
+    Command::Keys => {
+      for info in keys.keys() {
+        println!("{} {:?} created {}", info.kid, info.alg, info.created);
+      }
+    }
+    Command::Rotate { alg } => {
+      let alg = alg.unwrap_or(keys.signing().info.alg);
+      let key = keys.rotate(alg, args.retain_keys)?;
+      println!("now signing with {} ({:?})", key.info.kid, key.info.alg);
+    }
+    Command::Retire { kid } => {
+      if !keys.retire(&kid)? {
+        return Err(format!("no key {:?}", kid).into());
+      }
+    }
+    Command::Revoke { subject } => {
+      let revoked = refresh.revoke_subject(&subject, unix_now())?;
+      println!("revoked {} refresh token families", revoked);
+    }
+  }
+  Ok(())
 }
@@ -0,0 +1,259 @@
+//! Refresh tokens are opaque and single use: exchanging one returns its successor in the same
+//! family. Presenting an exchanged token again means two parties hold the family, so the whole
+//! family is revoked. Only SHA-256 hashes of the tokens are stored.
+
+use std::{path::Path, sync::Mutex};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use sha2::{Digest, Sha256};
+
+use crate::error::TokenError;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS families (
+  id         TEXT PRIMARY KEY,
+  subject    TEXT NOT NULL,
+  claims     TEXT NOT NULL,
+  created_at INTEGER NOT NULL,
+  revoked_at INTEGER,
+  reason     TEXT
+);
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+  hash       TEXT PRIMARY KEY,
+  family     TEXT NOT NULL REFERENCES families(id),
+  issued_at  INTEGER NOT NULL,
+  expires_at INTEGER NOT NULL,
+  used_at    INTEGER
+);
+CREATE INDEX IF NOT EXISTS refresh_tokens_family ON refresh_tokens(family);
+";
+
+/// Who a refresh token family was issued to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grant {
+  pub family: String,
+  pub subject: String,
+  /// The custom access token claims, as JSON.
+  pub claims: String,
+}
+
+pub struct RefreshStore {
+  conn: Mutex<Connection>,
+}
+
+impl RefreshStore {
+  pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<RefreshStore> {
+    RefreshStore::init(Connection::open(path)?)
+  }
+
+  pub fn open_in_memory() -> rusqlite::Result<RefreshStore> {
+    RefreshStore::init(Connection::open_in_memory()?)
+  }
+
+  fn init(conn: Connection) -> rusqlite::Result<RefreshStore> {
+    conn.execute_batch(SCHEMA)?;
+    Ok(RefreshStore {
+      conn: Mutex::new(conn),
+    })
+  }
+
+  /// Starts a family for `subject`, returning its first token.
+  pub fn create(
+    &self,
+    subject: &str,
+    claims: &str,
+    ttl: u64,
+    now: u64,
+  ) -> Result<String, TokenError> {
+    let mut conn = self.conn.lock().unwrap();
+    let tx = conn.transaction()?;
+    let family = random_token();
+    tx.execute(
+      "INSERT INTO families (id, subject, claims, created_at) VALUES (?1, ?2, ?3, ?4)",
+      params![family, subject, claims, now],
+    )?;
+    let token = insert_token(&tx, &family, ttl, now)?;
+    tx.commit()?;
+    Ok(token)
+  }
+
+  /// Exchanges `token` for its successor.
+  pub fn rotate(&self, token: &str, ttl: u64, now: u64) -> Result<(String, Grant), TokenError> {
+    let mut conn = self.conn.lock().unwrap();
+    let tx = conn.transaction()?;
+    let row = tx
+      .query_row(
+        "SELECT t.family, t.expires_at, t.used_at, f.revoked_at, f.subject, f.claims
+         FROM refresh_tokens t JOIN families f ON f.id = t.family
+         WHERE t.hash = ?1",
+        [hash(token)],
+        |row| {
+          Ok((
+            Grant {
+              family: row.get(0)?,
+              subject: row.get(4)?,
+              claims: row.get(5)?,
+            },
+            row.get::<_, u64>(1)?,
+            row.get::<_, Option<u64>>(2)?,
+            row.get::<_, Option<u64>>(3)?,
+          ))
+        },
+      )
+      .optional()?;
+    let Some((grant, expires_at, used_at, revoked_at)) = row else {
+      return Err(TokenError::UnknownRefreshToken);
+    };
+    if revoked_at.is_some() {
+      return Err(TokenError::Revoked);
+    }
+    if used_at.is_some() {
+      revoke_family(&tx, &grant.family, "reuse", now)?;
+      tx.commit()?;
+      return Err(TokenError::RefreshReused);
+    }
+    if expires_at <= now {
+      return Err(TokenError::RefreshExpired);
+    }
+    tx.execute(
+      "UPDATE refresh_tokens SET used_at = ?2 WHERE hash = ?1",
+      params![hash(token), now],
+    )?;
+    let next = insert_token(&tx, &grant.family, ttl, now)?;
+    tx.commit()?;
+    Ok((next, grant))
+  }
+
+  /// Revokes the family of `token`.
+  pub fn revoke(&self, token: &str, now: u64) -> Result<(), TokenError> {
+    let mut conn = self.conn.lock().unwrap();
+    let tx = conn.transaction()?;
+    let family: Option<String> = tx
+      .query_row(
+        "SELECT family FROM refresh_tokens WHERE hash = ?1",
+        [hash(token)],
+        |row| row.get(0),
+      )
+      .optional()?;
+    let family = family.ok_or(TokenError::UnknownRefreshToken)?;
+    revoke_family(&tx, &family, "revoked", now)?;
+    tx.commit()?;
+    Ok(())
+  }
+
+  /// Revokes every family of `subject`, returning how many were still live.
+  pub fn revoke_subject(&self, subject: &str, now: u64) -> Result<usize, TokenError> {
+    let conn = self.conn.lock().unwrap();
+    Ok(conn.execute(
+      "UPDATE families SET revoked_at = ?2, reason = 'revoked'
+       WHERE subject = ?1 AND revoked_at IS NULL",
+      params![subject, now],
+    )?)
+  }
+
+  /// Forgets tokens that expired before `now`, returning how many.
+  pub fn prune(&self, now: u64) -> Result<usize, TokenError> {
+    let conn = self.conn.lock().unwrap();
+    let pruned = conn.execute("DELETE FROM refresh_tokens WHERE expires_at <= ?1", [now])?;
+    conn.execute(
+      "DELETE FROM families
+       WHERE NOT EXISTS (SELECT 1 FROM refresh_tokens WHERE family = families.id)",
+      [],
+    )?;
+    Ok(pruned)
+  }
+}
+
+fn insert_token(
+  tx: &Transaction<'_>,
+  family: &str,
+  ttl: u64,
+  now: u64,
+) -> Result<String, TokenError> {
+  let token = random_token();
+  tx.execute(
+    "INSERT INTO refresh_tokens (hash, family, issued_at, expires_at) VALUES (?1, ?2, ?3, ?4)",
+    params![hash(&token), family, now, now.saturating_add(ttl)],
+  )?;
+  Ok(token)
+}
+
+fn revoke_family(
+  tx: &Transaction<'_>,
+  family: &str,
+  reason: &str,
+  now: u64,
+) -> Result<(), TokenError> {
+  tx.execute(
+    "UPDATE families SET revoked_at = ?2, reason = ?3 WHERE id = ?1 AND revoked_at IS NULL",
+    params![family, now, reason],
+  )?;
+  Ok(())
+}
+
+fn random_token() -> String {
+  URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+fn hash(token: &str) -> String {
+  URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const DAY: u64 = 24 * 60 * 60;
+
+  #[test]
+  fn rotation_detects_reuse() {
+    let store = RefreshStore::open_in_memory().unwrap();
+    let first = store.create("alice", "{}", DAY, 100).unwrap();
+    let (second, grant) = store.rotate(&first, DAY, 200).unwrap();
+    assert_eq!(grant.subject, "alice");
+    let (third, _) = store.rotate(&second, DAY, 300).unwrap();
+
+    // Replaying `first` revokes the family, including the newest token
+    assert!(matches!(
+      store.rotate(&first, DAY, 400),
+      Err(TokenError::RefreshReused)
+    ));
+    assert!(matches!(
+      store.rotate(&third, DAY, 500),
+      Err(TokenError::Revoked)
+    ));
+    assert!(matches!(
+      store.rotate("made up", DAY, 500),
+      Err(TokenError::UnknownRefreshToken)
+    ));
+  }
+
+  #[test]
+  fn revocation_and_expiry() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("tokens.db");
+    let store = RefreshStore::open(&path).unwrap();
+    let token = store.create("alice", "{}", DAY, 0).unwrap();
+    let other = store.create("alice", "{}", DAY, 0).unwrap();
+    let bob = store.create("bob", "{}", 10, 0).unwrap();
+    store.revoke(&token, 1).unwrap();
+    drop(store);
+
+    let store = RefreshStore::open(&path).unwrap();
+    assert!(matches!(
+      store.rotate(&token, DAY, 2),
+      Err(TokenError::Revoked)
+    ));
+    assert_eq!(store.revoke_subject("alice", 3).unwrap(), 1);
+    assert!(matches!(
+      store.rotate(&other, DAY, 4),
+      Err(TokenError::Revoked)
+    ));
+    assert!(matches!(
+      store.rotate(&bob, DAY, 10),
+      Err(TokenError::RefreshExpired)
+    ));
+    assert_eq!(store.prune(DAY + 1).unwrap(), 3);
+  }
+}
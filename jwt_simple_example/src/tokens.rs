@@ -0,0 +1,360 @@
+use std::{collections::HashSet, sync::RwLock, time};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jwt_simple::{prelude::*, JWTError};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  error::TokenError,
+  keys::{Algorithm, KeyInfo, KeyRing},
+  refresh::RefreshStore,
+  unix_now,
+};
+
+/// The claims of access tokens besides the registered ones.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AccessClaims {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub email: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenConfig {
+  pub issuer: String,
+  pub audience: String,
+  pub access_ttl: time::Duration,
+  pub refresh_ttl: time::Duration,
+  /// Clock skew tolerated when checking expiry.
+  pub leeway: time::Duration,
+  /// Keys kept for verification after a rotation.
+  pub retain_keys: usize,
+}
+
+impl Default for TokenConfig {
+  fn default() -> Self {
+    TokenConfig {
+      issuer: "jwt_simple_example".to_string(),
+      audience: "api".to_string(),
+      access_ttl: time::Duration::from_secs(15 * 60),
+      refresh_ttl: time::Duration::from_secs(30 * 24 * 60 * 60),
+      leeway: time::Duration::from_secs(60),
+      retain_keys: 2,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenPair {
+  pub access_token: String,
+  pub token_type: String,
+  /// Seconds.
+  pub expires_in: u64,
+  pub refresh_token: String,
+}
+
+pub struct TokenService {
+  config: TokenConfig,
+  keys: RwLock<KeyRing>,
+  refresh: RefreshStore,
+}
+
+impl TokenService {
+  pub fn new(config: TokenConfig, keys: KeyRing, refresh: RefreshStore) -> TokenService {
+    TokenService {
+      config,
+      keys: RwLock::new(keys),
+      refresh,
+    }
+  }
+
+  pub fn config(&self) -> &TokenConfig {
+    &self.config
+  }
+
+  /// Issues an access token for `subject` and starts a refresh token family. A client that sent
+  /// a `nonce` finds it in the access token.
+  pub fn issue(
+    &self,
+    subject: &str,
+    custom: AccessClaims,
+    nonce: Option<&str>,
+  ) -> Result<TokenPair, TokenError> {
+    let stored = serde_json::to_string(&custom).unwrap();
+    let refresh_token = self.refresh.create(
+      subject,
+      &stored,
+      self.config.refresh_ttl.as_secs(),
+      unix_now(),
+    )?;
+    self.pair(subject, custom, nonce, refresh_token)
+  }
+
+  /// Exchanges a refresh token for new tokens.
+  pub fn refresh(&self, refresh_token: &str, nonce: Option<&str>) -> Result<TokenPair, TokenError> {
+    let (refresh_token, grant) =
+      self
+        .refresh
+        .rotate(refresh_token, self.config.refresh_ttl.as_secs(), unix_now())?;
+    let custom = serde_json::from_str(&grant.claims).unwrap_or_default();
+    self.pair(&grant.subject, custom, nonce, refresh_token)
+  }
+
+  /// Revokes the family of `refresh_token`. Access tokens already issued stay valid until they
+  /// expire.
+  pub fn revoke(&self, refresh_token: &str) -> Result<(), TokenError> {
+    self.refresh.revoke(refresh_token, unix_now())
+  }
+
+  pub fn revoke_subject(&self, subject: &str) -> Result<usize, TokenError> {
+    self.refresh.revoke_subject(subject, unix_now())
+  }
+
+  /// Checks the signature, issuer, audience and expiry of `token`, and its nonce if one is
+  /// expected.
+  pub fn verify(
+    &self,
+    token: &str,
+    nonce: Option<&str>,
+  ) -> Result<JWTClaims<AccessClaims>, TokenError> {
+    let metadata = Token::decode_metadata(token).map_err(invalid)?;
+    let kid = metadata
+      .key_id()
+      .ok_or_else(|| TokenError::Invalid("no kid header".to_string()))?;
+    let keys = self.keys.read().unwrap();
+    let key = keys
+      .get(kid)
+      .ok_or_else(|| TokenError::UnknownKey(kid.to_string()))?;
+    let options = VerificationOptions {
+      allowed_issuers: Some(HashSet::from([self.config.issuer.clone()])),
+      allowed_audiences: Some(HashSet::from([self.config.audience.clone()])),
+      required_nonce: nonce.map(str::to_string),
+      time_tolerance: Some(Duration::from_secs(self.config.leeway.as_secs())),
+      ..Default::default()
+    };
+    key.verify(token, options).map_err(invalid)
+  }
+
+  /// Signs `claims` as they are with the current key.
+  pub fn sign(&self, claims: JWTClaims<AccessClaims>) -> Result<String, TokenError> {
+    let keys = self.keys.read().unwrap();
+    let key = keys.signing();
+    key.sign(claims).map_err(|e| TokenError::Key {
+      kid: key.info.kid.clone(),
+      message: e.to_string(),
+    })
+  }
+
+  /// The claims of an access token for `subject` issued now.
+  pub fn claims(
+    &self,
+    subject: &str,
+    custom: AccessClaims,
+    nonce: Option<&str>,
+  ) -> JWTClaims<AccessClaims> {
+    let ttl = Duration::from_secs(self.config.access_ttl.as_secs());
+    let jwt_id = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 12]>());
+    let claims = Claims::with_custom_claims(custom, ttl)
+      .with_issuer(&self.config.issuer)
+      .with_audience(&self.config.audience)
+      .with_subject(subject)
+      .with_jwt_id(jwt_id);
+    match nonce {
+      Some(nonce) => claims.with_nonce(nonce),
+      None => claims,
+    }
+  }
+
+  pub fn rotate(&self, alg: Algorithm) -> Result<KeyInfo, TokenError> {
+    let mut keys = self.keys.write().unwrap();
+    Ok(keys.rotate(alg, self.config.retain_keys)?.info.clone())
+  }
+
+  pub fn retire(&self, kid: &str) -> Result<bool, TokenError> {
+    self.keys.write().unwrap().retire(kid)
+  }
+
+  pub fn keys(&self) -> Vec<KeyInfo> {
+    self.keys.read().unwrap().keys()
+  }
+
+  pub fn jwks(&self) -> serde_json::Value {
+    self.keys.read().unwrap().jwks()
+  }
+
+  fn pair(
+    &self,
+    subject: &str,
+    custom: AccessClaims,
+    nonce: Option<&str>,
+    refresh_token: String,
+  ) -> Result<TokenPair, TokenError> {
+    Ok(TokenPair {
+      access_token: self.sign(self.claims(subject, custom, nonce))?,
+      token_type: "Bearer".to_string(),
+      expires_in: self.config.access_ttl.as_secs(),
+      refresh_token,
+    })
+  }
+}
+
+fn invalid(e: jwt_simple::Error) -> TokenError {
+  match e.downcast_ref::<JWTError>() {
+    Some(JWTError::TokenHasExpired) => TokenError::Expired,
+    _ => TokenError::Invalid(e.to_string()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use tempfile::TempDir;
+
+  use super::*;
+
+  fn service(dir: &TempDir, alg: Algorithm) -> TokenService {
+    TokenService::new(
+      TokenConfig::default(),
+      KeyRing::open(dir.path(), alg).unwrap(),
+      RefreshStore::open_in_memory().unwrap(),
+    )
+  }
+
+  fn email(email: &str) -> AccessClaims {
+    AccessClaims {
+      email: Some(email.to_string()),
+    }
+  }
+
+  #[test]
+  fn issued_tokens_verify_with_every_algorithm() {
+    for alg in [Algorithm::HS256, Algorithm::ES256, Algorithm::EdDSA] {
+      let dir = TempDir::new().unwrap();
+      let service = service(&dir, alg);
+      let pair = service
+        .issue("alice", email("alice@example.com"), Some("n0nce"))
+        .unwrap();
+      let claims = service.verify(&pair.access_token, Some("n0nce")).unwrap();
+      assert_eq!(claims.subject.as_deref(), Some("alice"));
+      assert_eq!(claims.custom, email("alice@example.com"));
+      assert!(matches!(
+        service.verify(&pair.access_token, Some("other")),
+        Err(TokenError::Invalid(_))
+      ));
+    }
+  }
+
+  #[test]
+  fn tampered_tokens_are_rejected() {
+    let dir = TempDir::new().unwrap();
+    let service = service(&dir, Algorithm::ES256);
+    let token = service
+      .issue("alice", email("a@example.com"), None)
+      .unwrap()
+      .access_token;
+    let parts: Vec<&str> = token.split('.').collect();
+
+    // A payload claiming to be someone else, keeping the signature
+    let mut payload: serde_json::Value =
+      serde_json::from_slice(&URL_SAFE_NO_PAD.decode(parts[1]).unwrap()).unwrap();
+    payload["sub"] = "mallory".into();
+    let forged = format!(
+      "{}.{}.{}",
+      parts[0],
+      URL_SAFE_NO_PAD.encode(payload.to_string()),
+      parts[2]
+    );
+    assert!(matches!(
+      service.verify(&forged, None),
+      Err(TokenError::Invalid(_))
+    ));
+
+    let mut signature = URL_SAFE_NO_PAD.decode(parts[2]).unwrap();
+    signature[0] ^= 1;
+    let resigned = format!(
+      "{}.{}.{}",
+      parts[0],
+      parts[1],
+      URL_SAFE_NO_PAD.encode(signature)
+    );
+    assert!(matches!(
+      service.verify(&resigned, None),
+      Err(TokenError::Invalid(_))
+    ));
+
+    // Signed by a key that merely claims the kid
+    let kid = service.keys()[0].kid.clone();
+    let impostor = ES256KeyPair::generate().with_key_id(&kid);
+    let claims = service.claims("mallory", AccessClaims::default(), None);
+    assert!(matches!(
+      service.verify(&impostor.sign(claims.clone()).unwrap(), None),
+      Err(TokenError::Invalid(_))
+    ));
+    // Or by an HMAC key, hoping the public key is used as the secret
+    let hmac = HS256Key::from_bytes(kid.as_bytes()).with_key_id(&kid);
+    assert!(matches!(
+      service.verify(&hmac.authenticate(claims).unwrap(), None),
+      Err(TokenError::Invalid(_))
+    ));
+    assert!(service.verify("not.a.token", None).is_err());
+  }
+
+  #[test]
+  fn expired_and_misaddressed_tokens_are_rejected() {
+    let dir = TempDir::new().unwrap();
+    let service = service(&dir, Algorithm::EdDSA);
+
+    let mut expired = service.claims("alice", AccessClaims::default(), None);
+    let an_hour_ago = Clock::now_since_epoch() - Duration::from_hours(1);
+    expired.issued_at = Some(an_hour_ago - Duration::from_mins(15));
+    expired.invalid_before = expired.issued_at;
+    expired.expires_at = Some(an_hour_ago);
+    assert!(matches!(
+      service.verify(&service.sign(expired).unwrap(), None),
+      Err(TokenError::Expired)
+    ));
+
+    let elsewhere = service
+      .claims("alice", AccessClaims::default(), None)
+      .with_audience("another api");
+    assert!(service
+      .verify(&service.sign(elsewhere).unwrap(), None)
+      .is_err());
+    let foreign = service
+      .claims("alice", AccessClaims::default(), None)
+      .with_issuer("someone else");
+    assert!(service
+      .verify(&service.sign(foreign).unwrap(), None)
+      .is_err());
+    // A nonce is required when expected
+    let token = service
+      .issue("alice", AccessClaims::default(), None)
+      .unwrap()
+      .access_token;
+    assert!(service.verify(&token, Some("n0nce")).is_err());
+  }
+
+  #[test]
+  fn rotation_keeps_recent_tokens_valid() {
+    let dir = TempDir::new().unwrap();
+    let service = service(&dir, Algorithm::ES256);
+    let old = service
+      .issue("alice", AccessClaims::default(), None)
+      .unwrap();
+    let old_kid = service.keys()[0].kid.clone();
+
+    let new_kid = service.rotate(Algorithm::EdDSA).unwrap().kid;
+    let new = service.refresh(&old.refresh_token, None).unwrap();
+    assert_eq!(
+      Token::decode_metadata(&new.access_token).unwrap().key_id(),
+      Some(new_kid.as_str())
+    );
+    assert!(service.verify(&old.access_token, None).is_ok());
+    assert_eq!(service.jwks()["keys"].as_array().unwrap().len(), 2);
+
+    assert!(service.retire(&old_kid).unwrap());
+    assert!(matches!(
+      service.verify(&old.access_token, None),
+      Err(TokenError::UnknownKey(_))
+    ));
+    assert!(service.verify(&new.access_token, None).is_ok());
+  }
+}
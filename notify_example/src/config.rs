@@ -0,0 +1,118 @@
+use std::{fs, path::Path, time::Duration};
+
+use serde::{de, Deserialize, Deserializer};
+
+use crate::{Action, Error, Rule, Watch};
+
+/// The `watch.toml` format, see the file next to `Cargo.toml` for an example.
+#[derive(Debug, Deserialize)]
+pub struct Config {
+  #[serde(default = "default_root")]
+  pub root: String,
+  #[serde(default = "default_debounce_ms")]
+  pub debounce_ms: u64,
+  #[serde(default, rename = "rule")]
+  pub rules: Vec<RuleConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RuleConfig {
+  pub name: String,
+  #[serde(default)]
+  pub include: Vec<String>,
+  #[serde(default)]
+  pub exclude: Vec<String>,
+  #[serde(default = "default_true")]
+  pub gitignore: bool,
+  /// Program followed by its arguments.
+  #[serde(deserialize_with = "command")]
+  pub command: Vec<String>,
+  #[serde(default)]
+  pub restart: bool,
+}
+
+fn default_root() -> String {
+  ".".to_string()
+}
+
+fn default_debounce_ms() -> u64 {
+  200
+}
+
+fn default_true() -> bool {
+  true
+}
+
+fn command<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+  let command = Vec::<String>::deserialize(deserializer)?;
+  if command.first().is_none_or(|program| program.is_empty()) {
+    return Err(de::Error::custom("command needs a program to run"));
+  }
+  Ok(command)
+}
+
+impl Config {
+  pub fn load(path: &Path) -> Result<Config, Error> {
+    Ok(toml::from_str(&fs::read_to_string(path)?)?)
+  }
+
+  pub fn into_watch(self) -> Watch {
+    let mut watch = Watch::new(&self.root).debounce(Duration::from_millis(self.debounce_ms));
+    for rule in self.rules {
+      let mut command = rule.command.into_iter();
+      let action = Action::Command {
+        program: command.next().unwrap_or_default(),
+        args: command.collect(),
+      };
+      let mut built = Rule::new(rule.name, action)
+        .gitignore(rule.gitignore)
+        .restart(rule.restart);
+      for glob in rule.include {
+        built = built.include(glob);
+      }
+      for glob in rule.exclude {
+        built = built.exclude(glob);
+      }
+      watch = watch.rule(built);
+    }
+    watch
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_rules_with_defaults() {
+    let config: Config = toml::from_str(
+      r#"
+      [[rule]]
+      name = "test"
+      include = ["src/**/*.rs"]
+      command = ["cargo", "test"]
+      restart = true
+      "#,
+    )
+    .unwrap();
+    assert_eq!(config.root, ".");
+    assert_eq!(config.debounce_ms, 200);
+    assert_eq!(config.rules.len(), 1);
+    assert!(config.rules[0].gitignore);
+    assert!(config.rules[0].restart);
+    assert_eq!(config.rules[0].command, vec!["cargo", "test"]);
+  }
+
+  #[test]
+  fn rejects_rules_without_a_program() {
+    for command in ["[]", r#"["", "x"]"#] {
+      let toml = format!("[[rule]]\nname = \"x\"\ncommand = {}\n", command);
+      let err = toml::from_str::<Config>(&toml).unwrap_err();
+      assert!(
+        err.to_string().contains("command needs a program"),
+        "{}",
+        err
+      );
+    }
+  }
+}
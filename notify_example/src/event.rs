@@ -0,0 +1,162 @@
+use std::{
+  collections::HashMap,
+  path::{Path, PathBuf},
+};
+
+use notify::event::{EventKind, ModifyKind, RenameMode};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileEventKind {
+  Created,
+  Modified,
+  Removed,
+  Renamed { from: PathBuf },
+}
+
+/// A change to one path, after the raw notify event has been classified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEvent {
+  pub kind: FileEventKind,
+  pub path: PathBuf,
+}
+
+impl FileEvent {
+  pub fn new(kind: FileEventKind, path: impl Into<PathBuf>) -> FileEvent {
+    FileEvent {
+      kind,
+      path: path.into(),
+    }
+  }
+
+  /// Every path the event touches, the old name of a rename included.
+  pub fn paths(&self) -> Vec<&Path> {
+    match &self.kind {
+      FileEventKind::Renamed { from } => vec![from.as_path(), self.path.as_path()],
+      _ => vec![self.path.as_path()],
+    }
+  }
+}
+
+/// Turns a notify event into typed events; access and unknown events are dropped.
+pub fn from_notify(event: notify::Event) -> Vec<FileEvent> {
+  let kind = match event.kind {
+    EventKind::Create(_) => FileEventKind::Created,
+    EventKind::Remove(_) => FileEventKind::Removed,
+    EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+      let mut paths = event.paths.into_iter();
+      let from = paths.next().unwrap();
+      let to = paths.next().unwrap();
+      return vec![FileEvent::new(FileEventKind::Renamed { from }, to)];
+    }
+    EventKind::Modify(ModifyKind::Name(RenameMode::From)) => FileEventKind::Removed,
+    EventKind::Modify(ModifyKind::Name(RenameMode::To)) => FileEventKind::Created,
+    EventKind::Modify(_) => FileEventKind::Modified,
+    EventKind::Access(_) | EventKind::Any | EventKind::Other => return vec![],
+  };
+  event
+    .paths
+    .into_iter()
+    .map(|path| FileEvent::new(kind.clone(), path))
+    .collect()
+}
+
+/// Folds a burst of events into at most one event per path, keeping the first-seen order.
+///
+/// Created then modified stays created, created then removed disappears, removed then created
+/// becomes modified, and a rename followed by edits of the new name stays a rename.
+pub fn coalesce(events: Vec<FileEvent>) -> Vec<FileEvent> {
+  let mut order: Vec<PathBuf> = vec![];
+  let mut merged: HashMap<PathBuf, Option<FileEventKind>> = HashMap::new();
+
+  for event in events {
+    let previous = match merged.get(&event.path) {
+      Some(previous) => previous.clone(),
+      None => {
+        order.push(event.path.clone());
+        None
+      }
+    };
+    use FileEventKind::*;
+    let next = match (previous, event.kind) {
+      (None, kind) => Some(kind),
+      (Some(Created), Modified) => Some(Created),
+      (Some(Created), Removed) => None,
+      (Some(Removed), Created) => Some(Modified),
+      (Some(Renamed { from }), Modified) => Some(Renamed { from }),
+      (Some(Renamed { from }), Removed) => {
+        // The file is gone under its new name, which for watchers means the old one went away.
+        merged.insert(event.path.clone(), None);
+        if !merged.contains_key(&from) {
+          order.push(from.clone());
+        }
+        merged.insert(from, Some(Removed));
+        continue;
+      }
+      (Some(_), kind) => Some(kind),
+    };
+    merged.insert(event.path, next);
+  }
+
+  order
+    .into_iter()
+    .filter_map(|path| {
+      let kind = merged.remove(&path)??;
+      Some(FileEvent { kind, path })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use notify::event::{CreateKind, DataChange};
+
+  use super::{FileEventKind::*, *};
+
+  fn ev(kind: FileEventKind, path: &str) -> FileEvent {
+    FileEvent::new(kind, path)
+  }
+
+  #[test]
+  fn classifies_notify_events() {
+    let rename = notify::Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+      .add_path("a".into())
+      .add_path("b".into());
+    assert_eq!(
+      from_notify(rename),
+      vec![ev(Renamed { from: "a".into() }, "b")]
+    );
+    let create = notify::Event::new(EventKind::Create(CreateKind::File)).add_path("c".into());
+    assert_eq!(from_notify(create), vec![ev(Created, "c")]);
+    let write =
+      notify::Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Any))).add_path("c".into());
+    assert_eq!(from_notify(write), vec![ev(Modified, "c")]);
+    let access = notify::Event::new(EventKind::Access(notify::event::AccessKind::Any));
+    assert!(from_notify(access).is_empty());
+  }
+
+  #[test]
+  fn coalesces_bursts() {
+    let events = vec![
+      ev(Created, "a"),
+      ev(Modified, "a"),
+      ev(Modified, "b"),
+      ev(Created, "tmp"),
+      ev(Modified, "tmp"),
+      ev(Removed, "tmp"),
+      ev(Removed, "c"),
+      ev(Created, "c"),
+      ev(Modified, "b"),
+      ev(Renamed { from: "d".into() }, "e"),
+      ev(Modified, "e"),
+    ];
+    assert_eq!(
+      coalesce(events),
+      vec![
+        ev(Created, "a"),
+        ev(Modified, "b"),
+        ev(Modified, "c"),
+        ev(Renamed { from: "d".into() }, "e"),
+      ]
+    );
+  }
+}
@@ -0,0 +1,111 @@
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+/// Decides which paths under the watched root a rule cares about.
+///
+/// Globs are matched against the path relative to the root, e.g. `src/**/*.rs`. A path must match
+/// one include glob (or there are none) and no exclude glob, and must not be ignored by the root
+/// `.gitignore` when that is enabled.
+#[derive(Debug, Clone)]
+pub struct Filter {
+  root: PathBuf,
+  include: Option<GlobSet>,
+  exclude: GlobSet,
+  gitignore: Option<Gitignore>,
+}
+
+impl Filter {
+  pub fn new(
+    root: &Path,
+    include: &[String],
+    exclude: &[String],
+    use_gitignore: bool,
+  ) -> Result<Filter, globset::Error> {
+    let include = match include.is_empty() {
+      true => None,
+      false => Some(glob_set(include)?),
+    };
+    let gitignore = use_gitignore.then(|| {
+      let mut builder = GitignoreBuilder::new(root);
+      // A missing .gitignore just means nothing extra is ignored.
+      _ = builder.add(root.join(".gitignore"));
+      _ = builder.add_line(None, ".git/");
+      builder.build().unwrap_or_else(|_| Gitignore::empty())
+    });
+    Ok(Filter {
+      root: root.to_path_buf(),
+      include,
+      exclude: glob_set(exclude)?,
+      gitignore,
+    })
+  }
+
+  /// Accepts everything under `root`.
+  pub fn all(root: &Path) -> Filter {
+    Filter::new(root, &[], &[], false).expect("no globs to parse")
+  }
+
+  pub fn matches(&self, path: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(&self.root) else {
+      return false;
+    };
+    if let Some(gitignore) = &self.gitignore {
+      if gitignore
+        .matched_path_or_any_parents(relative, path.is_dir())
+        .is_ignore()
+      {
+        return false;
+      }
+    }
+    if self.exclude.is_match(relative) {
+      return false;
+    }
+    match &self.include {
+      Some(include) => include.is_match(relative),
+      None => true,
+    }
+  }
+}
+
+fn glob_set(globs: &[String]) -> Result<GlobSet, globset::Error> {
+  let mut builder = GlobSetBuilder::new();
+  for glob in globs {
+    builder.add(Glob::new(glob)?);
+  }
+  builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use super::*;
+
+  #[test]
+  fn applies_globs_and_gitignore() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join(".gitignore"), "target/\n*.log\n").unwrap();
+    let filter = Filter::new(
+      root,
+      &["**/*.rs".to_string(), "Cargo.toml".to_string()],
+      &["**/generated/**".to_string()],
+      true,
+    )
+    .unwrap();
+
+    assert!(filter.matches(&root.join("src/main.rs")));
+    assert!(filter.matches(&root.join("Cargo.toml")));
+    assert!(!filter.matches(&root.join("README.md")));
+    assert!(!filter.matches(&root.join("src/generated/proto.rs")));
+    assert!(!filter.matches(&root.join("target/debug/build.rs")));
+    assert!(!filter.matches(&root.join(".git/hooks/x.rs")));
+    assert!(!filter.matches(Path::new("/elsewhere/main.rs")));
+
+    let all = Filter::all(root);
+    assert!(all.matches(&root.join("debug.log")));
+    assert!(all.matches(&root.join("target/x")));
+  }
+}
@@ -0,0 +1,267 @@
+//! A small `watchexec`: watch a directory, debounce the events and hand them to rules that run a
+//! command or an async callback.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), notify_example::Error> {
+//! use notify_example::{Action, Rule, Watch};
+//!
+//! let rule = Rule::new(
+//!   "test",
+//!   Action::Command {
+//!     program: "cargo".into(),
+//!     args: vec!["test".into()],
+//!   },
+//! )
+//! .include("src/**/*.rs")
+//! .gitignore(true)
+//! .restart(true);
+//! let handle = Watch::new(".").rule(rule).spawn()?;
+//! handle.wait().await;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+  fmt, io,
+  path::{Path, PathBuf},
+  time::Duration,
+};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::{
+  sync::mpsc,
+  task::JoinHandle,
+  time::{sleep_until, Instant},
+};
+
+pub mod config;
+pub mod event;
+pub mod filter;
+pub mod rule;
+
+pub use event::{FileEvent, FileEventKind};
+pub use filter::Filter;
+pub use rule::{Action, Rule};
+
+#[derive(Debug)]
+pub enum Error {
+  Io(io::Error),
+  Notify(notify::Error),
+  Glob(globset::Error),
+  Config(toml::de::Error),
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Error::Io(e) => write!(f, "io error: {}", e),
+      Error::Notify(e) => write!(f, "watch error: {}", e),
+      Error::Glob(e) => write!(f, "invalid glob: {}", e),
+      Error::Config(e) => write!(f, "invalid config: {}", e),
+    }
+  }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+  fn from(e: io::Error) -> Self {
+    Error::Io(e)
+  }
+}
+
+impl From<notify::Error> for Error {
+  fn from(e: notify::Error) -> Self {
+    Error::Notify(e)
+  }
+}
+
+impl From<globset::Error> for Error {
+  fn from(e: globset::Error) -> Self {
+    Error::Glob(e)
+  }
+}
+
+impl From<toml::de::Error> for Error {
+  fn from(e: toml::de::Error) -> Self {
+    Error::Config(e)
+  }
+}
+
+/// Builder for a recursive watch over one directory.
+pub struct Watch {
+  root: PathBuf,
+  debounce: Duration,
+  rules: Vec<Rule>,
+}
+
+impl Watch {
+  pub fn new(root: impl AsRef<Path>) -> Watch {
+    Watch {
+      root: root.as_ref().to_path_buf(),
+      debounce: Duration::from_millis(200),
+      rules: vec![],
+    }
+  }
+
+  /// Quiet period that has to pass before a burst of events is delivered.
+  pub fn debounce(mut self, debounce: Duration) -> Watch {
+    self.debounce = debounce;
+    self
+  }
+
+  pub fn rule(mut self, rule: Rule) -> Watch {
+    self.rules.push(rule);
+    self
+  }
+
+  /// Starts watching. Must be called inside a tokio runtime; dropping the handle stops everything.
+  pub fn spawn(self) -> Result<WatchHandle, Error> {
+    let root = self.root.canonicalize()?;
+    let mut rules = vec![];
+    for rule in self.rules {
+      let filter = rule.filter(&root)?;
+      let (tx, task) = rule.spawn_runner();
+      rules.push((filter, tx, task));
+    }
+
+    let (raw_tx, mut raw_rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |res| match res {
+      Ok(event) => {
+        _ = raw_tx.send(event);
+      }
+      Err(e) => eprintln!("watch error: {:?}", e),
+    })?;
+    watcher.watch(&root, RecursiveMode::Recursive)?;
+
+    let debounce = self.debounce;
+    let task = tokio::spawn(async move {
+      while let Some(batch) = next_batch(&mut raw_rx, debounce).await {
+        let events = event::coalesce(batch);
+        for (filter, tx, _) in &rules {
+          let matched: Vec<FileEvent> = events
+            .iter()
+            .filter(|e| e.paths().iter().any(|p| filter.matches(p)))
+            .cloned()
+            .collect();
+          if !matched.is_empty() {
+            _ = tx.send(matched);
+          }
+        }
+      }
+      for (_, tx, runner) in rules {
+        drop(tx);
+        _ = runner.await;
+      }
+    });
+
+    Ok(WatchHandle {
+      root,
+      watcher: Some(watcher),
+      task,
+    })
+  }
+}
+
+/// Waits for an event and collects those following it until things go quiet for `debounce`, but
+/// never holds a batch back for more than ten times that. `None` once the watcher is gone.
+async fn next_batch(
+  raw_rx: &mut mpsc::UnboundedReceiver<notify::Event>,
+  debounce: Duration,
+) -> Option<Vec<FileEvent>> {
+  let mut batch = event::from_notify(raw_rx.recv().await?);
+  let give_up = Instant::now() + debounce * 10;
+  loop {
+    let quiet = (Instant::now() + debounce).min(give_up);
+    tokio::select! {
+      next = raw_rx.recv() => match next {
+        Some(event) => batch.extend(event::from_notify(event)),
+        None => break,
+      },
+      _ = sleep_until(quiet) => break,
+    }
+  }
+  Some(batch)
+}
+
+pub struct WatchHandle {
+  root: PathBuf,
+  watcher: Option<RecommendedWatcher>,
+  task: JoinHandle<()>,
+}
+
+impl WatchHandle {
+  /// The canonical directory being watched; event paths start with it.
+  pub fn root(&self) -> &Path {
+    &self.root
+  }
+
+  /// Runs until the watcher fails.
+  pub async fn wait(mut self) {
+    _ = (&mut self.task).await;
+  }
+
+  /// Stops watching and kills whatever actions are still running.
+  pub async fn stop(mut self) {
+    self.watcher.take();
+    _ = (&mut self.task).await;
+  }
+}
+
+impl Drop for WatchHandle {
+  fn drop(&mut self) {
+    self.task.abort();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use notify::{event::CreateKind, EventKind};
+
+  use super::*;
+
+  const DEBOUNCE: Duration = Duration::from_millis(100);
+
+  fn created(path: &str) -> notify::Event {
+    notify::Event::new(EventKind::Create(CreateKind::File)).add_path(path.into())
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn batches_until_quiet() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let start = Instant::now();
+    tokio::spawn(async move {
+      for path in ["a", "b", "c"] {
+        _ = tx.send(created(path));
+        tokio::time::sleep(DEBOUNCE / 2).await;
+      }
+      tokio::time::sleep(DEBOUNCE * 2).await;
+      _ = tx.send(created("d"));
+    });
+
+    let batch = next_batch(&mut rx, DEBOUNCE).await.unwrap();
+    assert_eq!(batch.len(), 3);
+    // The last event came at 100ms, then 100ms without any
+    assert_eq!(start.elapsed(), DEBOUNCE * 2);
+    let batch = next_batch(&mut rx, DEBOUNCE).await.unwrap();
+    assert_eq!(batch, vec![FileEvent::new(FileEventKind::Created, "d")]);
+    assert!(next_batch(&mut rx, DEBOUNCE).await.is_none());
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn delivers_a_steady_stream_in_time() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let start = Instant::now();
+    tokio::spawn(async move {
+      loop {
+        if tx.send(created("busy")).is_err() {
+          break;
+        }
+        tokio::time::sleep(DEBOUNCE / 2).await;
+      }
+    });
+
+    next_batch(&mut rx, DEBOUNCE).await.unwrap();
+    assert_eq!(start.elapsed(), DEBOUNCE * 10);
+  }
+}
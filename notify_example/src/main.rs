@@ -1,26 +1,26 @@
 use std::path::Path;
 
-use notify::{RecursiveMode, Result, Watcher};
-use tokio::sync::watch;
+use notify_example::{config::Config, Action, Error, Rule, Watch};
 
+// cargo run              -> prints every change under .
+// cargo run -- watch.toml -> runs the commands configured per rule
 #[tokio::main]
-async fn main() -> Result<()> {
-  // Automatically select the best implementation for your platform.
-  let (tx, mut rx) = watch::channel(());
+async fn main() -> Result<(), Error> {
+  let watch = match std::env::args().nth(1) {
+    Some(path) => Config::load(Path::new(&path))?.into_watch(),
+    None => Watch::new(".").rule(
+      Rule::new(
+        "print",
+        Action::callback(|events| async move {
+          for event in events {
+            println!("file changed: {:?} {}", event.kind, event.path.display());
+          }
+        }),
+      )
+      .gitignore(true),
+    ),
+  };
 
-  let mut watcher = notify::recommended_watcher(move |res| match res {
-    Ok(_event) => {
-      _ = tx.send(());
-    }
-    Err(e) => println!("watch error: {:?}", e),
-  })?;
-
-  // Add a path to be watched. All files and directories at that path and
-  // below will be monitored for changes.
-  watcher.watch(Path::new("."), RecursiveMode::Recursive)?;
-
-  loop {
-    _ = rx.changed().await;
-    println!("file changed");
-  }
+  watch.spawn()?.wait().await;
+  Ok(())
 }
@@ -0,0 +1,211 @@
+use std::{future::Future, path::Path, pin::Pin, process::Stdio, sync::Arc};
+
+use tokio::{
+  process::Command,
+  sync::mpsc::{self, UnboundedReceiver},
+  task::JoinHandle,
+};
+
+use crate::{
+  event::{coalesce, FileEvent},
+  filter::Filter,
+  Error,
+};
+
+pub type Callback =
+  Arc<dyn Fn(Vec<FileEvent>) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+/// What to do with a batch of events that passed a rule's filter.
+#[derive(Clone)]
+pub enum Action {
+  /// Runs `program` with the changed paths in `WATCH_PATHS`, one per line.
+  Command {
+    program: String,
+    args: Vec<String>,
+  },
+  Callback(Callback),
+}
+
+impl Action {
+  pub fn callback<F, Fut>(f: F) -> Action
+  where
+    F: Fn(Vec<FileEvent>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+  {
+    Action::Callback(Arc::new(move |events| Box::pin(f(events))))
+  }
+
+  /// Starts the action; aborting the returned task kills a running command.
+  fn start(&self, events: Vec<FileEvent>) -> JoinHandle<()> {
+    match self {
+      Action::Command { program, args } => {
+        let paths: Vec<String> = events
+          .iter()
+          .map(|e| e.path.display().to_string())
+          .collect();
+        let mut command = Command::new(program);
+        command
+          .args(args)
+          .env("WATCH_PATHS", paths.join("\n"))
+          .stdin(Stdio::null())
+          .kill_on_drop(true);
+        tokio::spawn(async move {
+          match command.spawn() {
+            Ok(mut child) => {
+              _ = child.wait().await;
+            }
+            Err(e) => eprintln!("watch: cannot run command: {}", e),
+          }
+        })
+      }
+      Action::Callback(callback) => tokio::spawn(callback(events)),
+    }
+  }
+}
+
+/// A filter plus the action to run when something it matches changes.
+#[derive(Clone)]
+pub struct Rule {
+  pub name: String,
+  include: Vec<String>,
+  exclude: Vec<String>,
+  gitignore: bool,
+  restart: bool,
+  action: Action,
+}
+
+impl Rule {
+  pub fn new(name: impl Into<String>, action: Action) -> Rule {
+    Rule {
+      name: name.into(),
+      include: vec![],
+      exclude: vec![],
+      gitignore: false,
+      restart: false,
+      action,
+    }
+  }
+
+  pub fn include(mut self, glob: impl Into<String>) -> Rule {
+    self.include.push(glob.into());
+    self
+  }
+
+  pub fn exclude(mut self, glob: impl Into<String>) -> Rule {
+    self.exclude.push(glob.into());
+    self
+  }
+
+  /// Skip paths ignored by the root `.gitignore` (and everything in `.git/`).
+  pub fn gitignore(mut self, gitignore: bool) -> Rule {
+    self.gitignore = gitignore;
+    self
+  }
+
+  /// Kill a still running action when new changes arrive instead of waiting for it to finish.
+  pub fn restart(mut self, restart: bool) -> Rule {
+    self.restart = restart;
+    self
+  }
+
+  pub(crate) fn filter(&self, root: &Path) -> Result<Filter, Error> {
+    Ok(Filter::new(
+      root,
+      &self.include,
+      &self.exclude,
+      self.gitignore,
+    )?)
+  }
+
+  /// Runs the action for every batch, at most one instance at a time.
+  pub(crate) fn spawn_runner(self) -> (mpsc::UnboundedSender<Vec<FileEvent>>, JoinHandle<()>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let task = tokio::spawn(self.run(rx));
+    (tx, task)
+  }
+
+  async fn run(self, mut rx: UnboundedReceiver<Vec<FileEvent>>) {
+    let mut running: Option<JoinHandle<()>> = None;
+    while let Some(mut events) = rx.recv().await {
+      if let Some(task) = running.take() {
+        if self.restart {
+          task.abort();
+        }
+        _ = task.await;
+      }
+      // Anything that arrived while waiting goes into the same run.
+      while let Ok(more) = rx.try_recv() {
+        events.extend(more);
+      }
+      running = Some(self.action.start(coalesce(events)));
+    }
+    if let Some(task) = running {
+      task.abort();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{sync::Mutex, time::Duration};
+
+  use tokio::time::sleep;
+
+  use super::*;
+  use crate::event::FileEventKind;
+
+  /// A rule whose action takes a second, logging when each run starts and finishes.
+  fn slow_rule(restart: bool) -> (Rule, Arc<Mutex<Vec<String>>>) {
+    let log = Arc::new(Mutex::new(vec![]));
+    let runs = log.clone();
+    let rule = Rule::new(
+      "slow",
+      Action::callback(move |events: Vec<FileEvent>| {
+        let log = runs.clone();
+        async move {
+          let path = events[0].path.display().to_string();
+          log.lock().unwrap().push(format!("start {}", path));
+          sleep(Duration::from_secs(1)).await;
+          log.lock().unwrap().push(format!("done {}", path));
+        }
+      }),
+    )
+    .restart(restart);
+    (rule, log)
+  }
+
+  fn batch(path: &str) -> Vec<FileEvent> {
+    vec![FileEvent::new(FileEventKind::Modified, path)]
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn restarts_a_running_action() {
+    let (rule, log) = slow_rule(true);
+    let (tx, task) = rule.spawn_runner();
+    tx.send(batch("a")).unwrap();
+    sleep(Duration::from_millis(500)).await;
+    tx.send(batch("b")).unwrap();
+    sleep(Duration::from_secs(2)).await;
+    assert_eq!(*log.lock().unwrap(), ["start a", "start b", "done b"]);
+    drop(tx);
+    task.await.unwrap();
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn waits_for_a_running_action() {
+    let (rule, log) = slow_rule(false);
+    let (tx, task) = rule.spawn_runner();
+    tx.send(batch("a")).unwrap();
+    sleep(Duration::from_millis(500)).await;
+    // Both arrive while `a` runs, and run together after it
+    tx.send(batch("b")).unwrap();
+    tx.send(batch("c")).unwrap();
+    sleep(Duration::from_secs(3)).await;
+    assert_eq!(
+      *log.lock().unwrap(),
+      ["start a", "done a", "start b", "done b"]
+    );
+    drop(tx);
+    task.await.unwrap();
+  }
+}
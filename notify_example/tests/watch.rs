@@ -0,0 +1,166 @@
+use std::{fs, path::Path, time::Duration};
+
+use notify_example::{Action, FileEvent, FileEventKind, Rule, Watch};
+use tokio::{sync::mpsc, time::timeout};
+
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+async fn next_batch(rx: &mut mpsc::UnboundedReceiver<Vec<FileEvent>>) -> Vec<FileEvent> {
+  timeout(Duration::from_secs(5), rx.recv())
+    .await
+    .expect("no events within 5s")
+    .expect("watcher stopped")
+}
+
+fn kinds_for(events: &[FileEvent], path: &Path) -> Vec<FileEventKind> {
+  events
+    .iter()
+    .filter(|e| e.path == path)
+    .map(|e| e.kind.clone())
+    .collect()
+}
+
+#[tokio::test]
+async fn delivers_typed_filtered_events() {
+  let dir = tempfile::tempdir().unwrap();
+  fs::write(dir.path().join(".gitignore"), "*.log\n").unwrap();
+  let (tx, mut rx) = mpsc::unbounded_channel();
+  let rule = Rule::new(
+    "rust",
+    Action::callback(move |events| {
+      let tx = tx.clone();
+      async move {
+        _ = tx.send(events);
+      }
+    }),
+  )
+  .include("**/*.rs")
+  .exclude("skip/**")
+  .gitignore(true);
+  let handle = Watch::new(dir.path())
+    .debounce(DEBOUNCE)
+    .rule(rule)
+    .spawn()
+    .unwrap();
+  let root = handle.root().to_path_buf();
+  tokio::time::sleep(DEBOUNCE).await;
+
+  fs::create_dir(root.join("skip")).unwrap();
+  fs::write(root.join("skip/x.rs"), "x").unwrap();
+  fs::write(root.join("debug.log"), "x").unwrap();
+  fs::write(root.join("notes.txt"), "x").unwrap();
+  fs::write(root.join("a.rs"), "fn main() {}").unwrap();
+  let events = next_batch(&mut rx).await;
+  assert_eq!(
+    events,
+    vec![FileEvent::new(FileEventKind::Created, root.join("a.rs"))]
+  );
+
+  fs::rename(root.join("a.rs"), root.join("b.rs")).unwrap();
+  let events = next_batch(&mut rx).await;
+  assert_eq!(
+    kinds_for(&events, &root.join("b.rs")),
+    vec![FileEventKind::Renamed {
+      from: root.join("a.rs")
+    }]
+  );
+
+  fs::remove_file(root.join("b.rs")).unwrap();
+  let events = next_batch(&mut rx).await;
+  assert_eq!(
+    kinds_for(&events, &root.join("b.rs")),
+    vec![FileEventKind::Removed]
+  );
+
+  handle.stop().await;
+}
+
+#[tokio::test]
+async fn coalesces_a_burst_into_one_batch() {
+  let dir = tempfile::tempdir().unwrap();
+  let (tx, mut rx) = mpsc::unbounded_channel();
+  let rule = Rule::new(
+    "all",
+    Action::callback(move |events| {
+      let tx = tx.clone();
+      async move {
+        _ = tx.send(events);
+      }
+    }),
+  );
+  let handle = Watch::new(dir.path())
+    .debounce(DEBOUNCE)
+    .rule(rule)
+    .spawn()
+    .unwrap();
+  let root = handle.root().to_path_buf();
+  tokio::time::sleep(DEBOUNCE).await;
+
+  for i in 0 .. 5 {
+    fs::write(root.join("burst.txt"), i.to_string()).unwrap();
+  }
+  fs::write(root.join("tmp"), "x").unwrap();
+  fs::remove_file(root.join("tmp")).unwrap();
+
+  let events = next_batch(&mut rx).await;
+  assert_eq!(
+    events,
+    vec![FileEvent::new(
+      FileEventKind::Created,
+      root.join("burst.txt")
+    )]
+  );
+  assert!(timeout(DEBOUNCE * 3, rx.recv()).await.is_err());
+}
+
+#[tokio::test]
+async fn restarts_a_running_command() {
+  let dir = tempfile::tempdir().unwrap();
+  let src = dir.path().join("src");
+  let out = tempfile::tempdir().unwrap();
+  let log = out.path().join("log");
+  fs::create_dir(&src).unwrap();
+  let rule = Rule::new(
+    "server",
+    Action::Command {
+      program: "sh".into(),
+      args: vec![
+        "-c".into(),
+        format!(
+          "echo start >> {0}; sleep 0.5; echo done >> {0}",
+          log.display()
+        ),
+      ],
+    },
+  )
+  .restart(true);
+  let handle = Watch::new(&src)
+    .debounce(DEBOUNCE)
+    .rule(rule)
+    .spawn()
+    .unwrap();
+  tokio::time::sleep(DEBOUNCE).await;
+
+  // The timing of rules is tested with paused time next to them, this only checks the command
+  // is killed.
+  fs::write(src.join("main.rs"), "1").unwrap();
+  tokio::time::sleep(DEBOUNCE * 3).await;
+  fs::write(src.join("main.rs"), "2").unwrap();
+  timeout(Duration::from_secs(5), async {
+    while !fs::read_to_string(&log)
+      .unwrap_or_default()
+      .contains("done")
+    {
+      tokio::time::sleep(DEBOUNCE).await;
+    }
+  })
+  .await
+  .expect("the command never finished");
+
+  assert_eq!(
+    fs::read_to_string(&log).unwrap(),
+    "start\nstart\ndone\n",
+    "the first run should have been killed before it finished"
+  );
+  handle.stop().await;
+}
@@ -0,0 +1,314 @@
+// Writes the captures under tests/data the tests read:
+// cargo run --example make_captures
+use std::{
+  borrow::Cow,
+  error::Error,
+  fs::File,
+  net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+  str::FromStr,
+  time::Duration,
+};
+
+use pcap_file::{
+  pcap::{PcapHeader, PcapPacket, PcapWriter},
+  pcapng::{
+    blocks::{
+      enhanced_packet::EnhancedPacketBlock,
+      interface_description::{InterfaceDescriptionBlock, InterfaceDescriptionOption},
+    },
+    PcapNgWriter,
+  },
+  DataLink, TsResolution,
+};
+use pnet::{
+  packet::{
+    ethernet::{EtherType, EtherTypes, MutableEthernetPacket},
+    icmp::{self, IcmpTypes, MutableIcmpPacket},
+    icmpv6::{self, Icmpv6Types, MutableIcmpv6Packet},
+    ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
+    ipv4::{self, MutableIpv4Packet},
+    ipv6::MutableIpv6Packet,
+    tcp::{self, MutableTcpPacket, TcpFlags},
+    udp::{self, MutableUdpPacket},
+    vlan::MutableVlanPacket,
+  },
+  util::MacAddr,
+};
+use trust_dns_proto::{
+  op::{Message, MessageType, Query},
+  rr::{Name, RData, Record, RecordType},
+};
+
+/// 2023-11-14 22:13:20 UTC
+const EPOCH: u64 = 1_700_000_000;
+
+fn at(ms: u64) -> Duration {
+  Duration::from_secs(EPOCH) + Duration::from_millis(ms)
+}
+
+fn tcp(
+  from: SocketAddr,
+  to: SocketAddr,
+  flags: u8,
+  sequence: u32,
+  acknowledgement: u32,
+  payload: &[u8],
+) -> Vec<u8> {
+  let mut buf = vec![0; 20 + payload.len()];
+  let mut tcp = MutableTcpPacket::new(&mut buf).unwrap();
+  tcp.set_source(from.port());
+  tcp.set_destination(to.port());
+  tcp.set_sequence(sequence);
+  tcp.set_acknowledgement(acknowledgement);
+  tcp.set_data_offset(5);
+  tcp.set_flags(flags);
+  tcp.set_window(64240);
+  tcp.set_payload(payload);
+  let checksum = match (from.ip(), to.ip()) {
+    (IpAddr::V4(from), IpAddr::V4(to)) => tcp::ipv4_checksum(&tcp.to_immutable(), &from, &to),
+    (IpAddr::V6(from), IpAddr::V6(to)) => tcp::ipv6_checksum(&tcp.to_immutable(), &from, &to),
+    _ => unreachable!(),
+  };
+  tcp.set_checksum(checksum);
+  ip(from.ip(), to.ip(), IpNextHeaderProtocols::Tcp, &buf)
+}
+
+fn udp(from: SocketAddr, to: SocketAddr, payload: &[u8]) -> Vec<u8> {
+  let mut buf = vec![0; 8 + payload.len()];
+  let mut udp = MutableUdpPacket::new(&mut buf).unwrap();
+  udp.set_source(from.port());
+  udp.set_destination(to.port());
+  udp.set_length(8 + payload.len() as u16);
+  udp.set_payload(payload);
+  let checksum = match (from.ip(), to.ip()) {
+    (IpAddr::V4(from), IpAddr::V4(to)) => udp::ipv4_checksum(&udp.to_immutable(), &from, &to),
+    (IpAddr::V6(from), IpAddr::V6(to)) => udp::ipv6_checksum(&udp.to_immutable(), &from, &to),
+    _ => unreachable!(),
+  };
+  udp.set_checksum(checksum);
+  ip(from.ip(), to.ip(), IpNextHeaderProtocols::Udp, &buf)
+}
+
+/// An echo request, or reply, with 8 bytes of data.
+fn ping(from: Ipv4Addr, to: Ipv4Addr, reply: bool) -> Vec<u8> {
+  let mut buf = vec![0; 16];
+  let mut icmp = MutableIcmpPacket::new(&mut buf).unwrap();
+  icmp.set_icmp_type(if reply {
+    IcmpTypes::EchoReply
+  } else {
+    IcmpTypes::EchoRequest
+  });
+  icmp.set_payload(&[0, 1, 0, 1, b'p', b'i', b'n', b'g']);
+  icmp.set_checksum(icmp::checksum(&icmp.to_immutable()));
+  ip(from.into(), to.into(), IpNextHeaderProtocols::Icmp, &buf)
+}
+
+fn ping6(from: Ipv6Addr, to: Ipv6Addr) -> Vec<u8> {
+  let mut buf = vec![0; 16];
+  let mut icmp = MutableIcmpv6Packet::new(&mut buf).unwrap();
+  icmp.set_icmpv6_type(Icmpv6Types::EchoRequest);
+  icmp.set_payload(&[0, 2, 0, 1, b'p', b'i', b'n', b'g']);
+  icmp.set_checksum(icmpv6::checksum(&icmp.to_immutable(), &from, &to));
+  ip(from.into(), to.into(), IpNextHeaderProtocols::Icmpv6, &buf)
+}
+
+fn ip(from: IpAddr, to: IpAddr, protocol: IpNextHeaderProtocol, payload: &[u8]) -> Vec<u8> {
+  match (from, to) {
+    (IpAddr::V4(from), IpAddr::V4(to)) => {
+      let mut buf = vec![0; 20 + payload.len()];
+      let mut ip = MutableIpv4Packet::new(&mut buf).unwrap();
+      ip.set_version(4);
+      ip.set_header_length(5);
+      ip.set_total_length(20 + payload.len() as u16);
+      ip.set_ttl(64);
+      ip.set_next_level_protocol(protocol);
+      ip.set_source(from);
+      ip.set_destination(to);
+      ip.set_payload(payload);
+      ip.set_checksum(ipv4::checksum(&ip.to_immutable()));
+      buf
+    }
+    (IpAddr::V6(from), IpAddr::V6(to)) => {
+      let mut buf = vec![0; 40 + payload.len()];
+      let mut ip = MutableIpv6Packet::new(&mut buf).unwrap();
+      ip.set_version(6);
+      ip.set_payload_length(payload.len() as u16);
+      ip.set_next_header(protocol);
+      ip.set_hop_limit(64);
+      ip.set_source(from);
+      ip.set_destination(to);
+      ip.set_payload(payload);
+      buf
+    }
+    _ => unreachable!(),
+  }
+}
+
+/// An Ethernet frame, tagged when `vlan` is set.
+fn ethernet(ethertype: EtherType, vlan: Option<u16>, payload: &[u8]) -> Vec<u8> {
+  let (ethertype, payload) = match vlan {
+    Some(id) => {
+      let mut buf = vec![0; 4 + payload.len()];
+      let mut vlan = MutableVlanPacket::new(&mut buf).unwrap();
+      vlan.set_vlan_identifier(id);
+      vlan.set_ethertype(ethertype);
+      vlan.set_payload(payload);
+      (EtherTypes::Vlan, buf)
+    }
+    None => (ethertype, payload.to_vec()),
+  };
+  // Padded to the 60 bytes minimum
+  let mut buf = vec![0; (14 + payload.len()).max(60)];
+  let mut ethernet = MutableEthernetPacket::new(&mut buf).unwrap();
+  ethernet.set_source(MacAddr::new(0x02, 0, 0, 0, 0, 1));
+  ethernet.set_destination(MacAddr::new(0x02, 0, 0, 0, 0, 2));
+  ethernet.set_ethertype(ethertype);
+  ethernet.set_payload(&payload);
+  buf
+}
+
+fn dns(id: u16, response: bool) -> Vec<u8> {
+  let name = Name::from_str("example.com.").unwrap();
+  let mut message = Message::new();
+  message
+    .set_id(id)
+    .set_recursion_desired(true)
+    .add_query(Query::query(name.clone(), RecordType::A));
+  if response {
+    message
+      .set_message_type(MessageType::Response)
+      .add_answer(Record::from_rdata(
+        name,
+        3600,
+        RData::A(Ipv4Addr::new(93, 184, 216, 34)),
+      ));
+  }
+  message.to_vec().unwrap()
+}
+
+/// An HTTP exchange over IPv4: the second segment of the response overtakes the first, and is
+/// sent again after being acked.
+fn http() -> Result<(), Box<dyn Error>> {
+  let client: SocketAddr = "192.168.1.10:51000".parse()?;
+  let server: SocketAddr = "93.184.216.34:80".parse()?;
+  let request = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+  let head = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n";
+  let body = b"hello";
+  let (c, s) = (1000u32, 5000u32);
+  let requested = c + 1 + request.len() as u32;
+  let body_at = s + 1 + head.len() as u32;
+  let responded = body_at + body.len() as u32;
+  let (syn, ack, fin) = (TcpFlags::SYN, TcpFlags::ACK, TcpFlags::FIN | TcpFlags::ACK);
+  let push = TcpFlags::PSH | TcpFlags::ACK;
+  let up = |flags, sequence, acknowledgement, payload| {
+    tcp(client, server, flags, sequence, acknowledgement, payload)
+  };
+  let down = |flags, sequence, acknowledgement, payload| {
+    tcp(server, client, flags, sequence, acknowledgement, payload)
+  };
+
+  let frames = [
+    (0, up(syn, c, 0, b"")),
+    (20, down(syn | ack, s, c + 1, b"")),
+    (21, up(ack, c + 1, s + 1, b"")),
+    (22, up(push, c + 1, s + 1, request)),
+    (45, down(ack, s + 1, requested, b"")),
+    (46, down(push, body_at, requested, body)),
+    (47, down(ack, s + 1, requested, head)),
+    (48, up(ack, requested, responded, b"")),
+    (60, down(push, body_at, requested, body)),
+    (70, up(fin, requested, responded, b"")),
+    (90, down(fin, responded, requested + 1, b"")),
+    (91, up(ack, requested + 1, responded + 1, b"")),
+  ];
+  let mut writer = PcapWriter::with_header(
+    File::create("tests/data/http.pcap")?,
+    PcapHeader {
+      datalink: DataLink::ETHERNET,
+      ts_resolution: TsResolution::MicroSecond,
+      ..Default::default()
+    },
+  )?;
+  // Something else than IP first
+  let mut arp = vec![0; 28];
+  arp[.. 8].copy_from_slice(&[0, 1, 8, 0, 6, 4, 0, 1]);
+  let frames = [(0, ethernet(EtherTypes::Arp, None, &arp))]
+    .into_iter()
+    .chain(
+      frames
+        .into_iter()
+        .map(|(ms, packet)| (ms, ethernet(EtherTypes::Ipv4, None, &packet))),
+    );
+  for (ms, frame) in frames {
+    writer.write_packet(&PcapPacket::new(at(ms), frame.len() as u32, &frame))?;
+  }
+  Ok(())
+}
+
+/// Two interfaces: Ethernet at the default microsecond resolution, with a VLAN-tagged IPv6
+/// connection reset after the handshake and a DNS lookup, and raw IP at nanoseconds with pings.
+fn mixed() -> Result<(), Box<dyn Error>> {
+  let client: SocketAddr = "[2001:db8::1]:40000".parse()?;
+  let server: SocketAddr = "[2001:db8::2]:443".parse()?;
+  let resolver: SocketAddr = "10.0.0.53:53".parse()?;
+  let stub: SocketAddr = "10.0.0.1:53000".parse()?;
+  let (v4, v4_peer) = (Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2));
+  let (v6, v6_peer) = ("2001:db8::1".parse()?, "2001:db8::2".parse()?);
+  let (syn, ack) = (TcpFlags::SYN, TcpFlags::ACK);
+  let up =
+    |flags, sequence, acknowledgement| tcp(client, server, flags, sequence, acknowledgement, b"");
+  let down =
+    |flags, sequence, acknowledgement| tcp(server, client, flags, sequence, acknowledgement, b"");
+
+  let dns_frames = [
+    (0, udp(stub, resolver, &dns(0xbeef, false))),
+    (5, udp(resolver, stub, &dns(0xbeef, true))),
+  ];
+  let tcp_frames = [
+    (10, up(syn, 7, 0)),
+    (15, down(syn | ack, 70, 8)),
+    (16, up(ack, 8, 71)),
+    (17, up(TcpFlags::RST, 8, 0)),
+  ];
+  let raw_frames = [
+    (20, ping(v4, v4_peer, false)),
+    (21, ping(v4_peer, v4, true)),
+    (22, ping6(v6, v6_peer)),
+  ];
+
+  let mut writer = PcapNgWriter::new(File::create("tests/data/mixed.pcapng")?)?;
+  writer.write_pcapng_block(InterfaceDescriptionBlock::new(DataLink::ETHERNET, 65535))?;
+  writer.write_pcapng_block(InterfaceDescriptionBlock {
+    linktype: DataLink::RAW,
+    snaplen: 65535,
+    options: vec![InterfaceDescriptionOption::IfTsResol(9)],
+  })?;
+  // pcap-file writes the timestamp as nanoseconds whatever the resolution: scale it by hand
+  let mut packets = vec![];
+  let ethernet_frames = dns_frames
+    .map(|(ms, packet)| (ms, ethernet(EtherTypes::Ipv4, None, &packet)))
+    .into_iter()
+    .chain(tcp_frames.map(|(ms, packet)| (ms, ethernet(EtherTypes::Ipv6, Some(100), &packet))));
+  for (ms, frame) in ethernet_frames {
+    let micros = at(ms).as_micros() as u64;
+    packets.push((0, Duration::from_nanos(micros), frame));
+  }
+  for (ms, packet) in raw_frames {
+    packets.push((1, at(ms), packet));
+  }
+  for (interface_id, timestamp, data) in packets {
+    writer.write_pcapng_block(EnhancedPacketBlock {
+      interface_id,
+      timestamp,
+      original_len: data.len() as u32,
+      data: Cow::Owned(data),
+      options: vec![],
+    })?;
+  }
+  Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+  http()?;
+  mixed()
+}
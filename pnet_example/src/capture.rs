@@ -0,0 +1,211 @@
+use std::{
+  fs::File,
+  io::{BufRead, BufReader},
+  path::{Path, PathBuf},
+  time::Duration,
+};
+
+use pcap_file::{
+  pcap::PcapReader,
+  pcapng::{blocks::interface_description::InterfaceDescriptionOption, Block, PcapNgReader},
+  DataLink,
+};
+
+use crate::error::CaptureError;
+
+/// How a frame starts, the link layers the decoder knows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkType {
+  Ethernet,
+  /// Linux "cooked" capture, what `tcpdump -i any` writes.
+  LinuxSll,
+  /// A bare IPv4 or IPv6 packet.
+  Raw,
+  /// BSD loopback: the address family, in host byte order.
+  Null,
+  Other(u32),
+}
+
+impl From<DataLink> for LinkType {
+  fn from(link: DataLink) -> Self {
+    match link {
+      DataLink::ETHERNET => LinkType::Ethernet,
+      DataLink::LINUX_SLL => LinkType::LinuxSll,
+      DataLink::RAW | DataLink::IPV4 | DataLink::IPV6 => LinkType::Raw,
+      DataLink::NULL | DataLink::LOOP => LinkType::Null,
+      other => LinkType::Other(other.into()),
+    }
+  }
+}
+
+/// A captured frame.
+#[derive(Debug, Clone)]
+pub struct Frame {
+  /// Since the Unix epoch.
+  pub timestamp: Duration,
+  pub link: LinkType,
+  /// Length on the wire, `data` may have been cut short by the snap length.
+  pub original_len: u32,
+  pub data: Vec<u8>,
+}
+
+enum Reader {
+  Pcap(PcapReader<BufReader<File>>),
+  PcapNg(PcapNgReader<BufReader<File>>),
+}
+
+/// Frames of a pcap or pcapng file, told apart by their magic number.
+pub struct Capture {
+  path: PathBuf,
+  reader: Reader,
+}
+
+const PCAPNG_MAGIC: [u8; 4] = [0x0a, 0x0d, 0x0d, 0x0a];
+const PCAP_MAGICS: [[u8; 4]; 4] = [
+  [0xa1, 0xb2, 0xc3, 0xd4],
+  [0xd4, 0xc3, 0xb2, 0xa1],
+  // Nanosecond timestamps
+  [0xa1, 0xb2, 0x3c, 0x4d],
+  [0x4d, 0x3c, 0xb2, 0xa1],
+];
+
+impl Capture {
+  pub fn open(path: &Path) -> Result<Capture, CaptureError> {
+    let io_error = |source| CaptureError::Io {
+      path: path.to_path_buf(),
+      source,
+    };
+    let pcap_error = |source| CaptureError::Pcap {
+      path: path.to_path_buf(),
+      source,
+    };
+    let mut file = BufReader::new(File::open(path).map_err(io_error)?);
+    let magic: [u8; 4] = match file.fill_buf().map_err(io_error)?.get(.. 4) {
+      Some(magic) => magic.try_into().unwrap(),
+      None => {
+        return Err(CaptureError::UnknownFormat {
+          path: path.to_path_buf(),
+        })
+      }
+    };
+    let reader = if magic == PCAPNG_MAGIC {
+      Reader::PcapNg(PcapNgReader::new(file).map_err(pcap_error)?)
+    } else if PCAP_MAGICS.contains(&magic) {
+      Reader::Pcap(PcapReader::new(file).map_err(pcap_error)?)
+    } else {
+      return Err(CaptureError::UnknownFormat {
+        path: path.to_path_buf(),
+      });
+    };
+    Ok(Capture {
+      path: path.to_path_buf(),
+      reader,
+    })
+  }
+
+  fn next_frame(&mut self) -> Option<Result<Frame, CaptureError>> {
+    let pcap_error = |source| CaptureError::Pcap {
+      path: self.path.clone(),
+      source,
+    };
+    match &mut self.reader {
+      Reader::Pcap(reader) => {
+        let link = reader.header().datalink.into();
+        let packet = match reader.next_packet()? {
+          Ok(packet) => packet,
+          Err(e) => return Some(Err(pcap_error(e))),
+        };
+        Some(Ok(Frame {
+          timestamp: packet.timestamp,
+          link,
+          original_len: packet.orig_len,
+          data: packet.data.into_owned(),
+        }))
+      }
+      Reader::PcapNg(reader) => loop {
+        let (interface, raw_timestamp, original_len, data) = match reader.next_block()? {
+          Ok(Block::EnhancedPacket(packet)) => (
+            packet.interface_id,
+            packet.timestamp.as_nanos(),
+            packet.original_len,
+            packet.data.into_owned(),
+          ),
+          // No interface id nor timestamp: the first interface, at the epoch
+          Ok(Block::SimplePacket(packet)) => (0, 0, packet.original_len, packet.data.into_owned()),
+          // Timestamps in units below 10^-47 s would not fit a u128 of nanoseconds
+          Ok(Block::InterfaceDescription(interface)) => match resolution(&interface.options) {
+            resolution @ 48 ..= 0x7f => return Some(Err(CaptureError::Resolution(resolution))),
+            _ => continue,
+          },
+          Ok(_) => continue,
+          Err(e) => return Some(Err(pcap_error(e))),
+        };
+        let Some(interface) = reader.interfaces().get(interface as usize) else {
+          return Some(Err(CaptureError::UnknownInterface(interface)));
+        };
+        return Some(Ok(Frame {
+          timestamp: timestamp(raw_timestamp, resolution(&interface.options)),
+          link: interface.linktype.into(),
+          original_len,
+          data,
+        }));
+      },
+    }
+  }
+}
+
+impl Iterator for Capture {
+  type Item = Result<Frame, CaptureError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.next_frame()
+  }
+}
+
+/// The `if_tsresol` of an interface, microseconds by default.
+fn resolution(options: &[InterfaceDescriptionOption]) -> u8 {
+  options
+    .iter()
+    .find_map(|option| match option {
+      InterfaceDescriptionOption::IfTsResol(resolution) => Some(*resolution),
+      _ => None,
+    })
+    .unwrap_or(6)
+}
+
+/// A pcapng timestamp in units of the interface's `if_tsresol`: 10^-n seconds, or 2^-n when the
+/// high bit is set. pcap-file hands the units out as nanoseconds whatever the resolution.
+fn timestamp(units: u128, resolution: u8) -> Duration {
+  let nanos = if resolution & 0x80 == 0 {
+    let exponent = resolution as i32 - 9;
+    if exponent <= 0 {
+      units * 10u128.pow(exponent.unsigned_abs())
+    } else {
+      // Rejected with the interface, this is for the packets read past that error
+      10u128
+        .checked_pow(exponent as u32)
+        .map_or(0, |scale| units / scale)
+    }
+  } else {
+    (units * 1_000_000_000) >> (resolution & 0x7f)
+  };
+  Duration::from_nanos(nanos as u64)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn pcapng_timestamps_follow_the_interface_resolution() {
+    let second = Duration::from_secs(1);
+    assert_eq!(timestamp(1_000_000, 6), second);
+    assert_eq!(timestamp(1_000_000_000, 9), second);
+    assert_eq!(timestamp(1_000, 3), second);
+    assert_eq!(timestamp(1 << 20, 0x80 | 20), second);
+    assert_eq!(
+      timestamp(1_500_000_000_000, 12),
+      Duration::from_millis(1500)
+    );
+  }
+}
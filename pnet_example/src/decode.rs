@@ -0,0 +1,409 @@
+use std::{
+  fmt,
+  net::{IpAddr, SocketAddr},
+};
+
+use pnet::packet::{
+  ethernet::{EtherType, EtherTypes, EthernetPacket},
+  icmp::IcmpPacket,
+  icmpv6::Icmpv6Packet,
+  ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
+  ipv4::Ipv4Packet,
+  ipv6::Ipv6Packet,
+  sll::SLLPacket,
+  tcp::{TcpFlags, TcpPacket},
+  udp::UdpPacket,
+  vlan::VlanPacket,
+  Packet,
+};
+
+use crate::{
+  capture::LinkType,
+  dns::{self, Dns},
+};
+
+/// What a frame carries, as far down as the decoder goes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decoded {
+  /// VLAN ids, outermost first.
+  pub vlans: Vec<u16>,
+  pub network: Network,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Network {
+  Ip(Ip),
+  Arp,
+  /// An ethertype the decoder does not know.
+  Other(u16),
+  /// Cut short by the snap length, or not a valid packet.
+  Malformed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ip {
+  pub source: IpAddr,
+  pub destination: IpAddr,
+  /// TTL, or hop limit for IPv6.
+  pub ttl: u8,
+  pub transport: Transport,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transport {
+  Tcp(Tcp),
+  Udp(Udp),
+  Icmp {
+    icmp_type: u8,
+    code: u8,
+  },
+  Icmpv6 {
+    icmp_type: u8,
+    code: u8,
+  },
+  /// An IPv4 fragment other than the first, without a transport header.
+  Fragment,
+  Other(u8),
+  Malformed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tcp {
+  pub source: u16,
+  pub destination: u16,
+  pub sequence: u32,
+  pub acknowledgement: u32,
+  pub flags: u8,
+  pub window: u16,
+  pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Udp {
+  pub source: u16,
+  pub destination: u16,
+  pub payload: Vec<u8>,
+  /// Set for port 53 and mDNS traffic that parses as DNS.
+  pub dns: Option<Dns>,
+}
+
+impl Ip {
+  /// The transport protocol number, as in the IP header.
+  pub fn protocol(&self) -> u8 {
+    match self.transport {
+      Transport::Tcp(_) => IpNextHeaderProtocols::Tcp.0,
+      Transport::Udp(_) => IpNextHeaderProtocols::Udp.0,
+      Transport::Icmp { .. } => IpNextHeaderProtocols::Icmp.0,
+      Transport::Icmpv6 { .. } => IpNextHeaderProtocols::Icmpv6.0,
+      Transport::Other(protocol) => protocol,
+      Transport::Fragment | Transport::Malformed => 0,
+    }
+  }
+
+  /// Source and destination with their ports, 0 for protocols without ports.
+  pub fn endpoints(&self) -> (SocketAddr, SocketAddr) {
+    let (source, destination) = match &self.transport {
+      Transport::Tcp(tcp) => (tcp.source, tcp.destination),
+      Transport::Udp(udp) => (udp.source, udp.destination),
+      _ => (0, 0),
+    };
+    (
+      SocketAddr::new(self.source, source),
+      SocketAddr::new(self.destination, destination),
+    )
+  }
+}
+
+/// Decodes a frame of the given link type. Never fails: what cannot be parsed is reported as
+/// [`Network::Malformed`] or [`Transport::Malformed`].
+pub fn decode(link: LinkType, data: &[u8]) -> Decoded {
+  let mut vlans = vec![];
+  let network = match link {
+    LinkType::Ethernet => match EthernetPacket::new(data) {
+      Some(ethernet) => network(ethernet.get_ethertype(), ethernet.payload(), &mut vlans),
+      None => Network::Malformed,
+    },
+    LinkType::LinuxSll => match SLLPacket::new(data) {
+      Some(sll) => network(sll.get_protocol(), sll.payload(), &mut vlans),
+      None => Network::Malformed,
+    },
+    LinkType::Raw => match data.first().map(|byte| byte >> 4) {
+      Some(4) => ipv4(data),
+      Some(6) => ipv6(data),
+      _ => Network::Malformed,
+    },
+    // The family is in the byte order of the capturing host: 2 for IPv4, and one of 24, 28 or 30
+    // for IPv6 depending on the BSD
+    LinkType::Null => match data.get(.. 4) {
+      Some(family) => {
+        let family = u32::from_le_bytes(family.try_into().unwrap());
+        match if family > 0xffff {
+          family.swap_bytes()
+        } else {
+          family
+        } {
+          2 => ipv4(&data[4 ..]),
+          24 | 28 | 30 => ipv6(&data[4 ..]),
+          _ => Network::Malformed,
+        }
+      }
+      None => Network::Malformed,
+    },
+    LinkType::Other(_) => Network::Malformed,
+  };
+  Decoded { vlans, network }
+}
+
+/// 802.1ad outer tag, pnet only knows the older 0x9100.
+const PROVIDER_BRIDGING: EtherType = EtherType(0x88a8);
+
+fn network(ethertype: EtherType, payload: &[u8], vlans: &mut Vec<u16>) -> Network {
+  match ethertype {
+    EtherTypes::Ipv4 => ipv4(payload),
+    EtherTypes::Ipv6 => ipv6(payload),
+    EtherTypes::Arp => Network::Arp,
+    EtherTypes::Vlan | EtherTypes::QinQ | PROVIDER_BRIDGING => match VlanPacket::new(payload) {
+      Some(vlan) => {
+        vlans.push(vlan.get_vlan_identifier());
+        network(vlan.get_ethertype(), vlan.payload(), vlans)
+      }
+      None => Network::Malformed,
+    },
+    other => Network::Other(other.0),
+  }
+}
+
+fn ipv4(data: &[u8]) -> Network {
+  let Some(ip) = Ipv4Packet::new(data) else {
+    return Network::Malformed;
+  };
+  let header_len = ip.get_header_length() as usize * 4;
+  let total_len = ip.get_total_length() as usize;
+  if ip.get_version() != 4 || header_len < 20 || total_len < header_len || total_len > data.len() {
+    return Network::Malformed;
+  }
+  let transport = if ip.get_fragment_offset() != 0 {
+    Transport::Fragment
+  } else {
+    transport(ip.get_next_level_protocol(), &data[header_len .. total_len])
+  };
+  Network::Ip(Ip {
+    source: ip.get_source().into(),
+    destination: ip.get_destination().into(),
+    ttl: ip.get_ttl(),
+    transport,
+  })
+}
+
+fn ipv6(data: &[u8]) -> Network {
+  let Some(ip) = Ipv6Packet::new(data) else {
+    return Network::Malformed;
+  };
+  let end = 40 + ip.get_payload_length() as usize;
+  if ip.get_version() != 6 || end > data.len() {
+    return Network::Malformed;
+  }
+  let mut next = ip.get_next_header();
+  let mut payload = &data[40 .. end];
+  // Hop-by-hop, routing and destination options headers: next header, length in 8 bytes beyond
+  // the first 8
+  while matches!(
+    next,
+    IpNextHeaderProtocols::Hopopt
+      | IpNextHeaderProtocols::Ipv6Route
+      | IpNextHeaderProtocols::Ipv6Opts
+  ) {
+    let Some(&[header, len]) = payload.get(.. 2) else {
+      break;
+    };
+    let len = (len as usize + 1) * 8;
+    if len > payload.len() {
+      break;
+    }
+    next = IpNextHeaderProtocol(header);
+    payload = &payload[len ..];
+  }
+  Network::Ip(Ip {
+    source: ip.get_source().into(),
+    destination: ip.get_destination().into(),
+    ttl: ip.get_hop_limit(),
+    transport: transport(next, payload),
+  })
+}
+
+fn transport(protocol: IpNextHeaderProtocol, payload: &[u8]) -> Transport {
+  match protocol {
+    IpNextHeaderProtocols::Tcp => match TcpPacket::new(payload) {
+      Some(tcp)
+        if tcp.get_data_offset() >= 5 && tcp.get_data_offset() as usize * 4 <= payload.len() =>
+      {
+        Transport::Tcp(Tcp {
+          source: tcp.get_source(),
+          destination: tcp.get_destination(),
+          sequence: tcp.get_sequence(),
+          acknowledgement: tcp.get_acknowledgement(),
+          flags: tcp.get_flags(),
+          window: tcp.get_window(),
+          payload: tcp.payload().to_vec(),
+        })
+      }
+      _ => Transport::Malformed,
+    },
+    IpNextHeaderProtocols::Udp => match UdpPacket::new(payload) {
+      Some(udp) => {
+        let (source, destination) = (udp.get_source(), udp.get_destination());
+        let payload = udp.payload().to_vec();
+        let dns = [source, destination]
+          .iter()
+          .any(|port| dns::PORTS.contains(port))
+          .then(|| Dns::parse(&payload))
+          .flatten();
+        Transport::Udp(Udp {
+          source,
+          destination,
+          payload,
+          dns,
+        })
+      }
+      None => Transport::Malformed,
+    },
+    IpNextHeaderProtocols::Icmp => match IcmpPacket::new(payload) {
+      Some(icmp) => Transport::Icmp {
+        icmp_type: icmp.get_icmp_type().0,
+        code: icmp.get_icmp_code().0,
+      },
+      None => Transport::Malformed,
+    },
+    IpNextHeaderProtocols::Icmpv6 => match Icmpv6Packet::new(payload) {
+      Some(icmp) => Transport::Icmpv6 {
+        icmp_type: icmp.get_icmpv6_type().0,
+        code: icmp.get_icmpv6_code().0,
+      },
+      None => Transport::Malformed,
+    },
+    other => Transport::Other(other.0),
+  }
+}
+
+/// TCP flags the way tcpdump prints them.
+pub fn tcp_flags(flags: u8) -> String {
+  [
+    (TcpFlags::SYN, 'S'),
+    (TcpFlags::FIN, 'F'),
+    (TcpFlags::RST, 'R'),
+    (TcpFlags::PSH, 'P'),
+    (TcpFlags::ACK, '.'),
+    (TcpFlags::URG, 'U'),
+  ]
+  .iter()
+  .filter(|(flag, _)| flags & flag != 0)
+  .map(|(_, c)| *c)
+  .collect()
+}
+
+impl fmt::Display for Decoded {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for vlan in &self.vlans {
+      write!(f, "vlan {} ", vlan)?;
+    }
+    match &self.network {
+      Network::Ip(ip) => write!(f, "{}", ip),
+      Network::Arp => write!(f, "ARP"),
+      Network::Other(ethertype) => write!(f, "ethertype {:#06x}", ethertype),
+      Network::Malformed => write!(f, "malformed"),
+    }
+  }
+}
+
+impl fmt::Display for Ip {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let (source, destination) = self.endpoints();
+    match &self.transport {
+      Transport::Tcp(tcp) => write!(
+        f,
+        "TCP {} > {} [{}] seq {} ack {} win {} len {}",
+        source,
+        destination,
+        tcp_flags(tcp.flags),
+        tcp.sequence,
+        tcp.acknowledgement,
+        tcp.window,
+        tcp.payload.len()
+      ),
+      Transport::Udp(udp) => {
+        write!(
+          f,
+          "UDP {} > {} len {}",
+          source,
+          destination,
+          udp.payload.len()
+        )?;
+        match &udp.dns {
+          Some(dns) => write!(f, " {}", dns),
+          None => Ok(()),
+        }
+      }
+      Transport::Icmp { icmp_type, code } => write!(
+        f,
+        "ICMP {} > {} type {} code {}",
+        self.source, self.destination, icmp_type, code
+      ),
+      Transport::Icmpv6 { icmp_type, code } => write!(
+        f,
+        "ICMPv6 {} > {} type {} code {}",
+        self.source, self.destination, icmp_type, code
+      ),
+      Transport::Fragment => write!(f, "IP fragment {} > {}", self.source, self.destination),
+      Transport::Other(protocol) => write!(
+        f,
+        "IP {} > {} protocol {}",
+        self.source, self.destination, protocol
+      ),
+      Transport::Malformed => write!(f, "IP {} > {} malformed", self.source, self.destination),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn non_ip_and_truncated_frames_are_reported() {
+    let mut arp = vec![0; 14];
+    arp[12 ..].copy_from_slice(&[0x08, 0x06]);
+    assert_eq!(decode(LinkType::Ethernet, &arp).network, Network::Arp);
+    assert_eq!(
+      decode(LinkType::Ethernet, &arp[.. 10]).network,
+      Network::Malformed
+    );
+
+    // An IPv4 header claiming more bytes than were captured
+    let mut ip = vec![0x45, 0, 0, 60];
+    ip.resize(20, 0);
+    assert_eq!(decode(LinkType::Raw, &ip).network, Network::Malformed);
+  }
+
+  #[test]
+  fn later_fragments_have_no_transport_header() {
+    let mut ip = vec![
+      0x45, 0, 0, 28, 0, 0, 0, 10, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+    ];
+    ip.resize(28, 0xff);
+    let Network::Ip(ip) = decode(LinkType::Null, &[&2u32.to_le_bytes()[..], &ip].concat()).network
+    else {
+      panic!("not decoded as IP");
+    };
+    assert_eq!(ip.transport, Transport::Fragment);
+    assert_eq!(ip.ttl, 64);
+  }
+
+  #[test]
+  fn flags_print_like_tcpdump() {
+    assert_eq!(tcp_flags(TcpFlags::SYN | TcpFlags::ACK), "S.");
+    assert_eq!(
+      tcp_flags(TcpFlags::FIN | TcpFlags::PSH | TcpFlags::ACK),
+      "FP."
+    );
+  }
+}
@@ -0,0 +1,99 @@
+use std::fmt;
+
+use trust_dns_proto::op::{Message, MessageType};
+
+/// DNS and mDNS.
+pub const PORTS: [u16; 2] = [53, 5353];
+
+/// The parts of a DNS message worth a line of output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dns {
+  pub id: u16,
+  pub response: bool,
+  /// `NoError`, `NXDomain`, ...
+  pub rcode: String,
+  /// Name and record type of each question.
+  pub questions: Vec<(String, String)>,
+  /// Each answer as `name type data`.
+  pub answers: Vec<String>,
+}
+
+impl Dns {
+  /// `None` when the payload is not a DNS message.
+  pub fn parse(payload: &[u8]) -> Option<Dns> {
+    let message = Message::from_vec(payload).ok()?;
+    Some(Dns {
+      id: message.id(),
+      response: message.message_type() == MessageType::Response,
+      rcode: format!("{:?}", message.response_code()),
+      questions: message
+        .queries()
+        .iter()
+        .map(|query| (query.name().to_string(), query.query_type().to_string()))
+        .collect(),
+      answers: message
+        .answers()
+        .iter()
+        .map(|record| {
+          format!(
+            "{} {} {}",
+            record.name(),
+            record.record_type(),
+            record.rdata()
+          )
+        })
+        .collect(),
+    })
+  }
+}
+
+impl fmt::Display for Dns {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.response {
+      write!(f, "DNS response {:#06x} {}", self.id, self.rcode)?;
+    } else {
+      write!(f, "DNS query {:#06x}", self.id)?;
+    }
+    for (name, record_type) in &self.questions {
+      write!(f, " {} {}", name, record_type)?;
+    }
+    if !self.answers.is_empty() {
+      write!(f, " -> {}", self.answers.join(", "))?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{net::Ipv4Addr, str::FromStr};
+
+  use trust_dns_proto::{
+    op::{Query, ResponseCode},
+    rr::{Name, RData, Record, RecordType},
+  };
+
+  use super::*;
+
+  #[test]
+  fn queries_and_answers_are_summarized() {
+    let name = Name::from_str("example.com.").unwrap();
+    let mut message = Message::new();
+    message
+      .set_id(0x1234)
+      .set_message_type(MessageType::Response)
+      .set_response_code(ResponseCode::NoError)
+      .add_query(Query::query(name.clone(), RecordType::A))
+      .add_answer(Record::from_rdata(
+        name,
+        300,
+        RData::A(Ipv4Addr::new(93, 184, 216, 34)),
+      ));
+    let dns = Dns::parse(&message.to_vec().unwrap()).unwrap();
+    assert_eq!(
+      dns.to_string(),
+      "DNS response 0x1234 NoError example.com. A -> example.com. A 93.184.216.34"
+    );
+    assert_eq!(Dns::parse(b"not dns"), None);
+  }
+}
@@ -0,0 +1,30 @@
+use std::{io, path::PathBuf};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum CaptureError {
+  #[error("{path}: {source}")]
+  Io { path: PathBuf, source: io::Error },
+  #[error("{path}: neither a pcap nor a pcapng file")]
+  UnknownFormat { path: PathBuf },
+  #[error("{path}: {source}")]
+  Pcap {
+    path: PathBuf,
+    source: pcap_file::PcapError,
+  },
+  #[error("packet for unknown interface {0}")]
+  UnknownInterface(u32),
+  #[error("unsupported timestamp resolution {0:#04x}")]
+  Resolution(u8),
+}
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+  #[error("I/O error: {0}")]
+  Io(#[from] io::Error),
+  #[error("CSV error: {0}")]
+  Csv(#[from] csv::Error),
+  #[error("JSON error: {0}")]
+  Json(#[from] serde_json::Error),
+}
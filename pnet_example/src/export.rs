@@ -0,0 +1,91 @@
+use std::{io::Write, net::SocketAddr, time::Duration};
+
+use serde::Serialize;
+
+use crate::{
+  error::ExportError,
+  flow::{Direction, Flow, State},
+};
+
+/// A flow as one row: the same fields in CSV and JSON.
+#[derive(Debug, Serialize)]
+pub struct FlowRecord {
+  pub protocol: String,
+  pub client: SocketAddr,
+  pub server: SocketAddr,
+  /// Seconds since the Unix epoch.
+  pub first_seen: f64,
+  pub duration: f64,
+  pub state: &'static str,
+  pub client_packets: u64,
+  pub client_bytes: u64,
+  pub client_payload_bytes: u64,
+  pub client_retransmissions: u64,
+  pub client_out_of_order: u64,
+  pub server_packets: u64,
+  pub server_bytes: u64,
+  pub server_payload_bytes: u64,
+  pub server_retransmissions: u64,
+  pub server_out_of_order: u64,
+  pub rtt_samples: u32,
+  pub rtt_min_ms: Option<f64>,
+  pub rtt_mean_ms: Option<f64>,
+  pub rtt_max_ms: Option<f64>,
+  pub handshake_ms: Option<f64>,
+}
+
+fn ms(duration: Duration) -> f64 {
+  duration.as_secs_f64() * 1000.0
+}
+
+impl From<&Flow> for FlowRecord {
+  fn from(flow: &Flow) -> Self {
+    let client = flow.traffic(Direction::ClientToServer);
+    let server = flow.traffic(Direction::ServerToClient);
+    FlowRecord {
+      protocol: flow.protocol_name(),
+      client: flow.client,
+      server: flow.server,
+      first_seen: flow.first_seen.as_secs_f64(),
+      duration: flow.duration().as_secs_f64(),
+      state: match flow.state {
+        State::Open => "open",
+        State::Closed => "closed",
+        State::Reset => "reset",
+      },
+      client_packets: client.packets,
+      client_bytes: client.bytes,
+      client_payload_bytes: client.payload_bytes,
+      client_retransmissions: client.retransmissions,
+      client_out_of_order: client.out_of_order,
+      server_packets: server.packets,
+      server_bytes: server.bytes,
+      server_payload_bytes: server.payload_bytes,
+      server_retransmissions: server.retransmissions,
+      server_out_of_order: server.out_of_order,
+      rtt_samples: flow.rtt.samples,
+      rtt_min_ms: flow.rtt.min.map(ms),
+      rtt_mean_ms: flow.rtt.mean().map(ms),
+      rtt_max_ms: flow.rtt.max.map(ms),
+      handshake_ms: flow.handshake.map(ms),
+    }
+  }
+}
+
+/// One line per flow after a header, missing RTTs left empty.
+pub fn write_csv(writer: impl Write, flows: &[Flow]) -> Result<(), ExportError> {
+  let mut writer = csv::Writer::from_writer(writer);
+  for flow in flows {
+    writer.serialize(FlowRecord::from(flow))?;
+  }
+  writer.flush()?;
+  Ok(())
+}
+
+/// An array of flows, missing RTTs as `null`.
+pub fn write_json(mut writer: impl Write, flows: &[Flow]) -> Result<(), ExportError> {
+  let records: Vec<FlowRecord> = flows.iter().map(FlowRecord::from).collect();
+  serde_json::to_writer_pretty(&mut writer, &records)?;
+  writeln!(writer)?;
+  Ok(())
+}
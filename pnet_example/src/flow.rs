@@ -0,0 +1,549 @@
+use std::{
+  collections::{BTreeMap, HashMap},
+  net::SocketAddr,
+  time::Duration,
+};
+
+use pnet::packet::{
+  ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
+  tcp::TcpFlags,
+};
+
+use crate::decode::{Ip, Tcp, Transport};
+
+/// Stream bytes kept per direction for [`Flow::stream`], the rest is only counted.
+pub const STREAM_LIMIT: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+  ClientToServer,
+  ServerToClient,
+}
+
+impl Direction {
+  fn index(self) -> usize {
+    self as usize
+  }
+
+  fn reverse(self) -> Direction {
+    match self {
+      Direction::ClientToServer => Direction::ServerToClient,
+      Direction::ServerToClient => Direction::ClientToServer,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+  /// Always the state of connectionless flows.
+  Open,
+  /// Both sides sent a FIN.
+  Closed,
+  Reset,
+}
+
+/// What went one way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Traffic {
+  pub packets: u64,
+  /// Frame lengths on the wire.
+  pub bytes: u64,
+  /// Transport payload, retransmissions included.
+  pub payload_bytes: u64,
+  /// TCP segments carrying data, a SYN or a FIN seen before.
+  pub retransmissions: u64,
+  /// TCP segments filling a gap behind data already seen.
+  pub out_of_order: u64,
+}
+
+/// Round trips from a TCP segment to the ack covering it, both ways. They are measured where the
+/// capture was taken: close to a host, the samples for the data it sends are its round trips.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rtt {
+  pub samples: u32,
+  pub min: Option<Duration>,
+  pub max: Option<Duration>,
+  total: Duration,
+}
+
+impl Rtt {
+  fn add(&mut self, sample: Duration) {
+    self.samples += 1;
+    self.total += sample;
+    self.min = Some(self.min.map_or(sample, |min| min.min(sample)));
+    self.max = Some(self.max.map_or(sample, |max| max.max(sample)));
+  }
+
+  pub fn mean(&self) -> Option<Duration> {
+    (self.samples > 0).then(|| self.total / self.samples)
+  }
+}
+
+/// Packets between two endpoints of one protocol, a TCP connection or what passes for a UDP or
+/// ICMP conversation.
+#[derive(Debug, Clone)]
+pub struct Flow {
+  pub protocol: u8,
+  /// The side that sent the SYN, or else the first packet.
+  pub client: SocketAddr,
+  pub server: SocketAddr,
+  pub first_seen: Duration,
+  pub last_seen: Duration,
+  traffic: [Traffic; 2],
+  pub rtt: Rtt,
+  /// From the SYN to the ack of the SYN-ACK.
+  pub handshake: Option<Duration>,
+  pub state: State,
+  streams: [Stream; 2],
+  syn_at: Option<Duration>,
+}
+
+impl Flow {
+  fn new(protocol: u8, client: SocketAddr, server: SocketAddr, timestamp: Duration) -> Flow {
+    Flow {
+      protocol,
+      client,
+      server,
+      first_seen: timestamp,
+      last_seen: timestamp,
+      traffic: Default::default(),
+      rtt: Rtt::default(),
+      handshake: None,
+      state: State::Open,
+      streams: Default::default(),
+      syn_at: None,
+    }
+  }
+
+  pub fn traffic(&self, direction: Direction) -> &Traffic {
+    &self.traffic[direction.index()]
+  }
+
+  /// The TCP payload sent one way, reassembled in order and without the retransmitted copies, up
+  /// to the first gap or [`STREAM_LIMIT`].
+  pub fn stream(&self, direction: Direction) -> &[u8] {
+    &self.streams[direction.index()].data
+  }
+
+  /// `TCP`, `UDP`, `ICMP`, `ICMPv6`, or the protocol number.
+  pub fn protocol_name(&self) -> String {
+    match IpNextHeaderProtocol(self.protocol) {
+      IpNextHeaderProtocols::Tcp => "TCP".into(),
+      IpNextHeaderProtocols::Udp => "UDP".into(),
+      IpNextHeaderProtocols::Icmp => "ICMP".into(),
+      IpNextHeaderProtocols::Icmpv6 => "ICMPv6".into(),
+      other => other.0.to_string(),
+    }
+  }
+
+  pub fn duration(&self) -> Duration {
+    self.last_seen - self.first_seen
+  }
+
+  fn on_tcp(&mut self, direction: Direction, tcp: &Tcp, timestamp: Duration) {
+    let syn = tcp.flags & TcpFlags::SYN != 0;
+    let ack = tcp.flags & TcpFlags::ACK != 0;
+    if syn && !ack && direction == Direction::ClientToServer {
+      self.syn_at.get_or_insert(timestamp);
+    }
+    let segment = self.streams[direction.index()].on_segment(tcp, timestamp);
+    let traffic = &mut self.traffic[direction.index()];
+    traffic.retransmissions += segment.retransmission as u64;
+    traffic.out_of_order += segment.out_of_order as u64;
+
+    if ack {
+      let acked = &mut self.streams[direction.reverse().index()];
+      if let Some(sample) = acked.on_ack(tcp.acknowledgement, timestamp) {
+        self.rtt.add(sample);
+      }
+      if direction == Direction::ClientToServer && !syn && acked.syn && self.handshake.is_none() {
+        // Timestamps of real captures sometimes go backwards
+        self.handshake = self.syn_at.map(|syn_at| timestamp.saturating_sub(syn_at));
+      }
+    }
+    if tcp.flags & TcpFlags::RST != 0 {
+      self.state = State::Reset;
+    } else if self.state == State::Open && self.streams.iter().all(|stream| stream.fin) {
+      self.state = State::Closed;
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+struct Segment {
+  retransmission: bool,
+  out_of_order: bool,
+}
+
+/// One direction of a TCP connection. Offsets count stream bytes from the first after the SYN.
+#[derive(Debug, Clone, Default)]
+struct Stream {
+  /// Sequence number of offset 0: after the ISN, or the first segment seen of a connection
+  /// caught midway.
+  base: Option<u32>,
+  syn: bool,
+  fin: bool,
+  /// Bytes received without a gap.
+  delivered: u64,
+  /// End of the furthest segment.
+  highest: u64,
+  /// Segments beyond a gap, by offset.
+  pending: BTreeMap<u64, Vec<u8>>,
+  data: Vec<u8>,
+  /// Ends of the segments not acked yet, when they were sent and whether they were sent again:
+  /// the samples of those are ambiguous and left out (Karn's algorithm).
+  unacked: BTreeMap<u64, (Duration, bool)>,
+}
+
+impl Stream {
+  fn on_segment(&mut self, tcp: &Tcp, timestamp: Duration) -> Segment {
+    let mut segment = Segment::default();
+    let mut sequence = tcp.sequence;
+    if tcp.flags & TcpFlags::SYN != 0 {
+      sequence = sequence.wrapping_add(1);
+      if self.base.is_none() || !self.syn {
+        self.base = Some(sequence);
+      } else {
+        segment.retransmission = true;
+      }
+      self.syn = true;
+      // Acked by offset 0
+      self.track(0, timestamp, segment.retransmission);
+    }
+    let base = *self.base.get_or_insert(sequence);
+    // Far behind the base: the connection was caught midway and this is older data
+    let Some(offset) = offset(base, sequence) else {
+      segment.retransmission = true;
+      return segment;
+    };
+    let end = offset + tcp.payload.len() as u64;
+
+    if !tcp.payload.is_empty() {
+      if end <= self.delivered
+        || self
+          .pending
+          .get(&offset)
+          .is_some_and(|pending| pending.len() >= tcp.payload.len())
+      {
+        segment.retransmission = true;
+      } else {
+        if offset < self.delivered {
+          segment.retransmission = true;
+        } else if offset < self.highest {
+          segment.out_of_order = true;
+        }
+        self.pending.insert(offset, tcp.payload.clone());
+        self.reassemble();
+      }
+      self.highest = self.highest.max(end);
+      self.track(end, timestamp, segment.retransmission);
+    }
+    if tcp.flags & TcpFlags::FIN != 0 {
+      segment.retransmission |= self.fin;
+      self.fin = true;
+      self.track(end + 1, timestamp, segment.retransmission);
+    }
+    segment
+  }
+
+  /// Moves the segments that no longer follow a gap into the stream.
+  fn reassemble(&mut self) {
+    while let Some(entry) = self.pending.first_entry() {
+      let offset = *entry.key();
+      if offset > self.delivered {
+        break;
+      }
+      let payload = entry.remove();
+      let end = offset + payload.len() as u64;
+      if end <= self.delivered {
+        continue;
+      }
+      let new = &payload[(self.delivered - offset) as usize ..];
+      let room = STREAM_LIMIT.saturating_sub(self.data.len());
+      self.data.extend_from_slice(&new[.. new.len().min(room)]);
+      self.delivered = end;
+    }
+  }
+
+  fn track(&mut self, end: u64, timestamp: Duration, retransmission: bool) {
+    self
+      .unacked
+      .entry(end)
+      .and_modify(|(_, again)| *again = true)
+      .or_insert((timestamp, retransmission));
+  }
+
+  /// Drops the segments an ack covers, returning a round trip sample for the last of them.
+  fn on_ack(&mut self, acknowledgement: u32, timestamp: Duration) -> Option<Duration> {
+    let acked = offset(self.base?, acknowledgement)?;
+    let mut sample = None;
+    while let Some(entry) = self.unacked.first_entry() {
+      if *entry.key() > acked {
+        break;
+      }
+      let (sent, again) = entry.remove();
+      sample = (!again).then(|| timestamp.saturating_sub(sent));
+    }
+    sample
+  }
+}
+
+/// Offset of a sequence number, `None` when it is behind the base.
+fn offset(base: u32, sequence: u32) -> Option<u64> {
+  let offset = sequence.wrapping_sub(base);
+  (offset <= i32::MAX as u32).then_some(offset as u64)
+}
+
+/// The flows of a capture, in the order they started.
+#[derive(Debug, Default)]
+pub struct FlowTable {
+  flows: Vec<Flow>,
+  /// By protocol and endpoints, the lower address first.
+  index: HashMap<(u8, SocketAddr, SocketAddr), usize>,
+}
+
+impl FlowTable {
+  pub fn new() -> FlowTable {
+    FlowTable::default()
+  }
+
+  /// Accounts for an IP packet of `len` bytes on the wire. Fragments past the first, without
+  /// ports, are left out.
+  pub fn add(&mut self, ip: &Ip, len: u32, timestamp: Duration) {
+    if matches!(ip.transport, Transport::Fragment | Transport::Malformed) {
+      return;
+    }
+    let protocol = ip.protocol();
+    let (source, destination) = ip.endpoints();
+    let key = (protocol, source.min(destination), source.max(destination));
+    let tcp = match &ip.transport {
+      Transport::Tcp(tcp) => Some(tcp),
+      _ => None,
+    };
+    let syn = tcp.map_or(0, |tcp| tcp.flags & (TcpFlags::SYN | TcpFlags::ACK));
+
+    let index = match self.index.get(&key) {
+      // A new connection between the same ports
+      Some(&index) if syn == TcpFlags::SYN && self.flows[index].state != State::Open => None,
+      index => index.copied(),
+    };
+    let index = index.unwrap_or_else(|| {
+      let flow = if syn == TcpFlags::SYN | TcpFlags::ACK {
+        Flow::new(protocol, destination, source, timestamp)
+      } else {
+        Flow::new(protocol, source, destination, timestamp)
+      };
+      self.flows.push(flow);
+      self.index.insert(key, self.flows.len() - 1);
+      self.flows.len() - 1
+    });
+
+    let flow = &mut self.flows[index];
+    let direction = if source == flow.client {
+      Direction::ClientToServer
+    } else {
+      Direction::ServerToClient
+    };
+    flow.last_seen = flow.last_seen.max(timestamp);
+    let traffic = &mut flow.traffic[direction.index()];
+    traffic.packets += 1;
+    traffic.bytes += len as u64;
+    traffic.payload_bytes += match &ip.transport {
+      Transport::Tcp(tcp) => tcp.payload.len(),
+      Transport::Udp(udp) => udp.payload.len(),
+      _ => 0,
+    } as u64;
+    if let Some(tcp) = tcp {
+      flow.on_tcp(direction, tcp, timestamp);
+    }
+  }
+
+  pub fn flows(&self) -> &[Flow] {
+    &self.flows
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::IpAddr;
+
+  use super::*;
+
+  const CLIENT: &str = "10.0.0.1:40000";
+  const SERVER: &str = "10.0.0.2:80";
+
+  fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+  }
+
+  fn tcp(
+    from: &str,
+    to: &str,
+    flags: u8,
+    sequence: u32,
+    acknowledgement: u32,
+    payload: &[u8],
+  ) -> Ip {
+    let (from, to): (SocketAddr, SocketAddr) = (from.parse().unwrap(), to.parse().unwrap());
+    Ip {
+      source: from.ip(),
+      destination: to.ip(),
+      ttl: 64,
+      transport: Transport::Tcp(Tcp {
+        source: from.port(),
+        destination: to.port(),
+        sequence,
+        acknowledgement,
+        flags,
+        window: 65535,
+        payload: payload.to_vec(),
+      }),
+    }
+  }
+
+  /// A handshake with ISNs 1000 and 5000, 10 ms apart.
+  fn connect(table: &mut FlowTable) {
+    table.add(&tcp(CLIENT, SERVER, TcpFlags::SYN, 1000, 0, b""), 60, ms(0));
+    table.add(
+      &tcp(
+        SERVER,
+        CLIENT,
+        TcpFlags::SYN | TcpFlags::ACK,
+        5000,
+        1001,
+        b"",
+      ),
+      60,
+      ms(10),
+    );
+    table.add(
+      &tcp(CLIENT, SERVER, TcpFlags::ACK, 1001, 5001, b""),
+      54,
+      ms(20),
+    );
+  }
+
+  #[test]
+  fn segments_are_reassembled_in_order() {
+    let mut table = FlowTable::new();
+    connect(&mut table);
+    let ack = TcpFlags::ACK;
+    table.add(&tcp(CLIENT, SERVER, ack, 1001, 5001, b"hello "), 60, ms(30));
+    // "big " is lost, "world" overtakes it
+    table.add(&tcp(CLIENT, SERVER, ack, 1011, 5001, b"world"), 60, ms(31));
+    table.add(&tcp(SERVER, CLIENT, ack, 5001, 1007, b""), 54, ms(40));
+    table.add(&tcp(CLIENT, SERVER, ack, 1007, 5001, b"big "), 60, ms(45));
+    table.add(&tcp(CLIENT, SERVER, ack, 1007, 5001, b"big "), 60, ms(46));
+    table.add(&tcp(SERVER, CLIENT, ack, 5001, 1016, b""), 54, ms(55));
+
+    let flow = &table.flows()[0];
+    assert_eq!(flow.client, CLIENT.parse().unwrap());
+    assert_eq!(flow.stream(Direction::ClientToServer), b"hello big world");
+    let sent = flow.traffic(Direction::ClientToServer);
+    assert_eq!((sent.packets, sent.bytes, sent.payload_bytes), (6, 354, 19));
+    assert_eq!((sent.out_of_order, sent.retransmissions), (1, 1));
+
+    // Both SYNs, "hello " and "world", held back by the gap. "big " was sent twice: which copy
+    // the ack is for cannot be told
+    assert_eq!(flow.rtt.samples, 4);
+    assert_eq!((flow.rtt.min, flow.rtt.max), (Some(ms(10)), Some(ms(24))));
+    assert_eq!(flow.handshake, Some(ms(20)));
+  }
+
+  #[test]
+  fn connections_close_and_ports_are_reused() {
+    let mut table = FlowTable::new();
+    connect(&mut table);
+    let fin = TcpFlags::FIN | TcpFlags::ACK;
+    table.add(&tcp(CLIENT, SERVER, fin, 1001, 5001, b""), 54, ms(30));
+    assert_eq!(table.flows()[0].state, State::Open);
+    table.add(&tcp(SERVER, CLIENT, fin, 5001, 1002, b""), 54, ms(40));
+    table.add(
+      &tcp(CLIENT, SERVER, TcpFlags::ACK, 1002, 5002, b""),
+      54,
+      ms(50),
+    );
+    assert_eq!(table.flows()[0].state, State::Closed);
+    assert_eq!(table.flows()[0].rtt.samples, 4);
+
+    connect(&mut table);
+    table.add(
+      &tcp(SERVER, CLIENT, TcpFlags::RST, 5001, 0, b""),
+      54,
+      ms(30),
+    );
+    assert_eq!(table.flows().len(), 2);
+    assert_eq!(table.flows()[1].state, State::Reset);
+  }
+
+  #[test]
+  fn timestamps_going_backwards_are_taken_in_stride() {
+    let mut table = FlowTable::new();
+    table.add(
+      &tcp(CLIENT, SERVER, TcpFlags::SYN, 1000, 0, b""),
+      60,
+      ms(20),
+    );
+    table.add(
+      &tcp(
+        SERVER,
+        CLIENT,
+        TcpFlags::SYN | TcpFlags::ACK,
+        5000,
+        1001,
+        b"",
+      ),
+      60,
+      ms(10),
+    );
+    table.add(
+      &tcp(CLIENT, SERVER, TcpFlags::ACK, 1001, 5001, b""),
+      54,
+      ms(5),
+    );
+    let flow = &table.flows()[0];
+    assert_eq!(flow.handshake, Some(Duration::ZERO));
+    assert_eq!(flow.duration(), Duration::ZERO);
+  }
+
+  #[test]
+  fn the_syn_ack_sender_is_the_server() {
+    let mut table = FlowTable::new();
+    table.add(
+      &tcp(
+        SERVER,
+        CLIENT,
+        TcpFlags::SYN | TcpFlags::ACK,
+        5000,
+        1001,
+        b"",
+      ),
+      60,
+      ms(0),
+    );
+    table.add(
+      &tcp(CLIENT, SERVER, TcpFlags::ACK, 1001, 5001, b"hi"),
+      56,
+      ms(10),
+    );
+    let flow = &table.flows()[0];
+    assert_eq!(flow.server, SERVER.parse().unwrap());
+    assert_eq!(flow.stream(Direction::ClientToServer), b"hi");
+    assert_eq!(flow.handshake, None);
+
+    let ping = Ip {
+      source: IpAddr::from([10, 0, 0, 1]),
+      destination: IpAddr::from([10, 0, 0, 2]),
+      ttl: 64,
+      transport: Transport::Icmp {
+        icmp_type: 8,
+        code: 0,
+      },
+    };
+    table.add(&ping, 98, ms(20));
+    assert_eq!(
+      table.flows()[1].traffic(Direction::ClientToServer).bytes,
+      98
+    );
+  }
+}
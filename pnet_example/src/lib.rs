@@ -0,0 +1,11 @@
+pub mod capture;
+pub mod decode;
+pub mod dns;
+pub mod error;
+pub mod export;
+pub mod flow;
+
+pub use capture::{Capture, Frame, LinkType};
+pub use decode::{decode, Decoded};
+pub use error::{CaptureError, ExportError};
+pub use flow::{Direction, Flow, FlowTable};
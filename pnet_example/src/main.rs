@@ -1,73 +1,142 @@
-use std::env;
+// sudo cargo run -- live en7
+// cargo run -- read tests/data/http.pcap --packets
+// cargo run -- read tests/data/mixed.pcapng --csv flows.csv --json -
+use std::{
+  error::Error,
+  fs::File,
+  io::{self, Write},
+  path::{Path, PathBuf},
+  time::Duration,
+};
 
-use pnet::{
-  datalink::{self, Channel::Ethernet, NetworkInterface},
-  packet::{
-    ethernet::{EtherTypes, EthernetPacket},
-    ip::IpNextHeaderProtocols,
-    ipv4::Ipv4Packet,
-    tcp::TcpPacket,
-    Packet,
-  },
+use clap::{Parser, Subcommand};
+use pnet::datalink::{self, Channel::Ethernet, NetworkInterface};
+use pnet_example::{
+  decode,
+  decode::Network,
+  export::{write_csv, write_json},
+  flow::Flow,
+  Capture, Direction, FlowTable, LinkType,
 };
 
-// Handles a single ethernet packet
-fn handle_packet(ethernet: &EthernetPacket) {
-  match ethernet.get_ethertype() {
-    EtherTypes::Ipv4 => {
-      let header = Ipv4Packet::new(ethernet.payload());
-      if let Some(header) = header {
-        match header.get_next_level_protocol() {
-          IpNextHeaderProtocols::Tcp => {
-            let tcp = TcpPacket::new(header.payload());
-            if let Some(tcp) = tcp {
-              println!(
-                "Got a TCP packet {}:{} to {}:{}",
-                header.get_source(),
-                tcp.get_source(),
-                header.get_destination(),
-                tcp.get_destination()
-              );
-            }
-          }
-          _ => println!("Ignoring non TCP packet"),
-        }
-      }
-    }
-    _ => println!("Ignoring non IPv4 packet"),
-  }
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+  #[command(subcommand)]
+  command: Command,
 }
 
-fn main() {
-  let interface_name = env::args().nth(1).unwrap();
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Prints the packets arriving on an interface, needs root
+  Live { interface: String },
+  /// Reads a pcap or pcapng file and prints its flows
+  Read {
+    file: PathBuf,
+    /// Prints every packet first
+    #[arg(long)]
+    packets: bool,
+    /// Writes the flows as CSV, `-` for stdout
+    #[arg(long)]
+    csv: Option<PathBuf>,
+    /// Writes the flows as JSON, `-` for stdout
+    #[arg(long)]
+    json: Option<PathBuf>,
+  },
+}
+
+fn live(interface_name: &str) -> Result<(), Box<dyn Error>> {
   // Get all interfaces
   let interfaces = datalink::interfaces();
   // Filter the list to find the given interface name
   let interface = interfaces
     .into_iter()
-    .filter(|iface: &NetworkInterface| iface.name == interface_name)
-    .next()
-    .expect("Error getting interface");
-  let (_tx, mut rx) = match datalink::channel(&interface, Default::default()) {
-    Ok(Ethernet(tx, rx)) => (tx, rx),
-    Ok(_) => panic!("Unhandled channel type"),
-    Err(e) => {
-      panic!(
-        "An error occurred when creating the datalink channel: {}",
-        e
-      )
-    }
+    .find(|iface: &NetworkInterface| iface.name == interface_name)
+    .ok_or_else(|| format!("no interface named {}", interface_name))?;
+  let (_tx, mut rx) = match datalink::channel(&interface, Default::default())? {
+    Ethernet(tx, rx) => (tx, rx),
+    _ => return Err("unhandled channel type".into()),
   };
   // Loop over packets arriving on the given interface
   loop {
-    match rx.next() {
-      Ok(packet) => {
-        let packet = EthernetPacket::new(packet).unwrap();
-        handle_packet(&packet);
+    let packet = rx.next()?;
+    println!("{}", decode(LinkType::Ethernet, packet));
+  }
+}
+
+/// A file, or stdout for `-`.
+fn output(path: &Path) -> io::Result<Box<dyn Write>> {
+  if path == Path::new("-") {
+    Ok(Box::new(io::stdout().lock()))
+  } else {
+    Ok(Box::new(File::create(path)?))
+  }
+}
+
+fn millis(duration: Option<Duration>) -> String {
+  match duration {
+    Some(duration) => format!("{:.1}", duration.as_secs_f64() * 1000.0),
+    None => "-".into(),
+  }
+}
+
+fn print_flows(flows: &[Flow]) {
+  println!(
+    "{:<6} {:<45} {:<45} {:<6} {:>11} {:>15} {:>9} {:>8} {:>9}",
+    "proto", "client", "server", "state", "packets", "bytes", "retrans", "rtt ms", "hshake ms"
+  );
+  for flow in flows {
+    let sent = flow.traffic(Direction::ClientToServer);
+    let received = flow.traffic(Direction::ServerToClient);
+    println!(
+      "{:<6} {:<45} {:<45} {:<6} {:>11} {:>15} {:>9} {:>8} {:>9}",
+      flow.protocol_name(),
+      flow.client.to_string(),
+      flow.server.to_string(),
+      format!("{:?}", flow.state).to_lowercase(),
+      format!("{}/{}", sent.packets, received.packets),
+      format!("{}/{}", sent.bytes, received.bytes),
+      format!("{}/{}", sent.retransmissions, received.retransmissions),
+      millis(flow.rtt.mean()),
+      millis(flow.handshake),
+    );
+  }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+  match Args::parse().command {
+    Command::Live { interface } => live(&interface),
+    Command::Read {
+      file,
+      packets,
+      csv,
+      json,
+    } => {
+      let mut flows = FlowTable::new();
+      let mut start = None;
+      for frame in Capture::open(&file)? {
+        let frame = frame?;
+        let decoded = decode(frame.link, &frame.data);
+        if packets {
+          let start: Duration = *start.get_or_insert(frame.timestamp);
+          let elapsed = frame.timestamp.saturating_sub(start);
+          println!("{:>12.6} {}", elapsed.as_secs_f64(), decoded);
+        }
+        if let Network::Ip(ip) = &decoded.network {
+          flows.add(ip, frame.original_len, frame.timestamp);
+        }
+      }
+      if packets {
+        println!();
+      }
+      print_flows(flows.flows());
+      if let Some(path) = csv {
+        write_csv(output(&path)?, flows.flows())?;
       }
-      Err(e) => {
-        panic!("An error occurred while reading: {}", e);
+      if let Some(path) = json {
+        write_json(output(&path)?, flows.flows())?;
       }
+      Ok(())
     }
   }
 }
@@ -0,0 +1,204 @@
+//! Against the captures `cargo run --example make_captures` writes.
+use std::{borrow::Cow, fs::File, net::SocketAddr, path::Path, time::Duration};
+
+use pcap_file::{
+  pcapng::{
+    blocks::{
+      enhanced_packet::EnhancedPacketBlock,
+      interface_description::{InterfaceDescriptionBlock, InterfaceDescriptionOption},
+    },
+    PcapNgWriter,
+  },
+  DataLink,
+};
+use pnet_example::{
+  decode,
+  decode::{Network, Transport},
+  export::{write_csv, write_json},
+  flow::State,
+  Capture, CaptureError, Decoded, Direction, FlowTable, Frame, LinkType,
+};
+
+const EPOCH: Duration = Duration::from_secs(1_700_000_000);
+
+fn frames(name: &str) -> Vec<Frame> {
+  let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+    .join("tests/data")
+    .join(name);
+  Capture::open(&path)
+    .unwrap()
+    .collect::<Result<_, _>>()
+    .unwrap()
+}
+
+fn flows(frames: &[Frame]) -> FlowTable {
+  let mut flows = FlowTable::new();
+  for frame in frames {
+    if let Network::Ip(ip) = decode(frame.link, &frame.data).network {
+      flows.add(&ip, frame.original_len, frame.timestamp);
+    }
+  }
+  flows
+}
+
+fn ms(ms: u64) -> Duration {
+  Duration::from_millis(ms)
+}
+
+#[test]
+fn tcp_flows_are_reassembled_and_timed() {
+  let frames = frames("http.pcap");
+  assert_eq!(frames.len(), 13);
+  assert_eq!(
+    decode(frames[0].link, &frames[0].data).network,
+    Network::Arp
+  );
+
+  let flows = flows(&frames);
+  let [flow] = flows.flows() else {
+    panic!("{:?}", flows.flows());
+  };
+  assert_eq!(
+    flow.client,
+    "192.168.1.10:51000".parse::<SocketAddr>().unwrap()
+  );
+  assert_eq!(
+    flow.server,
+    "93.184.216.34:80".parse::<SocketAddr>().unwrap()
+  );
+  assert_eq!((flow.first_seen, flow.duration()), (EPOCH, ms(91)));
+  assert_eq!(flow.state, State::Closed);
+  assert_eq!(
+    flow.stream(Direction::ClientToServer),
+    b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"
+  );
+  assert_eq!(
+    flow.stream(Direction::ServerToClient),
+    b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"
+  );
+
+  let (sent, received) = (
+    flow.traffic(Direction::ClientToServer),
+    flow.traffic(Direction::ServerToClient),
+  );
+  assert_eq!((sent.packets, received.packets), (6, 6));
+  assert_eq!((sent.payload_bytes, received.payload_bytes), (37, 48));
+  assert_eq!((sent.retransmissions, sent.out_of_order), (0, 0));
+  assert_eq!((received.retransmissions, received.out_of_order), (1, 1));
+
+  // Both SYNs, the request, the response and both FINs: the ack of the retransmission is left out
+  assert_eq!(flow.rtt.samples, 6);
+  assert_eq!((flow.rtt.min, flow.rtt.max), (Some(ms(1)), Some(ms(23))));
+  assert_eq!(flow.handshake, Some(ms(21)));
+}
+
+#[test]
+fn pcapng_interfaces_keep_their_link_type_and_resolution() {
+  let frames = frames("mixed.pcapng");
+  assert_eq!(frames.len(), 9);
+  let links: Vec<_> = frames.iter().map(|frame| frame.link).collect();
+  assert_eq!(links[.. 6], [LinkType::Ethernet; 6]);
+  assert_eq!(links[6 ..], [LinkType::Raw; 3]);
+  // Microseconds on the first interface, nanoseconds on the second
+  assert_eq!(frames[0].timestamp, EPOCH);
+  assert_eq!(frames[5].timestamp, EPOCH + ms(17));
+  assert_eq!(frames[6].timestamp, EPOCH + ms(20));
+
+  let decoded: Vec<Decoded> = frames
+    .iter()
+    .map(|frame| decode(frame.link, &frame.data))
+    .collect();
+  assert!(decoded[.. 2].iter().all(|packet| packet.vlans.is_empty()));
+  assert!(decoded[2 .. 6].iter().all(|packet| packet.vlans == [100]));
+  let Network::Ip(ip) = &decoded[1].network else {
+    panic!("{}", decoded[1]);
+  };
+  let Transport::Udp(udp) = &ip.transport else {
+    panic!("{}", ip);
+  };
+  let dns = udp.dns.as_ref().unwrap();
+  assert!(dns.response);
+  assert_eq!(dns.id, 0xbeef);
+  assert_eq!(dns.questions, [("example.com.".into(), "A".into())]);
+  assert_eq!(dns.answers, ["example.com. A 93.184.216.34"]);
+  assert!(matches!(
+    &decoded[8].network,
+    Network::Ip(ip) if ip.transport == Transport::Icmpv6 { icmp_type: 128, code: 0 }
+  ));
+
+  let flows = flows(&frames);
+  let protocols: Vec<_> = flows
+    .flows()
+    .iter()
+    .map(|flow| flow.protocol_name())
+    .collect();
+  assert_eq!(protocols, ["UDP", "TCP", "ICMP", "ICMPv6"]);
+  assert_eq!(flows.flows()[1].state, State::Reset);
+  assert_eq!(flows.flows()[1].handshake, Some(ms(6)));
+}
+
+#[test]
+fn flows_export_as_csv_and_json() {
+  let flows = flows(&frames("mixed.pcapng"));
+
+  let mut csv = vec![];
+  write_csv(&mut csv, flows.flows()).unwrap();
+  let csv = String::from_utf8(csv).unwrap();
+  let lines: Vec<_> = csv.lines().collect();
+  assert_eq!(lines.len(), 5);
+  assert!(lines[0].starts_with("protocol,client,server,first_seen,duration,state,"));
+  assert!(lines[1].starts_with("UDP,10.0.0.1:53000,10.0.0.53:53,1700000000.0,0.005,open,1,"));
+
+  let mut json = vec![];
+  write_json(&mut json, flows.flows()).unwrap();
+  let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+  let records = json.as_array().unwrap();
+  assert_eq!(records.len(), 4);
+  assert_eq!(records[1]["server"], "[2001:db8::2]:443");
+  assert_eq!(records[1]["state"], "reset");
+  assert_eq!(records[1]["rtt_samples"], 2);
+  assert_eq!(records[0]["rtt_mean_ms"], serde_json::Value::Null);
+}
+
+#[test]
+fn other_files_are_rejected() {
+  let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+  assert!(matches!(
+    Capture::open(&manifest),
+    Err(CaptureError::UnknownFormat { .. })
+  ));
+  assert!(matches!(
+    Capture::open(Path::new("missing.pcap")),
+    Err(CaptureError::Io { .. })
+  ));
+}
+
+#[test]
+fn timestamp_resolutions_beyond_a_u128_are_rejected() {
+  let path = std::env::temp_dir().join(format!("tsresol-{}.pcapng", std::process::id()));
+  let mut writer = PcapNgWriter::new(File::create(&path).unwrap()).unwrap();
+  writer
+    .write_pcapng_block(InterfaceDescriptionBlock {
+      linktype: DataLink::RAW,
+      snaplen: 65535,
+      options: vec![InterfaceDescriptionOption::IfTsResol(48)],
+    })
+    .unwrap();
+  writer
+    .write_pcapng_block(EnhancedPacketBlock {
+      interface_id: 0,
+      timestamp: EPOCH,
+      original_len: 0,
+      data: Cow::Borrowed(&[]),
+      options: vec![],
+    })
+    .unwrap();
+  drop(writer);
+  let frames: Vec<_> = Capture::open(&path).unwrap().collect();
+  std::fs::remove_file(&path).unwrap();
+  assert!(
+    matches!(frames[0], Err(CaptureError::Resolution(48))),
+    "{:?}",
+    frames
+  );
+}
@@ -0,0 +1,429 @@
+// Copyright (C) 2019, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice, this list of
+//       conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright notice, this list of
+//       conditions and the following disclaimer in the documentation and/or other materials
+//       provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+#[macro_use]
+extern crate log;
+
+use quiche::h3::NameValue;
+use ring::rand::*;
+use thiserror::Error;
+
+const MAX_DATAGRAM_SIZE: usize = 1350;
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("I/O error: {0}")]
+  Io(#[from] std::io::Error),
+  #[error("QUIC error: {0}")]
+  Quic(#[from] quiche::Error),
+  #[error("HTTP/3 error: {0}")]
+  Http3(#[from] quiche::h3::Error),
+  #[error("cannot connect to {0}")]
+  BadUrl(String),
+  #[error("request was reset by peer with {0}")]
+  Reset(u64),
+  #[error("connection closed before the response was complete")]
+  Closed,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Request {
+  pub method: String,
+  pub headers: Vec<(String, String)>,
+  pub body: Vec<u8>,
+}
+
+impl Request {
+  pub fn new(method: &str) -> Request {
+    Request {
+      method: method.to_string(),
+      ..Request::default()
+    }
+  }
+
+  pub fn with_header(mut self, name: &str, value: &str) -> Request {
+    self.headers.push((name.to_string(), value.to_string()));
+    self
+  }
+
+  pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Request {
+    self.body = body.into();
+    self
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Response {
+  pub status: u16,
+  pub headers: Vec<(String, String)>,
+  pub body: Vec<u8>,
+}
+
+impl Response {
+  pub fn header(&self, name: &str) -> Option<&str> {
+    self
+      .headers
+      .iter()
+      .find(|(n, _)| n.eq_ignore_ascii_case(name))
+      .map(|(_, v)| v.as_str())
+  }
+}
+
+/// Sends one request on a connection of its own and waits for the whole response.
+pub fn fetch(url: &url::Url, request: &Request) -> Result<Response, Error> {
+  let mut buf = [0; 65535];
+  let mut out = [0; MAX_DATAGRAM_SIZE];
+
+  // Setup the event loop.
+  let mut poll = mio::Poll::new()?;
+  let mut events = mio::Events::with_capacity(1024);
+
+  // Resolve server address.
+  let peer_addr = url
+    .socket_addrs(|| None)?
+    .into_iter()
+    .next()
+    .ok_or_else(|| Error::BadUrl(url.to_string()))?;
+
+  // Bind to INADDR_ANY or IN6ADDR_ANY depending on the IP family of the
+  // server address. This is needed on macOS and BSD variants that don't
+  // support binding to IN6ADDR_ANY for both v4 and v6.
+  let bind_addr = match peer_addr {
+    std::net::SocketAddr::V4(_) => "0.0.0.0:0",
+    std::net::SocketAddr::V6(_) => "[::]:0",
+  };
+
+  // Create the UDP socket backing the QUIC connection, and register it with
+  // the event loop.
+  let mut socket = mio::net::UdpSocket::bind(bind_addr.parse().unwrap())?;
+  poll
+    .registry()
+    .register(&mut socket, mio::Token(0), mio::Interest::READABLE)?;
+
+  // Create the configuration for the QUIC connection.
+  let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+
+  // *CAUTION*: this should not be set to `false` in production!!!
+  config.verify_peer(false);
+
+  config.set_application_protos(quiche::h3::APPLICATION_PROTOCOL)?;
+
+  config.set_max_idle_timeout(5000);
+  config.set_max_recv_udp_payload_size(MAX_DATAGRAM_SIZE);
+  config.set_max_send_udp_payload_size(MAX_DATAGRAM_SIZE);
+  config.set_initial_max_data(10_000_000);
+  config.set_initial_max_stream_data_bidi_local(1_000_000);
+  config.set_initial_max_stream_data_bidi_remote(1_000_000);
+  config.set_initial_max_stream_data_uni(1_000_000);
+  config.set_initial_max_streams_bidi(100);
+  config.set_initial_max_streams_uni(100);
+  config.set_disable_active_migration(true);
+
+  let mut http3_conn = None;
+
+  // Generate a random source connection ID for the connection.
+  let mut scid = [0; quiche::MAX_CONN_ID_LEN];
+  SystemRandom::new().fill(&mut scid[..]).unwrap();
+
+  let scid = quiche::ConnectionId::from_ref(&scid);
+
+  // Get local address.
+  let local_addr = socket.local_addr()?;
+
+  // Create a QUIC connection and initiate handshake.
+  let mut conn = quiche::connect(url.domain(), &scid, local_addr, peer_addr, &mut config)?;
+
+  info!(
+    "connecting to {:} from {:} with scid {}",
+    peer_addr,
+    local_addr,
+    hex_dump(&scid)
+  );
+
+  let (write, send_info) = conn.send(&mut out)?;
+
+  while let Err(e) = socket.send_to(&out[.. write], send_info.to) {
+    if e.kind() == std::io::ErrorKind::WouldBlock {
+      debug!("send() would block");
+      continue;
+    }
+
+    return Err(e.into());
+  }
+
+  debug!("written {}", write);
+
+  let h3_config = quiche::h3::Config::new()?;
+
+  // Prepare request.
+  let mut path = String::from(url.path());
+
+  if let Some(query) = url.query() {
+    path.push('?');
+    path.push_str(query);
+  }
+
+  let host = url
+    .host_str()
+    .ok_or_else(|| Error::BadUrl(url.to_string()))?;
+
+  let mut req = vec![
+    quiche::h3::Header::new(b":method", request.method.as_bytes()),
+    quiche::h3::Header::new(b":scheme", url.scheme().as_bytes()),
+    quiche::h3::Header::new(b":authority", host.as_bytes()),
+    quiche::h3::Header::new(b":path", path.as_bytes()),
+    quiche::h3::Header::new(b"user-agent", b"quiche"),
+  ];
+
+  req.extend(request.headers.iter().map(|(name, value)| {
+    quiche::h3::Header::new(name.to_lowercase().as_bytes(), value.as_bytes())
+  }));
+
+  let req_start = std::time::Instant::now();
+
+  // The request stream once the headers are sent, and how much of the body
+  // went out on it.
+  let mut req_stream = None;
+  let mut body_sent = 0;
+
+  let mut response = Response::default();
+  let mut outcome = None;
+
+  loop {
+    poll.poll(&mut events, conn.timeout())?;
+
+    // Read incoming UDP packets from the socket and feed them to quiche,
+    // until there are no more packets to read.
+    'read: loop {
+      // If the event loop reported no events, it means that the timeout
+      // has expired, so handle it without attempting to read packets. We
+      // will then proceed with the send loop.
+      if events.is_empty() {
+        debug!("timed out");
+
+        conn.on_timeout();
+
+        break 'read;
+      }
+
+      let (len, from) = match socket.recv_from(&mut buf) {
+        Ok(v) => v,
+
+        Err(e) => {
+          // There are no more UDP packets to read, so end the read
+          // loop.
+          if e.kind() == std::io::ErrorKind::WouldBlock {
+            debug!("recv() would block");
+            break 'read;
+          }
+
+          return Err(e.into());
+        }
+      };
+
+      debug!("got {} bytes", len);
+
+      let recv_info = quiche::RecvInfo {
+        to: local_addr,
+        from,
+      };
+
+      // Process potentially coalesced packets.
+      let read = match conn.recv(&mut buf[.. len], recv_info) {
+        Ok(v) => v,
+
+        Err(e) => {
+          error!("recv failed: {:?}", e);
+          continue 'read;
+        }
+      };
+
+      debug!("processed {} bytes", read);
+    }
+
+    debug!("done reading");
+
+    if conn.is_closed() {
+      info!("connection closed, {:?}", conn.stats());
+      break;
+    }
+
+    // Create a new HTTP/3 connection once the QUIC connection is established.
+    if conn.is_established() && http3_conn.is_none() {
+      http3_conn = Some(quiche::h3::Connection::with_transport(
+        &mut conn, &h3_config,
+      )?);
+    }
+
+    if let Some(h3_conn) = &mut http3_conn {
+      // Send the HTTP request once the QUIC connection is established.
+      if req_stream.is_none() {
+        info!("sending HTTP request {:?}", req);
+
+        req_stream = Some(h3_conn.send_request(&mut conn, &req, request.body.is_empty())?);
+      }
+
+      // Then its body, as flow control lets it through.
+      if let Some(stream_id) = req_stream {
+        if body_sent < request.body.len() {
+          match h3_conn.send_body(&mut conn, stream_id, &request.body[body_sent ..], true) {
+            Ok(written) => body_sent += written,
+
+            Err(quiche::h3::Error::Done) => (),
+
+            Err(e) => return Err(e.into()),
+          }
+        }
+      }
+    }
+
+    if let Some(http3_conn) = &mut http3_conn {
+      // Process HTTP/3 events.
+      loop {
+        match http3_conn.poll(&mut conn) {
+          Ok((stream_id, quiche::h3::Event::Headers { list, .. })) => {
+            info!(
+              "got response headers {:?} on stream id {}",
+              hdrs_to_strings(&list),
+              stream_id
+            );
+
+            for (name, value) in hdrs_to_strings(&list) {
+              if name == ":status" {
+                response.status = value.parse().unwrap_or_default();
+              } else {
+                response.headers.push((name, value));
+              }
+            }
+          }
+
+          Ok((stream_id, quiche::h3::Event::Data)) => {
+            while let Ok(read) = http3_conn.recv_body(&mut conn, stream_id, &mut buf) {
+              debug!(
+                "got {} bytes of response data on stream {}",
+                read, stream_id
+              );
+
+              response.body.extend_from_slice(&buf[.. read]);
+            }
+          }
+
+          Ok((_stream_id, quiche::h3::Event::Finished)) => {
+            info!("response received in {:?}, closing...", req_start.elapsed());
+
+            outcome = Some(Ok(()));
+
+            conn.close(true, 0x00, b"kthxbye").ok();
+          }
+
+          Ok((_stream_id, quiche::h3::Event::Reset(e))) => {
+            error!("request was reset by peer with {}, closing...", e);
+
+            outcome = Some(Err(Error::Reset(e)));
+
+            conn.close(true, 0x00, b"kthxbye").ok();
+          }
+
+          Ok((_, quiche::h3::Event::PriorityUpdate)) => (),
+
+          Ok((goaway_id, quiche::h3::Event::GoAway)) => {
+            info!("GOAWAY id={}", goaway_id);
+          }
+
+          Err(quiche::h3::Error::Done) => {
+            break;
+          }
+
+          Err(e) => {
+            error!("HTTP/3 processing failed: {:?}", e);
+
+            break;
+          }
+        }
+      }
+    }
+
+    // Generate outgoing QUIC packets and send them on the UDP socket, until
+    // quiche reports that there are no more packets to be sent.
+    loop {
+      let (write, send_info) = match conn.send(&mut out) {
+        Ok(v) => v,
+
+        Err(quiche::Error::Done) => {
+          debug!("done writing");
+          break;
+        }
+
+        Err(e) => {
+          error!("send failed: {:?}", e);
+
+          conn.close(false, 0x1, b"fail").ok();
+          break;
+        }
+      };
+
+      if let Err(e) = socket.send_to(&out[.. write], send_info.to) {
+        if e.kind() == std::io::ErrorKind::WouldBlock {
+          debug!("send() would block");
+          break;
+        }
+
+        return Err(e.into());
+      }
+
+      debug!("written {}", write);
+    }
+
+    if conn.is_closed() {
+      info!("connection closed, {:?}", conn.stats());
+      break;
+    }
+  }
+
+  match outcome {
+    Some(Ok(())) => Ok(response),
+    Some(Err(e)) => Err(e),
+    None => Err(Error::Closed),
+  }
+}
+
+fn hex_dump(buf: &[u8]) -> String {
+  let vec: Vec<String> = buf.iter().map(|b| format!("{:02x}", b)).collect();
+
+  vec.join("")
+}
+
+pub fn hdrs_to_strings(hdrs: &[quiche::h3::Header]) -> Vec<(String, String)> {
+  hdrs
+    .iter()
+    .map(|h| {
+      let name = String::from_utf8_lossy(h.name()).to_string();
+      let value = String::from_utf8_lossy(h.value()).to_string();
+
+      (name, value)
+    })
+    .collect()
+}
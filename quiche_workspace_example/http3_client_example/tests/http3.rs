@@ -0,0 +1,197 @@
+use std::{fs, sync::mpsc, thread};
+
+use http3_client_example::{fetch, Request, Response};
+use http3_server_example::{Config, Echo, Router, Server, StaticFiles};
+use tempfile::TempDir;
+
+/// A server on a port of its own over a fresh directory, running until the test process ends.
+fn serve() -> (String, TempDir) {
+  let root = tempfile::tempdir().unwrap();
+  fs::write(root.path().join("hello.txt"), "Hello, world!\n").unwrap();
+  fs::write(root.path().join("big.bin"), big()).unwrap();
+  fs::create_dir(root.path().join("site")).unwrap();
+  fs::write(root.path().join("site/index.html"), "<h1>site</h1>").unwrap();
+  fs::create_dir(root.path().join("docs")).unwrap();
+  fs::write(root.path().join("docs/a <b>.md"), "# a").unwrap();
+  fs::create_dir(root.path().join("docs/sub")).unwrap();
+
+  let (tx, rx) = mpsc::channel();
+  let files = root.path().to_path_buf();
+  thread::spawn(move || {
+    let config = Config {
+      listen: "127.0.0.1:0".parse().unwrap(),
+      ..Config::default()
+    };
+    let server = Server::bind(&config).unwrap();
+    tx.send(server.local_addr().unwrap()).unwrap();
+    server
+      .run(&Router::new(StaticFiles::new(files)).post(Echo))
+      .unwrap();
+  });
+  let addr = rx.recv().unwrap();
+  (format!("https://127.0.0.1:{}", addr.port()), root)
+}
+
+/// Bigger than the flow control windows, so it goes out over many writable events.
+fn big() -> Vec<u8> {
+  (0 .. 3_000_000u32).map(|i| (i % 251) as u8).collect()
+}
+
+fn get(base: &str, path: &str, request: Request) -> Response {
+  let url = url::Url::parse(&format!("{}{}", base, path)).unwrap();
+  fetch(&url, &request).unwrap()
+}
+
+#[test]
+fn files_are_served_with_their_type_and_etag() {
+  let (base, _root) = serve();
+
+  let response = get(&base, "/hello.txt", Request::new("GET"));
+  assert_eq!(response.status, 200);
+  assert_eq!(response.body, b"Hello, world!\n");
+  assert_eq!(
+    response.header("content-type"),
+    Some("text/plain; charset=utf-8")
+  );
+  assert_eq!(response.header("content-length"), Some("14"));
+  assert_eq!(response.header("accept-ranges"), Some("bytes"));
+  assert!(response.header("etag").is_some());
+
+  let response = get(&base, "/big.bin", Request::new("GET"));
+  assert_eq!(response.status, 200);
+  assert_eq!(
+    response.header("content-type"),
+    Some("application/octet-stream")
+  );
+  assert_eq!(response.body.len(), 3_000_000);
+  assert!(response.body == big());
+
+  assert_eq!(get(&base, "/missing", Request::new("GET")).status, 404);
+  assert_eq!(
+    get(&base, "/../etc/passwd", Request::new("GET")).status,
+    404
+  );
+}
+
+#[test]
+fn ranges_are_honoured() {
+  let (base, _root) = serve();
+
+  let response = get(
+    &base,
+    "/big.bin",
+    Request::new("GET").with_header("range", "bytes=1000000-1000009"),
+  );
+  assert_eq!(response.status, 206);
+  assert_eq!(
+    response.header("content-range"),
+    Some("bytes 1000000-1000009/3000000")
+  );
+  assert_eq!(response.body, &big()[1_000_000 .. 1_000_010]);
+
+  let response = get(
+    &base,
+    "/hello.txt",
+    Request::new("GET").with_header("range", "bytes=-6"),
+  );
+  assert_eq!(response.status, 206);
+  assert_eq!(response.body, b"orld!\n");
+
+  let response = get(
+    &base,
+    "/hello.txt",
+    Request::new("GET").with_header("range", "bytes=100-"),
+  );
+  assert_eq!(response.status, 416);
+  assert_eq!(response.header("content-range"), Some("bytes */14"));
+
+  // Ranges of another version of the file give the whole of this one
+  let response = get(
+    &base,
+    "/hello.txt",
+    Request::new("GET")
+      .with_header("range", "bytes=0-4")
+      .with_header("if-range", "\"stale\""),
+  );
+  assert_eq!(response.status, 200);
+  assert_eq!(response.body, b"Hello, world!\n");
+}
+
+#[test]
+fn conditional_and_head_requests_skip_the_body() {
+  let (base, _root) = serve();
+
+  let etag = get(&base, "/hello.txt", Request::new("GET"))
+    .header("etag")
+    .unwrap()
+    .to_string();
+  let response = get(
+    &base,
+    "/hello.txt",
+    Request::new("GET").with_header("if-none-match", &etag),
+  );
+  assert_eq!(response.status, 304);
+  assert_eq!(response.header("etag"), Some(etag.as_str()));
+  assert!(response.body.is_empty());
+
+  let response = get(
+    &base,
+    "/hello.txt",
+    Request::new("GET").with_header("if-none-match", "\"other\""),
+  );
+  assert_eq!(response.status, 200);
+
+  let response = get(&base, "/big.bin", Request::new("HEAD"));
+  assert_eq!(response.status, 200);
+  assert_eq!(response.header("content-length"), Some("3000000"));
+  assert!(response.body.is_empty());
+}
+
+#[test]
+fn directories_have_an_index() {
+  let (base, _root) = serve();
+
+  let response = get(&base, "/site/", Request::new("GET"));
+  assert_eq!(response.status, 200);
+  assert_eq!(response.body, b"<h1>site</h1>");
+
+  let response = get(&base, "/site", Request::new("GET"));
+  assert_eq!(response.status, 301);
+  assert_eq!(response.header("location"), Some("/site/"));
+
+  let response = get(&base, "/docs/", Request::new("GET"));
+  assert_eq!(response.status, 200);
+  assert_eq!(
+    response.header("content-type"),
+    Some("text/html; charset=utf-8")
+  );
+  let page = String::from_utf8(response.body).unwrap();
+  assert!(page.contains("<a href=\"../\">"));
+  assert!(page.contains("<a href=\"a%20%3Cb%3E.md\">a &lt;b&gt;.md</a>"));
+  assert!(page.contains("<a href=\"sub/\">sub/</a>"));
+
+  let response = get(&base, "/docs/a%20%3Cb%3E.md", Request::new("GET"));
+  assert_eq!(response.body, b"# a");
+}
+
+#[test]
+fn posts_reach_the_handler_with_their_body() {
+  let (base, _root) = serve();
+
+  let body: Vec<u8> = (0 .. 100_000u32).map(|i| (i % 7) as u8 + b'a').collect();
+  let response = get(
+    &base,
+    "/echo",
+    Request::new("POST")
+      .with_header("content-type", "text/plain")
+      .with_body(body.clone()),
+  );
+  assert_eq!(response.status, 200);
+  assert_eq!(response.header("content-type"), Some("text/plain"));
+  assert_eq!(response.header("content-length"), Some("100000"));
+  assert!(response.body == body);
+
+  let response = get(&base, "/hello.txt", Request::new("DELETE"));
+  assert_eq!(response.status, 405);
+  assert_eq!(response.header("allow"), Some("GET, HEAD, POST"));
+}
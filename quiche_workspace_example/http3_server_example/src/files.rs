@@ -0,0 +1,360 @@
+use std::{
+  fs::{self, File, Metadata},
+  io::{self, Seek, SeekFrom},
+  path::{Component, Path, PathBuf},
+  time::UNIX_EPOCH,
+};
+
+use crate::http::{method_not_allowed, Body, Handler, Request, Response};
+
+/// Serves the files under a directory: `index.html` or a listing for directories, single byte
+/// ranges, and ETags for conditional requests.
+pub struct StaticFiles {
+  root: PathBuf,
+}
+
+impl StaticFiles {
+  pub fn new(root: impl Into<PathBuf>) -> StaticFiles {
+    StaticFiles { root: root.into() }
+  }
+
+  /// The file a request path names. `None` for paths that would leave the root.
+  fn resolve(&self, path: &str) -> Option<PathBuf> {
+    let mut file_path = self.root.clone();
+    for component in Path::new(path).components() {
+      match component {
+        Component::Normal(v) => file_path.push(v),
+        Component::RootDir | Component::CurDir => (),
+        Component::ParentDir | Component::Prefix(_) => return None,
+      }
+    }
+    Some(file_path)
+  }
+}
+
+impl Handler for StaticFiles {
+  fn handle(&self, request: &Request) -> Response {
+    if request.method != "GET" && request.method != "HEAD" {
+      return method_not_allowed("GET, HEAD");
+    }
+    let raw_path = request.path.split('?').next().unwrap_or("/");
+    let Some(path) = percent_decode(raw_path) else {
+      return Response::text(400, "Bad Request");
+    };
+    let Some(file_path) = self.resolve(&path) else {
+      return not_found();
+    };
+    let metadata = match fs::metadata(&file_path) {
+      Ok(metadata) => metadata,
+      Err(e) => return io_error(e),
+    };
+
+    let response = if metadata.is_dir() {
+      // Relative links in the page need the slash
+      if !path.ends_with('/') {
+        return Response::new(301).with_header("location", &format!("{}/", raw_path));
+      }
+      let index = file_path.join("index.html");
+      match fs::metadata(&index) {
+        Ok(metadata) if metadata.is_file() => serve_file(request, &index, &metadata),
+        _ => listing(&path, &file_path),
+      }
+    } else {
+      serve_file(request, &file_path, &metadata)
+    };
+    response.unwrap_or_else(io_error)
+  }
+}
+
+fn not_found() -> Response {
+  Response::text(404, "Not Found!")
+}
+
+fn io_error(e: io::Error) -> Response {
+  match e.kind() {
+    io::ErrorKind::NotFound => not_found(),
+    io::ErrorKind::PermissionDenied => Response::text(403, "Forbidden"),
+    _ => {
+      error!("reading file failed: {}", e);
+      Response::text(500, "Internal Server Error")
+    }
+  }
+}
+
+fn serve_file(request: &Request, path: &Path, metadata: &Metadata) -> io::Result<Response> {
+  let len = metadata.len();
+  let etag = etag(metadata);
+  let response = Response::new(200)
+    .with_header("content-type", content_type(path))
+    .with_header("etag", &etag)
+    .with_header("accept-ranges", "bytes");
+
+  if let Some(if_none_match) = request.header("if-none-match") {
+    if etag_matches(if_none_match, &etag) {
+      let mut response = response;
+      response.status = 304;
+      response.headers.retain(|(name, _)| name == "etag");
+      return Ok(response);
+    }
+  }
+
+  // A range of a version of the file the client no longer has would not fit with its copy
+  let range = match request.header("range") {
+    Some(range) if request.header("if-range").is_none_or(|tag| tag == etag) => {
+      parse_range(range, len)
+    }
+    _ => Ok(None),
+  };
+  let (mut response, start, end) = match range {
+    Ok(Some((start, end))) => {
+      let mut response =
+        response.with_header("content-range", &format!("bytes {}-{}/{}", start, end, len));
+      response.status = 206;
+      (response, start, end + 1)
+    }
+    Ok(None) => (response, 0, len),
+    Err(Unsatisfiable) => {
+      return Ok(
+        Response::text(416, "Range Not Satisfiable")
+          .with_header("content-range", &format!("bytes */{}", len)),
+      );
+    }
+  };
+
+  let mut file = File::open(path)?;
+  file.seek(SeekFrom::Start(start))?;
+  response = response
+    .with_header("content-length", &(end - start).to_string())
+    .with_body(Body::File {
+      file,
+      remaining: end - start,
+    });
+  Ok(response)
+}
+
+/// Changes with the size or modification time of the file.
+fn etag(metadata: &Metadata) -> String {
+  let modified = metadata
+    .modified()
+    .ok()
+    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+    .map(|since| since.as_nanos())
+    .unwrap_or(0);
+  format!("\"{:x}-{:x}\"", metadata.len(), modified)
+}
+
+/// `If-None-Match` compares weakly: `W/"x"` matches `"x"`.
+fn etag_matches(header: &str, etag: &str) -> bool {
+  header
+    .split(',')
+    .map(str::trim)
+    .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Unsatisfiable;
+
+/// The first and last byte of a `bytes=` range within `len` bytes. `None` for what is not a
+/// single byte range, which is answered with the whole file as RFC 9110 allows.
+fn parse_range(header: &str, len: u64) -> Result<Option<(u64, u64)>, Unsatisfiable> {
+  let Some(spec) = header.trim().strip_prefix("bytes=") else {
+    return Ok(None);
+  };
+  if spec.contains(',') {
+    return Ok(None);
+  }
+  let Some((first, last)) = spec.trim().split_once('-') else {
+    return Ok(None);
+  };
+  if first.is_empty() {
+    // The last bytes
+    let Ok(suffix) = last.parse::<u64>() else {
+      return Ok(None);
+    };
+    if suffix == 0 || len == 0 {
+      return Err(Unsatisfiable);
+    }
+    return Ok(Some((len.saturating_sub(suffix), len - 1)));
+  }
+  let Ok(first) = first.parse::<u64>() else {
+    return Ok(None);
+  };
+  let last = match last {
+    "" => u64::MAX,
+    last => match last.parse::<u64>() {
+      Ok(last) if last >= first => last,
+      _ => return Ok(None),
+    },
+  };
+  if first >= len {
+    return Err(Unsatisfiable);
+  }
+  Ok(Some((first, last.min(len - 1))))
+}
+
+fn content_type(path: &Path) -> &'static str {
+  let extension = path
+    .extension()
+    .and_then(|extension| extension.to_str())
+    .map(str::to_ascii_lowercase);
+  match extension.as_deref() {
+    Some("html" | "htm") => "text/html; charset=utf-8",
+    Some("css") => "text/css; charset=utf-8",
+    Some("js" | "mjs") => "text/javascript; charset=utf-8",
+    Some("json") => "application/json",
+    Some("txt") => "text/plain; charset=utf-8",
+    Some("md") => "text/markdown; charset=utf-8",
+    Some("csv") => "text/csv; charset=utf-8",
+    Some("xml") => "application/xml",
+    Some("svg") => "image/svg+xml",
+    Some("png") => "image/png",
+    Some("jpg" | "jpeg") => "image/jpeg",
+    Some("gif") => "image/gif",
+    Some("webp") => "image/webp",
+    Some("ico") => "image/x-icon",
+    Some("wasm") => "application/wasm",
+    Some("pdf") => "application/pdf",
+    Some("zip") => "application/zip",
+    Some("gz") => "application/gzip",
+    Some("mp3") => "audio/mpeg",
+    Some("mp4") => "video/mp4",
+    Some("webm") => "video/webm",
+    Some("woff") => "font/woff",
+    Some("woff2") => "font/woff2",
+    _ => "application/octet-stream",
+  }
+}
+
+/// A page linking to the entries of a directory, itself with no `index.html`.
+fn listing(path: &str, dir: &Path) -> io::Result<Response> {
+  let mut entries = vec![];
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+    let mut name = entry.file_name().to_string_lossy().into_owned();
+    if entry.file_type()?.is_dir() {
+      name.push('/');
+    }
+    entries.push(name);
+  }
+  entries.sort();
+
+  let title = format!("Index of {}", escape_html(path));
+  let mut page = format!(
+    "<!DOCTYPE html>\n<html>\n<head><meta \
+     charset=\"utf-8\"><title>{}</title></head>\n<body>\n<h1>{}</h1>\n<ul>\n",
+    title, title
+  );
+  if path != "/" {
+    page.push_str("<li><a href=\"../\">../</a></li>\n");
+  }
+  for name in entries {
+    page.push_str(&format!(
+      "<li><a href=\"{}\">{}</a></li>\n",
+      percent_encode(&name),
+      escape_html(&name)
+    ));
+  }
+  page.push_str("</ul>\n</body>\n</html>\n");
+
+  Ok(
+    Response::new(200)
+      .with_header("content-type", "text/html; charset=utf-8")
+      .with_header("content-length", &page.len().to_string())
+      .with_body(Body::Bytes(page.into_bytes())),
+  )
+}
+
+fn escape_html(s: &str) -> String {
+  s.chars()
+    .map(|c| match c {
+      '&' => "&amp;".to_string(),
+      '<' => "&lt;".to_string(),
+      '>' => "&gt;".to_string(),
+      '"' => "&quot;".to_string(),
+      '\'' => "&#39;".to_string(),
+      c => c.to_string(),
+    })
+    .collect()
+}
+
+fn percent_encode(s: &str) -> String {
+  s.bytes()
+    .map(|b| match b {
+      b'A' ..= b'Z' | b'a' ..= b'z' | b'0' ..= b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+        (b as char).to_string()
+      }
+      b => format!("%{:02X}", b),
+    })
+    .collect()
+}
+
+/// `None` for a bad escape or what does not decode to UTF-8.
+fn percent_decode(s: &str) -> Option<String> {
+  let mut bytes = Vec::with_capacity(s.len());
+  let mut rest = s.as_bytes();
+  while let Some((&b, tail)) = rest.split_first() {
+    if b == b'%' {
+      let hex = std::str::from_utf8(tail.get(.. 2)?).ok()?;
+      bytes.push(u8::from_str_radix(hex, 16).ok()?);
+      rest = &tail[2 ..];
+    } else {
+      bytes.push(b);
+      rest = tail;
+    }
+  }
+  String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn ranges_are_parsed_against_the_length() {
+    assert_eq!(parse_range("bytes=0-9", 100), Ok(Some((0, 9))));
+    assert_eq!(parse_range("bytes=90-", 100), Ok(Some((90, 99))));
+    assert_eq!(parse_range("bytes=90-1000", 100), Ok(Some((90, 99))));
+    assert_eq!(parse_range("bytes=-10", 100), Ok(Some((90, 99))));
+    assert_eq!(parse_range("bytes=-1000", 100), Ok(Some((0, 99))));
+    assert_eq!(parse_range("bytes=100-", 100), Err(Unsatisfiable));
+    assert_eq!(parse_range("bytes=-0", 100), Err(Unsatisfiable));
+    // Ignored: the whole file is sent
+    assert_eq!(parse_range("bytes=0-1,5-6", 100), Ok(None));
+    assert_eq!(parse_range("bytes=9-0", 100), Ok(None));
+    assert_eq!(parse_range("lines=1-2", 100), Ok(None));
+    assert_eq!(parse_range("bytes=a-b", 100), Ok(None));
+  }
+
+  #[test]
+  fn etags_match_weakly_and_in_lists() {
+    assert!(etag_matches("\"a-1\"", "\"a-1\""));
+    assert!(etag_matches("\"b\", W/\"a-1\"", "\"a-1\""));
+    assert!(etag_matches("*", "\"a-1\""));
+    assert!(!etag_matches("\"a-2\"", "\"a-1\""));
+  }
+
+  #[test]
+  fn paths_decode_and_stay_under_the_root() {
+    assert_eq!(percent_decode("/a%20b/c").as_deref(), Some("/a b/c"));
+    assert_eq!(percent_decode("/%zz"), None);
+    assert_eq!(percent_decode("/%2"), None);
+    assert_eq!(percent_encode("a b/é"), "a%20b/%C3%A9");
+
+    let files = StaticFiles::new("root");
+    assert_eq!(files.resolve("/a/./b"), Some(PathBuf::from("root/a/b")));
+    assert_eq!(files.resolve("/a/../../etc/passwd"), None);
+  }
+
+  #[test]
+  fn content_types_follow_the_extension() {
+    assert_eq!(
+      content_type(Path::new("index.HTML")),
+      "text/html; charset=utf-8"
+    );
+    assert_eq!(content_type(Path::new("a/b.woff2")), "font/woff2");
+    assert_eq!(
+      content_type(Path::new("Makefile")),
+      "application/octet-stream"
+    );
+  }
+}
@@ -0,0 +1,217 @@
+use std::{
+  fs::File,
+  io::{self, Read},
+};
+
+use crate::files::StaticFiles;
+
+/// A request as the handlers see it, with the whole body when there was one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Request {
+  pub method: String,
+  pub path: String,
+  pub headers: Vec<(String, String)>,
+  pub body: Vec<u8>,
+}
+
+impl Request {
+  pub fn new(method: &str, path: &str) -> Request {
+    Request {
+      method: method.to_string(),
+      path: path.to_string(),
+      ..Request::default()
+    }
+  }
+
+  pub fn with_header(mut self, name: &str, value: &str) -> Request {
+    self.headers.push((name.to_string(), value.to_string()));
+    self
+  }
+
+  /// The first value of a header. HTTP/3 sends names in lowercase.
+  pub fn header(&self, name: &str) -> Option<&str> {
+    self
+      .headers
+      .iter()
+      .find(|(n, _)| n.eq_ignore_ascii_case(name))
+      .map(|(_, v)| v.as_str())
+  }
+}
+
+/// What a response sends after its headers. Files are read a chunk at a time, as the stream
+/// has room for them, rather than all at once.
+#[derive(Debug)]
+pub enum Body {
+  Empty,
+  Bytes(Vec<u8>),
+  File { file: File, remaining: u64 },
+}
+
+impl Body {
+  pub fn len(&self) -> u64 {
+    match self {
+      Body::Empty => 0,
+      Body::Bytes(bytes) => bytes.len() as u64,
+      Body::File { remaining, .. } => *remaining,
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Takes up to `max` bytes off the front of the body.
+  pub fn next_chunk(&mut self, max: usize) -> io::Result<Vec<u8>> {
+    match self {
+      Body::Empty => Ok(vec![]),
+      Body::Bytes(bytes) => {
+        let rest = bytes.split_off(max.min(bytes.len()));
+        Ok(std::mem::replace(bytes, rest))
+      }
+      Body::File { file, remaining } => {
+        let mut chunk = vec![0; max.min(*remaining as usize)];
+        file.read_exact(&mut chunk)?;
+        *remaining -= chunk.len() as u64;
+        Ok(chunk)
+      }
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct Response {
+  pub status: u16,
+  pub headers: Vec<(String, String)>,
+  pub body: Body,
+}
+
+impl Response {
+  pub fn new(status: u16) -> Response {
+    Response {
+      status,
+      headers: vec![],
+      body: Body::Empty,
+    }
+  }
+
+  /// A `text/plain` response.
+  pub fn text(status: u16, text: &str) -> Response {
+    Response::new(status)
+      .with_header("content-type", "text/plain; charset=utf-8")
+      .with_body(Body::Bytes(text.as_bytes().to_vec()))
+  }
+
+  pub fn with_header(mut self, name: &str, value: &str) -> Response {
+    self.headers.push((name.to_string(), value.to_string()));
+    self
+  }
+
+  pub fn with_body(mut self, body: Body) -> Response {
+    self.body = body;
+    self
+  }
+
+  pub fn header(&self, name: &str) -> Option<&str> {
+    self
+      .headers
+      .iter()
+      .find(|(n, _)| n.eq_ignore_ascii_case(name))
+      .map(|(_, v)| v.as_str())
+  }
+
+  /// Adds the `content-length` the handler left out, and drops the body of an answer to HEAD
+  /// after counting it.
+  pub(crate) fn finish(mut self, head: bool) -> Response {
+    let bodiless = self.status == 204 || self.status == 304;
+    if !bodiless && self.header("content-length").is_none() {
+      let len = self.body.len().to_string();
+      self = self.with_header("content-length", &len);
+    }
+    if head {
+      self.body = Body::Empty;
+    }
+    self
+  }
+}
+
+/// Turns requests into responses.
+pub trait Handler {
+  fn handle(&self, request: &Request) -> Response;
+}
+
+/// Sends GET and HEAD to the static files, POST to a handler of its own.
+pub struct Router {
+  files: StaticFiles,
+  post: Option<Box<dyn Handler>>,
+}
+
+impl Router {
+  pub fn new(files: StaticFiles) -> Router {
+    Router { files, post: None }
+  }
+
+  pub fn post(mut self, handler: impl Handler + 'static) -> Router {
+    self.post = Some(Box::new(handler));
+    self
+  }
+}
+
+impl Handler for Router {
+  fn handle(&self, request: &Request) -> Response {
+    match (request.method.as_str(), &self.post) {
+      ("GET" | "HEAD", _) => self.files.handle(request),
+      ("POST", Some(post)) => post.handle(request),
+      (_, Some(_)) => method_not_allowed("GET, HEAD, POST"),
+      (_, None) => method_not_allowed("GET, HEAD"),
+    }
+  }
+}
+
+pub(crate) fn method_not_allowed(allow: &str) -> Response {
+  Response::text(405, "Method Not Allowed").with_header("allow", allow)
+}
+
+/// Answers POST with what was posted.
+pub struct Echo;
+
+impl Handler for Echo {
+  fn handle(&self, request: &Request) -> Response {
+    let content_type = request
+      .header("content-type")
+      .unwrap_or("application/octet-stream");
+    Response::new(200)
+      .with_header("content-type", content_type)
+      .with_body(Body::Bytes(request.body.clone()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bodies_are_taken_in_chunks() {
+    let mut body = Body::Bytes(b"hello world".to_vec());
+    assert_eq!(body.next_chunk(5).unwrap(), b"hello");
+    assert_eq!(body.len(), 6);
+    assert_eq!(body.next_chunk(100).unwrap(), b" world");
+    assert!(body.is_empty());
+    assert!(body.next_chunk(100).unwrap().is_empty());
+  }
+
+  #[test]
+  fn answers_to_head_keep_the_length_of_the_body() {
+    let response = Response::text(404, "Not Found!").finish(true);
+    assert_eq!(response.header("content-length"), Some("10"));
+    assert!(response.body.is_empty());
+
+    let response = Response::new(304).finish(false);
+    assert_eq!(response.header("content-length"), None);
+    let response = Response::new(200)
+      .with_header("content-length", "3")
+      .with_body(Body::Bytes(b"abc".to_vec()))
+      .finish(false);
+    assert_eq!(response.headers.len(), 1);
+    assert_eq!(response.body.len(), 3);
+  }
+}
@@ -0,0 +1,10 @@
+#[macro_use]
+extern crate log;
+
+mod files;
+mod http;
+mod server;
+
+pub use files::StaticFiles;
+pub use http::{Body, Echo, Handler, Request, Response, Router};
+pub use server::{Config, Error, Server};
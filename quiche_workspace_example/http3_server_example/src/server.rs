@@ -0,0 +1,732 @@
+// Copyright (C) 2019, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice, this list of
+//       conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright notice, this list of
+//       conditions and the following disclaimer in the documentation and/or other materials
+//       provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::{
+  collections::HashMap,
+  io,
+  net::{self, SocketAddr},
+  path::PathBuf,
+};
+
+use quiche::h3::NameValue;
+use ring::rand::*;
+use thiserror::Error;
+
+use crate::http::{Body, Handler, Request, Response};
+
+const MAX_DATAGRAM_SIZE: usize = 1350;
+
+/// How much of a file is read at a time, as the stream has room for it.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Request bodies over this are answered with 413.
+const MAX_REQUEST_BODY: usize = 1 << 20;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+  pub listen: SocketAddr,
+  pub cert: PathBuf,
+  pub key: PathBuf,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      listen: "127.0.0.1:4433".parse().unwrap(),
+      cert: concat!(env!("CARGO_MANIFEST_DIR"), "/examples/cert.crt").into(),
+      key: concat!(env!("CARGO_MANIFEST_DIR"), "/examples/cert.key").into(),
+    }
+  }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("I/O error: {0}")]
+  Io(#[from] io::Error),
+  #[error("QUIC error: {0}")]
+  Quic(#[from] quiche::Error),
+  #[error("HTTP/3 error: {0}")]
+  Http3(#[from] quiche::h3::Error),
+}
+
+struct PartialResponse {
+  headers: Option<Vec<quiche::h3::Header>>,
+
+  body: Body,
+
+  /// Read from the body but not yet accepted by the stream.
+  chunk: Vec<u8>,
+
+  written: usize,
+}
+
+struct Client {
+  conn: quiche::Connection,
+
+  http3_conn: Option<quiche::h3::Connection>,
+
+  partial_responses: HashMap<u64, PartialResponse>,
+
+  /// Requests whose body is still coming in.
+  partial_requests: HashMap<u64, Request>,
+}
+
+type ClientMap = HashMap<quiche::ConnectionId<'static>, Client>;
+
+/// An HTTP/3 server on a UDP socket, handing requests to a [`Handler`].
+pub struct Server {
+  poll: mio::Poll,
+
+  socket: mio::net::UdpSocket,
+
+  config: quiche::Config,
+
+  h3_config: quiche::h3::Config,
+}
+
+impl Server {
+  pub fn bind(config: &Config) -> Result<Server, Error> {
+    // Setup the event loop.
+    let poll = mio::Poll::new()?;
+
+    // Create the UDP listening socket, and register it with the event loop.
+    let mut socket = mio::net::UdpSocket::bind(config.listen)?;
+    poll
+      .registry()
+      .register(&mut socket, mio::Token(0), mio::Interest::READABLE)?;
+
+    // Create the configuration for the QUIC connections.
+    let mut quic_config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+
+    quic_config.load_cert_chain_from_pem_file(&config.cert.to_string_lossy())?;
+    quic_config.load_priv_key_from_pem_file(&config.key.to_string_lossy())?;
+
+    quic_config.set_application_protos(quiche::h3::APPLICATION_PROTOCOL)?;
+
+    quic_config.set_max_idle_timeout(5000);
+    quic_config.set_max_recv_udp_payload_size(MAX_DATAGRAM_SIZE);
+    quic_config.set_max_send_udp_payload_size(MAX_DATAGRAM_SIZE);
+    quic_config.set_initial_max_data(10_000_000);
+    quic_config.set_initial_max_stream_data_bidi_local(1_000_000);
+    quic_config.set_initial_max_stream_data_bidi_remote(1_000_000);
+    quic_config.set_initial_max_stream_data_uni(1_000_000);
+    quic_config.set_initial_max_streams_bidi(100);
+    quic_config.set_initial_max_streams_uni(100);
+    quic_config.set_disable_active_migration(true);
+    quic_config.enable_early_data();
+
+    let h3_config = quiche::h3::Config::new()?;
+
+    Ok(Server {
+      poll,
+      socket,
+      config: quic_config,
+      h3_config,
+    })
+  }
+
+  pub fn local_addr(&self) -> io::Result<SocketAddr> {
+    self.socket.local_addr()
+  }
+
+  /// Serves connections until the socket fails.
+  pub fn run(mut self, handler: &dyn Handler) -> Result<(), Error> {
+    let mut buf = [0; 65535];
+    let mut out = [0; MAX_DATAGRAM_SIZE];
+    let mut events = mio::Events::with_capacity(1024);
+
+    let rng = SystemRandom::new();
+    let conn_id_seed = ring::hmac::Key::generate(ring::hmac::HMAC_SHA256, &rng).unwrap();
+
+    let mut clients = ClientMap::new();
+
+    let local_addr = self.socket.local_addr()?;
+
+    loop {
+      // Find the shorter timeout from all the active connections.
+      //
+      // TODO: use event loop that properly supports timers
+      let timeout = clients.values().filter_map(|c| c.conn.timeout()).min();
+
+      self.poll.poll(&mut events, timeout)?;
+
+      // Read incoming UDP packets from the socket and feed them to quiche,
+      // until there are no more packets to read.
+      'read: loop {
+        // If the event loop reported no events, it means that the timeout
+        // has expired, so handle it without attempting to read packets. We
+        // will then proceed with the send loop.
+        if events.is_empty() {
+          debug!("timed out");
+
+          clients.values_mut().for_each(|c| c.conn.on_timeout());
+
+          break 'read;
+        }
+
+        let (len, from) = match self.socket.recv_from(&mut buf) {
+          Ok(v) => v,
+
+          Err(e) => {
+            // There are no more UDP packets to read, so end the read
+            // loop.
+            if e.kind() == std::io::ErrorKind::WouldBlock {
+              debug!("recv() would block");
+              break 'read;
+            }
+
+            return Err(e.into());
+          }
+        };
+
+        debug!("got {} bytes", len);
+
+        let pkt_buf = &mut buf[.. len];
+
+        // Parse the QUIC packet's header.
+        let hdr = match quiche::Header::from_slice(pkt_buf, quiche::MAX_CONN_ID_LEN) {
+          Ok(v) => v,
+
+          Err(e) => {
+            error!("Parsing packet header failed: {:?}", e);
+            continue 'read;
+          }
+        };
+
+        trace!("got packet {:?}", hdr);
+
+        let conn_id = ring::hmac::sign(&conn_id_seed, &hdr.dcid);
+        let conn_id = &conn_id.as_ref()[.. quiche::MAX_CONN_ID_LEN];
+        let conn_id = conn_id.to_vec().into();
+
+        // Lookup a connection based on the packet's connection ID. If there
+        // is no connection matching, create a new one.
+        let client = if !clients.contains_key(&hdr.dcid) && !clients.contains_key(&conn_id) {
+          if hdr.ty != quiche::Type::Initial {
+            error!("Packet is not Initial");
+            continue 'read;
+          }
+
+          if !quiche::version_is_supported(hdr.version) {
+            warn!("Doing version negotiation");
+
+            let len = quiche::negotiate_version(&hdr.scid, &hdr.dcid, &mut out).unwrap();
+
+            let out = &out[.. len];
+
+            if let Err(e) = self.socket.send_to(out, from) {
+              if e.kind() == std::io::ErrorKind::WouldBlock {
+                debug!("send() would block");
+                break;
+              }
+
+              return Err(e.into());
+            }
+            continue 'read;
+          }
+
+          let mut scid = [0; quiche::MAX_CONN_ID_LEN];
+          scid.copy_from_slice(&conn_id);
+
+          let scid = quiche::ConnectionId::from_ref(&scid);
+
+          // Token is always present in Initial packets.
+          let token = hdr.token.as_ref().unwrap();
+
+          // Do stateless retry if the client didn't send a token.
+          if token.is_empty() {
+            warn!("Doing stateless retry");
+
+            let new_token = mint_token(&hdr, &from);
+
+            let len = quiche::retry(
+              &hdr.scid,
+              &hdr.dcid,
+              &scid,
+              &new_token,
+              hdr.version,
+              &mut out,
+            )
+            .unwrap();
+
+            let out = &out[.. len];
+
+            if let Err(e) = self.socket.send_to(out, from) {
+              if e.kind() == std::io::ErrorKind::WouldBlock {
+                debug!("send() would block");
+                break;
+              }
+
+              return Err(e.into());
+            }
+            continue 'read;
+          }
+
+          let odcid = validate_token(&from, token);
+
+          // The token was not valid, meaning the retry failed, so
+          // drop the packet.
+          if odcid.is_none() {
+            error!("Invalid address validation token");
+            continue 'read;
+          }
+
+          if scid.len() != hdr.dcid.len() {
+            error!("Invalid destination connection ID");
+            continue 'read;
+          }
+
+          // Reuse the source connection ID we sent in the Retry packet,
+          // instead of changing it again.
+          let scid = hdr.dcid.clone();
+
+          debug!("New connection: dcid={:?} scid={:?}", hdr.dcid, scid);
+
+          let conn = quiche::accept(&scid, odcid.as_ref(), local_addr, from, &mut self.config)?;
+
+          let client = Client {
+            conn,
+            http3_conn: None,
+            partial_responses: HashMap::new(),
+            partial_requests: HashMap::new(),
+          };
+
+          clients.insert(scid.clone(), client);
+
+          clients.get_mut(&scid).unwrap()
+        } else {
+          match clients.get_mut(&hdr.dcid) {
+            Some(v) => v,
+
+            None => clients.get_mut(&conn_id).unwrap(),
+          }
+        };
+
+        let recv_info = quiche::RecvInfo {
+          to: local_addr,
+          from,
+        };
+
+        // Process potentially coalesced packets.
+        let read = match client.conn.recv(pkt_buf, recv_info) {
+          Ok(v) => v,
+
+          Err(e) => {
+            error!("{} recv failed: {:?}", client.conn.trace_id(), e);
+            continue 'read;
+          }
+        };
+
+        debug!("{} processed {} bytes", client.conn.trace_id(), read);
+
+        // Create a new HTTP/3 connection as soon as the QUIC connection
+        // is established.
+        if (client.conn.is_in_early_data() || client.conn.is_established())
+          && client.http3_conn.is_none()
+        {
+          debug!(
+            "{} QUIC handshake completed, now trying HTTP/3",
+            client.conn.trace_id()
+          );
+
+          let h3_conn =
+            match quiche::h3::Connection::with_transport(&mut client.conn, &self.h3_config) {
+              Ok(v) => v,
+
+              Err(e) => {
+                error!("failed to create HTTP/3 connection: {}", e);
+                continue 'read;
+              }
+            };
+
+          // TODO: sanity check h3 connection before adding to map
+          client.http3_conn = Some(h3_conn);
+        }
+
+        if client.http3_conn.is_some() {
+          // Handle writable streams.
+          for stream_id in client.conn.writable() {
+            handle_writable(client, stream_id);
+          }
+
+          // Process HTTP/3 events.
+          loop {
+            let http3_conn = client.http3_conn.as_mut().unwrap();
+
+            match http3_conn.poll(&mut client.conn) {
+              Ok((stream_id, quiche::h3::Event::Headers { list, has_body })) => {
+                let request = parse_request(&list);
+
+                if has_body && request.method == "POST" {
+                  // Answered once the whole body is in.
+                  client.partial_requests.insert(stream_id, request);
+                } else {
+                  if has_body {
+                    // The response does not depend on the body, so stop
+                    // reading the request stream so that it is ignored and
+                    // pointless Data events are not generated.
+                    client
+                      .conn
+                      .stream_shutdown(stream_id, quiche::Shutdown::Read, 0)
+                      .ok();
+                  }
+
+                  handle_request(client, stream_id, request, handler);
+                }
+              }
+
+              Ok((stream_id, quiche::h3::Event::Data)) => {
+                handle_data(client, stream_id);
+              }
+
+              Ok((stream_id, quiche::h3::Event::Finished)) => {
+                if let Some(request) = client.partial_requests.remove(&stream_id) {
+                  handle_request(client, stream_id, request, handler);
+                }
+              }
+
+              Ok((stream_id, quiche::h3::Event::Reset(_))) => {
+                client.partial_requests.remove(&stream_id);
+                client.partial_responses.remove(&stream_id);
+              }
+
+              Ok((_prioritized_element_id, quiche::h3::Event::PriorityUpdate)) => (),
+
+              Ok((_goaway_id, quiche::h3::Event::GoAway)) => (),
+
+              Err(quiche::h3::Error::Done) => {
+                break;
+              }
+
+              Err(e) => {
+                error!("{} HTTP/3 error {:?}", client.conn.trace_id(), e);
+
+                break;
+              }
+            }
+          }
+        }
+      }
+
+      // Generate outgoing QUIC packets for all active connections and send
+      // them on the UDP socket, until quiche reports that there are no more
+      // packets to be sent.
+      for client in clients.values_mut() {
+        loop {
+          let (write, send_info) = match client.conn.send(&mut out) {
+            Ok(v) => v,
+
+            Err(quiche::Error::Done) => {
+              debug!("{} done writing", client.conn.trace_id());
+              break;
+            }
+
+            Err(e) => {
+              error!("{} send failed: {:?}", client.conn.trace_id(), e);
+
+              client.conn.close(false, 0x1, b"fail").ok();
+              break;
+            }
+          };
+
+          if let Err(e) = self.socket.send_to(&out[.. write], send_info.to) {
+            if e.kind() == std::io::ErrorKind::WouldBlock {
+              debug!("send() would block");
+              break;
+            }
+
+            return Err(e.into());
+          }
+
+          debug!("{} written {} bytes", client.conn.trace_id(), write);
+        }
+      }
+
+      // Garbage collect closed connections.
+      clients.retain(|_, ref mut c| {
+        debug!("Collecting garbage");
+
+        if c.conn.is_closed() {
+          info!(
+            "{} connection collected {:?}",
+            c.conn.trace_id(),
+            c.conn.stats()
+          );
+        }
+
+        !c.conn.is_closed()
+      });
+    }
+  }
+}
+
+/// Generate a stateless retry token.
+///
+/// The token includes the static string `"quiche"` followed by the IP address
+/// of the client and by the original destination connection ID generated by the
+/// client.
+///
+/// Note that this function is only an example and doesn't do any cryptographic
+/// authenticate of the token. *It should not be used in production system*.
+fn mint_token(hdr: &quiche::Header, src: &net::SocketAddr) -> Vec<u8> {
+  let mut token = Vec::new();
+
+  token.extend_from_slice(b"quiche");
+
+  let addr = match src.ip() {
+    std::net::IpAddr::V4(a) => a.octets().to_vec(),
+    std::net::IpAddr::V6(a) => a.octets().to_vec(),
+  };
+
+  token.extend_from_slice(&addr);
+  token.extend_from_slice(&hdr.dcid);
+
+  token
+}
+
+/// Validates a stateless retry token.
+///
+/// This checks that the ticket includes the `"quiche"` static string, and that
+/// the client IP address matches the address stored in the ticket.
+///
+/// Note that this function is only an example and doesn't do any cryptographic
+/// authenticate of the token. *It should not be used in production system*.
+fn validate_token<'a>(src: &net::SocketAddr, token: &'a [u8]) -> Option<quiche::ConnectionId<'a>> {
+  if token.len() < 6 {
+    return None;
+  }
+
+  if &token[.. 6] != b"quiche" {
+    return None;
+  }
+
+  let token = &token[6 ..];
+
+  let addr = match src.ip() {
+    std::net::IpAddr::V4(a) => a.octets().to_vec(),
+    std::net::IpAddr::V6(a) => a.octets().to_vec(),
+  };
+
+  if token.len() < addr.len() || &token[.. addr.len()] != addr.as_slice() {
+    return None;
+  }
+
+  Some(quiche::ConnectionId::from_ref(&token[addr.len() ..]))
+}
+
+/// The method, path and regular headers of a request.
+fn parse_request(headers: &[quiche::h3::Header]) -> Request {
+  let mut request = Request::default();
+
+  for hdr in headers {
+    let value = String::from_utf8_lossy(hdr.value()).into_owned();
+
+    match hdr.name() {
+      b":method" => request.method = value,
+
+      b":path" => request.path = value,
+
+      name if name.starts_with(b":") => (),
+
+      name => request
+        .headers
+        .push((String::from_utf8_lossy(name).into_owned(), value)),
+    }
+  }
+
+  request
+}
+
+/// Reads what arrived of a request body.
+fn handle_data(client: &mut Client, stream_id: u64) {
+  let conn = &mut client.conn;
+  let http3_conn = client.http3_conn.as_mut().unwrap();
+
+  let Some(request) = client.partial_requests.get_mut(&stream_id) else {
+    return;
+  };
+
+  let mut buf = [0; 16 * 1024];
+
+  while let Ok(read) = http3_conn.recv_body(conn, stream_id, &mut buf) {
+    debug!(
+      "{} got {} bytes of request body on stream {}",
+      conn.trace_id(),
+      read,
+      stream_id
+    );
+
+    request.body.extend_from_slice(&buf[.. read]);
+
+    if request.body.len() > MAX_REQUEST_BODY {
+      client.partial_requests.remove(&stream_id);
+
+      conn
+        .stream_shutdown(stream_id, quiche::Shutdown::Read, 0)
+        .ok();
+
+      send_response(
+        client,
+        stream_id,
+        Response::text(413, "Content Too Large").finish(false),
+      );
+      return;
+    }
+  }
+}
+
+/// Handles incoming HTTP/3 requests.
+fn handle_request(client: &mut Client, stream_id: u64, request: Request, handler: &dyn Handler) {
+  info!(
+    "{} got request {} {} with {} byte body on stream id {}",
+    client.conn.trace_id(),
+    request.method,
+    request.path,
+    request.body.len(),
+    stream_id
+  );
+
+  let response = handler.handle(&request).finish(request.method == "HEAD");
+
+  send_response(client, stream_id, response);
+}
+
+fn send_response(client: &mut Client, stream_id: u64, response: Response) {
+  let mut headers = vec![
+    quiche::h3::Header::new(b":status", response.status.to_string().as_bytes()),
+    quiche::h3::Header::new(b"server", b"quiche"),
+  ];
+
+  headers.extend(
+    response
+      .headers
+      .iter()
+      .map(|(name, value)| quiche::h3::Header::new(name.as_bytes(), value.as_bytes())),
+  );
+
+  let mut partial = PartialResponse {
+    headers: Some(headers),
+    body: response.body,
+    chunk: Vec::new(),
+    written: 0,
+  };
+
+  let http3_conn = client.http3_conn.as_mut().unwrap();
+
+  if !send_partial(&mut client.conn, http3_conn, stream_id, &mut partial) {
+    client.partial_responses.insert(stream_id, partial);
+  }
+}
+
+/// Sends as much of a response as the stream takes. True once it is all sent, or failed.
+fn send_partial(
+  conn: &mut quiche::Connection,
+  http3_conn: &mut quiche::h3::Connection,
+  stream_id: u64,
+  resp: &mut PartialResponse,
+) -> bool {
+  if let Some(ref headers) = resp.headers {
+    match http3_conn.send_response(conn, stream_id, headers, resp.body.is_empty()) {
+      Ok(_) => (),
+
+      Err(quiche::h3::Error::StreamBlocked) => {
+        return false;
+      }
+
+      Err(e) => {
+        error!("{} stream send failed {:?}", conn.trace_id(), e);
+        return true;
+      }
+    }
+
+    resp.headers = None;
+
+    if resp.body.is_empty() {
+      return true;
+    }
+  }
+
+  loop {
+    // Read on from disk only once the last chunk went out, so that a large
+    // file never sits in memory as a whole.
+    if resp.written == resp.chunk.len() {
+      resp.chunk = match resp.body.next_chunk(CHUNK_SIZE) {
+        Ok(v) => v,
+
+        Err(e) => {
+          error!("{} reading body failed: {}", conn.trace_id(), e);
+
+          conn
+            .stream_shutdown(stream_id, quiche::Shutdown::Write, 0)
+            .ok();
+          return true;
+        }
+      };
+
+      resp.written = 0;
+    }
+
+    let fin = resp.body.is_empty();
+
+    let written = match http3_conn.send_body(conn, stream_id, &resp.chunk[resp.written ..], fin) {
+      Ok(v) => v,
+
+      Err(quiche::h3::Error::Done) => 0,
+
+      Err(e) => {
+        error!("{} stream send failed {:?}", conn.trace_id(), e);
+        return true;
+      }
+    };
+
+    resp.written += written;
+
+    // Flow control: the rest goes once the stream is writable again.
+    if resp.written < resp.chunk.len() {
+      return false;
+    }
+
+    if fin {
+      return true;
+    }
+  }
+}
+
+/// Handles newly writable streams.
+fn handle_writable(client: &mut Client, stream_id: u64) {
+  let conn = &mut client.conn;
+  let http3_conn = client.http3_conn.as_mut().unwrap();
+
+  debug!("{} stream {} is writable", conn.trace_id(), stream_id);
+
+  let Some(resp) = client.partial_responses.get_mut(&stream_id) else {
+    return;
+  };
+
+  if send_partial(conn, http3_conn, stream_id, resp) {
+    client.partial_responses.remove(&stream_id);
+  }
+}
@@ -0,0 +1,300 @@
+//! Generates the messages of the `.proto` files, and for each service in them a trait to
+//! implement, a server to register the implementation with, and a typed client.
+use std::{collections::HashMap, env, fmt::Write, fs, path::Path};
+
+use protobuf::descriptor::{FileDescriptorProto, ServiceDescriptorProto};
+
+const PROTOS: &[&str] = &["proto/envelope.proto", "proto/example.proto"];
+
+fn main() {
+  for proto in PROTOS {
+    println!("cargo:rerun-if-changed={}", proto);
+  }
+
+  protobuf_codegen::Codegen::new()
+    .pure()
+    .include("proto")
+    .inputs(PROTOS)
+    .cargo_out_dir("protos")
+    .run_from_script();
+
+  let parsed = protobuf_parse::Parser::new()
+    .pure()
+    .include("proto")
+    .inputs(PROTOS)
+    .parse_and_typecheck()
+    .expect("invalid .proto");
+
+  let services = services(&parsed.file_descriptors);
+  let out = Path::new(&env::var("OUT_DIR").unwrap()).join("services.rs");
+  fs::write(out, services).unwrap();
+}
+
+fn services(files: &[FileDescriptorProto]) -> String {
+  // Fully qualified message names to the Rust types generated for them
+  let mut types = HashMap::new();
+  for file in files {
+    let module = Path::new(file.name())
+      .file_stem()
+      .unwrap()
+      .to_string_lossy()
+      .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+    for message in &file.message_type {
+      let name = match file.package() {
+        "" => format!(".{}", message.name()),
+        package => format!(".{}.{}", package, message.name()),
+      };
+      types.insert(
+        name,
+        format!("crate::protos::{}::{}", module, message.name()),
+      );
+    }
+  }
+
+  let mut out = String::new();
+  for file in files {
+    for service in &file.service {
+      generate(&mut out, file.package(), service, &types);
+    }
+  }
+  out
+}
+
+fn generate(
+  out: &mut String,
+  package: &str,
+  service: &ServiceDescriptorProto,
+  types: &HashMap<String, String>,
+) {
+  let name = service.name();
+  let full_name = match package {
+    "" => name.to_string(),
+    package => format!("{}.{}", package, name),
+  };
+  let methods: Vec<Method> = service
+    .method
+    .iter()
+    .map(|method| {
+      assert!(
+        !method.client_streaming(),
+        "{}.{}: client streaming is not supported",
+        full_name,
+        method.name()
+      );
+      let path = format!("/{}/{}", full_name, method.name());
+      Method {
+        function: snake_case(method.name()),
+        constant: snake_case(method.name()).to_uppercase(),
+        id: method_id(&path),
+        path,
+        input: types[method.input_type()].clone(),
+        output: types[method.output_type()].clone(),
+        streaming: method.server_streaming(),
+      }
+    })
+    .collect();
+
+  let w = out;
+  writeln!(w, "/// The `{}` service.", full_name).unwrap();
+  writeln!(w, "pub mod {} {{", snake_case(name)).unwrap();
+  writeln!(
+    w,
+    "  use std::{{future::Future, sync::Arc, time::Duration}};"
+  )
+  .unwrap();
+  writeln!(w).unwrap();
+  writeln!(w, "  use futures_util::future::BoxFuture;").unwrap();
+  writeln!(w).unwrap();
+  writeln!(
+    w,
+    "  use crate::rpc::{{self, Client, Context, Reply, ResponseStream, Service, Status, \
+     Streaming}};"
+  )
+  .unwrap();
+  writeln!(w).unwrap();
+  writeln!(w, "  pub const NAME: &str = \"{}\";", full_name).unwrap();
+  for method in &methods {
+    writeln!(w).unwrap();
+    writeln!(w, "  /// `{}`", method.path).unwrap();
+    writeln!(
+      w,
+      "  pub const {}: u32 = {:#010x};",
+      method.constant, method.id
+    )
+    .unwrap();
+  }
+
+  writeln!(w).unwrap();
+  writeln!(w, "  pub trait {}: Send + Sync + 'static {{", name).unwrap();
+  for method in &methods {
+    let output = if method.streaming {
+      format!("ResponseStream<{}>", method.output)
+    } else {
+      method.output.clone()
+    };
+    writeln!(w, "    fn {}(", method.function).unwrap();
+    writeln!(w, "      &self,").unwrap();
+    writeln!(w, "      ctx: Context,").unwrap();
+    writeln!(w, "      request: {},", method.input).unwrap();
+    writeln!(
+      w,
+      "    ) -> impl Future<Output = Result<{}, Status>> + Send;",
+      output
+    )
+    .unwrap();
+  }
+  writeln!(w, "  }}").unwrap();
+
+  writeln!(w).unwrap();
+  writeln!(w, "  #[derive(Clone)]").unwrap();
+  writeln!(w, "  pub struct {}Client {{", name).unwrap();
+  writeln!(w, "    client: Client,").unwrap();
+  writeln!(w, "  }}").unwrap();
+  writeln!(w).unwrap();
+  writeln!(w, "  impl {}Client {{", name).unwrap();
+  writeln!(w, "    pub fn new(client: Client) -> Self {{").unwrap();
+  writeln!(w, "      {}Client {{ client }}", name).unwrap();
+  writeln!(w, "    }}").unwrap();
+  writeln!(w).unwrap();
+  writeln!(
+    w,
+    "    /// The same client, with a deadline this far ahead of each call."
+  )
+  .unwrap();
+  writeln!(
+    w,
+    "    pub fn with_timeout(&self, timeout: Duration) -> Self {{"
+  )
+  .unwrap();
+  writeln!(w, "      {}Client {{", name).unwrap();
+  writeln!(
+    w,
+    "        client: self.client.clone().with_timeout(timeout),"
+  )
+  .unwrap();
+  writeln!(w, "      }}").unwrap();
+  writeln!(w, "    }}").unwrap();
+  for method in &methods {
+    writeln!(w).unwrap();
+    if method.streaming {
+      writeln!(
+        w,
+        "    pub async fn {}(&self, request: &{}) -> Result<Streaming<{}>, Status> {{",
+        method.function, method.input, method.output
+      )
+      .unwrap();
+      writeln!(
+        w,
+        "      self.client.call_streaming({}, request).await",
+        method.constant
+      )
+      .unwrap();
+    } else {
+      writeln!(
+        w,
+        "    pub async fn {}(&self, request: &{}) -> Result<{}, Status> {{",
+        method.function, method.input, method.output
+      )
+      .unwrap();
+      writeln!(
+        w,
+        "      self.client.call({}, request).await",
+        method.constant
+      )
+      .unwrap();
+    }
+    writeln!(w, "    }}").unwrap();
+  }
+  writeln!(w, "  }}").unwrap();
+
+  writeln!(w).unwrap();
+  writeln!(w, "  pub struct {}Server<T>(Arc<T>);", name).unwrap();
+  writeln!(w).unwrap();
+  writeln!(w, "  impl<T: {}> {}Server<T> {{", name, name).unwrap();
+  writeln!(w, "    pub fn new(service: T) -> Self {{").unwrap();
+  writeln!(w, "      {}Server(Arc::new(service))", name).unwrap();
+  writeln!(w, "    }}").unwrap();
+  writeln!(w, "  }}").unwrap();
+  writeln!(w).unwrap();
+  writeln!(w, "  impl<T: {}> Service for {}Server<T> {{", name, name).unwrap();
+  writeln!(w, "    fn name(&self) -> &'static str {{").unwrap();
+  writeln!(w, "      NAME").unwrap();
+  writeln!(w, "    }}").unwrap();
+  writeln!(w).unwrap();
+  writeln!(w, "    fn methods(&self) -> &'static [u32] {{").unwrap();
+  let constants: Vec<&str> = methods.iter().map(|m| m.constant.as_str()).collect();
+  writeln!(w, "      &[{}]", constants.join(", ")).unwrap();
+  writeln!(w, "    }}").unwrap();
+  writeln!(w).unwrap();
+  writeln!(
+    w,
+    "    fn call(&self, method: u32, ctx: Context, payload: Vec<u8>) -> BoxFuture<'static, \
+     Result<Reply, Status>> {{"
+  )
+  .unwrap();
+  writeln!(w, "      let service = self.0.clone();").unwrap();
+  writeln!(w, "      match method {{").unwrap();
+  for method in &methods {
+    writeln!(w, "        {} => Box::pin(async move {{", method.constant).unwrap();
+    writeln!(w, "          let request = rpc::decode(&payload)?;").unwrap();
+    writeln!(
+      w,
+      "          let response = service.{}(ctx, request).await?;",
+      method.function
+    )
+    .unwrap();
+    if method.streaming {
+      writeln!(
+        w,
+        "          Ok(Reply::Streaming(rpc::encode_stream(response)))"
+      )
+      .unwrap();
+    } else {
+      writeln!(w, "          rpc::encode(&response).map(Reply::Unary)").unwrap();
+    }
+    writeln!(w, "        }}),").unwrap();
+  }
+  writeln!(
+    w,
+    "        method => Box::pin(async move {{ Err(Status::unimplemented(format!(\"{{}} has no \
+     method {{:#010x}}\", NAME, method))) }}),"
+  )
+  .unwrap();
+  writeln!(w, "      }}").unwrap();
+  writeln!(w, "    }}").unwrap();
+  writeln!(w, "  }}").unwrap();
+  writeln!(w, "}}").unwrap();
+}
+
+struct Method {
+  function: String,
+  constant: String,
+  path: String,
+  id: u32,
+  input: String,
+  output: String,
+  streaming: bool,
+}
+
+fn snake_case(name: &str) -> String {
+  let mut snake = String::new();
+  for (i, c) in name.chars().enumerate() {
+    if c.is_ascii_uppercase() {
+      if i > 0 {
+        snake.push('_');
+      }
+      snake.push(c.to_ascii_lowercase());
+    } else {
+      snake.push(c);
+    }
+  }
+  snake
+}
+
+/// FNV-1a of `/package.Service/Method`: the same on both sides without a registry, and
+/// unchanged when methods are added or reordered.
+fn method_id(path: &str) -> u32 {
+  path.bytes().fold(0x811c_9dc5, |hash, b| {
+    (hash ^ b as u32).wrapping_mul(0x0100_0193)
+  })
+}
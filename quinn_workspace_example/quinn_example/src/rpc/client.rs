@@ -0,0 +1,175 @@
+use std::{
+  marker::PhantomData,
+  time::{Duration, Instant},
+};
+
+use protobuf::Message;
+use quinn::{RecvStream, SendStream};
+
+use super::{
+  deadline_in, decode, encode, read_frame, with_deadline, write_frame, Status, CANCELLED,
+};
+use crate::protos::envelope::{self, response::Kind};
+
+/// Makes calls on a connection, each on a stream of its own. Cheap to clone.
+#[derive(Clone)]
+pub struct Client {
+  conn: quinn::Connection,
+  timeout: Option<Duration>,
+}
+
+impl Client {
+  pub fn new(conn: quinn::Connection) -> Client {
+    Client {
+      conn,
+      timeout: None,
+    }
+  }
+
+  /// Calls from now on give up `timeout` after they start, with `DEADLINE_EXCEEDED`. They fail
+  /// with `INVALID_ARGUMENT` for a timeout over [`MAX_TIMEOUT`](super::MAX_TIMEOUT).
+  pub fn with_timeout(mut self, timeout: Duration) -> Client {
+    self.timeout = Some(timeout);
+    self
+  }
+
+  pub fn connection(&self) -> &quinn::Connection {
+    &self.conn
+  }
+
+  pub async fn call<Req: Message, Resp: Message>(
+    &self,
+    method: u32,
+    request: &Req,
+  ) -> Result<Resp, Status> {
+    let deadline = self.timeout.map(deadline_in).transpose()?;
+    with_deadline(deadline, async {
+      let mut call = self.start(method, request, deadline).await?;
+      let response = call
+        .next_item()
+        .await?
+        .ok_or_else(|| Status::internal("no response"))?;
+      let response = decode(&response)?;
+      match call.next_item().await? {
+        None => Ok(response),
+        Some(_) => Err(Status::internal("more than one response")),
+      }
+    })
+    .await
+  }
+
+  /// Starts a call to a server streaming method. The deadline covers the whole stream.
+  pub async fn call_streaming<Req: Message, Resp: Message>(
+    &self,
+    method: u32,
+    request: &Req,
+  ) -> Result<Streaming<Resp>, Status> {
+    let deadline = self.timeout.map(deadline_in).transpose()?;
+    let call = with_deadline(deadline, self.start(method, request, deadline)).await?;
+    Ok(Streaming {
+      call,
+      deadline,
+      _response: PhantomData,
+    })
+  }
+
+  async fn start(
+    &self,
+    method: u32,
+    request: &impl Message,
+    deadline: Option<Instant>,
+  ) -> Result<Call, Status> {
+    let (send, recv) = self.conn.open_bi().await.map_err(Status::unavailable)?;
+    let mut call = Call {
+      send,
+      recv,
+      done: false,
+    };
+    let timeout_ms = deadline
+      .map(|deadline| {
+        deadline
+          .saturating_duration_since(Instant::now())
+          .as_millis()
+          .max(1) as u64
+      })
+      .unwrap_or(0);
+    let request = envelope::Request {
+      method,
+      timeout_ms,
+      payload: encode(request)?,
+      ..Default::default()
+    };
+    write_frame(&mut call.send, &request).await?;
+    Ok(call)
+  }
+}
+
+/// The responses of a server streaming method, as they come.
+pub struct Streaming<T> {
+  call: Call,
+  deadline: Option<Instant>,
+  _response: PhantomData<fn() -> T>,
+}
+
+impl<T: Message> Streaming<T> {
+  /// The next response, `None` once the server is done.
+  pub async fn message(&mut self) -> Result<Option<T>, Status> {
+    match with_deadline(self.deadline, self.call.next_item()).await {
+      Ok(Some(item)) => decode(&item).map(Some),
+      Ok(None) => Ok(None),
+      Err(status) => {
+        self.call.cancel();
+        Err(status)
+      }
+    }
+  }
+
+  /// Stops the call; the same as dropping it.
+  pub fn cancel(self) {}
+}
+
+/// The streams of a call. Dropped before the server's status, it cancels the call.
+struct Call {
+  send: SendStream,
+  recv: RecvStream,
+  done: bool,
+}
+
+impl Call {
+  /// The next item, `None` after the server ended the call fine.
+  async fn next_item(&mut self) -> Result<Option<Vec<u8>>, Status> {
+    if self.done {
+      return Ok(None);
+    }
+    let response: envelope::Response = read_frame(&mut self.recv)
+      .await?
+      .ok_or_else(|| Status::unavailable("stream ended without a status"))?;
+    match response.kind {
+      Some(Kind::Item(item)) => Ok(Some(item)),
+      Some(Kind::Status(status)) => {
+        self.done = true;
+        self.send.finish().ok();
+        let status = Status::from(status);
+        match status.code {
+          envelope::Code::OK => Ok(None),
+          _ => Err(status),
+        }
+      }
+      None => Err(Status::internal("empty response")),
+    }
+  }
+
+  fn cancel(&mut self) {
+    if !self.done {
+      self.done = true;
+      self.send.reset(CANCELLED).ok();
+      self.recv.stop(CANCELLED).ok();
+    }
+  }
+}
+
+impl Drop for Call {
+  fn drop(&mut self) {
+    self.cancel();
+  }
+}
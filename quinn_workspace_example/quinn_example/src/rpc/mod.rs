@@ -0,0 +1,218 @@
+//! The runtime of the generated services.
+//!
+//! A call is one stream. The client writes an [`envelope::Request`] with the method ID, its
+//! timeout and the encoded request. The server answers with a [`envelope::Response`] item per
+//! response, one for unary methods, and ends with a status. Each frame is its length as a big
+//! endian `u32` followed by the encoded message.
+//!
+//! A client giving up on a call, at its deadline or by dropping it, resets its side of the
+//! stream; the server drops the handler in response.
+
+mod client;
+mod server;
+
+use std::{
+  fmt,
+  net::SocketAddr,
+  time::{Duration, Instant},
+};
+
+pub use client::{Client, Streaming};
+use futures_util::{future::BoxFuture, stream::BoxStream, Future, StreamExt};
+use protobuf::{EnumOrUnknown, Message};
+use quinn::{ReadExactError, RecvStream, SendStream, VarInt};
+pub use server::Server;
+use thiserror::Error;
+
+use crate::protos::envelope;
+pub use crate::protos::envelope::Code;
+
+/// Frames over this are refused.
+const MAX_FRAME: usize = 16 << 20;
+
+/// Calls given longer are refused with `INVALID_ARGUMENT`, on either side.
+pub const MAX_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Reset code of a call given up on.
+const CANCELLED: VarInt = VarInt::from_u32(Code::CANCELLED as u32);
+
+/// How a call failed.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{code:?}: {message}")]
+pub struct Status {
+  pub code: Code,
+  pub message: String,
+}
+
+impl Status {
+  pub fn new(code: Code, message: impl Into<String>) -> Status {
+    Status {
+      code,
+      message: message.into(),
+    }
+  }
+
+  pub fn invalid_argument(message: impl Into<String>) -> Status {
+    Status::new(Code::INVALID_ARGUMENT, message)
+  }
+
+  pub fn not_found(message: impl Into<String>) -> Status {
+    Status::new(Code::NOT_FOUND, message)
+  }
+
+  pub fn internal(message: impl Into<String>) -> Status {
+    Status::new(Code::INTERNAL, message)
+  }
+
+  pub fn unimplemented(message: impl Into<String>) -> Status {
+    Status::new(Code::UNIMPLEMENTED, message)
+  }
+
+  pub fn deadline_exceeded() -> Status {
+    Status::new(Code::DEADLINE_EXCEEDED, "deadline exceeded")
+  }
+
+  /// The connection or the stream failed under the call.
+  pub fn unavailable(error: impl fmt::Display) -> Status {
+    Status::new(Code::UNAVAILABLE, error.to_string())
+  }
+}
+
+impl From<envelope::Status> for Status {
+  fn from(status: envelope::Status) -> Self {
+    Status::new(status.code.enum_value_or(Code::UNKNOWN), status.message)
+  }
+}
+
+impl From<&Status> for envelope::Status {
+  fn from(status: &Status) -> Self {
+    envelope::Status {
+      code: EnumOrUnknown::new(status.code),
+      message: status.message.clone(),
+      ..Default::default()
+    }
+  }
+}
+
+/// What a handler knows of the call besides the request.
+#[derive(Debug, Clone)]
+pub struct Context {
+  peer: SocketAddr,
+  deadline: Option<Instant>,
+}
+
+impl Context {
+  pub fn peer(&self) -> SocketAddr {
+    self.peer
+  }
+
+  /// When the caller stops waiting. The handler is dropped then.
+  pub fn deadline(&self) -> Option<Instant> {
+    self.deadline
+  }
+}
+
+/// The items of a server streaming method.
+pub type ResponseStream<T> = BoxStream<'static, Result<T, Status>>;
+
+/// Encoded responses of a method.
+pub enum Reply {
+  Unary(Vec<u8>),
+  Streaming(ResponseStream<Vec<u8>>),
+}
+
+/// Methods served under IDs, implemented by the generated `*Server` types.
+pub trait Service: Send + Sync + 'static {
+  fn name(&self) -> &'static str;
+
+  fn methods(&self) -> &'static [u32];
+
+  fn call(
+    &self,
+    method: u32,
+    ctx: Context,
+    payload: Vec<u8>,
+  ) -> BoxFuture<'static, Result<Reply, Status>>;
+}
+
+pub fn decode<M: Message>(payload: &[u8]) -> Result<M, Status> {
+  M::parse_from_bytes(payload)
+    .map_err(|e| Status::invalid_argument(format!("malformed message: {}", e)))
+}
+
+pub fn encode(message: &impl Message) -> Result<Vec<u8>, Status> {
+  message
+    .write_to_bytes()
+    .map_err(|e| Status::internal(format!("cannot encode message: {}", e)))
+}
+
+pub fn encode_stream<M: Message>(items: ResponseStream<M>) -> ResponseStream<Vec<u8>> {
+  items
+    .map(|item| item.and_then(|item| encode(&item)))
+    .boxed()
+}
+
+/// Fails with `DEADLINE_EXCEEDED`, dropping `call`, once the deadline passes.
+async fn with_deadline<T>(
+  deadline: Option<Instant>,
+  call: impl Future<Output = Result<T, Status>>,
+) -> Result<T, Status> {
+  match deadline {
+    Some(deadline) => tokio::time::timeout_at(deadline.into(), call)
+      .await
+      .unwrap_or_else(|_| Err(Status::deadline_exceeded())),
+    None => call.await,
+  }
+}
+
+fn deadline_in(timeout: Duration) -> Result<Instant, Status> {
+  Some(timeout)
+    .filter(|&timeout| timeout <= MAX_TIMEOUT)
+    .and_then(|timeout| Instant::now().checked_add(timeout))
+    .ok_or_else(|| {
+      Status::invalid_argument(format!(
+        "timeout of {:?}, more than {:?}",
+        timeout, MAX_TIMEOUT
+      ))
+    })
+}
+
+async fn write_frame(send: &mut SendStream, message: &impl Message) -> Result<(), Status> {
+  let bytes = encode(message)?;
+  if bytes.len() > MAX_FRAME {
+    return Err(Status::invalid_argument(format!(
+      "message of {} bytes, more than {}",
+      bytes.len(),
+      MAX_FRAME
+    )));
+  }
+  let mut frame = Vec::with_capacity(4 + bytes.len());
+  frame.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+  frame.extend_from_slice(&bytes);
+  send.write_all(&frame).await.map_err(Status::unavailable)
+}
+
+/// `None` when the stream ends between frames.
+async fn read_frame<M: Message>(recv: &mut RecvStream) -> Result<Option<M>, Status> {
+  let mut len = [0; 4];
+  match recv.read_exact(&mut len).await {
+    Ok(()) => (),
+    Err(ReadExactError::FinishedEarly(0)) => return Ok(None),
+    Err(e) => return Err(Status::unavailable(e)),
+  }
+  let len = u32::from_be_bytes(len) as usize;
+  if len > MAX_FRAME {
+    return Err(Status::internal(format!(
+      "frame of {} bytes, more than {}",
+      len, MAX_FRAME
+    )));
+  }
+  let mut frame = vec![0; len];
+  recv
+    .read_exact(&mut frame)
+    .await
+    .map_err(Status::unavailable)?;
+  M::parse_from_bytes(&frame)
+    .map(Some)
+    .map_err(|e| Status::internal(format!("malformed frame: {}", e)))
+}
@@ -0,0 +1,159 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+
+use futures_util::{future, StreamExt};
+use quinn::{ReadError, RecvStream, SendStream, VarInt};
+
+use super::{
+  deadline_in, read_frame, with_deadline, write_frame, Context, Reply, Service, Status, CANCELLED,
+};
+use crate::protos::envelope::{self, response::Kind};
+
+/// Dispatches the calls of its connections to the services by method ID.
+#[derive(Clone, Default)]
+pub struct Server {
+  services: HashMap<u32, Arc<dyn Service>>,
+}
+
+impl Server {
+  pub fn new() -> Server {
+    Server::default()
+  }
+
+  /// Panics on a method ID already taken: two methods whose names hash the same.
+  pub fn service(mut self, service: impl Service) -> Server {
+    let service: Arc<dyn Service> = Arc::new(service);
+    for &method in service.methods() {
+      if let Some(other) = self.services.insert(method, service.clone()) {
+        panic!(
+          "method {:#010x} of {} already taken by {}",
+          method,
+          service.name(),
+          other.name()
+        );
+      }
+    }
+    self
+  }
+
+  /// Serves the connections of `endpoint` until it is closed.
+  pub async fn serve(self, endpoint: quinn::Endpoint) {
+    while let Some(incoming) = endpoint.accept().await {
+      let server = self.clone();
+      tokio::spawn(async move {
+        match incoming.await {
+          Ok(conn) => server.serve_connection(conn).await,
+          Err(e) => log::info!("handshake failed: {}", e),
+        }
+      });
+    }
+  }
+
+  /// Serves the calls of a connection until it is closed.
+  pub async fn serve_connection(&self, conn: quinn::Connection) {
+    let peer = conn.remote_address();
+    loop {
+      let (send, recv) = match conn.accept_bi().await {
+        Ok(streams) => streams,
+        Err(e) => {
+          log::debug!("connection with {} over: {}", peer, e);
+          return;
+        }
+      };
+      let server = self.clone();
+      tokio::spawn(async move { server.handle(peer, send, recv).await });
+    }
+  }
+
+  async fn handle(&self, peer: SocketAddr, mut send: SendStream, mut recv: RecvStream) {
+    let request: envelope::Request = match read_frame(&mut recv).await {
+      Ok(Some(request)) => request,
+      Ok(None) => return,
+      Err(status) => {
+        log::debug!("bad call from {}: {}", peer, status);
+        return;
+      }
+    };
+    let deadline = (request.timeout_ms > 0)
+      .then(|| deadline_in(Duration::from_millis(request.timeout_ms)))
+      .transpose();
+
+    let outcome = match (deadline, self.services.get(&request.method)) {
+      (Err(status), _) => Err(status),
+      (Ok(deadline), Some(service)) => {
+        let ctx = Context { peer, deadline };
+        let call = run(
+          service.as_ref(),
+          request.method,
+          ctx,
+          request.payload,
+          &mut send,
+        );
+        tokio::select! {
+          outcome = with_deadline(deadline, call) => outcome,
+          code = cancelled(&mut recv) => {
+            log::debug!("call {:#010x} from {} cancelled with {}", request.method, peer, code);
+            send.reset(code).ok();
+            return;
+          }
+        }
+      }
+      (Ok(_), None) => Err(Status::unimplemented(format!(
+        "no method {:#010x}",
+        request.method
+      ))),
+    };
+
+    let status = match &outcome {
+      Ok(()) => Status::new(envelope::Code::OK, ""),
+      Err(status) => status.clone(),
+    };
+    let response = envelope::Response {
+      kind: Some(Kind::Status((&status).into())),
+      ..Default::default()
+    };
+    if write_frame(&mut send, &response).await.is_ok() {
+      send.finish().ok();
+    }
+  }
+}
+
+/// Runs a method and writes what it returns, up to the status.
+async fn run(
+  service: &dyn Service,
+  method: u32,
+  ctx: Context,
+  payload: Vec<u8>,
+  send: &mut SendStream,
+) -> Result<(), Status> {
+  match service.call(method, ctx, payload).await? {
+    Reply::Unary(item) => write_item(send, item).await,
+    Reply::Streaming(mut items) => {
+      while let Some(item) = items.next().await {
+        write_item(send, item?).await?;
+      }
+      Ok(())
+    }
+  }
+}
+
+async fn write_item(send: &mut SendStream, item: Vec<u8>) -> Result<(), Status> {
+  let response = envelope::Response {
+    kind: Some(Kind::Item(item)),
+    ..Default::default()
+  };
+  write_frame(send, &response).await
+}
+
+/// Returns once the client resets its side of the stream, or is gone.
+async fn cancelled(recv: &mut RecvStream) -> VarInt {
+  let mut buf = [0; 64];
+  loop {
+    match recv.read(&mut buf).await {
+      // Nothing is expected after the request
+      Ok(Some(_)) => (),
+      Ok(None) => future::pending::<()>().await,
+      Err(ReadError::Reset(code)) => return code,
+      Err(_) => return CANCELLED,
+    }
+  }
+}
@@ -0,0 +1,353 @@
+use std::{
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+
+use futures_util::{future, stream, StreamExt};
+use protobuf::Message;
+use quinn::{ClientConfig, Endpoint, ServerConfig};
+use quinn_example::{
+  protos::{
+    envelope::{self, response::Kind},
+    example::{AddRequest, CountRequest, DivideRequest, Number, Quotient, Sum},
+  },
+  rpc::{Client, Code, Context, ResponseStream, Server, Status, MAX_TIMEOUT},
+  services::calculator::{self, Calculator, CalculatorClient, CalculatorServer},
+};
+use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+use tokio::time::{sleep, Instant};
+
+/// Counts the handlers dropped before they were done.
+#[derive(Clone, Default)]
+struct Cancellations(Arc<AtomicUsize>);
+
+impl Cancellations {
+  fn count(&self) -> usize {
+    self.0.load(Ordering::SeqCst)
+  }
+
+  fn guard(&self) -> Guard {
+    Guard {
+      cancellations: self.clone(),
+      done: false,
+    }
+  }
+}
+
+struct Guard {
+  cancellations: Cancellations,
+  done: bool,
+}
+
+impl Guard {
+  fn finish(mut self) {
+    self.done = true;
+  }
+}
+
+impl Drop for Guard {
+  fn drop(&mut self) {
+    if !self.done {
+      self.cancellations.0.fetch_add(1, Ordering::SeqCst);
+    }
+  }
+}
+
+struct Calc {
+  cancellations: Cancellations,
+}
+
+impl Calculator for Calc {
+  async fn add(&self, ctx: Context, request: AddRequest) -> Result<Sum, Status> {
+    let guard = self.cancellations.guard();
+    assert!(ctx.peer().ip().is_loopback());
+    sleep(Duration::from_millis(request.delay_ms)).await;
+    guard.finish();
+    Ok(Sum {
+      value: request.a + request.b,
+      ..Default::default()
+    })
+  }
+
+  async fn divide(&self, _ctx: Context, request: DivideRequest) -> Result<Quotient, Status> {
+    if request.divisor == 0 {
+      return Err(Status::invalid_argument("division by zero"));
+    }
+    Ok(Quotient {
+      value: request.dividend / request.divisor,
+      remainder: request.dividend % request.divisor,
+      ..Default::default()
+    })
+  }
+
+  async fn count(
+    &self,
+    _ctx: Context,
+    request: CountRequest,
+  ) -> Result<ResponseStream<Number>, Status> {
+    if request.to < request.from {
+      return Err(Status::invalid_argument("nothing to count"));
+    }
+    let interval = Duration::from_millis(request.interval_ms);
+    let guard = self.cancellations.guard();
+    let numbers = stream::unfold(
+      (request.from, request.to, guard),
+      move |(next, to, guard)| async move {
+        if next > to {
+          guard.finish();
+          return None;
+        }
+        sleep(interval).await;
+        let number = Number {
+          value: next,
+          ..Default::default()
+        };
+        Some((Ok(number), (next + 1, to, guard)))
+      },
+    );
+    Ok(numbers.boxed())
+  }
+}
+
+/// A server on loopback with a self-signed certificate, and a client connected to it.
+async fn connect() -> (Client, Cancellations) {
+  let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+  let cert_der = CertificateDer::from(cert.cert);
+  let key = PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+
+  let server_config = ServerConfig::with_single_cert(vec![cert_der.clone()], key.into()).unwrap();
+  let endpoint = Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+  let addr = endpoint.local_addr().unwrap();
+  let cancellations = Cancellations::default();
+  let server = Server::new().service(CalculatorServer::new(Calc {
+    cancellations: cancellations.clone(),
+  }));
+  tokio::spawn(server.serve(endpoint));
+
+  let mut roots = rustls::RootCertStore::empty();
+  roots.add(cert_der).unwrap();
+  let mut endpoint = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+  endpoint
+    .set_default_client_config(ClientConfig::with_root_certificates(Arc::new(roots)).unwrap());
+  let conn = endpoint.connect(addr, "localhost").unwrap().await.unwrap();
+
+  (Client::new(conn), cancellations)
+}
+
+async fn eventually(what: &str, condition: impl Fn() -> bool) {
+  for _ in 0 .. 100 {
+    if condition() {
+      return;
+    }
+    sleep(Duration::from_millis(20)).await;
+  }
+  panic!("timed out waiting for {}", what);
+}
+
+fn add(a: i64, b: i64, delay_ms: u64) -> AddRequest {
+  AddRequest {
+    a,
+    b,
+    delay_ms,
+    ..Default::default()
+  }
+}
+
+fn count(from: i64, to: i64, interval_ms: u64) -> CountRequest {
+  CountRequest {
+    from,
+    to,
+    interval_ms,
+    ..Default::default()
+  }
+}
+
+#[tokio::test]
+async fn calls_are_answered_with_responses_or_statuses() {
+  let (client, _) = connect().await;
+  let calc = CalculatorClient::new(client.clone());
+
+  assert_eq!(calc.add(&add(2, 3, 0)).await.unwrap().value, 5);
+  let quotient = calc
+    .divide(&DivideRequest {
+      dividend: 17,
+      divisor: 5,
+      ..Default::default()
+    })
+    .await
+    .unwrap();
+  assert_eq!((quotient.value, quotient.remainder), (3, 2));
+
+  let status = calc
+    .divide(&DivideRequest {
+      dividend: 1,
+      ..Default::default()
+    })
+    .await
+    .unwrap_err();
+  assert_eq!(status, Status::invalid_argument("division by zero"));
+
+  let status = client
+    .call::<_, Sum>(0x1234_5678, &add(1, 1, 0))
+    .await
+    .unwrap_err();
+  assert_eq!(status.code, Code::UNIMPLEMENTED);
+  // The connection is still good for more calls
+  assert_eq!(
+    client
+      .call::<_, Sum>(calculator::ADD, &add(-1, 1, 0))
+      .await
+      .unwrap()
+      .value,
+    0
+  );
+}
+
+#[tokio::test]
+async fn calls_on_one_connection_do_not_wait_for_each_other() {
+  let (client, _) = connect().await;
+  let calc = CalculatorClient::new(client);
+
+  // The first calls take longest, so answers come back in reverse
+  let started = Instant::now();
+  let sums = future::join_all((0 .. 50).map(|i| {
+    let calc = calc.clone();
+    async move { calc.add(&add(i, 1000, 500 - i as u64 * 10)).await }
+  }))
+  .await;
+  for (i, sum) in sums.into_iter().enumerate() {
+    assert_eq!(sum.unwrap().value, i as i64 + 1000);
+  }
+  // One after the other they would take over 12 seconds
+  assert!(started.elapsed() < Duration::from_secs(3));
+}
+
+#[tokio::test]
+async fn calls_past_their_deadline_are_cancelled_on_both_sides() {
+  let (client, cancellations) = connect().await;
+  let calc = CalculatorClient::new(client);
+
+  let started = Instant::now();
+  let status = calc
+    .with_timeout(Duration::from_millis(200))
+    .add(&add(1, 2, 10_000))
+    .await
+    .unwrap_err();
+  assert_eq!(status.code, Code::DEADLINE_EXCEEDED);
+  assert!(started.elapsed() < Duration::from_secs(1));
+  eventually("the handler to be dropped", || cancellations.count() == 1).await;
+
+  // In time
+  let sum = calc
+    .with_timeout(Duration::from_secs(5))
+    .add(&add(1, 2, 10))
+    .await
+    .unwrap();
+  assert_eq!(sum.value, 3);
+
+  // Given up on by dropping the call
+  let request = add(1, 2, 10_000);
+  assert!(
+    tokio::time::timeout(Duration::from_millis(100), calc.add(&request))
+      .await
+      .is_err()
+  );
+  eventually("the handler to be dropped", || cancellations.count() == 2).await;
+}
+
+#[tokio::test]
+async fn timeouts_over_the_maximum_are_refused() {
+  let (client, cancellations) = connect().await;
+  let calc = CalculatorClient::new(client.clone());
+
+  for timeout in [MAX_TIMEOUT + Duration::from_millis(1), Duration::MAX] {
+    let status = calc
+      .clone()
+      .with_timeout(timeout)
+      .add(&add(1, 2, 0))
+      .await
+      .unwrap_err();
+    assert_eq!(status.code, Code::INVALID_ARGUMENT);
+  }
+  let sum = calc.with_timeout(MAX_TIMEOUT).add(&add(1, 2, 0)).await;
+  assert_eq!(sum.unwrap().value, 3);
+
+  // Sent as is, past the client
+  let (mut send, mut recv) = client.connection().open_bi().await.unwrap();
+  let request = envelope::Request {
+    method: calculator::ADD,
+    timeout_ms: u64::MAX,
+    payload: add(1, 2, 0).write_to_bytes().unwrap(),
+    ..Default::default()
+  };
+  let bytes = request.write_to_bytes().unwrap();
+  send
+    .write_all(&(bytes.len() as u32).to_be_bytes())
+    .await
+    .unwrap();
+  send.write_all(&bytes).await.unwrap();
+  send.finish().unwrap();
+  let frame = recv.read_to_end(1 << 16).await.unwrap();
+  let response = envelope::Response::parse_from_bytes(&frame[4 ..]).unwrap();
+  match response.kind {
+    Some(Kind::Status(status)) => {
+      assert_eq!(status.code.enum_value(), Ok(Code::INVALID_ARGUMENT))
+    }
+    kind => panic!("not a status: {:?}", kind),
+  }
+  assert_eq!(cancellations.count(), 0);
+}
+
+#[tokio::test]
+async fn streams_deliver_every_response_in_order() {
+  let (client, _) = connect().await;
+  let calc = CalculatorClient::new(client);
+
+  let mut numbers = calc.count(&count(1, 5, 10)).await.unwrap();
+  let mut values = vec![];
+  while let Some(number) = numbers.message().await.unwrap() {
+    values.push(number.value);
+  }
+  assert_eq!(values, vec![1, 2, 3, 4, 5]);
+  assert_eq!(numbers.message().await, Ok(None));
+
+  // Failing before the first response
+  let mut numbers = calc.count(&count(5, 1, 10)).await.unwrap();
+  assert_eq!(
+    numbers.message().await,
+    Err(Status::invalid_argument("nothing to count"))
+  );
+}
+
+#[tokio::test]
+async fn streams_stop_when_dropped_or_late() {
+  let (client, cancellations) = connect().await;
+  let calc = CalculatorClient::new(client);
+
+  let mut numbers = calc.count(&count(0, 1_000_000, 10)).await.unwrap();
+  for expected in 0 .. 3 {
+    assert_eq!(numbers.message().await.unwrap().unwrap().value, expected);
+  }
+  numbers.cancel();
+  eventually("the stream to be dropped", || cancellations.count() == 1).await;
+
+  let mut numbers = calc
+    .with_timeout(Duration::from_millis(300))
+    .count(&count(0, 1_000_000, 50))
+    .await
+    .unwrap();
+  let mut received = 0;
+  let status = loop {
+    match numbers.message().await {
+      Ok(Some(_)) => received += 1,
+      Ok(None) => panic!("the stream ended"),
+      Err(status) => break status,
+    }
+  };
+  assert_eq!(status.code, Code::DEADLINE_EXCEEDED);
+  assert!((1 .. 10).contains(&received), "{} received", received);
+  eventually("the stream to be dropped", || cancellations.count() == 2).await;
+}
@@ -0,0 +1,595 @@
+use std::{
+  fs,
+  io::Write,
+  net::IpAddr,
+  path::{Path, PathBuf},
+};
+
+use rand::Rng;
+use rcgen::{
+  BasicConstraints, CertificateParams, CertificateRevocationListParams, DistinguishedName, DnType,
+  ExtendedKeyUsagePurpose, IsCa, Issuer, KeyIdMethod, KeyPair, KeyUsagePurpose, RevokedCertParams,
+  SanType, SerialNumber,
+};
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+  error::CaError,
+  index::{Entry, Index, Kind, Reason, Status},
+};
+
+pub const ROOT_LIFETIME: Duration = Duration::days(10 * 365);
+pub const INTERMEDIATE_LIFETIME: Duration = Duration::days(5 * 365);
+/// Leaves are short-lived so that renewing them is routine rather than an event.
+pub const SERVER_LIFETIME: Duration = Duration::days(90);
+pub const CLIENT_LIFETIME: Duration = Duration::days(30);
+/// How long a published CRL stays valid, it must be republished before then.
+pub const CRL_LIFETIME: Duration = Duration::days(7);
+/// Certificates start a little in the past to absorb clock skew between hosts.
+const BACKDATE: Duration = Duration::minutes(5);
+
+/// What to put in a leaf certificate.
+#[derive(Debug, Clone)]
+pub struct LeafRequest {
+  pub kind: Kind,
+  pub common_name: String,
+  /// DNS names and IP addresses. A server certificate without any gets its common name.
+  pub sans: Vec<String>,
+  /// Defaults to [`SERVER_LIFETIME`] or [`CLIENT_LIFETIME`].
+  pub lifetime: Option<Duration>,
+}
+
+impl LeafRequest {
+  pub fn server(common_name: &str, sans: &[&str]) -> LeafRequest {
+    LeafRequest {
+      kind: Kind::Server,
+      common_name: common_name.to_string(),
+      sans: sans.iter().map(|san| san.to_string()).collect(),
+      lifetime: None,
+    }
+  }
+
+  pub fn client(common_name: &str) -> LeafRequest {
+    LeafRequest {
+      kind: Kind::Client,
+      common_name: common_name.to_string(),
+      sans: vec![],
+      lifetime: None,
+    }
+  }
+}
+
+/// A two-level CA kept in a directory: a root that only signs the intermediate, and an
+/// intermediate that signs leaves and their CRL.
+///
+/// ```text
+/// root.pem root.key                  trust anchor, keep the key offline once initialized
+/// intermediate.pem intermediate.key
+/// root.crl.pem intermediate.crl.pem
+/// issued/<serial>.pem .key .chain.pem
+/// index.json
+/// ```
+pub struct Ca {
+  dir: PathBuf,
+  index: Index,
+}
+
+impl Ca {
+  /// Creates the root and intermediate CAs in `dir` and publishes empty CRLs.
+  pub fn init(dir: &Path, organization: &str) -> Result<Ca, CaError> {
+    if dir.join("index.json").exists() {
+      return Err(CaError::AlreadyInitialized(dir.to_path_buf()));
+    }
+    let issued = dir.join("issued");
+    fs::create_dir_all(&issued).map_err(|source| CaError::Io {
+      path: issued,
+      source,
+    })?;
+    let mut ca = Ca {
+      dir: dir.to_path_buf(),
+      index: Index::default(),
+    };
+    let now = now();
+
+    let root_name = format!("{} Root CA", organization);
+    let mut root = ca_params(organization, &root_name, now, ROOT_LIFETIME)?;
+    root.serial_number = Some(ca.new_serial());
+    root.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let root_key = KeyPair::generate()?;
+    let root_cert = root.self_signed(&root_key)?;
+    let root_entry = ca_entry(&root, Kind::Root, &root_name);
+    let root = Issuer::new(root, root_key);
+
+    let intermediate_name = format!("{} Intermediate CA", organization);
+    let mut intermediate = ca_params(organization, &intermediate_name, now, INTERMEDIATE_LIFETIME)?;
+    intermediate.serial_number = Some(ca.new_serial());
+    // May sign leaves only, never another CA
+    intermediate.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    intermediate.use_authority_key_identifier_extension = true;
+    let intermediate_key = KeyPair::generate()?;
+    let intermediate_cert = intermediate.signed_by(&intermediate_key, &root)?;
+    let intermediate_entry = ca_entry(&intermediate, Kind::Intermediate, &intermediate_name);
+
+    write_private(&ca.root_key_path(), root.key().serialize_pem().as_bytes())?;
+    write_public(&ca.root_cert_path(), root_cert.pem().as_bytes())?;
+    write_private(
+      &ca.intermediate_key_path(),
+      intermediate_key.serialize_pem().as_bytes(),
+    )?;
+    write_public(
+      &ca.intermediate_cert_path(),
+      intermediate_cert.pem().as_bytes(),
+    )?;
+    ca.index.entries.push(root_entry);
+    ca.index.entries.push(intermediate_entry);
+    ca.publish_crl(CRL_LIFETIME)?;
+    Ok(ca)
+  }
+
+  pub fn open(dir: &Path) -> Result<Ca, CaError> {
+    let index = dir.join("index.json");
+    if !index.exists() {
+      return Err(CaError::NotInitialized(dir.to_path_buf()));
+    }
+    Ok(Ca {
+      dir: dir.to_path_buf(),
+      index: Index::load(&index)?,
+    })
+  }
+
+  pub fn entries(&self) -> &[Entry] {
+    &self.index.entries
+  }
+
+  pub fn get(&self, serial: &str) -> Option<&Entry> {
+    self.index.get(serial)
+  }
+
+  /// Signs a new leaf with the intermediate and writes its key, certificate and the chain a peer
+  /// should present: the leaf followed by the intermediate.
+  pub fn issue(&mut self, request: &LeafRequest) -> Result<Entry, CaError> {
+    let (key_usages, extended_key_usages) = match request.kind {
+      // ECDSA keys only sign: no keyEncipherment
+      Kind::Server => (
+        vec![KeyUsagePurpose::DigitalSignature],
+        vec![ExtendedKeyUsagePurpose::ServerAuth],
+      ),
+      Kind::Client => (
+        vec![KeyUsagePurpose::DigitalSignature],
+        vec![ExtendedKeyUsagePurpose::ClientAuth],
+      ),
+      Kind::Root | Kind::Intermediate => {
+        return Err(CaError::NotALeaf(request.common_name.clone()))
+      }
+    };
+    let mut sans = request.sans.clone();
+    if sans.is_empty() && request.kind == Kind::Server {
+      sans.push(request.common_name.clone());
+    }
+
+    let issuer = self.intermediate()?;
+    let now = now();
+    let lifetime = request.lifetime.unwrap_or(match request.kind {
+      Kind::Server => SERVER_LIFETIME,
+      _ => CLIENT_LIFETIME,
+    });
+    // A leaf outliving its issuer would stop verifying early anyway
+    let intermediate_expiry = self
+      .index
+      .entries
+      .iter()
+      .find(|entry| entry.kind == Kind::Intermediate)
+      .map(|entry| entry.not_after)
+      .ok_or_else(|| CaError::NotInitialized(self.dir.clone()))?;
+
+    let mut params = CertificateParams::default();
+    params.distinguished_name = DistinguishedName::new();
+    params
+      .distinguished_name
+      .push(DnType::CommonName, request.common_name.as_str());
+    params.subject_alt_names = sans
+      .iter()
+      .map(|san| parse_san(san))
+      .collect::<Result<_, _>>()?;
+    params.not_before = now - BACKDATE;
+    params.not_after = expiry(now, lifetime)?.min(intermediate_expiry);
+    params.key_usages = key_usages;
+    params.extended_key_usages = extended_key_usages;
+    params.use_authority_key_identifier_extension = true;
+    let serial = self.new_serial();
+    params.serial_number = Some(serial.clone());
+
+    let key = KeyPair::generate()?;
+    let cert = params.signed_by(&key, &issuer)?;
+    let serial = hex(serial.as_ref());
+    let intermediate = self.read(&self.intermediate_cert_path())?;
+    write_private(&self.key_path(&serial), key.serialize_pem().as_bytes())?;
+    write_public(&self.cert_path(&serial), cert.pem().as_bytes())?;
+    write_public(
+      &self.chain_path(&serial),
+      format!("{}{}", cert.pem(), intermediate).as_bytes(),
+    )?;
+
+    let entry = Entry {
+      serial,
+      kind: request.kind,
+      common_name: request.common_name.clone(),
+      sans,
+      not_before: params.not_before,
+      not_after: params.not_after,
+      status: Status::Valid,
+      renewed_by: None,
+    };
+    self.index.entries.push(entry.clone());
+    self.save()?;
+    Ok(entry)
+  }
+
+  /// Marks a leaf as revoked and publishes new CRLs.
+  pub fn revoke(&mut self, serial: &str, reason: Reason) -> Result<(), CaError> {
+    let entry = self
+      .index
+      .get_mut(serial)
+      .ok_or_else(|| CaError::UnknownSerial(serial.to_string()))?;
+    if !entry.is_leaf() {
+      return Err(CaError::NotALeaf(serial.to_string()));
+    }
+    if entry.is_revoked() {
+      return Err(CaError::AlreadyRevoked(serial.to_string()));
+    }
+    entry.status = Status::Revoked { at: now(), reason };
+    self.publish_crl(CRL_LIFETIME)
+  }
+
+  /// Signs and writes the CRLs of both CAs, valid for `lifetime`. Verifiers checking the whole
+  /// chain need the root's too, even though it lists nothing while the intermediate is in use.
+  pub fn publish_crl(&mut self, lifetime: Duration) -> Result<(), CaError> {
+    let now = now();
+    let next_update = expiry(now, lifetime)?;
+    self.index.crl_number += 1;
+    let revoked = self
+      .index
+      .entries
+      .iter()
+      .filter_map(|entry| match entry.status {
+        Status::Revoked { at, reason } if entry.is_leaf() => Some(RevokedCertParams {
+          serial_number: SerialNumber::from_slice(&unhex(&entry.serial)),
+          revocation_time: at,
+          reason_code: Some(reason.into()),
+          invalidity_date: None,
+        }),
+        _ => None,
+      })
+      .collect();
+    let crl = |revoked_certs| CertificateRevocationListParams {
+      this_update: now,
+      next_update,
+      crl_number: SerialNumber::from(self.index.crl_number),
+      issuing_distribution_point: None,
+      revoked_certs,
+      key_identifier_method: KeyIdMethod::Sha256,
+    };
+    let root_crl = crl(vec![]).signed_by(&self.root()?)?;
+    let intermediate_crl = crl(revoked).signed_by(&self.intermediate()?)?;
+    write_public(&self.root_crl_path(), root_crl.pem()?.as_bytes())?;
+    write_public(
+      &self.intermediate_crl_path(),
+      intermediate_crl.pem()?.as_bytes(),
+    )?;
+    self.save()
+  }
+
+  /// Issues a replacement for a leaf with a fresh key, the same names and the same lifetime. The
+  /// old certificate stays valid until it expires, giving its users time to switch.
+  pub fn renew(&mut self, serial: &str) -> Result<Entry, CaError> {
+    let old = self
+      .index
+      .get(serial)
+      .ok_or_else(|| CaError::UnknownSerial(serial.to_string()))?;
+    if !old.is_leaf() {
+      return Err(CaError::NotALeaf(serial.to_string()));
+    }
+    if old.is_revoked() {
+      return Err(CaError::AlreadyRevoked(serial.to_string()));
+    }
+    let request = LeafRequest {
+      kind: old.kind,
+      common_name: old.common_name.clone(),
+      sans: old.sans.clone(),
+      lifetime: Some(old.not_after - old.not_before - BACKDATE),
+    };
+    let new = self.issue(&request)?;
+    if let Some(old) = self.index.get_mut(serial) {
+      old.renewed_by = Some(new.serial.clone());
+    }
+    self.save()?;
+    Ok(new)
+  }
+
+  /// Valid leaves, not yet renewed, that expire within `within`.
+  pub fn expiring(&self, within: Duration) -> Vec<&Entry> {
+    let deadline = now() + within;
+    self
+      .index
+      .entries
+      .iter()
+      .filter(|entry| {
+        entry.is_leaf()
+          && !entry.is_revoked()
+          && entry.renewed_by.is_none()
+          && entry.not_after <= deadline
+      })
+      .collect()
+  }
+
+  /// Renews every leaf [`Ca::expiring`] returns, returning the new certificates.
+  pub fn renew_expiring(&mut self, within: Duration) -> Result<Vec<Entry>, CaError> {
+    let serials: Vec<String> = self
+      .expiring(within)
+      .into_iter()
+      .map(|entry| entry.serial.clone())
+      .collect();
+    serials.iter().map(|serial| self.renew(serial)).collect()
+  }
+
+  pub fn root_cert_path(&self) -> PathBuf {
+    self.dir.join("root.pem")
+  }
+
+  pub fn intermediate_cert_path(&self) -> PathBuf {
+    self.dir.join("intermediate.pem")
+  }
+
+  pub fn root_crl_path(&self) -> PathBuf {
+    self.dir.join("root.crl.pem")
+  }
+
+  pub fn intermediate_crl_path(&self) -> PathBuf {
+    self.dir.join("intermediate.crl.pem")
+  }
+
+  pub fn cert_path(&self, serial: &str) -> PathBuf {
+    self.dir.join("issued").join(format!("{}.pem", serial))
+  }
+
+  pub fn key_path(&self, serial: &str) -> PathBuf {
+    self.dir.join("issued").join(format!("{}.key", serial))
+  }
+
+  pub fn chain_path(&self, serial: &str) -> PathBuf {
+    self
+      .dir
+      .join("issued")
+      .join(format!("{}.chain.pem", serial))
+  }
+
+  fn root_key_path(&self) -> PathBuf {
+    self.dir.join("root.key")
+  }
+
+  fn intermediate_key_path(&self) -> PathBuf {
+    self.dir.join("intermediate.key")
+  }
+
+  fn root(&self) -> Result<Issuer<'static, KeyPair>, CaError> {
+    self.issuer(&self.root_cert_path(), &self.root_key_path())
+  }
+
+  fn intermediate(&self) -> Result<Issuer<'static, KeyPair>, CaError> {
+    self.issuer(
+      &self.intermediate_cert_path(),
+      &self.intermediate_key_path(),
+    )
+  }
+
+  fn issuer(&self, cert: &Path, key: &Path) -> Result<Issuer<'static, KeyPair>, CaError> {
+    let key = KeyPair::from_pem(&self.read(key)?)?;
+    Ok(Issuer::from_ca_cert_pem(&self.read(cert)?, key)?)
+  }
+
+  fn read(&self, path: &Path) -> Result<String, CaError> {
+    fs::read_to_string(path).map_err(|source| CaError::Io {
+      path: path.to_path_buf(),
+      source,
+    })
+  }
+
+  fn save(&self) -> Result<(), CaError> {
+    self.index.save(&self.dir.join("index.json"))
+  }
+
+  /// A random positive 127-bit serial, as the CA/Browser Forum asks for at least 64 random bits,
+  /// that the index has not seen yet.
+  fn new_serial(&self) -> SerialNumber {
+    loop {
+      let mut bytes: [u8; 16] = rand::thread_rng().gen();
+      // DER integers are signed and drop leading zero bytes, which would change the hex form
+      bytes[0] = bytes[0].clamp(1, 0x7f);
+      if self.index.get(&hex(&bytes)).is_none() {
+        return SerialNumber::from_slice(&bytes);
+      }
+    }
+  }
+}
+
+fn ca_params(
+  organization: &str,
+  common_name: &str,
+  now: OffsetDateTime,
+  lifetime: Duration,
+) -> Result<CertificateParams, CaError> {
+  let mut params = CertificateParams::default();
+  params.distinguished_name = DistinguishedName::new();
+  params
+    .distinguished_name
+    .push(DnType::OrganizationName, organization);
+  params
+    .distinguished_name
+    .push(DnType::CommonName, common_name);
+  params.not_before = now - BACKDATE;
+  params.not_after = expiry(now, lifetime)?;
+  params.key_usages = vec![
+    KeyUsagePurpose::KeyCertSign,
+    KeyUsagePurpose::CrlSign,
+    KeyUsagePurpose::DigitalSignature,
+  ];
+  Ok(params)
+}
+
+fn ca_entry(params: &CertificateParams, kind: Kind, common_name: &str) -> Entry {
+  Entry {
+    serial: hex(params.serial_number.as_ref().unwrap().as_ref()),
+    kind,
+    common_name: common_name.to_string(),
+    sans: vec![],
+    not_before: params.not_before,
+    not_after: params.not_after,
+    status: Status::Valid,
+    renewed_by: None,
+  }
+}
+
+fn parse_san(san: &str) -> Result<SanType, CaError> {
+  if let Ok(ip) = san.parse::<IpAddr>() {
+    return Ok(SanType::IpAddress(ip));
+  }
+  let valid = !san.is_empty()
+    && san.split('.').all(|label| {
+      !label.is_empty()
+        && (label == "*" || label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+    });
+  if !valid {
+    return Err(CaError::InvalidSan(san.to_string()));
+  }
+  let name = san
+    .try_into()
+    .map_err(|_| CaError::InvalidSan(san.to_string()))?;
+  Ok(SanType::DnsName(name))
+}
+
+/// When something starting at `now` and lasting `lifetime` ends, for lifetimes that make sense.
+fn expiry(now: OffsetDateTime, lifetime: Duration) -> Result<OffsetDateTime, CaError> {
+  Some(lifetime)
+    .filter(|lifetime| lifetime.is_positive())
+    .and_then(|lifetime| now.checked_add(lifetime))
+    .ok_or(CaError::Lifetime(lifetime))
+}
+
+/// Certificates carry whole seconds, so the index does too.
+fn now() -> OffsetDateTime {
+  OffsetDateTime::now_utc().replace_nanosecond(0).unwrap()
+}
+
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(hex: &str) -> Vec<u8> {
+  (0 .. hex.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(&hex[i .. i + 2], 16).unwrap())
+    .collect()
+}
+
+fn write_public(path: &Path, contents: &[u8]) -> Result<(), CaError> {
+  fs::write(path, contents).map_err(|source| CaError::Io {
+    path: path.to_path_buf(),
+    source,
+  })
+}
+
+/// Creates `path` readable by its owner only.
+fn write_private(path: &Path, contents: &[u8]) -> Result<(), CaError> {
+  let mut options = fs::OpenOptions::new();
+  options.write(true).create_new(true);
+  #[cfg(unix)]
+  std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+  options
+    .open(path)
+    .and_then(|mut file| file.write_all(contents))
+    .map_err(|source| CaError::Io {
+      path: path.to_path_buf(),
+      source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use tempfile::TempDir;
+
+  use super::*;
+
+  #[test]
+  fn sans_are_dns_names_or_ips() {
+    assert!(matches!(
+      parse_san("127.0.0.1").unwrap(),
+      SanType::IpAddress(_)
+    ));
+    assert!(matches!(parse_san("::1").unwrap(), SanType::IpAddress(_)));
+    assert!(matches!(
+      parse_san("*.crabs.local").unwrap(),
+      SanType::DnsName(_)
+    ));
+    assert!(parse_san("crabs..local").is_err());
+    assert!(parse_san("crabs local").is_err());
+  }
+
+  #[test]
+  fn serials_round_trip_through_hex() {
+    let bytes = [0x01, 0xab, 0x00, 0x7f];
+    assert_eq!(hex(&bytes), "01ab007f");
+    assert_eq!(unhex("01ab007f"), bytes);
+  }
+
+  #[test]
+  fn index_tracks_revocation_and_renewal() {
+    let dir = TempDir::new().unwrap();
+    let mut ca = Ca::init(dir.path(), "Crab widgits SE").unwrap();
+    assert!(matches!(
+      Ca::init(dir.path(), "Crab widgits SE"),
+      Err(CaError::AlreadyInitialized(_))
+    ));
+    let root = ca.entries()[0].serial.clone();
+    assert!(matches!(
+      ca.revoke(&root, Reason::KeyCompromise),
+      Err(CaError::NotALeaf(_))
+    ));
+
+    let mut request = LeafRequest::client("billing");
+    for lifetime in [Duration::days(-5), Duration::ZERO, Duration::MAX] {
+      request.lifetime = Some(lifetime);
+      assert!(matches!(ca.issue(&request), Err(CaError::Lifetime(_))));
+    }
+    assert!(matches!(
+      ca.publish_crl(Duration::days(10_000_000)),
+      Err(CaError::Lifetime(_))
+    ));
+    request.lifetime = Some(Duration::days(3));
+    let short = ca.issue(&request).unwrap();
+    let long = ca.issue(&LeafRequest::server("api", &[])).unwrap();
+    assert_eq!(long.sans, vec!["api".to_string()]);
+    assert_eq!(long.not_after - long.not_before, SERVER_LIFETIME + BACKDATE);
+
+    let renewed = ca.renew_expiring(Duration::days(7)).unwrap();
+    assert_eq!(renewed.len(), 1);
+    assert_eq!(renewed[0].common_name, "billing");
+    // The replacement keeps the three day lifetime, only it is left to renew
+    let expiring = ca.expiring(Duration::days(7));
+    assert_eq!(expiring.len(), 1);
+    assert_eq!(expiring[0].serial, renewed[0].serial);
+
+    ca.revoke(&long.serial, Reason::Superseded).unwrap();
+    assert!(matches!(
+      ca.revoke(&long.serial, Reason::Superseded),
+      Err(CaError::AlreadyRevoked(_))
+    ));
+
+    let ca = Ca::open(dir.path()).unwrap();
+    assert_eq!(ca.entries().len(), 5);
+    assert_eq!(
+      ca.get(&short.serial).unwrap().renewed_by,
+      Some(renewed[0].serial.clone())
+    );
+    assert!(ca.get(&long.serial).unwrap().is_revoked());
+  }
+}
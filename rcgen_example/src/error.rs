@@ -0,0 +1,25 @@
+use std::{io, path::PathBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum CaError {
+  #[error("{path}: {source}")]
+  Io { path: PathBuf, source: io::Error },
+  #[error("{0} already holds a CA")]
+  AlreadyInitialized(PathBuf),
+  #[error("{0} holds no CA, run `init` first")]
+  NotInitialized(PathBuf),
+  #[error("corrupt index: {0}")]
+  Index(#[from] serde_json::Error),
+  #[error("no certificate with serial {0}")]
+  UnknownSerial(String),
+  #[error("certificate {0} is not a leaf certificate")]
+  NotALeaf(String),
+  #[error("certificate {0} is already revoked")]
+  AlreadyRevoked(String),
+  #[error("invalid lifetime {0}: it must be positive and end before the year 10000")]
+  Lifetime(time::Duration),
+  #[error("invalid subject alternative name {0:?}")]
+  InvalidSan(String),
+  #[error(transparent)]
+  Certificate(#[from] rcgen::Error),
+}
@@ -0,0 +1,133 @@
+use std::{fmt, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::error::CaError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+  #[value(skip)]
+  Root,
+  #[value(skip)]
+  Intermediate,
+  /// TLS server certificate: `serverAuth`, SANs required.
+  Server,
+  /// TLS client certificate for mTLS: `clientAuth`.
+  Client,
+}
+
+impl fmt::Display for Kind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      Kind::Root => "root",
+      Kind::Intermediate => "intermediate",
+      Kind::Server => "server",
+      Kind::Client => "client",
+    };
+    f.write_str(name)
+  }
+}
+
+/// The reasons RFC 5280 defines that make sense for a leaf we issued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+  Unspecified,
+  KeyCompromise,
+  AffiliationChanged,
+  Superseded,
+  CessationOfOperation,
+}
+
+impl From<Reason> for rcgen::RevocationReason {
+  fn from(reason: Reason) -> Self {
+    match reason {
+      Reason::Unspecified => rcgen::RevocationReason::Unspecified,
+      Reason::KeyCompromise => rcgen::RevocationReason::KeyCompromise,
+      Reason::AffiliationChanged => rcgen::RevocationReason::AffiliationChanged,
+      Reason::Superseded => rcgen::RevocationReason::Superseded,
+      Reason::CessationOfOperation => rcgen::RevocationReason::CessationOfOperation,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Status {
+  Valid,
+  Revoked {
+    #[serde(with = "time::serde::rfc3339")]
+    at: OffsetDateTime,
+    reason: Reason,
+  },
+}
+
+/// One certificate the CA signed, or one of the CA's own.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+  /// Lowercase hex, as `openssl x509 -serial` prints it.
+  pub serial: String,
+  pub kind: Kind,
+  pub common_name: String,
+  pub sans: Vec<String>,
+  #[serde(with = "time::serde::rfc3339")]
+  pub not_before: OffsetDateTime,
+  #[serde(with = "time::serde::rfc3339")]
+  pub not_after: OffsetDateTime,
+  #[serde(flatten)]
+  pub status: Status,
+  /// Serial of the certificate `renew` issued in place of this one.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub renewed_by: Option<String>,
+}
+
+impl Entry {
+  pub fn is_leaf(&self) -> bool {
+    matches!(self.kind, Kind::Server | Kind::Client)
+  }
+
+  pub fn is_revoked(&self) -> bool {
+    matches!(self.status, Status::Revoked { .. })
+  }
+}
+
+/// `index.json`: every serial the CA handed out, so none is reused and revocations survive
+/// between runs.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Index {
+  /// Number of the last CRL published, CRL numbers must increase.
+  pub crl_number: u64,
+  pub entries: Vec<Entry>,
+}
+
+impl Index {
+  pub fn load(path: &Path) -> Result<Index, CaError> {
+    let json = fs::read_to_string(path).map_err(|source| CaError::Io {
+      path: path.to_path_buf(),
+      source,
+    })?;
+    Ok(serde_json::from_str(&json)?)
+  }
+
+  /// Writes a temporary file and renames it over `path`, so a crash never leaves half an index.
+  pub fn save(&self, path: &Path) -> Result<(), CaError> {
+    let json = serde_json::to_string_pretty(self)?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, json)
+      .and_then(|()| fs::rename(&tmp, path))
+      .map_err(|source| CaError::Io {
+        path: path.to_path_buf(),
+        source,
+      })
+  }
+
+  pub fn get(&self, serial: &str) -> Option<&Entry> {
+    self.entries.iter().find(|entry| entry.serial == serial)
+  }
+
+  pub fn get_mut(&mut self, serial: &str) -> Option<&mut Entry> {
+    self.entries.iter_mut().find(|entry| entry.serial == serial)
+  }
+}
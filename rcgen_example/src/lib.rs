@@ -0,0 +1,12 @@
+//! A small two-level certificate authority for mTLS between services.
+
+mod ca;
+mod error;
+mod index;
+
+pub use ca::{
+  Ca, LeafRequest, CLIENT_LIFETIME, CRL_LIFETIME, INTERMEDIATE_LIFETIME, ROOT_LIFETIME,
+  SERVER_LIFETIME,
+};
+pub use error::CaError;
+pub use index::{Entry, Index, Kind, Reason, Status};
@@ -1,37 +1,153 @@
-use std::fs;
+// cargo run -- init --organization "Crab widgits SE"
+// cargo run -- issue server api.crabs.local --san api.crabs.local --san 127.0.0.1
+// cargo run -- issue client billing --days 7
+// cargo run -- list
+// cargo run -- revoke <serial> --reason key-compromise
+// cargo run -- renew --within-days 30
+// cargo run -- crl
+use std::path::PathBuf;
 
-use rcgen::{date_time_ymd, Certificate, CertificateParams, DistinguishedName, DnType, SanType};
+use clap::{Parser, Subcommand};
+use rcgen_example::{Ca, Kind, LeafRequest, Reason, Status, CRL_LIFETIME};
+use time::Duration;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-  let mut params: CertificateParams = Default::default();
-  params.not_before = date_time_ymd(1975, 01, 01);
-  params.not_after = date_time_ymd(4096, 01, 01);
-  params.distinguished_name = DistinguishedName::new();
-  params
-    .distinguished_name
-    .push(DnType::OrganizationName, "Crab widgits SE");
-  params
-    .distinguished_name
-    .push(DnType::CommonName, "Master Cert");
-  params.subject_alt_names = vec![
-    SanType::DnsName("crabs.crabs".to_string()),
-    SanType::DnsName("localhost".to_string()),
-  ];
+/// The longest lifetime the flags take, a century.
+const MAX_DAYS: i64 = 100 * 365;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+  /// Directory holding the CA's keys, certificates and index
+  #[arg(long, default_value = "ca")]
+  dir: PathBuf,
+  #[command(subcommand)]
+  command: Command,
+}
 
-  let cert = Certificate::from_params(params)?;
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Create the root and intermediate CAs
+  Init {
+    #[arg(long, default_value = "Crab widgits SE")]
+    organization: String,
+  },
+  /// Issue a leaf certificate signed by the intermediate
+  Issue {
+    #[arg(value_enum)]
+    kind: Kind,
+    common_name: String,
+    /// DNS name or IP address, repeatable
+    #[arg(long)]
+    san: Vec<String>,
+    /// Lifetime in days, 90 for servers and 30 for clients by default
+    #[arg(long, value_parser = clap::value_parser!(i64).range(1 ..= MAX_DAYS))]
+    days: Option<i64>,
+  },
+  /// Print the index of issued certificates
+  List,
+  /// Revoke a leaf certificate and publish new CRLs
+  Revoke {
+    serial: String,
+    #[arg(long, value_enum, default_value = "unspecified")]
+    reason: Reason,
+  },
+  /// Renew one certificate, or every one expiring soon
+  Renew {
+    serial: Option<String>,
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(i64).range(0 ..= MAX_DAYS))]
+    within_days: i64,
+  },
+  /// Republish the CRLs, before the previous ones expire
+  Crl {
+    #[arg(
+      long,
+      default_value_t = CRL_LIFETIME.whole_days(),
+      value_parser = clap::value_parser!(i64).range(1 ..= MAX_DAYS),
+    )]
+    days: i64,
+  },
+}
 
-  let pem_serialized = cert.serialize_pem()?;
-  let pem = pem::parse(&pem_serialized)?;
-  let der_serialized = pem.contents();
-  println!("{pem_serialized}");
-  println!("{}", cert.serialize_private_key_pem());
-  std::fs::create_dir_all("certs/")?;
-  fs::write("certs/cert.pem", &pem_serialized.as_bytes())?;
-  fs::write("certs/cert.der", &der_serialized)?;
-  fs::write(
-    "certs/key.pem",
-    &cert.serialize_private_key_pem().as_bytes(),
-  )?;
-  fs::write("certs/key.der", &cert.serialize_private_key_der())?;
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+  let args = Args::parse();
+  match args.command {
+    Command::Init { organization } => {
+      let ca = Ca::init(&args.dir, &organization)?;
+      println!("trust anchor: {}", ca.root_cert_path().display());
+    }
+    Command::Issue {
+      kind,
+      common_name,
+      san,
+      days,
+    } => {
+      let mut ca = Ca::open(&args.dir)?;
+      let entry = ca.issue(&LeafRequest {
+        kind,
+        common_name,
+        sans: san,
+        lifetime: days.map(Duration::days),
+      })?;
+      print_issued(&ca, &entry.serial);
+    }
+    Command::List => {
+      let ca = Ca::open(&args.dir)?;
+      for entry in ca.entries() {
+        let status = match &entry.status {
+          Status::Valid => match &entry.renewed_by {
+            Some(serial) => format!("renewed by {}", serial),
+            None => "valid".to_string(),
+          },
+          Status::Revoked { reason, .. } => format!("revoked ({:?})", reason),
+        };
+        println!(
+          "{}  {:<12}  {:<24}  expires {}  {}",
+          entry.serial,
+          entry.kind,
+          entry.common_name,
+          entry.not_after.date(),
+          status
+        );
+      }
+    }
+    Command::Revoke { serial, reason } => {
+      let mut ca = Ca::open(&args.dir)?;
+      ca.revoke(&serial, reason)?;
+      println!(
+        "revoked {}, CRL: {}",
+        serial,
+        ca.intermediate_crl_path().display()
+      );
+    }
+    Command::Renew {
+      serial,
+      within_days,
+    } => {
+      let mut ca = Ca::open(&args.dir)?;
+      let renewed = match serial {
+        Some(serial) => vec![ca.renew(&serial)?],
+        None => ca.renew_expiring(Duration::days(within_days))?,
+      };
+      if renewed.is_empty() {
+        println!("nothing expires within {} days", within_days);
+      }
+      for entry in renewed {
+        print_issued(&ca, &entry.serial);
+      }
+    }
+    Command::Crl { days } => {
+      let mut ca = Ca::open(&args.dir)?;
+      ca.publish_crl(Duration::days(days))?;
+      println!("{}", ca.root_crl_path().display());
+      println!("{}", ca.intermediate_crl_path().display());
+    }
+  }
   Ok(())
 }
+
+fn print_issued(ca: &Ca, serial: &str) {
+  println!("serial: {}", serial);
+  println!("  certificate: {}", ca.cert_path(serial).display());
+  println!("  chain: {}", ca.chain_path(serial).display());
+  println!("  key: {}", ca.key_path(serial).display());
+}
@@ -0,0 +1,256 @@
+use std::{
+  fs,
+  io::{Read, Write},
+  path::Path,
+  sync::Arc,
+};
+
+use rcgen_example::{Ca, LeafRequest, Reason};
+use rustls::{
+  client::{danger::ServerCertVerifier, WebPkiServerVerifier},
+  crypto::{ring, CryptoProvider},
+  pki_types::{
+    pem::PemObject, CertificateDer, CertificateRevocationListDer, PrivateKeyDer, ServerName,
+    UnixTime,
+  },
+  server::WebPkiClientVerifier,
+  CertificateError, ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection,
+};
+use tempfile::TempDir;
+
+fn provider() -> Arc<CryptoProvider> {
+  Arc::new(ring::default_provider())
+}
+
+fn certs(path: &Path) -> Vec<CertificateDer<'static>> {
+  CertificateDer::pem_file_iter(path)
+    .unwrap()
+    .collect::<Result<_, _>>()
+    .unwrap()
+}
+
+fn key(path: &Path) -> PrivateKeyDer<'static> {
+  PrivateKeyDer::from_pem_file(path).unwrap()
+}
+
+fn roots(ca: &Ca) -> Arc<RootCertStore> {
+  let mut roots = RootCertStore::empty();
+  roots.add_parsable_certificates(certs(&ca.root_cert_path()));
+  Arc::new(roots)
+}
+
+fn crls(ca: &Ca) -> Vec<CertificateRevocationListDer<'static>> {
+  [ca.root_crl_path(), ca.intermediate_crl_path()]
+    .iter()
+    .map(|path| CertificateRevocationListDer::from_pem_file(path).unwrap())
+    .collect()
+}
+
+fn server_verifier(ca: &Ca) -> Arc<WebPkiServerVerifier> {
+  WebPkiServerVerifier::builder_with_provider(roots(ca), provider())
+    .with_crls(crls(ca))
+    .build()
+    .unwrap()
+}
+
+fn verify_server(ca: &Ca, serial: &str, name: &str) -> Result<(), rustls::Error> {
+  let chain = certs(&ca.chain_path(serial));
+  server_verifier(ca)
+    .verify_server_cert(
+      &chain[0],
+      &chain[1 ..],
+      &ServerName::try_from(name.to_string()).unwrap(),
+      &[],
+      UnixTime::now(),
+    )
+    .map(|_| ())
+}
+
+fn verify_client(ca: &Ca, serial: &str) -> Result<(), rustls::Error> {
+  let chain = certs(&ca.chain_path(serial));
+  WebPkiClientVerifier::builder_with_provider(roots(ca), provider())
+    .with_crls(crls(ca))
+    .build()
+    .unwrap()
+    .verify_client_cert(&chain[0], &chain[1 ..], UnixTime::now())
+    .map(|_| ())
+}
+
+#[test]
+fn leaves_verify_against_the_root() {
+  let dir = TempDir::new().unwrap();
+  let mut ca = Ca::init(dir.path(), "Crab widgits SE").unwrap();
+  let server = ca
+    .issue(&LeafRequest::server(
+      "api.crabs.local",
+      &["api.crabs.local", "127.0.0.1"],
+    ))
+    .unwrap();
+  let client = ca.issue(&LeafRequest::client("billing")).unwrap();
+
+  verify_server(&ca, &server.serial, "api.crabs.local").unwrap();
+  verify_server(&ca, &server.serial, "127.0.0.1").unwrap();
+  verify_client(&ca, &client.serial).unwrap();
+
+  assert!(matches!(
+    verify_server(&ca, &server.serial, "other.crabs.local"),
+    Err(rustls::Error::InvalidCertificate(
+      CertificateError::NotValidForNameContext { .. }
+    ))
+  ));
+  // Key usages keep each kind of leaf to its own side of the connection
+  assert!(verify_server(&ca, &client.serial, "billing").is_err());
+  assert!(verify_client(&ca, &server.serial).is_err());
+}
+
+#[test]
+fn a_root_from_another_ca_is_not_trusted() {
+  let dir = TempDir::new().unwrap();
+  let mut ca = Ca::init(&dir.path().join("ours"), "Crab widgits SE").unwrap();
+  let other = Ca::init(&dir.path().join("theirs"), "Crab widgits SE").unwrap();
+  let server = ca
+    .issue(&LeafRequest::server("api.crabs.local", &[]))
+    .unwrap();
+
+  let chain = certs(&ca.chain_path(&server.serial));
+  let result = WebPkiServerVerifier::builder_with_provider(roots(&other), provider())
+    .build()
+    .unwrap()
+    .verify_server_cert(
+      &chain[0],
+      &chain[1 ..],
+      &ServerName::try_from("api.crabs.local").unwrap(),
+      &[],
+      UnixTime::now(),
+    );
+  assert!(matches!(
+    result,
+    Err(rustls::Error::InvalidCertificate(
+      CertificateError::UnknownIssuer | CertificateError::BadSignature
+    ))
+  ));
+}
+
+#[test]
+fn revoked_leaves_are_rejected_once_the_crl_is_published() {
+  let dir = TempDir::new().unwrap();
+  let mut ca = Ca::init(dir.path(), "Crab widgits SE").unwrap();
+  let server = ca
+    .issue(&LeafRequest::server("api.crabs.local", &[]))
+    .unwrap();
+  let client = ca.issue(&LeafRequest::client("billing")).unwrap();
+  let other = ca.issue(&LeafRequest::client("shipping")).unwrap();
+  let stale_crls = crls(&ca);
+
+  ca.revoke(&client.serial, Reason::KeyCompromise).unwrap();
+  assert!(matches!(
+    verify_client(&ca, &client.serial),
+    Err(rustls::Error::InvalidCertificate(CertificateError::Revoked))
+  ));
+  verify_server(&ca, &server.serial, "api.crabs.local").unwrap();
+
+  // A verifier still holding the CRLs from before the revocation accepts the certificate
+  let chain = certs(&ca.chain_path(&client.serial));
+  WebPkiClientVerifier::builder_with_provider(roots(&ca), provider())
+    .with_crls(stale_crls)
+    .build()
+    .unwrap()
+    .verify_client_cert(&chain[0], &chain[1 ..], UnixTime::now())
+    .unwrap();
+
+  // Without the root's CRL the intermediate's revocation status is unknown
+  let chain = certs(&ca.chain_path(&other.serial));
+  let result = WebPkiClientVerifier::builder_with_provider(roots(&ca), provider())
+    .with_crls([CertificateRevocationListDer::from_pem_file(ca.intermediate_crl_path()).unwrap()])
+    .build()
+    .unwrap()
+    .verify_client_cert(&chain[0], &chain[1 ..], UnixTime::now());
+  assert!(matches!(
+    result,
+    Err(rustls::Error::InvalidCertificate(
+      CertificateError::UnknownRevocationStatus
+    ))
+  ));
+}
+
+#[test]
+fn renewed_certificates_verify_alongside_the_old_ones() {
+  let dir = TempDir::new().unwrap();
+  let mut ca = Ca::init(dir.path(), "Crab widgits SE").unwrap();
+  let mut request = LeafRequest::server("api.crabs.local", &[]);
+  request.lifetime = Some(time::Duration::days(1));
+  let old = ca.issue(&request).unwrap();
+
+  let renewed = ca.renew_expiring(time::Duration::days(30)).unwrap();
+  assert_eq!(renewed.len(), 1);
+  assert_ne!(renewed[0].serial, old.serial);
+  assert_eq!(
+    renewed[0].not_after - renewed[0].not_before,
+    old.not_after - old.not_before
+  );
+  assert_ne!(
+    fs::read(ca.key_path(&old.serial)).unwrap(),
+    fs::read(ca.key_path(&renewed[0].serial)).unwrap()
+  );
+  verify_server(&ca, &old.serial, "api.crabs.local").unwrap();
+  verify_server(&ca, &renewed[0].serial, "api.crabs.local").unwrap();
+}
+
+/// Full mutual TLS handshake between in-memory connections, as two services would do it.
+#[test]
+fn services_complete_a_mutual_tls_handshake() {
+  let dir = TempDir::new().unwrap();
+  let mut ca = Ca::init(dir.path(), "Crab widgits SE").unwrap();
+  let server = ca
+    .issue(&LeafRequest::server("api.crabs.local", &[]))
+    .unwrap();
+  let client = ca.issue(&LeafRequest::client("billing")).unwrap();
+
+  let client_verifier = WebPkiClientVerifier::builder_with_provider(roots(&ca), provider())
+    .with_crls(crls(&ca))
+    .build()
+    .unwrap();
+  let server_config = ServerConfig::builder_with_provider(provider())
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_client_cert_verifier(client_verifier)
+    .with_single_cert(
+      certs(&ca.chain_path(&server.serial)),
+      key(&ca.key_path(&server.serial)),
+    )
+    .unwrap();
+  let client_config = ClientConfig::builder_with_provider(provider())
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_webpki_verifier(server_verifier(&ca))
+    .with_client_auth_cert(
+      certs(&ca.chain_path(&client.serial)),
+      key(&ca.key_path(&client.serial)),
+    )
+    .unwrap();
+
+  let mut server_tls = ServerConnection::new(Arc::new(server_config)).unwrap();
+  let mut client_tls = ClientConnection::new(
+    Arc::new(client_config),
+    ServerName::try_from("api.crabs.local").unwrap(),
+  )
+  .unwrap();
+
+  client_tls.writer().write_all(b"hello").unwrap();
+  while client_tls.is_handshaking() || server_tls.is_handshaking() || client_tls.wants_write() {
+    let mut buffer = vec![];
+    client_tls.write_tls(&mut buffer).unwrap();
+    server_tls.read_tls(&mut buffer.as_slice()).unwrap();
+    server_tls.process_new_packets().unwrap();
+    let mut buffer = vec![];
+    server_tls.write_tls(&mut buffer).unwrap();
+    client_tls.read_tls(&mut buffer.as_slice()).unwrap();
+    client_tls.process_new_packets().unwrap();
+  }
+
+  let peer = server_tls.peer_certificates().unwrap();
+  assert_eq!(peer[0], certs(&ca.cert_path(&client.serial))[0]);
+  let mut received = [0; 5];
+  server_tls.reader().read_exact(&mut received).unwrap();
+  assert_eq!(&received, b"hello");
+}
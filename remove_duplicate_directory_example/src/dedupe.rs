@@ -0,0 +1,333 @@
+use std::{
+  fs::{self, File, OpenOptions},
+  io::{self, BufRead, BufReader, Write},
+  os::unix::fs::{MetadataExt, PermissionsExt},
+  path::{Path, PathBuf},
+  time::SystemTime,
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::scan::{hash_file, FileGroup};
+
+/// How a duplicate is replaced once the first path of its group is chosen as the keeper.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+  Hardlink,
+  Reflink,
+  /// Moves the duplicate into the journal's trash directory.
+  Delete,
+}
+
+/// One line of the journal, enough to put `path` back the way it was.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+  pub mode: Mode,
+  pub keep: PathBuf,
+  pub path: PathBuf,
+  pub trash: Option<PathBuf>,
+  pub permissions: u32,
+  pub modified: SystemTime,
+}
+
+/// Append-only JSON lines file, flushed after every action so an interrupted run can be undone.
+pub struct Journal {
+  path: PathBuf,
+  file: File,
+  trashed: usize,
+}
+
+impl Journal {
+  pub fn create(path: &Path) -> io::Result<Journal> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let trashed = read_journal(path)?
+      .iter()
+      .filter(|e| e.trash.is_some())
+      .count();
+    Ok(Journal {
+      path: path.to_path_buf(),
+      file,
+      trashed,
+    })
+  }
+
+  fn trash_dir(journal: &Path) -> PathBuf {
+    let mut name = journal.as_os_str().to_owned();
+    name.push(".trash");
+    PathBuf::from(name)
+  }
+
+  fn next_trash_path(&mut self, path: &Path) -> io::Result<PathBuf> {
+    let dir = Journal::trash_dir(&self.path);
+    fs::create_dir_all(&dir)?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    self.trashed += 1;
+    Ok(dir.join(format!("{}-{}", self.trashed, name)))
+  }
+
+  fn record(&mut self, entry: &JournalEntry) -> io::Result<()> {
+    serde_json::to_writer(&mut self.file, entry)?;
+    self.file.write_all(b"\n")?;
+    self.file.sync_data()
+  }
+}
+
+pub fn read_journal(path: &Path) -> io::Result<Vec<JournalEntry>> {
+  let file = match File::open(path) {
+    Ok(file) => file,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+    Err(e) => return Err(e),
+  };
+  let mut entries = vec![];
+  for line in BufReader::new(file).lines() {
+    let line = line?;
+    if !line.trim().is_empty() {
+      entries.push(serde_json::from_str(&line)?);
+    }
+  }
+  Ok(entries)
+}
+
+/// Replaces every path but the first in each group. Files that changed since the scan, or that are
+/// already hardlinked to the keeper, are left alone, and so is the whole group when the keeper
+/// changed. Returns the number of bytes saved.
+pub fn dedupe(groups: &[FileGroup], mode: Mode, journal: Option<&mut Journal>) -> io::Result<u64> {
+  let mut journal = journal;
+  let mut saved = 0;
+  for group in groups {
+    let Some((keep, duplicates)) = group.paths.split_first() else {
+      continue;
+    };
+    let keep_metadata = fs::metadata(keep)?;
+    // Hashed once a duplicate needs replacing, every copy of it would take the new content.
+    let mut keep_unchanged = None;
+    for path in duplicates {
+      let metadata = fs::metadata(path)?;
+      if metadata.dev() == keep_metadata.dev() && metadata.ino() == keep_metadata.ino() {
+        continue;
+      }
+      let keep_unchanged = match keep_unchanged {
+        Some(unchanged) => unchanged,
+        None => *keep_unchanged.insert(hash_file(keep, None)?.to_hex().as_str() == group.hash),
+      };
+      if !keep_unchanged {
+        eprintln!("skip {}: changed since scan", keep.display());
+        break;
+      }
+      if hash_file(path, None)?.to_hex().as_str() != group.hash {
+        eprintln!("skip {}: changed since scan", path.display());
+        continue;
+      }
+      let Some(journal) = journal.as_deref_mut() else {
+        println!("{:?} {} -> {}", mode, path.display(), keep.display());
+        saved += group.size;
+        continue;
+      };
+
+      let mut entry = JournalEntry {
+        mode,
+        keep: keep.clone(),
+        path: path.clone(),
+        trash: None,
+        permissions: metadata.permissions().mode(),
+        modified: metadata.modified()?,
+      };
+      match mode {
+        Mode::Hardlink => replace_with(path, |tmp| fs::hard_link(keep, tmp))?,
+        Mode::Reflink => replace_with(path, |tmp| reflink_copy::reflink(keep, tmp))?,
+        Mode::Delete => {
+          let trash = journal.next_trash_path(path)?;
+          move_file(path, &trash)?;
+          entry.trash = Some(trash);
+        }
+      }
+      journal.record(&entry)?;
+      saved += group.size;
+    }
+  }
+  Ok(saved)
+}
+
+/// Creates the replacement next to `path` and renames it over, so `path` is never missing.
+fn replace_with<F>(path: &Path, create: F) -> io::Result<()>
+where
+  F: FnOnce(&Path) -> io::Result<()>,
+{
+  let tmp = sibling(path, "dedupe");
+  create(&tmp)?;
+  fs::rename(&tmp, path).inspect_err(|_| {
+    _ = fs::remove_file(&tmp);
+  })
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+  let name = path.file_name().unwrap_or_default().to_string_lossy();
+  path.with_file_name(format!(".{}.{}", name, suffix))
+}
+
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+  if fs::rename(from, to).is_ok() {
+    return Ok(());
+  }
+  // Different filesystem, fall back to copy and remove.
+  fs::copy(from, to)?;
+  fs::remove_file(from)
+}
+
+/// Reverts a journal newest entry first and removes it once everything is restored.
+pub fn undo(journal_path: &Path) -> io::Result<usize> {
+  let entries = read_journal(journal_path)?;
+  for entry in entries.iter().rev() {
+    match &entry.trash {
+      Some(trash) => {
+        if let Some(parent) = entry.path.parent() {
+          fs::create_dir_all(parent)?;
+        }
+        move_file(trash, &entry.path)?;
+      }
+      None => {
+        // Break the link by writing an independent copy of the kept content.
+        let tmp = sibling(&entry.path, "undo");
+        fs::copy(&entry.keep, &tmp)?;
+        fs::rename(&tmp, &entry.path)?;
+      }
+    }
+    fs::set_permissions(&entry.path, fs::Permissions::from_mode(entry.permissions))?;
+    File::options()
+      .write(true)
+      .open(&entry.path)
+      .and_then(|f| f.set_modified(entry.modified))
+      .or_else(|e| match e.kind() {
+        io::ErrorKind::PermissionDenied => Ok(()),
+        _ => Err(e),
+      })?;
+  }
+  fs::remove_file(journal_path)?;
+  let trash = Journal::trash_dir(journal_path);
+  if trash.exists() {
+    fs::remove_dir(trash)?;
+  }
+  Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::scan::{collect_files, find_duplicate_files};
+
+  fn setup() -> (tempfile::TempDir, PathBuf, Vec<FileGroup>) {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("data");
+    fs::create_dir_all(root.join("sub")).unwrap();
+    fs::write(root.join("a.txt"), "hello").unwrap();
+    fs::write(root.join("b.txt"), "hello").unwrap();
+    fs::write(root.join("sub/c.txt"), "hello").unwrap();
+    fs::write(root.join("d.txt"), "world").unwrap();
+    fs::set_permissions(root.join("b.txt"), fs::Permissions::from_mode(0o600)).unwrap();
+    let groups = find_duplicate_files(collect_files(std::slice::from_ref(&root), 1));
+    (dir, root, groups)
+  }
+
+  fn inode(path: &Path) -> u64 {
+    fs::metadata(path).unwrap().ino()
+  }
+
+  #[test]
+  fn hardlink_then_undo() {
+    let (dir, root, groups) = setup();
+    let journal_path = dir.path().join("journal");
+    let mut journal = Journal::create(&journal_path).unwrap();
+
+    assert_eq!(
+      dedupe(&groups, Mode::Hardlink, Some(&mut journal)).unwrap(),
+      10
+    );
+    assert_eq!(inode(&root.join("a.txt")), inode(&root.join("b.txt")));
+    assert_eq!(inode(&root.join("a.txt")), inode(&root.join("sub/c.txt")));
+    assert_eq!(read_journal(&journal_path).unwrap().len(), 2);
+
+    // A second run finds nothing left to link.
+    assert_eq!(
+      dedupe(&groups, Mode::Hardlink, Some(&mut journal)).unwrap(),
+      0
+    );
+
+    assert_eq!(undo(&journal_path).unwrap(), 2);
+    assert_ne!(inode(&root.join("a.txt")), inode(&root.join("b.txt")));
+    assert_eq!(fs::read_to_string(root.join("b.txt")).unwrap(), "hello");
+    let mode = fs::metadata(root.join("b.txt"))
+      .unwrap()
+      .permissions()
+      .mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert!(!journal_path.exists());
+  }
+
+  #[test]
+  fn delete_then_undo() {
+    let (dir, root, groups) = setup();
+    let journal_path = dir.path().join("journal");
+    let mut journal = Journal::create(&journal_path).unwrap();
+
+    dedupe(&groups, Mode::Delete, Some(&mut journal)).unwrap();
+    assert!(root.join("a.txt").exists());
+    assert!(!root.join("b.txt").exists());
+    assert!(!root.join("sub/c.txt").exists());
+    assert_eq!(
+      fs::read_dir(dir.path().join("journal.trash"))
+        .unwrap()
+        .count(),
+      2
+    );
+
+    undo(&journal_path).unwrap();
+    assert_eq!(fs::read_to_string(root.join("b.txt")).unwrap(), "hello");
+    assert_eq!(fs::read_to_string(root.join("sub/c.txt")).unwrap(), "hello");
+    assert!(!dir.path().join("journal.trash").exists());
+  }
+
+  #[test]
+  fn dry_run_and_changed_files_touch_nothing() {
+    let (dir, root, groups) = setup();
+    assert_eq!(dedupe(&groups, Mode::Delete, None).unwrap(), 10);
+    assert!(root.join("b.txt").exists());
+
+    fs::write(root.join("b.txt"), "HELLO").unwrap();
+    let journal_path = dir.path().join("journal");
+    let mut journal = Journal::create(&journal_path).unwrap();
+    assert_eq!(
+      dedupe(&groups, Mode::Delete, Some(&mut journal)).unwrap(),
+      5
+    );
+    assert_eq!(fs::read_to_string(root.join("b.txt")).unwrap(), "HELLO");
+
+    // Nothing is replaced by a keeper that changed.
+    let (dir, root, groups) = setup();
+    fs::write(root.join("a.txt"), "HELLO").unwrap();
+    let journal_path = dir.path().join("journal");
+    let mut journal = Journal::create(&journal_path).unwrap();
+    assert_eq!(
+      dedupe(&groups, Mode::Hardlink, Some(&mut journal)).unwrap(),
+      0
+    );
+    assert_eq!(fs::read_to_string(root.join("b.txt")).unwrap(), "hello");
+    assert!(read_journal(&journal_path).unwrap().is_empty());
+  }
+
+  #[test]
+  fn reflink_replaces_or_fails_cleanly() {
+    let (dir, root, groups) = setup();
+    let journal_path = dir.path().join("journal");
+    let mut journal = Journal::create(&journal_path).unwrap();
+
+    // Not every filesystem supports reflinks (tmpfs, ext4), then nothing may be touched.
+    match dedupe(&groups, Mode::Reflink, Some(&mut journal)) {
+      Ok(saved) => assert_eq!(saved, 10),
+      Err(_) => assert!(read_journal(&journal_path).unwrap().is_empty()),
+    }
+    assert_eq!(fs::read_to_string(root.join("b.txt")).unwrap(), "hello");
+    assert_eq!(fs::read_dir(&root).unwrap().count(), 4);
+  }
+}
@@ -1,22 +1,29 @@
 // use error_chain::error_chain;
 use std::{
   fs,
-  io::Error,
+  io::{Error, ErrorKind},
   path::{Path, PathBuf},
 };
 
-// when TWO_DIRECTORY_FLAG is true
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+
+mod dedupe;
+mod scan;
+mod tree;
+
+// cargo run -- scan ~/Downloads ~/Documents
+// cargo run -- dedupe ~/Downloads --mode hardlink --journal /tmp/dedupe.journal
+// cargo run -- undo --journal /tmp/dedupe.journal
+
+// when --depth 2
 // mkdir -p /tmp/a/b/c/d
 // touch /tmp/a/b/c/d/file.txt
 
-// when TWO_DIRECTORY_FLAG is false
+// when --depth 1
 // mkdir -p /tmp/a/b/c
 // touch /tmp/a/b/c/file.txt
-const TWO_DIRECTORY_FLAG: bool = false;
-// change the directory you want to modify
-const FILE_PATH: &[u8] = b"/tmp/a/b/";
-
-const WRITE_FLAG: bool = false;
+// cargo run -- flatten /tmp/a/b/ --write
 
 // error_chain! {
 //     foreign_links {
@@ -25,12 +32,115 @@ const WRITE_FLAG: bool = false;
 //     }
 // }
 
+/// Find and remove duplicate files and directory trees
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Print a JSON report of duplicate files and directory trees
+  Scan {
+    #[arg(required = true)]
+    roots: Vec<String>,
+    /// Ignore files smaller than this many bytes
+    #[arg(long, default_value_t = 1)]
+    min_size: u64,
+  },
+  /// Replace duplicate files, keeping the first path of each group
+  Dedupe {
+    #[arg(required = true)]
+    roots: Vec<String>,
+    #[arg(long, value_enum)]
+    mode: dedupe::Mode,
+    /// Where to record every change so it can be undone
+    #[arg(long, required_unless_present = "dry_run")]
+    journal: Option<PathBuf>,
+    /// Print what would be done without touching anything
+    #[arg(long)]
+    dry_run: bool,
+    #[arg(long, default_value_t = 1)]
+    min_size: u64,
+  },
+  /// Restore everything recorded in a journal
+  Undo {
+    #[arg(long)]
+    journal: PathBuf,
+  },
+  /// Move the content of directories holding exactly one subdirectory up one level
+  Flatten {
+    dir: String,
+    /// 2 checks the subdirectories of each subdirectory
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1 ..= 2))]
+    depth: u8,
+    /// Without it the moves are only printed
+    #[arg(long)]
+    write: bool,
+  },
+}
+
+#[derive(Serialize)]
+struct Report {
+  wasted_bytes: u64,
+  files: Vec<scan::FileGroup>,
+  dirs: Vec<tree::DirGroup>,
+}
+
 fn main() -> Result<(), Error> {
-  let dir_name =
-    String::from_utf8(tilde_expand::tilde_expand(FILE_PATH)).expect("Invalid UTF-8 sequence");
+  match Args::parse().command {
+    Command::Scan { roots, min_size } => {
+      let roots = expand(&roots)?;
+      let files = scan::find_duplicate_files(scan::collect_files(&roots, min_size));
+      let report = Report {
+        wasted_bytes: files.iter().map(|g| g.wasted()).sum(),
+        files,
+        dirs: tree::find_duplicate_dirs(&roots)?,
+      };
+      println!("{}", serde_json::to_string_pretty(&report)?);
+    }
+    Command::Dedupe {
+      roots,
+      mode,
+      journal,
+      dry_run,
+      min_size,
+    } => {
+      let groups = scan::find_duplicate_files(scan::collect_files(&expand(&roots)?, min_size));
+      let mut journal = match (dry_run, journal) {
+        (false, Some(path)) => Some(dedupe::Journal::create(&path)?),
+        _ => None,
+      };
+      let saved = dedupe::dedupe(&groups, mode, journal.as_mut())?;
+      println!("saved {} bytes", saved);
+    }
+    Command::Undo { journal } => {
+      let count = dedupe::undo(&journal)?;
+      println!("restored {} files", count);
+    }
+    Command::Flatten { dir, depth, write } => flatten(&expand(&[dir])?[0], depth, write)?,
+  }
+  Ok(())
+}
 
-  let current_dir = PathBuf::from(dir_name);
+fn expand(paths: &[String]) -> Result<Vec<PathBuf>, Error> {
+  paths
+    .iter()
+    .map(|p| {
+      let expanded = tilde_expand::tilde_expand(p.as_bytes());
+      String::from_utf8(expanded).map(PathBuf::from).map_err(|_| {
+        Error::new(
+          ErrorKind::InvalidData,
+          format!("{}: invalid UTF-8 once expanded", p),
+        )
+      })
+    })
+    .collect()
+}
 
+fn flatten(current_dir: &Path, depth: u8, write: bool) -> Result<(), Error> {
   for entry in fs::read_dir(current_dir)? {
     let first_entry = entry?;
     let first_path = first_entry.path();
@@ -39,7 +149,7 @@ fn main() -> Result<(), Error> {
 
     if first_metadata.is_dir() {
       // println!("path : {:?}", path);
-      if TWO_DIRECTORY_FLAG {
+      if depth == 2 {
         for second_entry in fs::read_dir(first_path)? {
           let second_entry = second_entry?;
           let second_path = second_entry.path();
@@ -47,11 +157,11 @@ fn main() -> Result<(), Error> {
           let second_metadata = fs::metadata(&second_path)?;
 
           if second_metadata.is_dir() {
-            _ = check_path(second_path);
+            _ = check_path(second_path, write);
           }
         }
       } else {
-        _ = check_path(first_path);
+        _ = check_path(first_path, write);
       }
     }
   }
@@ -59,7 +169,7 @@ fn main() -> Result<(), Error> {
   Ok(())
 }
 
-fn check_path(first_path: PathBuf) -> Result<(), Error> {
+fn check_path(first_path: PathBuf, write: bool) -> Result<(), Error> {
   let sub_paths = fs::read_dir(first_path.clone())?;
 
   let mut sub_dir_count = 0;
@@ -79,14 +189,14 @@ fn check_path(first_path: PathBuf) -> Result<(), Error> {
     //     sub_path.display(),
     //     sub_dir_name.display()
     // );
-    move_directory(&sub_dir_name, &first_path)?
+    move_directory(&sub_dir_name, &first_path, write)?
   }
   Ok(())
 }
 
-fn move_directory(source: &PathBuf, destination: &Path) -> std::io::Result<()> {
+fn move_directory(source: &PathBuf, destination: &Path, write: bool) -> std::io::Result<()> {
   // Read the entries in the source directory
-  if WRITE_FLAG {
+  if write {
     for entry in fs::read_dir(source)? {
       let entry = entry?;
       let entry_path = entry.path();
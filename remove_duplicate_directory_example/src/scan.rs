@@ -0,0 +1,165 @@
+use std::{
+  collections::HashMap,
+  fs::File,
+  io::{self, Read},
+  path::{Path, PathBuf},
+};
+
+use rayon::prelude::*;
+use serde::Serialize;
+use walkdir::WalkDir;
+
+/// Bytes hashed from the start of each file before deciding whether a full hash is worth it.
+const PARTIAL_HASH_LEN: u64 = 4096;
+
+/// Files with identical content.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct FileGroup {
+  pub hash: String,
+  pub size: u64,
+  pub paths: Vec<PathBuf>,
+}
+
+impl FileGroup {
+  /// Bytes that would be freed by keeping only one copy.
+  pub fn wasted(&self) -> u64 {
+    self.size * (self.paths.len() as u64 - 1)
+  }
+}
+
+/// Regular files under `roots`, skipping symlinks and anything smaller than `min_size`.
+pub fn collect_files(roots: &[PathBuf], min_size: u64) -> Vec<(PathBuf, u64)> {
+  let mut files = vec![];
+  for root in roots {
+    for entry in WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
+      if !entry.file_type().is_file() {
+        continue;
+      }
+      if let Ok(metadata) = entry.metadata() {
+        if metadata.len() >= min_size {
+          files.push((entry.into_path(), metadata.len()));
+        }
+      }
+    }
+  }
+  files.sort();
+  files.dedup();
+  files
+}
+
+/// Narrows candidates down by size, then by a hash of the first few KiB, then by a full BLAKE3
+/// hash. Each hashing pass runs in parallel.
+pub fn find_duplicate_files(files: Vec<(PathBuf, u64)>) -> Vec<FileGroup> {
+  let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+  for (path, size) in files {
+    by_size.entry(size).or_default().push(path);
+  }
+  let candidates: Vec<(u64, PathBuf)> = by_size
+    .into_iter()
+    .filter(|(_, paths)| paths.len() > 1)
+    .flat_map(|(size, paths)| paths.into_iter().map(move |p| (size, p)))
+    .collect();
+
+  let partial = group_by_hash(candidates, |path, size| {
+    hash_file(path, Some(PARTIAL_HASH_LEN.min(size)))
+  });
+  let candidates: Vec<(u64, PathBuf)> = partial
+    .into_values()
+    .filter(|paths| paths.len() > 1)
+    .flatten()
+    .collect();
+
+  let full = group_by_hash(candidates, |path, _| hash_file(path, None));
+  let mut groups: Vec<FileGroup> = full
+    .into_iter()
+    .filter(|(_, paths)| paths.len() > 1)
+    .map(|((size, hash), paths)| {
+      let mut paths: Vec<PathBuf> = paths.into_iter().map(|(_, p)| p).collect();
+      paths.sort();
+      FileGroup {
+        hash: hash.to_hex().to_string(),
+        size,
+        paths,
+      }
+    })
+    .collect();
+  groups.sort_by(|a, b| b.wasted().cmp(&a.wasted()).then(a.paths.cmp(&b.paths)));
+  groups
+}
+
+type HashKey = (u64, blake3::Hash);
+
+fn group_by_hash<F>(
+  candidates: Vec<(u64, PathBuf)>,
+  hash: F,
+) -> HashMap<HashKey, Vec<(u64, PathBuf)>>
+where
+  F: Fn(&Path, u64) -> io::Result<blake3::Hash> + Sync,
+{
+  let hashed: Vec<(HashKey, (u64, PathBuf))> = candidates
+    .into_par_iter()
+    .filter_map(|(size, path)| match hash(&path, size) {
+      Ok(h) => Some(((size, h), (size, path))),
+      Err(e) => {
+        eprintln!("skip {}: {}", path.display(), e);
+        None
+      }
+    })
+    .collect();
+  let mut groups: HashMap<HashKey, Vec<(u64, PathBuf)>> = HashMap::new();
+  for (key, value) in hashed {
+    groups.entry(key).or_default().push(value);
+  }
+  groups
+}
+
+/// Hashes the whole file, or only its first `limit` bytes.
+pub fn hash_file(path: &Path, limit: Option<u64>) -> io::Result<blake3::Hash> {
+  let file = File::open(path)?;
+  let mut hasher = blake3::Hasher::new();
+  match limit {
+    Some(limit) => io::copy(&mut file.take(limit), &mut hasher)?,
+    None => io::copy(&mut io::BufReader::new(file), &mut hasher)?,
+  };
+  Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use super::*;
+
+  #[test]
+  fn groups_identical_files_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir(root.join("sub")).unwrap();
+    let big: Vec<u8> = (0 .. 10_000u32).map(|i| (i % 251) as u8).collect();
+    let mut big_other = big.clone();
+    *big_other.last_mut().unwrap() ^= 1;
+
+    fs::write(root.join("a.txt"), "same").unwrap();
+    fs::write(root.join("sub/b.txt"), "same").unwrap();
+    fs::write(root.join("c.txt"), "diff").unwrap();
+    fs::write(root.join("big1"), &big).unwrap();
+    fs::write(root.join("sub/big2"), &big).unwrap();
+    // Same size and same first 4 KiB, only the full hash tells them apart.
+    fs::write(root.join("big3"), &big_other).unwrap();
+    fs::write(root.join("empty1"), "").unwrap();
+    fs::write(root.join("empty2"), "").unwrap();
+
+    let groups = find_duplicate_files(collect_files(&[root.to_path_buf()], 1));
+    assert_eq!(groups.len(), 2);
+    assert_eq!(
+      groups[0].paths,
+      vec![root.join("big1"), root.join("sub/big2")]
+    );
+    assert_eq!(groups[0].wasted(), 10_000);
+    assert_eq!(
+      groups[1].paths,
+      vec![root.join("a.txt"), root.join("sub/b.txt")]
+    );
+    assert_eq!(groups[1].hash, blake3::hash(b"same").to_hex().to_string());
+  }
+}
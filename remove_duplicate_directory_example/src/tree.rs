@@ -0,0 +1,149 @@
+use std::{
+  collections::{HashMap, HashSet},
+  fs, io,
+  path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::scan::hash_file;
+
+/// Directories whose whole trees (names, layout and file contents) are identical.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct DirGroup {
+  pub hash: String,
+  pub size: u64,
+  pub files: usize,
+  pub paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct DirHash {
+  hash: blake3::Hash,
+  size: u64,
+  files: usize,
+}
+
+/// Hashes every directory as a Merkle node over its sorted children and returns the duplicated
+/// trees. A group is left out when all of its members sit inside an already reported duplicate.
+pub fn find_duplicate_dirs(roots: &[PathBuf]) -> io::Result<Vec<DirGroup>> {
+  let mut hashes = HashMap::new();
+  for root in roots {
+    hash_dir(root, &mut hashes)?;
+  }
+
+  let mut by_hash: HashMap<blake3::Hash, Vec<PathBuf>> = HashMap::new();
+  for (path, dir) in &hashes {
+    if dir.files > 0 {
+      by_hash.entry(dir.hash).or_default().push(path.clone());
+    }
+  }
+  by_hash.retain(|_, paths| paths.len() > 1);
+  let duplicated: HashSet<&PathBuf> = by_hash.values().flatten().collect();
+
+  let mut groups: Vec<DirGroup> = by_hash
+    .iter()
+    .filter(|(_, paths)| {
+      !paths.iter().all(|p| {
+        p.parent()
+          .map(|parent| duplicated.contains(&parent.to_path_buf()))
+          .unwrap_or(false)
+      })
+    })
+    .map(|(hash, paths)| {
+      let mut paths = paths.clone();
+      paths.sort();
+      let dir = hashes[&paths[0]];
+      DirGroup {
+        hash: hash.to_hex().to_string(),
+        size: dir.size,
+        files: dir.files,
+        paths,
+      }
+    })
+    .collect();
+  groups.sort_by(|a, b| b.size.cmp(&a.size).then(a.paths.cmp(&b.paths)));
+  Ok(groups)
+}
+
+fn hash_dir(dir: &Path, hashes: &mut HashMap<PathBuf, DirHash>) -> io::Result<DirHash> {
+  let mut entries: Vec<fs::DirEntry> = fs::read_dir(dir)?.collect::<io::Result<_>>()?;
+  entries.sort_by_key(|e| e.file_name());
+
+  let mut hasher = blake3::Hasher::new();
+  let mut size = 0;
+  let mut files = 0;
+  for entry in entries {
+    let path = entry.path();
+    let file_type = entry.file_type()?;
+    let (kind, hash) = if file_type.is_dir() {
+      let child = hash_dir(&path, hashes)?;
+      size += child.size;
+      files += child.files;
+      (b'd', child.hash)
+    } else if file_type.is_symlink() {
+      let target = fs::read_link(&path)?;
+      (b'l', blake3::hash(target.as_os_str().as_encoded_bytes()))
+    } else {
+      size += entry.metadata()?.len();
+      files += 1;
+      (b'f', hash_file(&path, None)?)
+    };
+    let name = entry.file_name();
+    let name = name.as_encoded_bytes();
+    hasher.update(&[kind]);
+    hasher.update(&(name.len() as u64).to_le_bytes());
+    hasher.update(name);
+    hasher.update(hash.as_bytes());
+  }
+
+  let dir_hash = DirHash {
+    hash: hasher.finalize(),
+    size,
+    files,
+  };
+  hashes.insert(dir.to_path_buf(), dir_hash);
+  Ok(dir_hash)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn make_tree(root: &Path, content: &str) {
+    fs::create_dir_all(root.join("c/d")).unwrap();
+    fs::write(root.join("a.txt"), "a").unwrap();
+    fs::write(root.join("c/d/file.txt"), content).unwrap();
+  }
+
+  #[test]
+  fn reports_the_outermost_duplicate_trees() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    make_tree(&root.join("x"), "same");
+    make_tree(&root.join("y"), "same");
+    make_tree(&root.join("z"), "other");
+    fs::create_dir(root.join("empty1")).unwrap();
+    fs::create_dir(root.join("empty2")).unwrap();
+
+    let groups = find_duplicate_dirs(&[root.to_path_buf()]).unwrap();
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0].paths, vec![root.join("x"), root.join("y")]);
+    assert_eq!(groups[0].files, 2);
+    assert_eq!(groups[0].size, 5);
+  }
+
+  #[test]
+  fn names_are_part_of_the_hash() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir_all(root.join("x")).unwrap();
+    fs::create_dir_all(root.join("y")).unwrap();
+    fs::write(root.join("x/one"), "data").unwrap();
+    fs::write(root.join("y/two"), "data").unwrap();
+
+    assert!(find_duplicate_dirs(&[root.to_path_buf()])
+      .unwrap()
+      .is_empty());
+  }
+}
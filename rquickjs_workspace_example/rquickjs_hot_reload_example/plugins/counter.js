@@ -0,0 +1,24 @@
+export const manifest = {
+  name: "counter",
+  version: "1.0.0",
+  capabilities: ["kv", "timers"],
+};
+
+// Lost on reload, unlike what goes in host.kv
+let calls = 0;
+
+function tick() {
+  const ticks = (host.kv.get("ticks") ?? 0) + 1;
+  host.kv.set("ticks", ticks);
+  print(`tick ${ticks}`);
+  host.setTimeout(tick, 5000);
+}
+
+export function init() {
+  host.setTimeout(tick, 5000);
+}
+
+export function count() {
+  calls += 1;
+  return { ticks: host.kv.get("ticks") ?? 0, calls };
+}
@@ -0,0 +1,10 @@
+export const manifest = {
+  name: "status",
+  version: "1.0.0",
+  capabilities: ["fetch"],
+};
+
+export function check(url) {
+  const response = host.fetch(url);
+  return { url, status: response.status, bytes: response.body.length };
+}
@@ -1,6 +0,0 @@
-// export const n = 456;
-// export const s = "efg";
-// export const f = (a, b) => (a * b);
-
-// console.log("hello world");
-2 + 5;
@@ -0,0 +1,288 @@
+//! The `host` object plugins see. Rust exposes a few string-typed functions on a hidden object
+//! and [`PRELUDE`] wraps them into the JavaScript API, so values cross the boundary as JSON.
+
+use std::{
+  cell::RefCell,
+  collections::{BTreeMap, HashMap},
+  rc::Rc,
+  time::{Duration, Instant},
+};
+
+use rquickjs::{Ctx, Exception, Function, Object};
+use serde::Serialize;
+use url::Url;
+
+use crate::{
+  limits::Deadline,
+  manifest::{Capability, Manifest},
+};
+
+/// Timers a plugin may have pending at once.
+const MAX_TIMERS: usize = 1024;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Takes the native functions and returns the function the host calls to fire a timer.
+const PRELUDE: &str = r#"
+(raw) => {
+  const timers = new Map();
+  globalThis.host = Object.freeze({
+    kv: Object.freeze({
+      get(key) {
+        const value = raw.kvGet(String(key));
+        return value === undefined ? undefined : JSON.parse(value);
+      },
+      set(key, value) {
+        raw.kvSet(String(key), JSON.stringify(value));
+      },
+      delete(key) {
+        return raw.kvDelete(String(key));
+      },
+    }),
+    fetch(url) {
+      return JSON.parse(raw.fetch(String(url)));
+    },
+    setTimeout(callback, ms) {
+      const id = raw.setTimeout(Number(ms) || 0);
+      timers.set(id, callback);
+      return id;
+    },
+    clearTimeout(id) {
+      raw.clearTimeout(id);
+      timers.delete(id);
+    },
+  });
+  globalThis.print = (message) => raw.print(String(message));
+  return (id) => {
+    const callback = timers.get(id);
+    timers.delete(id);
+    if (callback) callback();
+  };
+}
+"#;
+
+/// Values plugins stored with `host.kv`, by plugin name then key, as JSON.
+pub type KvStore = HashMap<String, BTreeMap<String, String>>;
+
+/// Hosts `host.fetch` may reach: `example.com` allows any port, `localhost:8080` only that one.
+#[derive(Debug, Clone, Default)]
+pub struct FetchPolicy {
+  pub allowlist: Vec<String>,
+}
+
+impl FetchPolicy {
+  pub fn allows(&self, url: &Url) -> bool {
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+      return false;
+    };
+    let with_port = format!("{}:{}", host, port);
+    matches!(url.scheme(), "http" | "https")
+      && self
+        .allowlist
+        .iter()
+        .any(|allowed| *allowed == host || *allowed == with_port)
+  }
+}
+
+#[derive(Debug, Serialize)]
+struct FetchResponse {
+  status: u16,
+  body: String,
+}
+
+/// What the native functions of one plugin share with the host.
+pub(crate) struct Env {
+  pub(crate) name: RefCell<String>,
+  /// Empty until the manifest has been read.
+  pub(crate) manifest: RefCell<Option<Manifest>>,
+  /// What the manifest asks for and the host grants, empty until the manifest has been read so
+  /// top-level module code has no capabilities.
+  pub(crate) granted: RefCell<Vec<Capability>>,
+  pub(crate) kv: Rc<RefCell<KvStore>>,
+  pub(crate) fetch: Rc<FetchPolicy>,
+  pub(crate) deadline: Rc<Deadline>,
+  pub(crate) timers: RefCell<Timers>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Timers {
+  next_id: u32,
+  due: BTreeMap<u32, Instant>,
+}
+
+impl Timers {
+  /// Removes and returns the timers due at `now`, earliest first.
+  pub(crate) fn take_due(&mut self, now: Instant) -> Vec<u32> {
+    let mut due: Vec<(Instant, u32)> = self
+      .due
+      .iter()
+      .filter(|(_, at)| **at <= now)
+      .map(|(id, at)| (*at, *id))
+      .collect();
+    due.sort();
+    for (_, id) in &due {
+      self.due.remove(id);
+    }
+    due.into_iter().map(|(_, id)| id).collect()
+  }
+
+  pub(crate) fn next_due(&self) -> Option<Instant> {
+    self.due.values().min().copied()
+  }
+}
+
+impl Env {
+  fn require(&self, ctx: &Ctx<'_>, capability: Capability) -> rquickjs::Result<()> {
+    if self.granted.borrow().contains(&capability) {
+      Ok(())
+    } else {
+      let message = format!(
+        "plugin {:?} lacks the {:?} capability",
+        self.name.borrow(),
+        capability
+      );
+      Err(Exception::throw_message(ctx, &message))
+    }
+  }
+
+  fn fetch(&self, ctx: &Ctx<'_>, url: &str) -> rquickjs::Result<String> {
+    self.require(ctx, Capability::Fetch)?;
+    let parsed = Url::parse(url)
+      .map_err(|e| Exception::throw_message(ctx, &format!("invalid url {:?}: {}", url, e)))?;
+    if !self.fetch.allows(&parsed) {
+      return Err(Exception::throw_message(
+        ctx,
+        &format!("fetching {:?} is not allowed", url),
+      ));
+    }
+    // Redirects could leave the allowlist
+    let agent = ureq::AgentBuilder::new()
+      .timeout(FETCH_TIMEOUT)
+      .redirects(0)
+      .build();
+    let started = Instant::now();
+    let response = match agent.get(url).call() {
+      Ok(response) | Err(ureq::Error::Status(_, response)) => Ok(response),
+      Err(e) => Err(e),
+    };
+    // Waiting on the network does not count against the plugin's CPU time
+    self.deadline.extend(started.elapsed());
+    let response =
+      response.map_err(|e| Exception::throw_message(ctx, &format!("fetching {:?}: {}", url, e)))?;
+    let status = response.status();
+    let body = response
+      .into_string()
+      .map_err(|e| Exception::throw_message(ctx, &format!("reading {:?}: {}", url, e)))?;
+    Ok(serde_json::to_string(&FetchResponse { status, body }).unwrap())
+  }
+}
+
+/// Installs `host` and `print` and returns the timer trampoline.
+pub(crate) fn install<'js>(ctx: &Ctx<'js>, env: &Rc<Env>) -> rquickjs::Result<Function<'js>> {
+  let raw = Object::new(ctx.clone())?;
+
+  let e = env.clone();
+  raw.set(
+    "kvGet",
+    Function::new(ctx.clone(), move |ctx: Ctx<'_>, key: String| {
+      e.require(&ctx, Capability::Kv)?;
+      let kv = e.kv.borrow();
+      let value = kv
+        .get(&*e.name.borrow())
+        .and_then(|values| values.get(&key));
+      Ok::<_, rquickjs::Error>(value.cloned())
+    })?,
+  )?;
+  let e = env.clone();
+  raw.set(
+    "kvSet",
+    Function::new(
+      ctx.clone(),
+      move |ctx: Ctx<'_>, key: String, value: String| {
+        e.require(&ctx, Capability::Kv)?;
+        let name = e.name.borrow().clone();
+        e.kv
+          .borrow_mut()
+          .entry(name)
+          .or_default()
+          .insert(key, value);
+        Ok::<_, rquickjs::Error>(())
+      },
+    )?,
+  )?;
+  let e = env.clone();
+  raw.set(
+    "kvDelete",
+    Function::new(ctx.clone(), move |ctx: Ctx<'_>, key: String| {
+      e.require(&ctx, Capability::Kv)?;
+      let mut kv = e.kv.borrow_mut();
+      let removed = kv
+        .get_mut(&*e.name.borrow())
+        .is_some_and(|values| values.remove(&key).is_some());
+      Ok::<_, rquickjs::Error>(removed)
+    })?,
+  )?;
+  let e = env.clone();
+  raw.set(
+    "fetch",
+    Function::new(ctx.clone(), move |ctx: Ctx<'_>, url: String| {
+      e.fetch(&ctx, &url)
+    })?,
+  )?;
+  let e = env.clone();
+  raw.set(
+    "setTimeout",
+    Function::new(ctx.clone(), move |ctx: Ctx<'_>, ms: f64| {
+      e.require(&ctx, Capability::Timers)?;
+      let mut timers = e.timers.borrow_mut();
+      if timers.due.len() >= MAX_TIMERS {
+        return Err(Exception::throw_message(&ctx, "too many pending timers"));
+      }
+      let at = Duration::try_from_secs_f64(ms.max(0.0) / 1000.0)
+        .ok()
+        .and_then(|delay| Instant::now().checked_add(delay))
+        .ok_or_else(|| {
+          Exception::throw_range(&ctx, &format!("setTimeout delay {} ms is out of range", ms))
+        })?;
+      timers.next_id += 1;
+      let id = timers.next_id;
+      timers.due.insert(id, at);
+      Ok(id)
+    })?,
+  )?;
+  let e = env.clone();
+  raw.set(
+    "clearTimeout",
+    Function::new(ctx.clone(), move |id: u32| {
+      e.timers.borrow_mut().due.remove(&id);
+    })?,
+  )?;
+  let e = env.clone();
+  raw.set(
+    "print",
+    Function::new(ctx.clone(), move |message: String| {
+      println!("[{}] {}", e.name.borrow(), message);
+    })?,
+  )?;
+
+  let prelude: Function = ctx.eval(PRELUDE)?;
+  prelude.call((raw,))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn fetch_allowlist_matches_host_and_port() {
+    let policy = FetchPolicy {
+      allowlist: vec!["example.com".to_string(), "127.0.0.1:8080".to_string()],
+    };
+    let allows = |url: &str| policy.allows(&Url::parse(url).unwrap());
+    assert!(allows("https://example.com/a"));
+    assert!(allows("http://example.com:1234/"));
+    assert!(allows("http://127.0.0.1:8080/x"));
+    assert!(!allows("http://127.0.0.1:8081/x"));
+    assert!(!allows("http://example.com.evil.org/"));
+    assert!(!allows("file:///etc/passwd"));
+  }
+}
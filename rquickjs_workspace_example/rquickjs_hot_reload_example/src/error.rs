@@ -0,0 +1,40 @@
+use std::{fmt, io, path::PathBuf};
+
+use thiserror::Error;
+
+/// Why a plugin was stopped by the host rather than failing by itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KillReason {
+  CpuTime,
+  Memory,
+}
+
+impl fmt::Display for KillReason {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      KillReason::CpuTime => write!(f, "ran past its CPU time limit"),
+      KillReason::Memory => write!(f, "ran out of memory"),
+    }
+  }
+}
+
+#[derive(Debug, Error)]
+pub enum PluginError {
+  #[error("could not read {path:?}: {source}")]
+  Io { path: PathBuf, source: io::Error },
+  #[error("invalid manifest in {path:?}: {message}")]
+  Manifest { path: PathBuf, message: String },
+  #[error("a plugin named {name:?} is already loaded from {path:?}")]
+  Duplicate { name: String, path: PathBuf },
+  #[error("no plugin named {0:?}")]
+  Unknown(String),
+  #[error("plugin {plugin:?} has no function export {export:?}")]
+  NoSuchExport { plugin: String, export: String },
+  #[error("plugin {plugin:?} threw: {message}")]
+  Exception { plugin: String, message: String },
+  /// The plugin was unloaded; loading it again starts it from scratch.
+  #[error("plugin {plugin:?} was killed: it {reason}")]
+  Killed { plugin: String, reason: KillReason },
+  #[error("quickjs: {0}")]
+  Runtime(#[from] rquickjs::Error),
+}
@@ -0,0 +1,168 @@
+use std::{
+  cell::RefCell,
+  collections::{BTreeMap, HashMap},
+  fs,
+  path::{Path, PathBuf},
+  rc::Rc,
+  time::Instant,
+};
+
+use crate::{
+  api::{FetchPolicy, KvStore},
+  error::PluginError,
+  limits::Limits,
+  manifest::{Capability, Manifest},
+  plugin::Plugin,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct HostConfig {
+  pub limits: Limits,
+  pub fetch: FetchPolicy,
+  /// The capabilities each plugin may have, by its name. A plugin gets those it asks for in its
+  /// manifest and is granted here.
+  pub grants: HashMap<String, Vec<Capability>>,
+}
+
+/// Loads plugin modules and calls into them. A plugin that throws only fails that call; one that
+/// hits a limit is killed and unloaded. Either way the other plugins are left alone.
+pub struct PluginHost {
+  config: HostConfig,
+  fetch: Rc<FetchPolicy>,
+  kv: Rc<RefCell<KvStore>>,
+  plugins: BTreeMap<String, Plugin>,
+}
+
+impl PluginHost {
+  pub fn new(config: HostConfig) -> PluginHost {
+    PluginHost {
+      fetch: Rc::new(config.fetch.clone()),
+      config,
+      kv: Rc::default(),
+      plugins: BTreeMap::new(),
+    }
+  }
+
+  /// Loads every `.js` file of `dir`, in name order.
+  pub fn load_dir(&mut self, dir: &Path) -> Vec<(PathBuf, Result<Manifest, PluginError>)> {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(dir) {
+      Ok(entries) => entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| is_plugin(path))
+        .collect(),
+      Err(source) => {
+        let error = PluginError::Io {
+          path: dir.to_path_buf(),
+          source,
+        };
+        return vec![(dir.to_path_buf(), Err(error))];
+      }
+    };
+    paths.sort();
+    paths
+      .into_iter()
+      .map(|path| {
+        let result = self.load(&path);
+        (path, result)
+      })
+      .collect()
+  }
+
+  /// Loads `path`, replacing the plugin previously loaded from it only once the new version has
+  /// loaded: a broken edit leaves the old version running.
+  pub fn load(&mut self, path: &Path) -> Result<Manifest, PluginError> {
+    let source = fs::read_to_string(path).map_err(|source| PluginError::Io {
+      path: path.to_path_buf(),
+      source,
+    })?;
+    let granted = path
+      .file_stem()
+      .and_then(|stem| self.config.grants.get(&*stem.to_string_lossy()))
+      .map_or(&[][..], Vec::as_slice);
+    let plugin = Plugin::load(
+      path,
+      source,
+      granted,
+      self.config.limits,
+      self.kv.clone(),
+      self.fetch.clone(),
+    )?;
+    let name = plugin.name();
+    if let Some(other) = self.plugins.get(&name).filter(|other| other.path != path) {
+      return Err(PluginError::Duplicate {
+        name,
+        path: other.path.clone(),
+      });
+    }
+    self.unload_path(path);
+    let manifest = plugin.manifest();
+    self.plugins.insert(name, plugin);
+    Ok(manifest)
+  }
+
+  /// Unloads whatever was loaded from `path`, returning its name.
+  pub fn unload_path(&mut self, path: &Path) -> Option<String> {
+    let name = self
+      .plugins
+      .iter()
+      .find(|(_, plugin)| plugin.path == path)
+      .map(|(name, _)| name.clone())?;
+    self.plugins.remove(&name);
+    Some(name)
+  }
+
+  pub fn manifests(&self) -> Vec<Manifest> {
+    self.plugins.values().map(Plugin::manifest).collect()
+  }
+
+  /// Calls the function `export` of `plugin`, passing and returning JSON values.
+  pub fn call(
+    &mut self,
+    plugin: &str,
+    export: &str,
+    args: &[serde_json::Value],
+  ) -> Result<serde_json::Value, PluginError> {
+    let loaded = self
+      .plugins
+      .get(plugin)
+      .ok_or_else(|| PluginError::Unknown(plugin.to_string()))?;
+    let result = loaded.call(export, args);
+    self.kill_on_limit(plugin, &result);
+    result
+  }
+
+  /// Fires every due timer, returning the failures.
+  pub fn run_timers(&mut self) -> Vec<PluginError> {
+    let now = Instant::now();
+    let mut errors = vec![];
+    let names: Vec<String> = self.plugins.keys().cloned().collect();
+    for name in names {
+      let result = self.plugins[&name].run_timers(now);
+      self.kill_on_limit(&name, &result);
+      errors.extend(result.err());
+    }
+    errors
+  }
+
+  /// When the next timer of any plugin is due.
+  pub fn next_timer(&self) -> Option<Instant> {
+    self.plugins.values().filter_map(Plugin::next_timer).min()
+  }
+
+  /// What `plugin` stored under `key` with `host.kv`.
+  pub fn kv_get(&self, plugin: &str, key: &str) -> Option<serde_json::Value> {
+    let kv = self.kv.borrow();
+    let value = kv.get(plugin)?.get(key)?;
+    serde_json::from_str(value).ok()
+  }
+
+  fn kill_on_limit<T>(&mut self, name: &str, result: &Result<T, PluginError>) {
+    if let Err(PluginError::Killed { .. }) = result {
+      self.plugins.remove(name);
+    }
+  }
+}
+
+pub fn is_plugin(path: &Path) -> bool {
+  path.extension().is_some_and(|extension| extension == "js")
+}
@@ -0,0 +1,15 @@
+//! A plugin host on QuickJS: ES modules declaring a manifest, a capability-gated `host` API,
+//! memory and CPU time limits, and hot swapping of one plugin at a time.
+
+mod api;
+pub mod error;
+pub mod host;
+pub mod limits;
+pub mod manifest;
+mod plugin;
+
+pub use api::FetchPolicy;
+pub use error::{KillReason, PluginError};
+pub use host::{HostConfig, PluginHost};
+pub use limits::Limits;
+pub use manifest::{Capability, Manifest};
@@ -0,0 +1,60 @@
+use std::{
+  cell::Cell,
+  time::{Duration, Instant},
+};
+
+/// What a single plugin may use.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+  /// Bytes the plugin's runtime may allocate, including the runtime itself.
+  pub memory: usize,
+  /// How long one call into the plugin (loading it, an export or a timer) may run.
+  pub cpu_time: Duration,
+}
+
+impl Default for Limits {
+  fn default() -> Self {
+    Limits {
+      memory: 16 * 1024 * 1024,
+      cpu_time: Duration::from_millis(100),
+    }
+  }
+}
+
+/// Shared between a plugin and its runtime's interrupt handler: while armed, JavaScript is
+/// interrupted once the deadline passes.
+#[derive(Debug, Default)]
+pub(crate) struct Deadline {
+  at: Cell<Option<Instant>>,
+  hit: Cell<bool>,
+}
+
+impl Deadline {
+  pub(crate) fn arm(&self, budget: Duration) {
+    self.at.set(Some(Instant::now() + budget));
+    self.hit.set(false);
+  }
+
+  pub(crate) fn disarm(&self) {
+    self.at.set(None);
+  }
+
+  /// Gives back time spent outside JavaScript, such as waiting on an HTTP response.
+  pub(crate) fn extend(&self, by: Duration) {
+    if let Some(at) = self.at.get() {
+      self.at.set(Some(at + by));
+    }
+  }
+
+  pub(crate) fn hit(&self) -> bool {
+    self.hit.get()
+  }
+
+  pub(crate) fn should_interrupt(&self) -> bool {
+    let hit = self.at.get().is_some_and(|at| Instant::now() >= at);
+    if hit {
+      self.hit.set(true);
+    }
+    hit
+  }
+}
@@ -1,90 +1,188 @@
-use std::{path::Path, time::Duration};
+// cargo run -p rquickjs_hot_reload_example -- --allow-fetch example.com \
+//   --grant counter=kv,timers --grant status=fetch
+// then type `call counter count` or `call status check "https://example.com/"`, and edit
+// plugins/*.js while it runs.
+use std::{
+  collections::HashMap,
+  io::BufRead,
+  path::{Path, PathBuf},
+  sync::mpsc::{self, RecvTimeoutError},
+  time::{Duration, Instant},
+};
 
-use notify::{RecursiveMode, Result, Watcher};
-use rquickjs::{
-  async_with,
-  loader::{FileResolver, ScriptLoader},
-  AsyncContext, AsyncRuntime, Function,
+use anyhow::Result;
+use clap::Parser;
+use notify::{EventKind, RecursiveMode, Watcher};
+use rquickjs_hot_reload_example::{
+  host::is_plugin, Capability, FetchPolicy, HostConfig, Limits, PluginHost,
 };
-use tokio::{fs, sync::watch, time::sleep};
 
-const FILE_NAME: &str = "script_module.js";
+/// Runs the JavaScript plugins of a directory, reloading them as they change.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+  /// Directory of plugin modules
+  #[arg(default_value = "plugins")]
+  dir: PathBuf,
+  /// Host `host.fetch` may reach, as `host` or `host:port`
+  #[arg(long)]
+  allow_fetch: Vec<String>,
+  /// Capabilities a plugin gets if its manifest asks for them, as `file=kv,fetch,timers` for
+  /// plugins/file.js; plugins get none otherwise
+  #[arg(long, value_parser = parse_grant)]
+  grant: Vec<(String, Vec<Capability>)>,
+  /// Memory limit of each plugin, in bytes
+  #[arg(long, default_value_t = Limits::default().memory)]
+  memory_limit: usize,
+  /// CPU time limit of each call into a plugin, in milliseconds
+  #[arg(long, default_value_t = Limits::default().cpu_time.as_millis() as u64)]
+  cpu_ms: u64,
+}
+
+fn parse_grant(s: &str) -> Result<(String, Vec<Capability>), String> {
+  let (plugin, capabilities) = s
+    .split_once('=')
+    .ok_or_else(|| format!("expected PLUGIN=CAPABILITY,..., got {:?}", s))?;
+  let capabilities = capabilities
+    .split(',')
+    .map(|capability| {
+      serde_json::from_value(serde_json::Value::String(capability.to_string()))
+        .map_err(|_| format!("unknown capability {:?}", capability))
+    })
+    .collect::<Result<_, _>>()?;
+  Ok((plugin.to_string(), capabilities))
+}
+
+enum Input {
+  Changed(PathBuf),
+  Line(String),
+}
+
+fn call(host: &mut PluginHost, line: &str) -> Result<()> {
+  let mut words = line.splitn(4, ' ');
+  let (Some("call"), Some(plugin), Some(export)) = (words.next(), words.next(), words.next())
+  else {
+    anyhow::bail!("usage: call <plugin> <export> [json args...]");
+  };
+  let args = match words.next() {
+    Some(args) => serde_json::from_str(&format!("[{}]", args))?,
+    None => vec![],
+  };
+  let result = host.call(plugin, export, &args)?;
+  println!("{}", result);
+  Ok(())
+}
+
+fn command(host: &mut PluginHost, line: &str) {
+  if line.trim().is_empty() {
+    return;
+  }
+  if let Err(e) = call(host, line.trim()) {
+    println!("{}", e);
+  }
+}
 
-fn print(msg: String) {
-  println!("{}", msg);
+fn reload(host: &mut PluginHost, path: &Path) {
+  if !path.exists() {
+    if let Some(name) = host.unload_path(path) {
+      println!("unloaded {}", name);
+    }
+    return;
+  }
+  match host.load(path) {
+    Ok(manifest) => println!(
+      "loaded {} {} from {:?}",
+      manifest.name, manifest.version, path
+    ),
+    Err(e) => println!("{}", e),
+  }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-  let (tx, mut rx) = watch::channel(());
-  tokio::spawn(async move {
-    if let Ok(mut watcher) = notify::recommended_watcher(move |res| match res {
-      Ok(_event) => {
-        _ = tx.send(());
+fn main() -> Result<()> {
+  let args = Args::parse();
+  let mut grants: HashMap<String, Vec<Capability>> = HashMap::new();
+  for (plugin, capabilities) in args.grant {
+    grants.entry(plugin).or_default().extend(capabilities);
+  }
+  let mut host = PluginHost::new(HostConfig {
+    limits: Limits {
+      memory: args.memory_limit,
+      cpu_time: Duration::from_millis(args.cpu_ms),
+    },
+    fetch: FetchPolicy {
+      allowlist: args.allow_fetch,
+    },
+    grants,
+  });
+  for (path, result) in host.load_dir(&args.dir) {
+    match result {
+      Ok(manifest) => println!(
+        "loaded {} {} from {:?} asking for {:?}",
+        manifest.name, manifest.version, path, manifest.capabilities
+      ),
+      Err(e) => println!("{}", e),
+    }
+  }
+
+  let (tx, rx) = mpsc::channel();
+  let watch_tx = tx.clone();
+  let mut watcher =
+    notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+      Ok(event)
+        if matches!(
+          event.kind,
+          EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ) =>
+      {
+        for path in event.paths.into_iter().filter(|path| is_plugin(path)) {
+          _ = watch_tx.send(Input::Changed(path));
+        }
       }
+      Ok(_) => {}
       Err(e) => println!("watch error: {:?}", e),
-    }) {
-      if let Err(_) = watcher.watch(Path::new("."), RecursiveMode::Recursive) {
-        println!("wrong");
+    })?;
+  watcher.watch(&args.dir, RecursiveMode::NonRecursive)?;
+  std::thread::spawn(move || {
+    for line in std::io::stdin().lock().lines().map_while(|line| line.ok()) {
+      if tx.send(Input::Line(line)).is_err() {
+        break;
       }
-    } else {
-      println!("wrong");
     }
   });
 
-  let resolver = FileResolver::default().with_path("./");
-  let loader = ScriptLoader::default();
-  let rt = AsyncRuntime::new().unwrap();
-  rt.set_loader(resolver, loader).await;
-
-  let ctx = AsyncContext::full(&rt).await.unwrap();
-  async_with!(&ctx => |ctx| {
-    let global = ctx.globals();
-    global
-      .set(
-        "print",
-        Function::new(ctx.clone(), print)
-          .unwrap()
-          .with_name("print")
-          .unwrap(),
-      )
-      .unwrap();
-
-    println!("Importing script module");
-    let mut module_code =
-      if let Ok(file_content) = fs::read_to_string(FILE_NAME).await {
-        file_content
-      } else {
-        String::new()
-      };
-
-    loop {
-      tokio::select!{
-        Ok(_) = rx.changed() => {
-          println!("file changed");
-          if let Ok(file_content) = fs::read_to_string(FILE_NAME).await{
-            module_code = file_content;
-          }
-        },
-
-        _ = sleep(Duration::from_secs(0)) => {
-          if let Ok(res) = ctx.eval::<(), &str>("console.log('hello world');") {
-            println!("helloworld_print Result: {:?}", res);
-          } else {
-            println!("hellowrold_print Failed to evaluate JavaScript code");
+  loop {
+    let wait = host.next_timer().map_or(Duration::from_secs(1), |at| {
+      at.saturating_duration_since(Instant::now())
+    });
+    match rx.recv_timeout(wait) {
+      Ok(Input::Changed(path)) => {
+        // Editors write a file in several steps; let them finish.
+        std::thread::sleep(Duration::from_millis(50));
+        let mut changed = vec![path];
+        // Commands typed meanwhile run once the plugins are reloaded
+        let mut lines = vec![];
+        for input in rx.try_iter() {
+          match input {
+            Input::Changed(path) => changed.push(path),
+            Input::Line(line) => lines.push(line),
           }
-          if !module_code.is_empty() {
-            println!("&*code_str is {}", &*module_code);
-            if let Ok(res) = ctx.eval::<(), &str>(&*module_code) {
-              println!("Result: {:?}", res);
-            } else {
-              println!("Failed to evaluate JavaScript code");
-            }
-          }
-        },
+        }
+        changed.sort();
+        changed.dedup();
+        for path in changed {
+          reload(&mut host, &path);
+        }
+        for line in lines {
+          command(&mut host, &line);
+        }
       }
+      Ok(Input::Line(line)) => command(&mut host, &line),
+      Err(RecvTimeoutError::Timeout) => {}
+      Err(RecvTimeoutError::Disconnected) => break,
+    }
+    for error in host.run_timers() {
+      println!("{}", error);
     }
-  })
-  .await;
+  }
   Ok(())
 }
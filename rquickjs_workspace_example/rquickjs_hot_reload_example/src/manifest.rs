@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+/// What a plugin module declares about itself with `export const manifest = { ... }`. Its
+/// `name` must be that of its file without `.js`, and its `capabilities` are only asked for: a
+/// plugin gets those [`HostConfig::grants`] gives it too.
+///
+/// [`HostConfig::grants`]: crate::HostConfig::grants
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+  pub name: String,
+  #[serde(default)]
+  pub version: String,
+  #[serde(default)]
+  pub capabilities: Vec<Capability>,
+}
+
+/// Parts of the `host` API a plugin has to ask for; the others throw when called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Capability {
+  /// `host.kv.get/set/delete`, a store kept by the host across reloads.
+  Kv,
+  /// `host.fetch(url)`, limited to the hosts the host allows.
+  Fetch,
+  /// `host.setTimeout/clearTimeout`.
+  Timers,
+}
@@ -0,0 +1,239 @@
+use std::{
+  cell::RefCell,
+  path::{Path, PathBuf},
+  rc::Rc,
+  time::Instant,
+};
+
+use rquickjs::{
+  function::Rest, CaughtError, Context, Ctx, Function, Module, Object, Persistent, Runtime, Value,
+};
+
+use crate::{
+  api::{self, Env, FetchPolicy, KvStore, Timers},
+  error::{KillReason, PluginError},
+  limits::{Deadline, Limits},
+  manifest::{Capability, Manifest},
+};
+
+/// Plugins get their own runtime, so limits apply to each one and a plugin can be replaced
+/// without touching the others.
+pub(crate) struct Plugin {
+  // The persistent values belong to the runtime and must be dropped before it, which field order
+  // guarantees.
+  exports: Persistent<Object<'static>>,
+  fire_timer: Persistent<Function<'static>>,
+  context: Context,
+  runtime: Runtime,
+  env: Rc<Env>,
+  limits: Limits,
+  pub(crate) path: PathBuf,
+}
+
+impl Plugin {
+  pub(crate) fn load(
+    path: &Path,
+    source: String,
+    granted: &[Capability],
+    limits: Limits,
+    kv: Rc<RefCell<KvStore>>,
+    fetch: Rc<FetchPolicy>,
+  ) -> Result<Plugin, PluginError> {
+    let runtime = Runtime::new()?;
+    runtime.set_memory_limit(limits.memory);
+    let deadline = Rc::new(Deadline::default());
+    let handler_deadline = deadline.clone();
+    runtime.set_interrupt_handler(Some(Box::new(move || handler_deadline.should_interrupt())));
+    let context = Context::full(&runtime)?;
+
+    let env = Rc::new(Env {
+      name: RefCell::new(path.display().to_string()),
+      manifest: RefCell::new(None),
+      granted: RefCell::default(),
+      kv,
+      fetch,
+      deadline,
+      timers: RefCell::new(Timers::default()),
+    });
+
+    let (exports, fire_timer) = context.with(|ctx| {
+      let fire_timer = api::install(&ctx, &env)?;
+      let exports = guarded(&ctx, &env, limits, || {
+        let (module, promise) =
+          Module::declare(ctx.clone(), path.display().to_string(), source)?.eval()?;
+        promise.finish::<()>()?;
+        module.namespace()
+      })?;
+
+      let manifest = exports.get::<_, Value>("manifest")?;
+      let manifest = ctx
+        .json_stringify(manifest)?
+        .map(|json| json.to_string())
+        .transpose()?
+        .ok_or_else(|| manifest_error(path, "missing `export const manifest`"))?;
+      let manifest: Manifest =
+        serde_json::from_str(&manifest).map_err(|e| manifest_error(path, &e.to_string()))?;
+      // Grants and the KV store go by file, a plugin must not pass for another one
+      let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+      if manifest.name != stem {
+        let message = format!("named {:?}, not {:?} like its file", manifest.name, stem);
+        return Err(manifest_error(path, &message));
+      }
+      *env.name.borrow_mut() = manifest.name.clone();
+      *env.granted.borrow_mut() = manifest
+        .capabilities
+        .iter()
+        .filter(|capability| granted.contains(capability))
+        .copied()
+        .collect();
+      *env.manifest.borrow_mut() = Some(manifest);
+
+      // Capabilities are only granted now, so setup using them goes in `init`
+      if let Ok(init) = exports.get::<_, Function>("init") {
+        guarded(&ctx, &env, limits, || init.call::<_, ()>(()))?;
+      }
+      Ok::<_, PluginError>((
+        Persistent::save(&ctx, exports),
+        Persistent::save(&ctx, fire_timer),
+      ))
+    })?;
+
+    Ok(Plugin {
+      exports,
+      fire_timer,
+      context,
+      runtime,
+      env,
+      limits,
+      path: path.to_path_buf(),
+    })
+  }
+
+  pub(crate) fn manifest(&self) -> Manifest {
+    self.env.manifest.borrow().clone().unwrap()
+  }
+
+  pub(crate) fn name(&self) -> String {
+    self.env.name.borrow().clone()
+  }
+
+  pub(crate) fn call(
+    &self,
+    export: &str,
+    args: &[serde_json::Value],
+  ) -> Result<serde_json::Value, PluginError> {
+    let json = self.context.with(|ctx| {
+      let exports = self.exports.clone().restore(&ctx)?;
+      let Ok(function) = exports.get::<_, Function>(export) else {
+        return Err(PluginError::NoSuchExport {
+          plugin: self.name(),
+          export: export.to_string(),
+        });
+      };
+      let args = args
+        .iter()
+        .map(|arg| ctx.json_parse(arg.to_string()))
+        .collect::<rquickjs::Result<Vec<_>>>()?;
+      let json = guarded(&ctx, &self.env, self.limits, || {
+        let mut result: Value = function.call((Rest(args),))?;
+        if let Some(promise) = result.as_promise() {
+          result = promise.finish()?;
+        }
+        ctx
+          .json_stringify(result)?
+          .map(|json| json.to_string())
+          .transpose()
+      })?;
+      Ok::<_, PluginError>(json)
+    })?;
+    self.run_jobs()?;
+    Ok(match json {
+      Some(json) => serde_json::from_str(&json).unwrap_or(serde_json::Value::Null),
+      None => serde_json::Value::Null,
+    })
+  }
+
+  /// Fires the timers due at `now`.
+  pub(crate) fn run_timers(&self, now: Instant) -> Result<(), PluginError> {
+    let due = self.env.timers.borrow_mut().take_due(now);
+    if due.is_empty() {
+      return Ok(());
+    }
+    self.context.with(|ctx| {
+      let fire_timer = self.fire_timer.clone().restore(&ctx)?;
+      for id in due {
+        guarded(&ctx, &self.env, self.limits, || {
+          fire_timer.call::<_, ()>((id,))
+        })?;
+      }
+      Ok::<_, PluginError>(())
+    })?;
+    self.run_jobs()
+  }
+
+  pub(crate) fn next_timer(&self) -> Option<Instant> {
+    self.env.timers.borrow().next_due()
+  }
+
+  /// Promise callbacks queued by the last call, under the same limits.
+  fn run_jobs(&self) -> Result<(), PluginError> {
+    self.env.deadline.arm(self.limits.cpu_time);
+    let mut result = Ok(());
+    while self.runtime.is_job_pending() {
+      if self.runtime.execute_pending_job().is_err() {
+        let message = self
+          .context
+          .with(|ctx| describe(&ctx, rquickjs::Error::Exception));
+        result = Err(classify(&self.env, message));
+        break;
+      }
+    }
+    self.env.deadline.disarm();
+    result
+  }
+}
+
+/// Runs `f` under the CPU time limit and turns a JavaScript failure into a [`PluginError`].
+fn guarded<'js, T>(
+  ctx: &Ctx<'js>,
+  env: &Env,
+  limits: Limits,
+  f: impl FnOnce() -> rquickjs::Result<T>,
+) -> Result<T, PluginError> {
+  env.deadline.arm(limits.cpu_time);
+  let result = f();
+  env.deadline.disarm();
+  result.map_err(|e| classify(env, describe(ctx, e)))
+}
+
+fn classify(env: &Env, message: String) -> PluginError {
+  let plugin = env.name.borrow().clone();
+  if env.deadline.hit() {
+    PluginError::Killed {
+      plugin,
+      reason: KillReason::CpuTime,
+    }
+  } else if message.contains("out of memory") {
+    PluginError::Killed {
+      plugin,
+      reason: KillReason::Memory,
+    }
+  } else {
+    PluginError::Exception { plugin, message }
+  }
+}
+
+fn describe(ctx: &Ctx<'_>, error: rquickjs::Error) -> String {
+  match CaughtError::from_error(ctx, error) {
+    CaughtError::Exception(exception) => exception.message().unwrap_or_default(),
+    CaughtError::Value(value) => format!("{:?}", value),
+    CaughtError::Error(error) => error.to_string(),
+  }
+}
+
+fn manifest_error(path: &Path, message: &str) -> PluginError {
+  PluginError::Manifest {
+    path: path.to_path_buf(),
+    message: message.to_string(),
+  }
+}
@@ -0,0 +1,292 @@
+use std::{
+  fs,
+  io::{Read, Write},
+  net::TcpListener,
+  path::{Path, PathBuf},
+  time::{Duration, Instant},
+};
+
+use rquickjs_hot_reload_example::{
+  Capability, FetchPolicy, HostConfig, KillReason, Limits, PluginError, PluginHost,
+};
+use serde_json::json;
+use tempfile::TempDir;
+
+const STEADY: &str = r#"
+export const manifest = { name: "steady", version: "1", capabilities: ["kv"] };
+let calls = 0;
+export function bump() {
+  calls += 1;
+  host.kv.set("calls", calls);
+  return calls;
+}
+"#;
+
+fn write(dir: &Path, file: &str, source: &str) -> PathBuf {
+  let path = dir.join(file);
+  fs::write(&path, source).unwrap();
+  path
+}
+
+/// Files whose plugins get every capability they ask for.
+const GRANTED: [&str; 4] = ["steady", "swapped", "timer", "nosy"];
+
+fn host(limits: Limits) -> PluginHost {
+  let all = vec![Capability::Kv, Capability::Fetch, Capability::Timers];
+  PluginHost::new(HostConfig {
+    limits,
+    grants: GRANTED
+      .iter()
+      .map(|file| (file.to_string(), all.clone()))
+      .collect(),
+    ..Default::default()
+  })
+}
+
+fn assert_exception<T: std::fmt::Debug>(result: Result<T, PluginError>, expected: &str) {
+  match result {
+    Err(PluginError::Exception { message, .. }) => {
+      assert!(message.contains(expected), "{}", message)
+    }
+    other => panic!("expected an exception, got {:?}", other),
+  }
+}
+
+fn assert_killed<T: std::fmt::Debug>(result: Result<T, PluginError>, expected: KillReason) {
+  match result {
+    Err(PluginError::Killed { reason, .. }) => assert_eq!(reason, expected),
+    other => panic!("expected the plugin to be killed, got {:?}", other),
+  }
+}
+
+#[test]
+fn a_runaway_script_is_killed_without_harming_the_others() {
+  let dir = TempDir::new().unwrap();
+  write(dir.path(), "steady.js", STEADY);
+  write(
+    dir.path(),
+    "runaway.js",
+    r#"
+export const manifest = { name: "runaway" };
+export function spin() { for (;;) {} }
+export function ok() { return 1; }
+"#,
+  );
+  let mut host = host(Limits::default());
+  let loaded = host.load_dir(dir.path());
+  assert!(
+    loaded.iter().all(|(_, result)| result.is_ok()),
+    "{:?}",
+    loaded
+  );
+
+  assert_eq!(host.call("steady", "bump", &[]).unwrap(), json!(1));
+  assert_eq!(host.call("runaway", "ok", &[]).unwrap(), json!(1));
+
+  let started = Instant::now();
+  assert_killed(host.call("runaway", "spin", &[]), KillReason::CpuTime);
+  assert!(started.elapsed() < Duration::from_secs(2));
+  assert!(matches!(
+    host.call("runaway", "ok", &[]),
+    Err(PluginError::Unknown(_))
+  ));
+
+  // The other plugin kept its JavaScript state
+  assert_eq!(host.call("steady", "bump", &[]).unwrap(), json!(2));
+  assert_eq!(host.manifests().len(), 1);
+}
+
+#[test]
+fn runaway_module_code_and_timers_are_killed() {
+  let dir = TempDir::new().unwrap();
+  let mut host = host(Limits::default());
+  let at_load = write(
+    dir.path(),
+    "load.js",
+    r#"export const manifest = { name: "load" }; while (true) {}"#,
+  );
+  assert_killed(host.load(&at_load), KillReason::CpuTime);
+
+  let in_timer = write(
+    dir.path(),
+    "timer.js",
+    r#"
+export const manifest = { name: "timer", capabilities: ["timers"] };
+export function init() { host.setTimeout(() => { for (;;) {} }, 0); }
+"#,
+  );
+  host.load(&in_timer).unwrap();
+  let errors = host.run_timers();
+  assert_eq!(errors.len(), 1);
+  assert_killed(
+    Err::<(), _>(errors.into_iter().next().unwrap()),
+    KillReason::CpuTime,
+  );
+  assert!(host.manifests().is_empty());
+}
+
+#[test]
+fn exceeding_the_memory_limit_kills_the_plugin() {
+  let dir = TempDir::new().unwrap();
+  let path = write(
+    dir.path(),
+    "hog.js",
+    r#"
+export const manifest = { name: "hog" };
+const kept = [];
+export function grow() { for (;;) kept.push("x".repeat(64 * 1024)); }
+"#,
+  );
+  let mut host = host(Limits {
+    memory: 8 * 1024 * 1024,
+    cpu_time: Duration::from_secs(10),
+  });
+  host.load(&path).unwrap();
+  assert_killed(host.call("hog", "grow", &[]), KillReason::Memory);
+  assert!(host.manifests().is_empty());
+}
+
+#[test]
+fn capabilities_gate_the_host_api() {
+  let dir = TempDir::new().unwrap();
+  let path = write(
+    dir.path(),
+    "nosy.js",
+    r#"
+export const manifest = { name: "nosy", capabilities: ["timers"] };
+export function store() { host.kv.set("k", 1); }
+export function wait() { return host.setTimeout(() => {}, 1000) > 0; }
+export function wait_for(ms) {
+  try {
+    host.setTimeout(() => {}, ms);
+  } catch (e) {
+    return e instanceof RangeError;
+  }
+}
+"#,
+  );
+  let mut host = host(Limits::default());
+  host.load(&path).unwrap();
+  assert_exception(host.call("nosy", "store", &[]), "lacks");
+  assert_eq!(host.call("nosy", "wait", &[]).unwrap(), json!(true));
+  // Delays past what an Instant holds
+  for ms in ["Infinity", "1e300", "1.5e22"] {
+    let ms = host.call("nosy", "wait_for", &[json!(ms)]);
+    assert_eq!(ms.unwrap(), json!(true));
+  }
+  // A plain exception does not unload the plugin
+  assert_eq!(host.manifests().len(), 1);
+
+  // Asking is not enough, greedy.js was granted nothing
+  let greedy = write(
+    dir.path(),
+    "greedy.js",
+    r#"
+export const manifest = { name: "greedy", capabilities: ["kv", "timers"] };
+export function store() { host.kv.set("k", 1); }
+"#,
+  );
+  host.load(&greedy).unwrap();
+  assert_exception(host.call("greedy", "store", &[]), "lacks");
+
+  let missing = write(dir.path(), "bare.js", "export const x = 1;");
+  assert!(matches!(
+    host.load(&missing),
+    Err(PluginError::Manifest { .. })
+  ));
+}
+
+#[test]
+fn hot_swapping_keeps_other_plugins_and_the_kv_store() {
+  let dir = TempDir::new().unwrap();
+  write(dir.path(), "steady.js", STEADY);
+  let swapped = write(
+    dir.path(),
+    "swapped.js",
+    r#"
+export const manifest = { name: "swapped", version: "1", capabilities: ["kv", "timers"] };
+export function init() { host.setTimeout(() => host.kv.set("fired", true), 0); }
+export function version() { return 1; }
+"#,
+  );
+  let mut host = host(Limits::default());
+  host.load_dir(dir.path());
+  host.call("steady", "bump", &[]).unwrap();
+  assert!(host.run_timers().is_empty());
+  assert_eq!(host.kv_get("swapped", "fired"), Some(json!(true)));
+
+  // A broken edit leaves the running version alone
+  fs::write(&swapped, "export const manifest = {").unwrap();
+  assert!(host.load(&swapped).is_err());
+  assert_eq!(host.call("swapped", "version", &[]).unwrap(), json!(1));
+
+  fs::write(
+    &swapped,
+    r#"
+export const manifest = { name: "swapped", version: "2", capabilities: ["kv"] };
+export function version() { return 2; }
+export function fired() { return host.kv.get("fired"); }
+"#,
+  )
+  .unwrap();
+  assert_eq!(host.load(&swapped).unwrap().version, "2");
+  assert_eq!(host.call("swapped", "version", &[]).unwrap(), json!(2));
+  assert_eq!(host.call("swapped", "fired", &[]).unwrap(), json!(true));
+  assert_eq!(host.call("steady", "bump", &[]).unwrap(), json!(2));
+
+  // Another file cannot take a loaded plugin's name, nor its KV store
+  let copy = write(dir.path(), "copy.js", STEADY);
+  assert!(matches!(
+    host.load(&copy),
+    Err(PluginError::Manifest { .. })
+  ));
+  fs::create_dir(dir.path().join("other")).unwrap();
+  let copy = write(&dir.path().join("other"), "steady.js", STEADY);
+  assert!(matches!(
+    host.load(&copy),
+    Err(PluginError::Duplicate { .. })
+  ));
+  assert_eq!(host.unload_path(&swapped).as_deref(), Some("swapped"));
+}
+
+#[test]
+fn fetch_only_reaches_allowed_hosts() {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let port = listener.local_addr().unwrap().port();
+  std::thread::spawn(move || {
+    for mut stream in listener.incoming().flatten() {
+      let mut request = [0; 1024];
+      let _ = stream.read(&mut request);
+      let _ =
+        stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\nconnection: close\r\n\r\nhello");
+    }
+  });
+
+  let dir = TempDir::new().unwrap();
+  let path = write(
+    dir.path(),
+    "status.js",
+    include_str!("../plugins/status.js"),
+  );
+  let mut host = PluginHost::new(HostConfig {
+    limits: Limits::default(),
+    fetch: FetchPolicy {
+      allowlist: vec![format!("127.0.0.1:{}", port)],
+    },
+    grants: [("status".to_string(), vec![Capability::Fetch])].into(),
+  });
+  host.load(&path).unwrap();
+
+  let allowed = format!("http://127.0.0.1:{}/", port);
+  assert_eq!(
+    host.call("status", "check", &[json!(allowed)]).unwrap(),
+    json!({ "url": allowed, "status": 200, "bytes": 5 })
+  );
+  let denied = format!("http://localhost:{}/", port);
+  match host.call("status", "check", &[json!(denied)]) {
+    Err(PluginError::Exception { message, .. }) => {
+      assert!(message.contains("not allowed"), "{}", message)
+    }
+    other => panic!("{:?}", other),
+  }
+}
@@ -1,43 +1,174 @@
-use std::io;
-
-use sqlite::{self, Connection, State};
-
-pub fn addrecord(conn: &Connection) -> io::Result<()> {
-  let mut title = String::new();
-  let mut finding = String::new();
-  let mut details = String::new();
-  let mut justification = String::new();
-
-  println!("Title");
-  io::stdin().read_line(&mut title)?;
-  println!("Finding text");
-  io::stdin().read_line(&mut finding)?;
-  println!("Details of the finding");
-  io::stdin().read_line(&mut details)?;
-  println!("Justification");
-  io::stdin().read_line(&mut justification)?;
-
-  let commandstring = format!(
-    "INSERT INTO findings (title, finding, details, justification) VALUES (\"{}\",
-        \"{}\", \"{}\", \"{}\")",
-    title.trim(),
-    finding.trim(),
-    details.trim(),
-    justification.trim()
-  );
-  let _statement = conn.execute(&commandstring).unwrap();
+use std::io::{self, BufRead, Write};
+
+use serde::{Deserialize, Serialize};
+use sqlite::{self, Connection, State, Statement};
 
-  Ok(())
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Finding {
+  #[serde(default)]
+  pub id: Option<i64>,
+  pub title: String,
+  pub finding: String,
+  #[serde(default)]
+  pub details: Option<String>,
+  #[serde(default)]
+  pub justification: Option<String>,
+  #[serde(default = "default_severity")]
+  pub severity: String,
 }
 
-pub fn listrecords(conn: &Connection) {
-  let mut statement = conn.prepare("SELECT * FROM findings").unwrap();
+fn default_severity() -> String {
+  "info".to_string()
+}
 
-  while let State::Row = statement.next().unwrap() {
-    println!("-----------------------------");
-    println!("Title = {}", statement.read::<String>(1).unwrap());
-    println!("Finding = {}", statement.read::<String>(2).unwrap());
-    println!("Details = {}", statement.read::<String>(3).unwrap());
-    println!("Justification = {}", statement.read::<String>(4).unwrap());
+/// Fields to change in `edit`; `None` keeps the stored value. The optional fields are set with
+/// `Some(Some(_))` and cleared with `Some(None)`.
+#[derive(Debug, Clone, Default)]
+pub struct FindingUpdate {
+  pub title: Option<String>,
+  pub finding: Option<String>,
+  pub details: Option<Option<String>>,
+  pub justification: Option<Option<String>>,
+  pub severity: Option<String>,
+}
+
+const COLUMNS: &str = "findings_ID, title, finding, details, justification, severity";
+
+fn read_finding(statement: &Statement) -> sqlite::Result<Finding> {
+  Ok(Finding {
+    id: Some(statement.read::<i64>(0)?),
+    title: statement.read::<String>(1)?,
+    finding: statement.read::<String>(2)?,
+    details: statement.read::<Option<String>>(3)?,
+    justification: statement.read::<Option<String>>(4)?,
+    severity: statement.read::<String>(5)?,
+  })
+}
+
+fn collect(mut statement: Statement) -> sqlite::Result<Vec<Finding>> {
+  let mut findings = vec![];
+  while let State::Row = statement.next()? {
+    findings.push(read_finding(&statement)?);
   }
+  Ok(findings)
+}
+
+/// Inserts a finding with bound parameters and returns its id.
+pub fn insert(conn: &Connection, finding: &Finding) -> sqlite::Result<i64> {
+  let mut statement = conn.prepare(
+    "INSERT INTO findings (title, finding, details, justification, severity)
+     VALUES (?, ?, ?, ?, ?)",
+  )?;
+  statement.bind(1, finding.title.as_str())?;
+  statement.bind(2, finding.finding.as_str())?;
+  statement.bind(3, finding.details.as_deref())?;
+  statement.bind(4, finding.justification.as_deref())?;
+  statement.bind(5, finding.severity.as_str())?;
+  statement.next()?;
+
+  let mut statement = conn.prepare("SELECT last_insert_rowid()")?;
+  statement.next()?;
+  statement.read::<i64>(0)
+}
+
+pub fn get(conn: &Connection, id: i64) -> sqlite::Result<Option<Finding>> {
+  let mut statement = conn.prepare(format!(
+    "SELECT {} FROM findings WHERE findings_ID = ?",
+    COLUMNS
+  ))?;
+  statement.bind(1, id)?;
+  Ok(collect(statement)?.pop())
+}
+
+pub fn list(conn: &Connection, severity: Option<&str>) -> sqlite::Result<Vec<Finding>> {
+  let mut statement = conn.prepare(format!(
+    "SELECT {} FROM findings WHERE ?1 IS NULL OR severity = ?1 ORDER BY findings_ID",
+    COLUMNS
+  ))?;
+  statement.bind(1, severity)?;
+  collect(statement)
+}
+
+/// Full-text search over every text column, best matches first. `query` uses FTS5 syntax, e.g.
+/// `sql AND inject*` or `"exact phrase"`.
+pub fn search(conn: &Connection, query: &str) -> sqlite::Result<Vec<Finding>> {
+  let mut statement = conn.prepare(format!(
+    "SELECT {} FROM findings
+     JOIN findings_fts ON findings_fts.rowid = findings.findings_ID
+     WHERE findings_fts MATCH ? ORDER BY rank",
+    COLUMNS
+      .split(", ")
+      .map(|c| format!("findings.{}", c))
+      .collect::<Vec<_>>()
+      .join(", ")
+  ))?;
+  statement.bind(1, query)?;
+  collect(statement)
+}
+
+/// Returns false when there is no finding with that id.
+pub fn update(conn: &Connection, id: i64, update: &FindingUpdate) -> sqlite::Result<bool> {
+  let mut statement = conn.prepare(
+    "UPDATE findings SET
+       title = COALESCE(?, title),
+       finding = COALESCE(?, finding),
+       details = CASE WHEN ? THEN ? ELSE details END,
+       justification = CASE WHEN ? THEN ? ELSE justification END,
+       severity = COALESCE(?, severity)
+     WHERE findings_ID = ?",
+  )?;
+  statement.bind(1, update.title.as_deref())?;
+  statement.bind(2, update.finding.as_deref())?;
+  statement.bind(3, update.details.is_some() as i64)?;
+  statement.bind(4, update.details.as_ref().and_then(Option::as_deref))?;
+  statement.bind(5, update.justification.is_some() as i64)?;
+  statement.bind(6, update.justification.as_ref().and_then(Option::as_deref))?;
+  statement.bind(7, update.severity.as_deref())?;
+  statement.bind(8, id)?;
+  statement.next()?;
+  Ok(conn.change_count() > 0)
+}
+
+pub fn delete(conn: &Connection, id: i64) -> sqlite::Result<bool> {
+  let mut statement = conn.prepare("DELETE FROM findings WHERE findings_ID = ?")?;
+  statement.bind(1, id)?;
+  statement.next()?;
+  Ok(conn.change_count() > 0)
+}
+
+/// Asks for each field on stdin, like the original `add` command did.
+pub fn prompt_finding() -> io::Result<Finding> {
+  let stdin = io::stdin();
+  let ask = |label: &str| -> io::Result<String> {
+    println!("{}", label);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    stdin.lock().read_line(&mut line)?;
+    Ok(line.trim().to_string())
+  };
+  let title = ask("Title")?;
+  let finding = ask("Finding text")?;
+  let details = ask("Details of the finding")?;
+  let justification = ask("Justification")?;
+  Ok(Finding {
+    id: None,
+    title,
+    finding,
+    details: Some(details).filter(|s| !s.is_empty()),
+    justification: Some(justification).filter(|s| !s.is_empty()),
+    severity: default_severity(),
+  })
+}
+
+pub fn print_finding(finding: &Finding) {
+  println!("-----------------------------");
+  println!("ID = {}", finding.id.unwrap_or_default());
+  println!("Title = {}", finding.title);
+  println!("Severity = {}", finding.severity);
+  println!("Finding = {}", finding.finding);
+  println!("Details = {}", finding.details.as_deref().unwrap_or(""));
+  println!(
+    "Justification = {}",
+    finding.justification.as_deref().unwrap_or("")
+  );
 }
@@ -0,0 +1,13 @@
+use sqlite::Connection;
+
+pub mod dbfuncs;
+pub mod migrations;
+pub mod transfer;
+
+/// Opens (or creates) the findings database and brings its schema up to date. Use `":memory:"` for
+/// a throwaway database.
+pub fn open(path: &str) -> sqlite::Result<Connection> {
+  let conn = Connection::open(path)?;
+  migrations::migrate(&conn)?;
+  Ok(conn)
+}
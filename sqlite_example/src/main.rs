@@ -1,29 +1,168 @@
-use std::{env, error::Error};
+use std::{error::Error, fs::File, io};
 
-use sqlite::{self, Connection};
-mod dbfuncs;
+use clap::{Parser, Subcommand};
+use sqlite_example::{
+  dbfuncs::{self, Finding, FindingUpdate},
+  transfer::{self, Format},
+};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Track security findings", long_about = None)]
+struct Args {
+  #[arg(long, default_value = "stratapp.db")]
+  db: String,
+
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Add a finding; asks on stdin when --title is not given
+  Add {
+    #[arg(long)]
+    title: Option<String>,
+    #[arg(long, requires = "title")]
+    finding: Option<String>,
+    #[arg(long)]
+    details: Option<String>,
+    #[arg(long)]
+    justification: Option<String>,
+    #[arg(long, default_value = "info")]
+    severity: String,
+  },
+  /// List all findings
+  List {
+    #[arg(long)]
+    severity: Option<String>,
+  },
+  /// Full-text search, e.g. `search 'inject* NOT xss'`
+  Search {
+    query: String,
+  },
+  /// Change some fields of a finding
+  Edit {
+    id: i64,
+    #[arg(long)]
+    title: Option<String>,
+    #[arg(long)]
+    finding: Option<String>,
+    #[arg(long)]
+    details: Option<String>,
+    /// Remove the details
+    #[arg(long, conflicts_with = "details")]
+    clear_details: bool,
+    #[arg(long)]
+    justification: Option<String>,
+    /// Remove the justification
+    #[arg(long, conflicts_with = "justification")]
+    clear_justification: bool,
+    #[arg(long)]
+    severity: Option<String>,
+  },
+  Delete {
+    id: i64,
+  },
+  /// Write all findings to stdout or a file
+  Export {
+    #[arg(long, value_enum, default_value = "json")]
+    format: Format,
+    #[arg(short, long)]
+    output: Option<String>,
+  },
+  /// Read findings from a CSV or JSON file
+  Import {
+    file: String,
+    /// Defaults to the file extension
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+  },
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
-  let conn = Connection::open("stratapp.db")?;
-  conn.execute(
-    "CREATE TABLE IF NOT EXISTS findings (
-                findings_ID INTEGER PRIMARY KEY,
-                title TEXT NOT NULL,
-                finding TEXT NOT NULL,
-                details TEXT,
-                justification TEXT)",
-  )?;
+  let args = Args::parse();
+  let conn = sqlite_example::open(&args.db)?;
 
-  let args: Vec<String> = env::args().collect();
-  if args.len() > 1 {
-    let command: &str = &args[1];
-    match command {
-      "add" => dbfuncs::addrecord(&conn)?,
-      "list" => dbfuncs::listrecords(&conn),
-      _ => println!("Didn't send a valid command in"),
+  match args.command {
+    Command::Add {
+      title,
+      finding,
+      details,
+      justification,
+      severity,
+    } => {
+      let record = match title {
+        Some(title) => Finding {
+          id: None,
+          title,
+          finding: finding.unwrap_or_default(),
+          details,
+          justification,
+          severity,
+        },
+        None => dbfuncs::prompt_finding()?,
+      };
+      let id = dbfuncs::insert(&conn, &record)?;
+      println!("added finding {}", id);
+    }
+    Command::List { severity } => {
+      for finding in dbfuncs::list(&conn, severity.as_deref())? {
+        dbfuncs::print_finding(&finding);
+      }
+    }
+    Command::Search { query } => {
+      for finding in dbfuncs::search(&conn, &query)? {
+        dbfuncs::print_finding(&finding);
+      }
+    }
+    Command::Edit {
+      id,
+      title,
+      finding,
+      details,
+      clear_details,
+      justification,
+      clear_justification,
+      severity,
+    } => {
+      let update = FindingUpdate {
+        title,
+        finding,
+        details: if clear_details {
+          Some(None)
+        } else {
+          details.map(Some)
+        },
+        justification: if clear_justification {
+          Some(None)
+        } else {
+          justification.map(Some)
+        },
+        severity,
+      };
+      match dbfuncs::update(&conn, id, &update)? {
+        true => println!("updated finding {}", id),
+        false => println!("no finding {}", id),
+      }
+    }
+    Command::Delete { id } => match dbfuncs::delete(&conn, id)? {
+      true => println!("deleted finding {}", id),
+      false => println!("no finding {}", id),
+    },
+    Command::Export { format, output } => {
+      let count = match output {
+        Some(path) => transfer::export(&conn, format, File::create(path)?)?,
+        None => transfer::export(&conn, format, io::stdout().lock())?,
+      };
+      eprintln!("exported {} findings", count);
+    }
+    Command::Import { file, format } => {
+      let format = format
+        .or_else(|| Format::from_path(&file))
+        .ok_or("cannot tell the format from the file name, pass --format")?;
+      let count = transfer::import(&conn, format, File::open(&file)?)?;
+      println!("imported {} findings", count);
     }
-  } else {
-    println!("Please specify add or list as a command line parameter");
   }
   Ok(())
 }
@@ -0,0 +1,65 @@
+use sqlite::{Connection, State};
+
+/// Schema steps, applied in order. `PRAGMA user_version` holds how many have run, so a database
+/// created by an older build picks up where it left off.
+const MIGRATIONS: &[&str] = &[
+  // 1: the original table; IF NOT EXISTS keeps databases made before versioning working.
+  "CREATE TABLE IF NOT EXISTS findings (
+    findings_ID INTEGER PRIMARY KEY,
+    title TEXT NOT NULL,
+    finding TEXT NOT NULL,
+    details TEXT,
+    justification TEXT)",
+  // 2: full-text index kept in sync by triggers.
+  "CREATE VIRTUAL TABLE findings_fts USING fts5(
+    title, finding, details, justification,
+    content='findings', content_rowid='findings_ID');
+  CREATE TRIGGER findings_ai AFTER INSERT ON findings BEGIN
+    INSERT INTO findings_fts(rowid, title, finding, details, justification)
+    VALUES (new.findings_ID, new.title, new.finding, new.details, new.justification);
+  END;
+  CREATE TRIGGER findings_ad AFTER DELETE ON findings BEGIN
+    INSERT INTO findings_fts(findings_fts, rowid, title, finding, details, justification)
+    VALUES ('delete', old.findings_ID, old.title, old.finding, old.details, old.justification);
+  END;
+  CREATE TRIGGER findings_au AFTER UPDATE ON findings BEGIN
+    INSERT INTO findings_fts(findings_fts, rowid, title, finding, details, justification)
+    VALUES ('delete', old.findings_ID, old.title, old.finding, old.details, old.justification);
+    INSERT INTO findings_fts(rowid, title, finding, details, justification)
+    VALUES (new.findings_ID, new.title, new.finding, new.details, new.justification);
+  END;
+  INSERT INTO findings_fts(findings_fts) VALUES ('rebuild')",
+  // 3: severity, so findings can be triaged.
+  "ALTER TABLE findings ADD COLUMN severity TEXT NOT NULL DEFAULT 'info'",
+];
+
+pub fn version(conn: &Connection) -> sqlite::Result<usize> {
+  let mut statement = conn.prepare("PRAGMA user_version")?;
+  match statement.next()? {
+    State::Row => Ok(statement.read::<i64>(0)? as usize),
+    State::Done => Ok(0),
+  }
+}
+
+pub fn latest() -> usize {
+  MIGRATIONS.len()
+}
+
+/// Runs every migration newer than the database, each one in its own transaction.
+pub fn migrate(conn: &Connection) -> sqlite::Result<usize> {
+  let current = version(conn)?;
+  for (i, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+    conn.execute("BEGIN")?;
+    let applied = conn
+      .execute(migration)
+      .and_then(|_| conn.execute(format!("PRAGMA user_version = {}", i + 1)));
+    match applied {
+      Ok(()) => conn.execute("COMMIT")?,
+      Err(e) => {
+        conn.execute("ROLLBACK")?;
+        return Err(e);
+      }
+    }
+  }
+  Ok(MIGRATIONS.len() - current.min(MIGRATIONS.len()))
+}
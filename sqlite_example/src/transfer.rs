@@ -0,0 +1,74 @@
+use std::{
+  error::Error,
+  io::{Read, Write},
+};
+
+use clap::ValueEnum;
+use sqlite::Connection;
+
+use crate::dbfuncs::{self, Finding};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+  Csv,
+  Json,
+}
+
+impl Format {
+  /// Guesses the format from a file extension.
+  pub fn from_path(path: &str) -> Option<Format> {
+    match path.rsplit_once('.')?.1.to_ascii_lowercase().as_str() {
+      "csv" => Some(Format::Csv),
+      "json" => Some(Format::Json),
+      _ => None,
+    }
+  }
+}
+
+pub fn export<W: Write>(
+  conn: &Connection,
+  format: Format,
+  out: W,
+) -> Result<usize, Box<dyn Error>> {
+  let findings = dbfuncs::list(conn, None)?;
+  match format {
+    Format::Csv => {
+      let mut writer = csv::Writer::from_writer(out);
+      for finding in &findings {
+        writer.serialize(finding)?;
+      }
+      writer.flush()?;
+    }
+    Format::Json => {
+      let mut out = out;
+      serde_json::to_writer_pretty(&mut out, &findings)?;
+      writeln!(out)?;
+    }
+  }
+  Ok(findings.len())
+}
+
+/// Imports every record in one transaction, so a bad row leaves the database untouched. Ids in the
+/// input are ignored; imported findings always get new ones.
+pub fn import<R: Read>(
+  conn: &Connection,
+  format: Format,
+  input: R,
+) -> Result<usize, Box<dyn Error>> {
+  let findings: Vec<Finding> = match format {
+    Format::Csv => csv::Reader::from_reader(input)
+      .deserialize()
+      .collect::<Result<_, _>>()?,
+    Format::Json => serde_json::from_reader(input)?,
+  };
+
+  conn.execute("BEGIN")?;
+  for finding in &findings {
+    if let Err(e) = dbfuncs::insert(conn, finding) {
+      conn.execute("ROLLBACK")?;
+      return Err(e.into());
+    }
+  }
+  conn.execute("COMMIT")?;
+  Ok(findings.len())
+}
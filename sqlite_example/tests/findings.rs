@@ -0,0 +1,133 @@
+use sqlite::Connection;
+use sqlite_example::{
+  dbfuncs::{self, Finding, FindingUpdate},
+  migrations,
+  transfer::{self, Format},
+};
+
+fn finding(title: &str, text: &str) -> Finding {
+  Finding {
+    title: title.to_string(),
+    finding: text.to_string(),
+    severity: "info".to_string(),
+    ..Default::default()
+  }
+}
+
+#[test]
+fn quotes_are_stored_verbatim() {
+  let conn = sqlite_example::open(":memory:").unwrap();
+  let title = "it's \"quoted\"'); DROP TABLE findings; --";
+  let id = dbfuncs::insert(&conn, &finding(title, "O'Brien")).unwrap();
+
+  let stored = dbfuncs::get(&conn, id).unwrap().unwrap();
+  assert_eq!(stored.title, title);
+  assert_eq!(stored.finding, "O'Brien");
+  assert_eq!(dbfuncs::list(&conn, None).unwrap().len(), 1);
+}
+
+#[test]
+fn migrates_a_legacy_database() {
+  let conn = Connection::open(":memory:").unwrap();
+  conn
+    .execute(
+      "CREATE TABLE findings (
+        findings_ID INTEGER PRIMARY KEY,
+        title TEXT NOT NULL,
+        finding TEXT NOT NULL,
+        details TEXT,
+        justification TEXT);
+      INSERT INTO findings (title, finding) VALUES ('old', 'weak password policy')",
+    )
+    .unwrap();
+
+  assert_eq!(migrations::version(&conn).unwrap(), 0);
+  assert_eq!(migrations::migrate(&conn).unwrap(), migrations::latest());
+  assert_eq!(migrations::version(&conn).unwrap(), migrations::latest());
+  assert_eq!(migrations::migrate(&conn).unwrap(), 0);
+
+  let found = dbfuncs::search(&conn, "password").unwrap();
+  assert_eq!(found.len(), 1);
+  assert_eq!(found[0].severity, "info");
+}
+
+#[test]
+fn search_follows_edits_and_deletes() {
+  let conn = sqlite_example::open(":memory:").unwrap();
+  let id = dbfuncs::insert(&conn, &finding("xss", "reflected script")).unwrap();
+  dbfuncs::insert(&conn, &finding("csrf", "missing token")).unwrap();
+
+  let update = FindingUpdate {
+    finding: Some("stored injection".to_string()),
+    severity: Some("high".to_string()),
+    ..Default::default()
+  };
+  assert!(dbfuncs::update(&conn, id, &update).unwrap());
+  assert!(dbfuncs::search(&conn, "reflected").unwrap().is_empty());
+  assert_eq!(dbfuncs::search(&conn, "inject*").unwrap()[0].title, "xss");
+  assert_eq!(dbfuncs::list(&conn, Some("high")).unwrap().len(), 1);
+
+  assert!(dbfuncs::delete(&conn, id).unwrap());
+  assert!(!dbfuncs::delete(&conn, id).unwrap());
+  assert!(dbfuncs::search(&conn, "injection").unwrap().is_empty());
+  assert!(!dbfuncs::update(&conn, id, &update).unwrap());
+}
+
+#[test]
+fn edits_set_keep_or_clear_optional_fields() {
+  let conn = sqlite_example::open(":memory:").unwrap();
+  let id = dbfuncs::insert(&conn, &finding("xss", "reflected script")).unwrap();
+
+  let update = FindingUpdate {
+    details: Some(Some("in the search box".to_string())),
+    justification: Some(Some("user input is echoed".to_string())),
+    ..Default::default()
+  };
+  assert!(dbfuncs::update(&conn, id, &update).unwrap());
+  let stored = dbfuncs::get(&conn, id).unwrap().unwrap();
+  assert_eq!(stored.details.as_deref(), Some("in the search box"));
+
+  let update = FindingUpdate {
+    details: Some(None),
+    ..Default::default()
+  };
+  assert!(dbfuncs::update(&conn, id, &update).unwrap());
+  let stored = dbfuncs::get(&conn, id).unwrap().unwrap();
+  assert_eq!(stored.details, None);
+  assert_eq!(
+    stored.justification.as_deref(),
+    Some("user input is echoed")
+  );
+  assert!(dbfuncs::search(&conn, "search").unwrap().is_empty());
+}
+
+#[test]
+fn csv_and_json_round_trip() {
+  for format in [Format::Csv, Format::Json] {
+    let source = sqlite_example::open(":memory:").unwrap();
+    let mut with_details = finding("a, \"b\"", "line one\nline two");
+    with_details.details = Some("details".to_string());
+    dbfuncs::insert(&source, &with_details).unwrap();
+    dbfuncs::insert(&source, &finding("plain", "text")).unwrap();
+
+    let mut buf = vec![];
+    assert_eq!(transfer::export(&source, format, &mut buf).unwrap(), 2);
+
+    let target = sqlite_example::open(":memory:").unwrap();
+    assert_eq!(
+      transfer::import(&target, format, buf.as_slice()).unwrap(),
+      2
+    );
+    assert_eq!(
+      dbfuncs::list(&source, None).unwrap(),
+      dbfuncs::list(&target, None).unwrap()
+    );
+  }
+}
+
+#[test]
+fn failed_import_leaves_database_untouched() {
+  let conn = sqlite_example::open(":memory:").unwrap();
+  assert!(transfer::import(&conn, Format::Json, &b"[{\"title\": \"x\"}]"[..]).is_err());
+  assert!(dbfuncs::list(&conn, None).unwrap().is_empty());
+}
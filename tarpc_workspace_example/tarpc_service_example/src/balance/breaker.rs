@@ -0,0 +1,103 @@
+use std::time::{Duration, Instant};
+
+/// Ejects an endpoint after `threshold` failures in a row for `ejection`, then lets one call
+/// through to try it again.
+#[derive(Debug)]
+pub(crate) struct Breaker {
+  threshold: u32,
+  ejection: Duration,
+  failures: u32,
+  state: State,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+  Closed,
+  Open { until: Instant },
+  HalfOpen { trying: bool },
+}
+
+impl Breaker {
+  pub(crate) fn new(threshold: u32, ejection: Duration) -> Breaker {
+    Breaker {
+      threshold,
+      ejection,
+      failures: 0,
+      state: State::Closed,
+    }
+  }
+
+  /// Whether a call may go to the endpoint now.
+  pub(crate) fn available(&self, now: Instant) -> bool {
+    match self.state {
+      State::Closed => true,
+      State::Open { until } => now >= until,
+      State::HalfOpen { trying } => !trying,
+    }
+  }
+
+  /// Takes the one trial call of an ejection that ran out. False if it is already taken.
+  pub(crate) fn acquire(&mut self, now: Instant) -> bool {
+    if !self.available(now) {
+      return false;
+    }
+    if let State::Open { .. } | State::HalfOpen { .. } = self.state {
+      self.state = State::HalfOpen { trying: true };
+    }
+    true
+  }
+
+  pub(crate) fn ejected(&self, now: Instant) -> bool {
+    matches!(self.state, State::Open { until } if now < until)
+  }
+
+  pub(crate) fn success(&mut self) {
+    self.failures = 0;
+    self.state = State::Closed;
+  }
+
+  pub(crate) fn failure(&mut self, now: Instant) {
+    self.failures += 1;
+    if self.failures >= self.threshold || matches!(self.state, State::HalfOpen { .. }) {
+      self.state = State::Open {
+        until: now + self.ejection,
+      };
+    }
+  }
+
+  /// A call that neither succeeded nor failed, like one given up on by its caller, frees the
+  /// trial.
+  pub(crate) fn release(&mut self) {
+    if let State::HalfOpen { trying: true } = self.state {
+      self.state = State::HalfOpen { trying: false };
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn ejects_then_tries_once() {
+    let mut breaker = Breaker::new(2, Duration::from_secs(10));
+    let now = Instant::now();
+
+    breaker.failure(now);
+    assert!(breaker.available(now));
+    breaker.failure(now);
+    assert!(breaker.ejected(now));
+    assert!(!breaker.acquire(now));
+
+    let later = now + Duration::from_secs(10);
+    assert!(breaker.acquire(later));
+    assert!(!breaker.acquire(later), "a single trial at a time");
+    breaker.failure(later);
+    assert!(breaker.ejected(later), "a failed trial ejects again");
+
+    let later = later + Duration::from_secs(10);
+    assert!(breaker.acquire(later));
+    breaker.success();
+    assert!(breaker.acquire(later) && breaker.acquire(later));
+  }
+}
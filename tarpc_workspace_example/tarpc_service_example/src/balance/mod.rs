@@ -0,0 +1,482 @@
+//! Calls a service through whichever of its endpoints is least busy, retrying and ejecting
+//! endpoints that fail.
+//!
+//! ```ignore
+//! let world = Balancer::new("world", StaticFile("endpoints".into()), connect_world, Config::default());
+//! let hello = world
+//!   .call_idempotent(context::current(), |client, ctx| async move {
+//!     client.hello(ctx, "you".to_string()).await
+//!   })
+//!   .await?;
+//! ```
+
+use std::{
+  future::Future,
+  io,
+  net::SocketAddr,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex, RwLock,
+  },
+  time::{Duration, Instant},
+};
+
+use futures::{future::BoxFuture, FutureExt};
+use opentelemetry::trace::{SpanContext, TraceContextExt, TraceFlags, TraceId, TraceState};
+use rand::seq::SliceRandom;
+use tarpc::{client::RpcError, context};
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::discovery::Resolver;
+
+mod breaker;
+mod retry;
+
+use breaker::Breaker;
+use retry::{backoff, Budget};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+  #[error("cannot resolve {service}: {source}")]
+  Resolve {
+    service: String,
+    #[source]
+    source: io::Error,
+  },
+  #[error("no endpoint of {0} is available")]
+  NoEndpoints(String),
+  #[error("cannot connect to {addr}: {source}")]
+  Connect {
+    addr: SocketAddr,
+    #[source]
+    source: io::Error,
+  },
+  #[error("call to {addr} failed: {source}")]
+  Rpc {
+    addr: SocketAddr,
+    #[source]
+    source: RpcError,
+  },
+  #[error("the call exceeded its deadline")]
+  DeadlineExceeded,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+  /// How often the resolver is asked again, at the first call after.
+  pub refresh_interval: Duration,
+  pub connect_timeout: Duration,
+  /// Each attempt gets at most this much of the deadline of the call.
+  pub per_try_timeout: Option<Duration>,
+  /// Including the first.
+  pub max_attempts: u32,
+  pub backoff_base: Duration,
+  pub backoff_max: Duration,
+  /// Retries allowed per call made, on average.
+  pub retry_ratio: f64,
+  /// Retries allowed each second whatever the ratio.
+  pub min_retries_per_sec: u32,
+  /// Failures in a row that eject an endpoint.
+  pub failure_threshold: u32,
+  pub ejection: Duration,
+}
+
+impl Default for Config {
+  fn default() -> Config {
+    Config {
+      refresh_interval: Duration::from_secs(5),
+      connect_timeout: Duration::from_secs(1),
+      per_try_timeout: None,
+      max_attempts: 3,
+      backoff_base: Duration::from_millis(10),
+      backoff_max: Duration::from_secs(1),
+      retry_ratio: 0.2,
+      min_retries_per_sec: 10,
+      failure_threshold: 5,
+      ejection: Duration::from_secs(10),
+    }
+  }
+}
+
+type Connect<C> = Box<dyn Fn(SocketAddr) -> BoxFuture<'static, io::Result<C>> + Send + Sync>;
+
+/// One endpoint as last seen by the balancer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointStatus {
+  pub addr: SocketAddr,
+  pub in_flight: usize,
+  pub ejected: bool,
+}
+
+struct Endpoint<C> {
+  addr: SocketAddr,
+  client: tokio::sync::Mutex<Option<C>>,
+  in_flight: AtomicUsize,
+  breaker: Mutex<Breaker>,
+}
+
+/// Balances calls to one service over a client per endpoint, `C` being a client generated by
+/// `#[tarpc::service]`.
+pub struct Balancer<C> {
+  service: String,
+  resolver: Box<dyn Resolver>,
+  connect: Connect<C>,
+  config: Config,
+  budget: Budget,
+  endpoints: RwLock<Arc<[Arc<Endpoint<C>>]>>,
+  refreshed: tokio::sync::Mutex<Option<Instant>>,
+}
+
+/// Why an attempt failed, and whether the request may have reached the server.
+struct Failure {
+  error: Error,
+  maybe_sent: bool,
+}
+
+impl<C: Clone + Send + Sync + 'static> Balancer<C> {
+  /// Connects lazily, with `connect`, to the endpoints of `service` given by `resolver`.
+  pub fn new<F, Fut>(
+    service: impl Into<String>,
+    resolver: impl Resolver,
+    connect: F,
+    config: Config,
+  ) -> Balancer<C>
+  where
+    F: Fn(SocketAddr) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = io::Result<C>> + Send + 'static,
+  {
+    Balancer {
+      service: service.into(),
+      resolver: Box::new(resolver),
+      connect: Box::new(move |addr| connect(addr).boxed()),
+      budget: Budget::new(config.retry_ratio, config.min_retries_per_sec),
+      config,
+      endpoints: RwLock::new(Arc::new([])),
+      refreshed: tokio::sync::Mutex::new(None),
+    }
+  }
+
+  /// Resolves the service now. Endpoints still listed keep their connection and state; when
+  /// resolving fails, the endpoints known are kept.
+  pub async fn refresh(&self) -> Result<(), Error> {
+    let mut refreshed = self.refreshed.lock().await;
+    self.resolve().await?;
+    *refreshed = Some(Instant::now());
+    Ok(())
+  }
+
+  async fn resolve(&self) -> Result<(), Error> {
+    let addrs = self
+      .resolver
+      .resolve(&self.service)
+      .await
+      .map_err(|source| Error::Resolve {
+        service: self.service.clone(),
+        source,
+      })?;
+
+    let mut endpoints = self.endpoints.write().unwrap();
+    let updated = addrs
+      .into_iter()
+      .map(|addr| match endpoints.iter().find(|e| e.addr == addr) {
+        Some(endpoint) => endpoint.clone(),
+        None => Arc::new(Endpoint {
+          addr,
+          client: tokio::sync::Mutex::new(None),
+          in_flight: AtomicUsize::new(0),
+          breaker: Mutex::new(Breaker::new(
+            self.config.failure_threshold,
+            self.config.ejection,
+          )),
+        }),
+      })
+      .collect::<Vec<_>>();
+    *endpoints = updated.into();
+    Ok(())
+  }
+
+  async fn refresh_if_stale(&self) -> Result<(), Error> {
+    let mut refreshed = self.refreshed.lock().await;
+    let stale = refreshed.is_none_or(|at| at.elapsed() >= self.config.refresh_interval);
+    if !stale {
+      return Ok(());
+    }
+    match self.resolve().await {
+      Ok(()) => {}
+      Err(e) if refreshed.is_none() => return Err(e),
+      Err(e) => tracing::warn!("keeping the endpoints known: {}", e),
+    }
+    *refreshed = Some(Instant::now());
+    Ok(())
+  }
+
+  pub fn endpoints(&self) -> Vec<EndpointStatus> {
+    let now = Instant::now();
+    self
+      .endpoints
+      .read()
+      .unwrap()
+      .iter()
+      .map(|e| EndpointStatus {
+        addr: e.addr,
+        in_flight: e.in_flight.load(Ordering::Relaxed),
+        ejected: e.breaker.lock().unwrap().ejected(now),
+      })
+      .collect()
+  }
+
+  /// Power of two choices: the less busy of two endpoints taken at random among those not
+  /// ejected.
+  /// Retries go to endpoints not `tried` yet, as long as there are some.
+  fn pick(&self, tried: &[SocketAddr]) -> Option<Arc<Endpoint<C>>> {
+    let endpoints = self.endpoints.read().unwrap().clone();
+    let now = Instant::now();
+    let mut available = endpoints
+      .iter()
+      .filter(|e| e.breaker.lock().unwrap().available(now))
+      .collect::<Vec<_>>();
+    if available.iter().any(|e| !tried.contains(&e.addr)) {
+      available.retain(|e| !tried.contains(&e.addr));
+    }
+
+    while !available.is_empty() {
+      let two = available
+        .choose_multiple(&mut rand::thread_rng(), 2)
+        .collect::<Vec<_>>();
+      let chosen = match two.as_slice() {
+        [a, b] if b.in_flight.load(Ordering::Relaxed) < a.in_flight.load(Ordering::Relaxed) => **b,
+        [a, ..] => **a,
+        [] => unreachable!(),
+      };
+      if chosen.breaker.lock().unwrap().acquire(now) {
+        return Some(Arc::clone(chosen));
+      }
+      // Its trial call was taken in the meantime
+      available.retain(|e| !Arc::ptr_eq(e, chosen));
+    }
+    None
+  }
+
+  /// Calls that may have side effects: retried only when the request cannot have been sent.
+  pub async fn call<T, F, Fut>(&self, ctx: context::Context, f: F) -> Result<T, Error>
+  where
+    F: Fn(C, context::Context) -> Fut,
+    Fut: Future<Output = Result<T, RpcError>>,
+  {
+    self.call_with(ctx, false, f).await
+  }
+
+  /// Calls safe to make more than once: retried after any failure of the endpoint, within the
+  /// deadline of `ctx` and the retry budget.
+  pub async fn call_idempotent<T, F, Fut>(&self, ctx: context::Context, f: F) -> Result<T, Error>
+  where
+    F: Fn(C, context::Context) -> Fut,
+    Fut: Future<Output = Result<T, RpcError>>,
+  {
+    self.call_with(ctx, true, f).await
+  }
+
+  async fn call_with<T, F, Fut>(
+    &self,
+    ctx: context::Context,
+    idempotent: bool,
+    f: F,
+  ) -> Result<T, Error>
+  where
+    F: Fn(C, context::Context) -> Fut,
+    Fut: Future<Output = Result<T, RpcError>>,
+  {
+    self.refresh_if_stale().await?;
+    self.budget.deposit();
+
+    let mut attempt = 1;
+    let mut tried = Vec::new();
+    loop {
+      let endpoint = self
+        .pick(&tried)
+        .ok_or_else(|| Error::NoEndpoints(self.service.clone()))?;
+      let span = tracing::info_span!(
+        "attempt",
+        service = %self.service,
+        peer = %endpoint.addr,
+        attempt,
+      );
+      follow(&span, &ctx);
+
+      let failure = match self.attempt(&endpoint, &ctx, &f).instrument(span).await {
+        Ok(response) => return Ok(response),
+        Err(failure) => failure,
+      };
+      tried.push(endpoint.addr);
+      if (failure.maybe_sent && !idempotent)
+        || matches!(failure.error, Error::DeadlineExceeded)
+        || attempt >= self.config.max_attempts
+      {
+        return Err(failure.error);
+      }
+
+      let wait = backoff(self.config.backoff_base, self.config.backoff_max, attempt);
+      if Instant::now() + wait >= ctx.deadline || !self.budget.withdraw() {
+        return Err(failure.error);
+      }
+      tracing::debug!("retrying after {:?}: {}", wait, failure.error);
+      tokio::time::sleep(wait).await;
+      attempt += 1;
+    }
+  }
+
+  async fn attempt<T, F, Fut>(
+    &self,
+    endpoint: &Endpoint<C>,
+    ctx: &context::Context,
+    f: &F,
+  ) -> Result<T, Failure>
+  where
+    F: Fn(C, context::Context) -> Fut,
+    Fut: Future<Output = Result<T, RpcError>>,
+  {
+    let mut call = InFlight::start(endpoint);
+    let now = Instant::now();
+    if now >= ctx.deadline {
+      return Err(Failure {
+        error: Error::DeadlineExceeded,
+        maybe_sent: false,
+      });
+    }
+
+    let client = match self.client(endpoint, ctx.deadline - now).await {
+      Ok(client) => client,
+      Err(source) => {
+        call.failed();
+        return Err(Failure {
+          error: Error::Connect {
+            addr: endpoint.addr,
+            source,
+          },
+          maybe_sent: false,
+        });
+      }
+    };
+
+    let mut attempt_ctx = *ctx;
+    if let Some(per_try) = self.config.per_try_timeout {
+      attempt_ctx.deadline = attempt_ctx.deadline.min(Instant::now() + per_try);
+    }
+    let error = match f(client, attempt_ctx).await {
+      Ok(response) => {
+        call.succeeded();
+        return Ok(response);
+      }
+      // The server answered, with an error of its own
+      Err(RpcError::Server(source)) => {
+        call.succeeded();
+        return Err(Failure {
+          error: Error::Rpc {
+            addr: endpoint.addr,
+            source: RpcError::Server(source),
+          },
+          maybe_sent: true,
+        });
+      }
+      Err(RpcError::DeadlineExceeded) if Instant::now() >= ctx.deadline => {
+        call.released();
+        Error::DeadlineExceeded
+      }
+      Err(RpcError::DeadlineExceeded) => {
+        call.failed();
+        Error::Rpc {
+          addr: endpoint.addr,
+          source: RpcError::DeadlineExceeded,
+        }
+      }
+      Err(source) => {
+        // The connection is gone, make a new one next time
+        *endpoint.client.lock().await = None;
+        call.failed();
+        Error::Rpc {
+          addr: endpoint.addr,
+          source,
+        }
+      }
+    };
+    Err(Failure {
+      error,
+      maybe_sent: true,
+    })
+  }
+
+  async fn client(&self, endpoint: &Endpoint<C>, time_left: Duration) -> io::Result<C> {
+    let mut client = endpoint.client.lock().await;
+    if let Some(client) = &*client {
+      return Ok(client.clone());
+    }
+    let timeout = self.config.connect_timeout.min(time_left);
+    let connected = tokio::time::timeout(timeout, (self.connect)(endpoint.addr))
+      .await
+      .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))??;
+    *client = Some(connected.clone());
+    Ok(connected)
+  }
+}
+
+/// Makes `span` a child of the trace carried by `ctx`, so that every attempt, and the RPC under
+/// it, is part of the trace of the caller.
+fn follow(span: &tracing::Span, ctx: &context::Context) {
+  let trace_id = TraceId::from(ctx.trace_context.trace_id);
+  if trace_id == TraceId::INVALID {
+    return;
+  }
+  span.set_parent(
+    opentelemetry::Context::new().with_remote_span_context(SpanContext::new(
+      trace_id,
+      ctx.trace_context.span_id.into(),
+      TraceFlags::from(ctx.trace_context.sampling_decision),
+      true,
+      TraceState::default(),
+    )),
+  );
+}
+
+/// Counts an attempt in the load of its endpoint and reports how it went to the breaker,
+/// including when it is dropped halfway.
+struct InFlight<'a, C> {
+  endpoint: &'a Endpoint<C>,
+  reported: bool,
+}
+
+impl<'a, C> InFlight<'a, C> {
+  fn start(endpoint: &'a Endpoint<C>) -> InFlight<'a, C> {
+    endpoint.in_flight.fetch_add(1, Ordering::Relaxed);
+    InFlight {
+      endpoint,
+      reported: false,
+    }
+  }
+
+  fn report(&mut self, report: impl FnOnce(&mut Breaker)) {
+    report(&mut self.endpoint.breaker.lock().unwrap());
+    self.reported = true;
+  }
+
+  fn succeeded(&mut self) {
+    self.report(Breaker::success)
+  }
+
+  fn failed(&mut self) {
+    self.report(|breaker| breaker.failure(Instant::now()))
+  }
+
+  fn released(&mut self) {
+    self.report(Breaker::release)
+  }
+}
+
+impl<C> Drop for InFlight<'_, C> {
+  fn drop(&mut self) {
+    self.endpoint.in_flight.fetch_sub(1, Ordering::Relaxed);
+    if !self.reported {
+      self.endpoint.breaker.lock().unwrap().release();
+    }
+  }
+}
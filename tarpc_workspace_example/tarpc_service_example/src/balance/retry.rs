@@ -0,0 +1,105 @@
+use std::{
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+use rand::Rng;
+
+/// Bounds retries to a share of the calls made, so that a struggling service does not get
+/// several times its usual load: every call deposits `ratio` of a retry, every retry withdraws a
+/// whole one. `min_per_sec` retries a second are allowed regardless, for services called
+/// rarely.
+#[derive(Debug)]
+pub(crate) struct Budget {
+  ratio: f64,
+  min_per_sec: f64,
+  state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+  deposited: f64,
+  reserve: f64,
+  refilled: Instant,
+}
+
+/// Deposits are capped so that a long quiet spell cannot pay for a retry storm.
+const MAX_DEPOSITS: f64 = 100.0;
+
+impl Budget {
+  pub(crate) fn new(ratio: f64, min_per_sec: u32) -> Budget {
+    Budget {
+      ratio,
+      min_per_sec: min_per_sec as f64,
+      state: Mutex::new(State {
+        deposited: 0.0,
+        reserve: min_per_sec as f64,
+        refilled: Instant::now(),
+      }),
+    }
+  }
+
+  pub(crate) fn deposit(&self) {
+    let mut state = self.state.lock().unwrap();
+    state.deposited = (state.deposited + self.ratio).min(MAX_DEPOSITS);
+  }
+
+  pub(crate) fn withdraw(&self) -> bool {
+    let mut state = self.state.lock().unwrap();
+    let now = Instant::now();
+    let elapsed = now.duration_since(state.refilled).as_secs_f64();
+    state.reserve = (state.reserve + elapsed * self.min_per_sec).min(self.min_per_sec);
+    state.refilled = now;
+
+    if state.deposited >= 1.0 {
+      state.deposited -= 1.0;
+      true
+    } else if state.reserve >= 1.0 {
+      state.reserve -= 1.0;
+      true
+    } else {
+      false
+    }
+  }
+}
+
+/// How long to wait before retry number `retry`, from 1: exponential from `base` up to `max`,
+/// with full jitter.
+pub(crate) fn backoff(base: Duration, max: Duration, retry: u32) -> Duration {
+  let ceiling = base.saturating_mul(1 << (retry - 1).min(16)).min(max);
+  ceiling.mul_f64(rand::thread_rng().gen_range(0.0 ..= 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn budget_follows_deposits() {
+    let budget = Budget::new(0.5, 0);
+    assert!(!budget.withdraw());
+    budget.deposit();
+    assert!(!budget.withdraw());
+    budget.deposit();
+    assert!(budget.withdraw());
+    assert!(!budget.withdraw());
+  }
+
+  #[test]
+  fn budget_keeps_a_reserve() {
+    let budget = Budget::new(0.0, 2);
+    assert!(budget.withdraw());
+    assert!(budget.withdraw());
+    assert!(!budget.withdraw());
+  }
+
+  #[test]
+  fn backoff_grows_up_to_max() {
+    let base = Duration::from_millis(10);
+    let max = Duration::from_millis(50);
+    for retry in 1 .. 40 {
+      let ceiling = (base * 2u32.pow((retry - 1).min(16))).min(max);
+      assert!(backoff(base, max, retry) <= ceiling);
+    }
+  }
+}
@@ -4,19 +4,31 @@
 // license that can be found in the LICENSE file or at
 // https://opensource.org/licenses/MIT.
 
-use std::{net::SocketAddr, time::Duration};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
 use clap::Parser;
-use service::{init_tracing, WorldClient};
-use tarpc::{client, context, tokio_serde::formats::Json};
+use service::{
+  balance::{Balancer, Config},
+  connect_world,
+  discovery::{RemoteRegistry, Resolver, Static, StaticFile},
+  init_tracing, WorldClient,
+};
+use tarpc::context;
 use tokio::time::sleep;
 use tracing::Instrument;
 
 #[derive(Parser)]
+#[clap(group(clap::ArgGroup::new("target").required(true)))]
 struct Flags {
   /// Sets the server address to connect to.
-  #[clap(long)]
-  server_addr: SocketAddr,
+  #[clap(long, group = "target")]
+  server_addr: Option<SocketAddr>,
+  /// Sets a file listing the endpoints of `world`, a line `world host:port...`.
+  #[clap(long, group = "target")]
+  endpoints: Option<PathBuf>,
+  /// Sets the address of a registry to look up `world` in.
+  #[clap(long, group = "target")]
+  registry: Option<SocketAddr>,
   /// Sets the name to say hello to.
   #[clap(long)]
   name: String,
@@ -27,18 +39,29 @@ async fn main() -> anyhow::Result<()> {
   let flags = Flags::parse();
   init_tracing("Tarpc Example Client")?;
 
-  let mut transport = tarpc::serde_transport::tcp::connect(flags.server_addr, Json::default);
-  transport.config_mut().max_frame_length(usize::MAX);
-
-  // WorldClient is generated by the service attribute. It has a constructor `new` that takes a
-  // config and any Transport as input.
-  let client = WorldClient::new(client::Config::default(), transport.await?).spawn();
+  let resolver: Box<dyn Resolver> = match (flags.server_addr, flags.endpoints, flags.registry) {
+    (Some(addr), ..) => Box::new(Static(vec![addr])),
+    (_, Some(path), _) => Box::new(StaticFile(path)),
+    (.., Some(addr)) => Box::new(RemoteRegistry::connect(addr).await?),
+    _ => unreachable!("clap requires one of them"),
+  };
+  // Connections are made as calls need them, with `connect_world`, to the endpoints the
+  // resolver finds for `world`.
+  let world = Balancer::<WorldClient>::new("world", resolver, connect_world, Config::default());
 
+  let name = flags.name;
   let hello = async move {
     // Send the request twice, just to be safe! ;)
+    let hello = |n: u32| {
+      let name = format!("{name}{n}");
+      world.call_idempotent(context::current(), move |client, ctx| {
+        let name = name.clone();
+        async move { client.hello(ctx, name).await }
+      })
+    };
     tokio::select! {
-        hello1 = client.hello(context::current(), format!("{}1", flags.name)) => { hello1 }
-        hello2 = client.hello(context::current(), format!("{}2", flags.name)) => { hello2 }
+        hello1 = hello(1) => { hello1 }
+        hello2 = hello(2) => { hello2 }
     }
   }
   .instrument(tracing::info_span!("Two Hellos"))
@@ -0,0 +1,194 @@
+//! Where the endpoints of a service are: a fixed list, a file, or a registry servers announce
+//! themselves to.
+
+use std::{
+  collections::HashMap,
+  io,
+  net::SocketAddr,
+  path::PathBuf,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+use futures::{future::BoxFuture, FutureExt};
+use tarpc::{client, context, tokio_serde::formats::Json};
+
+/// Resolves a service name to the addresses serving it, possibly none.
+pub trait Resolver: Send + Sync + 'static {
+  fn resolve<'a>(&'a self, service: &'a str) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>>;
+}
+
+impl<R: Resolver + ?Sized> Resolver for Box<R> {
+  fn resolve<'a>(&'a self, service: &'a str) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+    (**self).resolve(service)
+  }
+}
+
+/// The same addresses whatever the name.
+#[derive(Debug, Clone)]
+pub struct Static(pub Vec<SocketAddr>);
+
+impl Resolver for Static {
+  fn resolve<'a>(&'a self, _: &'a str) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+    futures::future::ready(Ok(self.0.clone())).boxed()
+  }
+}
+
+/// A file read again on every resolution, with a line per service: its name, then its
+/// endpoints as `host:port`. Blank lines and lines starting with `#` are skipped.
+///
+/// ```text
+/// # service  endpoints
+/// world      127.0.0.1:8089 localhost:8090
+/// ```
+#[derive(Debug, Clone)]
+pub struct StaticFile(pub PathBuf);
+
+impl Resolver for StaticFile {
+  fn resolve<'a>(&'a self, service: &'a str) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+    async move {
+      let text = tokio::fs::read_to_string(&self.0).await?;
+      let mut addrs = Vec::new();
+      for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+          continue;
+        }
+        let mut words = line.split_whitespace();
+        if words.next() != Some(service) {
+          continue;
+        }
+        for endpoint in words {
+          addrs.extend(tokio::net::lookup_host(endpoint).await?);
+        }
+      }
+      Ok(addrs)
+    }
+    .boxed()
+  }
+}
+
+/// Registrations asking for longer are kept this long: servers renew well before.
+pub const MAX_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Keeps track of the servers of each service. Servers register for a while and register again
+/// before that runs out.
+#[tarpc::service]
+pub trait Registry {
+  /// Lists `addr` under `service` for `ttl`, up to [`MAX_TTL`].
+  async fn register(service: String, addr: SocketAddr, ttl: Duration);
+  async fn deregister(service: String, addr: SocketAddr);
+  /// The addresses registered under `service` and not expired.
+  async fn resolve(service: String) -> Vec<SocketAddr>;
+}
+
+/// An in-memory registry, to serve over tarpc or to resolve from directly in-process.
+#[derive(Debug, Clone, Default)]
+pub struct RegistryServer {
+  services: Arc<Mutex<HashMap<String, HashMap<SocketAddr, Instant>>>>,
+}
+
+impl RegistryServer {
+  pub fn new() -> RegistryServer {
+    RegistryServer::default()
+  }
+
+  /// Lists `addr` under `service` for `ttl`, up to [`MAX_TTL`].
+  pub fn add(&self, service: &str, addr: SocketAddr, ttl: Duration) {
+    let mut services = self.services.lock().unwrap();
+    services
+      .entry(service.to_owned())
+      .or_default()
+      .insert(addr, Instant::now() + ttl.min(MAX_TTL));
+  }
+
+  pub fn remove(&self, service: &str, addr: SocketAddr) {
+    if let Some(addrs) = self.services.lock().unwrap().get_mut(service) {
+      addrs.remove(&addr);
+    }
+  }
+
+  /// Sorted, so that resolutions are easy to compare.
+  pub fn addrs(&self, service: &str) -> Vec<SocketAddr> {
+    let mut services = self.services.lock().unwrap();
+    let Some(addrs) = services.get_mut(service) else {
+      return Vec::new();
+    };
+    let now = Instant::now();
+    addrs.retain(|_, expires| *expires > now);
+    let mut addrs = addrs.keys().copied().collect::<Vec<_>>();
+    addrs.sort();
+    addrs
+  }
+}
+
+impl Registry for RegistryServer {
+  async fn register(self, _: context::Context, service: String, addr: SocketAddr, ttl: Duration) {
+    self.add(&service, addr, ttl)
+  }
+
+  async fn deregister(self, _: context::Context, service: String, addr: SocketAddr) {
+    self.remove(&service, addr)
+  }
+
+  async fn resolve(self, _: context::Context, service: String) -> Vec<SocketAddr> {
+    self.addrs(&service)
+  }
+}
+
+impl Resolver for RegistryServer {
+  fn resolve<'a>(&'a self, service: &'a str) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+    futures::future::ready(Ok(self.addrs(service))).boxed()
+  }
+}
+
+/// Resolves through a [`Registry`] served over tarpc.
+#[derive(Clone)]
+pub struct RemoteRegistry(pub RegistryClient);
+
+impl RemoteRegistry {
+  pub async fn connect(addr: SocketAddr) -> io::Result<RemoteRegistry> {
+    let transport = tarpc::serde_transport::tcp::connect(addr, Json::default).await?;
+    Ok(RemoteRegistry(
+      RegistryClient::new(client::Config::default(), transport).spawn(),
+    ))
+  }
+}
+
+impl Resolver for RemoteRegistry {
+  fn resolve<'a>(&'a self, service: &'a str) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+    async move {
+      self
+        .0
+        .resolve(context::current(), service.to_owned())
+        .await
+        .map_err(io::Error::other)
+    }
+    .boxed()
+  }
+}
+
+/// Renewals are at least this far apart, however short the ttl.
+const MIN_RENEWAL: Duration = Duration::from_millis(10);
+
+/// Keeps `addr` registered under `service` until the returned task is aborted, renewing it
+/// every half `ttl`, as capped by [`MAX_TTL`]. Failures are logged and retried at the next
+/// renewal.
+pub fn announce(
+  registry: RegistryClient,
+  service: String,
+  addr: SocketAddr,
+  ttl: Duration,
+) -> tokio::task::JoinHandle<()> {
+  tokio::spawn(async move {
+    let mut renew = tokio::time::interval((ttl.min(MAX_TTL) / 2).max(MIN_RENEWAL));
+    loop {
+      renew.tick().await;
+      if let Err(e) = registry
+        .register(context::current(), service.clone(), addr, ttl)
+        .await
+      {
+        tracing::warn!("cannot register {} at {}: {}", service, addr, e);
+      }
+    }
+  })
+}
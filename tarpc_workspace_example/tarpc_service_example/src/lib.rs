@@ -4,9 +4,15 @@
 // license that can be found in the LICENSE file or at
 // https://opensource.org/licenses/MIT.
 
+use std::{io, net::SocketAddr};
+
 use opentelemetry::trace::TracerProvider as _;
+use tarpc::{client, tokio_serde::formats::Json};
 use tracing_subscriber::{fmt::format::FmtSpan, prelude::*};
 
+pub mod balance;
+pub mod discovery;
+
 /// This is the service definition. It looks a lot like a trait definition.
 /// It defines one RPC, hello, which takes one arg, name, and returns a String.
 #[tarpc::service]
@@ -15,6 +21,13 @@ pub trait World {
   async fn hello(name: String) -> String;
 }
 
+/// Connects a `WorldClient` over TCP with the JSON transport the server listens with.
+pub async fn connect_world(addr: SocketAddr) -> io::Result<WorldClient> {
+  let mut transport = tarpc::serde_transport::tcp::connect(addr, Json::default);
+  transport.config_mut().max_frame_length(usize::MAX);
+  Ok(WorldClient::new(client::Config::default(), transport.await?).spawn())
+}
+
 /// Initializes an OpenTelemetry tracing subscriber with a OTLP backend.
 pub fn init_tracing(service_name: &'static str) -> anyhow::Result<()> {
   let tracer_provider = opentelemetry_otlp::new_pipeline()
@@ -0,0 +1,43 @@
+use std::net::{IpAddr, Ipv6Addr};
+
+use clap::Parser;
+use futures::{future, prelude::*};
+use service::discovery::{Registry, RegistryServer};
+use tarpc::{
+  server::{self, Channel},
+  tokio_serde::formats::Json,
+};
+
+#[derive(Parser)]
+struct Flags {
+  /// Sets the port number to listen on.
+  #[clap(long)]
+  port: u16,
+}
+
+async fn spawn(fut: impl Future<Output = ()> + Send + 'static) {
+  tokio::spawn(fut);
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+  let flags = Flags::parse();
+  tracing_subscriber::fmt::init();
+
+  let registry = RegistryServer::new();
+  let registry_addr = (IpAddr::V6(Ipv6Addr::LOCALHOST), flags.port);
+  let listener = tarpc::serde_transport::tcp::listen(&registry_addr, Json::default).await?;
+  tracing::info!(
+    "Registry listening on port {}",
+    listener.local_addr().port()
+  );
+  listener
+    .filter_map(|r| future::ready(r.ok()))
+    .map(server::BaseChannel::with_defaults)
+    .map(|channel| channel.execute(registry.clone().serve()).for_each(spawn))
+    .buffer_unordered(100)
+    .for_each(|_| async {})
+    .await;
+
+  Ok(())
+}
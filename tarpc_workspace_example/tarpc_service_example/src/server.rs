@@ -15,9 +15,12 @@ use rand::{
   distributions::{Distribution, Uniform},
   thread_rng,
 };
-use service::{init_tracing, World};
+use service::{
+  discovery::{announce, RegistryClient},
+  init_tracing, World,
+};
 use tarpc::{
-  context,
+  client, context,
   server::{self, incoming::Incoming, Channel},
   tokio_serde::formats::Json,
 };
@@ -28,6 +31,9 @@ struct Flags {
   /// Sets the port number to listen on.
   #[clap(long)]
   port: u16,
+  /// Sets the address of a registry to announce the server to, as `world`.
+  #[clap(long)]
+  registry: Option<SocketAddr>,
 }
 
 // This is the type that implements the generated World trait. It is the business logic
@@ -59,6 +65,23 @@ async fn main() -> anyhow::Result<()> {
   let mut listener = tarpc::serde_transport::tcp::listen(&server_addr, Json::default).await?;
   tracing::info!("Listening on port {}", listener.local_addr().port());
   listener.config_mut().max_frame_length(usize::MAX);
+
+  let _announced = match flags.registry {
+    Some(registry_addr) => {
+      let transport = tarpc::serde_transport::tcp::connect(registry_addr, Json::default).await?;
+      let registry = RegistryClient::new(client::Config::default(), transport).spawn();
+      let addr = listener.local_addr();
+      tracing::info!("Announcing {} to {}", addr, registry_addr);
+      Some(announce(
+        registry,
+        "world".to_string(),
+        addr,
+        Duration::from_secs(10),
+      ))
+    }
+    None => None,
+  };
+
   listener
     // Ignore accept errors.
     .filter_map(|r| future::ready(r.ok()))
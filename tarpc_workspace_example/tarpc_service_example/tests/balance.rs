@@ -0,0 +1,393 @@
+use std::{
+  net::SocketAddr,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant},
+};
+
+use futures::{future, prelude::*};
+use service::{
+  balance::{Balancer, Config, Error},
+  connect_world,
+  discovery::{
+    announce, Registry, RegistryClient, RegistryServer, RemoteRegistry, Resolver, Static,
+    StaticFile,
+  },
+  World, WorldClient,
+};
+use tarpc::{
+  client, context,
+  server::{BaseChannel, Channel},
+  tokio_serde::formats::Json,
+  trace::TraceId,
+};
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+use tracing_subscriber::prelude::*;
+
+/// A World server that counts its calls and remembers their traces.
+#[derive(Clone)]
+struct Hello {
+  name: &'static str,
+  delay: Duration,
+  calls: Arc<AtomicUsize>,
+  traces: Arc<Mutex<Vec<TraceId>>>,
+}
+
+impl World for Hello {
+  async fn hello(self, ctx: context::Context, name: String) -> String {
+    self.calls.fetch_add(1, Ordering::SeqCst);
+    self.traces.lock().unwrap().push(*ctx.trace_id());
+    tokio::time::sleep(self.delay).await;
+    format!("{}: Hello, {name}!", self.name)
+  }
+}
+
+struct Server {
+  addr: SocketAddr,
+  hello: Hello,
+  task: JoinHandle<()>,
+}
+
+impl Server {
+  async fn start(name: &'static str, delay: Duration) -> Server {
+    let listener = tarpc::serde_transport::tcp::listen("127.0.0.1:0", Json::default)
+      .await
+      .unwrap();
+    let addr = listener.local_addr();
+    let hello = Hello {
+      name,
+      delay,
+      calls: Arc::default(),
+      traces: Arc::default(),
+    };
+    let server = hello.clone();
+    let task = tokio::spawn(
+      listener
+        .filter_map(|r| future::ready(r.ok()))
+        .map(BaseChannel::with_defaults)
+        .map(move |channel| {
+          channel.execute(server.clone().serve()).for_each(|f| async {
+            tokio::spawn(f);
+          })
+        })
+        .buffer_unordered(10)
+        .for_each(|_| async {}),
+    );
+    Server { addr, hello, task }
+  }
+
+  fn calls(&self) -> usize {
+    self.hello.calls.load(Ordering::SeqCst)
+  }
+
+  /// Closes the listener and every connection.
+  async fn kill(&self) {
+    self.task.abort();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+  }
+}
+
+/// An address nothing listens on.
+async fn dead_addr() -> SocketAddr {
+  let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+  listener.local_addr().unwrap()
+}
+
+fn balancer(resolver: impl Resolver, config: Config) -> Balancer<WorldClient> {
+  Balancer::new("world", resolver, connect_world, config)
+}
+
+async fn hello(world: &Balancer<WorldClient>) -> Result<String, Error> {
+  world
+    .call_idempotent(context::current(), |client, ctx| async move {
+      client.hello(ctx, "you".to_string()).await
+    })
+    .await
+}
+
+async fn hello_once(world: &Balancer<WorldClient>) -> Result<String, Error> {
+  world
+    .call(context::current(), |client, ctx| async move {
+      client.hello(ctx, "you".to_string()).await
+    })
+    .await
+}
+
+#[tokio::test]
+async fn spreads_calls_over_endpoints() {
+  let servers =
+    future::join_all(["a", "b", "c"].map(|name| Server::start(name, Duration::ZERO))).await;
+  let world = balancer(
+    Static(servers.iter().map(|s| s.addr).collect()),
+    Config::default(),
+  );
+
+  for _ in 0 .. 3 {
+    let replies = future::join_all((0 .. 30).map(|_| hello(&world))).await;
+    assert!(replies.iter().all(Result::is_ok));
+  }
+  for server in &servers {
+    assert!(server.calls() > 0, "{} got no calls", server.hello.name);
+  }
+  assert_eq!(servers.iter().map(Server::calls).sum::<usize>(), 90);
+}
+
+#[tokio::test]
+async fn prefers_the_less_busy_endpoint() {
+  let slow = Server::start("slow", Duration::from_millis(200)).await;
+  let fast = Server::start("fast", Duration::ZERO).await;
+  let world = balancer(Static(vec![slow.addr, fast.addr]), Config::default());
+
+  let replies = stream::iter(0 .. 40)
+    .map(|_| hello(&world))
+    .buffer_unordered(4)
+    .collect::<Vec<_>>()
+    .await;
+  assert!(replies.iter().all(Result::is_ok));
+  assert!(
+    slow.calls() < fast.calls(),
+    "slow {} fast {}",
+    slow.calls(),
+    fast.calls()
+  );
+}
+
+#[tokio::test]
+async fn retries_idempotent_calls_elsewhere() {
+  let a = Server::start("a", Duration::ZERO).await;
+  let b = Server::start("b", Duration::ZERO).await;
+  let world = balancer(Static(vec![a.addr, b.addr]), Config::default());
+  while a.calls() == 0 || b.calls() == 0 {
+    hello(&world).await.unwrap();
+  }
+
+  a.kill().await;
+  for _ in 0 .. 20 {
+    assert!(hello(&world).await.unwrap().starts_with("b:"));
+  }
+}
+
+#[tokio::test]
+async fn does_not_retry_calls_that_may_have_been_sent() {
+  let a = Server::start("a", Duration::ZERO).await;
+  let world = balancer(Static(vec![a.addr]), Config::default());
+  hello_once(&world).await.unwrap();
+
+  a.kill().await;
+  // The connection was there: the request may have gone out
+  assert!(matches!(hello_once(&world).await, Err(Error::Rpc { .. })));
+  // Then there is nothing to connect to
+  assert!(matches!(
+    hello_once(&world).await,
+    Err(Error::Connect { .. })
+  ));
+}
+
+#[tokio::test]
+async fn ejects_failing_endpoints() {
+  let live = Server::start("live", Duration::ZERO).await;
+  let dead = dead_addr().await;
+  let world = balancer(
+    Static(vec![dead, live.addr]),
+    Config {
+      failure_threshold: 2,
+      ejection: Duration::from_secs(60),
+      ..Config::default()
+    },
+  );
+
+  // Failing to connect is retried even for calls that are not idempotent
+  for _ in 0 .. 30 {
+    assert!(hello_once(&world).await.unwrap().starts_with("live:"));
+  }
+  let endpoints = world.endpoints();
+  assert!(endpoints.iter().any(|e| e.addr == dead && e.ejected));
+  assert!(endpoints.iter().any(|e| e.addr == live.addr && !e.ejected));
+}
+
+#[tokio::test]
+async fn gives_up_at_the_deadline() {
+  let slow = Server::start("slow", Duration::from_millis(500)).await;
+  let world = balancer(Static(vec![slow.addr]), Config::default());
+
+  let start = Instant::now();
+  let mut ctx = context::current();
+  ctx.deadline = start + Duration::from_millis(100);
+  let reply = world
+    .call_idempotent(ctx, |client, ctx| async move {
+      client.hello(ctx, "you".to_string()).await
+    })
+    .await;
+  assert!(matches!(reply, Err(Error::DeadlineExceeded)), "{:?}", reply);
+  assert!(start.elapsed() < Duration::from_millis(400));
+}
+
+#[tokio::test]
+async fn moves_on_from_slow_attempts() {
+  let slow = Server::start("slow", Duration::from_secs(1)).await;
+  let fast = Server::start("fast", Duration::ZERO).await;
+  let world = balancer(
+    Static(vec![slow.addr, fast.addr]),
+    Config {
+      per_try_timeout: Some(Duration::from_millis(50)),
+      failure_threshold: 1,
+      ..Config::default()
+    },
+  );
+
+  for _ in 0 .. 10 {
+    assert!(hello(&world).await.unwrap().starts_with("fast:"));
+  }
+  assert!(
+    world
+      .endpoints()
+      .iter()
+      .any(|e| e.addr == slow.addr && e.ejected)
+      || slow.calls() == 0
+  );
+}
+
+#[tokio::test]
+async fn resolves_a_static_file() {
+  let path = std::env::temp_dir().join(format!("tarpc-endpoints-{}", std::process::id()));
+  std::fs::write(
+    &path,
+    "# service endpoints\nworld 127.0.0.1:1 localhost:2\n\nother 127.0.0.1:3\n",
+  )
+  .unwrap();
+  let file = StaticFile(path.clone());
+
+  let world = file.resolve("world").await.unwrap();
+  assert_eq!(world[0], "127.0.0.1:1".parse().unwrap());
+  assert!(world[1 ..]
+    .iter()
+    .all(|a| a.port() == 2 && a.ip().is_loopback()));
+  assert_eq!(
+    file.resolve("other").await.unwrap(),
+    vec!["127.0.0.1:3".parse().unwrap()]
+  );
+  assert!(file.resolve("missing").await.unwrap().is_empty());
+
+  let missing = Balancer::new("missing", file, connect_world, Config::default());
+  assert!(matches!(hello(&missing).await, Err(Error::NoEndpoints(_))));
+  std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn follows_a_registry() {
+  let a = Server::start("a", Duration::ZERO).await;
+  let b = Server::start("b", Duration::ZERO).await;
+
+  let registry = RegistryServer::new();
+  let listener = tarpc::serde_transport::tcp::listen("127.0.0.1:0", Json::default)
+    .await
+    .unwrap();
+  let registry_addr = listener.local_addr();
+  let served = registry.clone();
+  tokio::spawn(
+    listener
+      .filter_map(|r| future::ready(r.ok()))
+      .map(BaseChannel::with_defaults)
+      .map(move |channel| {
+        channel.execute(served.clone().serve()).for_each(|f| async {
+          tokio::spawn(f);
+        })
+      })
+      .buffer_unordered(10)
+      .for_each(|_| async {}),
+  );
+  let transport = tarpc::serde_transport::tcp::connect(registry_addr, Json::default)
+    .await
+    .unwrap();
+  let client = RegistryClient::new(client::Config::default(), transport).spawn();
+
+  let ttl = Duration::from_millis(200);
+  let announced = announce(client.clone(), "world".to_string(), a.addr, ttl);
+  let world = balancer(
+    RemoteRegistry::connect(registry_addr).await.unwrap(),
+    Config {
+      refresh_interval: Duration::ZERO,
+      ..Config::default()
+    },
+  );
+  tokio::time::sleep(Duration::from_millis(50)).await;
+  assert!(hello(&world).await.unwrap().starts_with("a:"));
+
+  // Renewed past its ttl
+  tokio::time::sleep(ttl * 2).await;
+  assert_eq!(registry.addrs("world"), vec![a.addr]);
+
+  client
+    .register(context::current(), "world".to_string(), b.addr, ttl * 10)
+    .await
+    .unwrap();
+  announced.abort();
+  client
+    .deregister(context::current(), "world".to_string(), a.addr)
+    .await
+    .unwrap();
+  for _ in 0 .. 5 {
+    assert!(hello(&world).await.unwrap().starts_with("b:"));
+  }
+
+  // Expired without renewal
+  registry.add("world", a.addr, Duration::from_millis(10));
+  tokio::time::sleep(Duration::from_millis(20)).await;
+  assert_eq!(registry.addrs("world"), vec![b.addr]);
+
+  // Held for no longer than the registry allows
+  client
+    .register(
+      context::current(),
+      "world".to_string(),
+      a.addr,
+      Duration::MAX,
+    )
+    .await
+    .unwrap();
+  assert!(registry.addrs("world").contains(&a.addr));
+}
+
+#[tokio::test]
+async fn keeps_the_trace_of_the_caller() {
+  let tracer_provider = opentelemetry_sdk::trace::TracerProvider::builder().build();
+  let tracer = opentelemetry::trace::TracerProvider::tracer(&tracer_provider, "test");
+  let _subscriber = tracing::subscriber::set_default(
+    tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer)),
+  );
+
+  let a = Server::start("a", Duration::ZERO).await;
+  let b = Server::start("b", Duration::ZERO).await;
+  let world = balancer(
+    Static(vec![dead_addr().await, a.addr, b.addr]),
+    Config::default(),
+  );
+
+  let mut traces = Vec::new();
+  for _ in 0 .. 10 {
+    let span = tracing::info_span!("caller");
+    let trace = async {
+      let ctx = context::current();
+      let trace = *ctx.trace_id();
+      world
+        .call_idempotent(ctx, |client, ctx| async move {
+          client.hello(ctx, "you".to_string()).await
+        })
+        .await
+        .unwrap();
+      trace
+    }
+    .instrument(span)
+    .await;
+    traces.push(trace);
+  }
+
+  let mut seen = a.hello.traces.lock().unwrap().clone();
+  seen.extend(b.hello.traces.lock().unwrap().iter());
+  seen.sort_by_key(|t| u128::from(*t));
+  traces.sort_by_key(|t| u128::from(*t));
+  assert_eq!(seen, traces);
+}
@@ -0,0 +1,20 @@
+use std::{io, path::PathBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RulesError {
+  #[error("{path}: {source}")]
+  Io { path: PathBuf, source: io::Error },
+  #[error("line {line}: {message}")]
+  Parse { line: usize, message: String },
+}
+
+/// Why a connection was turned away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum Rejection {
+  #[error("denied by rule")]
+  Denied,
+  #[error("connection rate limit reached")]
+  RateLimited,
+  #[error("too many connections")]
+  TooManyConnections,
+}
@@ -0,0 +1,124 @@
+use std::{
+  net::IpAddr,
+  path::PathBuf,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, RwLock,
+  },
+  time::{Duration, Instant, SystemTime},
+};
+
+use tokio::task::JoinHandle;
+
+use crate::{
+  error::Rejection,
+  limits::{Limiter, Permit},
+  rules::{Action, RuleSet},
+};
+
+/// Counts since the firewall was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+  pub accepted: u64,
+  pub denied: u64,
+  pub rate_limited: u64,
+  pub too_many_connections: u64,
+  pub reloads: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+  accepted: AtomicU64,
+  denied: AtomicU64,
+  rate_limited: AtomicU64,
+  too_many_connections: AtomicU64,
+  reloads: AtomicU64,
+}
+
+/// Decides which connections to accept. Replacing the rules only affects connections accepted
+/// afterwards: live ones keep going and keep counting towards their IP's limits.
+#[derive(Debug)]
+pub struct Firewall {
+  rules: RwLock<Arc<RuleSet>>,
+  limiter: Arc<Limiter>,
+  counters: Counters,
+}
+
+impl Firewall {
+  pub fn new(rules: RuleSet) -> Firewall {
+    Firewall {
+      rules: RwLock::new(Arc::new(rules)),
+      limiter: Arc::default(),
+      counters: Counters::default(),
+    }
+  }
+
+  /// Admits a connection from `ip`, returning the permit to hold for as long as it is open.
+  pub fn check(&self, ip: IpAddr) -> Result<Permit, Rejection> {
+    let rules = self.rules();
+    let ip = ip.to_canonical();
+    let result = match rules.action(ip) {
+      Action::Deny => Err(Rejection::Denied),
+      Action::Allow => self.limiter.acquire(ip, rules.limits, Instant::now()),
+    };
+    let counter = match result {
+      Ok(_) => &self.counters.accepted,
+      Err(Rejection::Denied) => &self.counters.denied,
+      Err(Rejection::RateLimited) => &self.counters.rate_limited,
+      Err(Rejection::TooManyConnections) => &self.counters.too_many_connections,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+    result
+  }
+
+  pub fn rules(&self) -> Arc<RuleSet> {
+    self.rules.read().unwrap().clone()
+  }
+
+  pub fn reload(&self, rules: RuleSet) {
+    *self.rules.write().unwrap() = Arc::new(rules);
+    self.counters.reloads.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub fn stats(&self) -> Stats {
+    let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+    Stats {
+      accepted: get(&self.counters.accepted),
+      denied: get(&self.counters.denied),
+      rate_limited: get(&self.counters.rate_limited),
+      too_many_connections: get(&self.counters.too_many_connections),
+      reloads: get(&self.counters.reloads),
+    }
+  }
+
+  /// Reloads the rules from `path` whenever its modification time changes, checking `every`
+  /// interval. A file that fails to parse is reported and the current rules stay in force.
+  pub fn watch(self: &Arc<Self>, path: PathBuf, every: Duration) -> JoinHandle<()> {
+    let firewall = self.clone();
+    tokio::spawn(async move {
+      let modified = |path: &PathBuf| -> Option<SystemTime> {
+        std::fs::metadata(path)
+          .and_then(|meta| meta.modified())
+          .ok()
+      };
+      let mut seen = modified(&path);
+      let mut interval = tokio::time::interval(every);
+      interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+      loop {
+        interval.tick().await;
+        let current = modified(&path);
+        if current == seen {
+          continue;
+        }
+        seen = current;
+        match RuleSet::load(&path) {
+          Ok(rules) => {
+            println!("Reloaded {} rules from {}", rules.len(), path.display());
+            firewall.reload(rules);
+          }
+          Err(e) => eprintln!("Keeping the current rules: {}", e),
+        }
+      }
+    })
+  }
+}
@@ -0,0 +1,16 @@
+//! An accept filter for tokio TCP servers: ordered allow and deny rules over IPv4 and IPv6
+//! networks, per-IP rate and concurrency limits, and rules reloaded from a file while running.
+
+mod error;
+mod firewall;
+mod limits;
+mod listener;
+mod rules;
+mod trie;
+
+pub use error::{Rejection, RulesError};
+pub use firewall::{Firewall, Stats};
+pub use limits::Permit;
+pub use listener::{FilteredListener, FilteredStream};
+pub use rules::{Action, Limits, Rate, RuleSet};
+pub use trie::PrefixTrie;
@@ -0,0 +1,267 @@
+use std::{
+  collections::HashMap,
+  net::IpAddr,
+  sync::{Arc, Mutex},
+  time::Instant,
+};
+
+use crate::{error::Rejection, rules::Limits};
+
+/// Tracked addresses beyond which idle ones are forgotten.
+const MAX_TRACKED: usize = 4096;
+
+/// Forgetting stops here, so the next time is a thousand new addresses away.
+const KEEP_AFTER_PRUNE: usize = MAX_TRACKED / 4 * 3;
+
+#[derive(Debug)]
+struct PerIp {
+  /// Token bucket holding up to the rate's number of connections, refilled over its window.
+  tokens: f64,
+  refilled: Instant,
+  live: usize,
+}
+
+/// Per-IP connection rate and concurrency. It outlives rule reloads, so new limits apply to the
+/// counts already running. IPv6 addresses count per /64, the least a host is usually given.
+#[derive(Debug, Default)]
+pub(crate) struct Limiter {
+  ips: Mutex<HashMap<IpAddr, PerIp>>,
+}
+
+/// Counts as one live connection of its IP until dropped.
+#[derive(Debug)]
+pub struct Permit {
+  limiter: Arc<Limiter>,
+  ip: IpAddr,
+}
+
+impl Drop for Permit {
+  fn drop(&mut self) {
+    let mut ips = self.limiter.ips.lock().unwrap();
+    if let Some(entry) = ips.get_mut(&self.ip) {
+      entry.live -= 1;
+    }
+  }
+}
+
+impl Limiter {
+  pub(crate) fn acquire(
+    self: &Arc<Self>,
+    ip: IpAddr,
+    limits: Limits,
+    now: Instant,
+  ) -> Result<Permit, Rejection> {
+    let ip = key(ip);
+    let mut ips = self.ips.lock().unwrap();
+    if ips.len() >= MAX_TRACKED && !ips.contains_key(&ip) {
+      prune(&mut ips, limits, now);
+    }
+    let capacity = limits.rate.map_or(0.0, |rate| rate.connections as f64);
+    let entry = ips.entry(ip).or_insert(PerIp {
+      tokens: capacity,
+      refilled: now,
+      live: 0,
+    });
+
+    if limits.concurrent.is_some_and(|limit| entry.live >= limit) {
+      return Err(Rejection::TooManyConnections);
+    }
+    if let Some(rate) = limits.rate {
+      let elapsed = now.saturating_duration_since(entry.refilled);
+      let refill = elapsed.as_secs_f64() / rate.per.as_secs_f64() * capacity;
+      entry.tokens = (entry.tokens + refill).min(capacity);
+      entry.refilled = now;
+      if entry.tokens < 1.0 {
+        return Err(Rejection::RateLimited);
+      }
+      entry.tokens -= 1.0;
+    }
+    entry.live += 1;
+    Ok(Permit {
+      limiter: self.clone(),
+      ip,
+    })
+  }
+
+  #[cfg(test)]
+  fn tracked(&self) -> usize {
+    self.ips.lock().unwrap().len()
+  }
+}
+
+/// What an address is counted under: itself, or its /64 for IPv6.
+fn key(ip: IpAddr) -> IpAddr {
+  match ip.to_canonical() {
+    IpAddr::V6(ip) => IpAddr::V6((u128::from(ip) & !0 << 64).into()),
+    ip => ip,
+  }
+}
+
+/// Forgets addresses without live connections whose bucket has refilled: they start afresh. If
+/// that leaves too many, the idle ones refilled longest ago go too. Those with live connections
+/// are as many as the connections open, so they stay.
+fn prune(ips: &mut HashMap<IpAddr, PerIp>, limits: Limits, now: Instant) {
+  let window = limits.rate.map(|rate| rate.per).unwrap_or_default();
+  ips.retain(|_, entry| entry.live > 0 || now.saturating_duration_since(entry.refilled) < window);
+
+  let mut idle: Vec<(Instant, IpAddr)> = ips
+    .iter()
+    .filter(|(_, entry)| entry.live == 0)
+    .map(|(ip, entry)| (entry.refilled, *ip))
+    .collect();
+  let excess = ips.len().saturating_sub(KEEP_AFTER_PRUNE).min(idle.len());
+  if excess == 0 {
+    return;
+  }
+  idle.select_nth_unstable(excess - 1);
+  for (_, ip) in &idle[.. excess] {
+    ips.remove(ip);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::rules::Rate;
+
+  const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 1));
+
+  #[test]
+  fn concurrency_is_released_when_permits_drop() {
+    let limiter = Arc::new(Limiter::default());
+    let limits = Limits {
+      rate: None,
+      concurrent: Some(2),
+    };
+    let now = Instant::now();
+    let first = limiter.acquire(IP, limits, now).unwrap();
+    let _second = limiter.acquire(IP, limits, now).unwrap();
+    assert_eq!(
+      limiter.acquire(IP, limits, now).unwrap_err(),
+      Rejection::TooManyConnections
+    );
+    // Other addresses have their own count
+    let other = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 2));
+    limiter.acquire(other, limits, now).unwrap();
+
+    drop(first);
+    limiter.acquire(IP, limits, now).unwrap();
+  }
+
+  #[test]
+  fn rate_refills_over_the_window() {
+    let limiter = Arc::new(Limiter::default());
+    let limits = Limits {
+      rate: Some(Rate {
+        connections: 4,
+        per: Duration::from_secs(8),
+      }),
+      concurrent: None,
+    };
+    let start = Instant::now();
+    for _ in 0 .. 4 {
+      limiter.acquire(IP, limits, start).unwrap();
+    }
+    assert_eq!(
+      limiter.acquire(IP, limits, start).unwrap_err(),
+      Rejection::RateLimited
+    );
+    // One connection's worth every two seconds
+    let later = start + Duration::from_secs(2);
+    limiter.acquire(IP, limits, later).unwrap();
+    assert!(limiter.acquire(IP, limits, later).is_err());
+    let much_later = start + Duration::from_secs(60);
+    for _ in 0 .. 4 {
+      limiter.acquire(IP, limits, much_later).unwrap();
+    }
+    assert!(limiter.acquire(IP, limits, much_later).is_err());
+  }
+
+  #[test]
+  fn idle_addresses_are_forgotten() {
+    let limiter = Arc::new(Limiter::default());
+    let limits = Limits {
+      rate: Some(Rate {
+        connections: 1,
+        per: Duration::from_secs(1),
+      }),
+      concurrent: None,
+    };
+    let start = Instant::now();
+    let live = limiter.acquire(IP, limits, start).unwrap();
+    for i in 0 .. MAX_TRACKED as u32 - 1 {
+      let _ = limiter.acquire(IpAddr::V4(i.into()), limits, start);
+    }
+    limiter
+      .acquire(
+        "::1".parse().unwrap(),
+        limits,
+        start + Duration::from_secs(2),
+      )
+      .unwrap();
+    // The address with a live connection is kept
+    assert_eq!(limiter.tracked(), 2);
+    drop(live);
+  }
+
+  #[test]
+  fn busy_addresses_are_forgotten_oldest_first() {
+    let limiter = Arc::new(Limiter::default());
+    let limits = Limits {
+      rate: Some(Rate {
+        connections: 1,
+        per: Duration::from_secs(60),
+      }),
+      concurrent: None,
+    };
+    let start = Instant::now();
+    for i in 0 .. MAX_TRACKED as u32 {
+      let at = start + Duration::from_millis(i.into());
+      drop(limiter.acquire(IpAddr::V4(i.into()), limits, at).unwrap());
+    }
+    assert_eq!(limiter.tracked(), MAX_TRACKED);
+
+    // Every bucket is still empty, so the oldest ones make room
+    let now = start + Duration::from_secs(5);
+    limiter.acquire(IP, limits, now).unwrap();
+    assert_eq!(limiter.tracked(), KEEP_AFTER_PRUNE + 1);
+    let newest = IpAddr::V4((MAX_TRACKED as u32 - 1).into());
+    assert_eq!(
+      limiter.acquire(newest, limits, now).unwrap_err(),
+      Rejection::RateLimited
+    );
+    limiter.acquire(IpAddr::V4(0.into()), limits, now).unwrap();
+  }
+
+  #[test]
+  fn ipv6_counts_per_64() {
+    let limiter = Arc::new(Limiter::default());
+    let limits = Limits {
+      rate: None,
+      concurrent: Some(1),
+    };
+    let now = Instant::now();
+    let _first = limiter
+      .acquire("2001:db8::1".parse().unwrap(), limits, now)
+      .unwrap();
+    assert_eq!(
+      limiter
+        .acquire("2001:db8::ffff:1".parse().unwrap(), limits, now)
+        .unwrap_err(),
+      Rejection::TooManyConnections
+    );
+    limiter
+      .acquire("2001:db8:0:1::1".parse().unwrap(), limits, now)
+      .unwrap();
+    // Mapped IPv4 addresses count as themselves
+    let _v4 = limiter.acquire(IP, limits, now).unwrap();
+    assert!(limiter
+      .acquire("::ffff:10.0.0.2".parse().unwrap(), limits, now)
+      .is_ok());
+    assert!(limiter
+      .acquire("::ffff:10.0.0.1".parse().unwrap(), limits, now)
+      .is_err());
+  }
+}
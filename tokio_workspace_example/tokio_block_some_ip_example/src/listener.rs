@@ -0,0 +1,112 @@
+use std::{
+  io,
+  net::SocketAddr,
+  pin::Pin,
+  sync::Arc,
+  task::{Context, Poll},
+};
+
+use tokio::{
+  io::{AsyncRead, AsyncWrite, ReadBuf},
+  net::{TcpListener, TcpStream},
+};
+
+use crate::{error::Rejection, firewall::Firewall, limits::Permit};
+
+/// A [`TcpListener`] that only hands out connections the [`Firewall`] admits. Rejected ones are
+/// closed right away.
+#[derive(Debug)]
+pub struct FilteredListener {
+  listener: TcpListener,
+  firewall: Arc<Firewall>,
+}
+
+impl FilteredListener {
+  pub fn new(listener: TcpListener, firewall: Arc<Firewall>) -> FilteredListener {
+    FilteredListener { listener, firewall }
+  }
+
+  /// The next admitted connection. Rejected ones only show in the [`Firewall`]'s stats.
+  pub async fn accept(&self) -> io::Result<(FilteredStream, SocketAddr)> {
+    loop {
+      if let (Ok(stream), addr) = self.accept_or_reject().await? {
+        return Ok((stream, addr));
+      }
+    }
+  }
+
+  /// The next connection, admitted or with why it was rejected, for callers logging them. A
+  /// rejected connection is already closed.
+  pub async fn accept_or_reject(
+    &self,
+  ) -> io::Result<(Result<FilteredStream, Rejection>, SocketAddr)> {
+    let (stream, addr) = self.listener.accept().await?;
+    let stream = self.firewall.check(addr.ip()).map(|permit| FilteredStream {
+      stream,
+      _permit: permit,
+    });
+    Ok((stream, addr))
+  }
+
+  pub fn local_addr(&self) -> io::Result<SocketAddr> {
+    self.listener.local_addr()
+  }
+
+  pub fn firewall(&self) -> &Arc<Firewall> {
+    &self.firewall
+  }
+}
+
+/// An admitted [`TcpStream`], counting towards its IP's concurrency limit until dropped. It
+/// reads and writes like the stream, so servers taking any `AsyncRead + AsyncWrite` can use it.
+#[derive(Debug)]
+pub struct FilteredStream {
+  stream: TcpStream,
+  _permit: Permit,
+}
+
+impl FilteredStream {
+  pub fn get_ref(&self) -> &TcpStream {
+    &self.stream
+  }
+
+  pub fn get_mut(&mut self) -> &mut TcpStream {
+    &mut self.stream
+  }
+}
+
+impl AsyncRead for FilteredStream {
+  fn poll_read(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+  }
+}
+
+impl AsyncWrite for FilteredStream {
+  fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+    Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+  }
+
+  fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+  }
+
+  fn poll_write_vectored(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    bufs: &[io::IoSlice<'_>],
+  ) -> Poll<io::Result<usize>> {
+    Pin::new(&mut self.get_mut().stream).poll_write_vectored(cx, bufs)
+  }
+
+  fn is_write_vectored(&self) -> bool {
+    self.stream.is_write_vectored()
+  }
+}
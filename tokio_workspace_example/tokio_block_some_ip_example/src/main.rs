@@ -1,37 +1,65 @@
-use std::net::{IpAddr, Ipv4Addr};
+// cargo run -- --rules firewall.rules
+// nc 127.0.0.1 8080
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
-use tokio::net::{TcpListener, TcpStream};
-// use tokio_stream::StreamExt;
+use clap::Parser;
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::TcpListener,
+};
+use tokio_block_some_ip_example::{FilteredListener, FilteredStream, Firewall, RuleSet};
 
-async fn handle_connection(stream: TcpStream) {
-  // Handle the incoming connection here
-  println!("New connection from {:?}", stream.peer_addr().unwrap());
-  // ... do something with the connection ...
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+  #[arg(long, default_value = "firewall.rules")]
+  rules: PathBuf,
+  #[arg(long, default_value = "0.0.0.0:8080")]
+  addr: String,
 }
 
-async fn filter_ip_block(addr: IpAddr) -> bool {
-  // Define your IP block range here
-  let blocked_ip_start = IpAddr::V4(Ipv4Addr::new(192, 168, 0, 0));
-  let blocked_ip_end = IpAddr::V4(Ipv4Addr::new(192, 168, 255, 255));
-
-  addr >= blocked_ip_start && addr <= blocked_ip_end
+async fn handle_connection(mut stream: FilteredStream) {
+  // Handle the incoming connection here
+  println!(
+    "New connection from {:?}",
+    stream.get_ref().peer_addr().unwrap()
+  );
+  let mut buf = [0; 1024];
+  loop {
+    match stream.read(&mut buf).await {
+      Ok(0) | Err(_) => break,
+      Ok(n) => {
+        if stream.write_all(&buf[.. n]).await.is_err() {
+          break;
+        }
+      }
+    }
+  }
 }
 
 #[tokio::main]
-async fn main() {
-  let listener = TcpListener::bind("0.0.0.0:8080").await.unwrap();
-
-  loop {
-    let (stream, addr) = listener.accept().await.unwrap();
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+  let args = Args::parse();
+  let firewall = Arc::new(Firewall::new(RuleSet::load(&args.rules)?));
+  firewall.watch(args.rules, Duration::from_secs(1));
 
-    // Filter connections from blocked IP addresses
-    if filter_ip_block(addr.ip()).await {
-      println!("Blocked connection from {:?}", addr);
-      continue;
+  let stats = firewall.clone();
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(Duration::from_secs(30));
+    loop {
+      interval.tick().await;
+      println!("{:?}", stats.stats());
     }
+  });
 
-    tokio::spawn(async move {
-      handle_connection(stream).await;
-    });
+  let listener = FilteredListener::new(TcpListener::bind(&args.addr).await?, firewall);
+  println!("Listening on {}", listener.local_addr()?);
+  loop {
+    match listener.accept_or_reject().await? {
+      (Ok(stream), _) => {
+        tokio::spawn(handle_connection(stream));
+      }
+      (Err(rejection), addr) => println!("Rejected {}: {}", addr, rejection),
+    }
   }
 }
@@ -0,0 +1,243 @@
+use std::{fs, net::IpAddr, path::Path, str::FromStr, time::Duration};
+
+use ipnetwork::IpNetwork;
+
+use crate::{error::RulesError, trie::PrefixTrie};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+  Allow,
+  Deny,
+}
+
+/// At most `connections` new connections per IP every `per`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+  pub connections: u32,
+  pub per: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+  pub rate: Option<Rate>,
+  /// Live connections per IP.
+  pub concurrent: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Rule {
+  /// Position in the file: the first matching rule wins.
+  index: usize,
+  action: Action,
+}
+
+/// A parsed rules file:
+///
+/// ```text
+/// # Rules are tried in order and the first one containing the address wins
+/// deny 192.168.0.0/16
+/// allow 10.0.0.0/8
+/// deny 2001:db8::/32
+/// # Addresses no rule matches, `allow` when left out
+/// default allow
+/// # New connections per IP and window, and live connections per IP; IPv6 counts per /64
+/// rate 20/10s
+/// concurrent 50
+/// ```
+#[derive(Debug, Default)]
+pub struct RuleSet {
+  trie: PrefixTrie<Rule>,
+  rules: usize,
+  default: Option<Action>,
+  pub limits: Limits,
+}
+
+impl RuleSet {
+  pub fn load(path: &Path) -> Result<RuleSet, RulesError> {
+    let text = fs::read_to_string(path).map_err(|source| RulesError::Io {
+      path: path.to_path_buf(),
+      source,
+    })?;
+    text.parse()
+  }
+
+  /// What the rules say about `addr`. IPv4 addresses mapped into IPv6, as dual-stack listeners
+  /// report them, are matched against the IPv4 rules.
+  pub fn action(&self, addr: IpAddr) -> Action {
+    self
+      .trie
+      .matches(addr.to_canonical())
+      .min_by_key(|rule| rule.index)
+      .map(|rule| rule.action)
+      .or(self.default)
+      .unwrap_or(Action::Allow)
+  }
+
+  /// Number of allow and deny rules.
+  pub fn len(&self) -> usize {
+    self.rules
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.rules == 0
+  }
+}
+
+impl FromStr for RuleSet {
+  type Err = RulesError;
+
+  fn from_str(text: &str) -> Result<Self, Self::Err> {
+    let mut rules = RuleSet::default();
+    for (number, line) in text.lines().enumerate() {
+      let line = line.split('#').next().unwrap().trim();
+      if line.is_empty() {
+        continue;
+      }
+      let error = |message: String| RulesError::Parse {
+        line: number + 1,
+        message,
+      };
+      let mut words = line.split_whitespace();
+      let (keyword, argument) = (words.next().unwrap(), words.next());
+      let Some(argument) = argument else {
+        return Err(error(format!("`{}` needs an argument", keyword)));
+      };
+      if let Some(extra) = words.next() {
+        return Err(error(format!("unexpected {:?}", extra)));
+      }
+      match keyword {
+        "allow" | "deny" => {
+          let network: IpNetwork = argument
+            .parse()
+            .map_err(|e| error(format!("invalid network {:?}: {}", argument, e)))?;
+          let action = if keyword == "allow" {
+            Action::Allow
+          } else {
+            Action::Deny
+          };
+          // A repeated network is shadowed by its first occurrence
+          rules.trie.entry(network).get_or_insert(Rule {
+            index: rules.rules,
+            action,
+          });
+          rules.rules += 1;
+        }
+        "default" => {
+          rules.default = Some(match argument {
+            "allow" => Action::Allow,
+            "deny" => Action::Deny,
+            other => return Err(error(format!("invalid default {:?}", other))),
+          });
+        }
+        "rate" => rules.limits.rate = Some(parse_rate(argument).map_err(error)?),
+        "concurrent" => {
+          let limit = argument
+            .parse()
+            .map_err(|_| error(format!("invalid limit {:?}", argument)))?;
+          rules.limits.concurrent = Some(limit);
+        }
+        other => return Err(error(format!("unknown keyword {:?}", other))),
+      }
+    }
+    Ok(rules)
+  }
+}
+
+/// `20/10s`, `100/1m` or `5/s`.
+fn parse_rate(rate: &str) -> Result<Rate, String> {
+  let invalid = || format!("invalid rate {:?}, expected e.g. 20/10s", rate);
+  let (connections, window) = rate.split_once('/').ok_or_else(invalid)?;
+  let connections: u32 = connections.parse().map_err(|_| invalid())?;
+  let unit = match window.chars().last() {
+    Some('s') => 1,
+    Some('m') => 60,
+    Some('h') => 3600,
+    _ => return Err(invalid()),
+  };
+  let count = &window[.. window.len() - 1];
+  let count: u64 = if count.is_empty() {
+    1
+  } else {
+    count.parse().map_err(|_| invalid())?
+  };
+  if connections == 0 || count == 0 {
+    return Err(invalid());
+  }
+  let secs = count.checked_mul(unit).ok_or_else(invalid)?;
+  Ok(Rate {
+    connections,
+    per: Duration::from_secs(secs),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn action(rules: &RuleSet, addr: &str) -> Action {
+    rules.action(addr.parse().unwrap())
+  }
+
+  #[test]
+  fn first_matching_rule_wins() {
+    let rules: RuleSet = "
+      allow 192.168.1.10/32 # the printer
+      deny 192.168.0.0/16
+      allow 192.168.1.0/24  # shadowed by the rule above
+      deny 2001:db8::/32
+      default allow
+    "
+    .parse()
+    .unwrap();
+    assert_eq!(rules.len(), 4);
+    assert_eq!(action(&rules, "192.168.1.10"), Action::Allow);
+    assert_eq!(action(&rules, "192.168.1.11"), Action::Deny);
+    assert_eq!(action(&rules, "::ffff:192.168.1.11"), Action::Deny);
+    assert_eq!(action(&rules, "2001:db8::1"), Action::Deny);
+    assert_eq!(action(&rules, "10.0.0.1"), Action::Allow);
+  }
+
+  #[test]
+  fn default_policy_applies_when_nothing_matches() {
+    let rules: RuleSet = "allow 10.0.0.0/8\ndefault deny".parse().unwrap();
+    assert_eq!(action(&rules, "10.0.0.1"), Action::Allow);
+    assert_eq!(action(&rules, "8.8.8.8"), Action::Deny);
+    assert_eq!(action(&RuleSet::default(), "8.8.8.8"), Action::Allow);
+  }
+
+  #[test]
+  fn limits() {
+    let rules: RuleSet = "rate 20/10s\nconcurrent 4".parse().unwrap();
+    assert_eq!(
+      rules.limits,
+      Limits {
+        rate: Some(Rate {
+          connections: 20,
+          per: Duration::from_secs(10),
+        }),
+        concurrent: Some(4),
+      }
+    );
+    assert_eq!(parse_rate("5/s").unwrap().per, Duration::from_secs(1));
+    assert_eq!(parse_rate("100/2m").unwrap().per, Duration::from_secs(120));
+    assert!(parse_rate("0/s").is_err());
+    assert!(parse_rate("5/10").is_err());
+    assert!(parse_rate("5/18446744073709551615h").is_err());
+  }
+
+  #[test]
+  fn errors_name_the_line() {
+    let error = "allow 10.0.0.0/8\n\ndeny 10.0.0.0/33"
+      .parse::<RuleSet>()
+      .unwrap_err();
+    assert!(matches!(error, RulesError::Parse { line: 3, .. }));
+    for bad in [
+      "block 10.0.0.0/8",
+      "deny",
+      "deny 1.2.3.4 5.6.7.8",
+      "default maybe",
+    ] {
+      assert!(bad.parse::<RuleSet>().is_err(), "{}", bad);
+    }
+  }
+}
@@ -0,0 +1,134 @@
+use std::net::IpAddr;
+
+use ipnetwork::IpNetwork;
+
+/// Binary trie over address bits, one per address family. A lookup walks a single path, so it
+/// costs at most 32 or 128 steps however many networks are stored.
+#[derive(Debug)]
+pub struct PrefixTrie<T> {
+  v4: Node<T>,
+  v6: Node<T>,
+}
+
+#[derive(Debug)]
+struct Node<T> {
+  value: Option<T>,
+  children: [Option<Box<Node<T>>>; 2],
+}
+
+impl<T> Default for Node<T> {
+  fn default() -> Self {
+    Node {
+      value: None,
+      children: [None, None],
+    }
+  }
+}
+
+impl<T> Default for PrefixTrie<T> {
+  fn default() -> Self {
+    PrefixTrie {
+      v4: Node::default(),
+      v6: Node::default(),
+    }
+  }
+}
+
+impl<T> PrefixTrie<T> {
+  /// The slot of `network`, to insert into or update.
+  pub fn entry(&mut self, network: IpNetwork) -> &mut Option<T> {
+    let (mut node, bits) = match network {
+      IpNetwork::V4(_) => (&mut self.v4, 32),
+      IpNetwork::V6(_) => (&mut self.v6, 128),
+    };
+    let address = as_u128(network.network());
+    for i in 0 .. network.prefix() as u32 {
+      let bit = bit(address, bits, i);
+      node = node.children[bit].get_or_insert_with(Box::default);
+    }
+    &mut node.value
+  }
+
+  /// Values of every stored network containing `addr`, shortest prefix first.
+  pub fn matches(&self, addr: IpAddr) -> impl Iterator<Item = &T> {
+    let (root, bits) = match addr {
+      IpAddr::V4(_) => (&self.v4, 32),
+      IpAddr::V6(_) => (&self.v6, 128),
+    };
+    let address = as_u128(addr);
+    let mut node = Some(root);
+    let mut depth = 0;
+    std::iter::from_fn(move || loop {
+      let current = node?;
+      node = if depth < bits {
+        current.children[bit(address, bits, depth)].as_deref()
+      } else {
+        None
+      };
+      depth += 1;
+      if let Some(value) = &current.value {
+        return Some(value);
+      }
+    })
+  }
+}
+
+fn as_u128(addr: IpAddr) -> u128 {
+  match addr {
+    IpAddr::V4(addr) => u32::from(addr) as u128,
+    IpAddr::V6(addr) => u128::from(addr),
+  }
+}
+
+/// Bit `i` of an address `bits` long, counting from the most significant one.
+fn bit(address: u128, bits: u32, i: u32) -> usize {
+  ((address >> (bits - 1 - i)) & 1) as usize
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn trie(networks: &[&str]) -> PrefixTrie<&'static str> {
+    let mut trie = PrefixTrie::default();
+    for network in networks {
+      let name: &'static str = Box::leak(network.to_string().into_boxed_str());
+      *trie.entry(network.parse().unwrap()) = Some(name);
+    }
+    trie
+  }
+
+  fn matches(trie: &PrefixTrie<&'static str>, addr: &str) -> Vec<&'static str> {
+    trie.matches(addr.parse().unwrap()).copied().collect()
+  }
+
+  #[test]
+  fn matches_every_containing_network_shortest_first() {
+    let trie = trie(&[
+      "10.1.2.0/24",
+      "10.0.0.0/8",
+      "0.0.0.0/0",
+      "10.1.2.3/32",
+      "192.168.0.0/16",
+    ]);
+    assert_eq!(
+      matches(&trie, "10.1.2.3"),
+      ["0.0.0.0/0", "10.0.0.0/8", "10.1.2.0/24", "10.1.2.3/32"]
+    );
+    assert_eq!(matches(&trie, "10.9.9.9"), ["0.0.0.0/0", "10.0.0.0/8"]);
+    assert_eq!(matches(&trie, "192.169.0.1"), ["0.0.0.0/0"]);
+    // The families are kept apart
+    assert!(matches(&trie, "::ffff:10.1.2.3").is_empty());
+  }
+
+  #[test]
+  fn ipv6_prefixes() {
+    let trie = trie(&["2001:db8::/32", "2001:db8:1::/48", "::1/128"]);
+    assert_eq!(
+      matches(&trie, "2001:db8:1::5"),
+      ["2001:db8::/32", "2001:db8:1::/48"]
+    );
+    assert_eq!(matches(&trie, "2001:db9::1"), Vec::<&str>::new());
+    assert_eq!(matches(&trie, "::1"), ["::1/128"]);
+  }
+}
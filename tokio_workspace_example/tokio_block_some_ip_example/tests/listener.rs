@@ -0,0 +1,148 @@
+use std::{fs, sync::Arc, time::Duration};
+
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::{TcpListener, TcpStream},
+};
+use tokio_block_some_ip_example::{FilteredListener, Firewall, Rejection, RuleSet, Stats};
+
+/// An echo server behind `firewall`.
+async fn serve(firewall: Arc<Firewall>) -> std::net::SocketAddr {
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let listener = FilteredListener::new(listener, firewall);
+  let addr = listener.local_addr().unwrap();
+  tokio::spawn(async move {
+    loop {
+      let (mut stream, _) = listener.accept().await.unwrap();
+      tokio::spawn(async move {
+        let mut buf = [0; 64];
+        while let Ok(n @ 1 ..) = stream.read(&mut buf).await {
+          stream.write_all(&buf[.. n]).await.unwrap();
+        }
+      });
+    }
+  });
+  addr
+}
+
+/// Whether the server echoes on `stream`, rather than closing it.
+async fn echoes(stream: &mut TcpStream) -> bool {
+  if stream.write_all(b"ping").await.is_err() {
+    return false;
+  }
+  let mut buf = [0; 4];
+  matches!(
+    tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut buf)).await,
+    Ok(Ok(_)) if &buf == b"ping"
+  )
+}
+
+async fn connect(addr: std::net::SocketAddr) -> TcpStream {
+  TcpStream::connect(addr).await.unwrap()
+}
+
+fn rules(text: &str) -> RuleSet {
+  text.parse().unwrap()
+}
+
+#[tokio::test]
+async fn reloading_rules_admits_denied_addresses() {
+  let firewall = Arc::new(Firewall::new(rules("deny 127.0.0.0/8")));
+  let addr = serve(firewall.clone()).await;
+
+  assert!(!echoes(&mut connect(addr).await).await);
+  firewall.reload(rules("allow 127.0.0.1/32\ndeny 127.0.0.0/8"));
+  assert!(echoes(&mut connect(addr).await).await);
+  assert_eq!(
+    firewall.stats(),
+    Stats {
+      accepted: 1,
+      denied: 1,
+      reloads: 1,
+      ..Stats::default()
+    }
+  );
+}
+
+#[tokio::test]
+async fn live_connections_survive_a_reload_and_count_towards_limits() {
+  let firewall = Arc::new(Firewall::new(rules("concurrent 1")));
+  let addr = serve(firewall.clone()).await;
+
+  let mut first = connect(addr).await;
+  assert!(echoes(&mut first).await);
+  assert!(!echoes(&mut connect(addr).await).await);
+  assert_eq!(firewall.stats().too_many_connections, 1);
+
+  // Even rules denying the address leave the live connection alone
+  firewall.reload(rules("deny 0.0.0.0/0"));
+  assert!(echoes(&mut first).await);
+  assert!(!echoes(&mut connect(addr).await).await);
+
+  firewall.reload(rules("concurrent 2"));
+  let mut second = connect(addr).await;
+  assert!(echoes(&mut second).await);
+  assert!(!echoes(&mut connect(addr).await).await);
+
+  // Closing one frees its slot
+  drop(first);
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  assert!(echoes(&mut connect(addr).await).await);
+  assert!(echoes(&mut second).await);
+}
+
+#[tokio::test]
+async fn connection_rate_is_limited_per_ip() {
+  let firewall = Arc::new(Firewall::new(rules("rate 2/1h")));
+  let addr = serve(firewall.clone()).await;
+  for _ in 0 .. 2 {
+    assert!(echoes(&mut connect(addr).await).await);
+  }
+  assert!(!echoes(&mut connect(addr).await).await);
+  assert_eq!(firewall.stats().rate_limited, 1);
+}
+
+#[tokio::test]
+async fn rejections_are_handed_to_the_caller() {
+  let firewall = Arc::new(Firewall::new(rules("deny 127.0.0.0/8")));
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let listener = FilteredListener::new(listener, firewall);
+  let mut client = connect(listener.local_addr().unwrap()).await;
+  let (stream, addr) = listener.accept_or_reject().await.unwrap();
+  assert_eq!(stream.unwrap_err(), Rejection::Denied);
+  assert_eq!(addr, client.local_addr().unwrap());
+  assert!(!echoes(&mut client).await);
+}
+
+#[tokio::test]
+async fn rules_file_is_watched() {
+  let dir = std::env::temp_dir().join(format!("firewall-{}", std::process::id()));
+  fs::create_dir_all(&dir).unwrap();
+  let path = dir.join("firewall.rules");
+  fs::write(&path, "deny 127.0.0.0/8\n").unwrap();
+
+  let firewall = Arc::new(Firewall::new(RuleSet::load(&path).unwrap()));
+  let watcher = firewall.watch(path.clone(), Duration::from_millis(20));
+  let addr = serve(firewall.clone()).await;
+  assert!(!echoes(&mut connect(addr).await).await);
+
+  // A broken edit keeps the rules in force
+  tokio::time::sleep(Duration::from_millis(50)).await;
+  fs::write(&path, "deny 127.0.0.0/99\n").unwrap();
+  tokio::time::sleep(Duration::from_millis(200)).await;
+  assert_eq!(firewall.stats().reloads, 0);
+  assert!(!echoes(&mut connect(addr).await).await);
+
+  fs::write(&path, "allow 127.0.0.0/8\n").unwrap();
+  for _ in 0 .. 100 {
+    if firewall.stats().reloads == 1 {
+      break;
+    }
+    tokio::time::sleep(Duration::from_millis(20)).await;
+  }
+  assert_eq!(firewall.stats().reloads, 1);
+  assert!(echoes(&mut connect(addr).await).await);
+
+  watcher.abort();
+  fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,7 +1,11 @@
+use std::{env, path::PathBuf};
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+  let out_dir = PathBuf::from(env::var("OUT_DIR")?);
   tonic_build::configure()
     // .build_client(false)
-    .compile(&["proto/todo.proto"], &["proto"])
+    .file_descriptor_set_path(out_dir.join("todo_descriptor.bin"))
+    .compile_protos(&["proto/todo.proto"], &["proto"])
     .unwrap();
   Ok(())
 }
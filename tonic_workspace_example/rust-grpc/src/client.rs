@@ -1,13 +1,20 @@
-use todo::{todo_client::TodoClient, CreateTodoRequest};
-
-pub mod todo {
-  tonic::include_proto!("todo");
-}
+use grpc_server::todo::{
+  todo_client::TodoClient, CreateTodoRequest, DeleteTodoRequest, TodoItem, UpdateTodoRequest,
+  WatchTodosRequest,
+};
+use prost_types::FieldMask;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
   let mut client = TodoClient::connect("http://0.0.0.0:50051").await?;
 
+  let mut events = client
+    .watch_todos(WatchTodosRequest {
+      send_initial: false,
+    })
+    .await?
+    .into_inner();
+
   let request = tonic::Request::new(());
 
   let response = client.get_todos(request).await?;
@@ -22,7 +29,34 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
   let create_response = client.create_todo(create_request).await?;
 
-  println!("{:?}", create_response.into_inner().todo);
+  let todo = create_response.into_inner().todo.unwrap();
+  println!("{:?}", todo);
+
+  let update_request = tonic::Request::new(UpdateTodoRequest {
+    todo: Some(TodoItem {
+      id: todo.id,
+      completed: true,
+      ..Default::default()
+    }),
+    update_mask: Some(FieldMask {
+      paths: vec!["completed".to_string()],
+    }),
+  });
+
+  println!(
+    "{:?}",
+    client.update_todo(update_request).await?.into_inner()
+  );
+
+  client
+    .delete_todo(DeleteTodoRequest { id: todo.id })
+    .await?;
+
+  for _ in 0 .. 3 {
+    if let Some(event) = events.message().await? {
+      println!("{:?} {:?}", event.kind(), event.todo);
+    }
+  }
 
   Ok(())
 }
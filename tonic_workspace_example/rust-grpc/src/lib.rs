@@ -0,0 +1,34 @@
+pub use service::TodoService;
+pub use store::Store;
+use todo::todo_server::TodoServer;
+use tonic::transport::{server::Router, Server};
+
+pub mod service;
+pub mod store;
+
+pub mod todo {
+  tonic::include_proto!("todo");
+
+  pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("todo_descriptor");
+}
+
+/// The todo service next to `grpc.health.v1.Health`, reporting it as serving, and server
+/// reflection for tools like grpcurl.
+pub async fn server(service: TodoService) -> Result<Router, tonic_reflection::server::Error> {
+  let (mut reporter, health) = tonic_health::server::health_reporter();
+  reporter.set_serving::<TodoServer<TodoService>>().await;
+
+  let reflection = || {
+    tonic_reflection::server::Builder::configure()
+      .register_encoded_file_descriptor_set(todo::FILE_DESCRIPTOR_SET)
+      .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+  };
+
+  Ok(
+    Server::builder()
+      .add_service(health)
+      .add_service(reflection().build_v1()?)
+      .add_service(reflection().build_v1alpha()?)
+      .add_service(TodoServer::new(service)),
+  )
+}
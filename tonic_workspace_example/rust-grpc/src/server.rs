@@ -1,63 +1,14 @@
-use std::sync::Mutex;
-
-use todo::{
-  todo_server::{Todo, TodoServer},
-  CreateTodoRequest, CreateTodoResponse, GetTodosResponse, TodoItem,
-};
-use tonic::{transport::Server, Request, Response, Status};
-
-pub mod todo {
-  tonic::include_proto!("todo");
-}
-
-#[derive(Debug, Default)]
-pub struct TodoService {
-  todos: Mutex<Vec<TodoItem>>,
-}
-
-#[tonic::async_trait]
-impl Todo for TodoService {
-  async fn get_todos(&self, _: Request<()>) -> Result<Response<GetTodosResponse>, Status> {
-    let message = GetTodosResponse {
-      todos: self.todos.lock().unwrap().to_vec(),
-    };
-
-    Ok(Response::new(message))
-  }
-
-  async fn create_todo(
-    &self,
-    request: Request<CreateTodoRequest>,
-  ) -> Result<Response<CreateTodoResponse>, Status> {
-    let payload = request.into_inner();
-
-    let todo_item = TodoItem {
-      name: payload.name,
-      description: payload.description,
-      priority: payload.priority,
-      completed: false,
-    };
-
-    self.todos.lock().unwrap().push(todo_item.clone());
-
-    let message = CreateTodoResponse {
-      todo: Some(todo_item),
-      status: true,
-    };
-
-    Ok(Response::new(message))
-  }
-}
+use grpc_server::{Store, TodoService};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
   let addr = "0.0.0.0:50051".parse().unwrap();
-  let todo_service = TodoService::default();
+  let db = std::env::args()
+    .nth(1)
+    .unwrap_or_else(|| "todos.db".to_string());
+  let todo_service = TodoService::new(Store::open(&db).await?);
 
-  Server::builder()
-    .add_service(TodoServer::new(todo_service))
-    .serve(addr)
-    .await?;
+  grpc_server::server(todo_service).await?.serve(addr).await?;
 
   Ok(())
 }
@@ -0,0 +1,239 @@
+use std::pin::Pin;
+
+use prost_types::FieldMask;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::{
+  wrappers::{errors::BroadcastStreamRecvError, BroadcastStream, ReceiverStream},
+  Stream, StreamExt,
+};
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::{
+  store::Store,
+  todo::{
+    import_todos_response, todo_event::Kind, todo_server::Todo, CreateTodoRequest,
+    CreateTodoResponse, DeleteTodoRequest, GetTodoRequest, GetTodosResponse, ImportTodosResponse,
+    ListTodosRequest, TodoEvent, TodoItem, UpdateTodoRequest, WatchTodosRequest,
+  },
+};
+
+/// Watchers further behind than this many changes are dropped with `DATA_LOSS`.
+const EVENT_BACKLOG: usize = 256;
+
+/// The fields an update mask may name.
+const FIELDS: [&str; 4] = ["name", "description", "priority", "completed"];
+
+#[derive(Debug, Clone)]
+pub struct TodoService {
+  store: Store,
+  events: broadcast::Sender<TodoEvent>,
+}
+
+impl TodoService {
+  pub fn new(store: Store) -> TodoService {
+    TodoService {
+      store,
+      events: broadcast::channel(EVENT_BACKLOG).0,
+    }
+  }
+
+  fn notify(&self, kind: Kind, todo: &TodoItem) {
+    // No watchers is fine
+    let _ = self.events.send(TodoEvent {
+      kind: kind.into(),
+      todo: Some(todo.clone()),
+    });
+  }
+
+  async fn create(&self, new: CreateTodoRequest) -> Result<TodoItem, Status> {
+    if new.name.trim().is_empty() {
+      return Err(Status::invalid_argument("name is required"));
+    }
+    let todo = self.store.create(new).await.map_err(internal)?;
+    self.notify(Kind::Created, &todo);
+    Ok(todo)
+  }
+}
+
+fn internal(e: sqlx::Error) -> Status {
+  Status::internal(format!("database: {}", e))
+}
+
+fn not_found(id: i64) -> Status {
+  Status::not_found(format!("no todo {}", id))
+}
+
+fn names(mask: &FieldMask, field: &str) -> bool {
+  mask.paths.is_empty() || mask.paths.iter().any(|p| p == field)
+}
+
+/// Copies the fields named by `mask` from `from` into `to`, all of them for an empty mask.
+fn apply(mask: &FieldMask, from: TodoItem, to: &mut TodoItem) {
+  if names(mask, "name") {
+    to.name = from.name;
+  }
+  if names(mask, "description") {
+    to.description = from.description;
+  }
+  if names(mask, "priority") {
+    to.priority = from.priority;
+  }
+  if names(mask, "completed") {
+    to.completed = from.completed;
+  }
+}
+
+#[tonic::async_trait]
+impl Todo for TodoService {
+  async fn get_todos(&self, _: Request<()>) -> Result<Response<GetTodosResponse>, Status> {
+    let todos = self
+      .store
+      .list(&ListTodosRequest::default())
+      .await
+      .map_err(internal)?;
+    Ok(Response::new(GetTodosResponse { todos }))
+  }
+
+  async fn list_todos(
+    &self,
+    request: Request<ListTodosRequest>,
+  ) -> Result<Response<GetTodosResponse>, Status> {
+    let todos = self.store.list(request.get_ref()).await.map_err(internal)?;
+    Ok(Response::new(GetTodosResponse { todos }))
+  }
+
+  async fn create_todo(
+    &self,
+    request: Request<CreateTodoRequest>,
+  ) -> Result<Response<CreateTodoResponse>, Status> {
+    let todo = self.create(request.into_inner()).await?;
+
+    let message = CreateTodoResponse {
+      todo: Some(todo),
+      status: true,
+    };
+
+    Ok(Response::new(message))
+  }
+
+  async fn get_todo(&self, request: Request<GetTodoRequest>) -> Result<Response<TodoItem>, Status> {
+    let id = request.get_ref().id;
+    match self.store.get(id).await.map_err(internal)? {
+      Some(todo) => Ok(Response::new(todo)),
+      None => Err(not_found(id)),
+    }
+  }
+
+  async fn update_todo(
+    &self,
+    request: Request<UpdateTodoRequest>,
+  ) -> Result<Response<TodoItem>, Status> {
+    let UpdateTodoRequest { todo, update_mask } = request.into_inner();
+    let todo = todo.ok_or_else(|| Status::invalid_argument("todo is required"))?;
+    let mask = update_mask.unwrap_or_default();
+    if let Some(path) = mask.paths.iter().find(|p| !FIELDS.contains(&p.as_str())) {
+      return Err(Status::invalid_argument(format!(
+        "cannot update {:?}, only {:?}",
+        path, FIELDS
+      )));
+    }
+
+    if names(&mask, "name") && todo.name.trim().is_empty() {
+      return Err(Status::invalid_argument("name is required"));
+    }
+
+    let id = todo.id;
+    let updated = self
+      .store
+      .update(id, |current| apply(&mask, todo, current))
+      .await
+      .map_err(internal)?
+      .ok_or_else(|| not_found(id))?;
+    self.notify(Kind::Updated, &updated);
+    Ok(Response::new(updated))
+  }
+
+  async fn delete_todo(&self, request: Request<DeleteTodoRequest>) -> Result<Response<()>, Status> {
+    let id = request.get_ref().id;
+    let deleted = self
+      .store
+      .delete(id)
+      .await
+      .map_err(internal)?
+      .ok_or_else(|| not_found(id))?;
+    self.notify(Kind::Deleted, &deleted);
+    Ok(Response::new(()))
+  }
+
+  type WatchTodosStream = Pin<Box<dyn Stream<Item = Result<TodoEvent, Status>> + Send>>;
+
+  /// With `send_initial`, a todo created while the existing ones are read may be sent twice.
+  // Stream items are tonic's to choose
+  #[allow(clippy::result_large_err)]
+  async fn watch_todos(
+    &self,
+    request: Request<WatchTodosRequest>,
+  ) -> Result<Response<Self::WatchTodosStream>, Status> {
+    let changes = BroadcastStream::new(self.events.subscribe()).map(|event| {
+      event.map_err(|BroadcastStreamRecvError::Lagged(missed)| {
+        Status::data_loss(format!("watcher fell behind by {} changes", missed))
+      })
+    });
+
+    let initial = if request.get_ref().send_initial {
+      self
+        .store
+        .list(&ListTodosRequest::default())
+        .await
+        .map_err(internal)?
+    } else {
+      Vec::new()
+    };
+    let initial = tokio_stream::iter(initial).map(|todo| {
+      Ok(TodoEvent {
+        kind: Kind::Created.into(),
+        todo: Some(todo),
+      })
+    });
+
+    Ok(Response::new(Box::pin(initial.chain(changes))))
+  }
+
+  type ImportTodosStream = ReceiverStream<Result<ImportTodosResponse, Status>>;
+
+  async fn import_todos(
+    &self,
+    request: Request<Streaming<CreateTodoRequest>>,
+  ) -> Result<Response<Self::ImportTodosStream>, Status> {
+    let mut requests = request.into_inner();
+    let (tx, rx) = mpsc::channel(16);
+    let service = self.clone();
+
+    tokio::spawn(async move {
+      let mut index = 0;
+      loop {
+        let response = match requests.message().await {
+          Ok(Some(new)) => {
+            let result = match service.create(new).await {
+              Ok(todo) => import_todos_response::Result::Todo(todo),
+              Err(status) => import_todos_response::Result::Error(status.message().to_owned()),
+            };
+            Ok(ImportTodosResponse {
+              index,
+              result: Some(result),
+            })
+          }
+          Ok(None) => break,
+          Err(status) => Err(status),
+        };
+        let failed = response.is_err();
+        if tx.send(response).await.is_err() || failed {
+          break;
+        }
+        index += 1;
+      }
+    });
+
+    Ok(Response::new(ReceiverStream::new(rx)))
+  }
+}
@@ -0,0 +1,146 @@
+use sqlx::{
+  sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions, SqliteRow},
+  Row,
+};
+
+use crate::todo::{CreateTodoRequest, ListTodosRequest, TodoItem};
+
+pub type Result<T> = std::result::Result<T, sqlx::Error>;
+
+/// Todos in a SQLite database.
+#[derive(Debug, Clone)]
+pub struct Store {
+  pool: SqlitePool,
+}
+
+impl Store {
+  /// Creates the database file if needed.
+  pub async fn open(path: &str) -> Result<Store> {
+    let options = SqliteConnectOptions::new()
+      .filename(path)
+      .create_if_missing(true);
+    Store::with_pool(SqlitePoolOptions::new().connect_with(options).await?).await
+  }
+
+  /// Gone with the store: every connection to `:memory:` is a new database, so the pool holds
+  /// on to a single one.
+  pub async fn memory() -> Result<Store> {
+    let pool = SqlitePoolOptions::new()
+      .max_connections(1)
+      .idle_timeout(None)
+      .max_lifetime(None)
+      .connect("sqlite::memory:")
+      .await?;
+    Store::with_pool(pool).await
+  }
+
+  async fn with_pool(pool: SqlitePool) -> Result<Store> {
+    // AUTOINCREMENT so that the IDs of deleted todos are not given out again
+    sqlx::query(
+      "CREATE TABLE IF NOT EXISTS todos (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL,
+        description TEXT NOT NULL,
+        priority INTEGER NOT NULL,
+        completed BOOLEAN NOT NULL
+      )",
+    )
+    .execute(&pool)
+    .await?;
+    Ok(Store { pool })
+  }
+
+  pub async fn list(&self, filter: &ListTodosRequest) -> Result<Vec<TodoItem>> {
+    sqlx::query(
+      "SELECT * FROM todos
+      WHERE (?1 IS NULL OR completed = ?1)
+        AND (?2 IS NULL OR priority >= ?2)
+        AND instr(lower(name), lower(?3)) > 0
+      ORDER BY id",
+    )
+    .bind(filter.completed)
+    .bind(filter.min_priority)
+    .bind(&filter.name_contains)
+    .map(todo)
+    .fetch_all(&self.pool)
+    .await
+  }
+
+  pub async fn get(&self, id: i64) -> Result<Option<TodoItem>> {
+    sqlx::query("SELECT * FROM todos WHERE id = ?")
+      .bind(id)
+      .map(todo)
+      .fetch_optional(&self.pool)
+      .await
+  }
+
+  pub async fn create(&self, new: CreateTodoRequest) -> Result<TodoItem> {
+    let id = sqlx::query(
+      "INSERT INTO todos (name, description, priority, completed) VALUES (?, ?, ?, FALSE)",
+    )
+    .bind(&new.name)
+    .bind(&new.description)
+    .bind(new.priority)
+    .execute(&self.pool)
+    .await?
+    .last_insert_rowid();
+    Ok(TodoItem {
+      id,
+      name: new.name,
+      description: new.description,
+      priority: new.priority,
+      completed: false,
+    })
+  }
+
+  /// Reads, changes and writes back the todo in one transaction. `None` if there is no such
+  /// todo.
+  pub async fn update(
+    &self,
+    id: i64,
+    change: impl FnOnce(&mut TodoItem),
+  ) -> Result<Option<TodoItem>> {
+    let mut tx = self.pool.begin().await?;
+    let Some(mut item) = sqlx::query("SELECT * FROM todos WHERE id = ?")
+      .bind(id)
+      .map(todo)
+      .fetch_optional(&mut *tx)
+      .await?
+    else {
+      return Ok(None);
+    };
+    change(&mut item);
+    item.id = id;
+    sqlx::query(
+      "UPDATE todos SET name = ?, description = ?, priority = ?, completed = ? WHERE id = ?",
+    )
+    .bind(&item.name)
+    .bind(&item.description)
+    .bind(item.priority)
+    .bind(item.completed)
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(Some(item))
+  }
+
+  /// The todo as it was, `None` if there was no such todo.
+  pub async fn delete(&self, id: i64) -> Result<Option<TodoItem>> {
+    sqlx::query("DELETE FROM todos WHERE id = ? RETURNING *")
+      .bind(id)
+      .map(todo)
+      .fetch_optional(&self.pool)
+      .await
+  }
+}
+
+fn todo(row: SqliteRow) -> TodoItem {
+  TodoItem {
+    id: row.get("id"),
+    name: row.get("name"),
+    description: row.get("description"),
+    priority: row.get("priority"),
+    completed: row.get("completed"),
+  }
+}
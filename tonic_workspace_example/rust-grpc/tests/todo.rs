@@ -0,0 +1,361 @@
+use std::io;
+
+use grpc_server::{
+  todo::{
+    import_todos_response, todo_client::TodoClient, todo_event::Kind, CreateTodoRequest,
+    DeleteTodoRequest, GetTodoRequest, ListTodosRequest, TodoItem, UpdateTodoRequest,
+    WatchTodosRequest,
+  },
+  Store, TodoService,
+};
+use hyper_util::rt::TokioIo;
+use prost_types::FieldMask;
+use tonic::{
+  transport::{Channel, Endpoint, Uri},
+  Code,
+};
+use tonic_health::pb::{
+  health_check_response::ServingStatus, health_client::HealthClient, HealthCheckRequest,
+};
+use tonic_reflection::pb::v1::{
+  server_reflection_client::ServerReflectionClient, server_reflection_request::MessageRequest,
+  server_reflection_response::MessageResponse, ServerReflectionRequest,
+};
+use tower::service_fn;
+
+/// Serves `store` over an in-memory pipe, without a socket.
+async fn channel(store: Store) -> Channel {
+  let (client, server) = tokio::io::duplex(64 * 1024);
+  let router = grpc_server::server(TodoService::new(store)).await.unwrap();
+  tokio::spawn(router.serve_with_incoming(tokio_stream::once(Ok::<_, io::Error>(server))));
+
+  let mut client = Some(client);
+  Endpoint::try_from("http://[::]:50051")
+    .unwrap()
+    .connect_with_connector(service_fn(move |_: Uri| {
+      let client = client.take();
+      async move {
+        client
+          .map(TokioIo::new)
+          .ok_or_else(|| io::Error::other("the pipe is already connected"))
+      }
+    }))
+    .await
+    .unwrap()
+}
+
+async fn client() -> TodoClient<Channel> {
+  TodoClient::new(channel(Store::memory().await.unwrap()).await)
+}
+
+fn new(name: &str, priority: i32) -> CreateTodoRequest {
+  CreateTodoRequest {
+    name: name.to_string(),
+    description: format!("{} description", name),
+    priority,
+  }
+}
+
+async fn create(client: &mut TodoClient<Channel>, name: &str, priority: i32) -> TodoItem {
+  let response = client.create_todo(new(name, priority)).await.unwrap();
+  response.into_inner().todo.unwrap()
+}
+
+#[tokio::test]
+async fn creates_gets_updates_and_deletes() {
+  let mut client = client().await;
+
+  let first = create(&mut client, "first", 1).await;
+  let second = create(&mut client, "second", 2).await;
+  assert_ne!(first.id, second.id);
+  assert_eq!(
+    client
+      .get_todo(GetTodoRequest { id: first.id })
+      .await
+      .unwrap()
+      .into_inner(),
+    first
+  );
+
+  let updated = client
+    .update_todo(UpdateTodoRequest {
+      todo: Some(TodoItem {
+        id: first.id,
+        name: "ignored".to_string(),
+        completed: true,
+        priority: 9,
+        ..Default::default()
+      }),
+      update_mask: Some(FieldMask {
+        paths: vec!["completed".to_string(), "priority".to_string()],
+      }),
+    })
+    .await
+    .unwrap()
+    .into_inner();
+  assert_eq!(
+    updated,
+    TodoItem {
+      completed: true,
+      priority: 9,
+      ..first.clone()
+    }
+  );
+
+  client
+    .delete_todo(DeleteTodoRequest { id: first.id })
+    .await
+    .unwrap();
+  let status = client
+    .get_todo(GetTodoRequest { id: first.id })
+    .await
+    .unwrap_err();
+  assert_eq!(status.code(), Code::NotFound);
+  let status = client
+    .delete_todo(DeleteTodoRequest { id: first.id })
+    .await
+    .unwrap_err();
+  assert_eq!(status.code(), Code::NotFound);
+
+  // IDs of deleted todos are not reused
+  let third = create(&mut client, "third", 3).await;
+  assert!(third.id > second.id);
+  let todos = client.get_todos(()).await.unwrap().into_inner().todos;
+  assert_eq!(todos, vec![second, third]);
+}
+
+#[tokio::test]
+async fn rejects_invalid_requests() {
+  let mut client = client().await;
+  let todo = create(&mut client, "todo", 1).await;
+
+  let status = client.create_todo(new(" ", 1)).await.unwrap_err();
+  assert_eq!(status.code(), Code::InvalidArgument);
+
+  let update = |paths: &[&str], name: &str| UpdateTodoRequest {
+    todo: Some(TodoItem {
+      id: todo.id,
+      name: name.to_string(),
+      ..Default::default()
+    }),
+    update_mask: Some(FieldMask {
+      paths: paths.iter().map(|p| p.to_string()).collect(),
+    }),
+  };
+  for request in [
+    update(&["id"], "x"),
+    update(&["name"], ""),
+    update(&[], ""),
+    UpdateTodoRequest::default(),
+  ] {
+    let status = client.update_todo(request).await.unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+  }
+
+  // An empty mask replaces every field
+  let replaced = client
+    .update_todo(update(&[], "renamed"))
+    .await
+    .unwrap()
+    .into_inner();
+  assert_eq!(replaced.name, "renamed");
+  assert_eq!(replaced.description, "");
+  assert_eq!(replaced.priority, 0);
+
+  let mut missing = update(&["name"], "x");
+  missing.todo.as_mut().unwrap().id = 1000;
+  let status = client.update_todo(missing).await.unwrap_err();
+  assert_eq!(status.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn lists_with_filters() {
+  let mut client = client().await;
+  let low = create(&mut client, "Buy milk", 1).await;
+  let high = create(&mut client, "Write report", 5).await;
+  let done = create(&mut client, "Buy bread", 3).await;
+  let someday = create(&mut client, "Learn to juggle", -2).await;
+  let done = client
+    .update_todo(UpdateTodoRequest {
+      todo: Some(TodoItem {
+        id: done.id,
+        completed: true,
+        ..Default::default()
+      }),
+      update_mask: Some(FieldMask {
+        paths: vec!["completed".to_string()],
+      }),
+    })
+    .await
+    .unwrap()
+    .into_inner();
+
+  let list = |filter: ListTodosRequest| {
+    let mut client = client.clone();
+    async move { client.list_todos(filter).await.unwrap().into_inner().todos }
+  };
+  assert_eq!(
+    list(ListTodosRequest::default()).await,
+    vec![low.clone(), high.clone(), done.clone(), someday.clone()]
+  );
+  assert_eq!(
+    list(ListTodosRequest {
+      completed: Some(false),
+      ..Default::default()
+    })
+    .await,
+    vec![low.clone(), high.clone(), someday.clone()]
+  );
+  assert_eq!(
+    list(ListTodosRequest {
+      completed: Some(true),
+      ..Default::default()
+    })
+    .await,
+    vec![done.clone()]
+  );
+  assert_eq!(
+    list(ListTodosRequest {
+      min_priority: Some(3),
+      ..Default::default()
+    })
+    .await,
+    vec![high.clone(), done.clone()]
+  );
+  assert_eq!(
+    list(ListTodosRequest {
+      min_priority: Some(-2),
+      completed: Some(false),
+      ..Default::default()
+    })
+    .await,
+    vec![low.clone(), high.clone(), someday]
+  );
+  assert_eq!(
+    list(ListTodosRequest {
+      name_contains: "buy".to_string(),
+      min_priority: Some(2),
+      ..Default::default()
+    })
+    .await,
+    vec![done]
+  );
+}
+
+#[tokio::test]
+async fn watches_changes() {
+  let mut client = client().await;
+  let existing = create(&mut client, "existing", 1).await;
+
+  let mut events = client
+    .watch_todos(WatchTodosRequest { send_initial: true })
+    .await
+    .unwrap()
+    .into_inner();
+  let added = create(&mut client, "added", 2).await;
+  client
+    .delete_todo(DeleteTodoRequest { id: existing.id })
+    .await
+    .unwrap();
+
+  let mut seen = Vec::new();
+  for _ in 0 .. 3 {
+    let event = events.message().await.unwrap().unwrap();
+    seen.push((event.kind(), event.todo.unwrap()));
+  }
+  assert_eq!(
+    seen,
+    vec![
+      (Kind::Created, existing.clone()),
+      (Kind::Created, added),
+      (Kind::Deleted, existing),
+    ]
+  );
+}
+
+#[tokio::test]
+async fn imports_a_stream() {
+  let mut client = client().await;
+
+  let requests = tokio_stream::iter(vec![new("one", 1), new("", 2), new("three", 3)]);
+  let mut responses = client.import_todos(requests).await.unwrap().into_inner();
+  let mut results = Vec::new();
+  while let Some(response) = responses.message().await.unwrap() {
+    results.push((response.index, response.result.unwrap()));
+  }
+
+  assert_eq!(results.len(), 3);
+  assert!(matches!(&results[0], (0, import_todos_response::Result::Todo(t)) if t.name == "one"));
+  assert!(
+    matches!(&results[1], (1, import_todos_response::Result::Error(e)) if e.contains("name"))
+  );
+  assert!(matches!(&results[2], (2, import_todos_response::Result::Todo(t)) if t.name == "three"));
+
+  let todos = client.get_todos(()).await.unwrap().into_inner().todos;
+  assert_eq!(
+    todos.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(),
+    ["one", "three"]
+  );
+}
+
+#[tokio::test]
+async fn persists_across_restarts() {
+  let dir = std::env::temp_dir().join(format!("rust-grpc-test-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  let path = dir.join("todos.db");
+  let path = path.to_str().unwrap();
+
+  let mut client = TodoClient::new(channel(Store::open(path).await.unwrap()).await);
+  let todo = create(&mut client, "kept", 4).await;
+  drop(client);
+
+  let mut client = TodoClient::new(channel(Store::open(path).await.unwrap()).await);
+  let found = client
+    .get_todo(GetTodoRequest { id: todo.id })
+    .await
+    .unwrap();
+  assert_eq!(found.into_inner(), todo);
+
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn serves_health_and_reflection() {
+  let channel = channel(Store::memory().await.unwrap()).await;
+
+  let mut health = HealthClient::new(channel.clone());
+  let response = health
+    .check(HealthCheckRequest {
+      service: "todo.Todo".to_string(),
+    })
+    .await
+    .unwrap()
+    .into_inner();
+  assert_eq!(response.status(), ServingStatus::Serving);
+
+  let mut reflection = ServerReflectionClient::new(channel);
+  let request = ServerReflectionRequest {
+    host: String::new(),
+    message_request: Some(MessageRequest::ListServices(String::new())),
+  };
+  let mut responses = reflection
+    .server_reflection_info(tokio_stream::once(request))
+    .await
+    .unwrap()
+    .into_inner();
+  let response = responses.message().await.unwrap().unwrap();
+  let Some(MessageResponse::ListServicesResponse(list)) = response.message_response else {
+    panic!("unexpected {:?}", response.message_response);
+  };
+  let services = list.service.into_iter().map(|s| s.name).collect::<Vec<_>>();
+  assert!(
+    services.contains(&"todo.Todo".to_string()),
+    "{:?}",
+    services
+  );
+  assert!(
+    services.contains(&"grpc.health.v1.Health".to_string()),
+    "{:?}",
+    services
+  );
+}
@@ -0,0 +1,50 @@
+use std::{
+  sync::atomic::{AtomicU64, Ordering},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Where the current time comes from, so tests can pin it.
+pub trait Clock: Send + Sync {
+  /// Seconds since the Unix epoch.
+  fn now(&self) -> u64;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> u64 {
+    SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap()
+      .as_secs()
+  }
+}
+
+/// A clock that only moves when told to.
+#[derive(Debug, Default)]
+pub struct FixedClock {
+  now: AtomicU64,
+}
+
+impl FixedClock {
+  pub fn new(now: u64) -> FixedClock {
+    FixedClock {
+      now: AtomicU64::new(now),
+    }
+  }
+
+  pub fn set(&self, now: u64) {
+    self.now.store(now, Ordering::SeqCst);
+  }
+
+  pub fn advance(&self, seconds: u64) {
+    self.now.fetch_add(seconds, Ordering::SeqCst);
+  }
+}
+
+impl Clock for FixedClock {
+  fn now(&self) -> u64 {
+    self.now.load(Ordering::SeqCst)
+  }
+}
@@ -0,0 +1,14 @@
+#[derive(Debug, thiserror::Error)]
+pub enum TwoFactorError {
+  #[error("invalid code")]
+  InvalidCode,
+  /// The code was right, but its time step has already been used.
+  #[error("code already used")]
+  ReplayedCode,
+  #[error("invalid recovery code")]
+  InvalidRecoveryCode,
+  #[error("invalid secret")]
+  InvalidSecret,
+  #[error("{0}")]
+  Totp(String),
+}
@@ -0,0 +1,11 @@
+//! Two-factor authentication with TOTP: enrollment through `otpauth://` URIs and QR codes,
+//! replay-safe verification, recovery codes and an axum login flow enforcing it all.
+
+pub mod clock;
+mod error;
+pub mod recovery;
+mod two_factor;
+pub mod web;
+
+pub use error::TwoFactorError;
+pub use two_factor::{Enrollment, TwoFactor, TwoFactorConfig, TwoFactorState};
@@ -1,54 +1,82 @@
-// use std::fs::File;
-// use std::io::Write;
-// use std::time::SystemTime;
-use totp_rs::{Algorithm, Secret, TOTP};
+// cargo run -- enroll alice --qr qr.png
+// cargo run -- code <secret>
+// cargo run -- serve
+// curl -c jar -H 'content-type: application/json' -d '{"username":"alice","password":"hunter42"}'
+// localhost:3000/login curl -b jar -c jar -X POST localhost:3000/two-factor/enroll
+// curl -b jar -c jar -H 'content-type: application/json' -d '{"code":"123456"}'
+// localhost:3000/two-factor/confirm curl -b jar localhost:3000/me
+use std::{fs, net::SocketAddr, path::PathBuf, sync::Arc};
 
-fn main() {
-  // println!("Hello, world!");
-  // let totp = TOTP::new(Algorithm::SHA1, 6, 1, 30, "supersecret");
-  // create TOTP from base32 secret
-  let secret_b32 = Secret::Encoded(String::from("OBWGC2LOFVZXI4TJNZTS243FMNZGK5BNGEZDG"));
-  let totp_b32 = TOTP::new(
-    Algorithm::SHA1,
-    6,
-    1,
-    30,
-    secret_b32.to_bytes().unwrap(),
-    Some("issuer".to_string()),
-    "user-account".to_string(),
-  )
-  .unwrap();
+use clap::{Parser, Subcommand};
+use totp_rs::{Algorithm, Secret, TOTP};
+use totp_rs_example::{
+  clock::SystemClock,
+  web::{router, AppState},
+  TwoFactor, TwoFactorConfig,
+};
 
-  println!(
-    "base32 {} ; raw {}",
-    secret_b32,
-    secret_b32.to_raw().unwrap()
-  );
-  println!(
-    "code from base32:\t{}",
-    totp_b32.generate_current().unwrap()
-  );
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+  #[command(subcommand)]
+  command: Command,
+}
 
-  // create TOTP from raw binary value
-  let secret = [
-    0x70, 0x6c, 0x61, 0x69, 0x6e, 0x2d, 0x73, 0x74, 0x72, 0x69, 0x6e, 0x67, 0x2d, 0x73, 0x65, 0x63,
-    0x72, 0x65, 0x74, 0x2d, 0x31, 0x32, 0x33,
-  ];
-  let secret_raw = Secret::Raw(secret.to_vec());
-  let totp_raw = TOTP::new(
-    Algorithm::SHA1,
-    6,
-    1,
-    30,
-    secret_raw.to_bytes().unwrap(),
-    Some("issuer".to_string()),
-    "user-account".to_string(),
-  )
-  .unwrap();
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Generate a secret and print its otpauth:// URI
+  Enroll {
+    account: String,
+    /// Where to write the QR code
+    #[arg(long, default_value = "qr.png")]
+    qr: PathBuf,
+  },
+  /// Print the current code of a base32 secret, like an authenticator app would
+  Code { secret: String },
+  /// Serve the login flow, with the demo user alice / hunter42
+  Serve {
+    #[arg(long, default_value = "127.0.0.1:3000")]
+    addr: SocketAddr,
+  },
+}
 
-  println!("raw {} ; base32 {}", secret_raw, secret_raw.to_encoded());
-  println!(
-    "code from raw secret:\t{}",
-    totp_raw.generate_current().unwrap()
-  );
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+  let args = Args::parse();
+  match args.command {
+    Command::Enroll { account, qr } => {
+      let two_factor = TwoFactor::new(TwoFactorConfig::default(), Arc::new(SystemClock));
+      let enrollment = two_factor.enroll(&account)?;
+      fs::write(&qr, &enrollment.qr_png)?;
+      println!("secret: {}", enrollment.secret);
+      println!("uri: {}", enrollment.uri);
+      println!("qr code: {}", qr.display());
+    }
+    Command::Code { secret } => {
+      let config = TwoFactorConfig::default();
+      let totp = TOTP::new(
+        Algorithm::SHA1,
+        config.digits,
+        1,
+        config.step,
+        Secret::Encoded(secret)
+          .to_bytes()
+          .map_err(|e| format!("{:?}", e))?,
+        None,
+        String::new(),
+      )?;
+      println!("{}", totp.generate_current()?);
+    }
+    Command::Serve { addr } => {
+      let state = Arc::new(AppState::new(
+        TwoFactorConfig::default(),
+        Arc::new(SystemClock),
+      ));
+      state.add_user("alice", "hunter42");
+      let listener = tokio::net::TcpListener::bind(addr).await?;
+      println!("Listening on {}", listener.local_addr()?);
+      axum::serve(listener, router(state)).await?;
+    }
+  }
+  Ok(())
 }
@@ -0,0 +1,67 @@
+//! One-time recovery codes, for when the authenticator app is lost. Only their SHA-256 hashes
+//! are stored: the codes are random, so unlike passwords they need no slow hash.
+
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+pub const RECOVERY_CODES: usize = 10;
+/// Without 0/o and 1/l, which are easy to misread.
+const ALPHABET: &[u8] = b"23456789abcdefghijkmnpqrstuvwxyz";
+/// Two groups of five characters, 5 bits each from 32 symbols: 25 bits per group, 50 per code.
+const GROUP: usize = 5;
+
+/// Returns the codes to show the user once, and the hashes to store.
+pub fn generate() -> (Vec<String>, Vec<String>) {
+  let mut rng = rand::thread_rng();
+  let codes: Vec<String> = (0 .. RECOVERY_CODES)
+    .map(|_| {
+      let chars: String = (0 .. 2 * GROUP)
+        .map(|_| ALPHABET[rng.gen_range(0 .. ALPHABET.len())] as char)
+        .collect();
+      format!("{}-{}", &chars[.. GROUP], &chars[GROUP ..])
+    })
+    .collect();
+  let hashes = codes.iter().map(|code| hash(code)).collect();
+  (codes, hashes)
+}
+
+/// Removes the hash of `code` from `hashes`, returning whether it was there.
+pub fn redeem(hashes: &mut Vec<String>, code: &str) -> bool {
+  let hash = hash(code);
+  let before = hashes.len();
+  hashes.retain(|stored| !constant_time_eq::constant_time_eq(stored.as_bytes(), hash.as_bytes()));
+  hashes.len() < before
+}
+
+/// Hashes the code as typed, ignoring case, spaces and dashes.
+fn hash(code: &str) -> String {
+  let normalized: String = code
+    .chars()
+    .filter(|c| !matches!(c, '-' | ' '))
+    .map(|c| c.to_ascii_lowercase())
+    .collect();
+  Sha256::digest(normalized.as_bytes())
+    .iter()
+    .map(|byte| format!("{:02x}", byte))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn codes_work_once_and_are_stored_hashed() {
+    let (codes, mut hashes) = generate();
+    assert_eq!(codes.len(), RECOVERY_CODES);
+    assert!(hashes.iter().all(|hash| !codes.contains(hash)));
+
+    assert!(redeem(
+      &mut hashes,
+      &codes[3].to_uppercase().replace('-', " ")
+    ));
+    assert!(!redeem(&mut hashes, &codes[3]));
+    assert_eq!(hashes.len(), RECOVERY_CODES - 1);
+    assert!(!redeem(&mut hashes, "aaaaa-aaaaa"));
+  }
+}
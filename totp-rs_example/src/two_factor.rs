@@ -0,0 +1,258 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::{clock::Clock, error::TwoFactorError, recovery};
+
+/// The parameters authenticator apps assume when an `otpauth://` URI leaves them out.
+#[derive(Debug, Clone)]
+pub struct TwoFactorConfig {
+  pub issuer: String,
+  pub digits: usize,
+  /// Seconds per time step.
+  pub step: u64,
+  /// Time steps before and after the current one that are accepted, for clock skew.
+  pub skew: u64,
+}
+
+impl Default for TwoFactorConfig {
+  fn default() -> Self {
+    TwoFactorConfig {
+      issuer: "Crab widgits SE".to_string(),
+      digits: 6,
+      step: 30,
+      skew: 1,
+    }
+  }
+}
+
+/// What a user needs to add the account to an authenticator app.
+#[derive(Debug, Clone)]
+pub struct Enrollment {
+  /// Base32, for typing in by hand.
+  pub secret: String,
+  pub uri: String,
+  pub qr_png: Vec<u8>,
+}
+
+/// What to store per user once 2FA is enabled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TwoFactorState {
+  /// Base32.
+  pub secret: String,
+  /// Time step of the last accepted code: it and every earlier step are spent.
+  pub last_step: Option<u64>,
+  /// SHA-256 hashes of the unused recovery codes.
+  pub recovery_codes: Vec<String>,
+}
+
+impl TwoFactorState {
+  /// State for a confirmed `secret`, with the recovery codes to show the user once.
+  pub fn new(secret: String) -> (TwoFactorState, Vec<String>) {
+    let (codes, hashes) = recovery::generate();
+    let state = TwoFactorState {
+      secret,
+      last_step: None,
+      recovery_codes: hashes,
+    };
+    (state, codes)
+  }
+
+  /// Replaces the recovery codes, returning the new ones.
+  pub fn regenerate_recovery_codes(&mut self) -> Vec<String> {
+    let (codes, hashes) = recovery::generate();
+    self.recovery_codes = hashes;
+    codes
+  }
+
+  pub fn redeem_recovery_code(&mut self, code: &str) -> Result<(), TwoFactorError> {
+    if recovery::redeem(&mut self.recovery_codes, code) {
+      Ok(())
+    } else {
+      Err(TwoFactorError::InvalidRecoveryCode)
+    }
+  }
+}
+
+/// TOTP (RFC 6238) enrollment and verification.
+#[derive(Clone)]
+pub struct TwoFactor {
+  config: TwoFactorConfig,
+  clock: Arc<dyn Clock>,
+}
+
+impl TwoFactor {
+  pub fn new(config: TwoFactorConfig, clock: Arc<dyn Clock>) -> TwoFactor {
+    TwoFactor { config, clock }
+  }
+
+  /// Generates a new secret for `account`. Nothing is enabled until the user proves, with
+  /// [`TwoFactor::check`], that their app produces the right codes.
+  pub fn enroll(&self, account: &str) -> Result<Enrollment, TwoFactorError> {
+    let secret = Secret::generate_secret().to_encoded().to_string();
+    self.enrollment(account, &secret)
+  }
+
+  /// The URI and QR code of an existing secret, to show the pending enrollment again.
+  pub fn enrollment(&self, account: &str, secret: &str) -> Result<Enrollment, TwoFactorError> {
+    let totp = self.totp(secret, account)?;
+    Ok(Enrollment {
+      secret: secret.to_string(),
+      uri: totp.get_url(),
+      qr_png: totp.get_qr_png().map_err(TwoFactorError::Totp)?,
+    })
+  }
+
+  /// Checks `code` against `secret` without replay protection, returning its time step. Only
+  /// meant for confirming an enrollment, whose step then seeds [`TwoFactorState::last_step`].
+  pub fn check(&self, secret: &str, code: &str) -> Result<u64, TwoFactorError> {
+    let totp = self.totp(secret, "")?;
+    self
+      .matching_step(&totp, code, None)
+      .ok_or(TwoFactorError::InvalidCode)
+  }
+
+  /// Accepts a code from the current time step or one within the skew window, once: the step is
+  /// recorded, and neither it nor any earlier one is accepted again.
+  pub fn verify(&self, state: &mut TwoFactorState, code: &str) -> Result<(), TwoFactorError> {
+    let totp = self.totp(&state.secret, "")?;
+    if let Some(step) = self.matching_step(&totp, code, state.last_step) {
+      state.last_step = Some(step);
+      return Ok(());
+    }
+    if self.matching_step(&totp, code, None).is_some() {
+      return Err(TwoFactorError::ReplayedCode);
+    }
+    Err(TwoFactorError::InvalidCode)
+  }
+
+  /// The time steps of the window are all tried, so the time taken does not tell which matched.
+  fn matching_step(&self, totp: &TOTP, code: &str, after: Option<u64>) -> Option<u64> {
+    let code = code.trim();
+    let current = self.clock.now() / self.config.step;
+    let mut matched = None;
+    for step in current.saturating_sub(self.config.skew) ..= current + self.config.skew {
+      let expected = totp.generate(step * self.config.step);
+      let fresh = after.is_none_or(|after| step > after);
+      if constant_time_eq::constant_time_eq(expected.as_bytes(), code.as_bytes()) && fresh {
+        matched = Some(step);
+      }
+    }
+    matched
+  }
+
+  fn totp(&self, secret: &str, account: &str) -> Result<TOTP, TwoFactorError> {
+    let secret = Secret::Encoded(secret.to_string())
+      .to_bytes()
+      .map_err(|_| TwoFactorError::InvalidSecret)?;
+    TOTP::new(
+      Algorithm::SHA1,
+      self.config.digits,
+      self.config.skew as u8,
+      self.config.step,
+      secret,
+      Some(self.config.issuer.clone()),
+      account.to_string(),
+    )
+    .map_err(|e| TwoFactorError::Totp(e.to_string()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::clock::FixedClock;
+
+  /// The SHA-1 secret of RFC 6238's test vectors, "12345678901234567890".
+  const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+  fn two_factor(now: u64) -> (TwoFactor, Arc<FixedClock>) {
+    let clock = Arc::new(FixedClock::new(now));
+    (
+      TwoFactor::new(TwoFactorConfig::default(), clock.clone()),
+      clock,
+    )
+  }
+
+  fn state() -> TwoFactorState {
+    TwoFactorState::new(SECRET.to_string()).0
+  }
+
+  #[test]
+  fn codes_match_rfc_6238() {
+    // At 59 seconds the RFC's 8 digit code is 94287082
+    let (two_factor, _) = two_factor(59);
+    assert_eq!(two_factor.check(SECRET, "287082").unwrap(), 1);
+  }
+
+  #[test]
+  fn codes_within_the_skew_window_are_accepted() {
+    let (two_factor, clock) = two_factor(1111111109);
+    // 081804 belongs to step 37037036, the current one
+    let mut state = state();
+    two_factor.verify(&mut state, "081804").unwrap();
+    assert_eq!(state.last_step, Some(37037036));
+
+    // A step late, the phone's clock running behind
+    let mut state = self::state();
+    clock.advance(30);
+    two_factor.verify(&mut state, "081804").unwrap();
+
+    let mut state = self::state();
+    clock.advance(30);
+    assert!(matches!(
+      two_factor.verify(&mut state, "081804"),
+      Err(TwoFactorError::InvalidCode)
+    ));
+  }
+
+  #[test]
+  fn each_time_step_is_used_once() {
+    let (two_factor, clock) = two_factor(1111111109);
+    let mut state = state();
+    two_factor.verify(&mut state, "081804").unwrap();
+    assert!(matches!(
+      two_factor.verify(&mut state, "081804"),
+      Err(TwoFactorError::ReplayedCode)
+    ));
+
+    // Once a code is used, an older one still in the window is spent too
+    clock.advance(30);
+    let next = TOTP::new(
+      Algorithm::SHA1,
+      6,
+      1,
+      30,
+      Secret::Encoded(SECRET.to_string()).to_bytes().unwrap(),
+      None,
+      String::new(),
+    )
+    .unwrap()
+    .generate(clock.now());
+    two_factor.verify(&mut state, &next).unwrap();
+    assert!(matches!(
+      two_factor.verify(&mut state, "081804"),
+      Err(TwoFactorError::ReplayedCode)
+    ));
+    assert!(matches!(
+      two_factor.verify(&mut state, "000000"),
+      Err(TwoFactorError::InvalidCode)
+    ));
+  }
+
+  #[test]
+  fn enrollment_has_an_otpauth_uri_and_a_qr_code() {
+    let (two_factor, _) = two_factor(0);
+    let enrollment = two_factor.enroll("alice@crabs.local").unwrap();
+    assert!(enrollment
+      .uri
+      .starts_with("otpauth://totp/Crab%20widgits%20SE:alice%40crabs.local?secret="));
+    assert!(enrollment.uri.contains(&enrollment.secret));
+    assert!(enrollment.qr_png.starts_with(b"\x89PNG"));
+    assert_ne!(
+      two_factor.enroll("alice@crabs.local").unwrap().secret,
+      enrollment.secret
+    );
+  }
+}
@@ -0,0 +1,501 @@
+//! Password login followed by a TOTP or recovery code, over cookie sessions kept in memory.
+
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+};
+
+use argon2::{
+  password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+  Argon2,
+};
+use axum::{
+  extract::{Request, State},
+  http::{header, HeaderMap, StatusCode},
+  middleware::{self, Next},
+  response::{IntoResponse, Response},
+  routing::{delete, get, post},
+  Extension, Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+  clock::Clock,
+  error::TwoFactorError,
+  two_factor::{TwoFactor, TwoFactorConfig, TwoFactorState},
+};
+
+const COOKIE: &str = "session";
+/// How long a user has to enter their code after the password.
+const PENDING_TTL: u64 = 5 * 60;
+const SESSION_TTL: u64 = 12 * 60 * 60;
+/// Wrong codes a pending session may send before it is dropped and the password is needed again.
+const MAX_ATTEMPTS: u32 = 5;
+
+pub struct User {
+  password_hash: String,
+  two_factor: Option<TwoFactorState>,
+  /// Secret of an enrollment not yet confirmed with a code.
+  pending_secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+  /// The password was right, the second factor is still to come.
+  AwaitingCode { attempts: u32 },
+  /// `two_factor` is false for users who have not enrolled yet: they may only enroll.
+  SignedIn { two_factor: bool },
+}
+
+impl Stage {
+  fn ttl(self) -> u64 {
+    match self {
+      Stage::AwaitingCode { .. } => PENDING_TTL,
+      Stage::SignedIn { .. } => SESSION_TTL,
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+struct Session {
+  username: String,
+  stage: Stage,
+  expires_at: u64,
+}
+
+pub struct AppState {
+  two_factor: TwoFactor,
+  clock: Arc<dyn Clock>,
+  users: Mutex<HashMap<String, User>>,
+  sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl AppState {
+  pub fn new(config: TwoFactorConfig, clock: Arc<dyn Clock>) -> AppState {
+    AppState {
+      two_factor: TwoFactor::new(config, clock.clone()),
+      clock,
+      users: Mutex::default(),
+      sessions: Mutex::default(),
+    }
+  }
+
+  pub fn add_user(&self, username: &str, password: &str) {
+    let password_hash = Argon2::default()
+      .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+      .unwrap()
+      .to_string();
+    self.users.lock().unwrap().insert(
+      username.to_string(),
+      User {
+        password_hash,
+        two_factor: None,
+        pending_secret: None,
+      },
+    );
+  }
+
+  fn start_session(&self, username: &str, stage: Stage) -> String {
+    let mut token = [0; 32];
+    rand::thread_rng().fill_bytes(&mut token);
+    let token = URL_SAFE_NO_PAD.encode(token);
+    let session = Session {
+      username: username.to_string(),
+      stage,
+      expires_at: self.clock.now() + stage.ttl(),
+    };
+    self.sessions.lock().unwrap().insert(token.clone(), session);
+    token
+  }
+
+  /// The session the request's cookie points to, if it has not expired.
+  fn session(&self, headers: &HeaderMap) -> Result<(String, Session), ApiError> {
+    let token = session_cookie(headers).ok_or(ApiError::UNAUTHORIZED)?;
+    let mut sessions = self.sessions.lock().unwrap();
+    let session = sessions.get(&token).ok_or(ApiError::UNAUTHORIZED)?;
+    if session.expires_at <= self.clock.now() {
+      sessions.remove(&token);
+      return Err(ApiError::UNAUTHORIZED);
+    }
+    Ok((token, session.clone()))
+  }
+
+  fn set_stage(&self, token: &str, stage: Stage) {
+    if let Some(session) = self.sessions.lock().unwrap().get_mut(token) {
+      session.stage = stage;
+    }
+  }
+
+  /// Counts a code sent by a pending session before it is checked, so that guesses sent at once
+  /// cannot share an attempt, and returns how many it has sent. A session that already sent
+  /// [`MAX_ATTEMPTS`] is dropped.
+  fn count_attempt(&self, token: &str) -> Result<u32, ApiError> {
+    let mut sessions = self.sessions.lock().unwrap();
+    let session = sessions.get_mut(token).ok_or(ApiError::UNAUTHORIZED)?;
+    let Stage::AwaitingCode { attempts } = &mut session.stage else {
+      return Err(ApiError::new(StatusCode::CONFLICT, "already_signed_in"));
+    };
+    if *attempts >= MAX_ATTEMPTS {
+      sessions.remove(token);
+      return Err(ApiError::new(StatusCode::UNAUTHORIZED, "too_many_attempts"));
+    }
+    *attempts += 1;
+    Ok(*attempts)
+  }
+
+  /// Checks a code of a signed in user, for actions that change their 2FA settings.
+  fn verify(&self, username: &str, code: &str) -> Result<(), ApiError> {
+    let mut users = self.users.lock().unwrap();
+    let state = users
+      .get_mut(username)
+      .and_then(|user| user.two_factor.as_mut())
+      .ok_or(ApiError::NOT_ENROLLED)?;
+    self.two_factor.verify(state, code).map_err(ApiError::from)
+  }
+}
+
+/// Who the request is from, added by [`require_password`] and [`require_two_factor`].
+#[derive(Debug, Clone)]
+pub struct CurrentUser(pub String);
+
+#[derive(Debug, Clone, Copy)]
+pub struct ApiError {
+  status: StatusCode,
+  error: &'static str,
+}
+
+impl ApiError {
+  const UNAUTHORIZED: ApiError = ApiError::new(StatusCode::UNAUTHORIZED, "unauthorized");
+  const NOT_ENROLLED: ApiError = ApiError::new(StatusCode::CONFLICT, "two_factor_not_enabled");
+
+  const fn new(status: StatusCode, error: &'static str) -> ApiError {
+    ApiError { status, error }
+  }
+}
+
+impl From<TwoFactorError> for ApiError {
+  fn from(error: TwoFactorError) -> Self {
+    match error {
+      TwoFactorError::InvalidCode => ApiError::new(StatusCode::UNAUTHORIZED, "invalid_code"),
+      TwoFactorError::ReplayedCode => ApiError::new(StatusCode::UNAUTHORIZED, "code_already_used"),
+      TwoFactorError::InvalidRecoveryCode => {
+        ApiError::new(StatusCode::UNAUTHORIZED, "invalid_recovery_code")
+      }
+      TwoFactorError::InvalidSecret | TwoFactorError::Totp(_) => {
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error")
+      }
+    }
+  }
+}
+
+impl IntoResponse for ApiError {
+  fn into_response(self) -> Response {
+    (self.status, Json(json!({ "error": self.error }))).into_response()
+  }
+}
+
+/// Lets through sessions that passed the password, whether or not 2FA is set up: for enrolling.
+pub async fn require_password(
+  State(state): State<Arc<AppState>>,
+  mut request: Request,
+  next: Next,
+) -> Result<Response, ApiError> {
+  let (_, session) = state.session(request.headers())?;
+  match session.stage {
+    Stage::SignedIn { .. } => {
+      request
+        .extensions_mut()
+        .insert(CurrentUser(session.username));
+      Ok(next.run(request).await)
+    }
+    Stage::AwaitingCode { .. } => Err(ApiError::new(StatusCode::FORBIDDEN, "two_factor_required")),
+  }
+}
+
+/// Lets through sessions that passed both factors. Users who have not enrolled yet are told to.
+pub async fn require_two_factor(
+  State(state): State<Arc<AppState>>,
+  mut request: Request,
+  next: Next,
+) -> Result<Response, ApiError> {
+  let (_, session) = state.session(request.headers())?;
+  match session.stage {
+    Stage::SignedIn { two_factor: true } => {
+      request
+        .extensions_mut()
+        .insert(CurrentUser(session.username));
+      Ok(next.run(request).await)
+    }
+    Stage::SignedIn { two_factor: false } => Err(ApiError::new(
+      StatusCode::FORBIDDEN,
+      "two_factor_enrollment_required",
+    )),
+    Stage::AwaitingCode { .. } => Err(ApiError::new(StatusCode::FORBIDDEN, "two_factor_required")),
+  }
+}
+
+pub fn router(state: Arc<AppState>) -> Router {
+  let enrollment = Router::new()
+    .route("/two-factor/enroll", post(enroll))
+    .route("/two-factor/qr.png", get(qr_code))
+    .route("/two-factor/confirm", post(confirm))
+    .route_layer(middleware::from_fn_with_state(
+      state.clone(),
+      require_password,
+    ));
+  let protected = Router::new()
+    .route("/me", get(me))
+    .route(
+      "/two-factor/recovery-codes",
+      post(regenerate_recovery_codes),
+    )
+    .route("/two-factor", delete(disable))
+    .route_layer(middleware::from_fn_with_state(
+      state.clone(),
+      require_two_factor,
+    ));
+  Router::new()
+    .route("/login", post(login))
+    .route("/login/two-factor", post(login_two_factor))
+    .route("/logout", post(logout))
+    .merge(enrollment)
+    .merge(protected)
+    .with_state(state)
+}
+
+#[derive(Deserialize)]
+struct Credentials {
+  username: String,
+  password: String,
+}
+
+async fn login(
+  State(state): State<Arc<AppState>>,
+  Json(credentials): Json<Credentials>,
+) -> Result<Response, ApiError> {
+  let invalid = ApiError::new(StatusCode::UNAUTHORIZED, "invalid_credentials");
+  let (password_hash, enrolled) = {
+    let users = state.users.lock().unwrap();
+    let user = users.get(&credentials.username).ok_or(invalid)?;
+    (user.password_hash.clone(), user.two_factor.is_some())
+  };
+  let valid = tokio::task::spawn_blocking(move || {
+    let hash = PasswordHash::new(&password_hash).unwrap();
+    Argon2::default()
+      .verify_password(credentials.password.as_bytes(), &hash)
+      .is_ok()
+  })
+  .await
+  .unwrap();
+  if !valid {
+    return Err(invalid);
+  }
+  let stage = if enrolled {
+    Stage::AwaitingCode { attempts: 0 }
+  } else {
+    Stage::SignedIn { two_factor: false }
+  };
+  let token = state.start_session(&credentials.username, stage);
+  Ok(
+    (
+      [(header::SET_COOKIE, set_cookie(&token, stage.ttl()))],
+      Json(json!({ "two_factor_required": enrolled })),
+    )
+      .into_response(),
+  )
+}
+
+#[derive(Deserialize)]
+struct SecondFactor {
+  code: Option<String>,
+  recovery_code: Option<String>,
+}
+
+/// Completes a login with a TOTP code or a recovery code. The session gets a new token, so one
+/// seen before the second factor is worth nothing.
+async fn login_two_factor(
+  State(state): State<Arc<AppState>>,
+  headers: HeaderMap,
+  Json(factor): Json<SecondFactor>,
+) -> Result<Response, ApiError> {
+  let (token, session) = state.session(&headers)?;
+  let (code, recovery) = match (factor.code, factor.recovery_code) {
+    (Some(code), _) => (code, false),
+    (None, Some(code)) => (code, true),
+    (None, None) => return Err(ApiError::new(StatusCode::BAD_REQUEST, "missing_code")),
+  };
+  let attempts = state.count_attempt(&token)?;
+
+  let result = {
+    let mut users = state.users.lock().unwrap();
+    let two_factor = users
+      .get_mut(&session.username)
+      .and_then(|user| user.two_factor.as_mut())
+      .ok_or(ApiError::UNAUTHORIZED)?;
+    if recovery {
+      two_factor.redeem_recovery_code(&code)
+    } else {
+      state.two_factor.verify(two_factor, &code)
+    }
+    .map(|()| two_factor.recovery_codes.len())
+  };
+
+  match result {
+    Ok(recovery_codes_left) => {
+      state.sessions.lock().unwrap().remove(&token);
+      let token = state.start_session(&session.username, Stage::SignedIn { two_factor: true });
+      Ok(
+        (
+          [(header::SET_COOKIE, set_cookie(&token, SESSION_TTL))],
+          Json(json!({ "recovery_codes_left": recovery_codes_left })),
+        )
+          .into_response(),
+      )
+    }
+    Err(_) if attempts >= MAX_ATTEMPTS => {
+      state.sessions.lock().unwrap().remove(&token);
+      Err(ApiError::new(StatusCode::UNAUTHORIZED, "too_many_attempts"))
+    }
+    Err(error) => Err(error.into()),
+  }
+}
+
+async fn logout(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+  if let Some(token) = session_cookie(&headers) {
+    state.sessions.lock().unwrap().remove(&token);
+  }
+  (
+    StatusCode::NO_CONTENT,
+    [(header::SET_COOKIE, set_cookie("", 0))],
+  )
+    .into_response()
+}
+
+async fn me(Extension(CurrentUser(username)): Extension<CurrentUser>) -> Json<Value> {
+  Json(json!({ "username": username }))
+}
+
+/// Starts (or restarts) an enrollment: a new secret, pending until [`confirm`].
+async fn enroll(
+  State(state): State<Arc<AppState>>,
+  Extension(CurrentUser(username)): Extension<CurrentUser>,
+) -> Result<Json<Value>, ApiError> {
+  let mut users = state.users.lock().unwrap();
+  let user = users.get_mut(&username).ok_or(ApiError::UNAUTHORIZED)?;
+  if user.two_factor.is_some() {
+    return Err(ApiError::new(
+      StatusCode::CONFLICT,
+      "two_factor_already_enabled",
+    ));
+  }
+  let enrollment = state.two_factor.enroll(&username)?;
+  user.pending_secret = Some(enrollment.secret.clone());
+  Ok(Json(json!({
+    "secret": enrollment.secret,
+    "uri": enrollment.uri,
+    "qr_png": base64::engine::general_purpose::STANDARD.encode(enrollment.qr_png),
+  })))
+}
+
+async fn qr_code(
+  State(state): State<Arc<AppState>>,
+  Extension(CurrentUser(username)): Extension<CurrentUser>,
+) -> Result<Response, ApiError> {
+  let secret = state
+    .users
+    .lock()
+    .unwrap()
+    .get(&username)
+    .and_then(|user| user.pending_secret.clone())
+    .ok_or(ApiError::new(
+      StatusCode::NOT_FOUND,
+      "no_pending_enrollment",
+    ))?;
+  let enrollment = state.two_factor.enrollment(&username, &secret)?;
+  Ok(([(header::CONTENT_TYPE, "image/png")], enrollment.qr_png).into_response())
+}
+
+#[derive(Deserialize)]
+struct Code {
+  code: String,
+}
+
+/// Enables 2FA once the app shows the right code, and returns the recovery codes: the only time
+/// they are shown.
+async fn confirm(
+  State(state): State<Arc<AppState>>,
+  headers: HeaderMap,
+  Extension(CurrentUser(username)): Extension<CurrentUser>,
+  Json(Code { code }): Json<Code>,
+) -> Result<Json<Value>, ApiError> {
+  let recovery_codes = {
+    let mut users = state.users.lock().unwrap();
+    let user = users.get_mut(&username).ok_or(ApiError::UNAUTHORIZED)?;
+    let secret = user.pending_secret.clone().ok_or(ApiError::new(
+      StatusCode::NOT_FOUND,
+      "no_pending_enrollment",
+    ))?;
+    let step = state.two_factor.check(&secret, &code)?;
+    let (mut two_factor, recovery_codes) = TwoFactorState::new(secret);
+    two_factor.last_step = Some(step);
+    user.two_factor = Some(two_factor);
+    user.pending_secret = None;
+    recovery_codes
+  };
+  let (token, _) = state.session(&headers)?;
+  state.set_stage(&token, Stage::SignedIn { two_factor: true });
+  Ok(Json(json!({ "recovery_codes": recovery_codes })))
+}
+
+async fn regenerate_recovery_codes(
+  State(state): State<Arc<AppState>>,
+  Extension(CurrentUser(username)): Extension<CurrentUser>,
+  Json(Code { code }): Json<Code>,
+) -> Result<Json<Value>, ApiError> {
+  state.verify(&username, &code)?;
+  let mut users = state.users.lock().unwrap();
+  let two_factor = users
+    .get_mut(&username)
+    .and_then(|user| user.two_factor.as_mut())
+    .ok_or(ApiError::NOT_ENROLLED)?;
+  Ok(Json(
+    json!({ "recovery_codes": two_factor.regenerate_recovery_codes() }),
+  ))
+}
+
+async fn disable(
+  State(state): State<Arc<AppState>>,
+  headers: HeaderMap,
+  Extension(CurrentUser(username)): Extension<CurrentUser>,
+  Json(Code { code }): Json<Code>,
+) -> Result<StatusCode, ApiError> {
+  state.verify(&username, &code)?;
+  if let Some(user) = state.users.lock().unwrap().get_mut(&username) {
+    user.two_factor = None;
+  }
+  let (token, _) = state.session(&headers)?;
+  state.set_stage(&token, Stage::SignedIn { two_factor: false });
+  Ok(StatusCode::NO_CONTENT)
+}
+
+fn session_cookie(headers: &HeaderMap) -> Option<String> {
+  headers
+    .get_all(header::COOKIE)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(';'))
+    .find_map(|pair| {
+      let (name, value) = pair.trim().split_once('=')?;
+      (name == COOKIE).then(|| value.to_string())
+    })
+}
+
+fn set_cookie(token: &str, max_age: u64) -> String {
+  format!(
+    "{}={}; Max-Age={}; Path=/; HttpOnly; Secure; SameSite=Strict",
+    COOKIE, token, max_age
+  )
+}
@@ -0,0 +1,334 @@
+use std::sync::Arc;
+
+use axum::{
+  body::Body,
+  http::{header, Method, Request, StatusCode},
+  Router,
+};
+use http_body_util::BodyExt;
+use serde_json::{json, Value};
+use totp_rs::{Algorithm, Secret, TOTP};
+use totp_rs_example::{
+  clock::{Clock, FixedClock},
+  web::{router, AppState},
+  TwoFactorConfig,
+};
+use tower::ServiceExt;
+
+const START: u64 = 1_700_000_000;
+
+#[derive(Clone)]
+struct Client {
+  app: Router,
+  cookie: Option<String>,
+}
+
+impl Client {
+  async fn send(&mut self, method: Method, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(cookie) = &self.cookie {
+      request = request.header(header::COOKIE, cookie);
+    }
+    let body = match body {
+      Some(body) => {
+        request = request.header(header::CONTENT_TYPE, "application/json");
+        Body::from(body.to_string())
+      }
+      None => Body::empty(),
+    };
+    let response = self
+      .app
+      .clone()
+      .oneshot(request.body(body).unwrap())
+      .await
+      .unwrap();
+    if let Some(set_cookie) = response.headers().get(header::SET_COOKIE) {
+      let pair = set_cookie.to_str().unwrap().split(';').next().unwrap();
+      self.cookie = Some(pair.to_string());
+    }
+    let status = response.status();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    (
+      status,
+      serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+  }
+
+  async fn login(&mut self) -> Value {
+    let (status, body) = self
+      .send(
+        Method::POST,
+        "/login",
+        Some(json!({ "username": "alice", "password": "hunter42" })),
+      )
+      .await;
+    assert_eq!(status, StatusCode::OK);
+    body
+  }
+}
+
+fn code(secret: &str, now: u64) -> String {
+  TOTP::new(
+    Algorithm::SHA1,
+    6,
+    1,
+    30,
+    Secret::Encoded(secret.to_string()).to_bytes().unwrap(),
+    None,
+    String::new(),
+  )
+  .unwrap()
+  .generate(now)
+}
+
+fn app() -> (Router, Arc<FixedClock>) {
+  let clock = Arc::new(FixedClock::new(START));
+  let state = Arc::new(AppState::new(TwoFactorConfig::default(), clock.clone()));
+  state.add_user("alice", "hunter42");
+  (router(state), clock)
+}
+
+/// Signs in, enrolls and returns the secret and recovery codes.
+async fn enroll(client: &mut Client, clock: &FixedClock) -> (String, Vec<String>) {
+  client.login().await;
+  let (status, enrollment) = client.send(Method::POST, "/two-factor/enroll", None).await;
+  assert_eq!(status, StatusCode::OK);
+  let secret = enrollment["secret"].as_str().unwrap().to_string();
+  let (status, confirmed) = client
+    .send(
+      Method::POST,
+      "/two-factor/confirm",
+      Some(json!({ "code": code(&secret, clock.now()) })),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let recovery_codes = confirmed["recovery_codes"]
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|code| code.as_str().unwrap().to_string())
+    .collect();
+  (secret, recovery_codes)
+}
+
+#[tokio::test]
+async fn users_must_enroll_before_reaching_protected_routes() {
+  let (app, clock) = app();
+  let mut client = Client { app, cookie: None };
+
+  let (status, _) = client.send(Method::GET, "/me", None).await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  let (status, _) = client
+    .send(
+      Method::POST,
+      "/login",
+      Some(json!({ "username": "alice", "password": "wrong" })),
+    )
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+  assert_eq!(client.login().await["two_factor_required"], false);
+  let (status, body) = client.send(Method::GET, "/me", None).await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+  assert_eq!(body["error"], "two_factor_enrollment_required");
+
+  let (status, enrollment) = client.send(Method::POST, "/two-factor/enroll", None).await;
+  assert_eq!(status, StatusCode::OK);
+  assert!(enrollment["uri"]
+    .as_str()
+    .unwrap()
+    .starts_with("otpauth://totp/"));
+  let secret = enrollment["secret"].as_str().unwrap();
+
+  let (status, body) = client
+    .send(
+      Method::POST,
+      "/two-factor/confirm",
+      Some(json!({ "code": "000000" })),
+    )
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  assert_eq!(body["error"], "invalid_code");
+  let (status, body) = client
+    .send(
+      Method::POST,
+      "/two-factor/confirm",
+      Some(json!({ "code": code(secret, clock.now()) })),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body["recovery_codes"].as_array().unwrap().len(), 10);
+
+  let (status, body) = client.send(Method::GET, "/me", None).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body["username"], "alice");
+}
+
+#[tokio::test]
+async fn login_needs_a_fresh_code_after_the_password() {
+  let (app, clock) = app();
+  let mut client = Client {
+    app: app.clone(),
+    cookie: None,
+  };
+  let (secret, _) = enroll(&mut client, &clock).await;
+  let enrolled_with = code(&secret, clock.now());
+
+  let mut client = Client { app, cookie: None };
+  assert_eq!(client.login().await["two_factor_required"], true);
+  let pending = client.cookie.clone();
+  let (status, body) = client.send(Method::GET, "/me", None).await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+  assert_eq!(body["error"], "two_factor_required");
+  let (status, _) = client.send(Method::POST, "/two-factor/enroll", None).await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+
+  // The code that confirmed the enrollment is spent, even within the same time step
+  let (status, body) = client
+    .send(
+      Method::POST,
+      "/login/two-factor",
+      Some(json!({ "code": enrolled_with })),
+    )
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  assert_eq!(body["error"], "code_already_used");
+
+  clock.advance(30);
+  let (status, _) = client
+    .send(
+      Method::POST,
+      "/login/two-factor",
+      Some(json!({ "code": code(&secret, clock.now()) })),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_ne!(client.cookie, pending);
+  let (status, _) = client.send(Method::GET, "/me", None).await;
+  assert_eq!(status, StatusCode::OK);
+
+  // The token from before the second factor was dropped
+  client.cookie = pending;
+  let (status, _) = client.send(Method::GET, "/me", None).await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn recovery_codes_work_once() {
+  let (app, clock) = app();
+  let mut client = Client {
+    app: app.clone(),
+    cookie: None,
+  };
+  let (_, recovery_codes) = enroll(&mut client, &clock).await;
+
+  for expected in [StatusCode::OK, StatusCode::UNAUTHORIZED] {
+    let mut client = Client {
+      app: app.clone(),
+      cookie: None,
+    };
+    client.login().await;
+    let (status, body) = client
+      .send(
+        Method::POST,
+        "/login/two-factor",
+        Some(json!({ "recovery_code": recovery_codes[0] })),
+      )
+      .await;
+    assert_eq!(status, expected);
+    if status == StatusCode::OK {
+      assert_eq!(body["recovery_codes_left"], 9);
+    }
+  }
+}
+
+#[tokio::test]
+async fn pending_logins_are_limited() {
+  let (app, clock) = app();
+  let mut client = Client {
+    app: app.clone(),
+    cookie: None,
+  };
+  let (secret, _) = enroll(&mut client, &clock).await;
+  clock.advance(30);
+
+  let mut client = Client {
+    app: app.clone(),
+    cookie: None,
+  };
+  client.login().await;
+  for _ in 0 .. 4 {
+    let (_, body) = client
+      .send(
+        Method::POST,
+        "/login/two-factor",
+        Some(json!({ "code": "000000" })),
+      )
+      .await;
+    assert_eq!(body["error"], "invalid_code");
+  }
+  let (_, body) = client
+    .send(
+      Method::POST,
+      "/login/two-factor",
+      Some(json!({ "code": "000000" })),
+    )
+    .await;
+  assert_eq!(body["error"], "too_many_attempts");
+  let (status, _) = client
+    .send(
+      Method::POST,
+      "/login/two-factor",
+      Some(json!({ "code": code(&secret, clock.now()) })),
+    )
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+  // A pending login also expires
+  let mut client = Client { app, cookie: None };
+  client.login().await;
+  clock.advance(5 * 60);
+  let (status, body) = client
+    .send(
+      Method::POST,
+      "/login/two-factor",
+      Some(json!({ "code": code(&secret, clock.now()) })),
+    )
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  assert_eq!(body["error"], "unauthorized");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_guesses_share_the_attempt_limit() {
+  let (app, clock) = app();
+  let mut client = Client {
+    app: app.clone(),
+    cookie: None,
+  };
+  enroll(&mut client, &clock).await;
+  clock.advance(30);
+
+  let mut client = Client { app, cookie: None };
+  client.login().await;
+  let mut guesses = tokio::task::JoinSet::new();
+  for _ in 0 .. 20 {
+    let mut client = client.clone();
+    guesses.spawn(async move {
+      let (_, body) = client
+        .send(
+          Method::POST,
+          "/login/two-factor",
+          Some(json!({ "code": "000000" })),
+        )
+        .await;
+      body["error"] == "invalid_code"
+    });
+  }
+  let mut checked = 0;
+  while let Some(invalid) = guesses.join_next().await {
+    checked += invalid.unwrap() as u32;
+  }
+  // The fifth wrong code ends the pending login instead
+  assert_eq!(checked, 4);
+}
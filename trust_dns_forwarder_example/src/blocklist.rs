@@ -0,0 +1,102 @@
+use std::{
+  collections::HashSet,
+  fs,
+  net::IpAddr,
+  path::{Path, PathBuf},
+};
+
+use trust_dns_proto::rr::Name;
+
+use crate::{error::Error, hosts::parse_name};
+
+/// Domains answered with NXDOMAIN, along with everything under them. A line is a domain, or an
+/// address and domains as in the hosts-style lists published for ad blocking.
+#[derive(Debug, Default)]
+pub struct Blocklist {
+  domains: HashSet<Name>,
+}
+
+impl Blocklist {
+  pub fn load(paths: &[PathBuf]) -> Result<Blocklist, Error> {
+    let mut blocklist = Blocklist::default();
+    for path in paths {
+      let text = fs::read_to_string(path).map_err(|source| Error::Read {
+        path: path.clone(),
+        source,
+      })?;
+      blocklist.extend(&text, path)?;
+    }
+    Ok(blocklist)
+  }
+
+  pub fn extend(&mut self, text: &str, path: &Path) -> Result<(), Error> {
+    for (index, line) in text.lines().enumerate() {
+      let mut fields = line
+        .split('#')
+        .next()
+        .unwrap()
+        .split_whitespace()
+        .peekable();
+      if fields
+        .peek()
+        .is_some_and(|field| field.parse::<IpAddr>().is_ok())
+      {
+        fields.next();
+      }
+      for domain in fields {
+        let domain = domain.strip_prefix("*.").unwrap_or(domain);
+        self.domains.insert(parse_name(domain, path, index + 1)?);
+      }
+    }
+    Ok(())
+  }
+
+  pub fn len(&self) -> usize {
+    self.domains.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.domains.is_empty()
+  }
+
+  pub fn blocks(&self, name: &Name) -> bool {
+    let mut name = name.to_lowercase();
+    name.set_fqdn(true);
+    loop {
+      if self.domains.contains(&name) {
+        return true;
+      }
+      if name.is_root() {
+        return false;
+      }
+      name = name.base_name();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::str::FromStr;
+
+  use super::*;
+
+  #[test]
+  fn domains_block_their_subdomains() {
+    let mut blocklist = Blocklist::default();
+    blocklist
+      .extend(
+        "doubleclick.net\n# comment\n0.0.0.0 ads.example.com tracker.example.org\n*.Metrics.io\n",
+        Path::new("blocklist"),
+      )
+      .unwrap();
+    assert_eq!(blocklist.len(), 4);
+    let blocks = |name: &str| blocklist.blocks(&Name::from_str(name).unwrap());
+    assert!(blocks("doubleclick.net."));
+    assert!(blocks("stats.g.DoubleClick.net."));
+    assert!(blocks("ads.example.com"));
+    assert!(blocks("eu.metrics.io."));
+    assert!(!blocks("example.com."));
+    assert!(!blocks("notdoubleclick.net."));
+    assert!(!blocks("net."));
+  }
+}
@@ -0,0 +1,262 @@
+use std::{
+  collections::HashMap,
+  time::{Duration, Instant},
+};
+
+use trust_dns_proto::{
+  op::{Message, Query, ResponseCode},
+  rr::{DNSClass, Name, RData, Record, RecordType},
+};
+
+use crate::config::Config;
+
+/// An answer taken from the cache, its TTLs counting down since it was stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cached {
+  pub response_code: ResponseCode,
+  pub answers: Vec<Record>,
+  pub name_servers: Vec<Record>,
+  pub additionals: Vec<Record>,
+}
+
+struct Entry {
+  cached: Cached,
+  stored: Instant,
+  ttl: u32,
+}
+
+impl Entry {
+  fn expires(&self) -> Instant {
+    self.stored + Duration::from_secs(self.ttl as u64)
+  }
+}
+
+/// Upstream answers by question, kept as long as the TTLs of their records allow (RFC 1035), or for
+/// NXDOMAIN and empty answers as long as their SOA allows (RFC 2308).
+pub struct Cache {
+  entries: HashMap<(Name, RecordType, DNSClass), Entry>,
+  capacity: usize,
+  min_ttl: u32,
+  max_ttl: u32,
+  max_negative_ttl: u32,
+}
+
+fn key(query: &Query) -> (Name, RecordType, DNSClass) {
+  (
+    query.name().to_lowercase(),
+    query.query_type(),
+    query.query_class(),
+  )
+}
+
+impl Cache {
+  pub fn new(config: &Config) -> Cache {
+    Cache {
+      entries: HashMap::new(),
+      capacity: config.cache_size,
+      min_ttl: config.min_ttl,
+      max_ttl: config.max_ttl,
+      max_negative_ttl: config.max_negative_ttl,
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  pub fn get(&mut self, query: &Query, now: Instant) -> Option<Cached> {
+    let key = key(query);
+    let entry = self.entries.get(&key)?;
+    if entry.expires() <= now {
+      self.entries.remove(&key);
+      return None;
+    }
+    let elapsed = (now - entry.stored).as_secs() as u32;
+    let age = |records: &[Record]| {
+      records
+        .iter()
+        .map(|record| {
+          let mut record = record.clone();
+          record.set_ttl(record.ttl().min(entry.ttl).saturating_sub(elapsed));
+          record
+        })
+        .collect()
+    };
+    Some(Cached {
+      response_code: entry.cached.response_code,
+      answers: age(&entry.cached.answers),
+      name_servers: age(&entry.cached.name_servers),
+      additionals: age(&entry.cached.additionals),
+    })
+  }
+
+  /// Stores an upstream response, unless it cannot be: a failure, a truncated answer, or a
+  /// negative one without an SOA to say for how long.
+  pub fn insert(&mut self, query: &Query, response: &Message, now: Instant) {
+    let Some(ttl) = self.ttl(response) else {
+      return;
+    };
+    if self.capacity == 0 || ttl == 0 {
+      return;
+    }
+    let key = key(query);
+    if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+      self.entries.retain(|_, entry| entry.expires() > now);
+      // Still full: make room by dropping the answer that would have gone first
+      if self.entries.len() >= self.capacity {
+        let first = self
+          .entries
+          .iter()
+          .min_by_key(|(_, entry)| entry.expires())
+          .map(|(key, _)| key.clone())
+          .unwrap();
+        self.entries.remove(&first);
+      }
+    }
+    self.entries.insert(
+      key,
+      Entry {
+        cached: Cached {
+          response_code: response.response_code(),
+          answers: response.answers().to_vec(),
+          name_servers: response.name_servers().to_vec(),
+          additionals: response.additionals().to_vec(),
+        },
+        stored: now,
+        ttl,
+      },
+    );
+  }
+
+  fn ttl(&self, response: &Message) -> Option<u32> {
+    if response.truncated() {
+      return None;
+    }
+    let positive =
+      response.response_code() == ResponseCode::NoError && !response.answers().is_empty();
+    if positive {
+      let ttl = response.answers().iter().map(Record::ttl).min()?;
+      return Some(ttl.clamp(self.min_ttl, self.max_ttl.max(self.min_ttl)));
+    }
+    if !matches!(
+      response.response_code(),
+      ResponseCode::NoError | ResponseCode::NXDomain
+    ) {
+      return None;
+    }
+    response
+      .name_servers()
+      .iter()
+      .find_map(|record| match record.rdata() {
+        RData::SOA(soa) => Some(record.ttl().min(soa.minimum())),
+        _ => None,
+      })
+      .map(|ttl| ttl.min(self.max_negative_ttl))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{net::Ipv4Addr, str::FromStr};
+
+  use trust_dns_proto::rr::rdata::SOA;
+
+  use super::*;
+
+  fn query(name: &str) -> Query {
+    Query::query(Name::from_str(name).unwrap(), RecordType::A)
+  }
+
+  fn answer(name: &str, ttl: u32) -> Message {
+    let mut message = Message::new();
+    message.add_answer(Record::from_rdata(
+      Name::from_str(name).unwrap(),
+      ttl,
+      RData::A(Ipv4Addr::LOCALHOST),
+    ));
+    message
+  }
+
+  fn nxdomain(soa_ttl: u32, minimum: u32) -> Message {
+    let zone = Name::from_str("example.com.").unwrap();
+    let mut message = Message::new();
+    message
+      .set_response_code(ResponseCode::NXDomain)
+      .add_name_server(Record::from_rdata(
+        zone.clone(),
+        soa_ttl,
+        RData::SOA(SOA::new(zone.clone(), zone, 1, 7200, 900, 1209600, minimum)),
+      ));
+    message
+  }
+
+  fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+  }
+
+  #[test]
+  fn ttls_count_down_until_the_answer_expires() {
+    let mut cache = Cache::new(&Config::default());
+    let now = Instant::now();
+    cache.insert(&query("Example.com."), &answer("example.com.", 300), now);
+
+    let cached = cache.get(&query("example.COM."), now + secs(100)).unwrap();
+    assert_eq!(cached.answers[0].ttl(), 200);
+    assert!(cache.get(&query("example.com."), now + secs(300)).is_none());
+    assert!(cache.is_empty());
+  }
+
+  #[test]
+  fn ttls_are_clamped() {
+    let config = Config {
+      min_ttl: 60,
+      max_ttl: 600,
+      max_negative_ttl: 30,
+      ..Config::default()
+    };
+    let mut cache = Cache::new(&config);
+    let now = Instant::now();
+    cache.insert(&query("short.com."), &answer("short.com.", 5), now);
+    cache.insert(&query("long.com."), &answer("long.com.", 86400), now);
+    assert!(cache.get(&query("short.com."), now + secs(59)).is_some());
+    assert!(cache.get(&query("long.com."), now + secs(600)).is_none());
+
+    // SOA TTL 900, minimum 300: capped at 30
+    cache.insert(&query("missing.example.com."), &nxdomain(900, 300), now);
+    let cached = cache
+      .get(&query("missing.example.com."), now + secs(29))
+      .unwrap();
+    assert_eq!(cached.response_code, ResponseCode::NXDomain);
+    assert!(cache
+      .get(&query("missing.example.com."), now + secs(30))
+      .is_none());
+  }
+
+  #[test]
+  fn failures_are_not_cached_and_the_cache_stays_bounded() {
+    let mut cache = Cache::new(&Config {
+      cache_size: 2,
+      ..Config::default()
+    });
+    let now = Instant::now();
+    let mut failure = Message::new();
+    failure.set_response_code(ResponseCode::ServFail);
+    cache.insert(&query("a.com."), &failure, now);
+    // NXDOMAIN without SOA
+    let mut nxdomain = Message::new();
+    nxdomain.set_response_code(ResponseCode::NXDomain);
+    cache.insert(&query("b.com."), &nxdomain, now);
+    assert!(cache.is_empty());
+
+    cache.insert(&query("a.com."), &answer("a.com.", 100), now);
+    cache.insert(&query("b.com."), &answer("b.com.", 50), now);
+    cache.insert(&query("c.com."), &answer("c.com.", 200), now);
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get(&query("b.com."), now).is_none());
+    assert!(cache.get(&query("a.com."), now).is_some());
+  }
+}
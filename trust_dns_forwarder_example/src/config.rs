@@ -0,0 +1,35 @@
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+  /// Tried in order, starting from the last one that answered.
+  pub upstreams: Vec<SocketAddr>,
+  /// How long to wait for an upstream before trying the next.
+  pub upstream_timeout: Duration,
+  /// A hosts-style file of names answered locally.
+  pub hosts: Option<PathBuf>,
+  /// Files of domains answered with NXDOMAIN, subdomains included.
+  pub blocklists: Vec<PathBuf>,
+  /// Questions whose answers are cached at once.
+  pub cache_size: usize,
+  /// Bounds on how long an answer is cached, in seconds, whatever its TTL.
+  pub min_ttl: u32,
+  pub max_ttl: u32,
+  /// Upper bound for NXDOMAIN and empty answers, cached as long as their SOA says otherwise.
+  pub max_negative_ttl: u32,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      upstreams: vec![],
+      upstream_timeout: Duration::from_secs(2),
+      hosts: None,
+      blocklists: vec![],
+      cache_size: 10_000,
+      min_ttl: 0,
+      max_ttl: 86400,
+      max_negative_ttl: 3600,
+    }
+  }
+}
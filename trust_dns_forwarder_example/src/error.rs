@@ -0,0 +1,31 @@
+use std::{io, net::SocketAddr, path::PathBuf};
+
+use thiserror::Error;
+use trust_dns_proto::error::ProtoError;
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("{path}: {source}")]
+  Read { path: PathBuf, source: io::Error },
+  #[error("{path}:{line}: {message}")]
+  Parse {
+    path: PathBuf,
+    line: usize,
+    message: String,
+  },
+  #[error("no upstream server configured")]
+  NoUpstreams,
+}
+
+/// Why an upstream gave no usable answer.
+#[derive(Debug, Error)]
+pub enum UpstreamError {
+  #[error("{0}: {1}")]
+  Io(SocketAddr, io::Error),
+  #[error("{0}: {1}")]
+  Proto(SocketAddr, ProtoError),
+  #[error("{0}: timed out")]
+  TimedOut(SocketAddr),
+  #[error("{0}: answered another question")]
+  Mismatch(SocketAddr),
+}
@@ -0,0 +1,154 @@
+use std::{sync::Mutex, time::Instant};
+
+use trust_dns_proto::op::{Message, MessageType, OpCode, ResponseCode};
+
+use crate::{
+  blocklist::Blocklist,
+  cache::Cache,
+  config::Config,
+  error::Error,
+  hosts::Hosts,
+  metrics::{Metrics, Source},
+  upstream::{Upstreams, EDNS_PAYLOAD},
+};
+
+/// Answers questions from, in turn: the block lists, the hosts file, the cache and the upstreams.
+pub struct Forwarder {
+  hosts: Hosts,
+  blocklist: Blocklist,
+  cache: Mutex<Cache>,
+  upstreams: Upstreams,
+  metrics: Metrics,
+}
+
+impl Forwarder {
+  pub fn new(config: &Config) -> Result<Forwarder, Error> {
+    if config.upstreams.is_empty() {
+      return Err(Error::NoUpstreams);
+    }
+    let hosts = match &config.hosts {
+      Some(path) => Hosts::load(path)?,
+      None => Hosts::default(),
+    };
+    Ok(Forwarder {
+      hosts,
+      blocklist: Blocklist::load(&config.blocklists)?,
+      cache: Mutex::new(Cache::new(config)),
+      upstreams: Upstreams::new(config.upstreams.clone(), config.upstream_timeout),
+      metrics: Metrics::new(&config.upstreams),
+    })
+  }
+
+  pub fn hosts(&self) -> &Hosts {
+    &self.hosts
+  }
+
+  pub fn blocklist(&self) -> &Blocklist {
+    &self.blocklist
+  }
+
+  pub fn metrics(&self) -> &Metrics {
+    &self.metrics
+  }
+
+  pub fn render_metrics(&self) -> String {
+    self.metrics.render(self.cache.lock().unwrap().len())
+  }
+
+  /// Answers a query received as a datagram, or a frame of a TCP connection. `None` when there is
+  /// not even an id to answer with.
+  pub async fn handle(&self, bytes: &[u8], tcp: bool) -> Option<Vec<u8>> {
+    self.metrics.query(tcp);
+    let (response, max_payload) = match Message::from_vec(bytes) {
+      Ok(request) => (self.answer(&request).await, request.max_payload()),
+      Err(_) => {
+        let id = u16::from_be_bytes(bytes.get(.. 2)?.try_into().unwrap());
+        self.metrics.answer(Source::Error, ResponseCode::FormErr);
+        (
+          Message::error_msg(id, OpCode::Query, ResponseCode::FormErr),
+          512,
+        )
+      }
+    };
+    let bytes = response.to_vec().ok()?;
+    if tcp || bytes.len() <= max_payload as usize {
+      return Some(bytes);
+    }
+    // The client is to ask again over TCP
+    self.metrics.truncated();
+    let mut truncated = response;
+    truncated.set_truncated(true);
+    truncated.take_answers();
+    truncated.take_name_servers();
+    truncated.take_additionals();
+    truncated.to_vec().ok()
+  }
+
+  pub async fn answer(&self, request: &Message) -> Message {
+    let mut response = Message::new();
+    response
+      .set_id(request.id())
+      .set_message_type(MessageType::Response)
+      .set_op_code(request.op_code())
+      .set_recursion_desired(request.recursion_desired())
+      .set_recursion_available(true)
+      .add_queries(request.queries().to_vec());
+    if request.edns().is_some() {
+      response.edns_mut().set_max_payload(EDNS_PAYLOAD);
+    }
+
+    let source = self.fill(request, &mut response).await;
+    self.metrics.answer(source, response.response_code());
+    response
+  }
+
+  async fn fill(&self, request: &Message, response: &mut Message) -> Source {
+    if request.message_type() != MessageType::Query || request.op_code() != OpCode::Query {
+      response.set_response_code(ResponseCode::NotImp);
+      return Source::Error;
+    }
+    let [query] = request.queries() else {
+      response.set_response_code(ResponseCode::FormErr);
+      return Source::Error;
+    };
+
+    if self.blocklist.blocks(query.name()) {
+      response.set_response_code(ResponseCode::NXDomain);
+      return Source::Blocklist;
+    }
+    if let Some(answers) = self.hosts.lookup(query.name(), query.query_type()) {
+      response.set_authoritative(true).insert_answers(answers);
+      return Source::Hosts;
+    }
+    let cached = self.cache.lock().unwrap().get(query, Instant::now());
+    if let Some(cached) = cached {
+      response.set_response_code(cached.response_code);
+      response.insert_answers(cached.answers);
+      response.insert_name_servers(cached.name_servers);
+      response.insert_additionals(cached.additionals);
+      return Source::Cache;
+    }
+
+    match self.upstreams.resolve(query, &self.metrics).await {
+      Ok(mut upstream) => {
+        self
+          .cache
+          .lock()
+          .unwrap()
+          .insert(query, &upstream, Instant::now());
+        response.set_response_code(upstream.response_code());
+        response.insert_answers(upstream.take_answers());
+        response.insert_name_servers(upstream.take_name_servers());
+        response.insert_additionals(upstream.take_additionals());
+        Source::Upstream
+      }
+      Err(errors) => {
+        for error in errors {
+          eprintln!("{} {}: {}", query.name(), query.query_type(), error);
+        }
+        response.set_response_code(ResponseCode::ServFail);
+        Source::Error
+      }
+    }
+  }
+}
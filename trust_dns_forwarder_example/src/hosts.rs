@@ -0,0 +1,141 @@
+use std::{
+  collections::HashMap,
+  fs,
+  net::IpAddr,
+  path::{Path, PathBuf},
+  str::FromStr,
+};
+
+use trust_dns_proto::rr::{Name, RData, Record, RecordType};
+
+use crate::error::Error;
+
+/// TTL of the answers from the hosts file.
+pub const HOSTS_TTL: u32 = 60;
+
+/// Names to answer locally, as in `/etc/hosts`: an address followed by names, `#` for comments.
+#[derive(Debug, Default)]
+pub struct Hosts {
+  addresses: HashMap<Name, Vec<IpAddr>>,
+}
+
+/// A domain from a hosts or block list line, fully qualified and lowercased.
+pub(crate) fn parse_name(name: &str, path: &Path, line: usize) -> Result<Name, Error> {
+  let mut parsed = Name::from_str(name).map_err(|e| Error::Parse {
+    path: path.to_path_buf(),
+    line,
+    message: format!("{}: {}", name, e),
+  })?;
+  parsed.set_fqdn(true);
+  Ok(parsed.to_lowercase())
+}
+
+impl Hosts {
+  pub fn load(path: &Path) -> Result<Hosts, Error> {
+    let text = fs::read_to_string(path).map_err(|source| Error::Read {
+      path: PathBuf::from(path),
+      source,
+    })?;
+    Hosts::parse(&text, path)
+  }
+
+  pub fn parse(text: &str, path: &Path) -> Result<Hosts, Error> {
+    let mut hosts = Hosts::default();
+    for (index, line) in text.lines().enumerate() {
+      let line_number = index + 1;
+      let mut fields = line.split('#').next().unwrap().split_whitespace();
+      let Some(address) = fields.next() else {
+        continue;
+      };
+      let address: IpAddr = address.parse().map_err(|_| Error::Parse {
+        path: path.to_path_buf(),
+        line: line_number,
+        message: format!("invalid address {}", address),
+      })?;
+      let mut names = fields.peekable();
+      if names.peek().is_none() {
+        return Err(Error::Parse {
+          path: path.to_path_buf(),
+          line: line_number,
+          message: format!("no name for {}", address),
+        });
+      }
+      for name in names {
+        let name = parse_name(name, path, line_number)?;
+        hosts.addresses.entry(name).or_default().push(address);
+      }
+    }
+    Ok(hosts)
+  }
+
+  pub fn len(&self) -> usize {
+    self.addresses.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.addresses.is_empty()
+  }
+
+  /// The records for a name the file lists, empty when it has no address of the type asked for:
+  /// the name is never forwarded.
+  pub fn lookup(&self, name: &Name, record_type: RecordType) -> Option<Vec<Record>> {
+    let addresses = self.addresses.get(&name.to_lowercase())?;
+    Some(
+      addresses
+        .iter()
+        .filter_map(|address| {
+          let rdata = match (address, record_type) {
+            (IpAddr::V4(v4), RecordType::A | RecordType::ANY) => RData::A(*v4),
+            (IpAddr::V6(v6), RecordType::AAAA | RecordType::ANY) => RData::AAAA(*v6),
+            _ => return None,
+          };
+          Some(Record::from_rdata(name.clone(), HOSTS_TTL, rdata))
+        })
+        .collect(),
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn name(name: &str) -> Name {
+    Name::from_str(name).unwrap()
+  }
+
+  #[test]
+  fn names_resolve_to_their_addresses_of_the_asked_type() {
+    let hosts = Hosts::parse(
+      "# comment\n\n192.168.1.20 nas.lan NAS  # trailing\nfd00::20 nas.lan\n",
+      Path::new("hosts"),
+    )
+    .unwrap();
+    assert_eq!(hosts.len(), 2);
+
+    let a = hosts.lookup(&name("Nas.Lan."), RecordType::A).unwrap();
+    assert_eq!(a.len(), 1);
+    assert_eq!(a[0].rdata(), &RData::A([192, 168, 1, 20].into()));
+    assert_eq!(a[0].ttl(), HOSTS_TTL);
+    let aaaa = hosts.lookup(&name("nas.lan."), RecordType::AAAA).unwrap();
+    assert_eq!(
+      aaaa[0].rdata().to_ip_addr(),
+      Some("fd00::20".parse().unwrap())
+    );
+    // Known, but nothing to answer with
+    assert_eq!(hosts.lookup(&name("nas."), RecordType::AAAA), Some(vec![]));
+    assert_eq!(hosts.lookup(&name("example.com."), RecordType::A), None);
+  }
+
+  #[test]
+  fn bad_lines_are_reported_with_their_number() {
+    let error = Hosts::parse("127.0.0.1 localhost\nnot-an-ip host\n", Path::new("hosts"))
+      .unwrap_err()
+      .to_string();
+    assert_eq!(error, "hosts:2: invalid address not-an-ip");
+    let error = Hosts::parse("10.0.0.1\n", Path::new("hosts"))
+      .unwrap_err()
+      .to_string();
+    assert_eq!(error, "hosts:1: no name for 10.0.0.1");
+  }
+}
@@ -0,0 +1,15 @@
+pub mod blocklist;
+pub mod cache;
+pub mod config;
+pub mod error;
+pub mod forwarder;
+pub mod hosts;
+pub mod metrics;
+pub mod server;
+pub mod upstream;
+
+pub use config::Config;
+pub use error::{Error, UpstreamError};
+pub use forwarder::Forwarder;
+pub use metrics::Source;
+pub use server::{serve_metrics, Server};
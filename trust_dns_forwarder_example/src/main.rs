@@ -0,0 +1,63 @@
+// cargo run -- --listen 127.0.0.1:5353 --upstream 1.1.1.1:53 --upstream 8.8.8.8:53 \
+//   --hosts hosts --blocklist blocklist --metrics 127.0.0.1:9153
+// dig @127.0.0.1 -p 5353 nas.lan
+// curl http://127.0.0.1:9153/metrics
+use std::{error::Error, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use clap::Parser;
+use tokio::net::TcpListener;
+use trust_dns_forwarder_example::{serve_metrics, Config, Forwarder, Server};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+  #[arg(long, default_value = "127.0.0.1:5353")]
+  listen: SocketAddr,
+  /// Where misses go, tried in order when one fails to answer
+  #[arg(long = "upstream", required = true)]
+  upstreams: Vec<SocketAddr>,
+  /// Milliseconds to wait for an upstream before trying the next
+  #[arg(long, default_value_t = 2000)]
+  timeout: u64,
+  /// Hosts-style file of names answered locally
+  #[arg(long)]
+  hosts: Option<PathBuf>,
+  /// File of domains answered with NXDOMAIN, may be repeated
+  #[arg(long = "blocklist")]
+  blocklists: Vec<PathBuf>,
+  #[arg(long, default_value_t = 10_000)]
+  cache_size: usize,
+  /// Serves Prometheus metrics on this address
+  #[arg(long)]
+  metrics: Option<SocketAddr>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+  let args = Args::parse();
+  let config = Config {
+    upstreams: args.upstreams,
+    upstream_timeout: Duration::from_millis(args.timeout),
+    hosts: args.hosts,
+    blocklists: args.blocklists,
+    cache_size: args.cache_size,
+    ..Config::default()
+  };
+  let forwarder = Arc::new(Forwarder::new(&config)?);
+  println!(
+    "{} hosts, {} blocked domains, upstreams {:?}",
+    forwarder.hosts().len(),
+    forwarder.blocklist().len(),
+    config.upstreams
+  );
+
+  if let Some(addr) = args.metrics {
+    let listener = TcpListener::bind(addr).await?;
+    println!("Metrics on http://{}/metrics", listener.local_addr()?);
+    tokio::spawn(serve_metrics(listener, forwarder.clone()));
+  }
+  let server = Server::bind(args.listen).await?;
+  println!("Listening on {} (UDP and TCP)", server.local_addr()?);
+  server.run(forwarder).await?;
+  Ok(())
+}
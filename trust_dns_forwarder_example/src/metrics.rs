@@ -0,0 +1,206 @@
+use std::{
+  collections::BTreeMap,
+  fmt::Write,
+  net::SocketAddr,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+  },
+  time::Duration,
+};
+
+use trust_dns_proto::op::ResponseCode;
+
+/// Where an answer came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Source {
+  Cache,
+  Hosts,
+  Blocklist,
+  Upstream,
+  /// Malformed or unsupported questions, and upstream failures.
+  Error,
+}
+
+impl Source {
+  fn label(self) -> &'static str {
+    match self {
+      Source::Cache => "cache",
+      Source::Hosts => "hosts",
+      Source::Blocklist => "blocklist",
+      Source::Upstream => "upstream",
+      Source::Error => "error",
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+struct UpstreamMetrics {
+  queries: AtomicU64,
+  failures: AtomicU64,
+  latency_micros: AtomicU64,
+}
+
+/// Counters of the queries served, rendered in the Prometheus text format.
+#[derive(Debug)]
+pub struct Metrics {
+  udp_queries: AtomicU64,
+  tcp_queries: AtomicU64,
+  truncated: AtomicU64,
+  answers: Mutex<BTreeMap<Source, u64>>,
+  response_codes: Mutex<BTreeMap<String, u64>>,
+  upstreams: Vec<(SocketAddr, UpstreamMetrics)>,
+}
+
+impl Metrics {
+  pub fn new(upstreams: &[SocketAddr]) -> Metrics {
+    Metrics {
+      udp_queries: AtomicU64::new(0),
+      tcp_queries: AtomicU64::new(0),
+      truncated: AtomicU64::new(0),
+      answers: Mutex::default(),
+      response_codes: Mutex::default(),
+      upstreams: upstreams
+        .iter()
+        .map(|&addr| (addr, UpstreamMetrics::default()))
+        .collect(),
+    }
+  }
+
+  pub fn query(&self, tcp: bool) {
+    let counter = if tcp {
+      &self.tcp_queries
+    } else {
+      &self.udp_queries
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub fn truncated(&self) {
+    self.truncated.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub fn answer(&self, source: Source, response_code: ResponseCode) {
+    *self.answers.lock().unwrap().entry(source).or_default() += 1;
+    *self
+      .response_codes
+      .lock()
+      .unwrap()
+      .entry(format!("{:?}", response_code))
+      .or_default() += 1;
+  }
+
+  /// An exchange with the upstream at `index`, whether it answered.
+  pub fn upstream(&self, index: usize, latency: Duration, ok: bool) {
+    let (_, metrics) = &self.upstreams[index];
+    metrics.queries.fetch_add(1, Ordering::Relaxed);
+    if !ok {
+      metrics.failures.fetch_add(1, Ordering::Relaxed);
+    }
+    metrics
+      .latency_micros
+      .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+  }
+
+  /// How many answers came from `source` so far.
+  pub fn answers(&self, source: Source) -> u64 {
+    self
+      .answers
+      .lock()
+      .unwrap()
+      .get(&source)
+      .copied()
+      .unwrap_or(0)
+  }
+
+  pub fn render(&self, cache_entries: usize) -> String {
+    let mut out = String::new();
+    let mut family = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+      writeln!(out, "# HELP {} {}", name, help).unwrap();
+      writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+      for (labels, value) in samples {
+        writeln!(out, "{}{} {}", name, labels, value).unwrap();
+      }
+    };
+    let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed).to_string();
+
+    family(
+      "dns_queries_total",
+      "counter",
+      "Queries received.",
+      vec![
+        (r#"{transport="udp"}"#.into(), load(&self.udp_queries)),
+        (r#"{transport="tcp"}"#.into(), load(&self.tcp_queries)),
+      ],
+    );
+    family(
+      "dns_answers_total",
+      "counter",
+      "Answers sent, by where they came from.",
+      self
+        .answers
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(source, count)| {
+          (
+            format!(r#"{{source="{}"}}"#, source.label()),
+            count.to_string(),
+          )
+        })
+        .collect(),
+    );
+    family(
+      "dns_responses_total",
+      "counter",
+      "Answers sent, by response code.",
+      self
+        .response_codes
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(code, count)| (format!(r#"{{rcode="{}"}}"#, code), count.to_string()))
+        .collect(),
+    );
+    family(
+      "dns_truncated_total",
+      "counter",
+      "UDP answers truncated to fit, for the client to retry over TCP.",
+      vec![(String::new(), load(&self.truncated))],
+    );
+    family(
+      "dns_cache_entries",
+      "gauge",
+      "Questions with a cached answer.",
+      vec![(String::new(), cache_entries.to_string())],
+    );
+    let per_upstream = |value: &dyn Fn(&UpstreamMetrics) -> String| {
+      self
+        .upstreams
+        .iter()
+        .map(|(addr, metrics)| (format!(r#"{{upstream="{}"}}"#, addr), value(metrics)))
+        .collect::<Vec<_>>()
+    };
+    family(
+      "dns_upstream_queries_total",
+      "counter",
+      "Questions forwarded to each upstream.",
+      per_upstream(&|metrics| load(&metrics.queries)),
+    );
+    family(
+      "dns_upstream_failures_total",
+      "counter",
+      "Questions an upstream failed to answer in time.",
+      per_upstream(&|metrics| load(&metrics.failures)),
+    );
+    family(
+      "dns_upstream_seconds_total",
+      "counter",
+      "Time spent waiting for each upstream.",
+      per_upstream(&|metrics| {
+        (metrics.latency_micros.load(Ordering::Relaxed) as f64 / 1e6).to_string()
+      }),
+    );
+    out
+  }
+}
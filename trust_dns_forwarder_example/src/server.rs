@@ -0,0 +1,110 @@
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::{TcpListener, TcpStream, UdpSocket},
+  time,
+};
+
+use crate::forwarder::Forwarder;
+
+/// TCP connections with no query for this long are closed.
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A UDP socket and a TCP listener on the same address, as DNS clients expect.
+pub struct Server {
+  udp: Arc<UdpSocket>,
+  tcp: TcpListener,
+}
+
+impl Server {
+  /// Binds both; with port 0 TCP gets the port UDP was given.
+  pub async fn bind(addr: SocketAddr) -> io::Result<Server> {
+    let udp = UdpSocket::bind(addr).await?;
+    let tcp = TcpListener::bind(udp.local_addr()?).await?;
+    Ok(Server {
+      udp: Arc::new(udp),
+      tcp,
+    })
+  }
+
+  pub fn local_addr(&self) -> io::Result<SocketAddr> {
+    self.udp.local_addr()
+  }
+
+  /// Serves queries, each in a task of its own, until a socket fails.
+  pub async fn run(self, forwarder: Arc<Forwarder>) -> io::Result<()> {
+    let udp = {
+      let forwarder = forwarder.clone();
+      async move {
+        let mut buf = vec![0; 4096];
+        loop {
+          let (len, from) = self.udp.recv_from(&mut buf).await?;
+          let (socket, forwarder) = (self.udp.clone(), forwarder.clone());
+          let query = buf[.. len].to_vec();
+          tokio::spawn(async move {
+            if let Some(response) = forwarder.handle(&query, false).await {
+              let _ = socket.send_to(&response, from).await;
+            }
+          });
+        }
+      }
+    };
+    let tcp = async move {
+      loop {
+        let (stream, _) = self.tcp.accept().await?;
+        tokio::spawn(serve_tcp(stream, forwarder.clone()));
+      }
+    };
+    tokio::select! {
+      result = udp => result,
+      result = tcp => result,
+    }
+  }
+}
+
+/// Queries framed with their length, one after the other.
+async fn serve_tcp(mut stream: TcpStream, forwarder: Arc<Forwarder>) -> io::Result<()> {
+  loop {
+    let len = match time::timeout(TCP_IDLE_TIMEOUT, stream.read_u16()).await {
+      Ok(Ok(len)) => len,
+      Ok(Err(e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+      Ok(Err(e)) => return Err(e),
+      Err(_) => return Ok(()),
+    };
+    let mut query = vec![0; len as usize];
+    stream.read_exact(&mut query).await?;
+    let Some(response) = forwarder.handle(&query, true).await else {
+      return Ok(());
+    };
+    stream
+      .write_all(&[&(response.len() as u16).to_be_bytes()[..], &response].concat())
+      .await?;
+  }
+}
+
+/// Answers `GET /metrics` with the counters in the Prometheus text format.
+pub async fn serve_metrics(listener: TcpListener, forwarder: Arc<Forwarder>) -> io::Result<()> {
+  loop {
+    let (mut stream, _) = listener.accept().await?;
+    let forwarder = forwarder.clone();
+    tokio::spawn(async move {
+      let mut request = vec![0; 1024];
+      let len = stream.read(&mut request).await?;
+      let request = String::from_utf8_lossy(&request[.. len]);
+      let response = if request.starts_with("GET /metrics ") {
+        let body = forwarder.render_metrics();
+        format!(
+          "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: \
+           {}\r\nConnection: close\r\n\r\n{}",
+          body.len(),
+          body
+        )
+      } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+      };
+      stream.write_all(response.as_bytes()).await?;
+      stream.shutdown().await
+    });
+  }
+}
@@ -0,0 +1,146 @@
+use std::{
+  net::SocketAddr,
+  sync::atomic::{AtomicUsize, Ordering},
+  time::{Duration, Instant},
+};
+
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::{TcpStream, UdpSocket},
+  time,
+};
+use trust_dns_proto::op::{Message, MessageType, OpCode, Query};
+
+use crate::{error::UpstreamError, metrics::Metrics};
+
+/// Payload size advertised to upstreams with EDNS, the size that avoids IP fragmentation.
+pub const EDNS_PAYLOAD: u16 = 1232;
+
+/// The servers misses are forwarded to. They are tried in order, starting from the last one that
+/// answered, so that a dead server costs a timeout once rather than on every query.
+pub struct Upstreams {
+  servers: Vec<SocketAddr>,
+  timeout: Duration,
+  preferred: AtomicUsize,
+}
+
+impl Upstreams {
+  pub fn new(servers: Vec<SocketAddr>, timeout: Duration) -> Upstreams {
+    Upstreams {
+      servers,
+      timeout,
+      preferred: AtomicUsize::new(0),
+    }
+  }
+
+  pub fn servers(&self) -> &[SocketAddr] {
+    &self.servers
+  }
+
+  /// The first answer an upstream gives, or why each of them failed.
+  pub async fn resolve(
+    &self,
+    query: &Query,
+    metrics: &Metrics,
+  ) -> Result<Message, Vec<UpstreamError>> {
+    let first = self.preferred.load(Ordering::Relaxed);
+    let mut errors = vec![];
+    for index in (0 .. self.servers.len()).map(|i| (first + i) % self.servers.len()) {
+      let server = self.servers[index];
+      let start = Instant::now();
+      let result = time::timeout(self.timeout, exchange(server, query))
+        .await
+        .unwrap_or(Err(UpstreamError::TimedOut(server)));
+      metrics.upstream(index, start.elapsed(), result.is_ok());
+      match result {
+        Ok(response) => {
+          self.preferred.store(index, Ordering::Relaxed);
+          return Ok(response);
+        }
+        Err(e) => errors.push(e),
+      }
+    }
+    Err(errors)
+  }
+}
+
+/// Asks over UDP, and again over TCP when the answer did not fit.
+async fn exchange(server: SocketAddr, query: &Query) -> Result<Message, UpstreamError> {
+  let mut request = Message::new();
+  request
+    .set_id(rand::random())
+    .set_message_type(MessageType::Query)
+    .set_op_code(OpCode::Query)
+    .set_recursion_desired(true)
+    .add_query(query.clone())
+    .edns_mut()
+    .set_max_payload(EDNS_PAYLOAD);
+  let bytes = request
+    .to_vec()
+    .map_err(|e| UpstreamError::Proto(server, e))?;
+
+  let response = udp(server, &request, &bytes).await?;
+  if !response.truncated() {
+    return Ok(response);
+  }
+  tcp(server, &request, &bytes).await
+}
+
+fn check(
+  server: SocketAddr,
+  request: &Message,
+  response: Message,
+) -> Result<Message, UpstreamError> {
+  if response.id() != request.id()
+    || response.message_type() != MessageType::Response
+    || response.queries() != request.queries()
+  {
+    return Err(UpstreamError::Mismatch(server));
+  }
+  Ok(response)
+}
+
+async fn udp(
+  server: SocketAddr,
+  request: &Message,
+  bytes: &[u8],
+) -> Result<Message, UpstreamError> {
+  let io_error = |e| UpstreamError::Io(server, e);
+  let local: SocketAddr = if server.is_ipv4() {
+    "0.0.0.0:0".parse().unwrap()
+  } else {
+    "[::]:0".parse().unwrap()
+  };
+  // A socket of its own per query: a random source port, and only the server can answer
+  let socket = UdpSocket::bind(local).await.map_err(io_error)?;
+  socket.connect(server).await.map_err(io_error)?;
+  socket.send(bytes).await.map_err(io_error)?;
+  let mut buf = vec![0; EDNS_PAYLOAD as usize * 2];
+  loop {
+    let len = socket.recv(&mut buf).await.map_err(io_error)?;
+    // Answers to something else, or not DNS at all: wait for the right one
+    if let Ok(response) = Message::from_vec(&buf[.. len]) {
+      if let Ok(response) = check(server, request, response) {
+        return Ok(response);
+      }
+    }
+  }
+}
+
+async fn tcp(
+  server: SocketAddr,
+  request: &Message,
+  bytes: &[u8],
+) -> Result<Message, UpstreamError> {
+  let io_error = |e| UpstreamError::Io(server, e);
+  let mut stream = TcpStream::connect(server).await.map_err(io_error)?;
+  stream
+    .write_all(&[&(bytes.len() as u16).to_be_bytes()[..], bytes].concat())
+    .await
+    .map_err(io_error)?;
+  let len = stream.read_u16().await.map_err(io_error)?;
+  let mut buf = vec![0; len as usize];
+  stream.read_exact(&mut buf).await.map_err(io_error)?;
+  let response = Message::from_vec(&buf).map_err(|e| UpstreamError::Proto(server, e))?;
+  check(server, request, response)
+}
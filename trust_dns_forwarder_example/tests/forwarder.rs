@@ -0,0 +1,413 @@
+use std::{
+  collections::HashMap,
+  io::Write,
+  net::{Ipv4Addr, SocketAddr},
+  str::FromStr,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+
+use tempfile::NamedTempFile;
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::{TcpListener, TcpStream, UdpSocket},
+};
+use trust_dns_forwarder_example::{serve_metrics, Config, Error, Forwarder, Server, Source};
+use trust_dns_proto::{
+  op::{Edns, Message, MessageType, OpCode, Query, ResponseCode},
+  rr::{
+    rdata::{SOA, TXT},
+    Name, RData, Record, RecordType,
+  },
+};
+
+fn name(name: &str) -> Name {
+  Name::from_str(name).unwrap()
+}
+
+/// An authoritative server for `test.` on UDP and TCP, counting the questions it gets.
+struct Authority {
+  addr: SocketAddr,
+  udp_queries: Arc<AtomicUsize>,
+  tcp_queries: Arc<AtomicUsize>,
+}
+
+impl Authority {
+  async fn start(records: Vec<Record>) -> Authority {
+    let mut zone: HashMap<(Name, RecordType), Vec<Record>> = HashMap::new();
+    for record in records {
+      zone
+        .entry((record.name().clone(), record.record_type()))
+        .or_default()
+        .push(record);
+    }
+    let zone = Arc::new(zone);
+    let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = udp.local_addr().unwrap();
+    let tcp = TcpListener::bind(addr).await.unwrap();
+    let udp_queries = Arc::new(AtomicUsize::new(0));
+    let tcp_queries = Arc::new(AtomicUsize::new(0));
+
+    let (zone_udp, count) = (zone.clone(), udp_queries.clone());
+    tokio::spawn(async move {
+      let mut buf = vec![0; 4096];
+      loop {
+        let (len, from) = udp.recv_from(&mut buf).await.unwrap();
+        count.fetch_add(1, Ordering::SeqCst);
+        let request = Message::from_vec(&buf[.. len]).unwrap();
+        let mut response = respond(&zone_udp, &request);
+        let mut bytes = response.to_vec().unwrap();
+        if bytes.len() > request.max_payload() as usize {
+          response.set_truncated(true).take_answers();
+          bytes = response.to_vec().unwrap();
+        }
+        udp.send_to(&bytes, from).await.unwrap();
+      }
+    });
+    let count = tcp_queries.clone();
+    tokio::spawn(async move {
+      loop {
+        let (mut stream, _) = tcp.accept().await.unwrap();
+        let len = stream.read_u16().await.unwrap();
+        let mut request = vec![0; len as usize];
+        stream.read_exact(&mut request).await.unwrap();
+        count.fetch_add(1, Ordering::SeqCst);
+        let response = respond(&zone, &Message::from_vec(&request).unwrap())
+          .to_vec()
+          .unwrap();
+        stream
+          .write_all(&[&(response.len() as u16).to_be_bytes()[..], &response].concat())
+          .await
+          .unwrap();
+      }
+    });
+
+    Authority {
+      addr,
+      udp_queries,
+      tcp_queries,
+    }
+  }
+
+  fn queries(&self) -> usize {
+    self.udp_queries.load(Ordering::SeqCst) + self.tcp_queries.load(Ordering::SeqCst)
+  }
+}
+
+fn respond(zone: &HashMap<(Name, RecordType), Vec<Record>>, request: &Message) -> Message {
+  let query = &request.queries()[0];
+  let mut response = Message::new();
+  response
+    .set_id(request.id())
+    .set_message_type(MessageType::Response)
+    .set_op_code(OpCode::Query)
+    .set_authoritative(true)
+    .add_queries(request.queries().to_vec());
+  match zone.get(&(query.name().clone(), query.query_type())) {
+    Some(records) => {
+      response.insert_answers(records.clone());
+    }
+    None => {
+      let origin = name("test.");
+      let soa = SOA::new(origin.clone(), origin.clone(), 1, 3600, 600, 86400, 60);
+      response
+        .set_response_code(ResponseCode::NXDomain)
+        .add_name_server(Record::from_rdata(origin, 60, RData::SOA(soa)));
+    }
+  }
+  response
+}
+
+fn a(owner: &str, ttl: u32, address: [u8; 4]) -> Record {
+  Record::from_rdata(name(owner), ttl, RData::A(Ipv4Addr::from(address)))
+}
+
+fn config(upstreams: Vec<SocketAddr>) -> Config {
+  Config {
+    upstreams,
+    upstream_timeout: Duration::from_millis(300),
+    ..Config::default()
+  }
+}
+
+async fn serve(config: &Config) -> (SocketAddr, Arc<Forwarder>) {
+  let forwarder = Arc::new(Forwarder::new(config).unwrap());
+  let server = Server::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+  let addr = server.local_addr().unwrap();
+  tokio::spawn(server.run(forwarder.clone()));
+  (addr, forwarder)
+}
+
+fn request(owner: &str, record_type: RecordType) -> Message {
+  let mut request = Message::new();
+  request
+    .set_id(rand::random())
+    .set_message_type(MessageType::Query)
+    .set_op_code(OpCode::Query)
+    .set_recursion_desired(true)
+    .add_query(Query::query(name(owner), record_type));
+  request
+}
+
+async fn ask_udp(server: SocketAddr, request: &Message) -> Message {
+  let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+  socket
+    .send_to(&request.to_vec().unwrap(), server)
+    .await
+    .unwrap();
+  let mut buf = vec![0; 4096];
+  let len = socket.recv(&mut buf).await.unwrap();
+  let response = Message::from_vec(&buf[.. len]).unwrap();
+  assert_eq!(response.id(), request.id());
+  assert_eq!(response.queries(), request.queries());
+  response
+}
+
+async fn ask(server: SocketAddr, owner: &str, record_type: RecordType) -> Message {
+  ask_udp(server, &request(owner, record_type)).await
+}
+
+async fn ask_tcp(server: SocketAddr, request: &Message) -> Message {
+  let mut stream = TcpStream::connect(server).await.unwrap();
+  let bytes = request.to_vec().unwrap();
+  stream
+    .write_all(&[&(bytes.len() as u16).to_be_bytes()[..], &bytes].concat())
+    .await
+    .unwrap();
+  let len = stream.read_u16().await.unwrap();
+  let mut buf = vec![0; len as usize];
+  stream.read_exact(&mut buf).await.unwrap();
+  Message::from_vec(&buf).unwrap()
+}
+
+#[tokio::test]
+async fn answers_are_forwarded_once_then_served_from_the_cache() {
+  let authority = Authority::start(vec![a("www.test.", 300, [10, 0, 0, 1])]).await;
+  let (server, forwarder) = serve(&config(vec![authority.addr])).await;
+
+  let first = ask(server, "www.test.", RecordType::A).await;
+  assert_eq!(first.response_code(), ResponseCode::NoError);
+  assert!(first.recursion_available());
+  assert_eq!(first.answers(), &[a("www.test.", 300, [10, 0, 0, 1])]);
+  let second = ask(server, "WWW.test.", RecordType::A).await;
+  assert_eq!(second.answers()[0].rdata(), first.answers()[0].rdata());
+  assert!(second.answers()[0].ttl() <= 300);
+  assert_eq!(authority.queries(), 1);
+
+  // NXDOMAIN is kept for as long as the SOA says
+  for _ in 0 .. 2 {
+    let missing = ask(server, "missing.test.", RecordType::A).await;
+    assert_eq!(missing.response_code(), ResponseCode::NXDomain);
+    assert!(matches!(missing.name_servers()[0].rdata(), RData::SOA(_)));
+  }
+  assert_eq!(authority.queries(), 2);
+  assert_eq!(forwarder.metrics().answers(Source::Upstream), 2);
+  assert_eq!(forwarder.metrics().answers(Source::Cache), 2);
+}
+
+#[tokio::test]
+async fn expired_answers_are_asked_for_again() {
+  let authority = Authority::start(vec![a("short.test.", 1, [10, 0, 0, 2])]).await;
+  let (server, _) = serve(&config(vec![authority.addr])).await;
+
+  ask(server, "short.test.", RecordType::A).await;
+  ask(server, "short.test.", RecordType::A).await;
+  assert_eq!(authority.queries(), 1);
+  tokio::time::sleep(Duration::from_millis(1100)).await;
+  let again = ask(server, "short.test.", RecordType::A).await;
+  assert_eq!(again.answers()[0].ttl(), 1);
+  assert_eq!(authority.queries(), 2);
+}
+
+#[tokio::test]
+async fn failing_upstreams_are_skipped_and_the_answering_one_is_kept() {
+  // Nothing listens on the first, the second never answers
+  let closed = UdpSocket::bind("127.0.0.1:0")
+    .await
+    .unwrap()
+    .local_addr()
+    .unwrap();
+  let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+  let authority = Authority::start(vec![
+    a("one.test.", 300, [10, 0, 0, 1]),
+    a("two.test.", 300, [10, 0, 0, 2]),
+  ])
+  .await;
+  let (server, forwarder) = serve(&config(vec![
+    closed,
+    silent.local_addr().unwrap(),
+    authority.addr,
+  ]))
+  .await;
+
+  let one = ask(server, "one.test.", RecordType::A).await;
+  assert_eq!(one.response_code(), ResponseCode::NoError);
+  let two = ask(server, "two.test.", RecordType::A).await;
+  assert_eq!(two.answers()[0].rdata(), &RData::A([10, 0, 0, 2].into()));
+  assert_eq!(authority.queries(), 2);
+
+  let metrics = forwarder.render_metrics();
+  let silent = silent.local_addr().unwrap();
+  for line in [
+    format!(r#"dns_upstream_queries_total{{upstream="{}"}} 1"#, closed),
+    format!(r#"dns_upstream_failures_total{{upstream="{}"}} 1"#, silent),
+    format!(
+      r#"dns_upstream_queries_total{{upstream="{}"}} 2"#,
+      authority.addr
+    ),
+    format!(
+      r#"dns_upstream_failures_total{{upstream="{}"}} 0"#,
+      authority.addr
+    ),
+  ] {
+    assert!(metrics.contains(&line), "{} not in\n{}", line, metrics);
+  }
+}
+
+#[tokio::test]
+async fn all_upstreams_failing_is_a_server_failure() {
+  let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+  let (server, forwarder) = serve(&config(vec![silent.local_addr().unwrap()])).await;
+
+  let response = ask(server, "www.test.", RecordType::A).await;
+  assert_eq!(response.response_code(), ResponseCode::ServFail);
+  assert_eq!(forwarder.metrics().answers(Source::Error), 1);
+  // Failures are not cached
+  ask(server, "www.test.", RecordType::A).await;
+  assert_eq!(forwarder.metrics().answers(Source::Cache), 0);
+}
+
+#[tokio::test]
+async fn hosts_and_block_lists_are_answered_locally() {
+  let authority = Authority::start(vec![]).await;
+  let mut hosts = NamedTempFile::new().unwrap();
+  writeln!(hosts, "192.168.1.20 nas.lan nas\nfd00::20 nas.lan").unwrap();
+  let mut blocklist = NamedTempFile::new().unwrap();
+  writeln!(blocklist, "# ads\ndoubleclick.net\n0.0.0.0 ads.example.com").unwrap();
+  let (server, forwarder) = serve(&Config {
+    hosts: Some(hosts.path().to_path_buf()),
+    blocklists: vec![blocklist.path().to_path_buf()],
+    ..config(vec![authority.addr])
+  })
+  .await;
+  assert_eq!(forwarder.hosts().len(), 2);
+  assert_eq!(forwarder.blocklist().len(), 2);
+
+  let nas = ask(server, "NAS.lan.", RecordType::A).await;
+  assert!(nas.authoritative());
+  assert_eq!(
+    nas.answers()[0].rdata(),
+    &RData::A([192, 168, 1, 20].into())
+  );
+  let nas = ask(server, "nas.lan.", RecordType::AAAA).await;
+  assert_eq!(
+    nas.answers()[0].rdata().to_ip_addr(),
+    Some("fd00::20".parse().unwrap())
+  );
+  // Listed, but with no IPv6 address: no data rather than a forwarded question
+  let nas = ask(server, "nas.", RecordType::AAAA).await;
+  assert_eq!(nas.response_code(), ResponseCode::NoError);
+  assert!(nas.answers().is_empty());
+
+  for blocked in [
+    "ad.doubleclick.net.",
+    "doubleclick.net.",
+    "ads.example.com.",
+  ] {
+    let response = ask(server, blocked, RecordType::A).await;
+    assert_eq!(
+      response.response_code(),
+      ResponseCode::NXDomain,
+      "{}",
+      blocked
+    );
+  }
+  assert_eq!(authority.queries(), 0);
+  assert_eq!(forwarder.metrics().answers(Source::Hosts), 3);
+  assert_eq!(forwarder.metrics().answers(Source::Blocklist), 3);
+}
+
+#[tokio::test]
+async fn large_answers_are_truncated_over_udp_and_whole_over_tcp() {
+  let records = (0 .. 10)
+    .map(|i| {
+      let text = format!("{}{}", i, "x".repeat(200));
+      Record::from_rdata(name("big.test."), 300, RData::TXT(TXT::new(vec![text])))
+    })
+    .collect();
+  let authority = Authority::start(records).await;
+  let (server, forwarder) = serve(&config(vec![authority.addr])).await;
+
+  let request = request("big.test.", RecordType::TXT);
+  let truncated = ask_udp(server, &request).await;
+  assert!(truncated.truncated());
+  assert!(truncated.answers().is_empty());
+  // Too big for the forwarder's EDNS payload as well: it asked again over TCP
+  assert_eq!(authority.udp_queries.load(Ordering::SeqCst), 1);
+  assert_eq!(authority.tcp_queries.load(Ordering::SeqCst), 1);
+
+  let whole = ask_tcp(server, &request).await;
+  assert!(!whole.truncated());
+  assert_eq!(whole.answers().len(), 10);
+  assert_eq!(authority.queries(), 2);
+
+  // Fits in what the client says it can take
+  let mut edns = Edns::new();
+  edns.set_max_payload(4096);
+  let mut large = self::request("big.test.", RecordType::TXT);
+  large.set_edns(edns);
+  let response = ask_udp(server, &large).await;
+  assert!(!response.truncated());
+  assert_eq!(response.answers().len(), 10);
+  assert!(forwarder
+    .render_metrics()
+    .contains("dns_truncated_total 1\n"));
+}
+
+#[tokio::test]
+async fn metrics_are_served_over_http() {
+  let authority = Authority::start(vec![a("www.test.", 300, [10, 0, 0, 1])]).await;
+  let (server, forwarder) = serve(&config(vec![authority.addr])).await;
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let metrics = listener.local_addr().unwrap();
+  tokio::spawn(serve_metrics(listener, forwarder));
+
+  ask(server, "www.test.", RecordType::A).await;
+  ask(server, "www.test.", RecordType::A).await;
+  ask_tcp(server, &request("www.test.", RecordType::A)).await;
+
+  let get = |path: &'static str| async move {
+    let mut stream = TcpStream::connect(metrics).await.unwrap();
+    stream
+      .write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes())
+      .await
+      .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+  };
+  let response = get("/metrics").await;
+  assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+  for line in [
+    r#"dns_queries_total{transport="udp"} 2"#,
+    r#"dns_queries_total{transport="tcp"} 1"#,
+    r#"dns_answers_total{source="cache"} 2"#,
+    r#"dns_answers_total{source="upstream"} 1"#,
+    r#"dns_responses_total{rcode="NoError"} 3"#,
+    "dns_cache_entries 1",
+  ] {
+    assert!(response.contains(line), "{} not in\n{}", line, response);
+  }
+  assert!(get("/").await.starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
+
+#[test]
+fn upstreams_are_required() {
+  assert!(matches!(
+    Forwarder::new(&Config::default()),
+    Err(Error::NoUpstreams)
+  ));
+}
@@ -0,0 +1,34 @@
+use std::{
+  net::{Ipv4Addr, SocketAddrV4},
+  time::Duration,
+};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+  /// Multicast group and port every node of the LAN listens on.
+  pub group: SocketAddrV4,
+  /// Interface to join the group on and send from, the kernel picks one when unspecified.
+  pub interface: Ipv4Addr,
+  /// Secret shared by the group: messages not signed with it are dropped.
+  pub key: Vec<u8>,
+  /// How often a node announces its services.
+  pub announce_interval: Duration,
+  /// How long peers keep a node after its last announcement. Several intervals, so that a lost
+  /// datagram or two do not make it flap.
+  pub ttl: Duration,
+  /// Hops announcements may cross, 1 keeps them on the local network.
+  pub multicast_ttl: u32,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      group: SocketAddrV4::new(Ipv4Addr::new(239, 0, 0, 1), 6000),
+      interface: Ipv4Addr::UNSPECIFIED,
+      key: vec![],
+      announce_interval: Duration::from_secs(5),
+      ttl: Duration::from_secs(15),
+      multicast_ttl: 1,
+    }
+  }
+}
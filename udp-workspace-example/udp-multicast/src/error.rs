@@ -0,0 +1,40 @@
+use std::{io, net::Ipv4Addr};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("I/O error: {0}")]
+  Io(#[from] io::Error),
+  #[error("{group} is not a multicast address")]
+  NotMulticast { group: Ipv4Addr },
+  #[error("{field} of {len} bytes is over the {max} byte limit")]
+  TooLong {
+    field: &'static str,
+    len: usize,
+    max: usize,
+  },
+  #[error("announcement of {size} bytes is over the {max} byte datagram limit")]
+  TooLarge { size: usize, max: usize },
+}
+
+/// Why a datagram was dropped.
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+  #[error("datagram ends early")]
+  Truncated,
+  #[error("not one of our datagrams")]
+  BadMagic,
+  #[error("unsupported protocol version {0}")]
+  UnsupportedVersion(u8),
+  #[error("unknown message kind {0}")]
+  UnknownKind(u8),
+  #[error("signature does not match the group key")]
+  BadSignature,
+  #[error("unknown address family {0}")]
+  UnknownFamily(u8),
+  #[error("text is not UTF-8")]
+  InvalidUtf8,
+  #[error("bytes left after the message")]
+  TrailingBytes,
+}
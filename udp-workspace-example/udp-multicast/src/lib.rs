@@ -0,0 +1,18 @@
+//! Service discovery on the local network over UDP multicast. Every node periodically announces
+//! its services to the group, answers queries for them, and keeps a live view of the other nodes,
+//! dropping those it stops hearing from.
+//!
+//! Messages are signed with a key the group shares and carry a sequence number, so that nodes
+//! ignore anything forged, duplicated or replayed.
+
+mod config;
+mod error;
+mod membership;
+pub mod message;
+mod node;
+
+pub use config::Config;
+pub use error::{DecodeError, Error};
+pub use membership::{Event, LeaveReason, Peer};
+pub use message::Service;
+pub use node::Node;
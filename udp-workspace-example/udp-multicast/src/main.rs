@@ -1,32 +1,107 @@
+// cargo run -p udp-multicast -- --key secret announce web 8080 --meta path=/api
+// cargo run -p udp-multicast -- --key secret browse web
 use std::{
-  env,
-  net::{Ipv4Addr, UdpSocket},
-  str,
+  error::Error,
+  net::{Ipv4Addr, SocketAddrV4},
+  time::Duration,
 };
 
-fn main() {
-  let mcast_group: Ipv4Addr = "239.0.0.1".parse().unwrap();
-  let port: u16 = 6000;
-  let any = "0.0.0.0".parse().unwrap();
-  let mut buffer = [0u8; 1600];
-  if env::args().count() > 1 {
-    // client case
-    let socket = UdpSocket::bind((any, port)).expect("Could not bind client socket");
-    socket
-      .join_multicast_v4(&mcast_group, &any)
-      .expect("Could not join multicast group");
-    socket
-      .recv_from(&mut buffer)
-      .expect("Failed to write to server");
-    print!(
-      "{}",
-      str::from_utf8(&buffer).expect("Could not write buffer as string")
-    );
-  } else {
-    // server case
-    let socket = UdpSocket::bind((any, 0)).expect("Could not bind socket");
-    socket
-      .send_to("Hello world!".as_bytes(), &(mcast_group, port))
-      .expect("Failed to write data");
+use clap::{Parser, Subcommand};
+use tokio::{signal, sync::broadcast::error::RecvError};
+use udp_multicast::{Config, Event, Node, Service};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+  #[arg(long, default_value = "239.0.0.1:6000")]
+  group: SocketAddrV4,
+  /// Address of the interface to use, any when unspecified
+  #[arg(long, default_value = "0.0.0.0")]
+  interface: Ipv4Addr,
+  /// Secret shared by the nodes of the group
+  #[arg(long)]
+  key: String,
+  /// Seconds between announcements, nodes expire after three
+  #[arg(long, default_value_t = 5)]
+  interval: u64,
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Offers a service to the group
+  Announce {
+    name: String,
+    port: u16,
+    /// Metadata as key=value, may be repeated
+    #[arg(long = "meta", value_parser = parse_metadata)]
+    metadata: Vec<(String, String)>,
+  },
+  /// Lists the members of the group, or those offering a service
+  Browse { name: Option<String> },
+}
+
+fn parse_metadata(s: &str) -> Result<(String, String), String> {
+  let (key, value) = s.split_once('=').ok_or("expected key=value")?;
+  Ok((key.to_string(), value.to_string()))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+  let args = Args::parse();
+  let interval = Duration::from_secs(args.interval);
+  let config = Config {
+    group: args.group,
+    interface: args.interface,
+    key: args.key.into_bytes(),
+    announce_interval: interval,
+    ttl: interval * 3,
+    ..Config::default()
+  };
+
+  let (services, wanted) = match args.command {
+    Command::Announce {
+      name,
+      port,
+      metadata,
+    } => {
+      let service = metadata
+        .into_iter()
+        .fold(Service::new(name, port), |service, (key, value)| {
+          service.with_metadata(key, value)
+        });
+      (vec![service], None)
+    }
+    Command::Browse { name } => (vec![], name),
+  };
+  let node = Node::join(config, services).await?;
+  let mut events = node.subscribe();
+  if wanted.is_some() {
+    node.query(wanted.as_deref()).await?;
+  }
+  println!("Node {:016x} joined {}", node.id(), args.group);
+
+  loop {
+    tokio::select! {
+      event = events.recv() => match event {
+        Ok(Event::Joined(peer)) | Ok(Event::Updated(peer)) => {
+          if peer.services.is_empty() && wanted.is_none() {
+            println!("{:016x} {} offers nothing", peer.node, peer.addr);
+          }
+          for service in &peer.services {
+            if wanted.as_ref().is_none_or(|name| *name == service.name) {
+              println!("{:016x} {} {} {:?}", peer.node, service.name, service.addr(), service.metadata);
+            }
+          }
+        }
+        Ok(Event::Left { node, reason }) => println!("{:016x} left: {:?}", node, reason),
+        Err(RecvError::Lagged(missed)) => eprintln!("{} events missed", missed),
+        Err(RecvError::Closed) => break,
+      },
+      _ = signal::ctrl_c() => break,
+    }
   }
+  node.leave().await?;
+  Ok(())
 }
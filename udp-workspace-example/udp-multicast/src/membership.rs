@@ -0,0 +1,207 @@
+use std::{
+  collections::HashMap,
+  net::SocketAddr,
+  time::{Duration, Instant},
+};
+
+use crate::message::Service;
+
+/// A node of the group, as last announced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+  pub node: u64,
+  /// Where its announcements come from.
+  pub addr: SocketAddr,
+  /// With the unspecified hosts replaced by the address of `addr`.
+  pub services: Vec<Service>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaveReason {
+  Goodbye,
+  /// Nothing heard from it within the TTL of its last announcement.
+  Expired,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+  Joined(Peer),
+  /// Its services or address changed.
+  Updated(Peer),
+  Left {
+    node: u64,
+    reason: LeaveReason,
+  },
+}
+
+struct Member {
+  peer: Peer,
+  expires: Instant,
+}
+
+/// The live view of the group, fed with the messages of the other nodes.
+#[derive(Default)]
+pub struct Membership {
+  members: HashMap<u64, Member>,
+  /// The last sequence number of every node heard from, kept after it left so that replayed
+  /// announcements cannot bring it back. Nodes take a new random id when they start, so only
+  /// the holders of the key add entries, one per run of a node.
+  last_seq: HashMap<u64, u64>,
+}
+
+impl Membership {
+  pub fn peers(&self) -> Vec<Peer> {
+    let mut peers: Vec<Peer> = self
+      .members
+      .values()
+      .map(|member| member.peer.clone())
+      .collect();
+    peers.sort_by_key(|peer| peer.node);
+    peers
+  }
+
+  /// Whether `seq` is newer than anything seen from `node`, remembering it if so.
+  pub fn fresh(&mut self, node: u64, seq: u64) -> bool {
+    match self.last_seq.get(&node) {
+      Some(&last) if seq <= last => false,
+      _ => {
+        self.last_seq.insert(node, seq);
+        true
+      }
+    }
+  }
+
+  pub fn announce(
+    &mut self,
+    node: u64,
+    addr: SocketAddr,
+    ttl: Duration,
+    mut services: Vec<Service>,
+    now: Instant,
+  ) -> Option<Event> {
+    for service in &mut services {
+      if service.host.is_unspecified() {
+        service.host = addr.ip();
+      }
+    }
+    let peer = Peer {
+      node,
+      addr,
+      services,
+    };
+    let expires = now + ttl;
+    match self.members.get_mut(&node) {
+      Some(member) => {
+        member.expires = expires;
+        if member.peer == peer {
+          return None;
+        }
+        member.peer = peer.clone();
+        Some(Event::Updated(peer))
+      }
+      None => {
+        self.members.insert(
+          node,
+          Member {
+            peer: peer.clone(),
+            expires,
+          },
+        );
+        Some(Event::Joined(peer))
+      }
+    }
+  }
+
+  pub fn goodbye(&mut self, node: u64) -> Option<Event> {
+    self.members.remove(&node).map(|_| Event::Left {
+      node,
+      reason: LeaveReason::Goodbye,
+    })
+  }
+
+  pub fn expire(&mut self, now: Instant) -> Vec<Event> {
+    let mut expired: Vec<u64> = self
+      .members
+      .iter()
+      .filter(|(_, member)| member.expires <= now)
+      .map(|(&node, _)| node)
+      .collect();
+    expired.sort_unstable();
+    for node in &expired {
+      self.members.remove(node);
+    }
+    expired
+      .into_iter()
+      .map(|node| Event::Left {
+        node,
+        reason: LeaveReason::Expired,
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TTL: Duration = Duration::from_secs(15);
+
+  fn addr() -> SocketAddr {
+    "192.168.1.20:6000".parse().unwrap()
+  }
+
+  #[test]
+  fn peers_join_change_and_expire() {
+    let mut membership = Membership::default();
+    let now = Instant::now();
+    let web = vec![Service::new("web", 8080)];
+
+    let Some(Event::Joined(peer)) = membership.announce(7, addr(), TTL, web.clone(), now) else {
+      panic!("not joined");
+    };
+    assert_eq!(
+      peer.services[0].addr(),
+      "192.168.1.20:8080".parse().unwrap()
+    );
+    // The same again only refreshes it
+    let later = now + Duration::from_secs(10);
+    assert_eq!(membership.announce(7, addr(), TTL, web, later), None);
+    assert!(membership.expire(now + TTL).is_empty());
+
+    let ssh = vec![Service::new("ssh", 22).with_host("10.0.0.1".parse().unwrap())];
+    let Some(Event::Updated(peer)) = membership.announce(7, addr(), TTL, ssh, later) else {
+      panic!("not updated");
+    };
+    assert_eq!(peer.services[0].addr(), "10.0.0.1:22".parse().unwrap());
+    assert_eq!(membership.peers(), vec![peer]);
+
+    assert_eq!(
+      membership.expire(later + TTL),
+      vec![Event::Left {
+        node: 7,
+        reason: LeaveReason::Expired
+      }]
+    );
+    assert!(membership.peers().is_empty());
+    assert_eq!(membership.goodbye(7), None);
+  }
+
+  #[test]
+  fn old_and_repeated_sequence_numbers_are_not_fresh() {
+    let mut membership = Membership::default();
+    let now = Instant::now();
+    assert!(membership.fresh(7, 1));
+    assert!(membership.fresh(7, 3));
+    assert!(!membership.fresh(7, 3));
+    assert!(!membership.fresh(7, 2));
+    assert!(membership.fresh(8, 1));
+
+    // Still known once the node is gone, however long ago
+    membership.announce(7, addr(), TTL, vec![], now);
+    membership.expire(now + TTL);
+    assert!(membership.peers().is_empty());
+    membership.expire(now + 1000 * TTL);
+    assert!(!membership.fresh(7, 3));
+    assert!(membership.fresh(7, 4));
+  }
+}
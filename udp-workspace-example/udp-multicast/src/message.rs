@@ -0,0 +1,362 @@
+use std::{
+  collections::BTreeMap,
+  net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+  time::Duration,
+};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::error::{DecodeError, Error};
+
+const MAGIC: &[u8; 4] = b"UMSD";
+const VERSION: u8 = 1;
+const TAG_LEN: usize = 32;
+
+/// Largest datagram sent, to stay clear of IP fragmentation on Ethernet.
+pub const MAX_DATAGRAM: usize = 1400;
+
+/// A service a node offers: what it is, where to reach it, and free-form metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Service {
+  pub name: String,
+  /// Unspecified in what a node publishes stands for whatever address its announcements come
+  /// from; peers fill in that address.
+  pub host: IpAddr,
+  pub port: u16,
+  pub metadata: BTreeMap<String, String>,
+}
+
+impl Service {
+  pub fn new(name: impl Into<String>, port: u16) -> Service {
+    Service {
+      name: name.into(),
+      host: Ipv4Addr::UNSPECIFIED.into(),
+      port,
+      metadata: BTreeMap::new(),
+    }
+  }
+
+  pub fn with_host(mut self, host: IpAddr) -> Service {
+    self.host = host;
+    self
+  }
+
+  pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Service {
+    self.metadata.insert(key.into(), value.into());
+    self
+  }
+
+  pub fn addr(&self) -> SocketAddr {
+    SocketAddr::new(self.host, self.port)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Body {
+  /// The services of the sender, to be kept for `ttl` unless announced again.
+  Announce {
+    ttl: Duration,
+    services: Vec<Service>,
+  },
+  /// Asks nodes offering `name`, or any service when `None`, to announce themselves now.
+  Query { name: Option<String> },
+  /// The sender is leaving the group.
+  Goodbye,
+}
+
+/// What goes in a datagram. `seq` grows with every message a node sends, so that a replayed or
+/// duplicated datagram is told apart from a new one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+  pub node: u64,
+  pub seq: u64,
+  pub body: Body,
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn mac(key: &[u8]) -> HmacSha256 {
+  HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length")
+}
+
+impl Message {
+  /// The datagram for this message, signed with HMAC-SHA256 under `key`.
+  pub fn encode(&self, key: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::with_capacity(256);
+    buf.extend_from_slice(MAGIC);
+    buf.push(VERSION);
+    match &self.body {
+      Body::Announce { .. } => buf.push(1),
+      Body::Query { .. } => buf.push(2),
+      Body::Goodbye => buf.push(3),
+    }
+    buf.extend_from_slice(&self.node.to_be_bytes());
+    buf.extend_from_slice(&self.seq.to_be_bytes());
+    match &self.body {
+      Body::Announce { ttl, services } => {
+        buf.extend_from_slice(&(ttl.as_millis().min(u32::MAX as u128) as u32).to_be_bytes());
+        put_len(&mut buf, "services", services.len())?;
+        for service in services {
+          put_str(&mut buf, "service name", &service.name)?;
+          match service.host {
+            IpAddr::V4(v4) => {
+              buf.push(4);
+              buf.extend_from_slice(&v4.octets());
+            }
+            IpAddr::V6(v6) => {
+              buf.push(6);
+              buf.extend_from_slice(&v6.octets());
+            }
+          }
+          buf.extend_from_slice(&service.port.to_be_bytes());
+          put_len(&mut buf, "metadata", service.metadata.len())?;
+          for (key, value) in &service.metadata {
+            put_str(&mut buf, "metadata key", key)?;
+            put_str(&mut buf, "metadata value", value)?;
+          }
+        }
+      }
+      Body::Query { name } => put_str(&mut buf, "service name", name.as_deref().unwrap_or(""))?,
+      Body::Goodbye => {}
+    }
+
+    let mut mac = mac(key);
+    mac.update(&buf);
+    buf.extend_from_slice(&mac.finalize().into_bytes());
+    if buf.len() > MAX_DATAGRAM {
+      return Err(Error::TooLarge {
+        size: buf.len(),
+        max: MAX_DATAGRAM,
+      });
+    }
+    Ok(buf)
+  }
+
+  /// Checks the signature before looking at anything else.
+  pub fn decode(datagram: &[u8], key: &[u8]) -> Result<Message, DecodeError> {
+    if datagram.len() < MAGIC.len() + 2 + TAG_LEN {
+      return Err(DecodeError::Truncated);
+    }
+    if &datagram[.. MAGIC.len()] != MAGIC {
+      return Err(DecodeError::BadMagic);
+    }
+    let (signed, tag) = datagram.split_at(datagram.len() - TAG_LEN);
+    let mut mac = mac(key);
+    mac.update(signed);
+    mac
+      .verify_slice(tag)
+      .map_err(|_| DecodeError::BadSignature)?;
+
+    let mut reader = Reader(&signed[MAGIC.len() ..]);
+    let version = reader.u8()?;
+    if version != VERSION {
+      return Err(DecodeError::UnsupportedVersion(version));
+    }
+    let kind = reader.u8()?;
+    let node = reader.u64()?;
+    let seq = reader.u64()?;
+    let body = match kind {
+      1 => {
+        let ttl = Duration::from_millis(reader.u32()? as u64);
+        let services = (0 .. reader.u8()?)
+          .map(|_| reader.service())
+          .collect::<Result<_, _>>()?;
+        Body::Announce { ttl, services }
+      }
+      2 => {
+        let name = reader.str()?;
+        Body::Query {
+          name: (!name.is_empty()).then_some(name),
+        }
+      }
+      3 => Body::Goodbye,
+      kind => return Err(DecodeError::UnknownKind(kind)),
+    };
+    if !reader.0.is_empty() {
+      return Err(DecodeError::TrailingBytes);
+    }
+    Ok(Message { node, seq, body })
+  }
+}
+
+fn put_len(buf: &mut Vec<u8>, field: &'static str, len: usize) -> Result<(), Error> {
+  let len = u8::try_from(len).map_err(|_| Error::TooLong {
+    field,
+    len,
+    max: u8::MAX as usize,
+  })?;
+  buf.push(len);
+  Ok(())
+}
+
+fn put_str(buf: &mut Vec<u8>, field: &'static str, s: &str) -> Result<(), Error> {
+  put_len(buf, field, s.len())?;
+  buf.extend_from_slice(s.as_bytes());
+  Ok(())
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+    if self.0.len() < n {
+      return Err(DecodeError::Truncated);
+    }
+    let (head, rest) = self.0.split_at(n);
+    self.0 = rest;
+    Ok(head)
+  }
+
+  fn u8(&mut self) -> Result<u8, DecodeError> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u16(&mut self) -> Result<u16, DecodeError> {
+    Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+  }
+
+  fn u32(&mut self) -> Result<u32, DecodeError> {
+    Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+  }
+
+  fn u64(&mut self) -> Result<u64, DecodeError> {
+    Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+  }
+
+  fn str(&mut self) -> Result<String, DecodeError> {
+    let len = self.u8()? as usize;
+    String::from_utf8(self.take(len)?.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+  }
+
+  fn service(&mut self) -> Result<Service, DecodeError> {
+    let name = self.str()?;
+    let host = match self.u8()? {
+      4 => IpAddr::from(<[u8; 4]>::try_from(self.take(4)?).unwrap()),
+      6 => Ipv6Addr::from(<[u8; 16]>::try_from(self.take(16)?).unwrap()).into(),
+      family => return Err(DecodeError::UnknownFamily(family)),
+    };
+    let port = self.u16()?;
+    let metadata = (0 .. self.u8()?)
+      .map(|_| Ok((self.str()?, self.str()?)))
+      .collect::<Result<_, _>>()?;
+    Ok(Service {
+      name,
+      host,
+      port,
+      metadata,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const KEY: &[u8] = b"group secret";
+
+  #[test]
+  fn messages_round_trip() {
+    let messages = [
+      Body::Announce {
+        ttl: Duration::from_secs(15),
+        services: vec![
+          Service::new("web", 8080).with_metadata("path", "/api"),
+          Service::new("ssh", 22).with_host("fd00::1".parse().unwrap()),
+        ],
+      },
+      Body::Announce {
+        ttl: Duration::from_millis(1500),
+        services: vec![],
+      },
+      Body::Query {
+        name: Some("web".into()),
+      },
+      Body::Query { name: None },
+      Body::Goodbye,
+    ];
+    for body in messages {
+      let message = Message {
+        node: 0x1234_5678_9abc_def0,
+        seq: 42,
+        body,
+      };
+      let datagram = message.encode(KEY).unwrap();
+      assert_eq!(Message::decode(&datagram, KEY), Ok(message));
+    }
+  }
+
+  #[test]
+  fn tampered_or_foreign_datagrams_are_rejected() {
+    let message = Message {
+      node: 1,
+      seq: 1,
+      body: Body::Announce {
+        ttl: Duration::from_secs(15),
+        services: vec![Service::new("web", 8080)],
+      },
+    };
+    let datagram = message.encode(KEY).unwrap();
+    assert_eq!(
+      Message::decode(&datagram, b"other key"),
+      Err(DecodeError::BadSignature)
+    );
+    let mut tampered = datagram.clone();
+    // The port of the service
+    let port = tampered.len() - TAG_LEN - 3;
+    tampered[port] ^= 1;
+    assert_eq!(
+      Message::decode(&tampered, KEY),
+      Err(DecodeError::BadSignature)
+    );
+    assert_eq!(
+      Message::decode(&datagram[.. 10], KEY),
+      Err(DecodeError::Truncated)
+    );
+    assert_eq!(
+      Message::decode(b"Hello world! and some more bytes to be long enough", KEY),
+      Err(DecodeError::BadMagic)
+    );
+  }
+
+  #[test]
+  fn oversized_announcements_are_refused() {
+    let long = Service::new("x".repeat(256), 1);
+    let body = Body::Announce {
+      ttl: Duration::from_secs(1),
+      services: vec![long],
+    };
+    let message = Message {
+      node: 1,
+      seq: 1,
+      body,
+    };
+    assert!(matches!(
+      message.encode(KEY),
+      Err(Error::TooLong {
+        field: "service name",
+        len: 256,
+        ..
+      })
+    ));
+
+    let services = (0 .. 20)
+      .map(|i| Service::new(format!("service-{}", i), i).with_metadata("note", "y".repeat(100)))
+      .collect();
+    let message = Message {
+      node: 1,
+      seq: 1,
+      body: Body::Announce {
+        ttl: Duration::from_secs(1),
+        services,
+      },
+    };
+    assert!(matches!(
+      message.encode(KEY),
+      Err(Error::TooLarge {
+        max: MAX_DATAGRAM,
+        ..
+      })
+    ));
+  }
+}
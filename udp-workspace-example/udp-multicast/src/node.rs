@@ -0,0 +1,249 @@
+use std::{
+  net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{
+  net::UdpSocket,
+  sync::broadcast,
+  task::JoinHandle,
+  time::{self, MissedTickBehavior},
+};
+
+use crate::{
+  config::Config,
+  error::Error,
+  membership::{Event, Membership, Peer},
+  message::{Body, Message, Service},
+};
+
+/// A member of the group: announces its services every interval and on request, and keeps track
+/// of the other members from theirs.
+pub struct Node {
+  shared: Arc<Shared>,
+  events: broadcast::Sender<Event>,
+  task: JoinHandle<()>,
+}
+
+struct Shared {
+  id: u64,
+  config: Config,
+  socket: UdpSocket,
+  seq: AtomicU64,
+  services: Mutex<Vec<Service>>,
+  membership: Mutex<Membership>,
+}
+
+impl Node {
+  /// Joins the group, announces `services` and asks the other members to announce theirs.
+  pub async fn join(config: Config, services: Vec<Service>) -> Result<Node, Error> {
+    let shared = Arc::new(Shared {
+      id: rand::random(),
+      socket: bind(&config)?,
+      config,
+      seq: AtomicU64::new(0),
+      services: Mutex::new(services),
+      membership: Mutex::new(Membership::default()),
+    });
+    shared.announce().await?;
+    shared.send(Body::Query { name: None }).await?;
+    let (events, _) = broadcast::channel(64);
+    let task = tokio::spawn(run(shared.clone(), events.clone()));
+    Ok(Node {
+      shared,
+      events,
+      task,
+    })
+  }
+
+  /// Random, picked anew every time a node joins.
+  pub fn id(&self) -> u64 {
+    self.shared.id
+  }
+
+  pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+    Ok(self.shared.socket.local_addr()?)
+  }
+
+  /// The other members, in order of their ids.
+  pub fn peers(&self) -> Vec<Peer> {
+    self.shared.membership.lock().unwrap().peers()
+  }
+
+  /// Where the members offering `name` can be reached.
+  pub fn lookup(&self, name: &str) -> Vec<Service> {
+    self
+      .peers()
+      .into_iter()
+      .flat_map(|peer| peer.services)
+      .filter(|service| service.name == name)
+      .collect()
+  }
+
+  /// Members joining, changing and leaving from now on.
+  pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+    self.events.subscribe()
+  }
+
+  /// Replaces the services of this node and announces them right away.
+  pub async fn publish(&self, services: Vec<Service>) -> Result<(), Error> {
+    let datagram = self.shared.encode(Body::Announce {
+      ttl: self.shared.config.ttl,
+      services: services.clone(),
+    })?;
+    *self.shared.services.lock().unwrap() = services;
+    self.shared.send_datagram(&datagram).await
+  }
+
+  /// Asks the members offering `name`, or all of them, to announce themselves now rather than at
+  /// their next interval.
+  pub async fn query(&self, name: Option<&str>) -> Result<(), Error> {
+    self
+      .shared
+      .send(Body::Query {
+        name: name.map(String::from),
+      })
+      .await
+  }
+
+  /// Tells the other members this node is gone, rather than letting them wait for it to expire.
+  pub async fn leave(self) -> Result<(), Error> {
+    self.task.abort();
+    self.shared.send(Body::Goodbye).await
+  }
+}
+
+impl Drop for Node {
+  fn drop(&mut self) {
+    self.task.abort();
+  }
+}
+
+fn bind(config: &Config) -> Result<UdpSocket, Error> {
+  let group = *config.group.ip();
+  if !group.is_multicast() {
+    return Err(Error::NotMulticast { group });
+  }
+  let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+  // Every node on the host listens on the group port
+  socket.set_reuse_address(true)?;
+  #[cfg(unix)]
+  socket.set_reuse_port(true)?;
+  socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, config.group.port()).into())?;
+  socket.join_multicast_v4(&group, &config.interface)?;
+  socket.set_multicast_if_v4(&config.interface)?;
+  socket.set_multicast_loop_v4(true)?;
+  socket.set_multicast_ttl_v4(config.multicast_ttl)?;
+  socket.set_nonblocking(true)?;
+  Ok(UdpSocket::from_std(socket.into())?)
+}
+
+impl Shared {
+  fn encode(&self, body: Body) -> Result<Vec<u8>, Error> {
+    Message {
+      node: self.id,
+      seq: self.seq.fetch_add(1, Ordering::Relaxed) + 1,
+      body,
+    }
+    .encode(&self.config.key)
+  }
+
+  async fn send_datagram(&self, datagram: &[u8]) -> Result<(), Error> {
+    self.socket.send_to(datagram, self.config.group).await?;
+    Ok(())
+  }
+
+  async fn send(&self, body: Body) -> Result<(), Error> {
+    self.send_datagram(&self.encode(body)?).await
+  }
+
+  async fn announce(&self) -> Result<(), Error> {
+    let services = self.services.lock().unwrap().clone();
+    self
+      .send(Body::Announce {
+        ttl: self.config.ttl,
+        services,
+      })
+      .await
+  }
+
+  fn offers(&self, name: Option<&str>) -> bool {
+    let services = self.services.lock().unwrap();
+    match name {
+      Some(name) => services.iter().any(|service| service.name == name),
+      None => !services.is_empty(),
+    }
+  }
+
+  async fn receive(&self, datagram: &[u8], from: SocketAddr, events: &broadcast::Sender<Event>) {
+    // Datagrams of other applications on the port, or not signed with the key: not for us
+    let Ok(message) = Message::decode(datagram, &self.config.key) else {
+      return;
+    };
+    if message.node == self.id {
+      return;
+    }
+    let now = Instant::now();
+    let fresh = self
+      .membership
+      .lock()
+      .unwrap()
+      .fresh(message.node, message.seq);
+    // Duplicated or replayed
+    if !fresh {
+      return;
+    }
+    let event = match message.body {
+      Body::Announce { ttl, services } => {
+        let mut membership = self.membership.lock().unwrap();
+        membership.announce(message.node, from, ttl, services, now)
+      }
+      Body::Goodbye => self.membership.lock().unwrap().goodbye(message.node),
+      Body::Query { name } => {
+        if self.offers(name.as_deref()) {
+          if let Err(e) = self.announce().await {
+            eprintln!("announcement failed: {}", e);
+          }
+        }
+        None
+      }
+    };
+    if let Some(event) = event {
+      let _ = events.send(event);
+    }
+  }
+}
+
+async fn run(shared: Arc<Shared>, events: broadcast::Sender<Event>) {
+  let mut announce = time::interval(shared.config.announce_interval);
+  announce.set_missed_tick_behavior(MissedTickBehavior::Delay);
+  // The first tick is now, and joining already announced
+  announce.tick().await;
+  // Several checks per TTL, so that peers are dropped soon after they expire
+  let mut expire = time::interval((shared.config.ttl / 4).max(Duration::from_millis(10)));
+  let mut buf = vec![0; 2048];
+  loop {
+    tokio::select! {
+      _ = announce.tick() => {
+        if let Err(e) = shared.announce().await {
+          eprintln!("announcement failed: {}", e);
+        }
+      }
+      _ = expire.tick() => {
+        let expired = shared.membership.lock().unwrap().expire(Instant::now());
+        for event in expired {
+          let _ = events.send(event);
+        }
+      }
+      received = shared.socket.recv_from(&mut buf) => match received {
+        Ok((len, from)) => shared.receive(&buf[.. len], from, &events).await,
+        Err(e) => eprintln!("receive failed: {}", e),
+      },
+    }
+  }
+}
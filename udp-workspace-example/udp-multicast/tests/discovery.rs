@@ -0,0 +1,213 @@
+use std::{
+  net::{Ipv4Addr, SocketAddrV4},
+  time::Duration,
+};
+
+use tokio::{
+  net::UdpSocket,
+  sync::broadcast,
+  time::{sleep, timeout},
+};
+use udp_multicast::{
+  message::{Body, Message},
+  Config, Event, LeaveReason, Node, Service,
+};
+
+const KEY: &[u8] = b"test group key";
+
+/// A group of its own for each test, as they run side by side, on the loopback interface.
+fn config(port: u16) -> Config {
+  Config {
+    group: SocketAddrV4::new(Ipv4Addr::new(239, 255, 70, 1), port),
+    interface: Ipv4Addr::LOCALHOST,
+    key: KEY.to_vec(),
+    announce_interval: Duration::from_millis(100),
+    ttl: Duration::from_millis(400),
+    ..Config::default()
+  }
+}
+
+async fn eventually(what: &str, condition: impl Fn() -> bool) {
+  for _ in 0 .. 150 {
+    if condition() {
+      return;
+    }
+    sleep(Duration::from_millis(20)).await;
+  }
+  panic!("timed out waiting for {}", what);
+}
+
+/// The next event matching `wanted`, skipping others.
+async fn next(events: &mut broadcast::Receiver<Event>, wanted: impl Fn(&Event) -> bool) -> Event {
+  timeout(Duration::from_secs(3), async {
+    loop {
+      let event = events.recv().await.unwrap();
+      if wanted(&event) {
+        return event;
+      }
+    }
+  })
+  .await
+  .expect("no such event")
+}
+
+#[tokio::test]
+async fn nodes_see_each_other_and_their_services() {
+  let config = config(47101);
+  let web = Node::join(
+    config.clone(),
+    vec![Service::new("web", 8080).with_metadata("version", "1.2")],
+  )
+  .await
+  .unwrap();
+  let db = Node::join(
+    config.clone(),
+    vec![Service::new("db", 5432).with_host("10.0.0.5".parse().unwrap())],
+  )
+  .await
+  .unwrap();
+  let browser = Node::join(config, vec![]).await.unwrap();
+
+  for node in [&web, &db, &browser] {
+    eventually("the other two", || node.peers().len() == 2).await;
+  }
+  let found = browser.lookup("web");
+  assert_eq!(found.len(), 1);
+  // Announced from loopback with no host of its own
+  assert_eq!(found[0].addr(), "127.0.0.1:8080".parse().unwrap());
+  assert_eq!(found[0].metadata["version"], "1.2");
+  assert_eq!(web.lookup("db")[0].addr(), "10.0.0.5:5432".parse().unwrap());
+  assert!(browser.lookup("ssh").is_empty());
+  let ids: Vec<u64> = browser.peers().iter().map(|peer| peer.node).collect();
+  let mut expected = vec![web.id(), db.id()];
+  expected.sort();
+  assert_eq!(ids, expected);
+}
+
+#[tokio::test]
+async fn a_node_joining_gets_answers_before_the_next_announcements() {
+  // Announcements far apart: only the query on joining can explain the printer being found
+  let config = Config {
+    announce_interval: Duration::from_secs(60),
+    ttl: Duration::from_secs(180),
+    ..config(47102)
+  };
+  let printer = Node::join(config.clone(), vec![Service::new("printer", 631)])
+    .await
+    .unwrap();
+  sleep(Duration::from_millis(100)).await;
+  let laptop = Node::join(config, vec![]).await.unwrap();
+
+  eventually("the printer", || laptop.lookup("printer").len() == 1).await;
+  eventually("the laptop", || printer.peers().len() == 1).await;
+}
+
+#[tokio::test]
+async fn changes_and_departures_are_reported() {
+  let config = config(47103);
+  let observer = Node::join(config.clone(), vec![]).await.unwrap();
+  let mut events = observer.subscribe();
+  let api = Node::join(config.clone(), vec![Service::new("api", 3000)])
+    .await
+    .unwrap();
+  let worker = Node::join(config, vec![Service::new("worker", 9000)])
+    .await
+    .unwrap();
+  let (api_id, worker_id) = (api.id(), worker.id());
+  next(
+    &mut events,
+    |event| matches!(event, Event::Joined(peer) if peer.node == api_id),
+  )
+  .await;
+  next(
+    &mut events,
+    |event| matches!(event, Event::Joined(peer) if peer.node == worker_id),
+  )
+  .await;
+
+  api
+    .publish(vec![Service::new("api", 3001).with_metadata("tls", "yes")])
+    .await
+    .unwrap();
+  let Event::Updated(peer) = next(
+    &mut events,
+    |event| matches!(event, Event::Updated(peer) if peer.node == api_id),
+  )
+  .await
+  else {
+    unreachable!()
+  };
+  assert_eq!(peer.services[0].port, 3001);
+  assert_eq!(observer.lookup("api")[0].metadata["tls"], "yes");
+
+  api.leave().await.unwrap();
+  assert_eq!(
+    next(&mut events, |event| matches!(event, Event::Left { .. })).await,
+    Event::Left {
+      node: api_id,
+      reason: LeaveReason::Goodbye
+    }
+  );
+
+  // Gone without a word: dropped once its TTL is over
+  drop(worker);
+  assert_eq!(
+    next(&mut events, |event| matches!(event, Event::Left { .. })).await,
+    Event::Left {
+      node: worker_id,
+      reason: LeaveReason::Expired
+    }
+  );
+  assert!(observer.peers().is_empty());
+}
+
+#[tokio::test]
+async fn forged_foreign_and_replayed_datagrams_are_ignored() {
+  let config = config(47104);
+  let group = config.group;
+  let observer = Node::join(config.clone(), vec![]).await.unwrap();
+  let mut events = observer.subscribe();
+  let outsider = Node::join(
+    Config {
+      key: b"another key".to_vec(),
+      ..config
+    },
+    vec![Service::new("web", 80)],
+  )
+  .await
+  .unwrap();
+
+  let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+  socket.send_to(b"Hello world!", group).await.unwrap();
+  let announcement = Message {
+    node: 7,
+    seq: 1,
+    body: Body::Announce {
+      ttl: Duration::from_secs(60),
+      services: vec![Service::new("ssh", 22)],
+    },
+  }
+  .encode(KEY)
+  .unwrap();
+  socket.send_to(&announcement, group).await.unwrap();
+  assert!(matches!(
+    next(&mut events, |_| true).await,
+    Event::Joined(peer) if peer.node == 7
+  ));
+
+  let goodbye = Message {
+    node: 7,
+    seq: 2,
+    body: Body::Goodbye,
+  }
+  .encode(KEY)
+  .unwrap();
+  socket.send_to(&goodbye, group).await.unwrap();
+  next(&mut events, |event| matches!(event, Event::Left { .. })).await;
+  // Sent again by someone who recorded it: node 7 stays gone
+  socket.send_to(&announcement, group).await.unwrap();
+  sleep(Duration::from_millis(300)).await;
+  assert!(observer.peers().is_empty());
+  assert!(outsider.peers().is_empty());
+  assert!(events.try_recv().is_err());
+}
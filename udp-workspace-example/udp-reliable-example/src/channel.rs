@@ -0,0 +1,374 @@
+use std::{
+  collections::{BTreeMap, BTreeSet, HashMap},
+  time::{Duration, Instant},
+};
+
+use crate::packet::{Fragment, FRAGMENT_SIZE};
+
+/// Delivery guarantees of a message, the modes laminar and uflow offer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+  /// Sent once: may be lost, and arrives in whatever order the network delivers it.
+  Unreliable,
+  /// Retransmitted until acked and delivered once, as soon as it is complete.
+  ReliableUnordered,
+  /// Retransmitted until acked and delivered once, in the order it was sent.
+  ReliableOrdered,
+}
+
+impl Channel {
+  pub fn is_reliable(self) -> bool {
+    self != Channel::Unreliable
+  }
+
+  pub(crate) fn id(self) -> u8 {
+    match self {
+      Channel::Unreliable => 0,
+      Channel::ReliableUnordered => 1,
+      Channel::ReliableOrdered => 2,
+    }
+  }
+
+  pub(crate) fn from_id(id: u8) -> Option<Channel> {
+    match id {
+      0 => Some(Channel::Unreliable),
+      1 => Some(Channel::ReliableUnordered),
+      2 => Some(Channel::ReliableOrdered),
+      _ => None,
+    }
+  }
+}
+
+/// Splits a message into the fragments to send, numbered `sequence`.
+pub(crate) fn fragment(channel: Channel, sequence: u32, data: &[u8]) -> Vec<Fragment> {
+  let count = data.len().div_ceil(FRAGMENT_SIZE).max(1);
+  (0 .. count)
+    .map(|index| Fragment {
+      channel,
+      sequence,
+      index: index as u16,
+      count: count as u16,
+      data: data[index * FRAGMENT_SIZE .. data.len().min((index + 1) * FRAGMENT_SIZE)].to_vec(),
+    })
+    .collect()
+}
+
+/// Unreliable messages still missing fragments after this are given up on.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Incomplete unreliable messages kept at once.
+const MAX_UNRELIABLE_PARTIALS: usize = 64;
+/// How far ahead of the first reliable message not delivered yet others may be.
+const RECEIVE_WINDOW: u32 = 1024;
+/// Bytes reliable messages waiting on others may hold, in messages of the largest size.
+const BUFFERED_MESSAGES: usize = 4;
+
+struct Partial {
+  fragments: Vec<Option<Vec<u8>>>,
+  missing: usize,
+  started: Instant,
+}
+
+/// Sequences below `below` and those in `above` have been delivered.
+#[derive(Default)]
+struct Delivered {
+  below: u32,
+  above: BTreeSet<u32>,
+}
+
+impl Delivered {
+  fn contains(&self, sequence: u32) -> bool {
+    sequence < self.below || self.above.contains(&sequence)
+  }
+
+  fn insert(&mut self, sequence: u32) {
+    if sequence >= self.below {
+      self.above.insert(sequence);
+    }
+    while self.above.remove(&self.below) {
+      self.below += 1;
+    }
+  }
+}
+
+/// Receiving side of the channels: reassembles fragments, drops the copies of reliable messages
+/// retransmitted after all and holds ordered messages back until the ones before have arrived.
+pub(crate) struct Inbox {
+  max_fragments: usize,
+  max_buffered: usize,
+  /// Room taken by incomplete reliable messages, as if all their fragments were full, and by
+  /// ordered ones held back.
+  buffered: usize,
+  partials: HashMap<(Channel, u32), Partial>,
+  unordered: Delivered,
+  next_ordered: u32,
+  ordered: BTreeMap<u32, Vec<u8>>,
+}
+
+impl Inbox {
+  pub fn new(max_message_size: usize) -> Inbox {
+    Inbox {
+      max_fragments: max_message_size.div_ceil(FRAGMENT_SIZE).max(1),
+      max_buffered: BUFFERED_MESSAGES * max_message_size,
+      buffered: 0,
+      partials: HashMap::new(),
+      unordered: Delivered::default(),
+      next_ordered: 0,
+      ordered: BTreeMap::new(),
+    }
+  }
+
+  /// Whether a fragment is no larger than those sent and, if reliable, is within the receive
+  /// window and there is room to keep it. The first message missing always fits, so that the
+  /// window keeps moving.
+  pub fn accepts(&self, fragment: &Fragment) -> bool {
+    if fragment.data.len() > FRAGMENT_SIZE {
+      return false;
+    }
+    let next = match fragment.channel {
+      Channel::Unreliable => return true,
+      Channel::ReliableUnordered => self.unordered.below,
+      Channel::ReliableOrdered => self.next_ordered,
+    };
+    let Some(ahead) = fragment.sequence.checked_sub(next) else {
+      // Delivered already
+      return true;
+    };
+    ahead < RECEIVE_WINDOW
+      && (ahead == 0
+        || self
+          .partials
+          .contains_key(&(fragment.channel, fragment.sequence))
+        || self.buffered + room(fragment) <= self.max_buffered)
+  }
+
+  /// Takes in a fragment, returning the messages it makes deliverable in order. Those
+  /// [`Inbox::accepts`] turns down are dropped.
+  pub fn receive(&mut self, fragment: Fragment, now: Instant) -> Vec<(Channel, Vec<u8>)> {
+    if !self.accepts(&fragment) {
+      return vec![];
+    }
+    let room = room(&fragment);
+    let Fragment {
+      channel,
+      sequence,
+      index,
+      count,
+      data,
+    } = fragment;
+    let count = count as usize;
+    if count > self.max_fragments || self.is_delivered(channel, sequence) {
+      return vec![];
+    }
+    let message = if count == 1 {
+      data
+    } else {
+      self.expire(now);
+      let partial = self.partials.entry((channel, sequence)).or_insert_with(|| {
+        if channel.is_reliable() {
+          self.buffered += room;
+        }
+        Partial {
+          fragments: vec![None; count],
+          missing: count,
+          started: now,
+        }
+      });
+      if partial.fragments.len() != count {
+        return vec![];
+      }
+      let slot = &mut partial.fragments[index as usize];
+      if slot.is_some() {
+        return vec![];
+      }
+      *slot = Some(data);
+      partial.missing -= 1;
+      if partial.missing > 0 {
+        return vec![];
+      }
+      let partial = self.partials.remove(&(channel, sequence)).unwrap();
+      if channel.is_reliable() {
+        self.buffered -= room;
+      }
+      partial.fragments.into_iter().flatten().flatten().collect()
+    };
+
+    match channel {
+      Channel::Unreliable => vec![(channel, message)],
+      Channel::ReliableUnordered => {
+        self.unordered.insert(sequence);
+        vec![(channel, message)]
+      }
+      Channel::ReliableOrdered => {
+        self.buffered += message.len();
+        self.ordered.insert(sequence, message);
+        let mut ready = vec![];
+        while let Some(message) = self.ordered.remove(&self.next_ordered) {
+          self.buffered -= message.len();
+          ready.push((channel, message));
+          self.next_ordered += 1;
+        }
+        ready
+      }
+    }
+  }
+
+  fn is_delivered(&self, channel: Channel, sequence: u32) -> bool {
+    match channel {
+      Channel::Unreliable => false,
+      Channel::ReliableUnordered => self.unordered.contains(sequence),
+      Channel::ReliableOrdered => {
+        sequence < self.next_ordered || self.ordered.contains_key(&sequence)
+      }
+    }
+  }
+
+  /// Drops the unreliable messages whose missing fragments are not coming. Reliable ones always
+  /// complete, their fragments being retransmitted.
+  fn expire(&mut self, now: Instant) {
+    self.partials.retain(|(channel, _), partial| {
+      channel.is_reliable() || now - partial.started < REASSEMBLY_TIMEOUT
+    });
+    loop {
+      let unreliable = self
+        .partials
+        .iter()
+        .filter(|((channel, _), _)| !channel.is_reliable());
+      if unreliable.clone().count() < MAX_UNRELIABLE_PARTIALS {
+        break;
+      }
+      let oldest = *unreliable
+        .min_by_key(|(_, partial)| partial.started)
+        .unwrap()
+        .0;
+      self.partials.remove(&oldest);
+    }
+  }
+}
+
+/// The room a reliable fragment may take: all of its message's if it has several, since the
+/// others are coming, and its own if it is a whole ordered message that may be held back.
+fn room(fragment: &Fragment) -> usize {
+  match (fragment.channel, fragment.count) {
+    (Channel::Unreliable, _) => 0,
+    (Channel::ReliableOrdered, 1) => fragment.data.len(),
+    (Channel::ReliableUnordered, 1) => 0,
+    (_, count) => count as usize * FRAGMENT_SIZE,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn messages(received: Vec<(Channel, Vec<u8>)>) -> Vec<Vec<u8>> {
+    received.into_iter().map(|(_, message)| message).collect()
+  }
+
+  #[test]
+  fn fragments_are_reassembled_in_any_order() {
+    let message: Vec<u8> = (0 .. 3000).map(|i| i as u8).collect();
+    let mut fragments = fragment(Channel::ReliableUnordered, 0, &message);
+    assert_eq!(fragments.len(), 3);
+    assert_eq!(fragments[2].data.len(), 3000 - 2 * FRAGMENT_SIZE);
+    fragments.reverse();
+
+    let mut inbox = Inbox::new(1 << 20);
+    let now = Instant::now();
+    let mut received = vec![];
+    for fragment in fragments.iter().chain(&fragments) {
+      received.extend(inbox.receive(fragment.clone(), now));
+    }
+    assert_eq!(messages(received), vec![message]);
+  }
+
+  #[test]
+  fn ordered_messages_wait_for_earlier_ones() {
+    let mut inbox = Inbox::new(1 << 20);
+    let now = Instant::now();
+    let send = |inbox: &mut Inbox, sequence: u32| {
+      let message = vec![sequence as u8];
+      let fragment = fragment(Channel::ReliableOrdered, sequence, &message).remove(0);
+      messages(inbox.receive(fragment, now))
+    };
+    assert!(send(&mut inbox, 1).is_empty());
+    assert!(send(&mut inbox, 2).is_empty());
+    assert_eq!(send(&mut inbox, 0), vec![vec![0], vec![1], vec![2]]);
+    // A late retransmission
+    assert!(send(&mut inbox, 1).is_empty());
+    assert_eq!(send(&mut inbox, 3), vec![vec![3]]);
+  }
+
+  #[test]
+  fn reliable_messages_are_delivered_once() {
+    let mut inbox = Inbox::new(1 << 20);
+    let now = Instant::now();
+    for (sequence, delivered) in [(2, 1), (0, 1), (2, 0), (1, 1), (0, 0)] {
+      let fragment = fragment(Channel::ReliableUnordered, sequence, b"").remove(0);
+      assert_eq!(
+        inbox.receive(fragment, now).len(),
+        delivered,
+        "{}",
+        sequence
+      );
+    }
+    assert_eq!(inbox.unordered.below, 3);
+    assert!(inbox.unordered.above.is_empty());
+  }
+
+  #[test]
+  fn reliable_messages_are_kept_within_the_window_and_buffer() {
+    let mut inbox = Inbox::new(2 * FRAGMENT_SIZE);
+    let now = Instant::now();
+    for channel in [Channel::ReliableUnordered, Channel::ReliableOrdered] {
+      let far = fragment(channel, RECEIVE_WINDOW, b"far").remove(0);
+      assert!(!inbox.accepts(&far));
+      assert!(inbox.receive(far, now).is_empty());
+    }
+    assert!(inbox.unordered.above.is_empty());
+    assert!(inbox.ordered.is_empty());
+
+    // Messages ahead take room until there is none left
+    let large = [7; 2 * FRAGMENT_SIZE];
+    let mut waiting: Vec<_> = (1 ..= 5)
+      .map(|sequence| fragment(Channel::ReliableOrdered, sequence, &large))
+      .collect();
+    for fragments in &waiting {
+      inbox.receive(fragments[0].clone(), now);
+    }
+    assert_eq!(inbox.buffered, BUFFERED_MESSAGES * large.len());
+    assert_eq!(inbox.partials.len(), BUFFERED_MESSAGES);
+    assert!(!inbox.accepts(&waiting[4][0]));
+    // Not from the messages started already, nor from the next one
+    assert!(inbox.accepts(&waiting[0][1]));
+    let next = fragment(Channel::ReliableOrdered, 0, b"next").remove(0);
+    assert_eq!(messages(inbox.receive(next, now)), vec![b"next".to_vec()]);
+
+    let mut received = vec![];
+    for fragments in waiting.iter_mut().take(BUFFERED_MESSAGES) {
+      received.extend(inbox.receive(fragments.remove(1), now));
+    }
+    assert_eq!(received.len(), BUFFERED_MESSAGES);
+    assert_eq!(inbox.buffered, 0);
+    assert!(inbox.accepts(&waiting[4][0]));
+  }
+
+  #[test]
+  fn incomplete_unreliable_messages_expire() {
+    let mut inbox = Inbox::new(1 << 20);
+    let start = Instant::now();
+    let fragments = fragment(Channel::Unreliable, 0, &[0; 2000]);
+    assert!(inbox.receive(fragments[0].clone(), start).is_empty());
+    let later = start + REASSEMBLY_TIMEOUT;
+    inbox.receive(
+      fragment(Channel::Unreliable, 1, &[0; 2000]).remove(0),
+      later,
+    );
+    assert!(inbox.receive(fragments[1].clone(), later).is_empty());
+
+    // Messages over the size limit are not buffered at all
+    let mut inbox = Inbox::new(FRAGMENT_SIZE);
+    let fragments = fragment(Channel::ReliableOrdered, 0, &[0; 2000]);
+    assert!(inbox.receive(fragments[0].clone(), start).is_empty());
+    assert!(inbox.partials.is_empty());
+  }
+}
@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+  /// A ping goes out when nothing else has been sent for this long, so that the peer keeps
+  /// hearing from an idle connection.
+  pub heartbeat_interval: Duration,
+  /// A connection nothing has been received on for this long is dropped.
+  pub idle_timeout: Duration,
+  /// How long [`Endpoint::connect`](crate::Endpoint::connect) keeps trying.
+  pub connect_timeout: Duration,
+  /// Retransmission timeout until the first RTT sample.
+  pub initial_rto: Duration,
+  pub min_rto: Duration,
+  pub max_rto: Duration,
+  pub max_message_size: usize,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      heartbeat_interval: Duration::from_secs(1),
+      idle_timeout: Duration::from_secs(5),
+      connect_timeout: Duration::from_secs(5),
+      initial_rto: Duration::from_millis(300),
+      min_rto: Duration::from_millis(50),
+      max_rto: Duration::from_secs(3),
+      max_message_size: 1 << 20,
+    }
+  }
+}
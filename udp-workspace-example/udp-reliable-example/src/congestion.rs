@@ -0,0 +1,103 @@
+use crate::packet::MAX_DATAGRAM;
+
+const INITIAL_WINDOW: usize = 10 * MAX_DATAGRAM;
+const MINIMUM_WINDOW: usize = 2 * MAX_DATAGRAM;
+
+/// NewReno congestion control over bytes in flight: slow start doubles the window every round
+/// trip, congestion avoidance grows it by a datagram, and a loss halves it once per round trip.
+#[derive(Debug)]
+pub(crate) struct Congestion {
+  window: usize,
+  threshold: usize,
+  in_flight: usize,
+  /// Packets numbered below were sent before the last reduction, their losses are part of it.
+  recovery_until: Option<u32>,
+}
+
+impl Congestion {
+  pub fn new() -> Congestion {
+    Congestion {
+      window: INITIAL_WINDOW,
+      threshold: usize::MAX,
+      in_flight: 0,
+      recovery_until: None,
+    }
+  }
+
+  pub fn window(&self) -> usize {
+    self.window
+  }
+
+  pub fn in_flight(&self) -> usize {
+    self.in_flight
+  }
+
+  pub fn can_send(&self, bytes: usize) -> bool {
+    self.in_flight + bytes <= self.window
+  }
+
+  pub fn on_sent(&mut self, bytes: usize) {
+    self.in_flight += bytes;
+  }
+
+  pub fn on_acked(&mut self, number: u32, bytes: usize) {
+    self.in_flight -= bytes;
+    if self.recovery_until.is_some_and(|until| number < until) {
+      return;
+    }
+    if self.window < self.threshold {
+      self.window += bytes;
+    } else {
+      self.window += MAX_DATAGRAM * bytes / self.window;
+    }
+  }
+
+  /// `next` is the number the next packet sent will get.
+  pub fn on_lost(&mut self, number: u32, bytes: usize, next: u32) {
+    self.in_flight -= bytes;
+    if self.recovery_until.is_some_and(|until| number < until) {
+      return;
+    }
+    self.recovery_until = Some(next);
+    self.window = (self.window / 2).max(MINIMUM_WINDOW);
+    self.threshold = self.window;
+  }
+
+  /// Nothing acked for a whole retransmission timeout: start over from the minimum window.
+  pub fn on_timeout(&mut self) {
+    self.window = MINIMUM_WINDOW;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn losses_halve_the_window_once_per_round_trip() {
+    let mut congestion = Congestion::new();
+    for number in 0 .. 10 {
+      congestion.on_sent(MAX_DATAGRAM);
+      assert!(congestion.can_send(MAX_DATAGRAM) == (number < 9));
+    }
+    congestion.on_acked(0, MAX_DATAGRAM);
+    assert_eq!(congestion.window(), 11 * MAX_DATAGRAM);
+
+    congestion.on_lost(1, MAX_DATAGRAM, 10);
+    assert_eq!(congestion.window(), 11 * MAX_DATAGRAM / 2);
+    congestion.on_lost(2, MAX_DATAGRAM, 10);
+    congestion.on_acked(3, MAX_DATAGRAM);
+    assert_eq!(congestion.window(), 11 * MAX_DATAGRAM / 2);
+
+    // Past the threshold, a window's worth of acks adds one datagram
+    let window = congestion.window();
+    for number in 10 .. 15 {
+      congestion.on_sent(window / 5);
+      congestion.on_acked(number, window / 5);
+    }
+    assert!(congestion.window() > window && congestion.window() <= window + MAX_DATAGRAM);
+
+    congestion.on_timeout();
+    assert_eq!(congestion.window(), MINIMUM_WINDOW);
+  }
+}
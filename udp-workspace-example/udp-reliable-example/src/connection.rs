@@ -0,0 +1,778 @@
+use std::{
+  collections::{BTreeMap, VecDeque},
+  time::{Duration, Instant},
+};
+
+use crate::{
+  channel::{self, Channel, Inbox},
+  config::Config,
+  congestion::Congestion,
+  error::{DisconnectReason, Error},
+  packet::{Ack, Fragment, Frame, Packet, DATA_HEADER, MAX_DATAGRAM},
+  ranges::RangeSet,
+  rtt::RttEstimator,
+};
+
+/// How long an ack may wait for a packet to ride on, when one ack-eliciting packet came in.
+const MAX_ACK_DELAY: Duration = Duration::from_millis(10);
+/// A packet is lost once this many later ones have been acked.
+const PACKET_THRESHOLD: u32 = 3;
+/// Cap on the exponent of the retransmission timeout backoff.
+const MAX_BACKOFF: u32 = 6;
+
+/// Counters and estimates of one connection.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+  /// Smoothed round-trip time, once measured.
+  pub rtt: Option<Duration>,
+  pub rto: Duration,
+  pub congestion_window: usize,
+  pub bytes_in_flight: usize,
+  pub packets_sent: u64,
+  pub packets_received: u64,
+  pub packets_lost: u64,
+  pub timeouts: u64,
+  pub messages_sent: u64,
+  pub messages_received: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Event {
+  Connected,
+  Message(Channel, Vec<u8>),
+  Disconnected(DisconnectReason),
+}
+
+#[derive(Debug)]
+enum State {
+  /// Client waiting for [`Packet::Accept`].
+  Connecting {
+    first_sent: Instant,
+    attempts: u32,
+    retry_at: Instant,
+    deadline: Instant,
+  },
+  Established,
+  /// Closed locally, still delivering the queued reliable messages.
+  Closing {
+    deadline: Instant,
+  },
+  Closed,
+}
+
+struct SentPacket {
+  time: Instant,
+  size: usize,
+  /// Reliable fragments, sent again if the packet is lost.
+  fragments: Vec<Fragment>,
+}
+
+/// One side of a connection, without any I/O: datagrams and timeouts go in, datagrams to send and
+/// events come out. [`Endpoint`](crate::Endpoint) drives it over a socket.
+pub(crate) struct Connection {
+  id: u32,
+  config: Config,
+  state: State,
+  /// Handshake and disconnect packets waiting to go out.
+  control: VecDeque<Packet>,
+
+  next_number: u32,
+  /// Ack-eliciting packets in flight, by number.
+  sent: BTreeMap<u32, SentPacket>,
+  queue: VecDeque<Fragment>,
+  next_sequence: [u32; 3],
+  largest_acked: Option<u32>,
+  rtt: RttEstimator,
+  congestion: Congestion,
+  backoff: u32,
+  last_sent: Instant,
+  ping: bool,
+
+  received: RangeSet,
+  largest_received_at: Instant,
+  /// When the ack owed for received packets must go out.
+  ack_at: Option<Instant>,
+  unacked: u32,
+  last_received: Instant,
+  inbox: Inbox,
+
+  events: VecDeque<Event>,
+  stats: Stats,
+}
+
+impl Connection {
+  pub fn client(id: u32, config: Config, now: Instant) -> Connection {
+    let deadline = now + config.connect_timeout;
+    let mut connection = Connection::new(id, config, now);
+    connection.state = State::Connecting {
+      first_sent: now,
+      attempts: 1,
+      retry_at: (now + connection.config.initial_rto).min(deadline),
+      deadline,
+    };
+    connection.control.push_back(Packet::Connect { id });
+    connection
+  }
+
+  /// The server side, answering a [`Packet::Connect`] just received.
+  pub fn server(id: u32, config: Config, now: Instant) -> Connection {
+    let mut connection = Connection::new(id, config, now);
+    connection.control.push_back(Packet::Accept { id });
+    connection
+  }
+
+  fn new(id: u32, config: Config, now: Instant) -> Connection {
+    Connection {
+      id,
+      state: State::Established,
+      control: VecDeque::new(),
+      next_number: 0,
+      sent: BTreeMap::new(),
+      queue: VecDeque::new(),
+      next_sequence: [0; 3],
+      largest_acked: None,
+      rtt: RttEstimator::new(&config),
+      congestion: Congestion::new(),
+      backoff: 0,
+      last_sent: now,
+      ping: false,
+      received: RangeSet::default(),
+      largest_received_at: now,
+      ack_at: None,
+      unacked: 0,
+      last_received: now,
+      inbox: Inbox::new(config.max_message_size),
+      events: VecDeque::new(),
+      stats: Stats::default(),
+      config,
+    }
+  }
+
+  pub fn id(&self) -> u32 {
+    self.id
+  }
+
+  pub fn is_closed(&self) -> bool {
+    matches!(self.state, State::Closed)
+  }
+
+  pub fn stats(&self) -> Stats {
+    Stats {
+      rtt: self.rtt.smoothed(),
+      rto: self.rto(),
+      congestion_window: self.congestion.window(),
+      bytes_in_flight: self.congestion.in_flight(),
+      ..self.stats
+    }
+  }
+
+  pub fn send(&mut self, channel: Channel, data: &[u8]) -> Result<(), Error> {
+    if data.len() > self.config.max_message_size {
+      return Err(Error::TooLarge {
+        size: data.len(),
+        max: self.config.max_message_size,
+      });
+    }
+    if !matches!(self.state, State::Established) {
+      return Err(Error::Disconnected(DisconnectReason::Closed));
+    }
+    let sequence = &mut self.next_sequence[channel.id() as usize];
+    self
+      .queue
+      .extend(channel::fragment(channel, *sequence, data));
+    *sequence += 1;
+    self.stats.messages_sent += 1;
+    Ok(())
+  }
+
+  /// Starts closing: queued and unacked reliable messages are still delivered, then the peer is
+  /// told, unless it stops answering first.
+  pub fn close(&mut self, now: Instant) {
+    match self.state {
+      State::Established => {
+        self.state = State::Closing {
+          deadline: now + self.config.idle_timeout,
+        };
+      }
+      State::Connecting { .. } => self.disconnect(DisconnectReason::Closed),
+      State::Closing { .. } | State::Closed => {}
+    }
+  }
+
+  pub fn poll_event(&mut self) -> Option<Event> {
+    self.events.pop_front()
+  }
+
+  pub fn handle_datagram(&mut self, packet: Packet, now: Instant) {
+    if packet.id() != self.id || self.is_closed() {
+      return;
+    }
+    match packet {
+      Packet::Connect { .. } => {
+        // Our accept was lost
+        if self.control.is_empty() {
+          self.control.push_back(Packet::Accept { id: self.id });
+        }
+      }
+      Packet::Accept { .. } => self.on_accepted(now),
+      Packet::Data { number, frames, .. } => {
+        // Data before the accept means the accept was lost
+        self.on_accepted(now);
+        self.on_data(number, frames, now);
+      }
+      Packet::Disconnect { .. } => self.disconnect(DisconnectReason::ClosedByPeer),
+    }
+  }
+
+  fn on_accepted(&mut self, now: Instant) {
+    let State::Connecting {
+      first_sent,
+      attempts,
+      ..
+    } = self.state
+    else {
+      return;
+    };
+    // Karn's algorithm: after a retry there is no telling which connect was answered
+    if attempts == 1 {
+      self.rtt.update(now - first_sent);
+    }
+    self.state = State::Established;
+    self.last_received = now;
+    self.events.push_back(Event::Connected);
+  }
+
+  fn on_data(&mut self, number: u32, frames: Vec<Frame>, now: Instant) {
+    self.last_received = now;
+    // A packet the inbox has no room for is not acked: the peer sends it again once it has
+    let fits = frames.iter().all(|frame| match frame {
+      Frame::Message(fragment) => self.inbox.accepts(fragment),
+      _ => true,
+    });
+    if !fits || !self.received.insert(number) {
+      return;
+    }
+    self.stats.packets_received += 1;
+    if self.received.largest() == Some(number) {
+      self.largest_received_at = now;
+    }
+    if frames.iter().any(Frame::is_ack_eliciting) {
+      self.unacked += 1;
+      let at = if self.unacked >= 2 {
+        now
+      } else {
+        now + MAX_ACK_DELAY
+      };
+      self.ack_at = Some(self.ack_at.map_or(at, |ack_at| ack_at.min(at)));
+    }
+    for frame in frames {
+      match frame {
+        Frame::Ack(ack) => self.on_ack(ack, now),
+        Frame::Message(fragment) => {
+          for (channel, message) in self.inbox.receive(fragment, now) {
+            self.stats.messages_received += 1;
+            self.events.push_back(Event::Message(channel, message));
+          }
+        }
+        Frame::Ping => {}
+      }
+    }
+  }
+
+  fn on_ack(&mut self, ack: Ack, now: Instant) {
+    let Some(&(_, largest)) = ack.ranges.first() else {
+      return;
+    };
+    let mut newly_acked = vec![];
+    for &(first, last) in &ack.ranges {
+      newly_acked.extend(self.sent.range(first ..= last).map(|(&number, _)| number));
+    }
+    if newly_acked.is_empty() {
+      return;
+    }
+    if let Some(packet) = self.sent.get(&largest) {
+      let rtt = now - packet.time;
+      self.rtt.update(rtt.checked_sub(ack.delay).unwrap_or(rtt));
+    }
+    for number in newly_acked {
+      let packet = self.sent.remove(&number).unwrap();
+      self.congestion.on_acked(number, packet.size);
+    }
+    self.largest_acked = self.largest_acked.max(Some(largest));
+    self.backoff = 0;
+    self.detect_losses(now);
+  }
+
+  /// A packet is lost when enough later ones have been acked, or one acked later was sent more
+  /// than a round trip after it.
+  fn detect_losses(&mut self, now: Instant) {
+    let Some(largest) = self.largest_acked else {
+      return;
+    };
+    let threshold = self.loss_threshold();
+    let lost = self
+      .sent
+      .range(.. largest)
+      .filter(|&(&number, packet)| {
+        number + PACKET_THRESHOLD <= largest || now - packet.time >= threshold
+      })
+      .map(|(&number, _)| number)
+      .collect();
+    self.declare_lost(lost);
+  }
+
+  fn loss_threshold(&self) -> Duration {
+    self.rtt.conservative() * 9 / 8
+  }
+
+  /// When the oldest packet sent before an acked one counts as lost, if it stays unacked.
+  fn loss_time(&self) -> Option<Instant> {
+    let (_, oldest) = self.sent.range(.. self.largest_acked?).next()?;
+    Some(oldest.time + self.loss_threshold())
+  }
+
+  fn declare_lost(&mut self, numbers: Vec<u32>) {
+    let mut fragments = vec![];
+    for number in numbers {
+      let packet = self.sent.remove(&number).unwrap();
+      self
+        .congestion
+        .on_lost(number, packet.size, self.next_number);
+      self.stats.packets_lost += 1;
+      fragments.extend(packet.fragments);
+    }
+    // Oldest messages first, ahead of the new ones: ordered channels are not held back longer
+    // than needed, and the peer only takes messages within its receive window. Sequences count
+    // per channel, so each channel is sorted within the places its fragments take.
+    let queue: Vec<Fragment> = fragments.into_iter().chain(self.queue.drain(..)).collect();
+    let channels: Vec<Channel> = queue.iter().map(|fragment| fragment.channel).collect();
+    let mut by_channel: [Vec<Fragment>; 3] = Default::default();
+    for fragment in queue {
+      by_channel[fragment.channel.id() as usize].push(fragment);
+    }
+    let mut by_channel = by_channel.map(|mut fragments| {
+      fragments.sort_by_key(|fragment| fragment.sequence);
+      fragments.into_iter()
+    });
+    self.queue = channels
+      .into_iter()
+      .map(|channel| by_channel[channel.id() as usize].next().unwrap())
+      .collect();
+  }
+
+  /// The estimate plus the time the peer may sit on an ack, doubled for every timeout in a row.
+  fn rto(&self) -> Duration {
+    ((self.rtt.rto() + MAX_ACK_DELAY) * 2u32.pow(self.backoff)).min(self.config.max_rto)
+  }
+
+  pub fn poll_timeout(&self) -> Option<Instant> {
+    match self.state {
+      State::Connecting { retry_at, .. } => Some(retry_at),
+      State::Established | State::Closing { .. } => {
+        let mut timeout = (self.last_received + self.config.idle_timeout)
+          .min(self.last_sent + self.config.heartbeat_interval);
+        if let State::Closing { deadline } = self.state {
+          timeout = timeout.min(deadline);
+        }
+        if let Some(ack_at) = self.ack_at {
+          timeout = timeout.min(ack_at);
+        }
+        if let Some((_, oldest)) = self.sent.first_key_value() {
+          timeout = timeout.min(oldest.time + self.rto());
+        }
+        if let Some(loss_time) = self.loss_time() {
+          timeout = timeout.min(loss_time);
+        }
+        Some(timeout)
+      }
+      State::Closed => None,
+    }
+  }
+
+  pub fn handle_timeout(&mut self, now: Instant) {
+    match self.state {
+      State::Connecting {
+        attempts,
+        retry_at,
+        deadline,
+        first_sent,
+      } => {
+        if now < retry_at {
+          return;
+        }
+        if now >= deadline {
+          self.disconnect(DisconnectReason::TimedOut);
+          return;
+        }
+        let backoff = self.config.initial_rto * 2u32.pow(attempts.min(MAX_BACKOFF));
+        self.state = State::Connecting {
+          first_sent,
+          attempts: attempts + 1,
+          retry_at: (now + backoff).min(deadline),
+          deadline,
+        };
+        self.control.push_back(Packet::Connect { id: self.id });
+      }
+      State::Established | State::Closing { .. } => {
+        if now >= self.last_received + self.config.idle_timeout {
+          self.disconnect(DisconnectReason::TimedOut);
+          return;
+        }
+        if matches!(self.state, State::Closing { deadline } if now >= deadline) {
+          self.disconnect(DisconnectReason::Closed);
+          return;
+        }
+        self.detect_losses(now);
+        let rto = self.rto();
+        let expired: Vec<u32> = self
+          .sent
+          .iter()
+          .take_while(|(_, packet)| packet.time + rto <= now)
+          .map(|(&number, _)| number)
+          .collect();
+        if !expired.is_empty() {
+          self.declare_lost(expired);
+          // One timeout may be a lost ack, only a second one in a row means the path is congested
+          if self.backoff > 0 {
+            self.congestion.on_timeout();
+          }
+          self.backoff = (self.backoff + 1).min(MAX_BACKOFF);
+          self.stats.timeouts += 1;
+        }
+        if now >= self.last_sent + self.config.heartbeat_interval {
+          self.ping = true;
+        }
+      }
+      State::Closed => {}
+    }
+  }
+
+  /// The next datagram to send, if any.
+  pub fn poll_transmit(&mut self, now: Instant) -> Option<Vec<u8>> {
+    if let Some(packet) = self.control.pop_front() {
+      self.last_sent = now;
+      return Some(packet.encode());
+    }
+    match self.state {
+      State::Established => {}
+      State::Closing { .. } => {
+        if self.queue.is_empty() && self.sent.values().all(|packet| packet.fragments.is_empty()) {
+          self.disconnect(DisconnectReason::Closed);
+          // Nothing acks a disconnect, a few copies make it likely to arrive
+          let disconnect = Packet::Disconnect { id: self.id };
+          self
+            .control
+            .extend([disconnect.clone(), disconnect.clone()]);
+          return Some(disconnect.encode());
+        }
+      }
+      State::Connecting { .. } | State::Closed => return None,
+    }
+
+    let mut frames = vec![];
+    let mut size = DATA_HEADER;
+    if self.ack_at.is_some_and(|at| at <= now) || (self.ack_at.is_some() && self.has_data_to_send())
+    {
+      let ack = Frame::Ack(Ack {
+        delay: now - self.largest_received_at,
+        ranges: self.received.newest_first(),
+      });
+      size += ack.encoded_len();
+      frames.push(ack);
+      self.ack_at = None;
+      self.unacked = 0;
+    }
+    if self.ping {
+      frames.push(Frame::Ping);
+      size += 1;
+      self.ping = false;
+    }
+    let mut fragments = vec![];
+    if self.congestion.can_send(MAX_DATAGRAM) {
+      while let Some(fragment) = self.queue.front() {
+        let frame_len = 12 + fragment.data.len();
+        if size + frame_len > MAX_DATAGRAM {
+          break;
+        }
+        size += frame_len;
+        let fragment = self.queue.pop_front().unwrap();
+        if fragment.channel.is_reliable() {
+          fragments.push(fragment.clone());
+        }
+        frames.push(Frame::Message(fragment));
+      }
+    }
+    if frames.is_empty() {
+      return None;
+    }
+
+    let number = self.next_number;
+    self.next_number += 1;
+    if frames.iter().any(Frame::is_ack_eliciting) {
+      self.congestion.on_sent(size);
+      self.sent.insert(
+        number,
+        SentPacket {
+          time: now,
+          size,
+          fragments,
+        },
+      );
+    }
+    self.stats.packets_sent += 1;
+    self.last_sent = now;
+    Some(
+      Packet::Data {
+        id: self.id,
+        number,
+        frames,
+      }
+      .encode(),
+    )
+  }
+
+  fn has_data_to_send(&self) -> bool {
+    self.ping || (!self.queue.is_empty() && self.congestion.can_send(MAX_DATAGRAM))
+  }
+
+  fn disconnect(&mut self, reason: DisconnectReason) {
+    self.state = State::Closed;
+    self.queue.clear();
+    self.sent.clear();
+    self.events.push_back(Event::Disconnected(reason));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Moves every datagram from one side to the other, dropping those `drop` picks.
+  fn exchange(
+    from: &mut Connection,
+    to: &mut Connection,
+    now: Instant,
+    mut drop: impl FnMut(&Packet) -> bool,
+  ) -> usize {
+    let mut moved = 0;
+    while let Some(datagram) = from.poll_transmit(now) {
+      let packet = Packet::decode(&datagram).unwrap();
+      if !drop(&packet) {
+        to.handle_datagram(packet, now);
+        moved += 1;
+      }
+    }
+    moved
+  }
+
+  fn connected(now: Instant) -> (Connection, Connection) {
+    let mut client = Connection::client(7, Config::default(), now);
+    let mut server = Connection::server(7, Config::default(), now);
+    exchange(&mut client, &mut server, now, |_| false);
+    exchange(&mut server, &mut client, now, |_| false);
+    assert_eq!(client.poll_event(), Some(Event::Connected));
+    (client, server)
+  }
+
+  fn messages(connection: &mut Connection) -> Vec<Vec<u8>> {
+    let mut messages = vec![];
+    while let Some(event) = connection.poll_event() {
+      if let Event::Message(_, message) = event {
+        messages.push(message);
+      }
+    }
+    messages
+  }
+
+  #[test]
+  fn connect_is_retried_until_the_timeout() {
+    let start = Instant::now();
+    let mut client = Connection::client(1, Config::default(), start);
+    assert!(client.poll_transmit(start).is_some());
+    let mut now = start;
+    let mut attempts = 1;
+    while let Some(timeout) = client.poll_timeout() {
+      now = timeout;
+      client.handle_timeout(now);
+      attempts += usize::from(client.poll_transmit(now).is_some());
+    }
+    assert_eq!(
+      client.poll_event(),
+      Some(Event::Disconnected(DisconnectReason::TimedOut))
+    );
+    assert_eq!(now - start, Config::default().connect_timeout);
+    // At 0, 0.3, 0.9, 2.1 and 4.5 seconds
+    assert_eq!(attempts, 5);
+  }
+
+  #[test]
+  fn lost_packets_are_retransmitted_after_later_ones_are_acked() {
+    let now = Instant::now();
+    let (mut client, mut server) = connected(now);
+    for i in 0 .. 6u8 {
+      client.send(Channel::ReliableOrdered, &[i; 1000]).unwrap();
+    }
+    // The first packet is lost: the others are held back, and acked
+    let mut first = true;
+    exchange(&mut client, &mut server, now, |_| {
+      std::mem::take(&mut first)
+    });
+    assert!(messages(&mut server).is_empty());
+    exchange(&mut server, &mut client, now, |_| false);
+    assert_eq!(client.stats().packets_lost, 1);
+
+    exchange(&mut client, &mut server, now, |_| false);
+    let received = messages(&mut server);
+    assert_eq!(received.len(), 6);
+    assert!(received.iter().enumerate().all(|(i, m)| m[0] == i as u8));
+  }
+
+  #[test]
+  fn lost_fragments_go_ahead_of_newer_ones_on_other_channels() {
+    let now = Instant::now();
+    let (mut client, mut server) = connected(now);
+    for i in 0 .. 3u8 {
+      client.send(Channel::ReliableOrdered, &[i; 1000]).unwrap();
+    }
+    let mut first = true;
+    exchange(&mut client, &mut server, now, |_| {
+      std::mem::take(&mut first)
+    });
+    // Queued before the loss is found, with a sequence as low as the lost one
+    client.send(Channel::ReliableUnordered, &[9; 1000]).unwrap();
+    exchange(&mut server, &mut client, now, |_| false);
+    assert_eq!(client.stats().packets_lost, 1);
+
+    let datagram = client.poll_transmit(now).unwrap();
+    let Packet::Data { frames, .. } = Packet::decode(&datagram).unwrap() else {
+      panic!("not data");
+    };
+    let resent = frames.iter().find_map(|frame| match frame {
+      Frame::Message(fragment) => Some((fragment.channel, fragment.sequence)),
+      _ => None,
+    });
+    assert_eq!(resent, Some((Channel::ReliableOrdered, 0)));
+  }
+
+  #[test]
+  fn packets_beyond_the_receive_window_are_sent_again() {
+    let mut now = Instant::now();
+    let (mut client, mut server) = connected(now);
+    for i in 0 .. 3000u32 {
+      client
+        .send(Channel::ReliableOrdered, &i.to_be_bytes())
+        .unwrap();
+    }
+    // Until the first message makes it, the server takes a window of messages and no more
+    let first = |packet: &Packet| match packet {
+      Packet::Data { frames, .. } => frames
+        .iter()
+        .any(|frame| matches!(frame, Frame::Message(fragment) if fragment.sequence == 0)),
+      _ => false,
+    };
+    let mut received = vec![];
+    for round in 0 .. 100 {
+      exchange(&mut client, &mut server, now, |packet| {
+        round < 20 && first(packet)
+      });
+      received.extend(messages(&mut server));
+      exchange(&mut server, &mut client, now, |_| false);
+      if received.len() == 3000 {
+        break;
+      }
+      now = client.poll_timeout().unwrap();
+      client.handle_timeout(now);
+      server.handle_timeout(now);
+    }
+    let expected: Vec<_> = (0 .. 3000u32).map(|i| i.to_be_bytes().to_vec()).collect();
+    assert_eq!(received, expected);
+  }
+
+  #[test]
+  fn the_retransmission_timeout_backs_off() {
+    let now = Instant::now();
+    let (mut client, mut server) = connected(now);
+    client.send(Channel::ReliableUnordered, b"hello").unwrap();
+    exchange(&mut client, &mut server, now, |_| true);
+
+    let rto = client.stats().rto;
+    let first = client.poll_timeout().unwrap();
+    assert_eq!(first, now + rto);
+    client.handle_timeout(first);
+    exchange(&mut client, &mut server, first, |_| true);
+    assert_eq!(client.poll_timeout().unwrap(), first + 2 * rto);
+    assert_eq!(client.stats().timeouts, 1);
+
+    client.handle_timeout(first + 2 * rto);
+    exchange(&mut client, &mut server, first + 2 * rto, |_| false);
+    assert_eq!(messages(&mut server), vec![b"hello".to_vec()]);
+  }
+
+  #[test]
+  fn unreliable_messages_are_not_retransmitted() {
+    let now = Instant::now();
+    let (mut client, mut server) = connected(now);
+    client.send(Channel::Unreliable, b"lost").unwrap();
+    client.send(Channel::ReliableOrdered, b"kept").unwrap();
+    exchange(&mut client, &mut server, now, |_| true);
+
+    let timeout = client.poll_timeout().unwrap();
+    client.handle_timeout(timeout);
+    exchange(&mut client, &mut server, timeout, |_| false);
+    assert_eq!(messages(&mut server), vec![b"kept".to_vec()]);
+  }
+
+  #[test]
+  fn idle_connections_ping_and_time_out() {
+    let now = Instant::now();
+    let (mut client, mut server) = connected(now);
+    let heartbeat = now + Config::default().heartbeat_interval;
+    assert_eq!(client.poll_timeout(), Some(heartbeat));
+    client.handle_timeout(heartbeat);
+    assert_eq!(exchange(&mut client, &mut server, heartbeat, |_| false), 1);
+
+    let silence = heartbeat + Config::default().idle_timeout;
+    client.handle_timeout(silence);
+    assert!(client.is_closed());
+    assert_eq!(
+      client.poll_event(),
+      Some(Event::Disconnected(DisconnectReason::TimedOut))
+    );
+  }
+
+  #[test]
+  fn closing_delivers_queued_messages_first() {
+    let now = Instant::now();
+    let (mut client, mut server) = connected(now);
+    client
+      .send(Channel::ReliableOrdered, b"last words")
+      .unwrap();
+    client.close(now);
+    assert!(client.send(Channel::ReliableOrdered, b"too late").is_err());
+    let mut first = true;
+    exchange(&mut client, &mut server, now, |_| {
+      std::mem::take(&mut first)
+    });
+    assert!(!client.is_closed());
+
+    let retry = client.poll_timeout().unwrap();
+    client.handle_timeout(retry);
+    exchange(&mut client, &mut server, retry, |_| false);
+    let later = retry + MAX_ACK_DELAY;
+    server.handle_timeout(later);
+    exchange(&mut server, &mut client, later, |_| false);
+    exchange(&mut client, &mut server, later, |_| false);
+    assert!(client.is_closed());
+    assert_eq!(
+      server.poll_event(),
+      Some(Event::Message(
+        Channel::ReliableOrdered,
+        b"last words".to_vec()
+      ))
+    );
+    assert_eq!(
+      server.poll_event(),
+      Some(Event::Disconnected(DisconnectReason::ClosedByPeer))
+    );
+  }
+}
@@ -0,0 +1,407 @@
+use std::{
+  collections::HashMap,
+  future, io,
+  net::SocketAddr,
+  sync::{Arc, Mutex},
+  time::Instant,
+};
+
+use tokio::{
+  net::{ToSocketAddrs, UdpSocket},
+  sync::{mpsc, oneshot},
+  task::JoinHandle,
+  time,
+};
+
+use crate::{
+  channel::Channel,
+  config::Config,
+  connection::{self, Event, Stats},
+  error::{DisconnectReason, Error},
+  packet::Packet,
+  transport::Transport,
+};
+
+/// Incoming connections waiting for [`Endpoint::accept`]. Connects beyond are ignored, and
+/// retried by the peer.
+const ACCEPT_BACKLOG: usize = 64;
+
+enum Command {
+  Connect {
+    addr: SocketAddr,
+    reply: oneshot::Sender<Result<Connection, Error>>,
+  },
+  Send {
+    addr: SocketAddr,
+    id: u32,
+    channel: Channel,
+    data: Vec<u8>,
+  },
+  Close {
+    addr: SocketAddr,
+    id: u32,
+  },
+}
+
+/// What a [`Connection`] reads without a round trip to the driver.
+#[derive(Default)]
+struct Shared {
+  stats: Stats,
+  closed: Option<DisconnectReason>,
+}
+
+/// A socket carrying connections to any number of peers, opened by either side. The protocol runs
+/// in a task of its own, until the endpoint and all its connections are dropped.
+pub struct Endpoint {
+  commands: mpsc::UnboundedSender<Command>,
+  incoming: mpsc::Receiver<Connection>,
+  local_addr: SocketAddr,
+  driver: JoinHandle<()>,
+}
+
+impl Endpoint {
+  pub async fn bind(addr: impl ToSocketAddrs, config: Config) -> io::Result<Endpoint> {
+    Endpoint::new(UdpSocket::bind(addr).await?, config)
+  }
+
+  pub fn new(transport: impl Transport, config: Config) -> io::Result<Endpoint> {
+    let local_addr = transport.local_addr()?;
+    let (commands, commands_rx) = mpsc::unbounded_channel();
+    let (incoming_tx, incoming) = mpsc::channel(ACCEPT_BACKLOG);
+    let driver = Driver {
+      transport,
+      config,
+      commands: commands.downgrade(),
+      commands_rx,
+      incoming: incoming_tx,
+      peers: HashMap::new(),
+      shutting_down: false,
+    };
+    Ok(Endpoint {
+      commands,
+      incoming,
+      local_addr,
+      driver: tokio::spawn(driver.run()),
+    })
+  }
+
+  pub fn local_addr(&self) -> SocketAddr {
+    self.local_addr
+  }
+
+  /// Opens a connection, retrying the handshake until [`Config::connect_timeout`].
+  pub async fn connect(&self, addr: SocketAddr) -> Result<Connection, Error> {
+    let (reply, response) = oneshot::channel();
+    self
+      .commands
+      .send(Command::Connect { addr, reply })
+      .map_err(|_| Error::EndpointClosed)?;
+    response.await.map_err(|_| Error::EndpointClosed)?
+  }
+
+  /// The next connection a peer opened.
+  pub async fn accept(&mut self) -> Option<Connection> {
+    self.incoming.recv().await
+  }
+
+  /// Waits for the connections to be dropped and done closing, so that peers are told rather
+  /// than left to time out when the process exits.
+  pub async fn shutdown(self) {
+    let Endpoint {
+      commands,
+      incoming,
+      driver,
+      ..
+    } = self;
+    drop((commands, incoming));
+    let _ = driver.await;
+  }
+}
+
+/// A connection to one peer. Dropping it closes the connection once the reliable messages sent
+/// have been acked.
+pub struct Connection {
+  addr: SocketAddr,
+  id: u32,
+  max_message_size: usize,
+  commands: mpsc::UnboundedSender<Command>,
+  events: mpsc::UnboundedReceiver<Event>,
+  shared: Arc<Mutex<Shared>>,
+}
+
+impl Connection {
+  pub fn remote_addr(&self) -> SocketAddr {
+    self.addr
+  }
+
+  /// Queues a message, splitting it into fragments when it does not fit in one datagram.
+  pub fn send(&self, channel: Channel, data: impl Into<Vec<u8>>) -> Result<(), Error> {
+    let data = data.into();
+    if data.len() > self.max_message_size {
+      return Err(Error::TooLarge {
+        size: data.len(),
+        max: self.max_message_size,
+      });
+    }
+    if let Some(reason) = self.shared.lock().unwrap().closed {
+      return Err(Error::Disconnected(reason));
+    }
+    self
+      .commands
+      .send(Command::Send {
+        addr: self.addr,
+        id: self.id,
+        channel,
+        data,
+      })
+      .map_err(|_| Error::EndpointClosed)
+  }
+
+  /// The next message, or why the connection ended.
+  pub async fn recv(&mut self) -> Result<(Channel, Vec<u8>), DisconnectReason> {
+    match self.events.recv().await {
+      Some(Event::Message(channel, data)) => Ok((channel, data)),
+      Some(Event::Disconnected(reason)) => Err(reason),
+      Some(Event::Connected) | None => Err(
+        self
+          .shared
+          .lock()
+          .unwrap()
+          .closed
+          .unwrap_or(DisconnectReason::Closed),
+      ),
+    }
+  }
+
+  pub fn stats(&self) -> Stats {
+    self.shared.lock().unwrap().stats
+  }
+}
+
+impl Drop for Connection {
+  fn drop(&mut self) {
+    let _ = self.commands.send(Command::Close {
+      addr: self.addr,
+      id: self.id,
+    });
+  }
+}
+
+struct Peer {
+  connection: connection::Connection,
+  /// Where messages go, once the application has a handle.
+  events: Option<mpsc::UnboundedSender<Event>>,
+  shared: Arc<Mutex<Shared>>,
+  /// The caller of [`Endpoint::connect`] waiting for the handshake.
+  connecting: Option<oneshot::Sender<Result<Connection, Error>>>,
+}
+
+impl Peer {
+  fn new(connection: connection::Connection) -> Peer {
+    Peer {
+      connection,
+      events: None,
+      shared: Arc::default(),
+      connecting: None,
+    }
+  }
+
+  fn handle(
+    &mut self,
+    addr: SocketAddr,
+    config: &Config,
+    commands: mpsc::UnboundedSender<Command>,
+  ) -> Connection {
+    let (events, events_rx) = mpsc::unbounded_channel();
+    self.events = Some(events);
+    Connection {
+      addr,
+      id: self.connection.id(),
+      max_message_size: config.max_message_size,
+      commands,
+      events: events_rx,
+      shared: self.shared.clone(),
+    }
+  }
+}
+
+/// Owns the transport and the connections: feeds them datagrams, commands and timeouts, and
+/// sends what they produce.
+struct Driver<T> {
+  transport: T,
+  config: Config,
+  /// Weak, so that the driver does not keep itself alive.
+  commands: mpsc::WeakUnboundedSender<Command>,
+  commands_rx: mpsc::UnboundedReceiver<Command>,
+  incoming: mpsc::Sender<Connection>,
+  peers: HashMap<SocketAddr, Peer>,
+  /// Every handle is gone: the driver stops once the closing connections are done.
+  shutting_down: bool,
+}
+
+impl<T: Transport> Driver<T> {
+  async fn run(mut self) {
+    let mut buf = vec![0; 65536];
+    loop {
+      self.flush().await;
+      if self.shutting_down && self.peers.is_empty() {
+        return;
+      }
+      let timeout = self
+        .peers
+        .values()
+        .filter_map(|peer| peer.connection.poll_timeout())
+        .min();
+      tokio::select! {
+        received = self.transport.recv_from(&mut buf) => {
+          if let Ok((len, from)) = received {
+            self.on_datagram(&buf[.. len], from);
+          }
+        }
+        command = self.commands_rx.recv(), if !self.shutting_down => match command {
+          Some(command) => self.on_command(command),
+          None => self.shutting_down = true,
+        },
+        () = sleep_until(timeout) => {
+          let now = Instant::now();
+          for peer in self.peers.values_mut() {
+            if peer.connection.poll_timeout().is_some_and(|timeout| timeout <= now) {
+              peer.connection.handle_timeout(now);
+            }
+          }
+        }
+      }
+    }
+  }
+
+  fn on_datagram(&mut self, datagram: &[u8], from: SocketAddr) {
+    let Ok(packet) = Packet::decode(datagram) else {
+      return;
+    };
+    let now = Instant::now();
+    match self.peers.get_mut(&from) {
+      Some(peer) if peer.connection.id() == packet.id() => {
+        peer.connection.handle_datagram(packet, now);
+      }
+      Some(peer) => {
+        // A new connection from the address of an accepted one: the peer restarted
+        let Packet::Connect { id } = packet else {
+          return;
+        };
+        if peer.connecting.is_some() {
+          return;
+        }
+        peer.shared.lock().unwrap().closed = Some(DisconnectReason::ClosedByPeer);
+        if let Some(events) = &peer.events {
+          let _ = events.send(Event::Disconnected(DisconnectReason::ClosedByPeer));
+        }
+        self.peers.remove(&from);
+        self.accept(from, id, now);
+      }
+      None => {
+        if let Packet::Connect { id } = packet {
+          self.accept(from, id, now);
+        }
+      }
+    }
+  }
+
+  fn accept(&mut self, addr: SocketAddr, id: u32, now: Instant) {
+    let Ok(permit) = self.incoming.try_reserve() else {
+      return;
+    };
+    let Some(commands) = self.commands.upgrade() else {
+      return;
+    };
+    let mut peer = Peer::new(connection::Connection::server(id, self.config.clone(), now));
+    permit.send(peer.handle(addr, &self.config, commands));
+    self.peers.insert(addr, peer);
+  }
+
+  fn on_command(&mut self, command: Command) {
+    let now = Instant::now();
+    match command {
+      Command::Connect { addr, reply } => {
+        if self.peers.contains_key(&addr) {
+          let _ = reply.send(Err(Error::AlreadyConnected(addr)));
+          return;
+        }
+        let connection = connection::Connection::client(rand::random(), self.config.clone(), now);
+        let mut peer = Peer::new(connection);
+        peer.connecting = Some(reply);
+        self.peers.insert(addr, peer);
+      }
+      Command::Send {
+        addr,
+        id,
+        channel,
+        data,
+      } => {
+        if let Some(peer) = self.peer(addr, id) {
+          let _ = peer.connection.send(channel, &data);
+        }
+      }
+      Command::Close { addr, id } => {
+        if let Some(peer) = self.peer(addr, id) {
+          peer.connection.close(now);
+          peer.events = None;
+        }
+      }
+    }
+  }
+
+  fn peer(&mut self, addr: SocketAddr, id: u32) -> Option<&mut Peer> {
+    self
+      .peers
+      .get_mut(&addr)
+      .filter(|peer| peer.connection.id() == id)
+  }
+
+  /// Sends what the connections have to send, hands their events out and drops the closed ones.
+  async fn flush(&mut self) {
+    let now = Instant::now();
+    for (&addr, peer) in &mut self.peers {
+      while let Some(datagram) = peer.connection.poll_transmit(now) {
+        let _ = self.transport.send_to(&datagram, addr).await;
+      }
+      peer.shared.lock().unwrap().stats = peer.connection.stats();
+      while let Some(event) = peer.connection.poll_event() {
+        match event {
+          Event::Connected => {
+            let Some(reply) = peer.connecting.take() else {
+              continue;
+            };
+            match self.commands.upgrade() {
+              Some(commands) => {
+                let _ = reply.send(Ok(peer.handle(addr, &self.config, commands)));
+              }
+              None => peer.connection.close(now),
+            }
+          }
+          Event::Message(..) => {
+            if let Some(events) = &peer.events {
+              let _ = events.send(event);
+            }
+          }
+          Event::Disconnected(reason) => {
+            peer.shared.lock().unwrap().closed = Some(reason);
+            if let Some(reply) = peer.connecting.take() {
+              let _ = reply.send(Err(Error::ConnectTimedOut(addr)));
+            }
+            if let Some(events) = peer.events.take() {
+              let _ = events.send(event);
+            }
+          }
+        }
+      }
+    }
+    self.peers.retain(|_, peer| !peer.connection.is_closed());
+  }
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+  match deadline {
+    Some(deadline) => time::sleep_until(deadline.into()).await,
+    None => future::pending().await,
+  }
+}
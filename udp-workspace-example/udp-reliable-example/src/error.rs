@@ -0,0 +1,52 @@
+use std::{io, net::SocketAddr};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("I/O error: {0}")]
+  Io(#[from] io::Error),
+  #[error("{0} did not accept the connection in time")]
+  ConnectTimedOut(SocketAddr),
+  #[error("already connected to {0}")]
+  AlreadyConnected(SocketAddr),
+  #[error("message of {size} bytes is over the {max} byte limit")]
+  TooLarge { size: usize, max: usize },
+  #[error("connection closed: {0}")]
+  Disconnected(DisconnectReason),
+  #[error("endpoint shut down")]
+  EndpointClosed,
+}
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+  #[error("closed locally")]
+  Closed,
+  #[error("closed by the peer")]
+  ClosedByPeer,
+  #[error("nothing heard from the peer within the idle timeout")]
+  TimedOut,
+}
+
+/// Why a datagram was dropped as malformed.
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+  #[error("datagram ends early")]
+  Truncated,
+  #[error("not one of our datagrams")]
+  BadMagic,
+  #[error("unsupported protocol version {0}")]
+  UnsupportedVersion(u8),
+  #[error("unknown packet kind {0}")]
+  UnknownPacket(u8),
+  #[error("unknown frame type {0}")]
+  UnknownFrame(u8),
+  #[error("unknown channel {0}")]
+  UnknownChannel(u8),
+  #[error("ack range ends before it starts")]
+  InvalidAck,
+  #[error("fragment index out of range")]
+  InvalidFragment,
+  #[error("bytes left after the last frame")]
+  TrailingBytes,
+}
@@ -0,0 +1,25 @@
+//! Reliable messaging over UDP: a connection handshake, packet numbers with selective acks,
+//! retransmission on timeouts estimated from the round-trip time, NewReno congestion control,
+//! fragmentation of messages larger than a datagram and heartbeats that detect dead peers.
+//!
+//! Messages go on one of three [`Channel`]s: unreliable, reliable and unordered, or reliable and
+//! ordered.
+
+mod channel;
+mod config;
+mod congestion;
+mod connection;
+mod endpoint;
+mod error;
+pub mod lossy;
+pub mod packet;
+mod ranges;
+mod rtt;
+mod transport;
+
+pub use channel::Channel;
+pub use config::Config;
+pub use connection::Stats;
+pub use endpoint::{Connection, Endpoint};
+pub use error::{DecodeError, DisconnectReason, Error};
+pub use transport::Transport;
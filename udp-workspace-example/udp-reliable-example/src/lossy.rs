@@ -0,0 +1,101 @@
+//! A UDP socket that mistreats what it sends, for testing the protocol over loopback.
+
+use std::{
+  io,
+  net::SocketAddr,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+  },
+  time::Duration,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tokio::net::{ToSocketAddrs, UdpSocket};
+
+use crate::transport::Transport;
+
+/// What happens to outgoing datagrams, each independently.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Impairments {
+  /// Probability of a datagram being dropped.
+  pub loss: f64,
+  /// Probability of a datagram being sent twice.
+  pub duplicate: f64,
+  /// Datagrams are held back up to this long, which reorders them.
+  pub max_delay: Duration,
+}
+
+pub struct LossySocket {
+  socket: Arc<UdpSocket>,
+  state: Mutex<(Impairments, StdRng)>,
+  dropped: AtomicU64,
+}
+
+impl LossySocket {
+  /// `seed` makes the choices of which datagrams to drop, duplicate and delay repeatable.
+  pub async fn bind(
+    addr: impl ToSocketAddrs,
+    impairments: Impairments,
+    seed: u64,
+  ) -> io::Result<LossySocket> {
+    Ok(LossySocket {
+      socket: Arc::new(UdpSocket::bind(addr).await?),
+      state: Mutex::new((impairments, StdRng::seed_from_u64(seed))),
+      dropped: AtomicU64::new(0),
+    })
+  }
+
+  /// Changes the impairments of the datagrams sent from now on.
+  pub fn set(&self, impairments: Impairments) {
+    self.state.lock().unwrap().0 = impairments;
+  }
+
+  /// Datagrams dropped so far.
+  pub fn dropped(&self) -> u64 {
+    self.dropped.load(Ordering::Relaxed)
+  }
+
+  /// The delays of the copies of a datagram to send, none when it is dropped.
+  fn copies(&self) -> Vec<Duration> {
+    let (impairments, rng) = &mut *self.state.lock().unwrap();
+    if rng.gen_bool(impairments.loss) {
+      self.dropped.fetch_add(1, Ordering::Relaxed);
+      return vec![];
+    }
+    let copies = if rng.gen_bool(impairments.duplicate) {
+      2
+    } else {
+      1
+    };
+    (0 .. copies)
+      .map(|_| impairments.max_delay.mul_f64(rng.gen()))
+      .collect()
+  }
+}
+
+impl Transport for LossySocket {
+  async fn send_to(&self, datagram: &[u8], target: SocketAddr) -> io::Result<()> {
+    for delay in self.copies() {
+      if delay.is_zero() {
+        self.socket.send_to(datagram, target).await?;
+        continue;
+      }
+      let socket = self.socket.clone();
+      let datagram = datagram.to_vec();
+      tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+        let _ = socket.send_to(&datagram, target).await;
+      });
+    }
+    Ok(())
+  }
+
+  async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    self.socket.recv_from(buf).await
+  }
+
+  fn local_addr(&self) -> io::Result<SocketAddr> {
+    self.socket.local_addr()
+  }
+}
@@ -0,0 +1,103 @@
+// cargo run -p udp-reliable-example -- server 127.0.0.1:8888
+// cargo run -p udp-reliable-example -- client 127.0.0.1:8888 --loss 0.2 --messages 200
+use std::{error::Error, net::SocketAddr, time::Duration};
+
+use clap::{Parser, Subcommand};
+use udp_reliable_example::{
+  lossy::{Impairments, LossySocket},
+  Channel, Config, Connection, Endpoint,
+};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Echoes every message back on the channel it came in on
+  Server { addr: SocketAddr },
+  /// Sends numbered messages and checks the echoes come back complete and in order
+  Client {
+    addr: SocketAddr,
+    #[arg(long, default_value_t = 100)]
+    messages: u32,
+    /// Bytes per message, fragmented above a datagram
+    #[arg(long, default_value_t = 3000)]
+    size: usize,
+    /// Share of the datagrams sent to drop, a quarter as many are duplicated
+    #[arg(long, default_value_t = 0.0)]
+    loss: f64,
+  },
+}
+
+async fn echo(mut connection: Connection) {
+  let addr = connection.remote_addr();
+  println!("{} connected", addr);
+  let reason = loop {
+    match connection.recv().await {
+      Ok((channel, message)) => {
+        if connection.send(channel, message).is_err() {
+          break None;
+        }
+      }
+      Err(reason) => break Some(reason),
+    }
+  };
+  println!("{} gone: {:?}, {:?}", addr, reason, connection.stats());
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+  match Args::parse().command {
+    Command::Server { addr } => {
+      let mut endpoint = Endpoint::bind(addr, Config::default()).await?;
+      println!("Listening on {}", endpoint.local_addr());
+      while let Some(connection) = endpoint.accept().await {
+        tokio::spawn(echo(connection));
+      }
+    }
+    Command::Client {
+      addr,
+      messages,
+      size,
+      loss,
+    } => {
+      let impairments = Impairments {
+        loss,
+        duplicate: loss / 4.0,
+        max_delay: Duration::from_millis(20),
+      };
+      let bind = if addr.is_ipv4() {
+        "0.0.0.0:0"
+      } else {
+        "[::]:0"
+      };
+      let socket = LossySocket::bind(bind, impairments, rand::random()).await?;
+      let endpoint = Endpoint::new(socket, Config::default())?;
+      let mut connection = endpoint.connect(addr).await?;
+      println!("Connected to {}", addr);
+
+      for i in 0 .. messages {
+        let mut message = vec![i as u8; size.max(4)];
+        message[.. 4].copy_from_slice(&i.to_be_bytes());
+        connection.send(Channel::ReliableOrdered, message)?;
+      }
+      for i in 0 .. messages {
+        let (_, echo) = connection.recv().await?;
+        let number = u32::from_be_bytes(echo[.. 4].try_into()?);
+        if number != i || echo.len() != size.max(4) {
+          return Err(format!("expected message {}, got {}", i, number).into());
+        }
+      }
+      println!("{} messages echoed: {:#?}", messages, connection.stats());
+      drop(connection);
+      endpoint.shutdown().await;
+      // Datagrams the shim holds back are sent by tasks of their own
+      tokio::time::sleep(impairments.max_delay).await;
+    }
+  }
+  Ok(())
+}
@@ -0,0 +1,360 @@
+//! Wire format. Every datagram starts with the same header, all integers big-endian:
+//!
+//! ```text
+//! magic "RU" | version u8 | kind u8 | connection id u32
+//! ```
+//!
+//! Data packets go on with a packet number and frames:
+//!
+//! ```text
+//! packet number u32 | frame*
+//!
+//! ack:     0x01 | delay µs u32 | range count u8 | (first u32 | last u32)*, newest first
+//! message: 0x02 | channel u8 | sequence u32 | index u16 | count u16 | length u16 | bytes
+//! ping:    0x03
+//! ```
+//!
+//! Packet numbers count every data packet sent, retransmissions included: a lost message is sent
+//! again in a new packet, and acks refer to packets, never to messages.
+
+use std::time::Duration;
+
+use crate::{channel::Channel, error::DecodeError};
+
+/// Largest datagram sent, small enough to get through any path without IP fragmentation.
+pub const MAX_DATAGRAM: usize = 1200;
+/// Message bytes per fragment. A full fragment, the largest ack and the headers fit in
+/// [`MAX_DATAGRAM`].
+pub const FRAGMENT_SIZE: usize = 1024;
+/// Ack ranges per ack frame, the oldest are dropped beyond this.
+pub const MAX_ACK_RANGES: usize = 16;
+
+const MAGIC: [u8; 2] = *b"RU";
+const VERSION: u8 = 1;
+pub(crate) const DATA_HEADER: usize = 12;
+
+const CONNECT: u8 = 1;
+const ACCEPT: u8 = 2;
+const DATA: u8 = 3;
+const DISCONNECT: u8 = 4;
+
+const ACK: u8 = 1;
+const MESSAGE: u8 = 2;
+const PING: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+  /// Opens a connection, with an id the client picked. Repeated until accepted.
+  Connect {
+    id: u32,
+  },
+  /// The server's answer to [`Packet::Connect`].
+  Accept {
+    id: u32,
+  },
+  Data {
+    id: u32,
+    number: u32,
+    frames: Vec<Frame>,
+  },
+  Disconnect {
+    id: u32,
+  },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+  Ack(Ack),
+  Message(Fragment),
+  /// Asks for an ack, keeping the connection alive when there is nothing else to send.
+  Ping,
+}
+
+/// Selective ack: the packet numbers received, as inclusive ranges.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ack {
+  /// How long the largest acked packet waited for this ack, taken out of RTT samples.
+  pub delay: Duration,
+  /// Newest first.
+  pub ranges: Vec<(u32, u32)>,
+}
+
+/// A message, or a piece of one larger than [`FRAGMENT_SIZE`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fragment {
+  pub channel: Channel,
+  /// Counts messages per channel.
+  pub sequence: u32,
+  pub index: u16,
+  pub count: u16,
+  pub data: Vec<u8>,
+}
+
+impl Packet {
+  pub fn id(&self) -> u32 {
+    match *self {
+      Packet::Connect { id }
+      | Packet::Accept { id }
+      | Packet::Data { id, .. }
+      | Packet::Disconnect { id } => id,
+    }
+  }
+
+  pub fn encode(&self) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(MAX_DATAGRAM);
+    buf.extend_from_slice(&MAGIC);
+    buf.push(VERSION);
+    buf.push(match self {
+      Packet::Connect { .. } => CONNECT,
+      Packet::Accept { .. } => ACCEPT,
+      Packet::Data { .. } => DATA,
+      Packet::Disconnect { .. } => DISCONNECT,
+    });
+    buf.extend_from_slice(&self.id().to_be_bytes());
+    if let Packet::Data { number, frames, .. } = self {
+      buf.extend_from_slice(&number.to_be_bytes());
+      for frame in frames {
+        frame.encode(&mut buf);
+      }
+    }
+    buf
+  }
+
+  pub fn decode(datagram: &[u8]) -> Result<Packet, DecodeError> {
+    let mut reader = Reader(datagram);
+    if reader.take(2)? != MAGIC {
+      return Err(DecodeError::BadMagic);
+    }
+    let version = reader.u8()?;
+    if version != VERSION {
+      return Err(DecodeError::UnsupportedVersion(version));
+    }
+    let kind = reader.u8()?;
+    let id = reader.u32()?;
+    let packet = match kind {
+      CONNECT => Packet::Connect { id },
+      ACCEPT => Packet::Accept { id },
+      DISCONNECT => Packet::Disconnect { id },
+      DATA => {
+        let number = reader.u32()?;
+        let mut frames = vec![];
+        while !reader.0.is_empty() {
+          frames.push(Frame::decode(&mut reader)?);
+        }
+        Packet::Data { id, number, frames }
+      }
+      other => return Err(DecodeError::UnknownPacket(other)),
+    };
+    if !reader.0.is_empty() {
+      return Err(DecodeError::TrailingBytes);
+    }
+    Ok(packet)
+  }
+}
+
+impl Frame {
+  pub fn encoded_len(&self) -> usize {
+    match self {
+      Frame::Ack(ack) => 6 + 8 * ack.ranges.len(),
+      Frame::Message(fragment) => 12 + fragment.data.len(),
+      Frame::Ping => 1,
+    }
+  }
+
+  /// Whether the peer has to ack the packet carrying this frame.
+  pub fn is_ack_eliciting(&self) -> bool {
+    !matches!(self, Frame::Ack(_))
+  }
+
+  fn encode(&self, buf: &mut Vec<u8>) {
+    match self {
+      Frame::Ack(ack) => {
+        buf.push(ACK);
+        let delay = ack.delay.as_micros().min(u32::MAX as u128) as u32;
+        buf.extend_from_slice(&delay.to_be_bytes());
+        buf.push(ack.ranges.len() as u8);
+        for (first, last) in &ack.ranges {
+          buf.extend_from_slice(&first.to_be_bytes());
+          buf.extend_from_slice(&last.to_be_bytes());
+        }
+      }
+      Frame::Message(fragment) => {
+        buf.push(MESSAGE);
+        buf.push(fragment.channel.id());
+        buf.extend_from_slice(&fragment.sequence.to_be_bytes());
+        buf.extend_from_slice(&fragment.index.to_be_bytes());
+        buf.extend_from_slice(&fragment.count.to_be_bytes());
+        buf.extend_from_slice(&(fragment.data.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&fragment.data);
+      }
+      Frame::Ping => buf.push(PING),
+    }
+  }
+
+  fn decode(reader: &mut Reader) -> Result<Frame, DecodeError> {
+    match reader.u8()? {
+      ACK => {
+        let delay = Duration::from_micros(reader.u32()? as u64);
+        let count = reader.u8()?;
+        let mut ranges = Vec::with_capacity(count as usize);
+        for _ in 0 .. count {
+          let (first, last) = (reader.u32()?, reader.u32()?);
+          if first > last {
+            return Err(DecodeError::InvalidAck);
+          }
+          ranges.push((first, last));
+        }
+        Ok(Frame::Ack(Ack { delay, ranges }))
+      }
+      MESSAGE => {
+        let channel = reader.u8()?;
+        let channel = Channel::from_id(channel).ok_or(DecodeError::UnknownChannel(channel))?;
+        let sequence = reader.u32()?;
+        let index = reader.u16()?;
+        let count = reader.u16()?;
+        if index >= count {
+          return Err(DecodeError::InvalidFragment);
+        }
+        let length = reader.u16()? as usize;
+        let data = reader.take(length)?.to_vec();
+        Ok(Frame::Message(Fragment {
+          channel,
+          sequence,
+          index,
+          count,
+          data,
+        }))
+      }
+      PING => Ok(Frame::Ping),
+      other => Err(DecodeError::UnknownFrame(other)),
+    }
+  }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+    if self.0.len() < n {
+      return Err(DecodeError::Truncated);
+    }
+    let (head, rest) = self.0.split_at(n);
+    self.0 = rest;
+    Ok(head)
+  }
+
+  fn u8(&mut self) -> Result<u8, DecodeError> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u16(&mut self) -> Result<u16, DecodeError> {
+    Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+  }
+
+  fn u32(&mut self) -> Result<u32, DecodeError> {
+    Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn data(frames: Vec<Frame>) -> Packet {
+    Packet::Data {
+      id: 0xdead_beef,
+      number: 7,
+      frames,
+    }
+  }
+
+  #[test]
+  fn packets_round_trip() {
+    let packets = [
+      Packet::Connect { id: 1 },
+      Packet::Accept { id: 2 },
+      Packet::Disconnect { id: 3 },
+      data(vec![]),
+      data(vec![
+        Frame::Ack(Ack {
+          delay: Duration::from_micros(1500),
+          ranges: vec![(10, 12), (4, 4), (0, 2)],
+        }),
+        Frame::Ping,
+        Frame::Message(Fragment {
+          channel: Channel::ReliableOrdered,
+          sequence: 42,
+          index: 1,
+          count: 3,
+          data: b"hello".to_vec(),
+        }),
+      ]),
+    ];
+    for packet in packets {
+      assert_eq!(Packet::decode(&packet.encode()).unwrap(), packet);
+    }
+  }
+
+  #[test]
+  fn the_largest_frames_fit_in_a_datagram() {
+    let packet = data(vec![
+      Frame::Ack(Ack {
+        delay: Duration::ZERO,
+        ranges: vec![(0, 0); MAX_ACK_RANGES],
+      }),
+      Frame::Message(Fragment {
+        channel: Channel::Unreliable,
+        sequence: 0,
+        index: 0,
+        count: 1,
+        data: vec![0; FRAGMENT_SIZE],
+      }),
+    ]);
+    let Packet::Data { frames, .. } = &packet else {
+      unreachable!()
+    };
+    let len = packet.encode().len();
+    assert_eq!(
+      len,
+      DATA_HEADER + frames.iter().map(Frame::encoded_len).sum::<usize>()
+    );
+    assert!(len <= MAX_DATAGRAM);
+  }
+
+  #[test]
+  fn malformed_datagrams_are_rejected() {
+    let valid = data(vec![Frame::Ping]).encode();
+    assert_eq!(Packet::decode(&valid[.. 5]), Err(DecodeError::Truncated));
+    assert_eq!(
+      Packet::decode(b"XX\x01\x01\0\0\0\0"),
+      Err(DecodeError::BadMagic)
+    );
+    assert_eq!(
+      Packet::decode(b"RU\x09\x01\0\0\0\0"),
+      Err(DecodeError::UnsupportedVersion(9))
+    );
+    assert_eq!(
+      Packet::decode(b"RU\x01\x09\0\0\0\0"),
+      Err(DecodeError::UnknownPacket(9))
+    );
+    assert_eq!(
+      Packet::decode(b"RU\x01\x01\0\0\0\0\0"),
+      Err(DecodeError::TrailingBytes)
+    );
+
+    let mut unknown_frame = valid.clone();
+    *unknown_frame.last_mut().unwrap() = 0x7f;
+    assert_eq!(
+      Packet::decode(&unknown_frame),
+      Err(DecodeError::UnknownFrame(0x7f))
+    );
+
+    let mut bad_fragment = data(vec![]).encode();
+    // Fragment 2 of 2
+    bad_fragment.extend_from_slice(&[MESSAGE, 0, 0, 0, 0, 0, 0, 2, 0, 2, 0, 0]);
+    assert_eq!(
+      Packet::decode(&bad_fragment),
+      Err(DecodeError::InvalidFragment)
+    );
+  }
+}
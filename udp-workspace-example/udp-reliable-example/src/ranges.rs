@@ -0,0 +1,77 @@
+use crate::packet::MAX_ACK_RANGES;
+
+/// Packet numbers received, as disjoint inclusive ranges in ascending order. Only the newest
+/// [`MAX_ACK_RANGES`] ranges are kept, which is all an ack frame carries.
+#[derive(Debug, Default)]
+pub(crate) struct RangeSet {
+  ranges: Vec<(u32, u32)>,
+}
+
+impl RangeSet {
+  /// Records `number`, returning false for one already received. Numbers older than every range
+  /// kept count as received: they are duplicates or stragglers whose content went out again.
+  pub fn insert(&mut self, number: u32) -> bool {
+    if self.ranges.len() == MAX_ACK_RANGES && number < self.ranges[0].0 {
+      return false;
+    }
+    let i = self.ranges.partition_point(|&(_, last)| last < number);
+    if i < self.ranges.len() && self.ranges[i].0 <= number {
+      return false;
+    }
+    let joins_previous = i > 0 && self.ranges[i - 1].1 + 1 == number;
+    let joins_next = i < self.ranges.len() && number + 1 == self.ranges[i].0;
+    match (joins_previous, joins_next) {
+      (true, true) => {
+        self.ranges[i - 1].1 = self.ranges[i].1;
+        self.ranges.remove(i);
+      }
+      (true, false) => self.ranges[i - 1].1 = number,
+      (false, true) => self.ranges[i].0 = number,
+      (false, false) => self.ranges.insert(i, (number, number)),
+    }
+    if self.ranges.len() > MAX_ACK_RANGES {
+      self.ranges.remove(0);
+    }
+    true
+  }
+
+  pub fn largest(&self) -> Option<u32> {
+    self.ranges.last().map(|&(_, last)| last)
+  }
+
+  /// The ranges for an ack frame, newest first.
+  pub fn newest_first(&self) -> Vec<(u32, u32)> {
+    self.ranges.iter().rev().copied().collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn adjacent_numbers_merge_into_ranges() {
+    let mut set = RangeSet::default();
+    for number in [5, 1, 3, 2, 9, 4] {
+      assert!(set.insert(number));
+    }
+    assert!(!set.insert(3));
+    assert_eq!(set.newest_first(), vec![(9, 9), (1, 5)]);
+    assert_eq!(set.largest(), Some(9));
+  }
+
+  #[test]
+  fn only_the_newest_ranges_are_kept() {
+    let mut set = RangeSet::default();
+    for number in (0 .. 2 * MAX_ACK_RANGES as u32).map(|n| n * 2) {
+      set.insert(number);
+    }
+    let ranges = set.newest_first();
+    assert_eq!(ranges.len(), MAX_ACK_RANGES);
+    assert_eq!(ranges[MAX_ACK_RANGES - 1], (32, 32));
+    assert!(!set.insert(1));
+    // Filling a gap merges two ranges
+    assert!(set.insert(33));
+    assert_eq!(set.newest_first().last(), Some(&(32, 34)));
+  }
+}
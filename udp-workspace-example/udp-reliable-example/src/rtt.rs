@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use crate::config::Config;
+
+/// Round-trip time and retransmission timeout, per RFC 6298.
+#[derive(Debug)]
+pub(crate) struct RttEstimator {
+  smoothed: Option<Duration>,
+  variance: Duration,
+  latest: Duration,
+  initial_rto: Duration,
+  min_rto: Duration,
+  max_rto: Duration,
+}
+
+/// Clock granularity, the floor of the variance term.
+const GRANULARITY: Duration = Duration::from_millis(1);
+
+impl RttEstimator {
+  pub fn new(config: &Config) -> RttEstimator {
+    RttEstimator {
+      smoothed: None,
+      variance: Duration::ZERO,
+      latest: Duration::ZERO,
+      initial_rto: config.initial_rto,
+      min_rto: config.min_rto,
+      max_rto: config.max_rto,
+    }
+  }
+
+  pub fn update(&mut self, sample: Duration) {
+    self.latest = sample;
+    match self.smoothed {
+      None => {
+        self.smoothed = Some(sample);
+        self.variance = sample / 2;
+      }
+      Some(smoothed) => {
+        let deviation = smoothed.abs_diff(sample);
+        self.variance = (self.variance * 3 + deviation) / 4;
+        self.smoothed = Some((smoothed * 7 + sample) / 8);
+      }
+    }
+  }
+
+  pub fn smoothed(&self) -> Option<Duration> {
+    self.smoothed
+  }
+
+  /// The larger of the smoothed and latest RTT, what loss detection measures lateness against.
+  pub fn conservative(&self) -> Duration {
+    self
+      .smoothed
+      .map_or(self.initial_rto, |smoothed| smoothed.max(self.latest))
+  }
+
+  pub fn rto(&self) -> Duration {
+    match self.smoothed {
+      None => self.initial_rto,
+      Some(smoothed) => {
+        (smoothed + (self.variance * 4).max(GRANULARITY)).clamp(self.min_rto, self.max_rto)
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn estimator() -> RttEstimator {
+    RttEstimator::new(&Config {
+      min_rto: Duration::from_millis(10),
+      ..Config::default()
+    })
+  }
+
+  #[test]
+  fn the_first_sample_sets_the_variance_to_half() {
+    let mut rtt = estimator();
+    assert_eq!(rtt.rto(), Config::default().initial_rto);
+    rtt.update(Duration::from_millis(100));
+    assert_eq!(rtt.smoothed(), Some(Duration::from_millis(100)));
+    assert_eq!(rtt.rto(), Duration::from_millis(300));
+  }
+
+  #[test]
+  fn steady_samples_shrink_the_timeout() {
+    let mut rtt = estimator();
+    rtt.update(Duration::from_millis(100));
+    rtt.update(Duration::from_millis(200));
+    // variance 3/4 * 50 + 1/4 * 100, smoothed 7/8 * 100 + 1/8 * 200
+    assert_eq!(rtt.smoothed(), Some(Duration::from_micros(112_500)));
+    assert_eq!(rtt.rto(), Duration::from_micros(112_500 + 4 * 62_500));
+    for _ in 0 .. 50 {
+      rtt.update(Duration::from_millis(20));
+    }
+    assert!(rtt.rto() < Duration::from_millis(25));
+    assert!(rtt.rto() >= Duration::from_millis(20));
+
+    rtt.update(Duration::from_micros(10));
+    assert_eq!(rtt.conservative(), rtt.smoothed().unwrap());
+  }
+}
@@ -0,0 +1,55 @@
+use std::{future::Future, io, net::SocketAddr, sync::Arc};
+
+use tokio::net::UdpSocket;
+
+/// What an [`Endpoint`](crate::Endpoint) sends and receives datagrams through: a UDP socket, or a
+/// wrapper around one such as [`LossySocket`](crate::lossy::LossySocket).
+pub trait Transport: Send + Sync + 'static {
+  fn send_to(
+    &self,
+    datagram: &[u8],
+    target: SocketAddr,
+  ) -> impl Future<Output = io::Result<()>> + Send;
+
+  fn recv_from(
+    &self,
+    buf: &mut [u8],
+  ) -> impl Future<Output = io::Result<(usize, SocketAddr)>> + Send;
+
+  fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl Transport for UdpSocket {
+  async fn send_to(&self, datagram: &[u8], target: SocketAddr) -> io::Result<()> {
+    UdpSocket::send_to(self, datagram, target).await.map(|_| ())
+  }
+
+  async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    UdpSocket::recv_from(self, buf).await
+  }
+
+  fn local_addr(&self) -> io::Result<SocketAddr> {
+    UdpSocket::local_addr(self)
+  }
+}
+
+impl<T: Transport> Transport for Arc<T> {
+  fn send_to(
+    &self,
+    datagram: &[u8],
+    target: SocketAddr,
+  ) -> impl Future<Output = io::Result<()>> + Send {
+    T::send_to(self, datagram, target)
+  }
+
+  fn recv_from(
+    &self,
+    buf: &mut [u8],
+  ) -> impl Future<Output = io::Result<(usize, SocketAddr)>> + Send {
+    T::recv_from(self, buf)
+  }
+
+  fn local_addr(&self) -> io::Result<SocketAddr> {
+    T::local_addr(self)
+  }
+}
@@ -0,0 +1,244 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use tokio::{net::UdpSocket, time::timeout};
+use udp_reliable_example::{
+  lossy::{Impairments, LossySocket},
+  Channel, Config, Connection, DisconnectReason, Endpoint, Error,
+};
+
+const BAD_NETWORK: Impairments = Impairments {
+  loss: 0.1,
+  duplicate: 0.05,
+  max_delay: Duration::from_millis(5),
+};
+
+struct Pair {
+  client_socket: Arc<LossySocket>,
+  server_socket: Arc<LossySocket>,
+  client: Connection,
+  server: Connection,
+}
+
+async fn pair(impairments: Impairments, config: Config) -> Pair {
+  let server_socket = Arc::new(
+    LossySocket::bind("127.0.0.1:0", impairments, 1)
+      .await
+      .unwrap(),
+  );
+  let client_socket = Arc::new(
+    LossySocket::bind("127.0.0.1:0", impairments, 2)
+      .await
+      .unwrap(),
+  );
+  let mut server_endpoint = Endpoint::new(server_socket.clone(), config.clone()).unwrap();
+  let client_endpoint = Endpoint::new(client_socket.clone(), config).unwrap();
+  let client = client_endpoint
+    .connect(server_endpoint.local_addr())
+    .await
+    .unwrap();
+  let server = server_endpoint.accept().await.unwrap();
+  assert_eq!(server.remote_addr(), client_socket_addr(&client_socket));
+  Pair {
+    client_socket,
+    server_socket,
+    client,
+    server,
+  }
+}
+
+fn client_socket_addr(socket: &LossySocket) -> std::net::SocketAddr {
+  udp_reliable_example::Transport::local_addr(socket).unwrap()
+}
+
+/// Message `i`: its number and filler, from a few bytes to three fragments long.
+fn message(i: u32) -> Vec<u8> {
+  let mut message = vec![i as u8; 4 + (i as usize * 397) % 3000];
+  message[.. 4].copy_from_slice(&i.to_be_bytes());
+  message
+}
+
+fn number(message: &[u8]) -> u32 {
+  u32::from_be_bytes(message[.. 4].try_into().unwrap())
+}
+
+async fn recv(connection: &mut Connection) -> (Channel, Vec<u8>) {
+  timeout(Duration::from_secs(20), connection.recv())
+    .await
+    .expect("no message in time")
+    .unwrap()
+}
+
+#[tokio::test]
+async fn ordered_messages_survive_loss_duplication_and_reordering() {
+  let mut pair = pair(BAD_NETWORK, Config::default()).await;
+  for i in 0 .. 300 {
+    pair
+      .client
+      .send(Channel::ReliableOrdered, message(i))
+      .unwrap();
+  }
+  for i in 0 .. 300 {
+    let (channel, received) = recv(&mut pair.server).await;
+    assert_eq!(channel, Channel::ReliableOrdered);
+    assert_eq!(received, message(i));
+  }
+  assert!(pair.client_socket.dropped() > 0 && pair.server_socket.dropped() > 0);
+  let stats = pair.client.stats();
+  assert!(stats.packets_lost > 0);
+  assert!(stats.rtt.is_some());
+  assert_eq!(pair.server.stats().messages_received, 300);
+}
+
+#[tokio::test]
+async fn unordered_messages_arrive_exactly_once() {
+  let mut pair = pair(BAD_NETWORK, Config::default()).await;
+  for i in 0 .. 300 {
+    pair
+      .client
+      .send(Channel::ReliableUnordered, message(i))
+      .unwrap();
+  }
+  let mut seen = HashSet::new();
+  for _ in 0 .. 300 {
+    let (_, received) = recv(&mut pair.server).await;
+    assert_eq!(received, message(number(&received)));
+    assert!(
+      seen.insert(number(&received)),
+      "{} twice",
+      number(&received)
+    );
+  }
+  assert!(
+    timeout(Duration::from_millis(500), pair.server.recv())
+      .await
+      .is_err(),
+    "a message arrived twice"
+  );
+}
+
+#[tokio::test]
+async fn unreliable_messages_may_be_lost_but_are_not_duplicated() {
+  let impairments = Impairments {
+    loss: 0.3,
+    duplicate: 0.3,
+    max_delay: Duration::from_millis(10),
+  };
+  let mut pair = pair(impairments, Config::default()).await;
+  for i in 0 .. 200 {
+    pair.client.send(Channel::Unreliable, message(i)).unwrap();
+  }
+  let mut seen = HashSet::new();
+  while let Ok(received) = timeout(Duration::from_millis(500), pair.server.recv()).await {
+    let (channel, received) = received.unwrap();
+    assert_eq!(channel, Channel::Unreliable);
+    assert_eq!(received, message(number(&received)));
+    assert!(
+      seen.insert(number(&received)),
+      "{} twice",
+      number(&received)
+    );
+  }
+  assert!(
+    !seen.is_empty() && seen.len() < 200,
+    "{} arrived",
+    seen.len()
+  );
+}
+
+#[tokio::test]
+async fn large_messages_are_fragmented_and_reassembled() {
+  let mut pair = pair(BAD_NETWORK, Config::default()).await;
+  let large: Vec<u8> = (0 .. 200_000u32).map(|i| (i % 251) as u8).collect();
+  pair
+    .client
+    .send(Channel::ReliableOrdered, large.clone())
+    .unwrap();
+  let (_, received) = recv(&mut pair.server).await;
+  assert!(received == large);
+
+  // And back
+  pair
+    .server
+    .send(Channel::ReliableUnordered, received)
+    .unwrap();
+  let (_, echoed) = recv(&mut pair.client).await;
+  assert!(echoed == large);
+
+  assert!(matches!(
+    pair.client.send(Channel::Unreliable, vec![0; 2 << 20]),
+    Err(Error::TooLarge { .. })
+  ));
+}
+
+#[tokio::test]
+async fn heartbeats_keep_idle_connections_alive_until_the_peer_goes_silent() {
+  let config = Config {
+    heartbeat_interval: Duration::from_millis(100),
+    idle_timeout: Duration::from_millis(500),
+    ..Config::default()
+  };
+  let mut pair = pair(Impairments::default(), config).await;
+  tokio::time::sleep(Duration::from_millis(1500)).await;
+  pair
+    .client
+    .send(Channel::ReliableOrdered, "still there?")
+    .unwrap();
+  assert_eq!(recv(&mut pair.server).await.1, b"still there?");
+  assert!(pair.server.stats().packets_received > 5);
+
+  let blackhole = Impairments {
+    loss: 1.0,
+    ..Impairments::default()
+  };
+  pair.client_socket.set(blackhole);
+  pair.server_socket.set(blackhole);
+  for connection in [&mut pair.client, &mut pair.server] {
+    let result = timeout(Duration::from_secs(2), connection.recv()).await;
+    assert_eq!(result.unwrap(), Err(DisconnectReason::TimedOut));
+  }
+  assert!(matches!(
+    pair.client.send(Channel::ReliableOrdered, "hello?"),
+    Err(Error::Disconnected(DisconnectReason::TimedOut))
+  ));
+}
+
+#[tokio::test]
+async fn the_handshake_is_retried_until_the_connect_timeout() {
+  // A third of the datagrams dropped, the handshake still completes
+  let impairments = Impairments {
+    loss: 0.3,
+    ..Impairments::default()
+  };
+  pair(impairments, Config::default()).await;
+
+  let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+  let config = Config {
+    connect_timeout: Duration::from_millis(500),
+    ..Config::default()
+  };
+  let endpoint = Endpoint::bind("127.0.0.1:0", config).await.unwrap();
+  let addr = silent.local_addr().unwrap();
+  assert!(matches!(
+    endpoint.connect(addr).await,
+    Err(Error::ConnectTimedOut(timed_out)) if timed_out == addr
+  ));
+  timeout(Duration::from_secs(1), endpoint.shutdown())
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn dropped_connections_deliver_their_messages_then_disconnect() {
+  let Pair {
+    client, mut server, ..
+  } = pair(BAD_NETWORK, Config::default()).await;
+  for i in 0 .. 20 {
+    client.send(Channel::ReliableOrdered, message(i)).unwrap();
+  }
+  drop(client);
+  for i in 0 .. 20 {
+    assert_eq!(recv(&mut server).await.1, message(i));
+  }
+  let result = timeout(Duration::from_secs(5), server.recv()).await;
+  assert_eq!(result.unwrap(), Err(DisconnectReason::ClosedByPeer));
+}
@@ -0,0 +1,53 @@
+//! The plugin the runtime's tests load.
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, vec::Vec};
+
+use guest_sdk::{export, kv, log, text};
+
+export! {
+  greet => greet,
+  count => count,
+  reverse => reverse,
+  forget => forget,
+  spin => spin,
+  fail => fail,
+}
+
+fn greet(input: &[u8]) -> Vec<u8> {
+  let name = text(input);
+  log::info(&format!("greeting {}", name));
+  format!("Hello, {}!", name).into_bytes()
+}
+
+/// Increments the counter named by the input.
+fn count(key: &[u8]) -> Vec<u8> {
+  let count = kv::get(key)
+    .and_then(|value| text(&value).parse::<u64>().ok())
+    .unwrap_or(0)
+    + 1;
+  let count = format!("{}", count);
+  kv::set(key, count.as_bytes());
+  count.into_bytes()
+}
+
+fn reverse(input: &[u8]) -> Vec<u8> {
+  input.iter().rev().copied().collect()
+}
+
+/// Deletes the counter named by the input.
+fn forget(key: &[u8]) -> Vec<u8> {
+  format!("{}", kv::delete(key)).into_bytes()
+}
+
+fn spin(_: &[u8]) -> Vec<u8> {
+  loop {
+    core::hint::black_box(());
+  }
+}
+
+fn fail(input: &[u8]) -> Vec<u8> {
+  panic!("asked to fail: {}", text(input))
+}
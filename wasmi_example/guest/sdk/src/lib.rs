@@ -0,0 +1,154 @@
+//! Writing plugins for the `wasmi_example` runtime in Rust.
+//!
+//! A plugin exports functions from bytes to bytes with [`export!`] and reaches the host through
+//! [`log`] and [`kv`]. The host passes input by calling the exported `alloc`, copying into the
+//! returned buffer and handing its ownership to the export, which returns its output as
+//! `ptr << 32 | len`; the host frees the output with `dealloc` once copied out.
+#![no_std]
+
+extern crate alloc;
+
+#[cfg(target_arch = "wasm32")]
+#[global_allocator]
+static ALLOCATOR: dlmalloc::GlobalDlmalloc = dlmalloc::GlobalDlmalloc;
+
+/// Logs the panic message before trapping, so the host can tell why.
+#[cfg(target_arch = "wasm32")]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+  log::error(&alloc::format!("{}", info));
+  core::arch::wasm32::unreachable()
+}
+
+mod host {
+  #[link(wasm_import_module = "env")]
+  extern "C" {
+    pub fn log(level: i32, ptr: *const u8, len: usize);
+    /// The value's length, or -1 when missing. Copies the value if it fits in `out_cap`.
+    pub fn kv_get(key_ptr: *const u8, key_len: usize, out_ptr: *mut u8, out_cap: usize) -> i32;
+    pub fn kv_set(key_ptr: *const u8, key_len: usize, value_ptr: *const u8, value_len: usize);
+    /// 1 if the key existed.
+    pub fn kv_delete(key_ptr: *const u8, key_len: usize) -> i32;
+  }
+}
+
+pub mod log {
+  #[derive(Debug, Clone, Copy)]
+  #[repr(i32)]
+  pub enum Level {
+    Debug = 0,
+    Info = 1,
+    Warn = 2,
+    Error = 3,
+  }
+
+  pub fn log(level: Level, message: &str) {
+    unsafe { super::host::log(level as i32, message.as_ptr(), message.len()) }
+  }
+
+  pub fn debug(message: &str) {
+    log(Level::Debug, message)
+  }
+
+  pub fn info(message: &str) {
+    log(Level::Info, message)
+  }
+
+  pub fn warn(message: &str) {
+    log(Level::Warn, message)
+  }
+
+  pub fn error(message: &str) {
+    log(Level::Error, message)
+  }
+}
+
+/// The host's key-value store. Each plugin has its own, kept across reloads.
+pub mod kv {
+  use alloc::{vec, vec::Vec};
+
+  pub fn get(key: &[u8]) -> Option<Vec<u8>> {
+    let mut value = vec![0; 64];
+    loop {
+      let len =
+        unsafe { super::host::kv_get(key.as_ptr(), key.len(), value.as_mut_ptr(), value.len()) };
+      let len = usize::try_from(len).ok()?;
+      if len <= value.len() {
+        value.truncate(len);
+        return Some(value);
+      }
+      value.resize(len, 0);
+    }
+  }
+
+  pub fn set(key: &[u8], value: &[u8]) {
+    unsafe { super::host::kv_set(key.as_ptr(), key.len(), value.as_ptr(), value.len()) }
+  }
+
+  pub fn delete(key: &[u8]) -> bool {
+    unsafe { super::host::kv_delete(key.as_ptr(), key.len()) == 1 }
+  }
+}
+
+/// Exports `alloc`, `dealloc` and, for each `name => handler`, a function `name` calling
+/// `handler: fn(&[u8]) -> Vec<u8>`.
+#[macro_export]
+macro_rules! export {
+  ($($name:ident => $handler:ident),* $(,)?) => {
+    mod __guest_exports {
+      #[no_mangle]
+      pub extern "C" fn alloc(len: usize) -> *mut u8 {
+        $crate::abi::alloc(len)
+      }
+
+      #[no_mangle]
+      pub unsafe extern "C" fn dealloc(ptr: *mut u8, len: usize) {
+        $crate::abi::dealloc(ptr, len)
+      }
+
+      $(
+        #[no_mangle]
+        pub unsafe extern "C" fn $name(ptr: *mut u8, len: usize) -> u64 {
+          $crate::abi::call(ptr, len, super::$handler)
+        }
+      )*
+    }
+  };
+}
+
+#[doc(hidden)]
+pub mod abi {
+  use alloc::{boxed::Box, vec::Vec};
+  use core::{alloc::Layout, ptr::NonNull};
+
+  pub fn alloc(len: usize) -> *mut u8 {
+    if len == 0 {
+      return NonNull::dangling().as_ptr();
+    }
+    // Same layout as a `Vec<u8>` of capacity `len`, which takes the buffer over
+    unsafe { alloc::alloc::alloc(Layout::array::<u8>(len).unwrap()) }
+  }
+
+  /// # Safety
+  ///
+  /// `ptr` and `len` come from [`alloc`] or an output of [`call`], and are freed once.
+  pub unsafe fn dealloc(ptr: *mut u8, len: usize) {
+    drop(Vec::from_raw_parts(ptr, len, len));
+  }
+
+  /// # Safety
+  ///
+  /// `ptr` and `len` come from [`alloc`], and the input is not used by the host afterwards.
+  pub unsafe fn call(ptr: *mut u8, len: usize, handler: fn(&[u8]) -> Vec<u8>) -> u64 {
+    let input = Vec::from_raw_parts(ptr, len, len);
+    let output: Box<[u8]> = handler(&input).into_boxed_slice();
+    let len = output.len();
+    let ptr = Box::into_raw(output) as *mut u8;
+    (ptr as u64) << 32 | len as u64
+  }
+}
+
+/// Reads the input of an export as text, replacing invalid UTF-8.
+pub fn text(input: &[u8]) -> alloc::borrow::Cow<'_, str> {
+  alloc::string::String::from_utf8_lossy(input)
+}
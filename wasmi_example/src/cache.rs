@@ -0,0 +1,73 @@
+use std::{collections::HashMap, rc::Rc};
+
+use sha2::{Digest, Sha256};
+use wasmi::Module;
+
+use crate::{error::PluginError, metering};
+
+/// Compiled modules by the SHA-256 of their bytes, so loading the same plugin again skips
+/// parsing, metering and validation.
+#[derive(Default)]
+pub struct ModuleCache {
+  modules: HashMap<[u8; 32], Rc<Module>>,
+  hits: u64,
+  misses: u64,
+}
+
+impl ModuleCache {
+  /// Accepts binary modules and the text format.
+  pub fn get_or_compile(&mut self, wasm: &[u8]) -> Result<Rc<Module>, PluginError> {
+    let key: [u8; 32] = Sha256::digest(wasm).into();
+    if let Some(module) = self.modules.get(&key) {
+      self.hits += 1;
+      return Ok(module.clone());
+    }
+    let binary = wat::parse_bytes(wasm).map_err(|e| PluginError::Invalid(e.to_string()))?;
+    let metered = metering::instrument(&binary)?;
+    let module =
+      Rc::new(Module::from_buffer(metered).map_err(|e| PluginError::Invalid(e.to_string()))?);
+    self.misses += 1;
+    self.modules.insert(key, module.clone());
+    Ok(module)
+  }
+
+  pub fn len(&self) -> usize {
+    self.modules.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.modules.is_empty()
+  }
+
+  pub fn hits(&self) -> u64 {
+    self.hits
+  }
+
+  pub fn misses(&self) -> u64 {
+    self.misses
+  }
+
+  pub fn clear(&mut self) {
+    self.modules.clear();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn identical_bytes_compile_once() {
+    let mut cache = ModuleCache::default();
+    let a = br#"(module (func (export "a")))"#;
+    let first = cache.get_or_compile(a).unwrap();
+    let second = cache.get_or_compile(a).unwrap();
+    assert!(Rc::ptr_eq(&first, &second));
+    cache
+      .get_or_compile(br#"(module (func (export "b")))"#)
+      .unwrap();
+    assert_eq!((cache.len(), cache.hits(), cache.misses()), (2, 1, 2));
+    assert!(cache.get_or_compile(b"not wasm").is_err());
+    assert_eq!(cache.len(), 2);
+  }
+}
@@ -0,0 +1,16 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum PluginError {
+  /// The bytes are not a module the runtime accepts.
+  #[error("invalid module: {0}")]
+  Invalid(String),
+  #[error("instantiating {plugin}: {message}")]
+  Instantiation { plugin: String, message: String },
+  #[error("{plugin} has no export {export:?}")]
+  MissingExport { plugin: String, export: String },
+  #[error("{0} ran out of fuel")]
+  OutOfFuel(String),
+  #[error("{plugin} trapped: {message}")]
+  Trap { plugin: String, message: String },
+}
@@ -0,0 +1,198 @@
+//! The functions plugins import from `env`, implemented as wasmi [`Externals`].
+
+use std::{
+  cell::{RefCell, RefMut},
+  collections::{BTreeMap, HashMap},
+  fmt,
+  rc::Rc,
+};
+
+use wasmi::{
+  Externals, FuncInstance, FuncRef, HostError, MemoryRef, ModuleImportResolver, RuntimeArgs,
+  RuntimeValue, Signature, Trap, TrapKind, ValueType,
+};
+
+use crate::metering::GAS_FUNCTION;
+
+const GAS: usize = 0;
+const LOG: usize = 1;
+const KV_GET: usize = 2;
+const KV_SET: usize = 3;
+const KV_DELETE: usize = 4;
+
+/// Longest key or value a plugin may store.
+const MAX_KV_LEN: u32 = 1024 * 1024;
+
+/// Values plugins stored, by plugin name then key.
+pub type KvStore = HashMap<String, BTreeMap<Vec<u8>, Vec<u8>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+  Debug,
+  Info,
+  Warn,
+  Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+  pub level: Level,
+  pub message: String,
+}
+
+/// Resolves the imports of the `env` module.
+pub(crate) struct HostResolver;
+
+impl ModuleImportResolver for HostResolver {
+  fn resolve_func(&self, field: &str, signature: &Signature) -> Result<FuncRef, wasmi::Error> {
+    use ValueType::{I32, I64};
+    let (index, params, result): (usize, &'static [ValueType], _) = match field {
+      GAS_FUNCTION => (GAS, &[I64], None),
+      "log" => (LOG, &[I32, I32, I32], None),
+      "kv_get" => (KV_GET, &[I32, I32, I32, I32], Some(I32)),
+      "kv_set" => (KV_SET, &[I32, I32, I32, I32], None),
+      "kv_delete" => (KV_DELETE, &[I32, I32], Some(I32)),
+      _ => {
+        return Err(wasmi::Error::Instantiation(format!(
+          "no host function env.{}",
+          field
+        )))
+      }
+    };
+    let expected = Signature::new(params, result);
+    if *signature != expected {
+      return Err(wasmi::Error::Instantiation(format!(
+        "env.{} is {:?}, not {:?}",
+        field, expected, signature
+      )));
+    }
+    Ok(FuncInstance::alloc_host(expected, index))
+  }
+}
+
+#[derive(Debug)]
+pub(crate) struct OutOfFuel;
+
+impl fmt::Display for OutOfFuel {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "out of fuel")
+  }
+}
+
+impl HostError for OutOfFuel {}
+
+/// A host function called with arguments it cannot use.
+#[derive(Debug)]
+struct BadCall(String);
+
+impl fmt::Display for BadCall {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl HostError for BadCall {}
+
+fn bad_call(message: String) -> Trap {
+  Trap::new(TrapKind::Host(Box::new(BadCall(message))))
+}
+
+/// What the host functions of one plugin instance work on.
+pub(crate) struct HostState {
+  pub(crate) name: String,
+  pub(crate) kv: Rc<RefCell<KvStore>>,
+  /// The guest's exported memory, once instantiated.
+  pub(crate) memory: Option<MemoryRef>,
+  pub(crate) fuel: u64,
+  pub(crate) logs: Vec<LogRecord>,
+}
+
+impl HostState {
+  fn memory(&self) -> Result<&MemoryRef, Trap> {
+    self
+      .memory
+      .as_ref()
+      .ok_or_else(|| bad_call("the plugin exports no memory".to_string()))
+  }
+
+  /// Reads the `(ptr, len)` buffer starting at argument `first`.
+  fn read(&self, args: &RuntimeArgs<'_>, first: usize) -> Result<Vec<u8>, Trap> {
+    let ptr: u32 = args.nth_checked(first)?;
+    let len: u32 = args.nth_checked(first + 1)?;
+    if len > MAX_KV_LEN {
+      return Err(bad_call(format!("{} byte buffer is too large", len)));
+    }
+    self
+      .memory()?
+      .get(ptr, len as usize)
+      .map_err(|e| bad_call(e.to_string()))
+  }
+
+  fn values(&self) -> RefMut<'_, BTreeMap<Vec<u8>, Vec<u8>>> {
+    RefMut::map(self.kv.borrow_mut(), |kv| {
+      kv.entry(self.name.clone()).or_default()
+    })
+  }
+}
+
+impl Externals for HostState {
+  fn invoke_index(
+    &mut self,
+    index: usize,
+    args: RuntimeArgs<'_>,
+  ) -> Result<Option<RuntimeValue>, Trap> {
+    match index {
+      GAS => {
+        // Negative costs wrap around to huge ones rather than refuelling
+        let cost = args.nth_checked::<i64>(0)? as u64;
+        match self.fuel.checked_sub(cost) {
+          Some(fuel) => self.fuel = fuel,
+          None => {
+            self.fuel = 0;
+            return Err(Trap::new(TrapKind::Host(Box::new(OutOfFuel))));
+          }
+        }
+        Ok(None)
+      }
+      LOG => {
+        let level = match args.nth_checked::<i32>(0)? {
+          0 => Level::Debug,
+          1 => Level::Info,
+          2 => Level::Warn,
+          3 => Level::Error,
+          level => return Err(bad_call(format!("no log level {}", level))),
+        };
+        let message = String::from_utf8_lossy(&self.read(&args, 1)?).into_owned();
+        self.logs.push(LogRecord { level, message });
+        Ok(None)
+      }
+      KV_GET => {
+        let key = self.read(&args, 0)?;
+        let out_ptr: u32 = args.nth_checked(2)?;
+        let out_cap: u32 = args.nth_checked(3)?;
+        let Some(value) = self.values().get(&key).cloned() else {
+          return Ok(Some(RuntimeValue::I32(-1)));
+        };
+        if value.len() <= out_cap as usize {
+          self
+            .memory()?
+            .set(out_ptr, &value)
+            .map_err(|e| bad_call(e.to_string()))?;
+        }
+        Ok(Some(RuntimeValue::I32(value.len() as i32)))
+      }
+      KV_SET => {
+        let key = self.read(&args, 0)?;
+        let value = self.read(&args, 2)?;
+        self.values().insert(key, value);
+        Ok(None)
+      }
+      KV_DELETE => {
+        let key = self.read(&args, 0)?;
+        let removed = self.values().remove(&key).is_some();
+        Ok(Some(RuntimeValue::I32(removed as i32)))
+      }
+      _ => Err(bad_call(format!("no host function {}", index))),
+    }
+  }
+}
@@ -0,0 +1,14 @@
+//! A sandboxed plugin runtime over wasmi. Plugins are metered WebAssembly modules that reach the
+//! host only through the functions of [`host`]; see the `guest` directory for the Rust SDK.
+
+pub mod cache;
+pub mod error;
+pub mod host;
+mod metering;
+pub mod plugin;
+
+pub use cache::ModuleCache;
+pub use error::PluginError;
+pub use host::{KvStore, Level, LogRecord};
+pub use plugin::{Config, Plugin, PluginRuntime};
+pub use wasmi::RuntimeValue;
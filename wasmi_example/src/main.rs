@@ -1,36 +1,42 @@
-extern crate wabt;
-extern crate wasmi;
+// cargo run
+// cargo build --release --target wasm32v1-none --manifest-path guest/Cargo.toml
+// cargo run -- guest/target/wasm32v1-none/release/guest_example.wasm greet world
 
-use wasmi::{ImportsBuilder, ModuleInstance, NopExternals, RuntimeValue};
+use std::{env, error::Error, fs, path::Path};
 
-fn main() {
-  // Parse WAT (WebAssembly Text format) into wasm bytecode.
-  let wasm_binary: Vec<u8> = wabt::wat2wasm(
-    r#"
+use wasmi_example::{Config, PluginRuntime, RuntimeValue};
+
+fn main() -> Result<(), Box<dyn Error>> {
+  let mut runtime = PluginRuntime::new(Config::default());
+  let args: Vec<String> = env::args().skip(1).collect();
+  let [path, export, input @ ..] = args.as_slice() else {
+    // WebAssembly text works too
+    let mut plugin = runtime.load(
+      "inline",
+      br#"
             (module
                 (func (export "test") (result i32)
                     i32.const 1337
                 )
             )
             "#,
-  )
-  .expect("failed to parse wat");
-
-  // Load wasm binary and prepare it for instantiation.
-  let module = wasmi::Module::from_buffer(&wasm_binary).expect("failed to load wasm");
-
-  // Instantiate a module with empty imports and
-  // assert that there is no `start` function.
-  let instance = ModuleInstance::new(&module, &ImportsBuilder::default())
-    .expect("failed to instantiate wasm module")
-    .assert_no_start();
+    )?;
+    assert_eq!(plugin.invoke("test", &[])?, Some(RuntimeValue::I32(1337)));
+    println!("test returned 1337 using {} fuel", plugin.fuel_used());
+    return Ok(());
+  };
 
-  // Finally, invoke the exported function "test" with no parameters
-  // and empty external function executor.
-  assert_eq!(
-    instance
-      .invoke_export("test", &[], &mut NopExternals,)
-      .expect("failed to execute export"),
-    Some(RuntimeValue::I32(1337)),
-  );
+  let name = Path::new(path)
+    .file_stem()
+    .map(|stem| stem.to_string_lossy().into_owned())
+    .unwrap_or_default();
+  let mut plugin = runtime.load(&name, &fs::read(path)?)?;
+  let result = plugin.call(export, input.join(" ").as_bytes());
+  for record in plugin.take_logs() {
+    println!("[{} {:?}] {}", name, record.level, record.message);
+  }
+  let output = result?;
+  println!("{}", String::from_utf8_lossy(&output));
+  println!("({} fuel)", plugin.fuel_used());
+  Ok(())
 }
@@ -0,0 +1,66 @@
+//! Instruction metering: [`instrument`] makes every block of guest code first pass its cost to
+//! the imported `env.gas`, which fails once the call's fuel runs out.
+
+use wasm_instrument::{
+  gas_metering::{self, host_function, ConstantCostRules},
+  parity_wasm::{
+    self,
+    elements::{self, Module},
+  },
+};
+
+use crate::error::PluginError;
+
+pub(crate) const GAS_MODULE: &str = "env";
+pub(crate) const GAS_FUNCTION: &str = "gas";
+
+/// Fuel per instruction, per page of `memory.grow` and per local of a called function.
+const INSTRUCTION_COST: u32 = 1;
+const PAGE_COST: u32 = 1024;
+const LOCAL_COST: u32 = 1;
+
+pub(crate) fn instrument(wasm: &[u8]) -> Result<Vec<u8>, PluginError> {
+  let module: Module = parity_wasm::deserialize_buffer(wasm)
+    .map_err(|e: elements::Error| PluginError::Invalid(e.to_string()))?;
+  // A guest importing the gas function could refuel itself with a negative cost
+  let imports_gas = module.import_section().is_some_and(|imports| {
+    imports
+      .entries()
+      .iter()
+      .any(|import| import.module() == GAS_MODULE && import.field() == GAS_FUNCTION)
+  });
+  if imports_gas {
+    return Err(PluginError::Invalid(format!(
+      "modules may not import {}.{}",
+      GAS_MODULE, GAS_FUNCTION
+    )));
+  }
+  let rules = ConstantCostRules::new(INSTRUCTION_COST, PAGE_COST, LOCAL_COST);
+  let backend = host_function::Injector::new(GAS_MODULE, GAS_FUNCTION);
+  let module = gas_metering::inject(module, backend, &rules)
+    .map_err(|_| PluginError::Invalid("cannot meter the module".to_string()))?;
+  parity_wasm::serialize(module).map_err(|e| PluginError::Invalid(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn instrumented_modules_import_gas() {
+    let wasm = wat::parse_str(r#"(module (func (export "spin") (loop (br 0))))"#).unwrap();
+    let module: Module = parity_wasm::deserialize_buffer(&instrument(&wasm).unwrap()).unwrap();
+    let imports = module.import_section().unwrap().entries();
+    assert_eq!(imports.len(), 1);
+    assert_eq!(
+      (imports[0].module(), imports[0].field()),
+      (GAS_MODULE, GAS_FUNCTION)
+    );
+  }
+
+  #[test]
+  fn modules_importing_gas_are_rejected() {
+    let wasm = wat::parse_str(r#"(module (import "env" "gas" (func (param i64))))"#).unwrap();
+    assert!(matches!(instrument(&wasm), Err(PluginError::Invalid(_))));
+  }
+}
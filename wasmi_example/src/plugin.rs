@@ -0,0 +1,211 @@
+use std::{cell::RefCell, rc::Rc};
+
+use wasmi::{ImportsBuilder, ModuleInstance, ModuleRef, RuntimeValue, TrapKind};
+
+use crate::{
+  cache::ModuleCache,
+  error::PluginError,
+  host::{HostResolver, HostState, KvStore, LogRecord, OutOfFuel},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+  /// Fuel for instantiating a plugin and for each call into it; one unit per instruction.
+  pub fuel: u64,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config { fuel: 10_000_000 }
+  }
+}
+
+/// Loads plugins, sharing compiled modules and the key-value store between them.
+pub struct PluginRuntime {
+  config: Config,
+  cache: ModuleCache,
+  kv: Rc<RefCell<KvStore>>,
+}
+
+impl PluginRuntime {
+  pub fn new(config: Config) -> PluginRuntime {
+    PluginRuntime {
+      config,
+      cache: ModuleCache::default(),
+      kv: Rc::default(),
+    }
+  }
+
+  /// Instantiates `wasm` (binary or text) as plugin `name`, which owns the keys it stores under
+  /// that name: an instance loaded later under the same name sees them.
+  pub fn load(&mut self, name: &str, wasm: &[u8]) -> Result<Plugin, PluginError> {
+    let module = self.cache.get_or_compile(wasm)?;
+    let mut state = HostState {
+      name: name.to_string(),
+      kv: self.kv.clone(),
+      memory: None,
+      fuel: self.config.fuel,
+      logs: vec![],
+    };
+    let imports = ImportsBuilder::new().with_resolver("env", &HostResolver);
+    let instance =
+      ModuleInstance::new(&module, &imports).map_err(|e| PluginError::Instantiation {
+        plugin: name.to_string(),
+        message: e.to_string(),
+      })?;
+    state.memory = instance
+      .not_started_instance()
+      .export_by_name("memory")
+      .and_then(|export| export.as_memory().cloned());
+    let instance = instance
+      .run_start(&mut state)
+      .map_err(|trap| error(name, wasmi::Error::Trap(trap)))?;
+    Ok(Plugin {
+      instance,
+      fuel_used: self.config.fuel - state.fuel,
+      state,
+      config: self.config,
+    })
+  }
+
+  pub fn cache(&self) -> &ModuleCache {
+    &self.cache
+  }
+
+  /// What `plugin` stored under `key`.
+  pub fn kv_get(&self, plugin: &str, key: &[u8]) -> Option<Vec<u8>> {
+    self.kv.borrow().get(plugin)?.get(key).cloned()
+  }
+}
+
+pub struct Plugin {
+  instance: ModuleRef,
+  state: HostState,
+  config: Config,
+  fuel_used: u64,
+}
+
+impl Plugin {
+  pub fn name(&self) -> &str {
+    &self.state.name
+  }
+
+  /// Calls `export` with plain WebAssembly values.
+  pub fn invoke(
+    &mut self,
+    export: &str,
+    args: &[RuntimeValue],
+  ) -> Result<Option<RuntimeValue>, PluginError> {
+    self.metered(|plugin| plugin.run(export, args))
+  }
+
+  /// Calls an export taking and returning bytes, as exported by `guest_sdk::export!`.
+  pub fn call(&mut self, export: &str, input: &[u8]) -> Result<Vec<u8>, PluginError> {
+    let memory = self
+      .state
+      .memory
+      .clone()
+      .ok_or_else(|| self.missing("memory"))?;
+    self.metered(|plugin| {
+      let len = plugin.len(input.len())?;
+      let ptr = match plugin.run("alloc", &[RuntimeValue::I32(len)])? {
+        Some(RuntimeValue::I32(ptr)) => ptr,
+        _ => return Err(plugin.trap("alloc did not return a pointer")),
+      };
+      memory
+        .set(ptr as u32, input)
+        .map_err(|e| plugin.trap(&e.to_string()))?;
+      // The export now owns the input buffer
+      let packed = match plugin.run(export, &[RuntimeValue::I32(ptr), RuntimeValue::I32(len)])? {
+        Some(RuntimeValue::I64(packed)) => packed as u64,
+        _ => return Err(plugin.trap(&format!("{} did not return a buffer", export))),
+      };
+      let (ptr, len) = ((packed >> 32) as u32, packed as u32);
+      let output = memory
+        .get(ptr, len as usize)
+        .map_err(|e| plugin.trap(&e.to_string()))?;
+      plugin.run(
+        "dealloc",
+        &[RuntimeValue::I32(ptr as i32), RuntimeValue::I32(len as i32)],
+      )?;
+      Ok(output)
+    })
+  }
+
+  /// Fuel the last call, or instantiation, used.
+  pub fn fuel_used(&self) -> u64 {
+    self.fuel_used
+  }
+
+  /// What the plugin logged since the last time.
+  pub fn take_logs(&mut self) -> Vec<LogRecord> {
+    std::mem::take(&mut self.state.logs)
+  }
+
+  /// Runs `f` on a full tank.
+  fn metered<T>(
+    &mut self,
+    f: impl FnOnce(&mut Plugin) -> Result<T, PluginError>,
+  ) -> Result<T, PluginError> {
+    self.state.fuel = self.config.fuel;
+    let result = f(self);
+    self.fuel_used = self.config.fuel - self.state.fuel;
+    result
+  }
+
+  fn run(
+    &mut self,
+    export: &str,
+    args: &[RuntimeValue],
+  ) -> Result<Option<RuntimeValue>, PluginError> {
+    let is_function = self
+      .instance
+      .export_by_name(export)
+      .is_some_and(|export| export.as_func().is_some());
+    if !is_function {
+      return Err(self.missing(export));
+    }
+    self
+      .instance
+      .invoke_export(export, args, &mut self.state)
+      .map_err(|e| error(&self.state.name, e))
+  }
+
+  fn len(&self, len: usize) -> Result<i32, PluginError> {
+    i32::try_from(len).map_err(|_| self.trap(&format!("{} byte input is too large", len)))
+  }
+
+  fn missing(&self, export: &str) -> PluginError {
+    PluginError::MissingExport {
+      plugin: self.state.name.clone(),
+      export: export.to_string(),
+    }
+  }
+
+  fn trap(&self, message: &str) -> PluginError {
+    PluginError::Trap {
+      plugin: self.state.name.clone(),
+      message: message.to_string(),
+    }
+  }
+}
+
+fn error(plugin: &str, error: wasmi::Error) -> PluginError {
+  let out_of_fuel = error
+    .as_host_error()
+    .is_some_and(|e| e.downcast_ref::<OutOfFuel>().is_some());
+  if out_of_fuel {
+    return PluginError::OutOfFuel(plugin.to_string());
+  }
+  let message = match error {
+    wasmi::Error::Trap(trap) => match trap.into_kind() {
+      TrapKind::Host(e) => e.to_string(),
+      kind => format!("{:?}", kind),
+    },
+    error => error.to_string(),
+  };
+  PluginError::Trap {
+    plugin: plugin.to_string(),
+    message,
+  }
+}
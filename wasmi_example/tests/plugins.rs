@@ -0,0 +1,151 @@
+use std::{fs, path::PathBuf, process::Command, sync::OnceLock};
+
+use wasmi_example::{Config, Level, LogRecord, PluginError, PluginRuntime};
+
+/// Builds `guest/example`, which needs `rustup target add wasm32v1-none`.
+fn guest() -> &'static [u8] {
+  static WASM: OnceLock<Vec<u8>> = OnceLock::new();
+  WASM.get_or_init(|| {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let target_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("guest");
+    let status = Command::new(env!("CARGO"))
+      .args(["build", "--release", "--target", "wasm32v1-none"])
+      .arg("--manifest-path")
+      .arg(manifest_dir.join("guest/Cargo.toml"))
+      .arg("--target-dir")
+      .arg(&target_dir)
+      .status()
+      .unwrap();
+    assert!(status.success(), "building the guest failed");
+    fs::read(target_dir.join("wasm32v1-none/release/guest_example.wasm")).unwrap()
+  })
+}
+
+#[test]
+fn guests_exchange_strings_and_bytes_with_the_host() {
+  let mut runtime = PluginRuntime::new(Config::default());
+  let mut plugin = runtime.load("example", guest()).unwrap();
+
+  assert_eq!(plugin.call("greet", b"wasm").unwrap(), b"Hello, wasm!");
+  assert_eq!(
+    plugin.take_logs(),
+    vec![LogRecord {
+      level: Level::Info,
+      message: "greeting wasm".to_string()
+    }]
+  );
+  assert!(plugin.take_logs().is_empty());
+
+  let input: Vec<u8> = (0 .. 100_000).map(|i| i as u8).collect();
+  let reversed: Vec<u8> = input.iter().rev().copied().collect();
+  assert_eq!(plugin.call("reverse", &input).unwrap(), reversed);
+  assert_eq!(plugin.call("reverse", b"").unwrap(), b"");
+
+  assert!(matches!(
+    plugin.call("nothing", b""),
+    Err(PluginError::MissingExport { .. })
+  ));
+}
+
+#[test]
+fn the_key_value_store_outlives_instances() {
+  let mut runtime = PluginRuntime::new(Config::default());
+  let mut plugin = runtime.load("counter", guest()).unwrap();
+  assert_eq!(plugin.call("count", b"visits").unwrap(), b"1");
+  assert_eq!(plugin.call("count", b"visits").unwrap(), b"2");
+  assert_eq!(
+    runtime.kv_get("counter", b"visits").as_deref(),
+    Some(&b"2"[..])
+  );
+
+  // Same name, same keys; another name, its own
+  let mut reloaded = runtime.load("counter", guest()).unwrap();
+  assert_eq!(reloaded.call("count", b"visits").unwrap(), b"3");
+  let mut other = runtime.load("other", guest()).unwrap();
+  assert_eq!(other.call("count", b"visits").unwrap(), b"1");
+
+  assert_eq!(reloaded.call("forget", b"visits").unwrap(), b"true");
+  assert_eq!(reloaded.call("forget", b"visits").unwrap(), b"false");
+  assert_eq!(runtime.kv_get("counter", b"visits"), None);
+
+  // The module was compiled once
+  assert_eq!(runtime.cache().len(), 1);
+  assert_eq!(runtime.cache().hits(), 2);
+}
+
+#[test]
+fn fuel_stops_infinite_loops() {
+  let mut runtime = PluginRuntime::new(Config { fuel: 1_000_000 });
+  let mut plugin = runtime.load("example", guest()).unwrap();
+  assert!(matches!(
+    plugin.call("spin", b""),
+    Err(PluginError::OutOfFuel(name)) if name == "example"
+  ));
+  assert_eq!(plugin.fuel_used(), 1_000_000);
+
+  // Each call gets a full tank
+  assert_eq!(plugin.call("greet", b"again").unwrap(), b"Hello, again!");
+  assert!(plugin.fuel_used() < 1_000_000);
+
+  let mut looping = runtime
+    .load(
+      "looping",
+      br#"(module (func (export "spin") (result i32) (loop (br 0)) (i32.const 0)))"#,
+    )
+    .unwrap();
+  assert!(matches!(
+    looping.invoke("spin", &[]),
+    Err(PluginError::OutOfFuel(_))
+  ));
+
+  // Including in a start function
+  let start = br#"(module (func $spin (loop (br 0))) (start $spin))"#;
+  assert!(matches!(
+    runtime.load("start", start),
+    Err(PluginError::OutOfFuel(_))
+  ));
+}
+
+#[test]
+fn guest_panics_trap_with_their_message_logged() {
+  let mut runtime = PluginRuntime::new(Config::default());
+  let mut plugin = runtime.load("example", guest()).unwrap();
+  assert!(matches!(
+    plugin.call("fail", b"on purpose"),
+    Err(PluginError::Trap { .. })
+  ));
+  let logs = plugin.take_logs();
+  assert_eq!(logs.len(), 1);
+  assert_eq!(logs[0].level, Level::Error);
+  assert!(logs[0].message.contains("asked to fail: on purpose"));
+}
+
+#[test]
+fn modules_only_import_known_host_functions() {
+  let mut runtime = PluginRuntime::new(Config::default());
+  let wasi = br#"(module (import "wasi_snapshot_preview1" "fd_write" (func (param i32 i32 i32 i32) (result i32))))"#;
+  assert!(matches!(
+    runtime.load("wasi", wasi),
+    Err(PluginError::Instantiation { .. })
+  ));
+  let wrong = br#"(module (import "env" "log" (func (param i64))))"#;
+  assert!(matches!(
+    runtime.load("wrong", wrong),
+    Err(PluginError::Instantiation { .. })
+  ));
+
+  // Pointers outside guest memory trap instead of reaching the host
+  let mut reader = runtime
+    .load(
+      "reader",
+      br#"(module
+        (import "env" "log" (func $log (param i32 i32 i32)))
+        (memory (export "memory") 1)
+        (func (export "run") (call $log (i32.const 1) (i32.const 65000) (i32.const 1000))))"#,
+    )
+    .unwrap();
+  assert!(matches!(
+    reader.invoke("run", &[]),
+    Err(PluginError::Trap { .. })
+  ));
+}